@@ -0,0 +1,68 @@
+//! Acknowledgement transactions.
+//!
+//! When enabled, the oracle sends a memo transaction as soon as it picks up an unanswered
+//! interaction, so front-ends can show that the oracle is working on it before the callback
+//! lands. The memo reads `llm-oracle:ack:<interaction>:<queue position>[:<eta seconds>]`, the
+//! ETA being present once response times were recorded, and can be found with
+//! `getSignaturesForAddress` on the oracle identity, whose results include the memo.
+
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::OracleError;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::time::Duration;
+
+pub const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// Memo of the ack for an interaction with `queue_position` interactions ahead of it
+pub fn ack_memo(
+    interaction_pubkey: &Pubkey,
+    queue_position: usize,
+    eta: Option<Duration>,
+) -> String {
+    let mut memo = format!("llm-oracle:ack:{}:{}", interaction_pubkey, queue_position);
+    if let Some(eta) = eta {
+        memo.push_str(&format!(":{}", eta.as_secs().max(1)));
+    }
+    memo
+}
+
+pub fn ack_instruction(
+    payer: &Pubkey,
+    interaction_pubkey: &Pubkey,
+    queue_position: usize,
+) -> Instruction {
+    Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![AccountMeta::new_readonly(*payer, true)],
+        data: ack_memo(interaction_pubkey, queue_position, eta).into_bytes(),
+    }
+}
+
+/// Send the ack without waiting for confirmation; it only costs the base fee
+pub async fn send_ack(
+    oracle: &Oracle,
+    interaction_pubkey: &Pubkey,
+    queue_position: usize,
+    eta: Option<Duration>,
+) -> Result<Signature, OracleError> {
+    let payer = oracle.config.payer.as_ref();
+    let recent_blockhash = oracle.rpc_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        &[ack_instruction(
+            &payer.pubkey(),
+            interaction_pubkey,
+            queue_position,
+        )],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    let signature = oracle.rpc_client.send_transaction(&transaction).await?;
+    METRICS.acks_sent.inc();
+    Ok(signature)
+}
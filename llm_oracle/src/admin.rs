@@ -0,0 +1,369 @@
+//! `llm_oracle admin ...` commands: context creation, fee sweeps and identity rotation.
+//!
+//! The oracle answers interactions with a software key, but administrative transactions can be
+//! signed by any signer given with `--signer`, in the Solana CLI syntax: a keypair file, or a
+//! hardware wallet through the Solana remote-wallet interface, e.g. `usb://ledger?key=0/0`.
+//! Hardware wallets need the `ledger` build feature. Without `--signer`, the oracle identity
+//! signs.
+
+use crate::callback::LAMPORTS_PER_SIGNATURE;
+use crate::config::OracleConfig;
+use crate::nonce;
+use crate::OracleError;
+use anchor_lang::{AccountDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use clap::Subcommand;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Signature, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Subcommand)]
+pub enum AdminCommand {
+    /// Create a context account, paid by the signer
+    CreateContext {
+        /// Context text
+        #[arg(required_unless_present = "file")]
+        text: Option<String>,
+        /// Read the context text from this file
+        #[arg(long, conflicts_with = "text")]
+        file: Option<PathBuf>,
+        /// Oracle program of the context, the first configured one by default
+        #[arg(long)]
+        program: Option<Pubkey>,
+        /// Keypair file or hardware wallet URL (`usb://ledger?key=0/0`), the identity by default
+        #[arg(long)]
+        signer: Option<String>,
+    },
+    /// Transfer the signer's balance above `--keep` lamports to another account
+    Sweep {
+        to: Pubkey,
+        /// Lamports left to the signer
+        #[arg(long, default_value_t = 0)]
+        keep: u64,
+        /// Keypair file or hardware wallet URL (`usb://ledger?key=0/0`), the identity by default
+        #[arg(long)]
+        signer: Option<String>,
+    },
+    /// Move the funds of the current identity, and of its nonce accounts, to a new identity
+    RotateIdentity {
+        new_identity: Pubkey,
+        /// The current identity, when it isn't the configured one: keypair file or hardware
+        /// wallet URL (`usb://ledger?key=0/0`)
+        #[arg(long)]
+        signer: Option<String>,
+    },
+}
+
+#[cfg(feature = "ledger")]
+fn remote_signer(url: &str) -> Result<Box<dyn Signer>, OracleError> {
+    use solana_derivation_path::DerivationPath;
+    use solana_remote_wallet::locator::Locator;
+    use solana_remote_wallet::remote_keypair::generate_remote_keypair;
+    use solana_remote_wallet::remote_wallet::maybe_wallet_manager;
+
+    let (locator, key) = match url.split_once("?key=") {
+        Some((locator, key)) => (locator, Some(key)),
+        None => (url, None),
+    };
+    let derivation_path = match key {
+        Some(key) => DerivationPath::from_key_str(key)?,
+        None => DerivationPath::default(),
+    };
+    let wallet_manager = maybe_wallet_manager()?.ok_or("No hardware wallet found")?;
+    let keypair = generate_remote_keypair(
+        Locator::new_from_path(locator)?,
+        derivation_path,
+        &wallet_manager,
+        true,
+        "signer",
+    )?;
+    Ok(Box::new(keypair))
+}
+
+#[cfg(not(feature = "ledger"))]
+fn remote_signer(url: &str) -> Result<Box<dyn Signer>, OracleError> {
+    Err(format!(
+        "Signing with {} needs a build with the `ledger` feature",
+        url
+    )
+    .into())
+}
+
+/// The signer selected with `--signer`, `None` for the oracle identity
+pub fn select_signer(signer: Option<&str>) -> Result<Option<Box<dyn Signer>>, OracleError> {
+    let Some(signer) = signer else {
+        return Ok(None);
+    };
+    if signer.starts_with("usb://") {
+        return remote_signer(signer).map(Some);
+    }
+    let keypair = read_keypair_file(signer)
+        .map_err(|e| format!("Can't read the keypair {}: {}", signer, e))?;
+    Ok(Some(Box::new(keypair)))
+}
+
+/// The selected signer, or the oracle identity
+pub fn signer<'a>(
+    selected: &'a Option<Box<dyn Signer>>,
+    config: &'a OracleConfig,
+) -> &'a dyn Signer {
+    match selected {
+        Some(signer) => signer.as_ref(),
+        None => config.payer.as_ref(),
+    }
+}
+
+/// The program selected with `--program`, the first configured one by default
+pub fn select_program(
+    config: &OracleConfig,
+    program: Option<Pubkey>,
+) -> Result<Pubkey, OracleError> {
+    match program {
+        Some(program) => Ok(config.program(&program)?.id),
+        None => Ok(config.programs[0].id),
+    }
+}
+
+pub async fn send(
+    rpc_client: &RpcClient,
+    signer: &dyn Signer,
+    instructions: &[Instruction],
+) -> Result<Signature, OracleError> {
+    let recent_blockhash = rpc_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&signer.pubkey()),
+        &[signer],
+        recent_blockhash,
+    );
+    Ok(rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .await?)
+}
+
+/// Number of contexts created with `program_id`, which numbers the next one
+pub async fn context_count(
+    rpc_client: &RpcClient,
+    program_id: &Pubkey,
+) -> Result<u32, OracleError> {
+    let counter = Pubkey::find_program_address(&[b"counter"], program_id).0;
+    let account = rpc_client.get_account(&counter).await?;
+    Ok(solana_gpt_oracle::Counter::try_deserialize(&mut account.data.as_slice())?.count)
+}
+
+/// Address of the context numbered `count`
+pub fn context_address(program_id: &Pubkey, count: u32) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            solana_gpt_oracle::ContextAccount::seed(),
+            &count.to_le_bytes(),
+        ],
+        program_id,
+    )
+    .0
+}
+
+/// The `create_llm_context` instruction creating the context numbered `count`
+pub fn create_context_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    count: u32,
+    text: String,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: solana_gpt_oracle::accounts::CreateLlmContext {
+            payer: *payer,
+            counter: Pubkey::find_program_address(&[b"counter"], program_id).0,
+            context_account: context_address(program_id, count),
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: solana_gpt_oracle::instruction::CreateLlmContext { text }.data(),
+    }
+}
+
+/// The `interact_with_llm` instruction asking `text` in `context` on behalf of `account`, answered
+/// with `callback_from_oracle`
+pub fn interact_instruction(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    context: &Pubkey,
+    text: String,
+) -> Instruction {
+    let interaction = Pubkey::find_program_address(
+        &[
+            solana_gpt_oracle::Interaction::seed(),
+            account.as_ref(),
+            context.as_ref(),
+        ],
+        program_id,
+    )
+    .0;
+    Instruction {
+        program_id: *program_id,
+        accounts: solana_gpt_oracle::accounts::InteractWithLlm {
+            payer: *account,
+            interaction,
+            context_account: *context,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: solana_gpt_oracle::instruction::InteractWithLlm {
+            text,
+            callback_program_id: *program_id,
+            callback_discriminator:
+                solana_gpt_oracle::instruction::CallbackFromOracle::DISCRIMINATOR
+                    .try_into()
+                    .unwrap(),
+            account_metas: None,
+        }
+        .data(),
+    }
+}
+
+/// The `upgrade_interaction` instruction growing an interaction created before `created_at` was
+/// recorded to the current layout, paid by `payer`
+pub fn upgrade_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    interaction: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: solana_gpt_oracle::accounts::UpgradeInteraction {
+            payer: *payer,
+            interaction: *interaction,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: solana_gpt_oracle::instruction::UpgradeInteraction {}.data(),
+    }
+}
+
+/// Space of a context account with `text`, as allocated by the program
+pub fn context_space(text: &str) -> usize {
+    8 + text.len() + 8 + 32
+}
+
+async fn create_context(
+    rpc_client: &RpcClient,
+    signer: &dyn Signer,
+    program_id: Pubkey,
+    text: String,
+) -> Result<(), OracleError> {
+    let count = context_count(rpc_client, &program_id).await?;
+    let context_account = context_address(&program_id, count);
+    let instruction = create_context_instruction(&program_id, &signer.pubkey(), count, text);
+    let signature = send(rpc_client, signer, &[instruction]).await?;
+    println!("Created context {} ({})", context_account, signature);
+    Ok(())
+}
+
+async fn sweep(
+    rpc_client: &RpcClient,
+    signer: &dyn Signer,
+    to: &Pubkey,
+    keep: u64,
+) -> Result<(), OracleError> {
+    let balance = rpc_client.get_balance(&signer.pubkey()).await?;
+    let lamports = balance.saturating_sub(keep + LAMPORTS_PER_SIGNATURE);
+    if lamports == 0 {
+        println!(
+            "Nothing to sweep: {} holds {} SOL",
+            signer.pubkey(),
+            lamports_to_sol(balance)
+        );
+        return Ok(());
+    }
+    let instruction = system_instruction::transfer(&signer.pubkey(), to, lamports);
+    let signature = send(rpc_client, signer, &[instruction]).await?;
+    println!(
+        "Swept {} SOL from {} to {} ({})",
+        lamports_to_sol(lamports),
+        signer.pubkey(),
+        to,
+        signature
+    );
+    Ok(())
+}
+
+async fn rotate_identity(
+    rpc_client: &RpcClient,
+    config: &OracleConfig,
+    signer: &dyn Signer,
+    new_identity: &Pubkey,
+) -> Result<(), OracleError> {
+    let old_identity = signer.pubkey();
+    if old_identity == *new_identity {
+        return Err("The new identity is the current one".into());
+    }
+    // The nonce accounts are derived from the identity: the new one creates its own
+    let accounts: Vec<Pubkey> = (0..config.nonce_accounts)
+        .map(|index| nonce::address(&old_identity, index))
+        .collect();
+    let existing = rpc_client.get_multiple_accounts(&accounts).await?;
+    for (account, existing) in accounts.iter().zip(existing) {
+        let Some(existing) = existing else {
+            continue;
+        };
+        let instruction = system_instruction::withdraw_nonce_account(
+            account,
+            &old_identity,
+            new_identity,
+            existing.lamports,
+        );
+        let signature = send(rpc_client, signer, &[instruction]).await?;
+        println!("Closed nonce account {} ({})", account, signature);
+    }
+    sweep(rpc_client, signer, new_identity, 0).await?;
+    println!(
+        "Configure the oracle with the keypair of {} and upgrade the program with \
+         ORACLE_IDENTITY = {}. Lookup tables created by {} keep working but can only be \
+         extended by it.",
+        new_identity, new_identity, old_identity
+    );
+    Ok(())
+}
+
+pub async fn run(command: AdminCommand) -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let rpc_client =
+        RpcClient::new_with_commitment(config.rpc_url.clone(), CommitmentConfig::confirmed());
+    match command {
+        AdminCommand::CreateContext {
+            text,
+            file,
+            program,
+            signer,
+        } => {
+            let text = match file {
+                Some(path) => fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read {}: {}", path.display(), e))?,
+                None => text.unwrap_or_default(),
+            };
+            let program = select_program(&config, program)?;
+            let selected = select_signer(signer.as_deref())?;
+            create_context(&rpc_client, self::signer(&selected, &config), program, text).await
+        }
+        AdminCommand::Sweep { to, keep, signer } => {
+            let selected = select_signer(signer.as_deref())?;
+            sweep(&rpc_client, self::signer(&selected, &config), &to, keep).await
+        }
+        AdminCommand::RotateIdentity {
+            new_identity,
+            signer,
+        } => {
+            let selected = select_signer(signer.as_deref())?;
+            let signer = self::signer(&selected, &config);
+            rotate_identity(&rpc_client, &config, signer, &new_identity).await
+        }
+    }
+}
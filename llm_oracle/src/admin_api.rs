@@ -0,0 +1,234 @@
+//! Admin HTTP API.
+//!
+//! With `admin_api.token` (`ADMIN_API_TOKEN`) set, the metrics server answers these requests
+//! under `/admin`, with `Authorization: Bearer <token>`, to inspect and steer a running oracle
+//! without restarting it:
+//!
+//! - `GET /admin/interactions`: the interactions queued (`detected`) and in flight, with their
+//!   stage, and whether processing is paused
+//! - `GET /admin/providers`: whether the last call to each provider succeeded, and the recent
+//!   errors
+//! - `POST /admin/pause` and `POST /admin/resume`: hold interactions before their worker starts,
+//!   and release them. Interactions in flight finish; dead-letter retries go on.
+//! - `DELETE /admin/memory/<interaction>`: forget the conversation of an interaction account
+//! - `POST /admin/reprocess/<interaction>`: process an interaction now through the worker pool,
+//!   once it isn't queued or in flight. An abandoned prompt is answered again; a failed callback
+//!   in the dead-letter queue is sent again.
+//! - `POST /admin/reload`: reload the prompts, guardrails and generation settings, see
+//!   [`crate::reload`]
+//! - `DELETE /admin/flood/<context>`: lift the throttle or deactivation of a context held for
+//!   flooding, dispatching its deferred interactions again, see [`crate::flood`]
+//!
+//! Answers are JSON. A pause lasts until resumed or the process restarts.
+
+use crate::decode::InteractionView;
+use crate::dlq;
+use crate::health::HEALTH;
+use crate::metrics;
+use crate::monitor::MONITOR;
+use crate::oracle::Oracle;
+use crate::reload;
+use crate::status::InteractionStatus;
+use crate::worker_pool::WorkerPool;
+use crate::OracleError;
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, OnceLock};
+use tokio::sync::watch;
+use tracing::{error, info, warn, Instrument};
+
+pub const ADMIN_PATH: &str = "/admin/";
+
+/// Worker pool reprocessed interactions are dispatched to, attached once it is started
+static WORKER_POOL: OnceLock<WorkerPool> = OnceLock::new();
+
+/// Whether processing is paused from the API
+static PAUSED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// Whether the API is enabled
+pub fn enabled(oracle: &Oracle) -> bool {
+    oracle.config.admin_api_token.is_some()
+}
+
+/// Hand the worker pool to the API, for `POST /admin/reprocess`
+pub fn attach(worker_pool: WorkerPool) {
+    let _ = WORKER_POOL.set(worker_pool);
+}
+
+pub fn is_paused() -> bool {
+    *PAUSED.borrow()
+}
+
+/// Wait until processing isn't paused, returning right away unless it is
+pub async fn resumed() {
+    let mut paused = PAUSED.subscribe();
+    let _ = paused.wait_for(|paused| !*paused).await;
+}
+
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+fn error_response(status: &str, message: &str) -> String {
+    response(status, &json!({ "error": message }).to_string())
+}
+
+/// Set or lift the pause, returning whether it changed
+fn set_paused(paused: bool) -> bool {
+    let changed = PAUSED.send_if_modified(|current| std::mem::replace(current, paused) != paused);
+    if changed && paused {
+        warn!("Processing paused from the admin API");
+    } else if changed {
+        info!("Processing resumed from the admin API");
+    }
+    changed
+}
+
+fn interactions() -> String {
+    let snapshot = MONITOR.snapshot();
+    let (queued, in_flight): (Vec<_>, Vec<_>) = snapshot
+        .in_flight
+        .into_iter()
+        .partition(|entry| entry.status == InteractionStatus::Detected);
+    let body = json!({
+        "paused": is_paused(),
+        "queue_depth": snapshot.queue_depth,
+        "queued": queued,
+        "in_flight": in_flight,
+        "dlq_entries": snapshot.dlq_entries,
+    });
+    response("200 OK", &body.to_string())
+}
+
+fn providers() -> String {
+    let body = json!({
+        "providers": HEALTH.providers(),
+        "recent_errors": MONITOR.snapshot().recent_errors,
+    });
+    response("200 OK", &body.to_string())
+}
+
+fn flush_memory(oracle: &Oracle, interaction: &Pubkey) -> Result<(), OracleError> {
+    let mut memory = oracle.interaction_memory.lock().unwrap();
+    memory.close(interaction)?;
+    memory.flush()?;
+    info!(%interaction, "Conversation memory flushed from the admin API");
+    Ok(())
+}
+
+/// Start processing `interaction` again, returning what was started
+async fn reprocess(oracle: &Arc<Oracle>, interaction: Pubkey) -> Result<String, OracleError> {
+    let worker_pool = WORKER_POOL
+        .get()
+        .ok_or("The worker pool isn't started yet")?;
+    if worker_pool.is_closing() {
+        return Err("The oracle is shutting down".into());
+    }
+    if worker_pool.is_pending(&interaction) {
+        return Err("The interaction is queued or in flight already".into());
+    }
+    let account = oracle.rpc_client.get_account(&interaction).await?;
+    oracle.config.program(&account.owner)?;
+    let view = InteractionView::parse(&account.data).ok_or("Not an interaction account")?;
+    if view.is_processed {
+        return Err("The interaction has been answered".into());
+    }
+    let oracle = oracle.clone();
+    let span = tracing::info_span!("admin_reprocess", %interaction);
+    if let Some(letter) = oracle.dlq.get(&interaction)? {
+        if letter.prompt == view.text {
+            info!(%interaction, "Retrying a dead letter from the admin API");
+            tokio::spawn(
+                async move {
+                    if let Err(e) = dlq::retry(&oracle, &letter).await {
+                        warn!(error = ?e, "Dead letter retry failed");
+                    }
+                }
+                .instrument(span),
+            );
+            return Ok("callback".to_string());
+        }
+    }
+    oracle.processed.reopen(&interaction, view.text)?;
+    // Checked again under the pool lock, an update may have been queued during the fetch
+    let _span = span.enter();
+    if !worker_pool.dispatch_idle(account.owner, interaction, account.data) {
+        return Err("The interaction is queued or in flight already".into());
+    }
+    info!(%interaction, "Reprocessing an interaction from the admin API");
+    Ok("interaction".to_string())
+}
+
+/// Answer a request under [`ADMIN_PATH`]
+pub async fn handle(oracle: &Arc<Oracle>, method: &str, path: &str, head: &str) -> String {
+    let Some(token) = oracle.config.admin_api_token.as_deref() else {
+        return "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string();
+    };
+    if !metrics::authorized(head, token) {
+        return error_response("401 Unauthorized", "Invalid token");
+    }
+    let route = path.trim_start_matches(ADMIN_PATH).trim_end_matches('/');
+    let segments: Vec<&str> = route.split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["interactions"]) => interactions(),
+        ("GET", ["providers"]) => providers(),
+        ("POST", ["pause"]) => {
+            let changed = set_paused(true);
+            response(
+                "200 OK",
+                &json!({ "paused": true, "changed": changed }).to_string(),
+            )
+        }
+        ("POST", ["resume"]) => {
+            let changed = set_paused(false);
+            response(
+                "200 OK",
+                &json!({ "paused": false, "changed": changed }).to_string(),
+            )
+        }
+        ("POST", ["reload"]) => match reload::reload_logged(oracle) {
+            Ok(()) => response("200 OK", &json!({ "reloaded": true }).to_string()),
+            Err(e) => error_response("422 Unprocessable Entity", &e.to_string()),
+        },
+        ("DELETE", ["memory", interaction]) => {
+            let Ok(interaction) = Pubkey::from_str(interaction) else {
+                return error_response("400 Bad Request", "Invalid interaction pubkey");
+            };
+            match flush_memory(oracle, &interaction) {
+                Ok(()) => response("200 OK", &json!({ "flushed": true }).to_string()),
+                Err(e) => {
+                    error!(error = ?e, "Failed to flush a conversation");
+                    error_response("500 Internal Server Error", &e.to_string())
+                }
+            }
+        }
+        ("DELETE", ["flood", context]) => {
+            let Ok(context) = Pubkey::from_str(context) else {
+                return error_response("400 Bad Request", "Invalid context pubkey");
+            };
+            let lifted = oracle.flood.lift(&context);
+            response("200 OK", &json!({ "lifted": lifted }).to_string())
+        }
+        ("POST", ["reprocess", interaction]) => {
+            let Ok(interaction) = Pubkey::from_str(interaction) else {
+                return error_response("400 Bad Request", "Invalid interaction pubkey");
+            };
+            match reprocess(oracle, interaction).await {
+                Ok(started) => response(
+                    "202 Accepted",
+                    &json!({ "interaction": interaction.to_string(), "started": started })
+                        .to_string(),
+                ),
+                Err(e) => error_response("409 Conflict", &e.to_string()),
+            }
+        }
+        _ => error_response("404 Not Found", "No such admin route"),
+    }
+}
@@ -0,0 +1,245 @@
+//! Archive of answered interactions.
+//!
+//! Every response sent on-chain is stored with the prompt it answered and the full metadata of
+//! the sources it cited, so answers can be verified after the fact. Records live in an embedded
+//! sled database at `ARCHIVE_PATH` (under the deployment name when set), keyed by interaction
+//! and time.
+//!
+//! On-chain user ratings are stored next to the responses, see [`crate::ratings`]. With
+//! `retention.prompt_days`, prompts and responses are replaced by their hashes once that old,
+//! see [`crate::retention`].
+
+use crate::config::deployment_path;
+use crate::costs::{self, Tokens};
+use crate::tools::Citation;
+use crate::trace;
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    pub prompt: String,
+    /// The response as sent in the callback, suffixes included
+    pub response: String,
+    pub provider: String,
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    /// Unix timestamp in milliseconds
+    pub created_at: u64,
+    /// From picking up the interaction to having its response, in milliseconds
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+    /// Hex SHA-256 of the prompt and the response, once their text is deleted
+    #[serde(default)]
+    pub prompt_sha256: Option<String>,
+    #[serde(default)]
+    pub response_sha256: Option<String>,
+    /// Sent with the provider requests, see [`crate::trace`]
+    #[serde(default)]
+    pub trace_id: Option<String>,
+    /// Tokens spent on the response, by provider, as reported by the providers when they do
+    #[serde(default)]
+    pub tokens: BTreeMap<String, Tokens>,
+}
+
+impl ArchiveRecord {
+    pub fn new(
+        interaction_pubkey: &Pubkey,
+        interaction: &solana_gpt_oracle::Interaction,
+        response: &str,
+        provider: &str,
+        citations: Vec<Citation>,
+    ) -> Self {
+        Self {
+            interaction: interaction_pubkey.to_string(),
+            context: interaction.context.to_string(),
+            user: interaction.user.to_string(),
+            prompt: interaction.text.clone(),
+            response: response.to_string(),
+            provider: provider.to_string(),
+            citations,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            latency_ms: None,
+            prompt_sha256: None,
+            response_sha256: None,
+            trace_id: trace::current(),
+            tokens: costs::metered_tokens(),
+        }
+    }
+
+    pub fn is_redacted(&self) -> bool {
+        self.prompt_sha256.is_some()
+    }
+
+    /// Replace the prompt and the response with their hashes
+    pub fn redact(&mut self) {
+        if self.is_redacted() {
+            return;
+        }
+        self.prompt_sha256 = Some(hex::encode(Sha256::digest(self.prompt.as_bytes())));
+        self.response_sha256 = Some(hex::encode(Sha256::digest(self.response.as_bytes())));
+        self.prompt.clear();
+        self.response.clear();
+    }
+}
+
+/// A user's on-chain rating of a response, joined with the archived response it rated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingRecord {
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    /// From 1 (bad) to 5 (good)
+    pub score: u8,
+    /// Transaction that emitted the rating
+    pub signature: String,
+    /// The rated response and the provider that wrote it, when archived
+    pub response: Option<String>,
+    pub provider: Option<String>,
+    /// When the rated response was archived, Unix timestamp in milliseconds
+    #[serde(default)]
+    pub answered_at: Option<u64>,
+    /// Unix timestamp in milliseconds
+    pub rated_at: u64,
+}
+
+pub struct Archive {
+    db: sled::Db,
+    ratings: sled::Tree,
+}
+
+impl Archive {
+    pub fn open(path: &str) -> Result<Self, OracleError> {
+        let db = sled::open(path)?;
+        let ratings = db.open_tree("ratings")?;
+        Ok(Self { db, ratings })
+    }
+
+    /// Open the archive at `ARCHIVE_PATH`; `None` when archiving is disabled
+    pub fn from_env() -> Result<Option<Self>, OracleError> {
+        match env::var("ARCHIVE_PATH") {
+            Ok(path) => Ok(Some(Self::open(&deployment_path(&path))?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn record(&self, record: &ArchiveRecord) -> Result<(), OracleError> {
+        let key = format!("{}/{:020}", record.interaction, record.created_at);
+        self.db.insert(key, serde_json::to_vec(record)?)?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), OracleError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Every archived response to an interaction, oldest first
+    pub fn records(&self, interaction: &Pubkey) -> Result<Vec<ArchiveRecord>, OracleError> {
+        self.db
+            .scan_prefix(format!("{}/", interaction))
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect()
+    }
+
+    /// Every archived response, by interaction
+    pub fn iter(&self) -> impl Iterator<Item = Result<ArchiveRecord, OracleError>> + '_ {
+        self.db
+            .iter()
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+    }
+
+    /// The most recent archived response to an interaction
+    pub fn latest(&self, interaction: &Pubkey) -> Result<Option<ArchiveRecord>, OracleError> {
+        match self.db.scan_prefix(format!("{}/", interaction)).next_back() {
+            Some(entry) => Ok(Some(serde_json::from_slice(&entry?.1)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Redact the records created and drop the rated responses of the ratings given before
+    /// `cutoff` (Unix milliseconds). Returns the number of records redacted.
+    pub fn redact_before(&self, cutoff: u64) -> Result<usize, OracleError> {
+        let mut redacted = 0;
+        for entry in self.db.iter() {
+            let (key, bytes) = entry?;
+            let mut record: ArchiveRecord = serde_json::from_slice(&bytes)?;
+            if record.created_at >= cutoff || record.is_redacted() {
+                continue;
+            }
+            record.redact();
+            self.db.insert(key, serde_json::to_vec(&record)?)?;
+            redacted += 1;
+        }
+        for entry in self.ratings.iter() {
+            let (key, bytes) = entry?;
+            let mut rating: RatingRecord = serde_json::from_slice(&bytes)?;
+            if rating.rated_at >= cutoff || rating.response.is_none() {
+                continue;
+            }
+            rating.response = None;
+            self.ratings.insert(key, serde_json::to_vec(&rating)?)?;
+        }
+        Ok(redacted)
+    }
+
+    /// Delete every record and rating of an interaction. Returns the number deleted.
+    pub fn purge(&self, interaction: &Pubkey) -> Result<usize, OracleError> {
+        let mut purged = 0;
+        for entry in self.db.scan_prefix(format!("{}/", interaction)) {
+            self.db.remove(entry?.0)?;
+            purged += 1;
+        }
+        let interaction = interaction.to_string();
+        for entry in self.ratings.iter() {
+            let (key, bytes) = entry?;
+            let rating: RatingRecord = serde_json::from_slice(&bytes)?;
+            if rating.interaction == interaction {
+                self.ratings.remove(key)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Store a rating, replacing the previous rating of the same response: only the creator of
+    /// an interaction can rate it, so the latest rating wins. Returns whether it is the first.
+    pub fn record_rating(&self, rating: &RatingRecord) -> Result<bool, OracleError> {
+        let key = format!(
+            "{}/{:020}",
+            rating.interaction,
+            rating.answered_at.unwrap_or_default()
+        );
+        let previous = self.ratings.insert(key, serde_json::to_vec(rating)?)?;
+        Ok(previous.is_none())
+    }
+
+    /// Ratings received since `since` (Unix milliseconds), oldest first
+    pub fn ratings(&self, since: u64) -> Result<Vec<RatingRecord>, OracleError> {
+        let mut ratings = self
+            .ratings
+            .iter()
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect::<Result<Vec<RatingRecord>, OracleError>>()?;
+        ratings.retain(|rating| rating.rated_at >= since);
+        ratings.sort_by_key(|rating| rating.rated_at);
+        Ok(ratings)
+    }
+}
+
+impl Drop for Archive {
+    fn drop(&mut self) {
+        let _ = self.db.flush();
+    }
+}
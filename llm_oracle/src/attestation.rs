@@ -0,0 +1,67 @@
+//! Signed response attestations.
+//!
+//! With `attestation.enabled` (`ATTESTATIONS`), callbacks prove which oracle key wrote the
+//! response, beyond the identity PDA proving it went through the oracle program: the response
+//! ends with ` [attestation:<hex signature>]`, the signature of `sha256(interaction || response)`
+//! by the identity, or by the key at `attestation.keypair_path` (`ATTESTATION_KEYPAIR_PATH`).
+//! The signed response is the text before the tag, with its `[prompt:...]` tag if any.
+//!
+//! The transaction carrying the tag verifies the signature with the Ed25519 program before the
+//! callback, so that callback programs check it with `solana_gpt_oracle::attestation::verify`.
+//! Chunked responses are signed whole and the verification comes with the last chunk. Attested
+//! callbacks aren't batched with others, and answers aren't streamed with attestations on.
+
+use crate::OracleError;
+use solana_gpt_oracle::attestation::{message, TAG_PREFIX};
+use solana_sdk::ed25519_program;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+
+/// Bytes of an Ed25519 program instruction before its key, signature and message: the number of
+/// signatures, a padding byte and the seven offsets of `Ed25519SignatureOffsets`
+const OFFSETS_END: u16 = 16;
+
+/// The Ed25519 program instruction verifying `signature` of `message` by `attester`
+pub fn verify_instruction(attester: &Pubkey, signature: &Signature, message: &[u8]) -> Instruction {
+    let key_offset = OFFSETS_END;
+    let signature_offset = key_offset + 32;
+    let message_offset = signature_offset + 64;
+    // The data of the instruction itself
+    let this = u16::MAX;
+    let mut data = vec![1, 0];
+    for value in [
+        signature_offset,
+        this,
+        key_offset,
+        this,
+        message_offset,
+        message.len() as u16,
+        this,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(attester.as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: ed25519_program::id(),
+        accounts: Vec::new(),
+        data,
+    }
+}
+
+/// `response` to `interaction` followed by its attestation tag, and the instruction verifying
+/// the attestation, to send before the callback
+pub fn attest(
+    signer: &dyn Signer,
+    interaction: &Pubkey,
+    response: &str,
+) -> Result<(String, Instruction), OracleError> {
+    let message = message(interaction, response);
+    let signature = signer.try_sign_message(&message)?;
+    Ok((
+        format!("{}{}{}]", response, TAG_PREFIX, hex::encode(signature)),
+        verify_instruction(&signer.pubkey(), &signature, &message),
+    ))
+}
@@ -0,0 +1,383 @@
+//! Audit log of the answers sent on-chain.
+//!
+//! With `audit.dir` (`AUDIT_LOG_DIR`), every answer the oracle sends is appended to
+//! `audit.jsonl` in the directory once its callbacks landed or failed, one [`AuditEntry`] per
+//! line: the interaction, context and prompt as on-chain, the provider and its raw response, the
+//! callback data as sent (sealed, tagged and attested), the transaction signatures and the
+//! outcome. Dead-letter retries append an entry per attempt.
+//!
+//! Entries are hash-chained: each holds the hash of the entry before it, and its own hash is the
+//! SHA-256 of that previous hash and of the entry without its hash, so an entry edited, removed
+//! or inserted breaks the chain. Only a truncated tail goes unnoticed, unless the last hash,
+//! printed by `llm_oracle audit export`, is kept somewhere else. Over `audit.max_bytes`
+//! (`AUDIT_LOG_MAX_BYTES`), the file is renamed `audit-<unix millis>.jsonl` and the chain goes on
+//! in a new one. Nothing removes entries, `llm_oracle purge` included.
+//!
+//! `llm_oracle audit export` verifies the chain through every file and writes the entries, of a
+//! period or an interaction, as JSONL.
+
+use crate::config::AuditConfig;
+use crate::trace;
+use crate::OracleError;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_gpt_oracle::Interaction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+pub const CURRENT_FILE: &str = "audit.jsonl";
+/// Previous hash of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Raw responses kept until their callbacks settle; all are dropped when there are more
+const MAX_GENERATED: usize = 4096;
+
+/// One answer sent on-chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the chain, from 1
+    pub seq: u64,
+    /// RFC 3339
+    pub at: String,
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    /// The interaction text, sealed prompts still sealed
+    pub prompt: String,
+    /// What generated the response: a provider, `cache`, or `None` for the canned responses
+    /// (limits, rejections) and callbacks retried after a restart
+    pub provider: Option<String>,
+    /// The response of the provider, before validation and post-processing
+    pub raw_response: Option<String>,
+    /// The answer, as recorded by the ledger
+    pub response: String,
+    /// The callback data as sent
+    pub callback: String,
+    pub signatures: Vec<String>,
+    /// `confirmed`, `failed` (queued for a retry) or `abandoned`
+    pub status: String,
+    /// Trace ID of the interaction's provider requests, see [`crate::trace`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    pub prev_hash: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+impl AuditEntry {
+    /// SHA-256 of the previous hash and of the entry without its hash, in hex
+    pub fn compute_hash(&self) -> Result<String, OracleError> {
+        let mut unhashed = self.clone();
+        unhashed.hash = String::new();
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(serde_json::to_vec(&unhashed)?);
+        Ok(hex::encode(hasher.finalize()))
+    }
+}
+
+/// The file being appended to, and the end of the chain
+struct Chain {
+    file: File,
+    len: u64,
+    seq: u64,
+    last_hash: String,
+}
+
+/// Append-only, hash-chained log of the answers sent
+pub struct AuditLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    chain: Mutex<Chain>,
+    /// Provider and raw response by interaction, until its callbacks settle
+    generated: Mutex<HashMap<Pubkey, (String, String)>>,
+}
+
+/// The log files of `dir`, oldest first: the rotated ones, then the current one
+pub fn files(dir: &Path) -> Result<Vec<PathBuf>, OracleError> {
+    let mut rotated = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let millis = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("audit-")?.strip_suffix(".jsonl"))
+            .and_then(|millis| millis.parse::<u128>().ok());
+        if let Some(millis) = millis {
+            rotated.push((millis, path));
+        }
+    }
+    rotated.sort();
+    let mut files: Vec<PathBuf> = rotated.into_iter().map(|(_, path)| path).collect();
+    let current = dir.join(CURRENT_FILE);
+    if current.exists() {
+        files.push(current);
+    }
+    Ok(files)
+}
+
+fn last_entry(path: &Path) -> Result<Option<AuditEntry>, OracleError> {
+    let mut last = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    Ok(match last {
+        Some(line) => Some(serde_json::from_str(&line)?),
+        None => None,
+    })
+}
+
+fn open_current(dir: &Path) -> Result<(File, u64), OracleError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(CURRENT_FILE))?;
+    let len = file.metadata()?.len();
+    Ok((file, len))
+}
+
+impl AuditLog {
+    /// Open the log of `config.dir`, carrying on its chain
+    pub fn open(config: &AuditConfig) -> Result<Option<Self>, OracleError> {
+        let Some(dir) = &config.dir else {
+            return Ok(None);
+        };
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir).map_err(|e| {
+            format!(
+                "Can't create the audit log directory {}: {}",
+                dir.display(),
+                e
+            )
+        })?;
+        let mut last = None;
+        for path in files(&dir)?.iter().rev() {
+            last = last_entry(path)
+                .map_err(|e| format!("Can't read the audit log {}: {}", path.display(), e))?;
+            if last.is_some() {
+                break;
+            }
+        }
+        let (seq, last_hash) = match last {
+            Some(entry) => (entry.seq, entry.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        let (file, len) = open_current(&dir)?;
+        info!(dir = %dir.display(), entries = seq, "Audit log opened");
+        Ok(Some(Self {
+            dir,
+            max_bytes: config.max_bytes,
+            chain: Mutex::new(Chain {
+                file,
+                len,
+                seq,
+                last_hash,
+            }),
+            generated: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Note what `provider` answered the interaction before post-processing, for its entry
+    pub fn generated(&self, interaction_pubkey: &Pubkey, provider: &str, raw_response: &str) {
+        let mut generated = self.generated.lock().unwrap();
+        if generated.len() >= MAX_GENERATED {
+            generated.clear();
+        }
+        generated.insert(
+            *interaction_pubkey,
+            (provider.to_string(), raw_response.to_string()),
+        );
+    }
+
+    /// Append the entry of callbacks carrying `callback`, landed or not
+    pub fn record(
+        &self,
+        interaction_pubkey: &Pubkey,
+        interaction: &Interaction,
+        answer: &str,
+        callback: &str,
+        signatures: &[Signature],
+        status: &str,
+    ) -> Result<(), OracleError> {
+        let generated = self.generated.lock().unwrap().remove(interaction_pubkey);
+        let (provider, raw_response) = generated.unzip();
+        let mut chain = self.chain.lock().unwrap();
+        let mut entry = AuditEntry {
+            seq: chain.seq + 1,
+            at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            interaction: interaction_pubkey.to_string(),
+            context: interaction.context.to_string(),
+            user: interaction.user.to_string(),
+            prompt: interaction.text.clone(),
+            provider,
+            raw_response,
+            response: answer.to_string(),
+            callback: callback.to_string(),
+            signatures: signatures.iter().map(Signature::to_string).collect(),
+            status: status.to_string(),
+            trace_id: trace::current(),
+            prev_hash: chain.last_hash.clone(),
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash()?;
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        if chain.len > 0 && chain.len + line.len() as u64 > self.max_bytes {
+            self.rotate(&mut chain)?;
+        }
+        chain.file.write_all(&line)?;
+        chain.file.sync_data()?;
+        chain.len += line.len() as u64;
+        chain.seq = entry.seq;
+        chain.last_hash = entry.hash;
+        Ok(())
+    }
+
+    fn rotate(&self, chain: &mut Chain) -> Result<(), OracleError> {
+        let rotated = self
+            .dir
+            .join(format!("audit-{}.jsonl", Utc::now().timestamp_millis()));
+        fs::rename(self.dir.join(CURRENT_FILE), &rotated)?;
+        info!(file = %rotated.display(), "Audit log rotated");
+        (chain.file, chain.len) = open_current(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// Outcome of an export
+pub struct Exported {
+    pub written: usize,
+    pub total: u64,
+    /// Hash of the last entry of the chain, to keep for checking the log isn't truncated later
+    pub last_hash: String,
+}
+
+/// Verify the chain of the log in `dir` and write its entries at `from` or later, before `to`
+/// and of `interaction`, as JSONL. Fails at the first entry breaking the chain.
+pub fn export(
+    dir: &Path,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    interaction: Option<&Pubkey>,
+    output: &mut dyn Write,
+) -> Result<Exported, OracleError> {
+    let interaction = interaction.map(Pubkey::to_string);
+    let mut exported = Exported {
+        written: 0,
+        total: 0,
+        last_hash: GENESIS_HASH.to_string(),
+    };
+    for path in files(dir)? {
+        for (number, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let broken = |reason: String| -> OracleError {
+                format!("{}:{}: {}", path.display(), number + 1, reason).into()
+            };
+            let entry: AuditEntry =
+                serde_json::from_str(&line).map_err(|e| broken(format!("invalid entry: {}", e)))?;
+            if entry.seq != exported.total + 1 {
+                return Err(broken(format!(
+                    "entry {} follows entry {}",
+                    entry.seq, exported.total
+                )));
+            }
+            if entry.prev_hash != exported.last_hash {
+                return Err(broken(
+                    "previous hash doesn't match, the chain is broken".into(),
+                ));
+            }
+            if entry.compute_hash()? != entry.hash {
+                return Err(broken("hash doesn't match, the entry was altered".into()));
+            }
+            exported.total = entry.seq;
+            exported.last_hash = entry.hash.clone();
+            let at = DateTime::parse_from_rfc3339(&entry.at)
+                .map_err(|e| broken(format!("invalid time: {}", e)))?;
+            if from.is_some_and(|from| at < from)
+                || to.is_some_and(|to| at >= to)
+                || interaction
+                    .as_ref()
+                    .is_some_and(|interaction| *interaction != entry.interaction)
+            {
+                continue;
+            }
+            output.write_all(line.as_bytes())?;
+            output.write_all(b"\n")?;
+            exported.written += 1;
+        }
+    }
+    output.flush()?;
+    Ok(exported)
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommand {
+    /// Verify the hash chain and write the entries as JSONL
+    Export {
+        /// Entries at this time or later (RFC 3339)
+        #[arg(long)]
+        from: Option<DateTime<Utc>>,
+        /// Entries before this time (RFC 3339)
+        #[arg(long)]
+        to: Option<DateTime<Utc>>,
+        /// Entries of this interaction only
+        #[arg(long)]
+        interaction: Option<Pubkey>,
+        /// Read the log from this directory instead of AUDIT_LOG_DIR
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+pub fn run(command: AuditCommand, config: &AuditConfig) -> Result<(), OracleError> {
+    match command {
+        AuditCommand::Export {
+            from,
+            to,
+            interaction,
+            dir,
+            output,
+        } => {
+            let dir = dir
+                .or_else(|| config.dir.as_ref().map(PathBuf::from))
+                .ok_or("Set AUDIT_LOG_DIR or --dir to export the audit log")?;
+            let exported = match output {
+                Some(path) => export(
+                    &dir,
+                    from,
+                    to,
+                    interaction.as_ref(),
+                    &mut File::create(path)?,
+                )?,
+                None => export(
+                    &dir,
+                    from,
+                    to,
+                    interaction.as_ref(),
+                    &mut io::stdout().lock(),
+                )?,
+            };
+            eprintln!(
+                "Exported {} of {} entries, chain verified, last hash {}",
+                exported.written, exported.total, exported.last_hash
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,290 @@
+//! Batched callbacks.
+//!
+//! Every callback transaction pays the base fee of its signature, whatever it carries. With
+//! `callback.batch_window_ms` set, the callbacks of interactions answered within that window of
+//! each other are packed into shared transactions instead.
+//!
+//! How many callbacks a transaction takes depends on the traffic: short answers of contexts
+//! with few callback accounts pack well, long ones don't. [`CallbackStats`] keeps the size and
+//! compute units of the recent callbacks, and a batch is flushed as soon as it holds what a
+//! transaction can fit by either measure (at most `callback.max_batch_size`), without waiting
+//! for the end of the window. The same statistics size the compute budget when a simulation
+//! doesn't report the consumed units.
+//!
+//! Callbacks that are chunked or need address lookup tables are sent on their own. When a
+//! batched callback reverts, the others are sent again one by one so only it fails.
+
+use crate::callback::{transaction_size, CallbackError, Landed, MAX_COMPUTE_UNIT_LIMIT};
+use crate::oracle::Oracle;
+use crate::OracleError;
+use futures::future::join_all;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::{Pubkey, PUBKEY_BYTES};
+use solana_sdk::signature::{Signature, Signer};
+use std::collections::VecDeque;
+use std::slice;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{oneshot, Notify};
+use tracing::{debug, warn};
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 8;
+/// Callbacks the statistics are computed over
+const STATS_WINDOW: usize = 256;
+/// Percentile of the recent callbacks a batch is sized for, so most batches fit
+const SIZE_PERCENTILE: usize = 90;
+/// Bytes of a batched transaction besides its callbacks: signature, header, blockhash, compute
+/// budget and the accounts every callback shares
+const BATCH_OVERHEAD: usize = 250;
+/// Accounts of a callback shared by the others of its batch: the payer and the identity
+const SHARED_ACCOUNTS: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    bytes: usize,
+    units: u64,
+}
+
+/// Size and compute units of the recent callbacks
+#[derive(Default)]
+pub struct CallbackStats {
+    recent: Mutex<VecDeque<Sample>>,
+}
+
+/// Bytes a callback adds to a transaction already carrying another one
+pub fn callback_bytes(callback: &Instruction) -> usize {
+    // Account indexes, the program index and the length prefixes
+    let indexes = callback.accounts.len() + 4;
+    let accounts = callback.accounts.len().saturating_sub(SHARED_ACCOUNTS) * PUBKEY_BYTES;
+    callback.data.len() + indexes + accounts
+}
+
+fn percentile<T: Ord + Copy>(mut values: Vec<T>) -> Option<T> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let index = (values.len() * SIZE_PERCENTILE / 100).min(values.len() - 1);
+    Some(values[index])
+}
+
+impl CallbackStats {
+    /// Record a transaction carrying `callbacks` that consumed `units`
+    pub fn observe(&self, callbacks: &[Instruction], units: u64) {
+        if callbacks.is_empty() {
+            return;
+        }
+        let units = units / callbacks.len() as u64;
+        let mut recent = self.recent.lock().unwrap();
+        for callback in callbacks {
+            recent.push_back(Sample {
+                bytes: callback_bytes(callback),
+                units,
+            });
+        }
+        while recent.len() > STATS_WINDOW {
+            recent.pop_front();
+        }
+    }
+
+    /// Bytes of most recent callbacks
+    pub fn bytes_per_callback(&self) -> Option<usize> {
+        percentile(
+            self.recent
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|s| s.bytes)
+                .collect(),
+        )
+    }
+
+    /// Compute units of most recent callbacks
+    pub fn units_per_callback(&self) -> Option<u64> {
+        percentile(
+            self.recent
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|s| s.units)
+                .collect(),
+        )
+    }
+}
+
+struct Pending {
+    interaction: Pubkey,
+    text: String,
+    callback: Instruction,
+    reply: oneshot::Sender<Result<Landed, OracleError>>,
+}
+
+pub struct CallbackBatcher {
+    window: Duration,
+    max_size: usize,
+    pending: Mutex<Vec<Pending>>,
+    /// Wakes the batch's first callback up when the batch is full
+    full: Notify,
+}
+
+impl CallbackBatcher {
+    pub fn new(window: Duration, max_size: usize) -> Self {
+        Self {
+            window,
+            max_size,
+            pending: Mutex::new(Vec::new()),
+            full: Notify::new(),
+        }
+    }
+
+    /// Callbacks a transaction can carry given the recent callbacks
+    pub fn target_size(&self, oracle: &Oracle) -> usize {
+        let sender = &oracle.callback_sender;
+        let by_bytes = sender
+            .stats
+            .bytes_per_callback()
+            .map_or(self.max_size, |bytes| {
+                (PACKET_DATA_SIZE - BATCH_OVERHEAD) / bytes.max(1)
+            });
+        let by_units = sender
+            .stats
+            .units_per_callback()
+            .map_or(self.max_size, |units| {
+                (MAX_COMPUTE_UNIT_LIMIT / sender.with_margin(units).max(1)) as usize
+            });
+        by_bytes.min(by_units).clamp(1, self.max_size)
+    }
+
+    /// Send the callback of an interaction, in a transaction shared with the callbacks
+    /// submitted within the window. The sent signatures are recorded like unbatched ones, and the
+    /// fee is split between the callbacks of the transaction.
+    pub async fn submit(
+        &self,
+        oracle: &Oracle,
+        interaction: &Pubkey,
+        text: &str,
+        callback: Instruction,
+    ) -> Result<Landed, OracleError> {
+        let target = self.target_size(oracle);
+        let (reply, replied) = oneshot::channel();
+        let first = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(Pending {
+                interaction: *interaction,
+                text: text.to_string(),
+                callback,
+                reply,
+            });
+            if pending.len() >= target {
+                self.full.notify_one();
+            }
+            pending.len() == 1
+        };
+        // The first callback of a batch sends it for everyone
+        if first {
+            if target > 1 {
+                let _ = tokio::time::timeout(self.window, self.full.notified()).await;
+            }
+            let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+            self.flush(oracle, batch, target).await;
+        }
+        replied
+            .await
+            .map_err(|_| "Callback batch was dropped before it was sent")?
+    }
+
+    /// Pack `batch` into transactions of at most `target` callbacks that fit in a packet
+    async fn flush(&self, oracle: &Oracle, batch: Vec<Pending>, target: usize) {
+        let payer = oracle.config.payer.pubkey();
+        let envelope = oracle.callback_sender.sizing_envelope(&payer);
+        let mut groups: Vec<Vec<Pending>> = Vec::new();
+        let mut group: Vec<Pending> = Vec::new();
+        for item in batch {
+            if !group.is_empty() {
+                let callbacks: Vec<Instruction> = group
+                    .iter()
+                    .chain([&item])
+                    .map(|pending| pending.callback.clone())
+                    .collect();
+                let fits = transaction_size(&payer, &callbacks, &[], &envelope)
+                    .is_ok_and(|size| size <= PACKET_DATA_SIZE);
+                if group.len() >= target || !fits {
+                    groups.push(std::mem::take(&mut group));
+                }
+            }
+            group.push(item);
+        }
+        if !group.is_empty() {
+            groups.push(group);
+        }
+        join_all(groups.into_iter().map(|group| send_group(oracle, group))).await;
+    }
+}
+
+async fn send(oracle: &Oracle, group: &[Pending]) -> Result<Landed, OracleError> {
+    let callbacks: Vec<Instruction> = group
+        .iter()
+        .map(|pending| pending.callback.clone())
+        .collect();
+    let record_sent = |signature: &Signature| {
+        for pending in group {
+            let recorded =
+                oracle
+                    .processed
+                    .record_sent(&pending.interaction, &pending.text, signature);
+            if let Err(e) = recorded {
+                warn!(interaction = %pending.interaction, error = ?e, "Failed to record the callback signature");
+            }
+        }
+    };
+    oracle
+        .callback_sender
+        .send(
+            &oracle.rpc_client,
+            oracle.config.payer.as_ref(),
+            &callbacks,
+            &[],
+            &record_sent,
+        )
+        .await
+}
+
+async fn send_group(oracle: &Oracle, group: Vec<Pending>) {
+    match send(oracle, &group).await {
+        Ok(landed) => {
+            debug!(
+                signature = %landed.signature,
+                callbacks = group.len(),
+                "Batched callbacks landed"
+            );
+            let share = Landed {
+                signature: landed.signature,
+                fee_lamports: landed.fee_lamports / group.len() as u64,
+            };
+            for pending in group {
+                let _ = pending.reply.send(Ok(share));
+            }
+        }
+        // One of the callbacks reverts: send them on their own so only that one fails
+        Err(e) if e.is::<CallbackError>() && group.len() > 1 => {
+            debug!(error = %e, "Batch simulation failed, sending its callbacks one by one");
+            join_all(group.into_iter().map(|pending| async move {
+                let result = send(oracle, slice::from_ref(&pending)).await;
+                let _ = pending.reply.send(result);
+            }))
+            .await;
+        }
+        Err(e) if group.len() == 1 => {
+            if let Some(pending) = group.into_iter().next() {
+                let _ = pending.reply.send(Err(e));
+            }
+        }
+        Err(e) => {
+            let error = e.to_string();
+            for pending in group {
+                let _ = pending.reply.send(Err(error.clone().into()));
+            }
+        }
+    }
+}
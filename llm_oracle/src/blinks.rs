@@ -0,0 +1,349 @@
+//! Solana Actions (Blinks) for contexts.
+//!
+//! `llm_oracle blink <context>` publishes a context as a [Solana Action]: it writes
+//! `<context pubkey>.json` to `BLINKS_DIR` and prints the links to share. The metrics server
+//! (`METRICS_ADDR`, exposed at `BLINKS_URL`) then answers:
+//!
+//! - `GET /actions.json`: the rules mapping `/blink/<context>` to the action, for Blinks unfurled
+//!   from the oracle's own URL
+//! - `GET /api/actions/interact/<context>`: the title, icon and question field shown by wallets
+//! - `POST /api/actions/interact/<context>?text=<question>`: an `interact_with_llm` transaction
+//!   paid by the wallet's `account`, built and priced by the oracle, for the wallet to sign
+//!
+//! Interactions submitted through a Blink are answered with the oracle program's own
+//! `callback_from_oracle`, which logs the response in the callback transaction. Blinks are read
+//! from `BLINKS_DIR` on every request, so they can be published and removed while the oracle
+//! runs.
+//!
+//! [Solana Action]: https://solana.com/docs/advanced/actions
+
+use crate::admin::interact_instruction;
+use crate::config::OracleConfig;
+use crate::decode::decode_context;
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::OracleError;
+use base64::Engine;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// Path of the actions, followed by the context pubkey
+pub const ACTIONS_PATH: &str = "/api/actions/interact/";
+pub const DEFAULT_LABEL: &str = "Ask";
+/// Version of the Actions specification implemented
+const ACTION_VERSION: &str = "2.4";
+const DESCRIPTION_CHARS: usize = 280;
+
+#[derive(Debug, Args)]
+pub struct BlinkArgs {
+    /// Context account to publish
+    context: Pubkey,
+    /// Title shown by wallets, "Ask <context>" by default
+    #[arg(long)]
+    title: Option<String>,
+    /// Description shown by wallets, the start of the context text by default
+    #[arg(long)]
+    description: Option<String>,
+    /// Absolute URL of the image shown by wallets (defaults to BLINKS_ICON)
+    #[arg(long)]
+    icon: Option<String>,
+    /// Label of the submit button
+    #[arg(long, default_value = DEFAULT_LABEL)]
+    label: String,
+    /// Stop serving the Blink of the context
+    #[arg(long)]
+    remove: bool,
+}
+
+/// A published context
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blink {
+    /// Oracle program of the context
+    pub program: String,
+    pub title: String,
+    pub description: String,
+    pub icon: String,
+    pub label: String,
+}
+
+/// The Blinks of `BLINKS_DIR`, one JSON file per context
+pub struct BlinkStore {
+    dir: PathBuf,
+}
+
+impl BlinkStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The store at `BLINKS_DIR`, `None` when it isn't set
+    pub fn from_env() -> Option<Self> {
+        env::var("BLINKS_DIR")
+            .ok()
+            .map(|dir| Self::new(PathBuf::from(dir)))
+    }
+
+    fn path(&self, context: &Pubkey) -> PathBuf {
+        self.dir.join(format!("{}.json", context))
+    }
+
+    pub fn get(&self, context: &Pubkey) -> Result<Option<Blink>, OracleError> {
+        match fs::read_to_string(self.path(context)) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, context: &Pubkey, blink: &Blink) -> Result<(), OracleError> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(context), serde_json::to_vec_pretty(blink)?)?;
+        Ok(())
+    }
+
+    /// Returns whether the context had a Blink
+    pub fn remove(&self, context: &Pubkey) -> Result<bool, OracleError> {
+        match fs::remove_file(self.path(context)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// CAIP-2 id of the cluster, sent in `X-Blockchain-Ids`
+async fn blockchain_id(rpc_client: &RpcClient) -> &'static str {
+    static ID: OnceCell<String> = OnceCell::const_new();
+    ID.get_or_try_init(|| async {
+        let genesis = rpc_client.get_genesis_hash().await?.to_string();
+        Ok::<_, OracleError>(format!("solana:{}", &genesis[..genesis.len().min(32)]))
+    })
+    .await
+    .map(String::as_str)
+    .unwrap_or("solana")
+}
+
+fn response(status: &str, blockchain_id: &str, body: &Value) -> String {
+    let body = body.to_string();
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nAccess-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET,POST,PUT,OPTIONS\r\nAccess-Control-Allow-Headers: \
+         Content-Type, Authorization, Content-Encoding, Accept-Encoding, X-Action-Version, \
+         X-Blockchain-Ids\r\nAccess-Control-Expose-Headers: X-Action-Version, X-Blockchain-Ids\r\n\
+         X-Action-Version: {}\r\nX-Blockchain-Ids: {}\r\nContent-Length: {}\r\nConnection: \
+         close\r\n\r\n{}",
+        status,
+        ACTION_VERSION,
+        blockchain_id,
+        body.len(),
+        body
+    )
+}
+
+/// Whether the metrics server should hand a request for `path` to [`handle`]
+pub fn is_route(path: &str) -> bool {
+    path == "/actions.json" || path.starts_with(ACTIONS_PATH)
+}
+
+/// The action metadata shown by wallets
+fn action(context: &Pubkey, blink: &Blink) -> Value {
+    json!({
+        "type": "action",
+        "icon": blink.icon,
+        "title": blink.title,
+        "description": blink.description,
+        "label": blink.label,
+        "links": {
+            "actions": [{
+                "type": "transaction",
+                "label": blink.label,
+                "href": format!("{}{}?text={{text}}", ACTIONS_PATH, context),
+                "parameters": [{
+                    "name": "text",
+                    "label": "Your question",
+                    "required": true,
+                }],
+            }],
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct ActionPostRequest {
+    account: String,
+}
+
+/// Build the transaction of a Blink submission, for the wallet to sign
+async fn transaction(
+    oracle: &Oracle,
+    context: &Pubkey,
+    blink: &Blink,
+    query: Option<&str>,
+    body: &[u8],
+) -> Result<Value, String> {
+    let program = Pubkey::from_str(&blink.program).map_err(|e| e.to_string())?;
+    oracle.config.program(&program).map_err(|e| e.to_string())?;
+    let request: ActionPostRequest =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid request: {}", e))?;
+    let account = Pubkey::from_str(&request.account).map_err(|_| "Invalid account")?;
+    let text = query
+        .and_then(|query| {
+            reqwest::Url::parse(&format!("http://localhost/?{}", query))
+                .ok()?
+                .query_pairs()
+                .find(|(name, _)| name == "text")
+                .map(|(_, text)| text.trim().to_string())
+        })
+        .filter(|text| !text.is_empty())
+        .ok_or("Ask a question")?;
+    let instruction = interact_instruction(&program, &account, context, text);
+    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&account));
+    transaction.message.recent_blockhash = oracle
+        .rpc_client
+        .get_latest_blockhash()
+        .await
+        .map_err(|e| format!("Can't get a blockhash: {}", e))?;
+    let bytes = bincode::serialize(&transaction).map_err(|e| e.to_string())?;
+    if bytes.len() > PACKET_DATA_SIZE {
+        return Err("The question is too long".to_string());
+    }
+    METRICS.blink_transactions.inc();
+    info!(%context, %account, "Built a Blink transaction");
+    Ok(json!({
+        "type": "transaction",
+        "transaction": base64::engine::general_purpose::STANDARD.encode(bytes),
+        "message": "The oracle will answer in the callback transaction",
+    }))
+}
+
+/// Answer a request of a Solana Actions client: the HTTP response, with the CORS and Actions
+/// headers
+pub async fn handle(oracle: &Oracle, method: &str, path: &str, body: &[u8]) -> String {
+    let blockchain_id = blockchain_id(&oracle.rpc_client).await;
+    let not_found = |message: &str| {
+        response(
+            "404 Not Found",
+            blockchain_id,
+            &json!({ "message": message }),
+        )
+    };
+    if method == "OPTIONS" {
+        return response("200 OK", blockchain_id, &json!({}));
+    }
+    let Some(store) = BlinkStore::from_env() else {
+        return not_found("Blinks are disabled");
+    };
+    if path == "/actions.json" {
+        return response(
+            "200 OK",
+            blockchain_id,
+            &json!({
+                "rules": [{
+                    "pathPattern": "/blink/*",
+                    "apiPath": format!("{}*", ACTIONS_PATH),
+                }],
+            }),
+        );
+    }
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let Some(context) = path
+        .strip_prefix(ACTIONS_PATH)
+        .and_then(|context| Pubkey::from_str(context).ok())
+    else {
+        return not_found("Unknown action");
+    };
+    let blink = match store.get(&context) {
+        Ok(Some(blink)) => blink,
+        Ok(None) => return not_found("This context has no Blink"),
+        Err(e) => {
+            warn!(%context, error = ?e, "Failed to read the Blink");
+            return not_found("This context has no Blink");
+        }
+    };
+    match method {
+        "GET" => response("200 OK", blockchain_id, &action(&context, &blink)),
+        "POST" => match transaction(oracle, &context, &blink, query, body).await {
+            Ok(transaction) => response("200 OK", blockchain_id, &transaction),
+            Err(message) => response(
+                "400 Bad Request",
+                blockchain_id,
+                &json!({ "message": message }),
+            ),
+        },
+        _ => response(
+            "405 Method Not Allowed",
+            blockchain_id,
+            &json!({ "message": "Method not allowed" }),
+        ),
+    }
+}
+
+/// `llm_oracle blink`: publish or remove the Blink of a context and print its links
+pub async fn run(args: BlinkArgs) -> Result<(), OracleError> {
+    let store = BlinkStore::from_env().ok_or("Set BLINKS_DIR to publish Blinks")?;
+    if args.remove {
+        if store.remove(&args.context)? {
+            println!("Removed the Blink of {}", args.context);
+        } else {
+            println!("{} has no Blink", args.context);
+        }
+        return Ok(());
+    }
+    let config = OracleConfig::load()?;
+    let rpc_client =
+        RpcClient::new_with_commitment(config.rpc_url.clone(), CommitmentConfig::confirmed());
+    let account = rpc_client
+        .get_account(&args.context)
+        .await
+        .map_err(|e| format!("Can't read the context {}: {}", args.context, e))?;
+    let program = config.program(&account.owner)?.id;
+    let context = decode_context(&account.data)
+        .ok_or_else(|| format!("{} is not a context account", args.context))?;
+    let icon = args
+        .icon
+        .or_else(|| env::var("BLINKS_ICON").ok())
+        .ok_or("Pass --icon or set BLINKS_ICON: wallets show Blinks with an image")?;
+    let blink = Blink {
+        program: program.to_string(),
+        title: args
+            .title
+            .unwrap_or_else(|| format!("Ask {}", args.context)),
+        description: args
+            .description
+            .unwrap_or_else(|| context.text.chars().take(DESCRIPTION_CHARS).collect()),
+        icon,
+        label: args.label,
+    };
+    store.save(&args.context, &blink)?;
+    println!("Published the Blink of {}", args.context);
+    match env::var("BLINKS_URL") {
+        Ok(url) => {
+            let url = url.trim_end_matches('/');
+            let action = format!("{}{}{}", url, ACTIONS_PATH, args.context);
+            println!("action: {}", action);
+            println!("blink:  {}/blink/{}", url, args.context);
+            println!("dial:   https://dial.to/?action=solana-action:{}", action);
+        }
+        Err(_) => println!(
+            "action: <BLINKS_URL>{}{} (set BLINKS_URL to the public URL of METRICS_ADDR)",
+            ACTIONS_PATH, args.context
+        ),
+    }
+    Ok(())
+}
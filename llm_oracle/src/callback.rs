@@ -0,0 +1,795 @@
+use crate::batching::{CallbackBatcher, CallbackStats};
+use crate::config::{ConfirmationConfig, ProgramConfig};
+use crate::confirmation::{self, Expiry, Outcome};
+use crate::fee_payers::FeePayers;
+use crate::fees::FeeEstimator;
+use crate::jito::{self, JitoClient};
+use crate::lookup_tables::LookupTables;
+use crate::metrics::METRICS;
+use crate::nonce::{self, NoncePool};
+use crate::OracleError;
+use anchor_lang::prelude::AccountMeta;
+use anchor_lang::{AnchorSerialize, Discriminator};
+use sha2::{Digest, Sha256};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::message::{v0, Message, VersionedMessage};
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    transaction::VersionedTransaction,
+};
+use std::fmt;
+use std::slice;
+use std::time::Instant;
+use tracing::{debug, warn};
+
+pub const DEFAULT_MAX_TX_RETRY_ATTEMPTS: u8 = 5;
+
+/// Compute unit limit used when the simulation doesn't report the consumed units
+pub const DEFAULT_COMPUTE_UNIT_LIMIT: u32 = 300_000;
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+pub const DEFAULT_COMPUTE_UNIT_MARGIN_PERCENT: u64 = 20;
+
+/// Base fee charged per transaction signature
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// Bytes reserved for the `[i/n] ` sequence header of a chunk
+const CHUNK_HEADER_RESERVE: usize = 12;
+
+/// Tag binding a response to the exact question it answers: `[prompt:<hex sha256 of the text>]`
+pub fn prompt_hash_tag(text: &str) -> String {
+    format!("[prompt:{}]", hex::encode(Sha256::digest(text.as_bytes())))
+}
+
+/// Build the `callback_from_llm` instruction answering an interaction of `program`
+pub fn build_callback_instruction(
+    payer: &Pubkey,
+    program: &ProgramConfig,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    response: &str,
+) -> Result<Instruction, OracleError> {
+    let response_data = [
+        solana_gpt_oracle::instruction::CallbackFromLlm::DISCRIMINATOR.to_vec(),
+        response.to_string().try_to_vec()?,
+    ]
+    .concat();
+
+    let mut callback_instruction = Instruction {
+        program_id: program.id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(program.identity_pda, false),
+            AccountMeta::new(*interaction_pubkey, false),
+            AccountMeta::new_readonly(interaction.callback_program_id, false),
+        ],
+        data: response_data,
+    };
+
+    // Add the remaining accounts from the callback_account_metas
+    let remaining_accounts: Vec<AccountMeta> = interaction
+        .callback_account_metas
+        .iter()
+        .map(|meta| AccountMeta {
+            pubkey: meta.pubkey,
+            is_signer: meta.is_signer,
+            is_writable: meta.is_writable,
+        })
+        .collect();
+    callback_instruction.accounts.extend(remaining_accounts);
+
+    Ok(callback_instruction)
+}
+
+/// Compute budget instructions prepended to every callback transaction
+fn compute_budget_instructions(units: u32, micro_lamports: u64) -> [Instruction; 2] {
+    [
+        ComputeBudgetInstruction::set_compute_unit_limit(units),
+        ComputeBudgetInstruction::set_compute_unit_price(micro_lamports),
+    ]
+}
+
+/// Lamports charged for a transaction: the base fee per signature plus the priority fee, which is
+/// paid on the requested compute unit limit
+pub fn transaction_fee(
+    transaction: &VersionedTransaction,
+    compute_unit_limit: u32,
+    micro_lamports: u64,
+) -> u64 {
+    let base = LAMPORTS_PER_SIGNATURE * transaction.signatures.len() as u64;
+    let priority = (compute_unit_limit as u128 * micro_lamports as u128).div_ceil(1_000_000);
+    base + priority as u64
+}
+
+/// Serialized size of a transaction of `message` once signed
+fn signed_size(message: VersionedMessage) -> Result<usize, OracleError> {
+    let transaction = VersionedTransaction {
+        signatures: vec![Signature::default(); message.header().num_required_signatures as usize],
+        message,
+    };
+    Ok(bincode::serialized_size(&transaction)? as usize)
+}
+
+/// A legacy message when it fits in a packet or there are no lookup tables, a v0 message
+/// referencing the accounts through `tables` otherwise
+pub fn compile_message(
+    payer: &Pubkey,
+    instructions: &[Instruction],
+    tables: &[AddressLookupTableAccount],
+    recent_blockhash: Hash,
+) -> Result<VersionedMessage, OracleError> {
+    let legacy = VersionedMessage::Legacy(Message::new_with_blockhash(
+        instructions,
+        Some(payer),
+        &recent_blockhash,
+    ));
+    if tables.is_empty() || signed_size(legacy.clone())? <= PACKET_DATA_SIZE {
+        return Ok(legacy);
+    }
+    Ok(VersionedMessage::V0(v0::Message::try_compile(
+        payer,
+        instructions,
+        tables,
+        recent_blockhash,
+    )?))
+}
+
+/// What a callback transaction carries besides its callbacks and compute budget
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Envelope {
+    /// Nonce account advanced first, when the transaction uses a durable nonce
+    pub nonce_account: Option<Pubkey>,
+    /// Jito tip account and lamports, transferred last, see [`crate::jito`]
+    pub tip: Option<(Pubkey, u64)>,
+    /// Pays the fees and the tip instead of the payer, see [`crate::fee_payers`]
+    pub fee_payer: Option<Pubkey>,
+}
+
+/// The instructions of a callback transaction: the nonce advance, the compute budget, the
+/// callbacks and the tip
+fn callback_instructions(
+    payer: &Pubkey,
+    callbacks: &[Instruction],
+    envelope: &Envelope,
+    compute_unit_limit: u32,
+    micro_lamports: u64,
+) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = envelope
+        .nonce_account
+        .map(|account| nonce::advance_instruction(&account, payer))
+        .into_iter()
+        .collect();
+    instructions.extend(compute_budget_instructions(
+        compute_unit_limit,
+        micro_lamports,
+    ));
+    instructions.extend_from_slice(callbacks);
+    if let Some((tip_account, lamports)) = envelope.tip {
+        let tipper = envelope.fee_payer.unwrap_or(*payer);
+        instructions.push(jito::tip_instruction(&tipper, &tip_account, lamports));
+    }
+    instructions
+}
+
+/// The callback transaction carrying `callbacks`, signed by the payer and by `fee_payer`, the
+/// fee payer of the envelope when it has one. `recent_blockhash` is the nonce of the envelope's
+/// nonce account when set.
+#[allow(clippy::too_many_arguments)]
+fn callback_transaction(
+    payer: &dyn Signer,
+    fee_payer: Option<&dyn Signer>,
+    callbacks: &[Instruction],
+    tables: &[AddressLookupTableAccount],
+    envelope: &Envelope,
+    compute_unit_limit: u32,
+    micro_lamports: u64,
+    recent_blockhash: Hash,
+) -> Result<VersionedTransaction, OracleError> {
+    let instructions = callback_instructions(
+        &payer.pubkey(),
+        callbacks,
+        envelope,
+        compute_unit_limit,
+        micro_lamports,
+    );
+    let message = compile_message(
+        &envelope.fee_payer.unwrap_or(payer.pubkey()),
+        &instructions,
+        tables,
+        recent_blockhash,
+    )?;
+    Ok(match fee_payer {
+        Some(fee_payer) => VersionedTransaction::try_new(message, &[fee_payer, payer])?,
+        None => VersionedTransaction::try_new(message, &[payer])?,
+    })
+}
+
+/// Serialized size of the (signed) callback transaction carrying `callbacks`
+pub fn transaction_size(
+    payer: &Pubkey,
+    callbacks: &[Instruction],
+    tables: &[AddressLookupTableAccount],
+    envelope: &Envelope,
+) -> Result<usize, OracleError> {
+    // The limit and price don't change the size of the instructions
+    let instructions = callback_instructions(payer, callbacks, envelope, 0, 0);
+    signed_size(compile_message(
+        &envelope.fee_payer.unwrap_or(*payer),
+        &instructions,
+        tables,
+        Hash::default(),
+    )?)
+}
+
+/// Split a response into pieces of at most `max_bytes` bytes (on char boundaries), each prefixed
+/// with its `[i/n] ` sequence number so the callback program can reassemble them.
+pub fn chunk_response(response: &str, max_bytes: usize) -> Vec<String> {
+    let max_bytes = max_bytes.max(4);
+    let mut pieces = Vec::new();
+    let mut rest = response;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(max_bytes);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    let total = pieces.len();
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(i, piece)| format!("[{}/{}] {}", i + 1, total, piece))
+        .collect()
+}
+
+/// Bytes of response a callback transaction has room for, after its `[i/n] ` header
+pub fn chunk_capacity(
+    payer: &Pubkey,
+    program: &ProgramConfig,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    tables: &[AddressLookupTableAccount],
+    envelope: &Envelope,
+) -> Result<usize, OracleError> {
+    let empty = build_callback_instruction(payer, program, interaction_pubkey, interaction, "")?;
+    PACKET_DATA_SIZE
+        .checked_sub(
+            transaction_size(payer, slice::from_ref(&empty), tables, envelope)?
+                + CHUNK_HEADER_RESERVE,
+        )
+        .filter(|available| *available > 0)
+        .ok_or_else(|| "Callback accounts leave no room for the response in a transaction".into())
+}
+
+/// Build the instructions of the callback transactions for a response, one transaction per
+/// callback. A response too large for a single transaction is split across several callbacks when
+/// `chunked` is set; otherwise it's sent as is. The `attestation` verification goes before the
+/// last callback, see [`crate::attestation`].
+#[allow(clippy::too_many_arguments)]
+pub fn build_callback_instructions(
+    payer: &Pubkey,
+    program: &ProgramConfig,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    response: &str,
+    chunked: bool,
+    attestation: Option<&Instruction>,
+    tables: &[AddressLookupTableAccount],
+    envelope: &Envelope,
+) -> Result<Vec<Vec<Instruction>>, OracleError> {
+    let with_attestation = |callback: Instruction| -> Vec<Instruction> {
+        attestation.cloned().into_iter().chain([callback]).collect()
+    };
+    let instruction =
+        build_callback_instruction(payer, program, interaction_pubkey, interaction, response)?;
+    let instructions = with_attestation(instruction);
+    if transaction_size(payer, &instructions, tables, envelope)? <= PACKET_DATA_SIZE {
+        return Ok(vec![instructions]);
+    }
+    if !chunked {
+        warn!(
+            interaction = %interaction_pubkey,
+            bytes = response.len(),
+            "Response exceeds the transaction size limit, set CHUNKED_CALLBACKS to split it"
+        );
+        return Ok(vec![instructions]);
+    }
+
+    let mut available = chunk_capacity(
+        payer,
+        program,
+        interaction_pubkey,
+        interaction,
+        tables,
+        envelope,
+    )?;
+    if attestation.is_some() {
+        // Room taken by the verification in the last transaction
+        let empty =
+            build_callback_instruction(payer, program, interaction_pubkey, interaction, "")?;
+        let verification =
+            transaction_size(payer, &with_attestation(empty.clone()), tables, envelope)?
+                - transaction_size(payer, slice::from_ref(&empty), tables, envelope)?;
+        available = available
+            .checked_sub(verification)
+            .filter(|available| *available > 0)
+            .ok_or("Callback accounts leave no room for the attested response in a transaction")?;
+    }
+    let chunks = chunk_response(response, available);
+    let last = chunks.len().saturating_sub(1);
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let callback =
+                build_callback_instruction(payer, program, interaction_pubkey, interaction, chunk)?;
+            Ok(if index == last {
+                with_attestation(callback)
+            } else {
+                vec![callback]
+            })
+        })
+        .collect()
+}
+
+/// Failures of the callback path that retrying won't fix
+#[derive(Debug)]
+pub enum CallbackError {
+    /// The simulation failed, e.g. because the callback program reverts
+    SimulationFailed { error: String, logs: Vec<String> },
+    /// The transaction landed and failed
+    Failed { signature: Signature, error: String },
+}
+
+impl fmt::Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallbackError::SimulationFailed { error, logs } => write!(
+                f,
+                "Callback simulation failed: {} (logs: {})",
+                error,
+                logs.join(" | ")
+            ),
+            CallbackError::Failed { signature, error } => {
+                write!(f, "Callback transaction {} failed: {}", signature, error)
+            }
+        }
+    }
+}
+
+impl CallbackError {
+    /// Whether the simulation failed as the fee payer can't pay for the transaction
+    pub fn unpaid(&self) -> bool {
+        matches!(
+            self,
+            CallbackError::SimulationFailed { error, .. }
+                if error == "InsufficientFundsForFee" || error == "AccountNotFound"
+        )
+    }
+}
+
+impl std::error::Error for CallbackError {}
+
+/// A callback transaction that landed
+#[derive(Debug, Clone, Copy)]
+pub struct Landed {
+    pub signature: Signature,
+    /// Base and priority fees paid for it
+    pub fee_lamports: u64,
+}
+
+/// Sends callback transactions: prices them with the [`FeeEstimator`], sizes their compute budget
+/// from a simulation and retries transient failures. A transaction can carry the callbacks of
+/// several interactions, see [`crate::batching`].
+pub struct CallbackSender {
+    pub fee_estimator: FeeEstimator,
+    /// Extra compute units added on top of the simulated consumption, in percent
+    pub compute_unit_margin_percent: u64,
+    /// Attempts per callback transaction
+    pub max_retries: u8,
+    pub lookup_tables: LookupTables,
+    /// Sign callbacks with durable nonces instead of recent blockhashes
+    pub nonces: Option<NoncePool>,
+    /// Batches the callbacks of interactions answered together, off when `None`
+    pub batcher: Option<CallbackBatcher>,
+    /// Recent callback sizes and compute units
+    pub stats: CallbackStats,
+    pub confirmation: ConfirmationConfig,
+    /// Submits callbacks as Jito bundles, off when `None`
+    pub jito: Option<JitoClient>,
+    /// Pay for callbacks in turn instead of the payer, off when `None`
+    pub fee_payers: Option<FeePayers>,
+}
+
+impl CallbackSender {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        fee_estimator: FeeEstimator,
+        compute_unit_margin_percent: u64,
+        max_retries: u8,
+        lookup_tables: LookupTables,
+        nonces: Option<NoncePool>,
+        batcher: Option<CallbackBatcher>,
+        confirmation: ConfirmationConfig,
+        jito: Option<JitoClient>,
+        fee_payers: Option<FeePayers>,
+    ) -> Self {
+        Self {
+            fee_estimator,
+            compute_unit_margin_percent,
+            max_retries,
+            lookup_tables,
+            nonces,
+            batcher,
+            stats: CallbackStats::default(),
+            confirmation,
+            jito,
+            fee_payers,
+        }
+    }
+
+    /// `units` plus the configured margin, within the transaction limit
+    pub fn with_margin(&self, units: u64) -> u32 {
+        let limit = units + units * self.compute_unit_margin_percent / 100;
+        limit.min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32
+    }
+
+    /// An envelope the size of the ones callbacks are sent in, to size transactions before
+    /// their nonce, tip and fee payer accounts are picked
+    pub fn sizing_envelope(&self, payer: &Pubkey) -> Envelope {
+        Envelope {
+            nonce_account: self.nonces.is_some().then(|| nonce::address(payer, 0)),
+            // Any account the callbacks don't use takes the room of a tip account
+            tip: self
+                .jito
+                .as_ref()
+                .map(|jito| (Pubkey::new_from_array([u8::MAX; 32]), jito.tip_lamports)),
+            fee_payer: self.fee_payers.as_ref().map(FeePayers::any),
+        }
+    }
+
+    /// Count `lamports` paid for a transaction that landed against its fee payer
+    fn paid(&self, envelope: &Envelope, lamports: u64) {
+        if let (Some(fee_payers), Some(fee_payer)) = (&self.fee_payers, &envelope.fee_payer) {
+            fee_payers.paid(fee_payer, lamports);
+        }
+    }
+
+    /// The tip of an attempt, or none when the tip accounts can't be fetched and the attempt
+    /// is sent through the RPC node only
+    async fn tip(&self) -> Option<(Pubkey, u64)> {
+        let jito = self.jito.as_ref()?;
+        match jito.tip_account().await {
+            Ok(account) => Some((account, jito.tip_lamports)),
+            Err(e) => {
+                warn!(error = ?e, "Failed to fetch the Jito tip accounts, sending without a tip");
+                None
+            }
+        }
+    }
+
+    /// The blockhash to sign an attempt with and when it expires: the nonce of the leased
+    /// account, or the latest blockhash
+    async fn blockhash(
+        &self,
+        rpc_client: &RpcClient,
+        nonce: Option<&nonce::NonceLease<'_>>,
+    ) -> Result<(Hash, Expiry), OracleError> {
+        match nonce {
+            Some(lease) => {
+                let nonce = lease.blockhash(rpc_client).await?;
+                Ok((
+                    nonce,
+                    Expiry::Nonce {
+                        account: lease.account,
+                        nonce,
+                    },
+                ))
+            }
+            None => {
+                let (blockhash, last_valid_block_height) = rpc_client
+                    .get_latest_blockhash_with_commitment(CommitmentConfig::processed())
+                    .await?;
+                Ok((blockhash, Expiry::BlockHeight(last_valid_block_height)))
+            }
+        }
+    }
+
+    /// Simulate the callbacks with the maximum compute budget and return the limit to request:
+    /// the consumed units plus the configured margin. When the simulation doesn't report the
+    /// consumed units, the limit is estimated from the recent callbacks.
+    #[allow(clippy::too_many_arguments)]
+    async fn simulate(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+        fee_payer: Option<&dyn Signer>,
+        callbacks: &[Instruction],
+        tables: &[AddressLookupTableAccount],
+        envelope: &Envelope,
+        micro_lamports: u64,
+        recent_blockhash: Hash,
+    ) -> Result<u32, OracleError> {
+        let transaction = callback_transaction(
+            payer,
+            fee_payer,
+            callbacks,
+            tables,
+            envelope,
+            MAX_COMPUTE_UNIT_LIMIT,
+            micro_lamports,
+            recent_blockhash,
+        )?;
+        let simulation = rpc_client
+            .simulate_transaction_with_config(
+                &transaction,
+                RpcSimulateTransactionConfig {
+                    sig_verify: false,
+                    replace_recent_blockhash: true,
+                    commitment: Some(CommitmentConfig::processed()),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .value;
+        if let Some(error) = simulation.err {
+            return Err(Box::new(CallbackError::SimulationFailed {
+                error: format!("{:?}", error),
+                logs: simulation.logs.unwrap_or_default(),
+            }));
+        }
+        Ok(match simulation.units_consumed {
+            Some(units) => {
+                self.stats.observe(callbacks, units);
+                self.with_margin(units)
+            }
+            None => self
+                .stats
+                .units_per_callback()
+                .map(|units| self.with_margin(units * callbacks.len() as u64))
+                .unwrap_or(DEFAULT_COMPUTE_UNIT_LIMIT),
+        })
+    }
+
+    /// Send the callback transaction carrying `callbacks`, retrying up to `max_retries` times.
+    /// `on_sent` is called with the signature of every attempt before it is sent. An attempt is
+    /// rebroadcast until it lands or expires, see [`crate::confirmation`]; only then is the next
+    /// one signed. The transaction is a v0 transaction using `tables` when it doesn't fit as a
+    /// legacy one. With durable nonces, a nonce account is leased for all the attempts. With
+    /// Jito, every attempt carries a tip and is submitted as a bundle, see [`crate::jito`].
+    /// With fee payers, every attempt is paid for by the next one, and one that can't pay is
+    /// left out and replaced. Any other failed simulation, or a transaction that landed and
+    /// failed, is returned right away as a [`CallbackError`].
+    pub async fn send(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+        callbacks: &[Instruction],
+        tables: &[AddressLookupTableAccount],
+        on_sent: &(dyn Fn(&Signature) + Sync),
+    ) -> Result<Landed, OracleError> {
+        let mut writable_accounts: Vec<Pubkey> = callbacks
+            .iter()
+            .flat_map(|callback| &callback.accounts)
+            .filter(|meta| meta.is_writable)
+            .map(|meta| meta.pubkey)
+            .collect();
+        // Batched callbacks share the payer and the identity
+        writable_accounts.sort();
+        writable_accounts.dedup();
+        let nonce = match &self.nonces {
+            Some(nonces) => Some(nonces.acquire(rpc_client, payer).await?),
+            None => None,
+        };
+        let nonce_account = nonce.as_ref().map(|lease| lease.account);
+        let mut attempts = 0;
+        let mut last_error: OracleError = "Callback transaction was never sent".into();
+        while attempts < self.max_retries {
+            METRICS.transaction_send_attempts.inc();
+            match self.blockhash(rpc_client, nonce.as_ref()).await {
+                Ok((recent_blockhash, expiry)) => {
+                    let tip = self.tip().await;
+                    let fee_payer = self.fee_payers.as_ref().and_then(FeePayers::next);
+                    let envelope = Envelope {
+                        nonce_account,
+                        tip,
+                        fee_payer: fee_payer.map(|fee_payer| fee_payer.pubkey()),
+                    };
+                    // Re-estimated on every attempt so retries follow congestion
+                    let micro_lamports = self
+                        .fee_estimator
+                        .estimate(rpc_client, &writable_accounts)
+                        .await;
+                    let simulated = self
+                        .simulate(
+                            rpc_client,
+                            payer,
+                            fee_payer,
+                            callbacks,
+                            tables,
+                            &envelope,
+                            micro_lamports,
+                            recent_blockhash,
+                        )
+                        .await;
+                    // Not an attempt: the fee payer is left out, and the next one tried
+                    if let (Err(e), Some(fee_payers), Some(fee_payer)) =
+                        (&simulated, &self.fee_payers, &envelope.fee_payer)
+                    {
+                        if e.downcast_ref::<CallbackError>()
+                            .is_some_and(CallbackError::unpaid)
+                        {
+                            warn!(%fee_payer, error = %e, "Fee payer can't pay for the callback");
+                            fee_payers.exclude(fee_payer);
+                            continue;
+                        }
+                    }
+                    let compute_unit_limit = match simulated {
+                        Ok(limit) => limit,
+                        Err(e) if e.is::<CallbackError>() => {
+                            METRICS
+                                .transaction_failures
+                                .with_label_values(&["simulation"])
+                                .inc();
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            attempts += 1;
+                            METRICS
+                                .transaction_failures
+                                .with_label_values(&["simulation"])
+                                .inc();
+                            warn!(attempt = attempts, error = ?e, "Failed to simulate transaction");
+                            last_error = e;
+                            continue;
+                        }
+                    };
+
+                    let transaction = callback_transaction(
+                        payer,
+                        fee_payer,
+                        callbacks,
+                        tables,
+                        &envelope,
+                        compute_unit_limit,
+                        micro_lamports,
+                        recent_blockhash,
+                    )?;
+
+                    let signature = transaction.signatures[0];
+                    on_sent(&signature);
+                    let started = Instant::now();
+                    let outcome = confirmation::land(
+                        rpc_client,
+                        &transaction,
+                        self.confirmation.commitment,
+                        self.confirmation.rebroadcast_interval,
+                        expiry,
+                        tip.and(self.jito.as_ref()),
+                    )
+                    .await;
+                    let elapsed = started.elapsed();
+                    METRICS
+                        .callback_attempt_seconds
+                        .with_label_values(&[outcome.label()])
+                        .observe(elapsed.as_secs_f64());
+                    match outcome {
+                        Outcome::Landed => {
+                            debug!(
+                                %signature,
+                                attempt = attempts + 1,
+                                elapsed_ms = elapsed.as_millis() as u64,
+                                "Callback transaction confirmed"
+                            );
+                            let fee_lamports =
+                                transaction_fee(&transaction, compute_unit_limit, micro_lamports);
+                            METRICS.fee_lamports.inc_by(fee_lamports);
+                            let tip_lamports = tip.map_or(0, |(_, lamports)| lamports);
+                            self.paid(&envelope, fee_lamports + tip_lamports);
+                            METRICS
+                                .compute_units_requested
+                                .observe(compute_unit_limit as f64);
+                            METRICS.callback_batch_size.observe(callbacks.len() as f64);
+                            if let Some((_, lamports)) = tip {
+                                METRICS.jito_tip_lamports.inc_by(lamports);
+                            }
+                            return Ok(Landed {
+                                signature,
+                                fee_lamports,
+                            });
+                        }
+                        // The fee is paid, not the tip, and the same transaction would fail again
+                        Outcome::Failed(error) => {
+                            let fee_lamports =
+                                transaction_fee(&transaction, compute_unit_limit, micro_lamports);
+                            METRICS.fee_lamports.inc_by(fee_lamports);
+                            self.paid(&envelope, fee_lamports);
+                            METRICS
+                                .transaction_failures
+                                .with_label_values(&["failed"])
+                                .inc();
+                            return Err(Box::new(CallbackError::Failed {
+                                signature,
+                                error: error.to_string(),
+                            }));
+                        }
+                        Outcome::Expired => {
+                            attempts += 1;
+                            METRICS
+                                .transaction_failures
+                                .with_label_values(&["expired"])
+                                .inc();
+                            warn!(
+                                %signature,
+                                attempt = attempts,
+                                elapsed_ms = elapsed.as_millis() as u64,
+                                "Callback transaction expired before landing"
+                            );
+                            last_error =
+                                format!("Callback transaction {} expired", signature).into();
+                        }
+                    }
+                }
+                Err(e) => {
+                    attempts += 1;
+                    METRICS
+                        .transaction_failures
+                        .with_label_values(&["blockhash"])
+                        .inc();
+                    warn!(attempt = attempts, error = ?e, "Failed to fetch blockhash");
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunk_response;
+
+    fn reassemble(pieces: &[String]) -> String {
+        pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                let header = format!("[{}/{}] ", i + 1, pieces.len());
+                piece.strip_prefix(&header).expect("numbered piece")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_reassemble_in_order() {
+        let response = "abcdefghij".repeat(10);
+        let pieces = chunk_response(&response, 30);
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces[0], format!("[1/4] {}", &response[..30]));
+        assert_eq!(reassemble(&pieces), response);
+    }
+
+    #[test]
+    fn chunks_split_on_char_boundaries() {
+        let response = "héllo wörld, ünïcode 🦀🦀🦀";
+        for max_bytes in 1..response.len() + 1 {
+            let pieces = chunk_response(response, max_bytes);
+            assert_eq!(reassemble(&pieces), response, "max_bytes {}", max_bytes);
+        }
+    }
+
+    #[test]
+    fn short_response_is_a_single_chunk() {
+        assert_eq!(chunk_response("hi", 100), vec!["[1/1] hi".to_string()]);
+        assert!(chunk_response("", 100).is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! Compressed interaction accounts.
+//!
+//! With ZK compression (Light Protocol), accounts live in state trees rather than as regular
+//! accounts: they cost no rent, but aren't returned by `getProgramAccounts` and can't be
+//! subscribed to. Their state comes from a compression RPC (Photon) at `solana.compression_url`
+//! (`COMPRESSION_RPC_URL`), which indexes them and serves the validity proofs a transaction
+//! reading or updating them must carry.
+//!
+//! [`CompressionClient`] lists the compressed interactions of a program, decoded like regular
+//! ones, and fetches the validity proof of their state. `list-pending` includes them. The program
+//! doesn't create compressed interactions yet, nor has it a callback taking a validity proof, so
+//! the oracle doesn't answer them: once it has, the proof from
+//! [`CompressionClient::validity_proof`] goes into that callback.
+
+use crate::decode::InteractionView;
+use crate::OracleError;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use std::time::Duration;
+
+/// Accounts per `getCompressedAccountsByOwner` call
+pub const PAGE_SIZE: u32 = 100;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A compressed account, as served by the compression RPC
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressedAccount {
+    /// Address of accounts created with one
+    pub address: Option<String>,
+    /// Hash of the current state, what validity proofs are asked for
+    pub hash: String,
+    pub data: Option<CompressedData>,
+    pub lamports: u64,
+    pub owner: String,
+    pub tree: String,
+    pub leaf_index: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressedData {
+    /// Base64, without the discriminator
+    pub data: String,
+    pub data_hash: String,
+    pub discriminator: u64,
+}
+
+impl CompressedAccount {
+    /// The account data with its discriminator first, as a regular account holds it
+    pub fn account_data(&self) -> Option<Vec<u8>> {
+        let data = self.data.as_ref()?;
+        let mut account_data = data.discriminator.to_le_bytes().to_vec();
+        account_data.extend(
+            base64::engine::general_purpose::STANDARD
+                .decode(&data.data)
+                .ok()?,
+        );
+        Some(account_data)
+    }
+
+    /// The address of the account, or its hash for accounts without one
+    pub fn id(&self) -> String {
+        self.address.clone().unwrap_or_else(|| self.hash.clone())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Page {
+    items: Vec<CompressedAccount>,
+    cursor: Option<String>,
+}
+
+/// A compressed interaction that hasn't been answered
+#[derive(Debug, Clone)]
+pub struct CompressedInteraction {
+    pub account: CompressedAccount,
+    pub interaction: solana_gpt_oracle::Interaction,
+}
+
+/// Client of a compression RPC
+pub struct CompressionClient {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl CompressionClient {
+    pub fn new(url: String) -> Result<Self, OracleError> {
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self { url, client })
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, OracleError> {
+        let response: RpcResponse = self
+            .client
+            .post(&self.url)
+            .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.error {
+            return Err(format!("{} failed: {}", method, error).into());
+        }
+        let result = response
+            .result
+            .ok_or_else(|| format!("{} returned no result", method))?;
+        // Results come wrapped in `{context, value}`
+        Ok(result.get("value").cloned().unwrap_or(result))
+    }
+
+    /// Every compressed account of `owner`, fetched [`PAGE_SIZE`] at a time
+    pub async fn accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+    ) -> Result<Vec<CompressedAccount>, OracleError> {
+        let mut accounts = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let page: Page = serde_json::from_value(
+                self.call(
+                    "getCompressedAccountsByOwner",
+                    json!({"owner": owner.to_string(), "cursor": cursor, "limit": PAGE_SIZE}),
+                )
+                .await?,
+            )?;
+            let last = page.items.is_empty() || page.cursor.is_none();
+            accounts.extend(page.items);
+            if last {
+                return Ok(accounts);
+            }
+            cursor = page.cursor;
+        }
+    }
+
+    /// The compressed interactions of `program` that haven't been answered yet
+    pub async fn pending_interactions(
+        &self,
+        program: &Pubkey,
+    ) -> Result<Vec<CompressedInteraction>, OracleError> {
+        Ok(self
+            .accounts_by_owner(program)
+            .await?
+            .into_iter()
+            .filter_map(|account| {
+                let data = account.account_data()?;
+                let interaction = InteractionView::parse(&data)
+                    .filter(|view| !view.is_processed)?
+                    .decode()
+                    .ok()?;
+                Some(CompressedInteraction {
+                    account,
+                    interaction,
+                })
+            })
+            .collect())
+    }
+
+    /// Proof that the accounts with these state `hashes` are current, for a transaction using
+    /// them. Holds `compressedProof`, `roots`, `rootIndices`, `leafIndices`, `leaves` and
+    /// `merkleTrees`.
+    pub async fn validity_proof(&self, hashes: &[String]) -> Result<Value, OracleError> {
+        self.call(
+            "getValidityProof",
+            json!({"hashes": hashes, "newAddressesWithTrees": []}),
+        )
+        .await
+    }
+}
@@ -0,0 +1,57 @@
+//! `llm_oracle config ...` commands checking and upgrading the configuration.
+
+use super::migrations::{self, CONFIG_VERSION};
+use super::{config_path, FileConfig, OracleConfig};
+use crate::OracleError;
+use clap::Subcommand;
+use std::fs;
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Load and validate the configuration, then print it with the defaults and environment
+    /// overrides applied, secrets redacted
+    Validate,
+    /// Print the config file migrated to the current version
+    Upgrade {
+        /// Config file, defaults to ORACLE_CONFIG or config.toml
+        path: Option<String>,
+        /// Replace the file, keeping the original as `<path>.bak`
+        #[arg(long)]
+        write: bool,
+    },
+}
+
+pub fn run(command: ConfigCommand) -> Result<(), OracleError> {
+    match command {
+        ConfigCommand::Validate => {
+            let config = OracleConfig::load()?;
+            print!("{}", config.effective()?);
+        }
+        ConfigCommand::Upgrade { path, write } => {
+            let path = path
+                .or_else(config_path)
+                .ok_or("No config file: pass its path, set ORACLE_CONFIG or create config.toml")?;
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format!("Can't read config file {}: {}", path, e))?;
+            let (upgraded, version) = migrations::upgrade(&text)
+                .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+            // The layout is checked, the values are checked by `validate`
+            toml::from_str::<FileConfig>(&upgraded)
+                .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+            if !write {
+                print!("{}", upgraded);
+            } else if version == CONFIG_VERSION {
+                println!("{} is already at version {}", path, CONFIG_VERSION);
+            } else {
+                let backup = format!("{}.bak", path);
+                fs::copy(&path, &backup)?;
+                fs::write(&path, upgraded)?;
+                println!(
+                    "Upgraded {} from version {} to {}, the original is in {}",
+                    path, version, CONFIG_VERSION, backup
+                );
+            }
+        }
+    }
+    Ok(())
+}
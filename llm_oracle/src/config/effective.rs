@@ -0,0 +1,385 @@
+//! The configuration in effect, written back in the layout of the config file.
+//!
+//! Defaults and environment overrides are applied, so the output reads as a config file that
+//! would load the same settings, except for secrets: notification keys and webhook URLs are
+//! replaced with `<redacted>`, as are the credentials and query of URLs and the path of the
+//! Solana endpoints, which often carry API keys. The identity isn't written, and the patterns of
+//! `guardrails.blocklist_file` are listed in `guardrails.blocklist`.
+
+use super::{
+    AdminApiSection, AttestationSection, AuditSection, CacheSection, CallbackSection,
+    ChannelConfig, ChannelKind, ContextSettingsSection, DigestSection, EncryptionSection,
+    FileConfig, FloodSection, GamesSection, GraphqlSection, GuardrailsSection, HealthSection,
+    ImagesSection, IncidentsSection, IngestSection, LimitsSection, ListenerBackend, LlmSection,
+    MemorySection, MetricsSection, NotifySection, OracleConfig, ProcessingSection, ProgramSection,
+    PromptSuggestionsSection, PushSection, RatingsSection, ReconcileSection, RefundsSection,
+    RefusalsSection, ResponseLengthSection, RetentionSection, ReviewSection, SolanaSection,
+    StructuredSection, WebhooksSection, CONFIG_VERSION, DIGEST_EMAIL_CHANNEL,
+};
+use crate::OracleError;
+use reqwest::Url;
+
+const REDACTED: &str = "<redacted>";
+
+/// `url` without its credentials and query, and without its path unless `keep_path`
+fn redact_url(url: &str, keep_path: bool) -> String {
+    let Ok(parsed) = Url::parse(url) else {
+        return REDACTED.to_string();
+    };
+    let mut redacted = format!("{}://", parsed.scheme());
+    if !parsed.username().is_empty() || parsed.password().is_some() {
+        redacted.push_str(REDACTED);
+        redacted.push('@');
+    }
+    redacted.push_str(parsed.host_str().unwrap_or_default());
+    if let Some(port) = parsed.port() {
+        redacted.push_str(&format!(":{}", port));
+    }
+    match parsed.path() {
+        path if keep_path || path == "/" => redacted.push_str(path),
+        _ => redacted.push_str(&format!("/{}", REDACTED)),
+    }
+    if parsed.query().is_some() {
+        redacted.push_str(&format!("?{}", REDACTED));
+    }
+    redacted
+}
+
+fn redact_channel(channel: &ChannelConfig) -> ChannelConfig {
+    let kind = match &channel.kind {
+        ChannelKind::Slack { .. } => ChannelKind::Slack {
+            url: REDACTED.to_string(),
+        },
+        ChannelKind::Discord { .. } => ChannelKind::Discord {
+            url: REDACTED.to_string(),
+        },
+        ChannelKind::Webhook { .. } => ChannelKind::Webhook {
+            url: REDACTED.to_string(),
+        },
+        ChannelKind::Email { smtp_url, from, to } => ChannelKind::Email {
+            smtp_url: redact_url(smtp_url, true),
+            from: from.clone(),
+            to: to.clone(),
+        },
+        ChannelKind::Pagerduty { .. } => ChannelKind::Pagerduty {
+            routing_key: REDACTED.to_string(),
+        },
+        ChannelKind::Opsgenie { api_url, .. } => ChannelKind::Opsgenie {
+            api_key: REDACTED.to_string(),
+            api_url: api_url.clone(),
+        },
+    };
+    ChannelConfig {
+        name: channel.name.clone(),
+        kind,
+    }
+}
+
+impl OracleConfig {
+    /// The configuration in effect as the TOML of a config file, with secrets redacted
+    pub fn effective(&self) -> Result<String, OracleError> {
+        let (listener, geyser_url) = match &self.listener {
+            ListenerBackend::Websocket => ("websocket", None),
+            ListenerBackend::Geyser { url } => ("geyser", Some(redact_url(url, false))),
+        };
+        let commitment = if self.confirmation.commitment.is_finalized() {
+            "finalized"
+        } else if self.confirmation.commitment.is_confirmed() {
+            "confirmed"
+        } else {
+            "processed"
+        };
+
+        // The email channel added by `digest.smtp_url` goes back to the digest section
+        let mut digest = self.digest.as_ref().map(|digest| DigestSection {
+            schedule: Some(digest.period.to_string()),
+            subject: Some(digest.subject.clone()),
+            ..Default::default()
+        });
+        let mut channels = Vec::new();
+        for channel in &self.notify.channels {
+            match (&mut digest, &channel.kind) {
+                (Some(digest), ChannelKind::Email { smtp_url, from, to })
+                    if channel.name == DIGEST_EMAIL_CHANNEL =>
+                {
+                    digest.smtp_url = Some(redact_url(smtp_url, true));
+                    digest.from = Some(from.clone());
+                    digest.to = Some(to.clone());
+                }
+                _ => channels.push(redact_channel(channel)),
+            }
+        }
+        let routes = self
+            .notify
+            .routes
+            .iter()
+            .filter(|route| {
+                digest
+                    .as_ref()
+                    .map_or(true, |digest| digest.smtp_url.is_none())
+                    || route.channels != [DIGEST_EMAIL_CHANNEL]
+            })
+            .cloned()
+            .collect();
+
+        let file = FileConfig {
+            version: Some(CONFIG_VERSION),
+            name: self.name.clone(),
+            solana: SolanaSection {
+                rpc_url: Some(redact_url(&self.rpc_url, false)),
+                websocket_url: Some(redact_url(&self.websocket_url, false)),
+                secondary_websocket_url: self
+                    .secondary_websocket_url
+                    .as_deref()
+                    .map(|url| redact_url(url, false)),
+                listener: Some(listener.to_string()),
+                geyser_url,
+                compression_url: self
+                    .compression_url
+                    .as_deref()
+                    .map(|url| redact_url(url, false)),
+                tx_audit_dir: self.tx_audit_dir.clone(),
+                ..Default::default()
+            },
+            llm: LlmSection {
+                provider: self.llm.provider.clone(),
+                base_url: self
+                    .llm
+                    .base_url
+                    .as_deref()
+                    .map(|url| redact_url(url, true)),
+                model: self.llm.model.clone(),
+                temperature: self.llm.temperature,
+                max_tokens: Some(self.llm.max_tokens),
+                top_p: self.llm.top_p,
+                presence_penalty: self.llm.presence_penalty,
+                frequency_penalty: self.llm.frequency_penalty,
+                system_prompt: self.llm.system_prompt.clone(),
+                vision: self.llm.vision,
+                history_token_budget: Some(self.llm.history_token_budget),
+                max_retries: Some(self.llm.max_retries),
+                retry_base_ms: Some(self.llm.retry_base_ms),
+                retry_max_ms: Some(self.llm.retry_max_ms),
+                hallucination_guard: Some(
+                    self.llm
+                        .hallucination_guard
+                        .as_ref()
+                        .map_or("off".to_string(), |guard| guard.to_string()),
+                ),
+                max_concurrent_requests: Some(
+                    self.llm
+                        .max_concurrent_requests
+                        .clone()
+                        .into_iter()
+                        .collect(),
+                ),
+                daily_tokens: Some(self.llm.daily_tokens.clone().into_iter().collect()),
+                daily_usd: Some(self.llm.daily_usd.clone().into_iter().collect()),
+                usd_per_1k_tokens: Some(self.llm.usd_per_1k_tokens.clone().into_iter().collect()),
+                fallback_providers: Some(self.llm.fallback_providers.clone()),
+                models: Some(self.llm.provider_models.clone().into_iter().collect()),
+                breaker_threshold: Some(self.llm.breaker_threshold),
+                breaker_cooldown_secs: Some(self.llm.breaker_cooldown_secs),
+                schema_corrections: Some(self.llm.schema_corrections),
+                consensus_providers: Some(self.llm.consensus_providers.clone()),
+                consensus: Some(self.llm.consensus.to_string()),
+            },
+            callback: CallbackSection {
+                compute_unit_margin_percent: Some(self.compute_unit_margin_percent),
+                max_retries: Some(self.max_tx_retries),
+                chunked: Some(self.chunked_callbacks),
+                ack: Some(self.ack_transactions),
+                prompt_hash: Some(self.prompt_hash_callbacks),
+                streaming: Some(self.streaming_callbacks),
+                stream_min_bytes: Some(self.stream_min_bytes),
+                dlq_max_attempts: Some(self.dlq_max_attempts),
+                lookup_tables: Some(
+                    self.lookup_tables
+                        .addresses
+                        .iter()
+                        .map(|address| address.to_string())
+                        .collect(),
+                ),
+                auto_lookup_table: Some(self.lookup_tables.auto),
+                lookup_table_min_uses: Some(self.lookup_tables.min_uses),
+                durable_nonce: Some(self.durable_nonce),
+                nonce_accounts: Some(self.nonce_accounts),
+                batch_window_ms: Some(self.batch_window_ms),
+                max_batch_size: Some(self.max_batch_size),
+                commitment: Some(commitment.to_string()),
+                rebroadcast_interval_ms: Some(
+                    self.confirmation.rebroadcast_interval.as_millis() as u64
+                ),
+                jito_url: self.jito_url.as_deref().map(|url| redact_url(url, true)),
+                jito_tip_lamports: Some(self.jito_tip_lamports),
+                fee_payers: Some(self.fee_payers.keypair_paths.clone()),
+                fee_payer_min_lamports: Some(self.fee_payers.min_lamports),
+            },
+            processing: ProcessingSection {
+                max_concurrent_interactions: Some(self.max_concurrent_interactions),
+                shutdown_timeout_secs: Some(self.shutdown_timeout_secs),
+                dedup_capacity: Some(self.dedup_capacity),
+                backlog_page_size: Some(self.backlog_page_size),
+            },
+            memory: MemorySection {
+                max_history: Some(self.memory_max_history),
+                ttl_secs: Some(self.memory_ttl_secs),
+                max_bytes: Some(self.memory_max_bytes),
+            },
+            guardrails: GuardrailsSection {
+                max_response_bytes: Some(self.guardrails.max_response_bytes),
+                blocklist: Some(self.guardrails.blocklist.clone()),
+                blocklist_file: None,
+                moderation: Some(self.guardrails.moderation),
+                fallback_response: Some(self.guardrails.fallback_response.clone()),
+            },
+            digest: digest.unwrap_or_default(),
+            notify: NotifySection {
+                channels: Some(channels),
+                routes: Some(routes),
+            },
+            incidents: IncidentsSection {
+                payer_min_lamports: Some(self.incidents.payer_min_lamports),
+                payer_check_secs: Some(self.incidents.payer_check_secs),
+                pause_when_payer_empty: Some(self.incidents.pause_when_payer_empty),
+                payer_airdrop_lamports: self.incidents.payer_airdrop_lamports,
+                subscription_dead_secs: Some(self.incidents.subscription_dead_secs),
+                subscription_lag_secs: Some(self.incidents.subscription_lag_secs),
+            },
+            health: HealthSection {
+                stall_secs: Some(self.health_stall_secs),
+            },
+            limits: LimitsSection {
+                per_context_per_minute: self.limits.per_context_per_minute,
+                per_user_per_minute: self.limits.per_user_per_minute,
+                action: Some(self.limits.action.to_string()),
+                response: Some(self.limits.response.clone()),
+            },
+            cache: CacheSection {
+                ttl_secs: self.cache.ttl_secs,
+                max_entries: Some(self.cache.max_entries),
+            },
+            retention: RetentionSection {
+                prompt_days: self.retention.prompt_days,
+            },
+            images: ImagesSection {
+                enabled: Some(self.images.enabled),
+                max_bytes: Some(self.images.max_bytes),
+                types: Some(self.images.types.clone()),
+                allowed_hosts: Some(self.images.allowed_hosts.clone()),
+                ipfs_gateway: Some(self.images.ipfs_gateway.clone()),
+            },
+            attestation: AttestationSection {
+                enabled: Some(self.attestation.enabled),
+                keypair_path: self.attestation.keypair_path.clone(),
+            },
+            refunds: RefundsSection {
+                enabled: Some(self.refunds.enabled),
+                interval_secs: Some(self.refunds.interval_secs),
+            },
+            encryption: EncryptionSection {
+                enabled: Some(self.encryption.enabled),
+                keypair_path: self.encryption.keypair_path.clone(),
+            },
+            context_settings: ContextSettingsSection {
+                enabled: Some(self.context_settings.enabled),
+                models: Some(self.context_settings.models.clone()),
+                max_tokens: Some(self.context_settings.max_tokens),
+            },
+            audit: AuditSection {
+                dir: self.audit.dir.clone(),
+                max_bytes: Some(self.audit.max_bytes),
+            },
+            response_length: ResponseLengthSection {
+                enabled: Some(self.response_length.enabled),
+                max_tokens: Some(self.response_length.max_tokens),
+                target_percent: Some(self.response_length.target_percent),
+                window: Some(self.response_length.window),
+            },
+            refusals: RefusalsSection {
+                enabled: Some(self.refusals.enabled),
+                max_age_secs: self.refusals.max_age_secs,
+            },
+            flood: FloodSection {
+                max_per_second: self.flood.max_per_second,
+                throttle_secs: Some(self.flood.throttle_secs),
+                deactivate: Some(self.flood.deactivate),
+            },
+            reconcile: ReconcileSection {
+                enabled: Some(self.reconcile.enabled),
+                interval_secs: Some(self.reconcile.interval_secs),
+                min_age_secs: Some(self.reconcile.min_age_secs),
+            },
+            games: GamesSection {
+                state_machines: self.game_state_machines.clone(),
+            },
+            structured: StructuredSection {
+                schemas: self.structured_output_schemas.clone(),
+            },
+            functions: FunctionsSection {
+                names: Some(
+                    self.functions
+                        .names
+                        .iter()
+                        .map(|function| function.name().to_string())
+                        .collect(),
+                ),
+                max_rounds: Some(self.functions.max_rounds),
+                contexts: self
+                    .functions
+                    .contexts
+                    .as_ref()
+                    .map(|contexts| contexts.iter().map(ToString::to_string).collect()),
+                rpc_url: self
+                    .functions
+                    .rpc_url
+                    .as_deref()
+                    .map(|url| redact_url(url, false)),
+            },
+            review: ReviewSection {
+                path: self.review.path.clone(),
+                sample_percent: Some(self.review.sample_percent),
+            },
+            prompt_suggestions: PromptSuggestionsSection {
+                dir: self.prompt_suggestions.dir.clone(),
+                interval_secs: Some(self.prompt_suggestions.interval_secs),
+                min_failures: Some(self.prompt_suggestions.min_failures),
+            },
+            ingest: IngestSection {
+                addr: self.ingest.addr.clone(),
+                keys_file: self.ingest.keys_file.clone(),
+                sponsor_keypair: self.ingest.sponsor_keypair.clone(),
+                timeout_secs: Some(self.ingest.timeout_secs),
+            },
+            graphql: GraphqlSection {
+                token: self.graphql_token.as_ref().map(|_| REDACTED.to_string()),
+            },
+            webhooks: WebhooksSection {
+                contexts: self.context_webhooks.clone(),
+            },
+            admin_api: AdminApiSection {
+                token: self.admin_api_token.as_ref().map(|_| REDACTED.to_string()),
+            },
+            push: PushSection {
+                addr: self.push.addr.clone(),
+                keys_file: self.push.keys_file.clone(),
+            },
+            metrics: MetricsSection {
+                addr: self.metrics_addr.clone(),
+            },
+            ratings: RatingsSection {
+                enabled: Some(self.ratings),
+            },
+            programs: self
+                .programs
+                .iter()
+                .map(|program| ProgramSection {
+                    id: program.id.to_string(),
+                    provider: program.provider.clone(),
+                    model: program.model.clone(),
+                })
+                .collect(),
+        };
+        Ok(toml::to_string_pretty(&file)?)
+    }
+}
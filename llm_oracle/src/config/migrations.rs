@@ -0,0 +1,96 @@
+//! Versions of the config file layout.
+//!
+//! A config file names the version of its layout with a top-level `version`; files without one
+//! are version 1. Files of an older version are migrated when loaded, with a warning, and
+//! `llm_oracle config upgrade` rewrites them in the current layout, keeping their comments.
+//! Files of a newer version than [`CONFIG_VERSION`] are refused rather than misread.
+//!
+//! 1. The original layout
+//! 2. The conversation memory settings moved from `processing.memory_max_history`,
+//!    `memory_ttl_secs` and `memory_max_bytes` to a `[memory]` section, as `max_history`,
+//!    `ttl_secs` and `max_bytes`
+
+use crate::OracleError;
+use toml_edit::{DocumentMut, Item, Table};
+
+/// Version of the layout read by this oracle
+pub const CONFIG_VERSION: u32 = 2;
+
+/// `MIGRATIONS[i]` moves a file from version `i + 1` to `i + 2`
+const MIGRATIONS: &[fn(&mut DocumentMut) -> Result<(), OracleError>] = &[memory_section];
+
+/// Version of the layout of a config file
+pub fn version(file: &DocumentMut) -> Result<u32, OracleError> {
+    let Some(item) = file.get("version") else {
+        return Ok(1);
+    };
+    match item.as_integer() {
+        Some(version) if version > CONFIG_VERSION as i64 => Err(format!(
+            "Config file version {} is newer than this oracle, which reads up to version {}",
+            version, CONFIG_VERSION
+        )
+        .into()),
+        Some(version) if version >= 1 => Ok(version as u32),
+        _ => Err(format!(
+            "`version` {}: must be a version number",
+            item.to_string().trim()
+        )
+        .into()),
+    }
+}
+
+/// Bring `file` to [`CONFIG_VERSION`], returning the version it had
+pub fn migrate(file: &mut DocumentMut) -> Result<u32, OracleError> {
+    let from = version(file)?;
+    if from == CONFIG_VERSION {
+        return Ok(from);
+    }
+    for migration in &MIGRATIONS[from as usize - 1..] {
+        migration(file)?;
+    }
+    file["version"] = toml_edit::value(CONFIG_VERSION as i64);
+    Ok(from)
+}
+
+/// The text of a config file brought to [`CONFIG_VERSION`], unchanged when already there, and
+/// the version it had
+pub fn upgrade(text: &str) -> Result<(String, u32), OracleError> {
+    let mut file: DocumentMut = text.parse()?;
+    let from = migrate(&mut file)?;
+    if from == CONFIG_VERSION {
+        return Ok((text.to_string(), from));
+    }
+    Ok((file.to_string(), from))
+}
+
+/// 1 to 2: `processing.memory_*` to `memory.*`
+fn memory_section(file: &mut DocumentMut) -> Result<(), OracleError> {
+    let Some(processing) = file.get_mut("processing").and_then(Item::as_table_like_mut) else {
+        return Ok(());
+    };
+    let mut moved = Vec::new();
+    for key in ["max_history", "ttl_secs", "max_bytes"] {
+        if let Some(item) = processing.remove(&format!("memory_{}", key)) {
+            moved.push((key, item));
+        }
+    }
+    if moved.is_empty() {
+        return Ok(());
+    }
+    let memory = file
+        .entry("memory")
+        .or_insert(Item::Table(Table::new()))
+        .as_table_like_mut()
+        .ok_or("`memory` must be a table")?;
+    for (key, item) in moved {
+        if memory.contains_key(key) {
+            return Err(format!(
+                "both `processing.memory_{}` and `memory.{}` are set",
+                key, key
+            )
+            .into());
+        }
+        memory.insert(key, item);
+    }
+    Ok(())
+}
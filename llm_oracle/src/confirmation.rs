@@ -0,0 +1,200 @@
+//! Landing callback transactions.
+//!
+//! A signed transaction is sent without preflight (it was just simulated) and without the RPC
+//! node's own retries, then its signature status is polled until it reaches
+//! `callback.commitment`. While it hasn't landed, the same signed transaction is rebroadcast
+//! every `callback.rebroadcast_interval_ms`: it can't land twice, so this is safe, unlike
+//! signing a new one. A new transaction is only signed once this one is known to have
+//! expired: its blockhash is past its last valid block height, or its durable nonce was
+//! advanced.
+//!
+//! With Jito, every broadcast submits the transaction as a bundle to the block engine, falling
+//! back to the RPC node when the block engine rejects it.
+
+use crate::jito::JitoClient;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::nonce_utils;
+use solana_client::rpc_config::RpcSendTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{TransactionError, VersionedTransaction};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+pub const DEFAULT_REBROADCAST_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// A blockhash is valid for 150 blocks, about a minute: past this, a transaction whose expiry
+/// couldn't be checked is considered expired
+const MAX_WATCH: Duration = Duration::from_secs(180);
+
+/// When a transaction can no longer land
+#[derive(Debug, Clone, Copy)]
+pub enum Expiry {
+    /// Past this block height
+    BlockHeight(u64),
+    /// Once the nonce of the account isn't this one anymore
+    Nonce { account: Pubkey, nonce: Hash },
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    /// Reached the commitment
+    Landed,
+    /// Landed and failed
+    Failed(TransactionError),
+    /// Can no longer land
+    Expired,
+}
+
+impl Outcome {
+    /// Label of the outcome in metrics
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Landed => "landed",
+            Outcome::Failed(_) => "failed",
+            Outcome::Expired => "expired",
+        }
+    }
+}
+
+async fn is_expired(rpc_client: &RpcClient, expiry: &Expiry) -> Result<bool, OracleError> {
+    Ok(match expiry {
+        Expiry::BlockHeight(last_valid) => {
+            rpc_client
+                .get_block_height_with_commitment(CommitmentConfig::processed())
+                .await?
+                > *last_valid
+        }
+        Expiry::Nonce { account, nonce } => {
+            let account = rpc_client
+                .get_account_with_commitment(account, CommitmentConfig::processed())
+                .await?
+                .value
+                .ok_or("Nonce account doesn't exist")?;
+            nonce_utils::data_from_account(&account)?.blockhash() != *nonce
+        }
+    })
+}
+
+enum Status {
+    /// Not seen, or the RPC node couldn't tell
+    Unknown,
+    /// Processed, not at the commitment yet
+    Processed,
+    Done(Outcome),
+}
+
+async fn status(
+    rpc_client: &RpcClient,
+    signature: &Signature,
+    commitment: CommitmentConfig,
+    history: bool,
+) -> Status {
+    let statuses = if history {
+        rpc_client
+            .get_signature_statuses_with_history(&[*signature])
+            .await
+    } else {
+        rpc_client.get_signature_statuses(&[*signature]).await
+    };
+    let status = match statuses {
+        Ok(statuses) => statuses.value.into_iter().next().flatten(),
+        Err(e) => {
+            debug!(%signature, error = ?e, "Failed to poll the signature status");
+            return Status::Unknown;
+        }
+    };
+    match status {
+        Some(status) => match status.err {
+            Some(error) => Status::Done(Outcome::Failed(error)),
+            None if status.satisfies_commitment(commitment) => Status::Done(Outcome::Landed),
+            None => Status::Processed,
+        },
+        None => Status::Unknown,
+    }
+}
+
+/// Send `transaction` through the block engine when it tips one, through the RPC node
+/// otherwise or when the bundle is rejected
+async fn broadcast(
+    rpc_client: &RpcClient,
+    transaction: &VersionedTransaction,
+    jito: Option<&JitoClient>,
+) -> Result<(), OracleError> {
+    let signature = transaction.signatures[0];
+    if let Some(jito) = jito {
+        match jito.send_bundle(std::slice::from_ref(transaction)).await {
+            Ok(bundle) => {
+                METRICS.bundles.with_label_values(&["sent"]).inc();
+                debug!(%signature, %bundle, "Submitted the transaction as a bundle");
+                return Ok(());
+            }
+            Err(e) => {
+                METRICS.bundles.with_label_values(&["rejected"]).inc();
+                warn!(%signature, error = ?e, "Bundle submission failed, sending through RPC");
+            }
+        }
+    }
+    rpc_client
+        .send_transaction_with_config(
+            transaction,
+            RpcSendTransactionConfig {
+                skip_preflight: true,
+                max_retries: Some(0),
+                ..Default::default()
+            },
+        )
+        .await?;
+    Ok(())
+}
+
+/// Send `transaction` and rebroadcast it until it reaches `commitment`, fails or expires. With
+/// `jito`, the transaction (which tips it) is submitted as a bundle.
+pub async fn land(
+    rpc_client: &RpcClient,
+    transaction: &VersionedTransaction,
+    commitment: CommitmentConfig,
+    rebroadcast_interval: Duration,
+    expiry: Expiry,
+    jito: Option<&JitoClient>,
+) -> Outcome {
+    let signature = transaction.signatures[0];
+    let started = Instant::now();
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        if last_sent.map_or(true, |at| at.elapsed() >= rebroadcast_interval) {
+            if last_sent.is_some() {
+                match is_expired(rpc_client, &expiry).await {
+                    // It may have landed right before expiring
+                    Ok(true) => match status(rpc_client, &signature, commitment, true).await {
+                        Status::Done(outcome) => return outcome,
+                        Status::Processed => {}
+                        Status::Unknown => return Outcome::Expired,
+                    },
+                    Ok(false) => {}
+                    Err(e) => debug!(error = ?e, "Failed to check the transaction expiry"),
+                }
+                METRICS.transaction_rebroadcasts.inc();
+            }
+            if let Err(e) = broadcast(rpc_client, transaction, jito).await {
+                warn!(%signature, error = ?e, "Failed to broadcast the transaction");
+            }
+            last_sent = Some(Instant::now());
+        }
+        if started.elapsed() >= MAX_WATCH {
+            return Outcome::Expired;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        match status(rpc_client, &signature, commitment, false).await {
+            Status::Done(outcome) => return outcome,
+            // Waiting for the commitment, there is nothing to rebroadcast
+            Status::Processed => last_sent = Some(Instant::now()),
+            Status::Unknown => {}
+        }
+    }
+}
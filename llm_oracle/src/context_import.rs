@@ -0,0 +1,374 @@
+//! `llm_oracle context import ...`: bulk context creation.
+//!
+//! Contexts are read from a JSON array (of texts, or of `{"name", "text"}` objects) or from a CSV
+//! file with a `text` column and an optional `name` column. The import first previews the
+//! context accounts it will create, numbered by the program's counter, their rent and the
+//! transaction fees, then creates them several per transaction.
+//!
+//! Each landed transaction is recorded in a progress file next to the input
+//! (`contexts.import.json` for `contexts.json`): running the same import again after a failure
+//! resumes with the contexts not created yet. Context addresses follow the program's counter, so
+//! contexts created by someone else meanwhile shift them: a batch built on a stale counter fails
+//! and is rebuilt on the next run.
+
+use crate::admin::{
+    context_address, context_count, context_space, create_context_instruction, select_program,
+    select_signer, send, signer,
+};
+use crate::callback::LAMPORTS_PER_SIGNATURE;
+use crate::config::OracleConfig;
+use crate::OracleError;
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::sysvar;
+use solana_sdk::transaction::Transaction;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Contexts created per transaction at most, when they fit
+pub const DEFAULT_BATCH_SIZE: usize = 8;
+
+#[derive(Debug, Subcommand)]
+pub enum ContextCommand {
+    /// Create the contexts of a JSON or CSV file, resuming a previous import of it
+    Import {
+        file: PathBuf,
+        /// Oracle program of the contexts, the first configured one by default
+        #[arg(long)]
+        program: Option<Pubkey>,
+        /// Keypair file or hardware wallet URL (`usb://ledger?key=0/0`), the identity by default
+        #[arg(long)]
+        signer: Option<String>,
+        /// Contexts per transaction at most
+        #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+        /// Only print the preview
+        #[arg(long)]
+        dry_run: bool,
+        /// Don't ask for confirmation after the preview
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+/// A context to create
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContextEntry {
+    /// Label shown in the preview and the progress file
+    #[serde(default)]
+    pub name: Option<String>,
+    pub text: String,
+}
+
+impl ContextEntry {
+    fn hash(&self) -> String {
+        hex::encode(&Sha256::digest(self.text.as_bytes())[..8])
+    }
+
+    fn label(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| format!("#{}", index))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Text(String),
+    Entry(ContextEntry),
+}
+
+/// The contexts of a `.csv` file, or of a JSON file otherwise
+pub fn read_entries(path: &Path) -> Result<Vec<ContextEntry>, OracleError> {
+    let is_csv = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("csv"));
+    if !is_csv {
+        let entries: Vec<JsonEntry> = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| format!("Invalid context file {}: {}", path.display(), e))?;
+        return Ok(entries
+            .into_iter()
+            .map(|entry| match entry {
+                JsonEntry::Text(text) => ContextEntry { name: None, text },
+                JsonEntry::Entry(entry) => entry,
+            })
+            .collect());
+    }
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|header| header.trim() == name);
+    let text = column("text").ok_or_else(|| format!("{} has no `text` column", path.display()))?;
+    let name = column("name");
+    reader
+        .records()
+        .map(|record| {
+            let record = record?;
+            Ok(ContextEntry {
+                name: name
+                    .and_then(|name| record.get(name))
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+                text: record.get(text).unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Created {
+    name: Option<String>,
+    /// Hash of the text, telling whether the input changed since
+    text_hash: String,
+    context: String,
+    signature: String,
+}
+
+/// Contexts of the input already created, by their index in the input
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Progress {
+    program: String,
+    created: BTreeMap<usize, Created>,
+}
+
+impl Progress {
+    fn load(path: &Path, program: &Pubkey) -> Result<Self, OracleError> {
+        let progress = match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid progress file {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Progress {
+                program: program.to_string(),
+                created: BTreeMap::new(),
+            },
+            Err(e) => return Err(e.into()),
+        };
+        if progress.program != program.to_string() {
+            return Err(format!(
+                "{} records an import to {}, not {}",
+                path.display(),
+                progress.program,
+                program
+            )
+            .into());
+        }
+        Ok(progress)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), OracleError> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Split `pending` into batches of at most `batch_size` contexts fitting in one transaction
+fn batches<'a>(
+    program: &Pubkey,
+    payer: &Pubkey,
+    pending: &[(usize, &'a ContextEntry)],
+    batch_size: usize,
+) -> Result<Vec<Vec<(usize, &'a ContextEntry)>>, OracleError> {
+    let size = |batch: &[(usize, &ContextEntry)]| -> Result<usize, OracleError> {
+        let instructions: Vec<_> = batch
+            .iter()
+            .enumerate()
+            .map(|(position, (_, entry))| {
+                create_context_instruction(program, payer, position as u32, entry.text.clone())
+            })
+            .collect();
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(payer));
+        transaction.message.recent_blockhash = Hash::default();
+        transaction.signatures = vec![Signature::default(); 1];
+        Ok(bincode::serialized_size(&transaction)? as usize)
+    };
+    let mut batches: Vec<Vec<(usize, &ContextEntry)>> = Vec::new();
+    let mut batch = Vec::new();
+    for &(index, entry) in pending {
+        if size(&[(index, entry)])? > PACKET_DATA_SIZE {
+            return Err(format!(
+                "Context {} ({} bytes) doesn't fit in a transaction",
+                entry.label(index),
+                entry.text.len()
+            )
+            .into());
+        }
+        batch.push((index, entry));
+        if batch.len() > batch_size.max(1) || size(&batch)? > PACKET_DATA_SIZE {
+            batch.pop();
+            batches.push(std::mem::take(&mut batch));
+            batch.push((index, entry));
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+fn confirm(prompt: &str) -> Result<bool, OracleError> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
+
+async fn import(
+    file: PathBuf,
+    program: Option<Pubkey>,
+    signer_url: Option<String>,
+    batch_size: usize,
+    dry_run: bool,
+    yes: bool,
+) -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let rpc_client =
+        RpcClient::new_with_commitment(config.rpc_url.clone(), CommitmentConfig::confirmed());
+    let program = select_program(&config, program)?;
+    let entries = read_entries(&file)?;
+    let progress_path = file.with_extension("import.json");
+    let mut progress = Progress::load(&progress_path, &program)?;
+    for (index, created) in &progress.created {
+        if entries.get(*index).map(ContextEntry::hash).as_ref() != Some(&created.text_hash) {
+            return Err(format!(
+                "{} changed since {} was recorded: context {} differs",
+                file.display(),
+                progress_path.display(),
+                index
+            )
+            .into());
+        }
+    }
+    let pending: Vec<(usize, &ContextEntry)> = entries
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !progress.created.contains_key(index))
+        .collect();
+    if pending.is_empty() {
+        println!(
+            "All {} contexts of {} are created",
+            entries.len(),
+            file.display()
+        );
+        return Ok(());
+    }
+
+    let selected = select_signer(signer_url.as_deref())?;
+    let signer = signer(&selected, &config);
+    let payer = signer.pubkey();
+    let batches = batches(&program, &payer, &pending, batch_size)?;
+    let rent: Rent =
+        bincode::deserialize(&rpc_client.get_account_data(&sysvar::rent::id()).await?)?;
+    let count = context_count(&rpc_client, &program).await?;
+
+    // Preview
+    let mut total_rent = 0;
+    for (position, (index, entry)) in pending.iter().enumerate() {
+        let space = context_space(&entry.text);
+        let lamports = rent.minimum_balance(space);
+        total_rent += lamports;
+        println!(
+            "{}\t{}\t{} bytes\t{} SOL rent",
+            entry.label(*index),
+            context_address(&program, count + position as u32),
+            space,
+            lamports_to_sol(lamports)
+        );
+    }
+    let fees = LAMPORTS_PER_SIGNATURE * batches.len() as u64;
+    let balance = rpc_client.get_balance(&payer).await?;
+    println!(
+        "{} context(s) to create ({} already created) in {} transaction(s): {} SOL rent, {} SOL \
+         fees, paid by {} holding {} SOL. Addresses assume no other context is created meanwhile.",
+        pending.len(),
+        progress.created.len(),
+        batches.len(),
+        lamports_to_sol(total_rent),
+        lamports_to_sol(fees),
+        payer,
+        lamports_to_sol(balance)
+    );
+    if balance < total_rent + fees {
+        return Err("The payer can't cover the rent and fees".into());
+    }
+    if dry_run || !(yes || confirm("Create them?")?) {
+        return Ok(());
+    }
+
+    let mut done = progress.created.len();
+    for batch in batches {
+        // Numbered from the counter as it is now
+        let count = context_count(&rpc_client, &program).await?;
+        let instructions: Vec<_> = batch
+            .iter()
+            .enumerate()
+            .map(|(position, (_, entry))| {
+                create_context_instruction(
+                    &program,
+                    &payer,
+                    count + position as u32,
+                    entry.text.clone(),
+                )
+            })
+            .collect();
+        let signature = match send(&rpc_client, signer, &instructions).await {
+            Ok(signature) => signature,
+            Err(e) => {
+                return Err(format!(
+                    "Import stopped after {} of {} contexts: {}. Run it again to resume.",
+                    done,
+                    entries.len(),
+                    e
+                )
+                .into())
+            }
+        };
+        for (position, (index, entry)) in batch.iter().enumerate() {
+            progress.created.insert(
+                *index,
+                Created {
+                    name: entry.name.clone(),
+                    text_hash: entry.hash(),
+                    context: context_address(&program, count + position as u32).to_string(),
+                    signature: signature.to_string(),
+                },
+            );
+        }
+        progress.save(&progress_path)?;
+        done += batch.len();
+        println!(
+            "[{}/{}] created {} context(s) ({})",
+            done,
+            entries.len(),
+            batch.len(),
+            signature
+        );
+    }
+    println!(
+        "Created the contexts of {}, listed in {}",
+        file.display(),
+        progress_path.display()
+    );
+    Ok(())
+}
+
+pub async fn run(command: ContextCommand) -> Result<(), OracleError> {
+    match command {
+        ContextCommand::Import {
+            file,
+            program,
+            signer,
+            batch_size,
+            dry_run,
+            yes,
+        } => import(file, program, signer, batch_size, dry_run, yes).await,
+    }
+}
@@ -0,0 +1,132 @@
+//! Per-context model settings.
+//!
+//! The creator of a context can store its [`LlmSettings`] (model, temperature, max tokens and
+//! system prompt) in the `ContextSettings` PDA of the context, with the program's
+//! `create_context_settings` and `update_context_settings`. With `context_settings.enabled`
+//! (`CONTEXT_SETTINGS`), the oracle reads them with each interaction and answers with them instead
+//! of the settings of the program. The operator keeps the last word:
+//!
+//! - models outside `context_settings.models` (`CONTEXT_SETTINGS_MODELS`) are ignored, so no
+//!   model can be chosen until some are listed
+//! - max tokens are capped at `context_settings.max_tokens` (`CONTEXT_SETTINGS_MAX_TOKENS`),
+//!   `llm.max_tokens` by default
+//! - temperatures outside 0 to 2 are ignored
+//!
+//! A provider is built for each distinct settings, with the fallbacks of the program and without
+//! consensus, and kept for the next interactions. It only differs from the program's in its
+//! generation settings: it draws from the same concurrency limits and daily budgets, which every
+//! client of an LLM provider shares. Answers of contexts with settings aren't kept in the
+//! response cache.
+
+use crate::config::{ContextSettingsConfig, LlmConfig};
+use crate::oracle::Oracle;
+use crate::providers::{self, ChatProvider};
+use crate::OracleError;
+use anchor_lang::AccountDeserialize;
+use solana_gpt_oracle::{ContextSettings, LlmSettings};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info};
+
+/// Providers kept at most; all are dropped when there are more
+pub const MAX_PROVIDERS: usize = 256;
+const MAX_TEMPERATURE: f32 = 2.0;
+
+/// Address of the settings of `context`
+pub fn settings_address(program: &Pubkey, context: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[ContextSettings::seed(), context.as_ref()], program).0
+}
+
+/// Providers of the contexts with settings
+pub struct ContextProviders {
+    config: ContextSettingsConfig,
+    /// By program and settings account data; `None` for settings that change nothing
+    providers: Mutex<HashMap<(Pubkey, Vec<u8>), Option<Arc<dyn ChatProvider>>>>,
+}
+
+impl ContextProviders {
+    pub fn new(config: ContextSettingsConfig) -> Self {
+        Self {
+            config,
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Apply the settings of a context to `llm` within the operator's bounds, returning whether
+    /// any applied
+    pub fn apply(&self, llm: &mut LlmConfig, settings: &LlmSettings) -> bool {
+        let mut applied = false;
+        if let Some(model) = &settings.model {
+            if self.config.models.contains(model) {
+                llm.model = Some(model.clone());
+                applied = true;
+            } else {
+                debug!(model, "Model of the context settings not allowed, ignored");
+            }
+        }
+        if let Some(temperature) = settings.temperature {
+            if (0.0..=MAX_TEMPERATURE).contains(&temperature) {
+                llm.temperature = Some(temperature);
+                applied = true;
+            } else {
+                debug!(
+                    temperature,
+                    "Temperature of the context settings out of range, ignored"
+                );
+            }
+        }
+        if let Some(max_tokens) = settings.max_tokens {
+            llm.max_tokens = max_tokens.clamp(1, self.config.max_tokens);
+            applied = true;
+        }
+        if let Some(system_prompt) = &settings.system_prompt {
+            llm.system_prompt = Some(system_prompt.clone());
+            applied = true;
+        }
+        if applied {
+            llm.consensus_providers.clear();
+        }
+        applied
+    }
+
+    /// The provider answering the interactions of `context` with its settings, `None` when it
+    /// has none that apply
+    pub async fn provider(
+        &self,
+        oracle: &Oracle,
+        program: &Pubkey,
+        context: &Pubkey,
+    ) -> Result<Option<Arc<dyn ChatProvider>>, OracleError> {
+        let rpc_client = &oracle.rpc_client;
+        let account = rpc_client
+            .get_account_with_commitment(
+                &settings_address(program, context),
+                rpc_client.commitment(),
+            )
+            .await?
+            .value
+            .filter(|account| account.owner == *program);
+        let Some(account) = account else {
+            return Ok(None);
+        };
+        let key = (*program, account.data);
+        if let Some(provider) = self.providers.lock().unwrap().get(&key) {
+            return Ok(provider.clone());
+        }
+        let settings = ContextSettings::try_deserialize(&mut key.1.as_slice())?;
+        let mut llm = oracle.config.program_llm(oracle.config.program(program)?);
+        let provider = if self.apply(&mut llm, &settings.settings) {
+            info!(%context, model = ?llm.model, "Building the provider of a context");
+            Some(Arc::from(providers::from_config(&llm)?))
+        } else {
+            None
+        };
+        let mut providers = self.providers.lock().unwrap();
+        if providers.len() >= MAX_PROVIDERS {
+            providers.clear();
+        }
+        providers.insert(key, provider.clone());
+        Ok(provider)
+    }
+}
@@ -0,0 +1,243 @@
+//! Context kill switch.
+//!
+//! The program has no flag to retire a context: closing the context account (or reassigning it
+//! away from the oracle program) deactivates it. The oracle subscribes to every context account
+//! it answers interactions of, and as soon as one is closed it stops answering its
+//! interactions: queued ones are abandoned before the LLM is called, and callbacks not sent yet
+//! are dropped. What the oracle keeps about the context is purged along the way: the
+//! conversation history, game state and dead letters of its interactions, its response time
+//! estimate and its chunks in the context retrieval index.
+//!
+//! Subscriptions go through `solana.websocket_url`. After a reconnection the watched contexts are
+//! fetched once, catching closures missed while disconnected, and an interaction whose context
+//! can't be fetched at all deactivates it too.
+
+use crate::metrics::METRICS;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::OracleError;
+use futures::stream::{self, SelectAll};
+use futures::StreamExt;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// A context account being watched
+struct Watched {
+    program: Pubkey,
+    /// Interactions of the context seen by this process
+    interactions: HashSet<Pubkey>,
+}
+
+#[derive(Default)]
+struct State {
+    watched: HashMap<Pubkey, Watched>,
+    deactivated: HashSet<Pubkey>,
+}
+
+pub struct ContextWatch {
+    state: Mutex<State>,
+    /// Contexts seen for the first time, to subscribe to
+    new_contexts: mpsc::UnboundedSender<Pubkey>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Pubkey>>>,
+}
+
+impl Default for ContextWatch {
+    fn default() -> Self {
+        let (new_contexts, receiver) = mpsc::unbounded_channel();
+        Self {
+            state: Mutex::new(State::default()),
+            new_contexts,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+}
+
+impl ContextWatch {
+    pub fn is_deactivated(&self, context: &Pubkey) -> bool {
+        self.state.lock().unwrap().deactivated.contains(context)
+    }
+
+    /// Watch `context`, a context account of `program`, while `interaction` is answered
+    pub fn watch(&self, program: &Pubkey, context: &Pubkey, interaction: &Pubkey) {
+        let mut state = self.state.lock().unwrap();
+        if state.deactivated.contains(context) {
+            return;
+        }
+        let watched = state.watched.entry(*context).or_insert_with(|| {
+            let _ = self.new_contexts.send(*context);
+            Watched {
+                program: *program,
+                interactions: HashSet::new(),
+            }
+        });
+        watched.interactions.insert(*interaction);
+    }
+
+    fn program(&self, context: &Pubkey) -> Option<Pubkey> {
+        let state = self.state.lock().unwrap();
+        state.watched.get(context).map(|watched| watched.program)
+    }
+
+    fn contexts(&self) -> Vec<Pubkey> {
+        self.state.lock().unwrap().watched.keys().copied().collect()
+    }
+}
+
+/// Stop answering the interactions of `context`, a context account of `program` found closed,
+/// and purge what is kept about it
+pub async fn deactivate(oracle: &Oracle, program: &Pubkey, context: &Pubkey) {
+    let watched = {
+        let mut state = oracle.contexts.state.lock().unwrap();
+        if !state.deactivated.insert(*context) {
+            return;
+        }
+        state.watched.remove(context)
+    };
+    let interactions = watched
+        .map(|watched| watched.interactions)
+        .unwrap_or_default();
+    info!(%context, %program, interactions = interactions.len(), "Context closed, deactivating");
+    METRICS.contexts_deactivated.inc();
+    {
+        let mut memory = oracle.interaction_memory.lock().unwrap();
+        for interaction in &interactions {
+            if let Err(e) = memory.close(interaction) {
+                warn!(%interaction, error = ?e, "Failed to drop the conversation history");
+            }
+            if let Err(e) = memory.set_game_state(interaction, None) {
+                warn!(%interaction, error = ?e, "Failed to drop the game state");
+            }
+        }
+    }
+    for interaction in &interactions {
+        if let Err(e) = oracle.dlq.remove(interaction) {
+            warn!(%interaction, error = ?e, "Failed to drop the dead letter");
+        }
+    }
+    oracle.latency.forget(context);
+    #[cfg(feature = "rag")]
+    if let Some(index) = &oracle.context_index {
+        if let Err(e) = index.remove(program, context).await {
+            warn!(%context, error = ?e, "Failed to drop the context from the retrieval index");
+        }
+    }
+    notify::emit(
+        Event::new(
+            Severity::Info,
+            "context_deactivated",
+            format!("Context {} deactivated", context),
+            format!(
+                "The context account was closed: its interactions are no longer answered and \
+                 {} conversation(s) were purged.",
+                interactions.len()
+            ),
+        )
+        .with_key(context.to_string()),
+    );
+}
+
+/// Whether the account of a context of `program` was closed: emptied, or no longer owned by
+/// the program
+fn closed(program: &Pubkey, lamports: u64, owner: &str) -> bool {
+    lamports == 0 || owner != program.to_string()
+}
+
+/// Deactivate the watched contexts that were closed, e.g. while the subscription was down
+async fn check(oracle: &Oracle, contexts: &[Pubkey]) -> Result<(), OracleError> {
+    for contexts in contexts.chunks(100) {
+        let accounts = oracle.rpc_client.get_multiple_accounts(contexts).await?;
+        for (context, account) in contexts.iter().zip(accounts) {
+            let Some(program) = oracle.contexts.program(context) else {
+                continue;
+            };
+            let closed = account.map_or(true, |account| {
+                closed(&program, account.lamports, &account.owner.to_string())
+            });
+            if closed {
+                deactivate(oracle, &program, context).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn subscribe(
+    oracle: &Oracle,
+    receiver: &mut mpsc::UnboundedReceiver<Pubkey>,
+) -> Result<(), OracleError> {
+    let pubsub_client = PubsubClient::new(&oracle.config.websocket_url).await?;
+    let config = || RpcAccountInfoConfig {
+        commitment: Some(CommitmentConfig::confirmed()),
+        encoding: Some(UiAccountEncoding::Base64),
+        ..Default::default()
+    };
+    // Each stream ends with `None`, when the connection drops
+    let mut updates = SelectAll::new();
+    let contexts = oracle.contexts.contexts();
+    for context in &contexts {
+        let (stream, _) = pubsub_client
+            .account_subscribe(context, Some(config()))
+            .await?;
+        let context = *context;
+        updates.push(
+            stream
+                .map(move |update| Some((context, update.value)))
+                .chain(stream::once(async { None }))
+                .boxed(),
+        );
+    }
+    check(oracle, &contexts).await?;
+    info!(contexts = contexts.len(), "Watching context accounts");
+
+    loop {
+        tokio::select! {
+            Some(context) = receiver.recv() => {
+                let (stream, _) = pubsub_client
+                    .account_subscribe(&context, Some(config()))
+                    .await?;
+                updates.push(
+                    stream
+                        .map(move |update| Some((context, update.value)))
+                        .chain(stream::once(async { None }))
+                        .boxed(),
+                );
+                // Closed before the subscription started
+                check(oracle, &[context]).await?;
+            }
+            Some(update) = updates.next() => {
+                let Some((context, account)) = update else {
+                    return Err("Context subscription closed".into());
+                };
+                let Some(program) = oracle.contexts.program(&context) else {
+                    continue;
+                };
+                if closed(&program, account.lamports, &account.owner) {
+                    deactivate(oracle, &program, &context).await;
+                }
+            }
+        }
+    }
+}
+
+/// Watch the context accounts of answered interactions until the process stops
+pub async fn run(oracle: Arc<Oracle>) {
+    let Some(mut receiver) = oracle.contexts.receiver.lock().unwrap().take() else {
+        return;
+    };
+    loop {
+        if let Err(e) = subscribe(&oracle, &mut receiver).await {
+            warn!(error = ?e, "Context subscription failed");
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
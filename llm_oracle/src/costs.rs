@@ -0,0 +1,282 @@
+//! Cost accounting.
+//!
+//! What answering each interaction cost is attributed to its context account: the tokens sent
+//! to and received from each LLM provider, as the provider reports them in its responses, or
+//! counted with the provider's tokenizer (an estimate of what it bills) when it doesn't, and the
+//! fees of its callback transactions (a share of the fee for batched callbacks). Costs are
+//! aggregated per context and UTC day in a sled database at `COSTS_PATH` (under the deployment
+//! name when set), printed by `llm_oracle costs`, and exported as the `llm_tokens_total`,
+//! `context_tokens_total` and `context_fee_lamports_total` metrics.
+//!
+//! Lamports refunded to the creators of unanswered interactions are added to the costs of their
+//! context, see [`crate::refunds`].
+//!
+//! Tokens are collected by the [`Meter`] of the interaction being processed: providers built by
+//! [`crate::providers::from_config`] report every call to it with [`record_tokens`]. Clients
+//! report the usage read from a response with [`report_usage`]; the estimate is still made, and
+//! how far it is from the usage is exported as `llm_token_estimate_ratio`.
+
+use crate::config::deployment_path;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::future::Future;
+use tracing::warn;
+
+pub const DEFAULT_COSTS_PATH: &str = "./oracle-costs";
+
+/// Tokens of the calls to one provider
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tokens {
+    pub prompt: u64,
+    pub completion: u64,
+}
+
+impl Tokens {
+    pub fn total(&self) -> u64 {
+        self.prompt + self.completion
+    }
+
+    fn add(&mut self, other: &Tokens) {
+        self.prompt += other.prompt;
+        self.completion += other.completion;
+    }
+}
+
+/// What was spent on one interaction
+#[derive(Debug, Default)]
+pub struct Meter {
+    pub context: Option<Pubkey>,
+    /// By provider name
+    pub tokens: BTreeMap<String, Tokens>,
+    pub fee_lamports: u64,
+}
+
+tokio::task_local! {
+    static METER: RefCell<Meter>;
+    /// Tokens reported by the provider for the call being made
+    static REPORTED: RefCell<Option<Tokens>>;
+}
+
+/// Run `future`, collecting what it spends in a [`Meter`]
+pub async fn metered<F: Future>(future: F) -> (F::Output, Meter) {
+    METER
+        .scope(RefCell::new(Meter::default()), async move {
+            let output = future.await;
+            (output, METER.with(RefCell::take))
+        })
+        .await
+}
+
+/// Run a provider call, returning the tokens reported for it with [`report_usage`], if any
+pub async fn reported_usage<F: Future>(call: F) -> (F::Output, Option<Tokens>) {
+    REPORTED
+        .scope(RefCell::new(None), async move {
+            let output = call.await;
+            (output, REPORTED.with(RefCell::take))
+        })
+        .await
+}
+
+/// Report the tokens a provider billed for a request, as read from its response
+pub fn report_usage(tokens: Tokens) {
+    let _ = REPORTED.try_with(|reported| {
+        reported
+            .borrow_mut()
+            .get_or_insert_with(Tokens::default)
+            .add(&tokens)
+    });
+}
+
+/// Compare the tokens counted with `provider`'s tokenizer to those it reported
+pub fn reconcile(provider: &str, estimated: Tokens, reported: Tokens) {
+    for (kind, estimated, reported) in [
+        ("prompt", estimated.prompt, reported.prompt),
+        ("completion", estimated.completion, reported.completion),
+    ] {
+        if reported > 0 {
+            METRICS
+                .llm_token_estimate_ratio
+                .with_label_values(&[provider, kind])
+                .observe(estimated as f64 / reported as f64);
+        }
+    }
+}
+
+/// Tokens spent so far by the interaction being processed, by provider
+pub fn metered_tokens() -> BTreeMap<String, Tokens> {
+    METER
+        .try_with(|meter| meter.borrow().tokens.clone())
+        .unwrap_or_default()
+}
+
+/// Attribute what the current interaction spends to `context`
+pub fn attribute(context: &Pubkey) {
+    let _ = METER.try_with(|meter| meter.borrow_mut().context = Some(*context));
+}
+
+/// Count the tokens of a call to `provider`, for the interaction being processed
+pub fn record_tokens(provider: &str, tokens: Tokens) {
+    METRICS
+        .llm_tokens
+        .with_label_values(&[provider, "prompt"])
+        .inc_by(tokens.prompt);
+    METRICS
+        .llm_tokens
+        .with_label_values(&[provider, "completion"])
+        .inc_by(tokens.completion);
+    let _ = METER.try_with(|meter| {
+        meter
+            .borrow_mut()
+            .tokens
+            .entry(provider.to_string())
+            .or_default()
+            .add(&tokens)
+    });
+}
+
+/// Count the fee of a callback of the interaction being processed
+pub fn record_fee(lamports: u64) {
+    let _ = METER.try_with(|meter| meter.borrow_mut().fee_lamports += lamports);
+}
+
+/// Costs of a context over a UTC day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DailyCost {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub context: String,
+    /// Interactions processed
+    pub interactions: u64,
+    /// By provider name
+    pub tokens: BTreeMap<String, Tokens>,
+    pub fee_lamports: u64,
+    /// Unanswered interactions refunded
+    #[serde(default)]
+    pub refunds: u64,
+    #[serde(default)]
+    pub refunded_lamports: u64,
+}
+
+impl DailyCost {
+    pub fn total_tokens(&self) -> u64 {
+        self.tokens.values().map(Tokens::total).sum()
+    }
+
+    /// Price of the tokens given the price per thousand tokens of each provider; providers
+    /// without a price count as free
+    pub fn usd(&self, usd_per_1k_tokens: &HashMap<String, f64>) -> f64 {
+        self.tokens
+            .iter()
+            .map(|(provider, tokens)| {
+                tokens.total() as f64 / 1000.0
+                    * usd_per_1k_tokens.get(provider).copied().unwrap_or_default()
+            })
+            .sum()
+    }
+}
+
+/// Costs per context and day. Keys are the day followed by the context, so a day is a prefix.
+pub struct CostLedger {
+    db: sled::Db,
+}
+
+impl CostLedger {
+    pub fn open(path: &str) -> Result<Self, OracleError> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// Open the ledger at `COSTS_PATH`
+    pub fn from_env() -> Result<Self, OracleError> {
+        let path =
+            deployment_path(&env::var("COSTS_PATH").unwrap_or(DEFAULT_COSTS_PATH.to_string()));
+        Self::open(&path)
+    }
+
+    /// Add what an interaction spent to today's costs of its context
+    pub fn record(&self, meter: &Meter) {
+        let Some(context) = meter.context else {
+            return;
+        };
+        let tokens: u64 = meter.tokens.values().map(Tokens::total).sum();
+        METRICS
+            .context_tokens
+            .with_label_values(&[&context.to_string()])
+            .inc_by(tokens);
+        METRICS
+            .context_fee_lamports
+            .with_label_values(&[&context.to_string()])
+            .inc_by(meter.fee_lamports);
+        let recorded = self.update(Utc::now().date_naive(), &context, |cost| {
+            cost.interactions += 1;
+            for (provider, tokens) in &meter.tokens {
+                cost.tokens.entry(provider.clone()).or_default().add(tokens);
+            }
+            cost.fee_lamports += meter.fee_lamports;
+        });
+        if let Err(e) = recorded {
+            warn!(%context, error = ?e, "Failed to record the interaction costs");
+        }
+    }
+
+    /// Add a refund of `lamports` to today's costs of `context`
+    pub fn record_refund(&self, context: &Pubkey, lamports: u64) {
+        let recorded = self.update(Utc::now().date_naive(), context, |cost| {
+            cost.refunds += 1;
+            cost.refunded_lamports += lamports;
+        });
+        if let Err(e) = recorded {
+            warn!(%context, error = ?e, "Failed to record the refund");
+        }
+    }
+
+    fn update(
+        &self,
+        day: NaiveDate,
+        context: &Pubkey,
+        apply: impl Fn(&mut DailyCost),
+    ) -> Result<(), OracleError> {
+        let key = format!("{}/{}", day, context);
+        let mut failed = None;
+        self.db.update_and_fetch(key.as_bytes(), |old| {
+            let mut cost = match old.map(serde_json::from_slice::<DailyCost>) {
+                Some(Ok(cost)) => cost,
+                Some(Err(e)) => {
+                    failed = Some(e.to_string());
+                    DailyCost::default()
+                }
+                None => DailyCost::default(),
+            };
+            cost.day = day.to_string();
+            cost.context = context.to_string();
+            apply(&mut cost);
+            serde_json::to_vec(&cost).ok()
+        })?;
+        match failed {
+            Some(e) => Err(format!("Corrupt costs of {}: {}, starting over", key, e).into()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), OracleError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Costs from `since` on, by day then context
+    pub fn since(&self, since: NaiveDate) -> Result<Vec<DailyCost>, OracleError> {
+        let mut costs = Vec::new();
+        for entry in self.db.range(since.to_string().as_bytes()..) {
+            let (_, bytes) = entry?;
+            costs.push(serde_json::from_slice(&bytes)?);
+        }
+        Ok(costs)
+    }
+}
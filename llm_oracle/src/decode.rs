@@ -0,0 +1,245 @@
+//! Zero-copy decoding of `Interaction` accounts.
+//!
+//! Every account update goes through the worker pool, but most only need `is_processed`, the
+//! context, the creator and the text. [`InteractionView`] reads those in place from the account
+//! data instead of Borsh-deserializing the whole account; [`InteractionView::decode`] does the
+//! full decode once an interaction is actually answered.
+//!
+//! Interactions created before `created_at` was added to the layout are read with a `created_at`
+//! of 0. Those without account metas end at `is_processed` and don't deserialize in the program
+//! until grown with `upgrade_interaction`, see [`crate::processor::upgrade_legacy`].
+
+use crate::OracleError;
+use anchor_lang::{AccountDeserialize, AnchorDeserialize, Discriminator};
+use solana_gpt_oracle::ContextAccount;
+use solana_sdk::pubkey::Pubkey;
+
+const DISCRIMINATOR_LEN: usize = 8;
+const PUBKEY_LEN: usize = 32;
+const LEN_PREFIX: usize = 4;
+/// Borsh size of `solana_gpt_oracle::AccountMeta`: pubkey, is_signer, is_writable
+const ACCOUNT_META_LEN: usize = PUBKEY_LEN + 2;
+
+const CONTEXT_OFFSET: usize = DISCRIMINATOR_LEN;
+const USER_OFFSET: usize = CONTEXT_OFFSET + PUBKEY_LEN;
+const TEXT_OFFSET: usize = USER_OFFSET + PUBKEY_LEN;
+/// Size of an interaction with an empty text and no account metas; the layout in between is
+/// `callback_program_id`, `callback_discriminator` and the `callback_account_metas` length, then
+/// `is_processed` and `created_at`. Matches `Interaction::space` in the program.
+const FIXED_LEN: usize = TEXT_OFFSET + LEN_PREFIX + PUBKEY_LEN + 8 + LEN_PREFIX + 1 + 8;
+/// Size of such an interaction in the layout preceding `created_at`
+const LEGACY_FIXED_LEN: usize = FIXED_LEN - 8;
+
+/// Borrowed view of the fields of an `Interaction` account the hot path reads
+#[derive(Debug, Clone, Copy)]
+pub struct InteractionView<'a> {
+    data: &'a [u8],
+    pub context: Pubkey,
+    /// Creator of the interaction
+    pub user: Pubkey,
+    pub text: &'a str,
+    pub is_processed: bool,
+    /// Unix timestamp of the request, 0 for interactions created before it was recorded
+    pub created_at: i64,
+    /// The data ends at `is_processed`, in the layout preceding `created_at`
+    legacy: bool,
+}
+
+fn read_len(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset + LEN_PREFIX)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Option<Pubkey> {
+    let bytes: [u8; PUBKEY_LEN] = data.get(offset..offset + PUBKEY_LEN)?.try_into().ok()?;
+    Some(Pubkey::new_from_array(bytes))
+}
+
+impl<'a> InteractionView<'a> {
+    /// Read an interaction in place. `None` if the data isn't a well-formed `Interaction`.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < LEGACY_FIXED_LEN
+            || data[..DISCRIMINATOR_LEN] != solana_gpt_oracle::Interaction::DISCRIMINATOR[..]
+        {
+            return None;
+        }
+        let context = read_pubkey(data, CONTEXT_OFFSET)?;
+        let user = read_pubkey(data, USER_OFFSET)?;
+        let text_len = read_len(data, TEXT_OFFSET)?;
+        let text_start = TEXT_OFFSET + LEN_PREFIX;
+        let text =
+            std::str::from_utf8(data.get(text_start..text_start.checked_add(text_len)?)?).ok()?;
+
+        let metas_offset = text_start + text_len + PUBKEY_LEN + 8;
+        let metas_len = read_len(data, metas_offset)?;
+        let processed_offset = metas_len
+            .checked_mul(ACCOUNT_META_LEN)?
+            .checked_add(metas_offset + LEN_PREFIX)?;
+        let is_processed = match data.get(processed_offset)? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        // Legacy interactions with account metas are padded past `is_processed`, which reads as
+        // a `created_at` of 0
+        let legacy = data.len() == processed_offset + 1;
+        let created_at = match legacy {
+            true => 0,
+            false => {
+                let created_at = data.get(processed_offset + 1..processed_offset + 9)?;
+                i64::from_le_bytes(created_at.try_into().ok()?)
+            }
+        };
+        Some(Self {
+            data,
+            context,
+            user,
+            text,
+            is_processed,
+            created_at,
+            legacy,
+        })
+    }
+
+    /// Fully deserialize the interaction, for the fields the view doesn't expose
+    pub fn decode(&self) -> Result<solana_gpt_oracle::Interaction, OracleError> {
+        if self.legacy {
+            let mut data = self.data.to_vec();
+            data.extend_from_slice(&0i64.to_le_bytes());
+            return Ok(solana_gpt_oracle::Interaction::try_deserialize_unchecked(
+                &mut &data[..],
+            )?);
+        }
+        Ok(solana_gpt_oracle::Interaction::try_deserialize_unchecked(
+            &mut &self.data[..],
+        )?)
+    }
+}
+
+/// Decode a `ContextAccount`, created before or after its creator was recorded; the authority of
+/// the former is the default pubkey. `None` if the data isn't a context.
+pub fn decode_context(data: &[u8]) -> Option<ContextAccount> {
+    let mut rest = data.strip_prefix(ContextAccount::DISCRIMINATOR)?;
+    let text = String::deserialize(&mut rest).ok()?;
+    let authority = read_pubkey(rest, 0).unwrap_or_default();
+    Some(ContextAccount { text, authority })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InteractionView;
+    use anchor_lang::AccountSerialize;
+    use solana_gpt_oracle::{AccountMeta, Interaction};
+    use solana_sdk::pubkey::Pubkey;
+
+    fn interaction(text: &str, metas: usize, is_processed: bool) -> Interaction {
+        Interaction {
+            context: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+            text: text.to_string(),
+            callback_program_id: Pubkey::new_unique(),
+            callback_discriminator: [7; 8],
+            callback_account_metas: (0..metas)
+                .map(|i| AccountMeta {
+                    pubkey: Pubkey::new_unique(),
+                    is_signer: false,
+                    is_writable: i % 2 == 0,
+                })
+                .collect(),
+            is_processed,
+            created_at: 1_700_000_000,
+        }
+    }
+
+    /// The account data the program writes: the serialized interaction, zero-padded to its space
+    fn account_data(interaction: &Interaction) -> Vec<u8> {
+        let mut data = Vec::new();
+        interaction.try_serialize(&mut data).unwrap();
+        let space = Interaction::space(&interaction.text, interaction.callback_account_metas.len());
+        assert!(data.len() <= space);
+        data.resize(space, 0);
+        data
+    }
+
+    #[test]
+    fn parses_what_the_program_serializes() {
+        for (text, metas, is_processed) in [
+            ("", 0, false),
+            ("What is the capital of France?", 0, false),
+            ("Réponds en français 🦀", 3, true),
+        ] {
+            let interaction = interaction(text, metas, is_processed);
+            let data = account_data(&interaction);
+            let view = InteractionView::parse(&data).expect("well-formed interaction");
+            assert_eq!(view.context, interaction.context);
+            assert_eq!(view.user, interaction.user);
+            assert_eq!(view.text, interaction.text);
+            assert_eq!(view.is_processed, interaction.is_processed);
+            assert_eq!(view.created_at, interaction.created_at);
+
+            let decoded = view.decode().unwrap();
+            assert_eq!(
+                decoded.callback_account_metas.len(),
+                interaction.callback_account_metas.len()
+            );
+            assert_eq!(decoded.callback_program_id, interaction.callback_program_id);
+        }
+    }
+
+    #[test]
+    fn rejects_other_accounts() {
+        let mut data = account_data(&interaction("hello", 1, false));
+        data[0] ^= 1;
+        assert!(InteractionView::parse(&data).is_none());
+        assert!(InteractionView::parse(&[]).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_data() {
+        let data = account_data(&interaction("hello", 2, false));
+        let mut serialized = Vec::new();
+        interaction("hello", 2, false)
+            .try_serialize(&mut serialized)
+            .unwrap();
+        // Up to `is_processed` is the legacy layout, tested below
+        let legacy = serialized.len() - 8;
+        for len in (0..serialized.len()).filter(|len| *len != legacy) {
+            assert!(
+                InteractionView::parse(&data[..len]).is_none(),
+                "len {}",
+                len
+            );
+        }
+
+        // A text length running past the end of the account
+        let mut data = account_data(&interaction("hello", 0, false));
+        data[72..76].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(InteractionView::parse(&data).is_none());
+    }
+
+    #[test]
+    fn parses_the_layout_preceding_created_at() {
+        // Without account metas, the account ends at `is_processed`
+        let asked = interaction("What is the capital of France?", 0, true);
+        let mut data = Vec::new();
+        asked.try_serialize(&mut data).unwrap();
+        data.truncate(data.len() - 8);
+        let view = InteractionView::parse(&data).expect("legacy interaction");
+        assert_eq!(view.text, asked.text);
+        assert!(view.is_processed);
+        assert_eq!(view.created_at, 0);
+        let decoded = view.decode().unwrap();
+        assert_eq!(decoded.created_at, 0);
+        assert_eq!(decoded.callback_program_id, asked.callback_program_id);
+
+        // With account metas, the padding of the legacy space is read as `created_at`
+        let asked = interaction("hello", 2, false);
+        let mut data = Vec::new();
+        asked.try_serialize(&mut data).unwrap();
+        data.truncate(data.len() - 8);
+        data.resize(Interaction::space(&asked.text, 2) - 8, 0);
+        let view = InteractionView::parse(&data).expect("legacy interaction");
+        assert_eq!(view.created_at, 0);
+        assert_eq!(view.decode().unwrap().callback_account_metas.len(), 2);
+    }
+}
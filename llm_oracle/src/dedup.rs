@@ -0,0 +1,590 @@
+//! Ledger of interactions.
+//!
+//! An interaction can be delivered again after it was answered but before its `is_processed`
+//! update is seen: by the gap-fill after a reconnect or a restart, or by updates queued behind
+//! the one being answered. [`ProcessedSet`] records the [`InteractionStatus`] of every
+//! interaction and the signatures of its callbacks, keyed by interaction account and prompt so
+//! a rewritten account asking a new question still goes through, and is consulted before
+//! answering. Entries are changed with a compare-and-swap, so the workers, recovery and
+//! reconciliation can't overwrite each other's transitions.
+//!
+//! The ledger is persisted in a sled database at `DEDUP_PATH` (under the deployment name when
+//! set), so it survives restarts. It is bounded: the least recently used entries are evicted
+//! past `capacity` in memory, and [`run`] compacts the store back to `capacity` entries every
+//! [`COMPACTION_INTERVAL`], evicting the oldest settled ones. Entries still in flight are never
+//! evicted, whatever their age.
+
+use crate::config::deployment_path;
+use crate::metrics::METRICS;
+use crate::monitor::MONITOR;
+use crate::oracle::Oracle;
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_DEDUP_CAPACITY: usize = 100_000;
+pub const DEFAULT_DEDUP_PATH: &str = "./oracle-dedup";
+/// Time between two compactions of the persistent store
+pub const COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interaction pubkey followed by the SHA-256 of the prompt
+type Key = [u8; 64];
+
+fn key(interaction: &Pubkey, text: &str) -> Key {
+    hash_key(interaction, &prompt_hash(text))
+}
+
+fn hash_key(interaction: &Pubkey, prompt_hash: &[u8; 32]) -> Key {
+    let mut key = [0; 64];
+    key[..32].copy_from_slice(interaction.as_ref());
+    key[32..].copy_from_slice(prompt_hash);
+    key
+}
+
+/// SHA-256 of a prompt, as keyed in the ledger
+pub fn prompt_hash(text: &str) -> [u8; 32] {
+    Sha256::digest(text.as_bytes()).into()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Ledger record of an interaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub status: InteractionStatus,
+    /// Signatures of the callback transactions that landed, in order
+    #[serde(default)]
+    pub signatures: Vec<String>,
+    /// Signatures of callback transactions sent but not confirmed yet
+    #[serde(default)]
+    pub sent: Vec<String>,
+    /// The answer generated, once there is one: a draft while validating, final once submitting
+    #[serde(default)]
+    pub response: Option<String>,
+    /// Times the reconciliation resumed it as stalled since it was last reopened, see
+    /// [`crate::reconcile::MAX_RESUMES`]
+    #[serde(default)]
+    pub resumes: u32,
+    /// Unix timestamp in seconds of the last transition
+    pub updated_at: u64,
+}
+
+/// In-memory LRU of settled keys
+#[derive(Default)]
+struct Lru {
+    ticks: HashMap<Key, u64>,
+    order: BTreeMap<u64, Key>,
+    next_tick: u64,
+}
+
+impl Lru {
+    fn touch(&mut self, key: Key) {
+        if let Some(tick) = self.ticks.insert(key, self.next_tick) {
+            self.order.remove(&tick);
+        }
+        self.order.insert(self.next_tick, key);
+        self.next_tick += 1;
+    }
+
+    fn contains(&mut self, key: &Key) -> bool {
+        if !self.ticks.contains_key(key) {
+            return false;
+        }
+        self.touch(*key);
+        true
+    }
+
+    fn forget(&mut self, key: &Key) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Drop the least recently used keys beyond `capacity`, returning how many were dropped
+    fn evict(&mut self, capacity: usize) -> usize {
+        let mut evicted = 0;
+        while self.ticks.len() > capacity {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            self.ticks.remove(&key);
+            evicted += 1;
+        }
+        evicted
+    }
+}
+
+/// Bounded ledger of interactions
+pub struct ProcessedSet {
+    /// Interactions known to be settled, so most lookups don't hit the store
+    recent: Mutex<Lru>,
+    /// Keys mapped to their JSON encoded [`LedgerEntry`]
+    store: Option<sled::Db>,
+    capacity: usize,
+}
+
+impl ProcessedSet {
+    pub fn new(store: Option<sled::Db>, capacity: usize) -> Self {
+        Self {
+            recent: Mutex::default(),
+            store,
+            capacity,
+        }
+    }
+
+    /// Persisted at `DEDUP_PATH` (under the deployment name when set)
+    pub fn from_env(capacity: usize) -> Result<Self, OracleError> {
+        let path =
+            deployment_path(&env::var("DEDUP_PATH").unwrap_or(DEFAULT_DEDUP_PATH.to_string()));
+        info!(%path, "Ledger: sled");
+        Ok(Self::new(Some(sled::open(path)?), capacity))
+    }
+
+    /// The ledger record of this prompt of the interaction
+    pub fn get(
+        &self,
+        interaction: &Pubkey,
+        text: &str,
+    ) -> Result<Option<LedgerEntry>, OracleError> {
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+        match store.get(key(interaction, text))? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether this prompt of the interaction is settled and must not be answered again
+    pub fn contains(&self, interaction: &Pubkey, text: &str) -> Result<bool, OracleError> {
+        let key = key(interaction, text);
+        if self.recent.lock().unwrap().contains(&key) {
+            return Ok(true);
+        }
+        match self.get(interaction, text)? {
+            Some(entry) if entry.status.is_final() => {
+                self.remember(key);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Move this prompt of the interaction to `status`, recording the signatures of the
+    /// callback transactions that landed on the way. Invalid transitions are refused.
+    pub fn transition(
+        &self,
+        interaction: &Pubkey,
+        text: &str,
+        status: InteractionStatus,
+        signatures: &[Signature],
+    ) -> Result<(), OracleError> {
+        self.transition_key(interaction, key(interaction, text), status, signatures)
+    }
+
+    /// Abandon an interaction known only by the hash of its prompt, which is no longer on-chain
+    pub fn abandon_by_hash(
+        &self,
+        interaction: &Pubkey,
+        prompt_hash: &[u8; 32],
+    ) -> Result<(), OracleError> {
+        self.transition_key(
+            interaction,
+            hash_key(interaction, prompt_hash),
+            InteractionStatus::Abandoned,
+            &[],
+        )
+    }
+
+    /// Count a resumption of an unsettled interaction known by the hash of its prompt,
+    /// returning how many times it was resumed
+    pub fn count_resume(
+        &self,
+        interaction: &Pubkey,
+        prompt_hash: &[u8; 32],
+    ) -> Result<u32, OracleError> {
+        let entry = self.modify(hash_key(interaction, prompt_hash), |stored| {
+            let Some(mut entry) = stored else {
+                return Err(format!("{} isn't in the ledger", interaction).into());
+            };
+            entry.resumes += 1;
+            Ok(Some(entry))
+        })?;
+        Ok(entry.map_or(0, |entry| entry.resumes))
+    }
+
+    fn transition_key(
+        &self,
+        interaction: &Pubkey,
+        key: Key,
+        status: InteractionStatus,
+        signatures: &[Signature],
+    ) -> Result<(), OracleError> {
+        self.modify(key, |stored| {
+            let mut entry = match stored {
+                Some(entry) if !entry.status.can_transition_to(status) => {
+                    return Err(format!(
+                        "Invalid status transition of {}: {} -> {}",
+                        interaction, entry.status, status
+                    )
+                    .into());
+                }
+                Some(entry) => entry,
+                None => LedgerEntry {
+                    status,
+                    signatures: Vec::new(),
+                    sent: Vec::new(),
+                    response: None,
+                    resumes: 0,
+                    updated_at: 0,
+                },
+            };
+            match status {
+                // Starting over: nothing generated or sent survives
+                InteractionStatus::Detected => {
+                    entry.sent.clear();
+                    entry.response = None;
+                }
+                InteractionStatus::Confirmed => entry.sent.clear(),
+                _ => {}
+            }
+            entry.status = status;
+            entry
+                .signatures
+                .extend(signatures.iter().map(ToString::to_string));
+            entry.updated_at = now();
+            Ok(Some(entry))
+        })?;
+        debug!(%interaction, %status, "Interaction status");
+        METRICS
+            .interaction_transitions
+            .with_label_values(&[status.as_str()])
+            .inc();
+        MONITOR.status(interaction, status);
+
+        if status.is_final() {
+            self.remember(key);
+        }
+        Ok(())
+    }
+
+    /// Replace the entry at `key` with `change` of it, atomically: when another writer stored
+    /// the entry in between, `change` runs again on theirs. Returns the entry stored, `None` when
+    /// `change` leaves it as is.
+    fn modify(
+        &self,
+        key: Key,
+        mut change: impl FnMut(Option<LedgerEntry>) -> Result<Option<LedgerEntry>, OracleError>,
+    ) -> Result<Option<LedgerEntry>, OracleError> {
+        let Some(store) = &self.store else {
+            return change(None);
+        };
+        loop {
+            let stored = store.get(key)?;
+            let current = match &stored {
+                Some(bytes) => Some(serde_json::from_slice(bytes)?),
+                None => None,
+            };
+            let Some(entry) = change(current)? else {
+                return Ok(None);
+            };
+            let swapped = store.compare_and_swap(key, stored, Some(serde_json::to_vec(&entry)?))?;
+            if swapped.is_ok() {
+                return Ok(Some(entry));
+            }
+        }
+    }
+
+    /// Change the record of an interaction without moving its status
+    fn update(
+        &self,
+        interaction: &Pubkey,
+        text: &str,
+        change: impl Fn(&mut LedgerEntry),
+    ) -> Result<(), OracleError> {
+        self.modify(key(interaction, text), |stored| {
+            let Some(mut entry) = stored else {
+                return Err(format!("{} isn't in the ledger", interaction).into());
+            };
+            change(&mut entry);
+            Ok(Some(entry))
+        })?;
+        Ok(())
+    }
+
+    /// Let an abandoned prompt of the interaction be answered again, at an operator's request.
+    /// Returns whether it was abandoned.
+    pub fn reopen(&self, interaction: &Pubkey, text: &str) -> Result<bool, OracleError> {
+        let reopened = self.restart(
+            interaction,
+            key(interaction, text),
+            InteractionStatus::Abandoned,
+        )?;
+        if reopened {
+            debug!(%interaction, "Abandoned interaction reopened");
+        }
+        Ok(reopened)
+    }
+
+    /// Let a confirmed prompt of the interaction be answered again, as none of its callbacks is
+    /// on-chain after all. Returns whether it was confirmed.
+    pub fn unconfirm(&self, interaction: &Pubkey, text: &str) -> Result<bool, OracleError> {
+        let reopened = self.restart(
+            interaction,
+            key(interaction, text),
+            InteractionStatus::Confirmed,
+        )?;
+        if reopened {
+            debug!(%interaction, "Confirmed interaction reopened");
+        }
+        Ok(reopened)
+    }
+
+    /// Move an entry settled as `settled` back to `Detected`, which transitions don't allow.
+    /// Returns whether it was settled so.
+    fn restart(
+        &self,
+        interaction: &Pubkey,
+        key: Key,
+        settled: InteractionStatus,
+    ) -> Result<bool, OracleError> {
+        let restarted = self.modify(key, |stored| {
+            Ok(stored
+                .filter(|entry| entry.status == settled)
+                .map(|mut entry| {
+                    // None of the callbacks of an unconfirmed entry is on-chain
+                    if settled == InteractionStatus::Confirmed {
+                        entry.signatures.clear();
+                    }
+                    entry.status = InteractionStatus::Detected;
+                    entry.sent.clear();
+                    entry.response = None;
+                    entry.resumes = 0;
+                    entry.updated_at = now();
+                    entry
+                }))
+        })?;
+        let Some(entry) = restarted else {
+            return Ok(false);
+        };
+        self.recent.lock().unwrap().forget(&key);
+        METRICS
+            .interaction_transitions
+            .with_label_values(&[entry.status.as_str()])
+            .inc();
+        MONITOR.status(interaction, entry.status);
+        Ok(true)
+    }
+
+    /// Persist the answer generated, so a crash doesn't cost another LLM call
+    pub fn save_response(
+        &self,
+        interaction: &Pubkey,
+        text: &str,
+        response: &str,
+    ) -> Result<(), OracleError> {
+        self.update(interaction, text, |entry| {
+            entry.response = Some(response.to_string())
+        })
+    }
+
+    /// Record a callback transaction about to be sent, so it can be checked after a crash
+    pub fn record_sent(
+        &self,
+        interaction: &Pubkey,
+        text: &str,
+        signature: &Signature,
+    ) -> Result<(), OracleError> {
+        self.update(interaction, text, |entry| {
+            entry.sent.push(signature.to_string())
+        })
+    }
+
+    /// Entries matching `select`, with their interaction and the hash of their prompt
+    fn select(
+        &self,
+        select: impl Fn(&LedgerEntry) -> bool,
+    ) -> Result<Vec<(Pubkey, [u8; 32], LedgerEntry)>, OracleError> {
+        let Some(store) = &self.store else {
+            return Ok(Vec::new());
+        };
+        let mut selected = Vec::new();
+        for entry in store.iter() {
+            let (key, bytes) = entry?;
+            let entry: LedgerEntry = serde_json::from_slice(&bytes)?;
+            if !select(&entry) || key.len() != 64 {
+                continue;
+            }
+            let interaction = Pubkey::try_from(&key[..32])?;
+            let prompt_hash = key[32..].try_into()?;
+            selected.push((interaction, prompt_hash, entry));
+        }
+        Ok(selected)
+    }
+
+    /// Interactions that aren't settled, with the hash of their prompt
+    pub fn unsettled(&self) -> Result<Vec<(Pubkey, [u8; 32], LedgerEntry)>, OracleError> {
+        self.select(|entry| !entry.status.is_final())
+    }
+
+    /// Interactions confirmed at or after `since` (Unix seconds), with the hash of their prompt
+    pub fn confirmed_since(
+        &self,
+        since: u64,
+    ) -> Result<Vec<(Pubkey, [u8; 32], LedgerEntry)>, OracleError> {
+        self.select(|entry| {
+            entry.status == InteractionStatus::Confirmed && entry.updated_at >= since
+        })
+    }
+
+    /// Number of interactions by status, of those whose last transition was at or after `since`
+    pub fn status_counts(
+        &self,
+        since: u64,
+    ) -> Result<HashMap<InteractionStatus, usize>, OracleError> {
+        let mut counts = HashMap::new();
+        let Some(store) = &self.store else {
+            return Ok(counts);
+        };
+        for entry in store.iter() {
+            let (_, bytes) = entry?;
+            let entry: LedgerEntry = serde_json::from_slice(&bytes)?;
+            if entry.updated_at >= since {
+                *counts.entry(entry.status).or_default() += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Drop the responses of the entries matching `redact`. Returns the number changed.
+    fn redact_where(
+        &self,
+        redact: impl Fn(&[u8], &LedgerEntry) -> bool,
+    ) -> Result<usize, OracleError> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let mut redacted = 0;
+        for entry in store.iter() {
+            let (key, bytes) = entry?;
+            let mut entry: LedgerEntry = serde_json::from_slice(&bytes)?;
+            if entry.response.is_none() || !redact(&key, &entry) {
+                continue;
+            }
+            entry.response = None;
+            // Changed since it was read: left to the next redaction
+            if store
+                .compare_and_swap(&key, Some(&bytes), Some(serde_json::to_vec(&entry)?))?
+                .is_ok()
+            {
+                redacted += 1;
+            }
+        }
+        Ok(redacted)
+    }
+
+    /// Drop the responses of the settled entries last updated before `cutoff` (Unix seconds)
+    pub fn redact_before(&self, cutoff: u64) -> Result<usize, OracleError> {
+        self.redact_where(|_, entry| entry.status.is_final() && entry.updated_at < cutoff)
+    }
+
+    /// Drop the responses of every prompt of an interaction, keeping its status and signatures
+    /// so it isn't answered again
+    pub fn redact(&self, interaction: &Pubkey) -> Result<usize, OracleError> {
+        self.redact_where(|key, _| key.starts_with(interaction.as_ref()))
+    }
+
+    fn remember(&self, key: Key) {
+        let mut recent = self.recent.lock().unwrap();
+        recent.touch(key);
+        let evicted = recent.evict(self.capacity);
+        METRICS
+            .dedup_evictions
+            .with_label_values(&["memory"])
+            .inc_by(evicted as u64);
+    }
+
+    /// Write the persistent store to disk
+    pub fn flush(&self) -> Result<(), OracleError> {
+        if let Some(store) = &self.store {
+            store.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Remove the oldest settled entries of the persistent store beyond `capacity`, returning
+    /// how many were removed
+    pub fn compact(&self) -> Result<usize, OracleError> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let excess = store.len().saturating_sub(self.capacity);
+        if excess == 0 {
+            return Ok(0);
+        }
+        let mut settled = Vec::new();
+        for entry in store.iter() {
+            let (key, bytes) = entry?;
+            // Unreadable entries are as good as gone
+            let updated_at = match serde_json::from_slice::<LedgerEntry>(&bytes) {
+                Ok(entry) if !entry.status.is_final() => continue,
+                Ok(entry) => entry.updated_at,
+                Err(_) => 0,
+            };
+            settled.push((updated_at, key, bytes));
+        }
+        settled.sort_by_key(|(updated_at, _, _)| *updated_at);
+        let mut evicted = 0;
+        for (_, key, bytes) in settled.into_iter().take(excess) {
+            // Changed since it was read, e.g. reopened: kept
+            if store
+                .compare_and_swap(&key, Some(&bytes), None as Option<&[u8]>)?
+                .is_ok()
+            {
+                evicted += 1;
+            }
+        }
+        store.flush()?;
+        METRICS
+            .dedup_evictions
+            .with_label_values(&["disk"])
+            .inc_by(evicted as u64);
+        debug!(evicted, "Compacted the dedup store");
+        Ok(evicted)
+    }
+}
+
+/// Compact the ledger every [`COMPACTION_INTERVAL`], off the workers' path
+pub async fn run(oracle: Arc<Oracle>) {
+    let mut interval = tokio::time::interval(COMPACTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let oracle = oracle.clone();
+        match tokio::task::spawn_blocking(move || oracle.processed.compact()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => warn!(error = ?e, "Failed to compact the ledger"),
+            Err(e) => warn!(error = ?e, "Ledger compaction panicked"),
+        }
+    }
+}
+
+impl Drop for ProcessedSet {
+    fn drop(&mut self) {
+        if let Some(store) = &self.store {
+            let _ = store.flush();
+        }
+    }
+}
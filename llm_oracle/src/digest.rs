@@ -0,0 +1,224 @@
+//! Digests.
+//!
+//! With `digest.schedule` set, the oracle sends a summary of the last day or week at midnight
+//! UTC (Monday for weekly digests): interactions answered, abandoned and failed according to the
+//! ledger, callback fees, the dead-letter queue and the most recent errors. The body and subject
+//! are [minijinja](https://docs.rs/minijinja) templates rendered with a [`Report`];
+//! [`DEFAULT_TEMPLATE`] is used unless `digest.template_file` is set. Digests are
+//! [`DIGEST_EVENT`] notifications, emailed through `digest.smtp_url` or routed to any channel
+//! of [`crate::notify`].
+
+use crate::config::{deployment_name, DigestConfig};
+use crate::metrics::METRICS;
+use crate::monitor::MONITOR;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
+use serde::Serialize;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Kind of the digest notifications
+pub const DIGEST_EVENT: &str = "digest";
+pub const DEFAULT_SUBJECT: &str = "[{{ deployment }}] Oracle {{ period }} digest";
+pub const DEFAULT_TEMPLATE: &str = "\
+{{ deployment }} oracle, {{ period }} digest
+{{ from }} to {{ to }}
+
+Interactions answered:  {{ confirmed }}
+Abandoned:              {{ abandoned }}
+Failed:                 {{ failed }}
+In progress:            {{ in_progress }}
+Dead letters queued:    {{ dead_letters }}
+Callback fees:          {{ fee_sol }} SOL
+{% if errors %}
+Recent errors:
+{% for error in errors %}- {{ error.at }} {{ error.interaction or \"\" }} {{ error.message }}
+{% endfor %}{% else %}
+No errors.
+{% endif %}";
+
+/// How often digests are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestPeriod {
+    Daily,
+    Weekly,
+}
+
+impl DigestPeriod {
+    pub fn length(self) -> TimeDelta {
+        match self {
+            Self::Daily => TimeDelta::days(1),
+            Self::Weekly => TimeDelta::weeks(1),
+        }
+    }
+
+    /// The next midnight UTC, or the next Monday midnight UTC for weekly digests
+    pub fn next_boundary(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let midnight = now.date_naive().and_time(Default::default()).and_utc();
+        match self {
+            Self::Daily => midnight + TimeDelta::days(1),
+            Self::Weekly => {
+                let days = 7 - now.weekday().num_days_from_monday() as i64;
+                midnight + TimeDelta::days(days)
+            }
+        }
+    }
+}
+
+impl FromStr for DigestPeriod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            other => Err(format!("expected daily or weekly, got {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for DigestPeriod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Daily => write!(f, "daily"),
+            Self::Weekly => write!(f, "weekly"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportError {
+    pub at: String,
+    pub interaction: Option<String>,
+    pub message: String,
+}
+
+/// What the digest templates are rendered with
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub deployment: String,
+    pub period: String,
+    pub from: String,
+    pub to: String,
+    /// Interactions settled or failed during the period, from the ledger
+    pub confirmed: usize,
+    pub abandoned: usize,
+    pub failed: usize,
+    pub in_progress: usize,
+    /// Callbacks currently in the dead-letter queue
+    pub dead_letters: usize,
+    /// Fees paid by this process during the period
+    pub fee_lamports: u64,
+    pub fee_sol: f64,
+    /// Most recent errors of this process during the period, most recent first
+    pub errors: Vec<ReportError>,
+}
+
+pub struct Digest {
+    config: DigestConfig,
+}
+
+impl Digest {
+    pub fn new(config: DigestConfig) -> Result<Self, OracleError> {
+        let digest = Self { config };
+        // Fail at startup rather than at the first digest
+        digest
+            .render(&Report::default())
+            .map_err(|e| format!("Invalid digest template: {}", e))?;
+        Ok(digest)
+    }
+
+    pub fn period(&self) -> DigestPeriod {
+        self.config.period
+    }
+
+    /// Summary of the period ending at `to`, with the fees paid during it
+    pub fn report(
+        &self,
+        oracle: &Oracle,
+        to: DateTime<Utc>,
+        fee_lamports: u64,
+    ) -> Result<Report, OracleError> {
+        let from = to - self.config.period.length();
+        let since = from.timestamp().max(0) as u64;
+        let mut report = Report {
+            deployment: deployment_name().unwrap_or("default").to_string(),
+            period: self.config.period.to_string(),
+            from: from.format("%Y-%m-%d %H:%M UTC").to_string(),
+            to: to.format("%Y-%m-%d %H:%M UTC").to_string(),
+            dead_letters: oracle.dlq.list()?.len(),
+            fee_lamports,
+            fee_sol: fee_lamports as f64 / LAMPORTS_PER_SOL as f64,
+            ..Report::default()
+        };
+        for (status, count) in oracle.processed.status_counts(since)? {
+            match status {
+                InteractionStatus::Confirmed => report.confirmed += count,
+                InteractionStatus::Abandoned => report.abandoned += count,
+                InteractionStatus::Failed => report.failed += count,
+                _ => report.in_progress += count,
+            }
+        }
+        report.errors = MONITOR
+            .snapshot()
+            .recent_errors
+            .into_iter()
+            .filter(|error| error.at >= since)
+            .map(|error| ReportError {
+                at: DateTime::from_timestamp(error.at as i64, 0)
+                    .map(|at| at.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                interaction: error.interaction,
+                message: error.message,
+            })
+            .collect();
+        Ok(report)
+    }
+
+    /// Subject and body of the digest
+    pub fn render(&self, report: &Report) -> Result<(String, String), OracleError> {
+        let env = minijinja::Environment::new();
+        let subject = env.render_str(&self.config.subject, report)?;
+        let body = env.render_str(&self.config.template, report)?;
+        Ok((subject, body))
+    }
+
+    pub async fn send(&self, report: &Report) -> Result<(), OracleError> {
+        let (subject, body) = self.render(report)?;
+        notify::send(&Event::new(
+            Severity::Info,
+            DIGEST_EVENT,
+            subject.trim(),
+            body,
+        ))
+        .await
+    }
+}
+
+/// Send a digest at the end of every period
+pub async fn run(oracle: Arc<Oracle>, digest: Digest) {
+    let mut fees_at_start = METRICS.fee_lamports.get();
+    loop {
+        let boundary = digest.period().next_boundary(Utc::now());
+        let wait = (boundary - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let fees = METRICS.fee_lamports.get();
+        let report = digest.report(&oracle, boundary, fees.saturating_sub(fees_at_start));
+        fees_at_start = fees;
+        let sent = match report {
+            Ok(report) => digest.send(&report).await,
+            Err(e) => Err(e),
+        };
+        match sent {
+            Ok(()) => info!(period = %digest.period(), "Sent the digest"),
+            Err(e) => warn!(error = ?e, "Failed to send the digest"),
+        }
+    }
+}
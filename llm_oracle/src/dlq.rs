@@ -0,0 +1,219 @@
+//! Dead-letter queue of callbacks that exhausted their retries.
+//!
+//! When every attempt of a callback transaction fails, the response is persisted in a sled
+//! database at `DLQ_PATH` (under the deployment name when set) instead of being dropped. The
+//! queue is retried in the background with exponential backoff, without calling the LLM again,
+//! until the callback lands, the interaction is answered or rewritten, or `max_attempts` is
+//! reached. Entries left after that wait for `llm_oracle dlq retry`.
+
+use crate::config::deployment_path;
+use crate::decode::InteractionView;
+use crate::metrics::METRICS;
+use crate::monitor::MONITOR;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::processor::submit_response;
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn, Instrument};
+
+pub const DEFAULT_DLQ_PATH: &str = "./oracle-dlq";
+pub const DEFAULT_DLQ_MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first retry, doubled on every failed one
+pub const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(3600);
+/// How often the queue is checked for due entries
+pub const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub interaction: String,
+    /// The prompt answered, to tell a rewritten account apart
+    pub prompt: String,
+    pub response: String,
+    pub last_error: String,
+    /// Retries from the queue so far
+    pub attempts: u32,
+    /// Unix timestamp in seconds of the next retry; `None` once retries are exhausted
+    pub next_attempt_at: Option<u64>,
+    /// Unix timestamp in seconds
+    pub failed_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(RETRY_MAX_DELAY)
+}
+
+pub struct DeadLetterQueue {
+    db: sled::Db,
+    max_attempts: u32,
+}
+
+impl DeadLetterQueue {
+    pub fn open(path: &str, max_attempts: u32) -> Result<Self, OracleError> {
+        let queue = Self {
+            db: sled::open(path)?,
+            max_attempts,
+        };
+        METRICS.dlq_entries.set(queue.db.len() as i64);
+        Ok(queue)
+    }
+
+    /// Open the queue at `DLQ_PATH`
+    pub fn from_env(max_attempts: u32) -> Result<Self, OracleError> {
+        let path = deployment_path(&env::var("DLQ_PATH").unwrap_or(DEFAULT_DLQ_PATH.to_string()));
+        Self::open(&path, max_attempts)
+    }
+
+    pub fn get(&self, interaction: &Pubkey) -> Result<Option<DeadLetter>, OracleError> {
+        match self.db.get(interaction.as_ref())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every dead letter, in interaction order
+    pub fn list(&self) -> Result<Vec<DeadLetter>, OracleError> {
+        let mut letters = Vec::new();
+        for entry in self.db.iter() {
+            let (_, bytes) = entry?;
+            letters.push(serde_json::from_slice(&bytes)?);
+        }
+        Ok(letters)
+    }
+
+    /// Queue a failed callback, or schedule the next retry of one already queued. Returns
+    /// whether it will be retried.
+    pub fn push(
+        &self,
+        interaction: &Pubkey,
+        prompt: &str,
+        response: &str,
+        error: &str,
+    ) -> Result<bool, OracleError> {
+        let attempts = match self.get(interaction)? {
+            Some(letter) if letter.prompt == prompt => letter.attempts + 1,
+            _ => 0,
+        };
+        let next_attempt_at =
+            (attempts < self.max_attempts).then(|| now() + retry_delay(attempts).as_secs());
+        if next_attempt_at.is_none() {
+            error!(%interaction, attempts, "Callback retries exhausted, left in the dead-letter queue");
+            notify::emit(
+                Event::new(
+                    Severity::Critical,
+                    "dlq_exhausted",
+                    format!("Callback of {} can't land", interaction),
+                    format!(
+                        "{} retries, last error: {}\nRetry with `llm_oracle dlq retry {}`",
+                        attempts, error, interaction
+                    ),
+                )
+                .with_key(interaction.to_string()),
+            );
+        }
+        let letter = DeadLetter {
+            interaction: interaction.to_string(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            last_error: error.to_string(),
+            attempts,
+            next_attempt_at,
+            failed_at: now(),
+        };
+        self.db
+            .insert(interaction.as_ref(), serde_json::to_vec(&letter)?)?;
+        self.db.flush()?;
+        METRICS.dlq_entries.set(self.db.len() as i64);
+        MONITOR.error(Some(interaction), error);
+        Ok(next_attempt_at.is_some())
+    }
+
+    pub fn remove(&self, interaction: &Pubkey) -> Result<(), OracleError> {
+        if self.db.remove(interaction.as_ref())?.is_some() {
+            METRICS.dlq_entries.set(self.db.len() as i64);
+        }
+        Ok(())
+    }
+
+    /// Dead letters whose next retry is due
+    pub fn due(&self) -> Result<Vec<DeadLetter>, OracleError> {
+        let now = now();
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|letter| letter.next_attempt_at.is_some_and(|at| at <= now))
+            .collect())
+    }
+}
+
+/// Send the callback of a dead letter again. Letters of interactions that were answered or
+/// rewritten in the meantime are dropped.
+pub async fn retry(oracle: &Oracle, letter: &DeadLetter) -> Result<(), OracleError> {
+    let interaction_pubkey = Pubkey::from_str(&letter.interaction)?;
+    // The owner is the program the interaction belongs to
+    let account = oracle.rpc_client.get_account(&interaction_pubkey).await?;
+    let view = InteractionView::parse(&account.data).ok_or("Invalid interaction account")?;
+    if view.is_processed || view.text != letter.prompt {
+        info!(interaction = %interaction_pubkey, "Interaction changed, dropping the dead letter");
+        METRICS.dlq_retries.with_label_values(&["dropped"]).inc();
+        oracle.processed.transition(
+            &interaction_pubkey,
+            &letter.prompt,
+            InteractionStatus::Abandoned,
+            &[],
+        )?;
+        return oracle.dlq.remove(&interaction_pubkey);
+    }
+    let interaction = view.decode()?;
+    // Lands the callback and leaves the queue, or is queued again with a later retry
+    submit_response(
+        oracle,
+        &account.owner,
+        &interaction_pubkey,
+        &interaction,
+        &letter.response,
+    )
+    .await?;
+    let result = match oracle.dlq.get(&interaction_pubkey)? {
+        Some(_) => "failed",
+        None => "landed",
+    };
+    METRICS.dlq_retries.with_label_values(&[result]).inc();
+    Ok(())
+}
+
+/// Retry due dead letters until the process exits
+pub async fn run(oracle: Arc<Oracle>) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let letters = match oracle.dlq.due() {
+            Ok(letters) => letters,
+            Err(e) => {
+                error!(error = ?e, "Failed to read the dead-letter queue");
+                continue;
+            }
+        };
+        for letter in letters {
+            let span = tracing::info_span!("dlq_retry", interaction = %letter.interaction, attempt = letter.attempts + 1);
+            if let Err(e) = retry(&oracle, &letter).instrument(span).await {
+                warn!(interaction = %letter.interaction, error = ?e, "Dead letter retry failed");
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Encrypted prompts.
+//!
+//! Interaction texts are public on-chain. With `encryption.enabled` (`ENCRYPTED_PROMPTS`), a
+//! client can send `[sealed:<base64>]` instead, the NaCl sealed box (libsodium
+//! `crypto_box_seal`) of the prompt to the oracle's X25519 key, which `check-config` prints. The
+//! oracle opens it and answers the prompt. With `[sealed:<base64>:<wallet>]`, the answer is
+//! sealed to the X25519 key of that Solana wallet too, and sent as `[sealed:<base64>]`, for the
+//! wallet's owner to open with its ed25519 secret key converted to X25519 (e.g. `ed2curve`).
+//!
+//! The key is the X25519 conversion of the identity keypair, or of the keypair at
+//! `encryption.keypair_path` (`ENCRYPTION_KEYPAIR_PATH`), needed when the identity is a remote
+//! signer. Prompts that can't be opened are abandoned. Opened prompts and answers are kept by
+//! the oracle like any other: in its ledger, archive and memory. Sealed answers aren't streamed,
+//! and reach push subscribers and webhooks sealed.
+
+use crate::OracleError;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use crypto_box::aead::OsRng;
+use crypto_box::{PublicKey, SecretKey};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use sha2::{Digest, Sha512};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Keypair;
+use std::str::FromStr;
+
+pub const SEALED_PREFIX: &str = "[sealed:";
+
+/// X25519 key opening sealed prompts
+pub struct PromptKey {
+    secret: SecretKey,
+}
+
+impl PromptKey {
+    /// The X25519 key of an ed25519 keypair: the clamped first half of the SHA-512 of its seed
+    pub fn from_keypair(keypair: &Keypair) -> Self {
+        let hash = Sha512::digest(&keypair.to_bytes()[..32]);
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&hash[..32]);
+        Self {
+            secret: SecretKey::from(scalar),
+        }
+    }
+
+    /// What clients seal prompts to
+    pub fn public_key(&self) -> PublicKey {
+        self.secret.public_key()
+    }
+
+    /// Open a sealed interaction text, returning the prompt
+    pub fn open(&self, text: &str) -> Result<String, OracleError> {
+        let (sealed, _) = parse(text).ok_or("Not a sealed prompt")?;
+        let sealed = STANDARD
+            .decode(sealed)
+            .map_err(|e| format!("Invalid sealed prompt: {}", e))?;
+        let prompt = self
+            .secret
+            .unseal(&sealed)
+            .map_err(|_| "Can't open the sealed prompt")?;
+        Ok(String::from_utf8(prompt)?)
+    }
+}
+
+/// The sealed box and reply wallet of `[sealed:<base64>(:<wallet>)]`
+pub fn parse(text: &str) -> Option<(&str, Option<&str>)> {
+    let inner = text.trim().strip_prefix(SEALED_PREFIX)?.strip_suffix(']')?;
+    Some(match inner.split_once(':') {
+        Some((sealed, wallet)) => (sealed, Some(wallet)),
+        None => (inner, None),
+    })
+}
+
+/// Whether an interaction text is a sealed prompt
+pub fn is_sealed(text: &str) -> bool {
+    parse(text).is_some()
+}
+
+/// The X25519 key of a Solana wallet
+pub fn wallet_key(wallet: &Pubkey) -> Option<PublicKey> {
+    let point = CompressedEdwardsY(wallet.to_bytes()).decompress()?;
+    Some(PublicKey::from(point.to_montgomery().to_bytes()))
+}
+
+/// The key a sealed prompt asks its answer to be sealed to
+pub fn reply_key(text: &str) -> Option<PublicKey> {
+    let (_, wallet) = parse(text)?;
+    wallet_key(&Pubkey::from_str(wallet?).ok()?)
+}
+
+/// What of an answer, sent on-chain as `callback`, goes out to push subscribers and webhooks:
+/// the callback when the answer was sealed to the asker of `text`, so its plaintext leaves the
+/// oracle nowhere, the answer otherwise
+pub fn outgoing<'a>(enabled: bool, text: &str, answer: &'a str, callback: &'a str) -> &'a str {
+    if enabled && reply_key(text).is_some() {
+        callback
+    } else {
+        answer
+    }
+}
+
+/// Seal an answer to `key`, as `[sealed:<base64>]`
+pub fn seal(key: &PublicKey, answer: &str) -> Result<String, OracleError> {
+    let sealed = key
+        .seal(&mut OsRng, answer.as_bytes())
+        .map_err(|e| format!("Failed to seal the answer: {}", e))?;
+    Ok(format!("{}{}]", SEALED_PREFIX, STANDARD.encode(sealed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::Signer;
+
+    #[test]
+    fn sends_out_sealed_answers_sealed_only() {
+        let text = format!("[sealed:AAAA:{}]", Keypair::new().pubkey());
+        let answer = "The vault code is 4242";
+        let callback = seal(&reply_key(&text).unwrap(), answer).unwrap();
+        let outgoing = outgoing(true, &text, answer, &callback);
+        assert_eq!(outgoing, callback);
+        assert!(!outgoing.contains(answer));
+    }
+
+    #[test]
+    fn sends_out_other_answers_as_is() {
+        let callback = "[prompt:ab] The vault code is 4242";
+        let answer = "The vault code is 4242";
+        assert_eq!(outgoing(true, "[sealed:AAAA]", answer, callback), answer);
+        assert_eq!(
+            outgoing(true, "What is the code?", answer, callback),
+            answer
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Response time estimates.
+//!
+//! The time to answer an interaction (from pickup to landed callback) is tracked as an
+//! exponentially weighted moving average per context and overall. An interaction's ETA is the
+//! time for the workers to get through the interactions queued ahead of it, plus the average
+//! response time of its context.
+
+use crate::metrics::METRICS;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Weight of the latest sample in the moving averages
+const SMOOTHING: f64 = 0.2;
+
+#[derive(Default)]
+struct Averages {
+    overall: Option<f64>,
+    contexts: HashMap<Pubkey, f64>,
+}
+
+fn update(average: Option<f64>, sample: f64) -> f64 {
+    match average {
+        Some(average) => average + SMOOTHING * (sample - average),
+        None => sample,
+    }
+}
+
+#[derive(Default)]
+pub struct LatencyTracker {
+    averages: Mutex<Averages>,
+}
+
+impl LatencyTracker {
+    /// Record the time it took to answer an interaction of `context`
+    pub fn record(&self, context: &Pubkey, elapsed: Duration) {
+        let sample = elapsed.as_secs_f64();
+        let mut averages = self.averages.lock().unwrap();
+        averages.overall = Some(update(averages.overall, sample));
+        let context_average = update(averages.contexts.get(context).copied(), sample);
+        averages.contexts.insert(*context, context_average);
+        METRICS
+            .response_time_estimate
+            .with_label_values(&[&context.to_string()])
+            .set(context_average);
+    }
+
+    /// Average response time of a context, falling back to the overall average
+    pub fn average(&self, context: &Pubkey) -> Option<Duration> {
+        let averages = self.averages.lock().unwrap();
+        averages
+            .contexts
+            .get(context)
+            .copied()
+            .or(averages.overall)
+            .map(Duration::from_secs_f64)
+    }
+
+    /// Expected time to answer an interaction of `context` with `queue_position` interactions
+    /// ahead of it, answered `max_concurrent` at a time. `None` until a response was recorded.
+    pub fn eta(
+        &self,
+        context: &Pubkey,
+        queue_position: usize,
+        max_concurrent: usize,
+    ) -> Option<Duration> {
+        let own = self.average(context)?;
+        let overall = self.averages.lock().unwrap().overall.unwrap_or_default();
+        let rounds_ahead = queue_position.div_ceil(max_concurrent.max(1)) as f64;
+        Some(own + Duration::from_secs_f64(overall * rounds_ahead))
+    }
+
+    /// Drop the average of a context that is gone
+    pub fn forget(&self, context: &Pubkey) {
+        self.averages.lock().unwrap().contexts.remove(context);
+        let _ = METRICS
+            .response_time_estimate
+            .remove_label_values(&[&context.to_string()]);
+    }
+}
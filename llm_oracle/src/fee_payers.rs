@@ -0,0 +1,190 @@
+//! Fee payer rotation.
+//!
+//! With `callback.fee_payers` (`FEE_PAYER_KEYPAIRS`), callback transactions are paid for by a
+//! pool of keypairs taken in turn instead of the oracle identity, so one underfunded or stuck key
+//! doesn't hold up every callback. The identity still signs each callback, as the oracle programs
+//! require, and still pays for the nonce accounts and lookup tables it owns.
+//!
+//! The balance of each payer is fetched every `incidents.payer_check_secs` and lowered by the
+//! fees and tips it pays in between. A payer below `callback.fee_payer_min_lamports`, or whose
+//! callback fails to simulate for lack of funds, is left out of the rotation with a
+//! [`FEE_PAYER_DRAINED`] warning until a check finds it funded again. While every payer is
+//! drained, the identity pays.
+
+use crate::config::FeePayerConfig;
+use crate::identity::OracleSigner;
+use crate::incidents;
+use crate::metrics::METRICS;
+use crate::notify::{Event, Severity};
+use crate::OracleError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Signer};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+pub const FEE_PAYER_DRAINED: &str = "fee_payer_drained";
+
+struct FeePayer {
+    signer: OracleSigner,
+    /// Balance last fetched less what was paid since, `None` until fetched
+    lamports: Mutex<Option<u64>>,
+    /// Left out of the rotation until funded again
+    drained: AtomicBool,
+}
+
+/// The payers of the callback transactions, taken in turn
+pub struct FeePayers {
+    payers: Vec<FeePayer>,
+    min_lamports: u64,
+    next: AtomicUsize,
+}
+
+impl FeePayers {
+    /// Load the keypairs of `config`, `None` when there are none and the identity pays
+    pub fn load(config: &FeePayerConfig, identity: &Pubkey) -> Result<Option<Self>, OracleError> {
+        let mut payers: Vec<FeePayer> = Vec::new();
+        for path in &config.keypair_paths {
+            let keypair = read_keypair_file(path)
+                .map_err(|e| format!("Can't read the fee payer keypair {}: {}", path, e))?;
+            let pubkey = keypair.pubkey();
+            if pubkey == *identity || payers.iter().any(|payer| payer.signer.pubkey() == pubkey) {
+                return Err(format!(
+                    "Invalid config: `callback.fee_payers` (FEE_PAYER_KEYPAIRS) {:?}: {} is the \
+                     identity or listed twice",
+                    path, pubkey
+                )
+                .into());
+            }
+            payers.push(FeePayer {
+                signer: Box::new(keypair),
+                lamports: Mutex::new(None),
+                drained: AtomicBool::new(false),
+            });
+        }
+        if payers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            payers,
+            min_lamports: config.min_lamports,
+            next: AtomicUsize::new(0),
+        }))
+    }
+
+    /// The payers of the rotation
+    pub fn pubkeys(&self) -> Vec<Pubkey> {
+        self.payers
+            .iter()
+            .map(|payer| payer.signer.pubkey())
+            .collect()
+    }
+
+    /// A payer of the rotation, to size transactions before theirs is picked
+    pub fn any(&self) -> Pubkey {
+        self.payers[0].signer.pubkey()
+    }
+
+    /// The payer of the next transaction, `None` when every payer is drained
+    pub fn next(&self) -> Option<&dyn Signer> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.payers.len())
+            .map(|offset| &self.payers[(start + offset) % self.payers.len()])
+            .find(|payer| !payer.drained.load(Ordering::Relaxed))
+            .map(|payer| payer.signer.as_ref() as &dyn Signer)
+    }
+
+    /// Whether a payer of the rotation is funded
+    pub fn funded(&self) -> bool {
+        self.payers
+            .iter()
+            .any(|payer| !payer.drained.load(Ordering::Relaxed))
+    }
+
+    fn find(&self, pubkey: &Pubkey) -> Option<&FeePayer> {
+        self.payers
+            .iter()
+            .find(|payer| payer.signer.pubkey() == *pubkey)
+    }
+
+    /// Count `lamports` paid by `payer` in fees and tips
+    pub fn paid(&self, payer: &Pubkey, lamports: u64) {
+        let Some(fee_payer) = self.find(payer) else {
+            return;
+        };
+        let remaining = {
+            let mut balance = fee_payer.lamports.lock().unwrap();
+            let Some(balance) = balance.as_mut() else {
+                return;
+            };
+            *balance = balance.saturating_sub(lamports);
+            *balance
+        };
+        self.observe(fee_payer, remaining);
+    }
+
+    /// Leave `payer` out of the rotation, as it couldn't pay for a transaction
+    pub fn exclude(&self, payer: &Pubkey) {
+        if let Some(fee_payer) = self.find(payer) {
+            *fee_payer.lamports.lock().unwrap() = Some(0);
+            // Whatever the minimum, so the payer isn't tried again before the next check
+            self.mark(fee_payer, 0, true);
+        }
+    }
+
+    /// Fetch the balance of every payer, putting the funded ones back in the rotation
+    pub async fn check(&self, rpc_client: &RpcClient) {
+        for fee_payer in &self.payers {
+            let pubkey = fee_payer.signer.pubkey();
+            match rpc_client.get_balance(&pubkey).await {
+                Ok(lamports) => {
+                    *fee_payer.lamports.lock().unwrap() = Some(lamports);
+                    self.observe(fee_payer, lamports);
+                }
+                Err(e) => warn!(payer = %pubkey, error = ?e, "Failed to fetch a fee payer balance"),
+            }
+        }
+    }
+
+    /// Follow a payer's balance, taking it out of the rotation or back in when it crosses the
+    /// minimum
+    fn observe(&self, fee_payer: &FeePayer, lamports: u64) {
+        self.mark(fee_payer, lamports, lamports < self.min_lamports);
+    }
+
+    /// Record a payer's balance, and whether it is left out of the rotation
+    fn mark(&self, fee_payer: &FeePayer, lamports: u64, drained: bool) {
+        let pubkey = fee_payer.signer.pubkey();
+        let key = pubkey.to_string();
+        METRICS
+            .fee_payer_lamports
+            .with_label_values(&[&key])
+            .set(lamports.try_into().unwrap_or(i64::MAX));
+        if fee_payer.drained.swap(drained, Ordering::Relaxed) == drained {
+            return;
+        }
+        if drained {
+            warn!(payer = %pubkey, lamports, "Fee payer drained, left out of the rotation");
+            incidents::trigger(
+                Event::new(
+                    Severity::Warning,
+                    FEE_PAYER_DRAINED,
+                    "Fee payer drained",
+                    format!(
+                        "Fee payer {} holds {} SOL, below the {} SOL it needs to pay for \
+                         callbacks. It is left out of the rotation until funded.",
+                        pubkey,
+                        lamports_to_sol(lamports),
+                        lamports_to_sol(self.min_lamports)
+                    ),
+                )
+                .with_key(&key),
+            );
+        } else {
+            info!(payer = %pubkey, lamports, "Fee payer funded, back in the rotation");
+            incidents::resolve(FEE_PAYER_DRAINED, &key);
+        }
+    }
+}
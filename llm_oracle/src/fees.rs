@@ -0,0 +1,147 @@
+//! Priority fee estimation for callback transactions.
+
+use crate::OracleError;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::env;
+use tracing::warn;
+
+pub const DEFAULT_MIN_MICRO_LAMPORTS: u64 = 1_000;
+pub const DEFAULT_MAX_MICRO_LAMPORTS: u64 = 1_000_000;
+pub const DEFAULT_PERCENTILE: u8 = 75;
+
+/// Picks the compute unit price of each callback transaction from recent network activity.
+///
+/// Uses the Helius `getPriorityFeeEstimate` API when `HELIUS_PRIORITY_FEE_URL` is set, and the
+/// given percentile of `getRecentPrioritizationFees` for the transaction's writable accounts
+/// otherwise. The estimate is always clamped to `[min, max]`; if estimation fails, `max` is used
+/// so the callback still lands.
+pub struct FeeEstimator {
+    pub min_micro_lamports: u64,
+    pub max_micro_lamports: u64,
+    pub percentile: u8,
+    helius_url: Option<String>,
+    http: reqwest::Client,
+}
+
+impl FeeEstimator {
+    pub fn new(min_micro_lamports: u64, max_micro_lamports: u64, percentile: u8) -> Self {
+        Self {
+            min_micro_lamports,
+            max_micro_lamports,
+            percentile: percentile.min(100),
+            helius_url: None,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_helius(mut self, url: String) -> Self {
+        self.helius_url = Some(url);
+        self
+    }
+
+    pub fn from_env() -> Result<Self, OracleError> {
+        fn parse<T: std::str::FromStr>(var: &str, default: T) -> Result<T, OracleError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            match env::var(var) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| format!("Invalid {} {:?}: {}", var, value, e).into()),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let min = parse("PRIORITY_FEE_MIN", DEFAULT_MIN_MICRO_LAMPORTS)?;
+        let max = parse("PRIORITY_FEE_MAX", DEFAULT_MAX_MICRO_LAMPORTS)?;
+        if min > max {
+            return Err(format!(
+                "PRIORITY_FEE_MIN ({}) is larger than PRIORITY_FEE_MAX ({})",
+                min, max
+            )
+            .into());
+        }
+        let estimator = Self::new(
+            min,
+            max,
+            parse("PRIORITY_FEE_PERCENTILE", DEFAULT_PERCENTILE)?,
+        );
+        Ok(match env::var("HELIUS_PRIORITY_FEE_URL") {
+            Ok(url) => estimator.with_helius(url),
+            Err(_) => estimator,
+        })
+    }
+
+    /// Compute unit price (in micro-lamports) for a transaction writing `writable_accounts`
+    pub async fn estimate(&self, rpc_client: &RpcClient, writable_accounts: &[Pubkey]) -> u64 {
+        let estimate = match &self.helius_url {
+            Some(url) => self.helius_estimate(url, writable_accounts).await,
+            None => {
+                self.recent_fees_estimate(rpc_client, writable_accounts)
+                    .await
+            }
+        };
+        match estimate {
+            Ok(fee) => fee.clamp(self.min_micro_lamports, self.max_micro_lamports),
+            Err(e) => {
+                warn!(error = ?e, "Priority fee estimation failed, using the maximum");
+                self.max_micro_lamports
+            }
+        }
+    }
+
+    async fn recent_fees_estimate(
+        &self,
+        rpc_client: &RpcClient,
+        writable_accounts: &[Pubkey],
+    ) -> Result<u64, OracleError> {
+        let fees: Vec<u64> = rpc_client
+            .get_recent_prioritization_fees(writable_accounts)
+            .await?
+            .into_iter()
+            .map(|fee| fee.prioritization_fee)
+            .collect();
+        Ok(percentile(fees, self.percentile))
+    }
+
+    async fn helius_estimate(
+        &self,
+        url: &str,
+        writable_accounts: &[Pubkey],
+    ) -> Result<u64, OracleError> {
+        let account_keys: Vec<String> = writable_accounts.iter().map(Pubkey::to_string).collect();
+        let response: Value = self
+            .http
+            .post(url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": "llm-oracle",
+                "method": "getPriorityFeeEstimate",
+                "params": [{
+                    "accountKeys": account_keys,
+                    "options": { "priorityLevel": "High" },
+                }],
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        response
+            .pointer("/result/priorityFeeEstimate")
+            .and_then(Value::as_f64)
+            .map(|fee| fee.ceil() as u64)
+            .ok_or_else(|| format!("Unexpected Helius response: {}", response).into())
+    }
+}
+
+/// The `p`th percentile of a set of fees (0 when empty)
+pub fn percentile(mut fees: Vec<u64>, p: u8) -> u64 {
+    if fees.is_empty() {
+        return 0;
+    }
+    fees.sort_unstable();
+    let index = (fees.len() - 1) * p.min(100) as usize / 100;
+    fees[index]
+}
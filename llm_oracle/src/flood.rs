@@ -0,0 +1,368 @@
+//! Flood protection.
+//!
+//! With `flood.max_per_second` (`FLOOD_MAX_PER_SECOND`), a context account producing more
+//! interactions than that within [`WINDOW_SLOTS`] slots, about a second, is taken for a flood,
+//! e.g. a griefer creating hundreds of interactions to drain the API quota and the payer.
+//! Interactions are counted by the slot their account update landed in, the first time the
+//! oracle sees them: redeliveries of an interaction queued, in flight or in the ledger don't
+//! count, and neither do the interactions caught up from the backlog after a restart or a
+//! reconnection. The context is throttled until it stays under the limit for
+//! `flood.throttle_secs`: its interactions are deferred before their ack and LLM call, left
+//! detected in the ledger without a worker, and dispatched again once the throttle ends. The
+//! reconciliation and the refund watchdog leave deferred interactions alone.
+//!
+//! With `flood.deactivate` (`FLOOD_DEACTIVATE`), a flooded context is deactivated instead, until
+//! an operator lifts it with `DELETE /admin/flood/<context>`, which dispatches its deferred
+//! interactions again. The program has no instruction retiring a context, so the oracle does it
+//! on its side: the context's interactions are deferred, its conversations are kept, and a
+//! restart lifts it, the deferred interactions being resumed from the ledger.
+//!
+//! Either way a [`CONTEXT_FLOODED`] incident is open while the context is held, critical when it
+//! is deactivated, and deferred interactions are counted in `interactions_limited_total` with
+//! `limit="flood"`.
+
+use crate::config::FloodConfig;
+use crate::incidents;
+use crate::metrics::METRICS;
+use crate::notify::{Event, Severity};
+use crate::oracle::Oracle;
+use crate::worker_pool::WorkerPool;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+pub const CONTEXT_FLOODED: &str = "context_flooded";
+
+pub const DEFAULT_FLOOD_THROTTLE_SECS: u64 = 300;
+
+/// Slots interactions are counted over, about a second
+pub const WINDOW_SLOTS: u64 = 3;
+/// Contexts tracked before the ones idle for a whole window are dropped
+const MAX_TRACKED: usize = 10_000;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A context held for flooding
+struct Flooded {
+    /// End of the throttle, `None` while deactivated
+    until: Option<Instant>,
+    /// Program of each interaction deferred meanwhile
+    deferred: HashMap<Pubkey, Pubkey>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Slots of the interactions of each context over the window, at most the limit
+    seen: HashMap<Pubkey, VecDeque<u64>>,
+    flooded: HashMap<Pubkey, Flooded>,
+    /// Program and pubkey of the deferred interactions of the contexts lifted from the admin API,
+    /// until the next sweep dispatches them
+    lifted: Vec<(Pubkey, Pubkey)>,
+}
+
+/// Interactions per window of each context, and the contexts held for flooding
+pub struct FloodGuard {
+    config: FloodConfig,
+    state: Mutex<State>,
+}
+
+impl FloodGuard {
+    pub fn new(config: FloodConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.max_per_second.is_some()
+    }
+
+    /// Count a new interaction of `context` landed at `slot`, holding the context when it is
+    /// over the limit
+    pub fn count(&self, context: &Pubkey, slot: u64) {
+        let Some(limit) = self.config.max_per_second else {
+            return;
+        };
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let seen = state.seen.entry(*context).or_default();
+        while seen
+            .front()
+            .is_some_and(|seen| seen.saturating_add(WINDOW_SLOTS) <= slot)
+        {
+            seen.pop_front();
+        }
+        seen.push_back(slot);
+        let flooding = seen.len() > limit as usize;
+        if flooding {
+            seen.pop_front();
+        }
+        if state.seen.len() > MAX_TRACKED {
+            state.seen.retain(|_, seen| {
+                seen.back()
+                    .is_some_and(|last| last.saturating_add(WINDOW_SLOTS) > slot)
+            });
+        }
+        if !flooding {
+            return;
+        }
+
+        let throttle = Duration::from_secs(self.config.throttle_secs);
+        if let Some(flooded) = state.flooded.get_mut(context) {
+            // Still flooding: the throttle starts over
+            if let Some(until) = flooded.until.as_mut() {
+                *until = now + throttle;
+            }
+            return;
+        }
+        let until = (!self.config.deactivate).then(|| now + throttle);
+        state.flooded.insert(
+            *context,
+            Flooded {
+                until,
+                deferred: HashMap::new(),
+            },
+        );
+        drop(state);
+        self.alert(context, limit);
+    }
+
+    /// Defer an interaction of `program` until its context is no longer held for flooding.
+    /// Returns whether it is deferred.
+    pub fn defer(&self, context: &Pubkey, program: Pubkey, interaction: Pubkey) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let Some(flooded) = state.flooded.get_mut(context) else {
+            return false;
+        };
+        if flooded.until.is_some_and(|until| until <= now) {
+            // Released by the next sweep
+            return false;
+        }
+        flooded.deferred.insert(interaction, program);
+        true
+    }
+
+    /// Whether an interaction waits for its context to be no longer held for flooding
+    pub fn is_deferred(&self, interaction: &Pubkey) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .flooded
+            .values()
+            .any(|flooded| flooded.deferred.contains_key(interaction))
+            || state.lifted.iter().any(|(_, lifted)| lifted == interaction)
+    }
+
+    fn alert(&self, context: &Pubkey, limit: u32) {
+        METRICS.contexts_flooded.inc();
+        let (severity, held) = if self.config.deactivate {
+            warn!(%context, limit, "Context flooding, deactivated until lifted");
+            (
+                Severity::Critical,
+                "It is deactivated until lifted with `DELETE /admin/flood/<context>`.".to_string(),
+            )
+        } else {
+            warn!(%context, limit, "Context flooding, throttled");
+            (
+                Severity::Warning,
+                format!(
+                    "It is throttled until it stays under the limit for {}s.",
+                    self.config.throttle_secs
+                ),
+            )
+        };
+        incidents::trigger(
+            Event::new(
+                severity,
+                CONTEXT_FLOODED,
+                format!("Context {} flooding", context),
+                format!(
+                    "The context produced more than {} interactions within {} slots. {} Its \
+                     interactions are deferred meanwhile.",
+                    limit, WINDOW_SLOTS, held
+                ),
+            )
+            .with_key(context.to_string()),
+        );
+    }
+
+    /// Lift the throttle or deactivation of `context`, returning whether it was held. Its
+    /// deferred interactions are dispatched by the next sweep.
+    pub fn lift(&self, context: &Pubkey) -> bool {
+        let deferred = {
+            let mut state = self.state.lock().unwrap();
+            state.seen.remove(context);
+            let Some(flooded) = state.flooded.remove(context) else {
+                return false;
+            };
+            let deferred = flooded.deferred.len();
+            state.lifted.extend(
+                flooded
+                    .deferred
+                    .into_iter()
+                    .map(|(interaction, program)| (program, interaction)),
+            );
+            deferred
+        };
+        info!(%context, deferred, "Flood hold lifted from the admin API");
+        incidents::resolve(CONTEXT_FLOODED, &context.to_string());
+        true
+    }
+
+    /// Release the contexts whose throttle ended without new interactions, returning the
+    /// program and pubkey of the interactions they deferred and of those of the lifted contexts
+    fn sweep(&self) -> Vec<(Pubkey, Pubkey)> {
+        let now = Instant::now();
+        let (ended, mut released) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ended = Vec::new();
+            state.flooded.retain(|context, flooded| {
+                let done = flooded.until.is_some_and(|until| until <= now);
+                if done {
+                    ended.push((*context, std::mem::take(&mut flooded.deferred)));
+                }
+                !done
+            });
+            (ended, std::mem::take(&mut state.lifted))
+        };
+        for (context, deferred) in ended {
+            info!(
+                %context,
+                deferred = deferred.len(),
+                "Flood subsided, context no longer throttled"
+            );
+            incidents::resolve(CONTEXT_FLOODED, &context.to_string());
+            released.extend(
+                deferred
+                    .into_iter()
+                    .map(|(interaction, program)| (program, interaction)),
+            );
+        }
+        released
+    }
+}
+
+/// Dispatch the unanswered interactions of `released` to the worker pool, returning those that
+/// couldn't be fetched
+async fn redispatch(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    released: Vec<(Pubkey, Pubkey)>,
+) -> Vec<(Pubkey, Pubkey)> {
+    let mut failed = Vec::new();
+    for chunk in released.chunks(100) {
+        let pubkeys: Vec<Pubkey> = chunk.iter().map(|(_, interaction)| *interaction).collect();
+        let accounts = match oracle.rpc_client.get_multiple_accounts(&pubkeys).await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                warn!(error = ?e, "Failed to fetch the interactions deferred for flooding");
+                failed.extend_from_slice(chunk);
+                continue;
+            }
+        };
+        for ((program, interaction), account) in chunk.iter().zip(accounts) {
+            // Closed or delegated since: the reconciliation settles its ledger entry
+            let Some(account) = account.filter(|account| account.owner == *program) else {
+                continue;
+            };
+            worker_pool.dispatch(*program, *interaction, account.data);
+        }
+    }
+    failed
+}
+
+/// Release the throttled contexts once their flood subsides, dispatching their deferred
+/// interactions again, until the process stops
+pub async fn run(oracle: Arc<Oracle>, worker_pool: WorkerPool) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    let mut released = Vec::new();
+    loop {
+        interval.tick().await;
+        released.extend(oracle.flood.sweep());
+        if !released.is_empty() {
+            released = redispatch(&oracle, &worker_pool, released).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(max_per_second: u32, deactivate: bool) -> FloodGuard {
+        FloodGuard::new(FloodConfig {
+            max_per_second: Some(max_per_second),
+            throttle_secs: 60,
+            deactivate,
+        })
+    }
+
+    #[test]
+    fn holds_a_context_over_the_limit_within_the_window() {
+        let flood = guard(2, false);
+        let (context, program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        flood.count(&context, 10);
+        flood.count(&context, 11);
+        assert!(!flood.defer(&context, program, Pubkey::new_unique()));
+        // The interaction of slot 10 left the window
+        flood.count(&context, 13);
+        assert!(!flood.defer(&context, program, Pubkey::new_unique()));
+        flood.count(&context, 13);
+        assert!(flood.defer(&context, program, Pubkey::new_unique()));
+        assert!(!flood.defer(&Pubkey::new_unique(), program, Pubkey::new_unique()));
+    }
+
+    #[test]
+    fn releases_the_interactions_deferred_by_a_throttle_once_it_ends() {
+        let flood = guard(1, false);
+        let (context, program, interaction) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        flood.count(&context, 1);
+        flood.count(&context, 1);
+        assert!(flood.defer(&context, program, interaction));
+        assert!(flood.defer(&context, program, interaction));
+        assert!(flood.is_deferred(&interaction));
+        assert!(flood.sweep().is_empty());
+
+        flood
+            .state
+            .lock()
+            .unwrap()
+            .flooded
+            .get_mut(&context)
+            .unwrap()
+            .until = Some(Instant::now());
+        assert!(!flood.defer(&context, program, Pubkey::new_unique()));
+        assert_eq!(flood.sweep(), vec![(program, interaction)]);
+        assert!(!flood.is_deferred(&interaction));
+        assert!(flood.sweep().is_empty());
+    }
+
+    #[test]
+    fn releases_the_interactions_deferred_by_a_deactivation_once_lifted() {
+        let flood = guard(1, true);
+        let (context, program, interaction) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        flood.count(&context, 1);
+        flood.count(&context, 1);
+        assert!(flood.defer(&context, program, interaction));
+        assert!(flood.sweep().is_empty());
+        assert!(flood.is_deferred(&interaction));
+
+        assert!(flood.lift(&context));
+        assert!(!flood.lift(&context));
+        assert!(!flood.defer(&context, program, Pubkey::new_unique()));
+        // Until the sweep dispatches it
+        assert!(flood.is_deferred(&interaction));
+        assert_eq!(flood.sweep(), vec![(program, interaction)]);
+        assert!(!flood.is_deferred(&interaction));
+    }
+}
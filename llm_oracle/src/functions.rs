@@ -0,0 +1,245 @@
+//! Function calling.
+//!
+//! With `functions.names` (`CHAIN_FUNCTIONS`) set, the model is offered read-only Solana RPC
+//! functions while it answers: it can look up a balance, the token accounts of a wallet or the data
+//! of an account, gets the results back, and only then writes the callback response. Unlike
+//! [`crate::tools`], which look for pubkeys in the prompt up front, the model decides what to
+//! fetch.
+//!
+//! Providers without function calling answer directly, as do interactions with a structured
+//! output schema.
+
+use crate::config::FunctionsConfig;
+use crate::guardrails::truncate;
+use crate::providers::{FunctionCall, FunctionSpec};
+use crate::tools::wallet::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::OracleError;
+use serde_json::{json, Value};
+use solana_account_decoder::UiAccountData;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::{RpcRequest, TokenAccountsFilter};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_MAX_ROUNDS: usize = 4;
+/// Results are cut to this size before being sent to the model
+const MAX_RESULT_BYTES: usize = 8 * 1024;
+const MAX_TOKEN_ACCOUNTS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChainFunction {
+    GetBalance,
+    GetTokenAccounts,
+    GetAccountData,
+}
+
+impl ChainFunction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::GetBalance => "get_balance",
+            Self::GetTokenAccounts => "get_token_accounts",
+            Self::GetAccountData => "get_account_data",
+        }
+    }
+
+    pub fn spec(self) -> FunctionSpec {
+        let description = match self {
+            Self::GetBalance => "Get the SOL balance of a Solana account.",
+            Self::GetTokenAccounts => {
+                "List the SPL token accounts of a Solana wallet, with the mint and balance of each."
+            }
+            Self::GetAccountData => {
+                "Get the owner, lamports and data of a Solana account, parsed when the program \
+                 is known (token accounts, mints, stake and vote accounts, ...)."
+            }
+        };
+        FunctionSpec {
+            name: self.name(),
+            description,
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "address": {
+                        "type": "string",
+                        "description": "Base58 address of the account",
+                    },
+                },
+                "required": ["address"],
+            }),
+        }
+    }
+}
+
+impl FromStr for ChainFunction {
+    type Err = OracleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "get_balance" => Ok(Self::GetBalance),
+            "get_token_accounts" => Ok(Self::GetTokenAccounts),
+            "get_account_data" => Ok(Self::GetAccountData),
+            other => Err(format!(
+                "Unknown chain function {:?}, expected get_balance, get_token_accounts or \
+                 get_account_data",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for ChainFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The functions offered to the model, run against the RPC node
+pub struct ChainFunctions {
+    rpc_client: RpcClient,
+    functions: Vec<ChainFunction>,
+    /// Contexts the functions are offered to, all when `None`
+    contexts: Option<HashSet<Pubkey>>,
+    max_rounds: usize,
+}
+
+impl ChainFunctions {
+    pub fn new(
+        rpc_url: String,
+        functions: Vec<ChainFunction>,
+        contexts: Option<HashSet<Pubkey>>,
+        max_rounds: usize,
+    ) -> Self {
+        Self {
+            rpc_client: RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed()),
+            functions,
+            contexts,
+            max_rounds: max_rounds.max(1),
+        }
+    }
+
+    /// The functions of `config`, `None` when it lists none
+    pub fn load(config: &FunctionsConfig, rpc_url: &str) -> Option<Self> {
+        if config.names.is_empty() {
+            return None;
+        }
+        Some(Self::new(
+            config.rpc_url.clone().unwrap_or(rpc_url.to_string()),
+            config.names.clone(),
+            config
+                .contexts
+                .as_ref()
+                .map(|contexts| contexts.iter().copied().collect()),
+            config.max_rounds,
+        ))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.functions
+            .iter()
+            .map(|function| function.name())
+            .collect()
+    }
+
+    pub fn specs(&self) -> Vec<FunctionSpec> {
+        self.functions
+            .iter()
+            .map(|function| function.spec())
+            .collect()
+    }
+
+    /// Whether the functions are offered for interactions of `context`
+    pub fn applies_to(&self, context: &Pubkey) -> bool {
+        self.contexts
+            .as_ref()
+            .map_or(true, |contexts| contexts.contains(context))
+    }
+
+    /// Rounds of calls allowed before the model must answer
+    pub fn max_rounds(&self) -> usize {
+        self.max_rounds
+    }
+
+    /// Run a call and return its result for the model. Errors are sent back to the model too,
+    /// so it can correct its call or answer without the data.
+    pub async fn call(&self, call: &FunctionCall) -> Result<String, String> {
+        let function = ChainFunction::from_str(&call.name)
+            .ok()
+            .filter(|function| self.functions.contains(function))
+            .ok_or_else(|| format!("unknown function {:?}", call.name))?;
+        let address = call.arguments["address"]
+            .as_str()
+            .ok_or("missing the \"address\" argument")?;
+        let address = Pubkey::from_str(address.trim())
+            .map_err(|e| format!("invalid address {:?}: {}", address, e))?;
+        let result = match function {
+            ChainFunction::GetBalance => self.balance(&address).await,
+            ChainFunction::GetTokenAccounts => self.token_accounts(&address).await,
+            ChainFunction::GetAccountData => self.account_data(&address).await,
+        }
+        .map_err(|e| e.to_string())?;
+        Ok(truncate(&result.to_string(), MAX_RESULT_BYTES).to_string())
+    }
+
+    async fn balance(&self, address: &Pubkey) -> Result<Value, OracleError> {
+        let lamports = self.rpc_client.get_balance(address).await?;
+        Ok(json!({
+            "lamports": lamports,
+            "sol": solana_sdk::native_token::lamports_to_sol(lamports),
+        }))
+    }
+
+    async fn token_accounts(&self, owner: &Pubkey) -> Result<Value, OracleError> {
+        let mut accounts = Vec::new();
+        for program_id in [TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID] {
+            let keyed_accounts = self
+                .rpc_client
+                .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(program_id))
+                .await?;
+            for keyed_account in keyed_accounts {
+                let UiAccountData::Json(parsed) = keyed_account.account.data else {
+                    continue;
+                };
+                let info = &parsed.parsed["info"];
+                accounts.push(json!({
+                    "account": keyed_account.pubkey,
+                    "mint": info["mint"],
+                    "amount": info["tokenAmount"]["uiAmountString"],
+                    "decimals": info["tokenAmount"]["decimals"],
+                }));
+            }
+        }
+        let total = accounts.len();
+        accounts.truncate(MAX_TOKEN_ACCOUNTS);
+        Ok(json!({ "total": total, "accounts": accounts }))
+    }
+
+    async fn account_data(&self, address: &Pubkey) -> Result<Value, OracleError> {
+        let response: Value = self
+            .rpc_client
+            .send(
+                RpcRequest::GetAccountInfo,
+                json!([address.to_string(), {
+                    "encoding": "jsonParsed",
+                    "commitment": "confirmed",
+                }]),
+            )
+            .await?;
+        let account = &response["value"];
+        if account.is_null() {
+            return Ok(json!({ "exists": false }));
+        }
+        Ok(json!({
+            "exists": true,
+            "owner": account["owner"],
+            "lamports": account["lamports"],
+            "executable": account["executable"],
+            "space": account["space"],
+            // Parsed JSON, or `[<base64>, "base64"]` for programs the node can't parse
+            "data": account["data"],
+        }))
+    }
+}
@@ -0,0 +1,343 @@
+//! Turn-based game state machines.
+//!
+//! Contexts listed in the `games.state_machines` JSON file (`GAME_STATE_MACHINES`) are treated as
+//! games: every interaction must start with an action (`/move e4`), the action has to be allowed
+//! from the session's current state, and every response is prefixed with the resulting state
+//! token (`[STATE:playing]`). Illegal turns are rejected deterministically without calling the
+//! LLM.
+//!
+//! The state of each session is kept in the [`MemoryStore`], across restarts with the sled
+//! backend. A game reaching a terminal state (one without transitions) is over: its state is
+//! cleared, and the session's next turn starts a new game from the initial state.
+//!
+//! ```json
+//! {
+//!   "<context pubkey>": {
+//!     "initial_state": "lobby",
+//!     "transitions": {
+//!       "lobby": { "start": "playing" },
+//!       "playing": { "move": "playing", "resign": "finished" }
+//!     }
+//!   }
+//! }
+//! ```
+
+use crate::memory::MemoryStore;
+use crate::OracleError;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct GameDefinition {
+    pub initial_state: String,
+    /// state -> (action -> next state). States without an entry are terminal.
+    pub transitions: HashMap<String, HashMap<String, String>>,
+}
+
+impl GameDefinition {
+    fn allowed_actions(&self, state: &str) -> Vec<&str> {
+        let mut actions: Vec<&str> = self
+            .transitions
+            .get(state)
+            .map(|actions| actions.keys().map(String::as_str).collect())
+            .unwrap_or_default();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// Whether `state` is the initial state or reached by some transition
+    fn has_state(&self, state: &str) -> bool {
+        self.initial_state == state
+            || self.transitions.contains_key(state)
+            || self
+                .transitions
+                .values()
+                .any(|actions| actions.values().any(|next| next == state))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TurnOutcome {
+    Accepted {
+        action: String,
+        from: String,
+        to: String,
+        allowed_next: Vec<String>,
+    },
+    Rejected {
+        state: String,
+        reason: String,
+    },
+}
+
+impl TurnOutcome {
+    /// Deterministic callback text for a rejected turn
+    pub fn rejection_response(&self) -> Option<String> {
+        match self {
+            TurnOutcome::Rejected { state, reason } => {
+                Some(format!("{} ILLEGAL_TURN: {}", state_token(state), reason))
+            }
+            TurnOutcome::Accepted { .. } => None,
+        }
+    }
+}
+
+/// Token prepended to every response of a game context
+pub fn state_token(state: &str) -> String {
+    format!("[STATE:{}]", state)
+}
+
+/// Split `/action rest of the text` into its action and remaining text
+pub fn parse_action(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start().strip_prefix('/')?;
+    let (action, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    if action.is_empty() {
+        return None;
+    }
+    Some((action, rest.trim()))
+}
+
+/// Game definitions per context; the state of each session (interaction account) is in the
+/// [`MemoryStore`]
+#[derive(Default)]
+pub struct GameSessions {
+    definitions: HashMap<Pubkey, GameDefinition>,
+}
+
+impl GameSessions {
+    pub fn new(definitions: HashMap<Pubkey, GameDefinition>) -> Self {
+        Self { definitions }
+    }
+
+    /// Load game definitions from the JSON file at `path`, if set
+    pub fn load(path: Option<&str>) -> Result<Self, OracleError> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let raw: HashMap<String, GameDefinition> = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| format!("Invalid game state machine file {}: {}", path, e))?;
+        let mut definitions = HashMap::new();
+        for (context, definition) in raw {
+            let context = Pubkey::from_str(&context)
+                .map_err(|e| format!("Invalid context pubkey {:?} in {}: {}", context, path, e))?;
+            if !definition
+                .transitions
+                .contains_key(&definition.initial_state)
+            {
+                return Err(format!(
+                    "Initial state {:?} of context {} has no transitions",
+                    definition.initial_state, context
+                )
+                .into());
+            }
+            definitions.insert(context, definition);
+        }
+        Ok(Self::new(definitions))
+    }
+
+    pub fn is_game_context(&self, context: &Pubkey) -> bool {
+        self.definitions.contains_key(context)
+    }
+
+    /// Current state of a session, falling back to the game's initial state when no game is
+    /// under way or its state isn't one of the game's anymore
+    pub fn current_state(
+        &self,
+        memory: &dyn MemoryStore,
+        context: &Pubkey,
+        session: &Pubkey,
+    ) -> Result<Option<String>, OracleError> {
+        let Some(definition) = self.definitions.get(context) else {
+            return Ok(None);
+        };
+        let state = memory
+            .game_state(session)?
+            .filter(|state| definition.has_state(state))
+            .unwrap_or_else(|| definition.initial_state.clone());
+        Ok(Some(state))
+    }
+
+    /// Validate a turn against the session's state. Returns `None` for non-game contexts.
+    pub fn check_turn(
+        &self,
+        memory: &dyn MemoryStore,
+        context: &Pubkey,
+        session: &Pubkey,
+        text: &str,
+    ) -> Result<Option<TurnOutcome>, OracleError> {
+        let (Some(definition), Some(state)) = (
+            self.definitions.get(context),
+            self.current_state(memory, context, session)?,
+        ) else {
+            return Ok(None);
+        };
+        let allowed = definition.allowed_actions(&state);
+
+        let Some((action, _)) = parse_action(text) else {
+            return Ok(Some(TurnOutcome::Rejected {
+                reason: format!("missing action, expected one of: {}", allowed.join(", ")),
+                state,
+            }));
+        };
+        let outcome = match definition
+            .transitions
+            .get(&state)
+            .and_then(|t| t.get(action))
+        {
+            Some(next) => TurnOutcome::Accepted {
+                action: action.to_string(),
+                allowed_next: definition
+                    .allowed_actions(next)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+                from: state,
+                to: next.clone(),
+            },
+            None if allowed.is_empty() => TurnOutcome::Rejected {
+                reason: format!("game is over, {:?} is not allowed", action),
+                state,
+            },
+            None => TurnOutcome::Rejected {
+                reason: format!(
+                    "{:?} is not allowed, expected one of: {}",
+                    action,
+                    allowed.join(", ")
+                ),
+                state,
+            },
+        };
+        Ok(Some(outcome))
+    }
+
+    /// Move a session to its next state once the turn has been answered. A game reaching a
+    /// terminal state is over and its state cleared, so the session's next turn starts a new one.
+    pub fn commit(
+        &self,
+        memory: &mut dyn MemoryStore,
+        context: &Pubkey,
+        session: &Pubkey,
+        state: &str,
+    ) -> Result<(), OracleError> {
+        let Some(definition) = self.definitions.get(context) else {
+            return Ok(());
+        };
+        let finished = definition.allowed_actions(state).is_empty();
+        memory.set_game_state(session, (!finished).then_some(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{InteractionMemory, MemoryLimits, SledMemory};
+    use std::time::Duration;
+
+    const LIMITS: MemoryLimits = MemoryLimits {
+        max_history: 10,
+        ttl: Duration::from_secs(60),
+        max_bytes: 1024,
+    };
+
+    fn sessions(context: Pubkey) -> GameSessions {
+        let definition: GameDefinition = serde_json::from_str(
+            r#"{
+                "initial_state": "lobby",
+                "transitions": {
+                    "lobby": { "start": "playing" },
+                    "playing": { "move": "playing", "resign": "finished" }
+                }
+            }"#,
+        )
+        .unwrap();
+        GameSessions::new(HashMap::from([(context, definition)]))
+    }
+
+    /// Check a turn and commit it when accepted, returning the resulting state
+    fn play(
+        games: &GameSessions,
+        memory: &mut dyn MemoryStore,
+        context: &Pubkey,
+        session: &Pubkey,
+        text: &str,
+    ) -> Result<String, String> {
+        match games.check_turn(memory, context, session, text).unwrap() {
+            Some(TurnOutcome::Accepted { to, .. }) => {
+                games.commit(memory, context, session, &to).unwrap();
+                Ok(to)
+            }
+            Some(TurnOutcome::Rejected { reason, .. }) => Err(reason),
+            None => Err("not a game".to_string()),
+        }
+    }
+
+    #[test]
+    fn starts_a_new_game_once_one_finishes() {
+        let (context, session) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let games = sessions(context);
+        let mut memory = InteractionMemory::new(LIMITS);
+        assert!(play(&games, &mut memory, &context, &session, "/move e4").is_err());
+        assert_eq!(
+            play(&games, &mut memory, &context, &session, "/start").unwrap(),
+            "playing"
+        );
+        assert_eq!(
+            play(&games, &mut memory, &context, &session, "/move e4").unwrap(),
+            "playing"
+        );
+        assert_eq!(
+            play(&games, &mut memory, &context, &session, "/resign").unwrap(),
+            "finished"
+        );
+        assert_eq!(memory.game_state(&session).unwrap(), None);
+
+        let state = games.current_state(&memory, &context, &session).unwrap();
+        assert_eq!(state.as_deref(), Some("lobby"));
+        assert_eq!(
+            play(&games, &mut memory, &context, &session, "/start").unwrap(),
+            "playing"
+        );
+    }
+
+    #[test]
+    fn keeps_games_across_restarts_with_sled() {
+        let (context, session) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let games = sessions(context);
+        let path = std::env::temp_dir().join(format!("oracle-games-{}", session));
+        let path = path.to_str().unwrap();
+        {
+            let mut memory = SledMemory::open(path, LIMITS).unwrap();
+            play(&games, &mut memory, &context, &session, "/start").unwrap();
+        }
+        let mut memory = SledMemory::open(path, LIMITS).unwrap();
+        let state = games.current_state(&memory, &context, &session).unwrap();
+        assert_eq!(state.as_deref(), Some("playing"));
+        assert_eq!(
+            play(&games, &mut memory, &context, &session, "/resign").unwrap(),
+            "finished"
+        );
+        drop(memory);
+        let memory = SledMemory::open(path, LIMITS).unwrap();
+        assert_eq!(memory.game_state(&session).unwrap(), None);
+        drop(memory);
+        let _ = std::fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn falls_back_to_the_initial_state_for_unknown_states() {
+        let (context, session) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let games = sessions(context);
+        let mut memory = InteractionMemory::new(LIMITS);
+        memory.set_game_state(&session, Some("removed")).unwrap();
+        let state = games.current_state(&memory, &context, &session).unwrap();
+        assert_eq!(state.as_deref(), Some("lobby"));
+        let other = Pubkey::new_unique();
+        assert_eq!(
+            games.current_state(&memory, &other, &session).unwrap(),
+            None
+        );
+    }
+}
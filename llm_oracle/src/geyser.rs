@@ -0,0 +1,95 @@
+//! Program updates from a Yellowstone gRPC (Geyser) endpoint.
+//!
+//! WebSocket `program_subscribe` on public RPC nodes drops updates under load. With
+//! `solana.listener = "geyser"`, the listener streams each oracle program's `Interaction`
+//! accounts (filtered by their discriminator) from the Yellowstone gRPC endpoint at
+//! `solana.geyser_url` instead. Only the source of the updates changes: reconnections, gap-fills
+//! through the RPC node and the worker pool are the same as with the websocket listener, see
+//! [`crate::listener`]. The endpoint's access token, if it needs one, is read from
+//! `GEYSER_X_TOKEN`.
+
+use crate::listener::AccountUpdate;
+use crate::OracleError;
+use anchor_lang::Discriminator;
+use futures::stream::{self, BoxStream};
+use futures::{SinkExt, StreamExt};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::env;
+use yellowstone_grpc_client::{ClientTlsConfig, GeyserGrpcClient};
+use yellowstone_grpc_proto::prelude::{
+    subscribe_request_filter_accounts_filter::Filter,
+    subscribe_request_filter_accounts_filter_memcmp::Data, subscribe_update::UpdateOneof,
+    CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
+    SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+    SubscribeRequestPing,
+};
+
+fn interaction_request(program: &Pubkey) -> SubscribeRequest {
+    let filter = SubscribeRequestFilterAccounts {
+        owner: vec![program.to_string()],
+        filters: vec![SubscribeRequestFilterAccountsFilter {
+            filter: Some(Filter::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                offset: 0,
+                data: Some(Data::Bytes(
+                    solana_gpt_oracle::Interaction::DISCRIMINATOR.to_vec(),
+                )),
+            })),
+        }],
+        ..Default::default()
+    };
+    SubscribeRequest {
+        accounts: HashMap::from([("interactions".to_string(), filter)]),
+        commitment: Some(CommitmentLevel::Processed as i32),
+        ..Default::default()
+    }
+}
+
+/// Subscribe to the `Interaction` accounts of `program` at `url`. The stream ends, or yields an
+/// error, when the subscription drops.
+pub async fn subscribe(
+    url: &str,
+    program: &Pubkey,
+) -> Result<BoxStream<'static, Result<AccountUpdate, OracleError>>, OracleError> {
+    let mut client = GeyserGrpcClient::build_from_shared(url.to_string())?
+        .x_token(env::var("GEYSER_X_TOKEN").ok())?
+        .tls_config(ClientTlsConfig::new().with_native_roots())?
+        .connect()
+        .await?;
+    let (sink, updates) = client
+        .subscribe_with_request(Some(interaction_request(program)))
+        .await?;
+    Ok(
+        stream::unfold((sink, updates), |(mut sink, mut updates)| async move {
+            loop {
+                let update = match updates.next().await? {
+                    Ok(update) => update,
+                    Err(status) => return Some((Err(status.into()), (sink, updates))),
+                };
+                match update.update_oneof {
+                    Some(UpdateOneof::Account(update)) => {
+                        let Some(account) = update.account else {
+                            continue;
+                        };
+                        let Ok(pubkey) = Pubkey::try_from(account.pubkey.as_slice()) else {
+                            continue;
+                        };
+                        return Some((Ok((pubkey, update.slot, account.data)), (sink, updates)));
+                    }
+                    // Load balancers close streams that stay silent
+                    Some(UpdateOneof::Ping(_)) => {
+                        let pong = SubscribeRequest {
+                            ping: Some(SubscribeRequestPing { id: 1 }),
+                            ..Default::default()
+                        };
+                        if let Err(e) = sink.send(pong).await {
+                            return Some((Err(e.into()), (sink, updates)));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .boxed(),
+    )
+}
@@ -0,0 +1,301 @@
+//! GraphQL query API.
+//!
+//! With `graphql.token` (`GRAPHQL_TOKEN`) set, the metrics server answers `POST /graphql` with
+//! `Authorization: Bearer <token>` and a `{"query": "...", "variables": {...}}` body, so the
+//! history of the oracle can be queried without access to its databases. The API is read-only:
+//!
+//! - `interactions(context, since, until, provider, limit)`: archived responses, newest first,
+//!   with their status and callback signatures when the ledger still has them
+//! - `latency(context, since, until)`: statistics of the time to answer the archived interactions
+//! - `costs(context, since, until)`: tokens and fees per context and UTC day, see [`crate::costs`]
+//!
+//! `since` and `until` are Unix timestamps in milliseconds, or `YYYY-MM-DD` days for costs.
+//! Interactions and latencies come from the archive (`ARCHIVE_PATH`), which every query scans.
+
+use crate::archive::ArchiveRecord;
+use crate::costs::DailyCost;
+use crate::metrics;
+use crate::oracle::Oracle;
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use chrono::NaiveDate;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use tracing::error;
+
+pub const GRAPHQL_PATH: &str = "/graphql";
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 1000;
+
+static SCHEMA: LazyLock<Schema<Query, EmptyMutation, EmptySubscription>> = LazyLock::new(|| {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(6)
+        .finish()
+});
+
+/// Whether the endpoint is enabled
+pub fn enabled(oracle: &Oracle) -> bool {
+    oracle.config.graphql_token.is_some()
+}
+
+/// An archived response
+#[derive(SimpleObject)]
+pub struct Interaction {
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    /// Empty once deleted by the retention policy, see [`crate::retention`]
+    pub prompt: String,
+    pub response: String,
+    pub redacted: bool,
+    pub provider: String,
+    /// Sources cited by the response
+    pub sources: Vec<String>,
+    /// Unix timestamp in milliseconds
+    pub created_at: u64,
+    pub latency_ms: Option<u64>,
+    /// Sent with the provider requests, see [`crate::trace`]
+    pub trace_id: Option<String>,
+    /// Ledger status, unless evicted from the ledger
+    pub status: Option<String>,
+    pub signatures: Vec<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct Latency {
+    /// Interactions with a recorded latency
+    pub count: usize,
+    pub mean_ms: f64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct ProviderTokens {
+    pub provider: String,
+    pub prompt: u64,
+    pub completion: u64,
+}
+
+#[derive(SimpleObject)]
+pub struct Cost {
+    /// `YYYY-MM-DD`
+    pub day: String,
+    pub context: String,
+    pub interactions: u64,
+    pub tokens: Vec<ProviderTokens>,
+    pub fee_lamports: u64,
+    /// Price of the tokens at `llm.usd_per_1k_tokens`
+    pub usd: f64,
+}
+
+impl Cost {
+    fn new(cost: DailyCost, oracle: &Oracle) -> Self {
+        let usd = cost.usd(&oracle.config.llm.usd_per_1k_tokens);
+        Self {
+            tokens: cost
+                .tokens
+                .into_iter()
+                .map(|(provider, tokens)| ProviderTokens {
+                    provider,
+                    prompt: tokens.prompt,
+                    completion: tokens.completion,
+                })
+                .collect(),
+            day: cost.day,
+            context: cost.context,
+            interactions: cost.interactions,
+            fee_lamports: cost.fee_lamports,
+            usd,
+        }
+    }
+}
+
+fn parse_context(context: Option<String>) -> async_graphql::Result<Option<String>> {
+    match context {
+        Some(context) => Ok(Some(
+            Pubkey::from_str(&context)
+                .map_err(|e| format!("Invalid context {}: {}", context, e))?
+                .to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+fn parse_day(day: &str) -> async_graphql::Result<NaiveDate> {
+    Ok(NaiveDate::from_str(day).map_err(|e| format!("Invalid day {}: {}", day, e))?)
+}
+
+pub struct Query;
+
+impl Query {
+    /// Archived responses in a context and time range, newest first
+    fn archived(
+        oracle: &Oracle,
+        context: Option<String>,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> async_graphql::Result<Vec<ArchiveRecord>> {
+        let archive = oracle
+            .archive
+            .as_ref()
+            .ok_or("The archive is disabled, set ARCHIVE_PATH")?;
+        let context = parse_context(context)?;
+        let mut records = Vec::new();
+        for record in archive.iter() {
+            let record = record?;
+            if context
+                .as_ref()
+                .is_some_and(|context| *context != record.context)
+                || since.is_some_and(|since| record.created_at < since)
+                || until.is_some_and(|until| record.created_at >= until)
+            {
+                continue;
+            }
+            records.push(record);
+        }
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        Ok(records)
+    }
+}
+
+#[Object]
+impl Query {
+    /// Archived responses, newest first
+    async fn interactions(
+        &self,
+        ctx: &Context<'_>,
+        context: Option<String>,
+        since: Option<u64>,
+        until: Option<u64>,
+        provider: Option<String>,
+        limit: Option<usize>,
+    ) -> async_graphql::Result<Vec<Interaction>> {
+        let oracle = ctx.data::<Arc<Oracle>>()?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        let mut records = Self::archived(oracle, context, since, until)?;
+        if let Some(provider) = &provider {
+            records.retain(|record| record.provider == *provider);
+        }
+        records.truncate(limit);
+        Ok(records
+            .into_iter()
+            .map(|record| {
+                let entry = Pubkey::from_str(&record.interaction)
+                    .ok()
+                    .and_then(|pubkey| oracle.processed.get(&pubkey, &record.prompt).ok())
+                    .flatten();
+                Interaction {
+                    redacted: record.is_redacted(),
+                    status: entry.as_ref().map(|entry| entry.status.to_string()),
+                    signatures: entry.map(|entry| entry.signatures).unwrap_or_default(),
+                    sources: record
+                        .citations
+                        .iter()
+                        .map(|citation| citation.source.clone())
+                        .collect(),
+                    interaction: record.interaction,
+                    context: record.context,
+                    user: record.user,
+                    prompt: record.prompt,
+                    response: record.response,
+                    provider: record.provider,
+                    created_at: record.created_at,
+                    latency_ms: record.latency_ms,
+                    trace_id: record.trace_id,
+                }
+            })
+            .collect())
+    }
+
+    /// Time to answer the archived interactions
+    async fn latency(
+        &self,
+        ctx: &Context<'_>,
+        context: Option<String>,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> async_graphql::Result<Latency> {
+        let oracle = ctx.data::<Arc<Oracle>>()?;
+        let mut latencies: Vec<u64> = Self::archived(oracle, context, since, until)?
+            .iter()
+            .filter_map(|record| record.latency_ms)
+            .collect();
+        latencies.sort_unstable();
+        let percentile = |p: usize| match latencies.len() {
+            0 => 0,
+            len => latencies[(len * p / 100).min(len - 1)],
+        };
+        Ok(Latency {
+            count: latencies.len(),
+            mean_ms: match latencies.len() {
+                0 => 0.0,
+                len => latencies.iter().sum::<u64>() as f64 / len as f64,
+            },
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            max_ms: latencies.last().copied().unwrap_or_default(),
+        })
+    }
+
+    /// Costs per context and UTC day, oldest first
+    async fn costs(
+        &self,
+        ctx: &Context<'_>,
+        context: Option<String>,
+        since: String,
+        until: Option<String>,
+    ) -> async_graphql::Result<Vec<Cost>> {
+        let oracle = ctx.data::<Arc<Oracle>>()?;
+        let context = parse_context(context)?;
+        let until = until.as_deref().map(parse_day).transpose()?;
+        let mut costs = oracle.costs.since(parse_day(&since)?)?;
+        costs.retain(|cost| {
+            context
+                .as_ref()
+                .map_or(true, |context| *context == cost.context)
+                && until.map_or(true, |until| cost.day < until.to_string())
+        });
+        Ok(costs
+            .into_iter()
+            .map(|cost| Cost::new(cost, oracle))
+            .collect())
+    }
+}
+
+fn response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Answer a `POST /graphql` request
+pub async fn handle(oracle: &Arc<Oracle>, head: &str, body: &[u8]) -> String {
+    let Some(token) = oracle.config.graphql_token.as_deref() else {
+        return "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            .to_string();
+    };
+    if !metrics::authorized(head, token) {
+        return response("401 Unauthorized", r#"{"error":"Invalid token"}"#);
+    }
+    let request: async_graphql::Request = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = serde_json::json!({ "error": format!("Invalid request: {}", e) });
+            return response("400 Bad Request", &error.to_string());
+        }
+    };
+    let result = SCHEMA.execute(request.data(oracle.clone())).await;
+    match serde_json::to_string(&result) {
+        Ok(body) => response("200 OK", &body),
+        Err(e) => {
+            error!(error = ?e, "Failed to render a GraphQL response");
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string()
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Guardrails on responses before they are written on-chain.
+//!
+//! Every response is sanitized (control, zero-width and bidi override characters removed) and
+//! truncated to `max_response_bytes`. It is then rejected if it matches the blocklist or, when
+//! enabled, is flagged by the OpenAI moderation API; rejected responses are replaced by the
+//! fallback response, or a refusal code (see [`crate::refusals`]).
+
+use crate::config::GuardrailConfig;
+use crate::metrics::METRICS;
+use crate::refusals::{self, Refusal};
+use crate::OracleError;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use tracing::{info, warn};
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 4096;
+pub const DEFAULT_FALLBACK_RESPONSE: &str = "Unable to answer this request.";
+const MODERATION_URL: &str = "https://api.openai.com/v1/moderations";
+const MODERATION_MODEL: &str = "omni-moderation-latest";
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+}
+
+/// Client of the OpenAI moderation API
+struct Moderation {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl Moderation {
+    async fn flagged(&self, text: &str) -> Result<bool, OracleError> {
+        let response = self
+            .client
+            .post(MODERATION_URL)
+            .bearer_auth(&self.api_key)
+            .json(&json!({ "model": MODERATION_MODEL, "input": text }))
+            .send()
+            .await?
+            .error_for_status()?;
+        let moderation: ModerationResponse = response.json().await?;
+        Ok(moderation.results.iter().any(|result| result.flagged))
+    }
+}
+
+/// Characters that can hide or reorder text: zero-width characters and bidi controls
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+    )
+}
+
+/// Remove control characters other than newlines and tabs, and invisible characters
+pub fn strip_hidden(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !(c.is_control() && c != '\n' && c != '\t') && !is_invisible(c))
+        .collect()
+}
+
+/// [`strip_hidden`], trimmed
+pub fn sanitize(text: &str) -> String {
+    strip_hidden(text).trim().to_string()
+}
+
+/// The longest prefix of `text` that fits in `max_bytes` without splitting a character
+pub fn truncate(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+pub struct Guardrails {
+    max_bytes: usize,
+    blocklist: Vec<Regex>,
+    moderation: Option<Moderation>,
+    fallback: String,
+    /// Reject responses with a refusal code instead of the fallback response
+    refusal_codes: bool,
+}
+
+impl Guardrails {
+    pub fn new(config: &GuardrailConfig) -> Result<Self, OracleError> {
+        let blocklist = config
+            .blocklist
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut moderation = None;
+        if config.moderation {
+            moderation = Some(Moderation {
+                api_key: env::var("OPENAI_API_KEY")
+                    .map_err(|_| "GUARDRAIL_MODERATION requires OPENAI_API_KEY")?,
+                client: reqwest::Client::new(),
+            });
+        }
+        Ok(Self {
+            max_bytes: config.max_response_bytes,
+            blocklist,
+            moderation,
+            fallback: config.fallback_response.clone(),
+            refusal_codes: false,
+        })
+    }
+
+    /// Reject responses with a refusal code instead of the fallback response when `enabled`
+    pub fn with_refusal_codes(mut self, enabled: bool) -> Self {
+        self.refusal_codes = enabled;
+        self
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Whether responses are checked against the blocklist or moderation, which needs them whole
+    pub fn inspects(&self) -> bool {
+        !self.blocklist.is_empty() || self.moderation.is_some()
+    }
+
+    /// The response to send on-chain: `response` sanitized and truncated, or the fallback
+    /// response when it is empty, blocklisted or flagged. A failing moderation call rejects it.
+    /// With refusal codes, an empty response is refused as `invalid_format`, the others as
+    /// `moderation`.
+    pub async fn apply(&self, response: &str) -> String {
+        self.check(response).await.0
+    }
+
+    /// Like [`Guardrails::apply`], with the reason the response was rejected, if it was
+    pub async fn check(&self, response: &str) -> (String, Option<&'static str>) {
+        let sanitized = sanitize(response);
+        let mut checked = truncate(&sanitized, self.max_bytes).to_string();
+        if checked.len() < sanitized.len() {
+            info!(
+                bytes = sanitized.len(),
+                max = self.max_bytes,
+                "Truncated the response"
+            );
+            METRICS.guardrails.with_label_values(&["truncated"]).inc();
+        }
+
+        let rejection = if checked.is_empty() {
+            Some("empty")
+        } else if let Some(pattern) = self
+            .blocklist
+            .iter()
+            .find(|pattern| pattern.is_match(&checked))
+        {
+            warn!(%pattern, "Response matches the blocklist");
+            Some("blocklisted")
+        } else if let Some(moderation) = &self.moderation {
+            match moderation.flagged(&checked).await {
+                Ok(false) => None,
+                Ok(true) => {
+                    warn!("Response flagged by moderation");
+                    Some("flagged")
+                }
+                Err(e) => {
+                    warn!(error = ?e, "Moderation failed, rejecting the response");
+                    Some("moderation_error")
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            METRICS.guardrails.with_label_values(&[reason]).inc();
+            checked = match (self.refusal_codes, reason) {
+                (true, "empty") => refusals::refuse(Refusal::InvalidFormat),
+                (true, _) => refusals::refuse(Refusal::Moderation),
+                (false, _) => truncate(&self.fallback, self.max_bytes).to_string(),
+            };
+        }
+        (checked, rejection)
+    }
+}
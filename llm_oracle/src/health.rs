@@ -0,0 +1,233 @@
+//! Liveness and readiness probes for orchestrators.
+//!
+//! The metrics server (`METRICS_ADDR`) also answers `GET /healthz` and `GET /readyz` with a JSON
+//! [`Report`]: 200 when the check passes, 503 with the reasons when it fails.
+//!
+//! - `/readyz` fails while a program has no subscription up, the payer balance is below
+//!   `incidents.payer_min_lamports` or not fetched yet and no fee payer is funded, or the last
+//!   call to an LLM provider failed: the oracle can't answer right now.
+//! - `/healthz` fails when the instance is wedged and restarting it may help: a program has had
+//!   no subscription for `incidents.subscription_dead_secs`, or an interaction has been stuck in
+//!   one stage for `health.stall_secs`.
+//!
+//! Both report the subscriptions, when an interaction was last answered, the payer balance and
+//! which providers are reachable, as last observed by the oracle: the probes don't call the RPC
+//! node or the providers themselves.
+
+use crate::fee_payers::FeePayers;
+use crate::monitor::MONITOR;
+use crate::oracle::Oracle;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_STALL_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubscriptionStatus {
+    pub program: String,
+    pub url: String,
+    pub up: bool,
+    /// Seconds since it went down
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down_secs: Option<u64>,
+}
+
+/// What `GET /healthz` and `GET /readyz` return
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    /// Why the check fails, empty when it passes
+    pub failures: Vec<String>,
+    pub subscriptions: Vec<SubscriptionStatus>,
+    /// Unix timestamp in seconds of the last answered interaction
+    pub last_answered_at: Option<u64>,
+    pub payer_lamports: Option<u64>,
+    /// Whether the last call to each provider succeeded
+    pub providers: BTreeMap<String, bool>,
+    /// Seconds the oldest interaction in flight has been in its current stage
+    pub oldest_in_flight_secs: Option<u64>,
+}
+
+struct State {
+    /// By program and url, with the time the subscription went down, `None` while it's up
+    subscriptions: HashMap<(Pubkey, String), Option<Instant>>,
+    last_answered_at: Option<u64>,
+    payer_lamports: Option<u64>,
+    providers: BTreeMap<String, bool>,
+}
+
+pub struct Health {
+    state: Mutex<State>,
+    started: Instant,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                subscriptions: HashMap::new(),
+                last_answered_at: None,
+                payer_lamports: None,
+                providers: BTreeMap::new(),
+            }),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Health {
+    /// Follow the subscription of `program` at `url` going up or down
+    pub fn subscription(&self, program: &Pubkey, url: &str, up: bool) {
+        let mut state = self.state.lock().unwrap();
+        let down_since = state
+            .subscriptions
+            .entry((*program, url.to_string()))
+            .or_insert(None);
+        if up {
+            *down_since = None;
+        } else {
+            down_since.get_or_insert_with(Instant::now);
+        }
+    }
+
+    pub fn answered(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.state.lock().unwrap().last_answered_at = Some(now);
+    }
+
+    pub fn payer_balance(&self, lamports: u64) {
+        self.state.lock().unwrap().payer_lamports = Some(lamports);
+    }
+
+    /// Record whether a call to `provider` succeeded
+    pub fn provider(&self, provider: &str, reachable: bool) {
+        self.state
+            .lock()
+            .unwrap()
+            .providers
+            .insert(provider.to_string(), reachable);
+    }
+
+    /// Whether the last call to each provider succeeded
+    pub fn providers(&self) -> BTreeMap<String, bool> {
+        self.state.lock().unwrap().providers.clone()
+    }
+
+    /// How long each program has had no subscription up, `None` for programs subscribed
+    fn programs_down(&self, oracle: &Oracle) -> Vec<(Pubkey, Option<Duration>)> {
+        let state = self.state.lock().unwrap();
+        oracle
+            .config
+            .programs
+            .iter()
+            .map(|program| {
+                let sources: Vec<&Option<Instant>> = state
+                    .subscriptions
+                    .iter()
+                    .filter(|((id, _), _)| *id == program.id)
+                    .map(|(_, down_since)| down_since)
+                    .collect();
+                if sources.iter().any(|down_since| down_since.is_none()) {
+                    return (program.id, None);
+                }
+                // Down since the first source went down, or since startup when never subscribed
+                let since = sources
+                    .iter()
+                    .filter_map(|down_since| **down_since)
+                    .min()
+                    .unwrap_or(self.started);
+                (program.id, Some(since.elapsed()))
+            })
+            .collect()
+    }
+
+    fn report(&self, failures: Vec<String>) -> Report {
+        let state = self.state.lock().unwrap();
+        let mut subscriptions: Vec<SubscriptionStatus> = state
+            .subscriptions
+            .iter()
+            .map(|((program, url), down_since)| SubscriptionStatus {
+                program: program.to_string(),
+                url: url.clone(),
+                up: down_since.is_none(),
+                down_secs: down_since.map(|since| since.elapsed().as_secs()),
+            })
+            .collect();
+        subscriptions.sort_by(|a, b| (&a.program, &a.url).cmp(&(&b.program, &b.url)));
+        Report {
+            ok: failures.is_empty(),
+            failures,
+            subscriptions,
+            last_answered_at: state.last_answered_at,
+            payer_lamports: state.payer_lamports,
+            providers: state.providers.clone(),
+            oldest_in_flight_secs: MONITOR.snapshot().in_flight.first().map(|entry| entry.secs),
+        }
+    }
+
+    /// Whether the oracle can answer interactions now
+    pub fn readiness(&self, oracle: &Oracle) -> Report {
+        let mut failures = Vec::new();
+        for (program, down) in self.programs_down(oracle) {
+            if down.is_some() {
+                failures.push(format!("No subscription to {} is up", program));
+            }
+        }
+        let min_lamports = oracle.config.incidents.payer_min_lamports;
+        let payer_lamports = self.state.lock().unwrap().payer_lamports;
+        let fee_payers_funded = oracle
+            .callback_sender
+            .fee_payers
+            .as_ref()
+            .is_some_and(FeePayers::funded);
+        match payer_lamports {
+            _ if fee_payers_funded => {}
+            None => failures.push("Payer balance not fetched yet".to_string()),
+            Some(lamports) if lamports < min_lamports => failures.push(format!(
+                "Payer balance {} is below {} lamports",
+                lamports, min_lamports
+            )),
+            Some(_) => {}
+        }
+        let providers = self.state.lock().unwrap().providers.clone();
+        for (provider, reachable) in providers {
+            if !reachable {
+                failures.push(format!("Last call to {} failed", provider));
+            }
+        }
+        self.report(failures)
+    }
+
+    /// Whether the oracle is making progress, or is wedged and should be restarted
+    pub fn liveness(&self, oracle: &Oracle) -> Report {
+        let mut failures = Vec::new();
+        let dead_after = Duration::from_secs(oracle.config.incidents.subscription_dead_secs);
+        for (program, down) in self.programs_down(oracle) {
+            if let Some(down) = down.filter(|down| *down >= dead_after) {
+                failures.push(format!(
+                    "No subscription to {} for {}s",
+                    program,
+                    down.as_secs()
+                ));
+            }
+        }
+        let stall_secs = oracle.config.health_stall_secs;
+        if let Some(stuck) = MONITOR.snapshot().in_flight.first() {
+            if stuck.secs >= stall_secs {
+                failures.push(format!(
+                    "Interaction {} stuck in {} for {}s",
+                    stuck.interaction, stuck.status, stuck.secs
+                ));
+            }
+        }
+        self.report(failures)
+    }
+}
+
+pub static HEALTH: LazyLock<Health> = LazyLock::new(Health::default);
@@ -0,0 +1,64 @@
+//! Oracle identity.
+//!
+//! The oracle signs callbacks through a [`Signer`] trait object so the key doesn't have to live
+//! in the process: today it is loaded from a Solana JSON keypair file or a base58 string, and a
+//! remote signer only needs to implement [`Signer`].
+
+use crate::OracleError;
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+
+/// Signer of the oracle's callback transactions
+pub type OracleSigner = Box<dyn Signer + Send + Sync>;
+
+/// Public test keypair shipped in `.env.example` and the docs
+pub const TEST_IDENTITY: Pubkey = pubkey!("tEsT3eV6RFCWs1BZ7AXTzasHqTtMnMLCB2tjQ42TDXD");
+
+/// Where the oracle keypair comes from
+#[derive(Debug, Clone)]
+pub enum IdentitySource {
+    /// Solana CLI JSON keypair file
+    KeypairFile(String),
+    /// Base58 encoded keypair
+    Base58(String),
+}
+
+impl IdentitySource {
+    pub fn load(&self) -> Result<OracleSigner, OracleError> {
+        Ok(Box::new(self.keypair()?))
+    }
+
+    /// The keypair itself, for keys derived from it such as [`crate::encryption::PromptKey`]
+    pub fn keypair(&self) -> Result<Keypair, OracleError> {
+        match self {
+            Self::KeypairFile(path) => Ok(read_keypair_file(path)
+                .map_err(|e| format!("Can't read the oracle keypair {}: {}", path, e))?),
+            Self::Base58(encoded) => {
+                let bytes = solana_sdk::bs58::decode(encoded.trim())
+                    .into_vec()
+                    .map_err(|e| format!("Invalid base58 oracle identity: {}", e))?;
+                Ok(Keypair::from_bytes(&bytes)
+                    .map_err(|e| format!("Invalid oracle identity keypair: {}", e))?)
+            }
+        }
+    }
+}
+
+/// Whether `rpc_url` points at Solana mainnet
+pub fn is_mainnet(rpc_url: &str) -> bool {
+    rpc_url.contains("mainnet")
+}
+
+/// Refuse the public test identity on mainnet, where anyone could drain it or sign as the oracle
+pub fn check_identity(signer: &dyn Signer, rpc_url: &str) -> Result<(), OracleError> {
+    if signer.pubkey() == TEST_IDENTITY && is_mainnet(rpc_url) {
+        return Err(format!(
+            "The public test identity {} can't be used on mainnet ({}): \
+             generate one with `llm_oracle keygen --outfile <file>` and set IDENTITY_KEYPAIR_PATH",
+            TEST_IDENTITY, rpc_url
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+//! Image inputs.
+//!
+//! With `images.enabled` (`IMAGE_INPUTS`), an interaction whose text starts with `[image:<uri>]`
+//! asks about an image: consumer programs store the URI of the image alongside the question, e.g.
+//! `[image:ipfs://<cid>] What is on this card?`. The tag is removed from the question and the
+//! image is fetched and sent with it to models that see images: Gemini, the OpenAI vision models
+//! (`gpt-4o`, `gpt-4.1`, ...), and OpenAI-compatible endpoints declared with `llm.vision`
+//! (`LLM_VISION`), which also overrides the detection for the others.
+//!
+//! URIs are `https://`, `ipfs://` (fetched through `images.ipfs_gateway`) or `ar://` (fetched
+//! from Arweave). Images larger than `images.max_bytes`, or whose first bytes aren't one of
+//! `images.types`, are refused; the type the server claims is ignored. Host names that are IP
+//! addresses or `localhost` are refused, and with `images.allowed_hosts` only those hosts are
+//! contacted, gateways and redirects included.
+//!
+//! When the model can't see images or the image can't be used, the model is told so and answers
+//! from the text. Interactions with an image aren't answered from the response cache, since the
+//! image behind a URI may change, and images aren't kept in the conversation history.
+
+use crate::config::ImageConfig;
+use crate::metrics::METRICS;
+use crate::providers::{ChatProvider, Image};
+use crate::OracleError;
+use base64::Engine;
+use reqwest::Url;
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::warn;
+
+pub const DEFAULT_MAX_IMAGE_BYTES: usize = 4 * 1024 * 1024;
+/// Types of images that can be sent, as recognized by [`sniff`]
+pub const IMAGE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/webp", "image/gif"];
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io";
+pub const ARWEAVE_GATEWAY: &str = "https://arweave.net";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Split the text of an interaction into the URI of its image and the question
+pub fn parse(text: &str) -> Option<(&str, &str)> {
+    let rest = text.trim_start().strip_prefix("[image:")?;
+    let (uri, question) = rest.split_once(']')?;
+    let uri = uri.trim();
+    (!uri.is_empty()).then_some((uri, question.trim_start()))
+}
+
+/// MIME type of an image, from its first bytes
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Fetches the images of interactions within the configured limits
+pub struct ImageFetcher {
+    config: ImageConfig,
+    client: reqwest::Client,
+}
+
+impl ImageFetcher {
+    pub fn new(config: ImageConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The URL the image at `uri` is fetched from
+    pub fn resolve(&self, uri: &str) -> Result<Url, OracleError> {
+        let url = if let Some(path) = uri.strip_prefix("ipfs://") {
+            format!(
+                "{}/ipfs/{}",
+                self.config.ipfs_gateway.trim_end_matches('/'),
+                path.trim_start_matches("ipfs/")
+            )
+        } else if let Some(id) = uri.strip_prefix("ar://") {
+            format!("{}/{}", ARWEAVE_GATEWAY, id)
+        } else if uri.starts_with("https://") {
+            uri.to_string()
+        } else {
+            return Err(format!(
+                "Unsupported image URI {:?}, expected https://, ipfs:// or ar://",
+                uri
+            )
+            .into());
+        };
+        let url = Url::parse(&url).map_err(|e| format!("Invalid image URI {:?}: {}", uri, e))?;
+        self.check_host(&url)?;
+        Ok(url)
+    }
+
+    fn check_host(&self, url: &Url) -> Result<(), OracleError> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| format!("Image URL {} has no host", url))?
+            .to_lowercase();
+        // IP addresses could reach the oracle's own network; IPv6 ones are bracketed
+        if host.starts_with('[') || host.parse::<IpAddr>().is_ok() {
+            return Err(format!("Image URL {} has an IP address for host", url).into());
+        }
+        if host == "localhost" || host.ends_with(".localhost") {
+            return Err(format!("Image URL {} is local", url).into());
+        }
+        if !self.config.allowed_hosts.is_empty() && !self.config.allowed_hosts.contains(&host) {
+            return Err(format!("Image host {} isn't allowed", host).into());
+        }
+        Ok(())
+    }
+
+    /// Download the image at `uri`
+    pub async fn fetch(&self, uri: &str) -> Result<Image, OracleError> {
+        let max_bytes = self.config.max_bytes;
+        let too_large = || format!("Image is larger than {} bytes", max_bytes);
+        let mut response = self
+            .client
+            .get(self.resolve(uri)?)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        // The host redirected to
+        self.check_host(response.url())?;
+        if response
+            .content_length()
+            .is_some_and(|len| len as usize > max_bytes)
+        {
+            return Err(too_large().into());
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if data.len() + chunk.len() > max_bytes {
+                return Err(too_large().into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let mime_type = sniff(&data)
+            .filter(|kind| self.config.types.iter().any(|allowed| allowed == kind))
+            .ok_or_else(|| format!("Image isn't one of {}", self.config.types.join(", ")))?;
+        Ok(Image {
+            mime_type: mime_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(&data),
+        })
+    }
+
+    /// The image at `uri` for `provider`, or the note telling the model why it isn't attached
+    pub async fn attach(&self, provider: &dyn ChatProvider, uri: &str) -> Result<Image, String> {
+        if !provider.sees_images() {
+            METRICS
+                .image_inputs
+                .with_label_values(&["unsupported"])
+                .inc();
+            return Err("The user attached an image, which you can't see.".to_string());
+        }
+        match self.fetch(uri).await {
+            Ok(image) => {
+                METRICS.image_inputs.with_label_values(&["attached"]).inc();
+                Ok(image)
+            }
+            Err(e) => {
+                warn!(uri, error = ?e, "Failed to fetch the image of an interaction");
+                METRICS.image_inputs.with_label_values(&["failed"]).inc();
+                Err("The user attached an image, which couldn't be loaded.".to_string())
+            }
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Incidents.
+//!
+//! Critical conditions open an incident: a critical [`Event`] with a key, so PagerDuty and
+//! Opsgenie deduplicate it, sent once while the condition lasts and resolved automatically when
+//! it clears:
+//!
+//! - [`PAYER_EMPTY`]: the payer balance is below `incidents.payer_min_lamports`, checked every
+//!   `incidents.payer_check_secs`. With `incidents.pause_when_payer_empty`, interactions wait
+//!   for the payer to be funded before their LLM call instead of paying for answers that can't
+//!   land; with `incidents.payer_airdrop_lamports` (devnet, testnet or a local validator), an
+//!   airdrop is requested on every check while it lasts. With fee payers (see
+//!   [`crate::fee_payers`]), callbacks go on while one of them is funded: interactions aren't
+//!   held, and the incident is a warning.
+//! - [`PROVIDER_HARD_DOWN`]: an LLM provider still fails after its circuit breaker cooldown
+//! - [`SUBSCRIPTION_DEAD`]: the program subscription has been down for
+//!   `incidents.subscription_dead_secs` (a warning while a second subscription is up)
+//! - [`crate::flood::CONTEXT_FLOODED`]: a context produced more than `flood.max_per_second`
+//!   interactions within about a second and was deactivated with `flood.deactivate` (a warning
+//!   while it is only throttled)
+//!
+//! [`SUBSCRIPTION_LAGGING`], raised by [`crate::multiplex`] when one of two subscriptions misses
+//! updates the other delivers, [`crate::providers::BUDGET_EXHAUSTED`], raised while a provider
+//! is out of its daily budget, and [`crate::fee_payers::FEE_PAYER_DRAINED`], raised while a fee
+//! payer is left out of the rotation, are warnings tracked the same way.
+//!
+//! Incidents go through [`crate::notify`] like any other event, so routes decide which channels
+//! page someone.
+
+use crate::fee_payers::FeePayers;
+use crate::health::HEALTH;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::signature::Signer;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+pub const PAYER_EMPTY: &str = "payer_empty";
+pub const PROVIDER_HARD_DOWN: &str = "provider_hard_down";
+pub const SUBSCRIPTION_DEAD: &str = "subscription_dead";
+pub const SUBSCRIPTION_LAGGING: &str = "subscription_lagging";
+
+/// Enough for a few hundred callbacks at the base fee
+pub const DEFAULT_PAYER_MIN_LAMPORTS: u64 = 1_000_000;
+pub const DEFAULT_SUBSCRIPTION_DEAD_SECS: u64 = 300;
+pub const DEFAULT_PAYER_CHECK_SECS: u64 = 60;
+
+/// Open incidents by kind and key, with the event that opened them
+static OPEN: LazyLock<Mutex<HashMap<(&'static str, String), Event>>> =
+    LazyLock::new(Default::default);
+
+/// Whether the payer holds at least `incidents.payer_min_lamports`, until checked otherwise
+static PAYER_FUNDED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(true));
+
+/// Open an incident for a critical event with a key, unless it is already open
+pub fn trigger(event: Event) {
+    let id = (event.kind, event.key.clone().unwrap_or_default());
+    {
+        let mut open = OPEN.lock().unwrap();
+        if open.contains_key(&id) {
+            return;
+        }
+        open.insert(id, event.clone());
+    }
+    error!(kind = event.kind, key = ?event.key, title = %event.title, "Incident opened");
+    notify::emit(event);
+}
+
+/// Resolve the incident of `kind` and `key`, if it is open
+pub fn resolve(kind: &'static str, key: &str) {
+    let Some(event) = OPEN.lock().unwrap().remove(&(kind, key.to_string())) else {
+        return;
+    };
+    info!(kind, key, title = %event.title, "Incident resolved");
+    notify::emit(event.into_resolved());
+}
+
+/// Wait until the payer holds at least `incidents.payer_min_lamports`, returning right away
+/// unless the last check found it empty
+pub async fn payer_funded() {
+    let mut funded = PAYER_FUNDED.subscribe();
+    let _ = funded.wait_for(|funded| *funded).await;
+}
+
+/// Check the payer balance every `incidents.payer_check_secs`, opening a [`PAYER_EMPTY`]
+/// incident while it is below `incidents.payer_min_lamports`, and those of the fee payers
+pub async fn watch_payer(oracle: Arc<Oracle>) {
+    let payer = oracle.config.payer.pubkey();
+    let key = payer.to_string();
+    let config = &oracle.config.incidents;
+    let min_lamports = config.payer_min_lamports;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.payer_check_secs));
+    loop {
+        interval.tick().await;
+        let fee_payers = oracle.callback_sender.fee_payers.as_ref();
+        if let Some(fee_payers) = fee_payers {
+            fee_payers.check(&oracle.rpc_client).await;
+        }
+        let fee_payers_funded = fee_payers.is_some_and(FeePayers::funded);
+        let lamports = match oracle.rpc_client.get_balance(&payer).await {
+            Ok(lamports) => lamports,
+            Err(e) => {
+                warn!(error = ?e, "Failed to fetch the payer balance");
+                continue;
+            }
+        };
+        HEALTH.payer_balance(lamports);
+        if config.pause_when_payer_empty {
+            PAYER_FUNDED.send_if_modified(|funded| {
+                let was_funded =
+                    std::mem::replace(funded, lamports >= min_lamports || fee_payers_funded);
+                if was_funded && !*funded {
+                    warn!("Payer is empty, holding interactions before their LLM call");
+                } else if !was_funded && *funded {
+                    info!("Payer is funded, resuming interactions");
+                }
+                was_funded != *funded
+            });
+        }
+        if lamports < min_lamports {
+            if let Some(airdrop) = config.payer_airdrop_lamports {
+                match oracle.rpc_client.request_airdrop(&payer, airdrop).await {
+                    Ok(signature) => info!(
+                        %signature,
+                        sol = lamports_to_sol(airdrop),
+                        "Requested an airdrop for the payer"
+                    ),
+                    Err(e) => warn!(error = ?e, "Payer airdrop failed"),
+                }
+            }
+            let severity = if fee_payers_funded {
+                Severity::Warning
+            } else {
+                Severity::Critical
+            };
+            trigger(
+                Event::new(
+                    severity,
+                    PAYER_EMPTY,
+                    "Payer balance is empty",
+                    format!(
+                        "Payer {} holds {} SOL, below the {} SOL needed to keep answering. \
+                         Fund it to resume callbacks.",
+                        payer,
+                        lamports_to_sol(lamports),
+                        lamports_to_sol(min_lamports)
+                    ),
+                )
+                .with_key(&key),
+            );
+        } else {
+            resolve(PAYER_EMPTY, &key);
+        }
+    }
+}
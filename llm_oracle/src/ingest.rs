@@ -0,0 +1,523 @@
+//! Sponsored HTTP ingestion.
+//!
+//! For apps whose users don't hold SOL: with `ingest.keys_file` (`INGEST_KEYS_FILE`) and
+//! `ingest.sponsor_keypair` (`SPONSOR_KEYPAIR`) set, a server on `ingest.addr` (`INGEST_ADDR`),
+//! apart from the metrics server, answers `POST /api/interactions` with
+//! `Authorization: Bearer <api key>` and a `{"context": "<pubkey>", "text": "..."}` body. The
+//! oracle creates the interaction on-chain, paid by the sponsor key and answered with
+//! `callback_from_oracle`, then waits up to `ingest.timeout_secs` (`INGEST_TIMEOUT_SECS`) for its
+//! own answer and returns it:
+//!
+//! - `200` with `{"interaction", "signature", "response"}` once answered
+//! - `202` with `{"interaction", "signature", "status"}` when the answer isn't in yet; it lands
+//!   on-chain in the callback transaction
+//! - `401` for an unknown key, `429` with `Retry-After` for a key over its quota
+//!
+//! Keys are listed in a JSON file, `[{"name": "app", "key": "...", "per_minute": 10,
+//! "per_day": 1000}]`, each with its quotas; usage is counted in memory, so daily quotas start
+//! over with the oracle. The program derives interaction accounts from the payer and the context,
+//! so there is one sponsored interaction per context: a context is held from a request until its
+//! interaction is settled, even past the `202`, so the next question doesn't overwrite one still
+//! unanswered. Requests to a held context wait up to `ingest.timeout_secs`, then get `409`. A
+//! question the key already had answered for the context is answered again from the ledger
+//! without a transaction; asked by another key, or abandoned, it gets `409`, as the oracle doesn't
+//! answer the same question of an interaction twice. All sponsored interactions have the sponsor
+//! as their user, which `limits.per_user_per_minute` counts as one.
+
+use crate::admin::interact_instruction;
+use crate::config::IngestConfig;
+use crate::dedup::prompt_hash;
+use crate::metrics::{self, METRICS};
+use crate::oracle::Oracle;
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_gpt_oracle::REFUND_TIMEOUT_SECS;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{read_keypair_file, Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::OwnedMutexGuard;
+use tracing::{error, info, warn, Instrument};
+
+pub const INGEST_PATH: &str = "/api/interactions";
+pub const DEFAULT_INGEST_TIMEOUT_SECS: u64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const HOLD_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest a context is held for an interaction left unanswered: past the refund timeout, it is
+/// for its refund
+const MAX_HOLD: Duration = Duration::from_secs(REFUND_TIMEOUT_SECS as u64);
+
+/// A client of the endpoint and its quotas
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    /// Shown in logs and metrics instead of the key
+    pub name: String,
+    pub key: String,
+    pub per_minute: Option<u32>,
+    pub per_day: Option<u32>,
+}
+
+#[derive(Default)]
+struct Usage {
+    minute: VecDeque<Instant>,
+    day: Option<NaiveDate>,
+    today: u32,
+}
+
+impl Usage {
+    /// Count a request if `key` is under its quotas, otherwise how long until it is
+    fn admit(&mut self, key: &ApiKey, now: Instant) -> Result<(), Duration> {
+        let window = Duration::from_secs(60);
+        while self
+            .minute
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= window)
+        {
+            self.minute.pop_front();
+        }
+        let today = Utc::now().date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.today = 0;
+        }
+        if key.per_day.is_some_and(|limit| self.today >= limit) {
+            let midnight = (today + chrono::Days::new(1))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc();
+            return Err((midnight - Utc::now()).to_std().unwrap_or_default());
+        }
+        if key
+            .per_minute
+            .is_some_and(|limit| self.minute.len() >= limit as usize)
+        {
+            let oldest = self.minute.front().copied().unwrap_or(now);
+            return Err(window.saturating_sub(now.duration_since(oldest)));
+        }
+        self.minute.push_back(now);
+        self.today += 1;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct IngestRequest {
+    context: String,
+    text: String,
+}
+
+/// The endpoint's keys, sponsor and per-context queues
+pub struct Ingest {
+    addr: String,
+    sponsor: Keypair,
+    keys: Vec<ApiKey>,
+    timeout: Duration,
+    usage: Mutex<HashMap<String, Usage>>,
+    /// Held while the sponsored interaction of a context is sent, until it is settled
+    contexts: Mutex<HashMap<Pubkey, Arc<tokio::sync::Mutex<()>>>>,
+    /// The hash of the last question of each sponsored interaction, and the key that asked it
+    askers: Mutex<HashMap<Pubkey, ([u8; 32], String)>>,
+}
+
+impl Ingest {
+    pub fn new(addr: String, sponsor: Keypair, keys: Vec<ApiKey>, timeout: Duration) -> Self {
+        Self {
+            addr,
+            sponsor,
+            keys,
+            timeout,
+            usage: Mutex::new(HashMap::new()),
+            contexts: Mutex::new(HashMap::new()),
+            askers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The endpoint of `config`; `None` when it has no keys file
+    pub fn load(config: &IngestConfig) -> Result<Option<Self>, OracleError> {
+        let Some(path) = &config.keys_file else {
+            return Ok(None);
+        };
+        let keys: Vec<ApiKey> = serde_json::from_str(
+            &fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read `ingest.keys_file` {}: {}", path, e))?,
+        )
+        .map_err(|e| format!("Invalid `ingest.keys_file` {}: {}", path, e))?;
+        if keys.iter().any(|key| key.key.len() < 16) {
+            return Err("`ingest.keys_file` keys must be at least 16 characters".into());
+        }
+        let addr = config
+            .addr
+            .clone()
+            .ok_or("`ingest.keys_file` requires `ingest.addr`")?;
+        let sponsor_path = config
+            .sponsor_keypair
+            .as_deref()
+            .ok_or("`ingest.keys_file` requires `ingest.sponsor_keypair`")?;
+        let sponsor = read_keypair_file(sponsor_path).map_err(|e| {
+            format!(
+                "Can't read `ingest.sponsor_keypair` {}: {}",
+                sponsor_path, e
+            )
+        })?;
+        info!(%addr, sponsor = %sponsor.pubkey(), keys = keys.len(), "Sponsored ingestion enabled");
+        Ok(Some(Self::new(
+            addr,
+            sponsor,
+            keys,
+            Duration::from_secs(config.timeout_secs),
+        )))
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn sponsor(&self) -> Pubkey {
+        self.sponsor.pubkey()
+    }
+
+    pub fn keys(&self) -> &[ApiKey] {
+        &self.keys
+    }
+
+    /// The key of a request head, from `Authorization: Bearer` or `X-Api-Key`, compared in
+    /// constant time
+    fn authenticate(&self, head: &str) -> Option<&ApiKey> {
+        let presented = head.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "authorization" => value.strip_prefix("Bearer ").map(str::trim),
+                "x-api-key" => Some(value),
+                _ => None,
+            }
+        })?;
+        self.keys
+            .iter()
+            .find(|key| metrics::constant_time_eq(&key.key, presented))
+    }
+
+    fn context_lock(&self, context: &Pubkey) -> Arc<tokio::sync::Mutex<()>> {
+        self.contexts
+            .lock()
+            .unwrap()
+            .entry(*context)
+            .or_default()
+            .clone()
+    }
+
+    /// Create the sponsored interaction asking `text` in `context` for `key` and wait for its
+    /// answer
+    async fn submit(
+        &self,
+        oracle: &Arc<Oracle>,
+        key: &ApiKey,
+        context: &Pubkey,
+        text: &str,
+    ) -> Result<(&'static str, Value), (&'static str, String)> {
+        let bad_request = |message: String| ("400 Bad Request", message);
+        let account = oracle
+            .rpc_client
+            .get_account(context)
+            .await
+            .map_err(|_| bad_request(format!("Unknown context {}", context)))?;
+        let program = oracle
+            .config
+            .program(&account.owner)
+            .map_err(|_| bad_request(format!("{} is not a context of this oracle", context)))?
+            .id;
+        let sponsor = self.sponsor.pubkey();
+        let interaction = Pubkey::find_program_address(
+            &[
+                solana_gpt_oracle::Interaction::seed(),
+                sponsor.as_ref(),
+                context.as_ref(),
+            ],
+            &program,
+        )
+        .0;
+
+        let held = tokio::time::timeout(self.timeout, self.context_lock(context).lock_owned());
+        let Ok(queued) = held.await else {
+            return Err((
+                "409 Conflict",
+                "Another question of this context is being answered, try again later".to_string(),
+            ));
+        };
+        // Asked before: the oracle wouldn't answer it again, and only the key that asked it gets
+        // the answer from the ledger
+        let hash = prompt_hash(text);
+        let asked = oracle.processed.get(&interaction, text).ok().flatten();
+        if let Some(entry) = asked.filter(|entry| entry.status.is_final()) {
+            let asked_by_key = self
+                .askers
+                .lock()
+                .unwrap()
+                .get(&interaction)
+                .is_some_and(|(asked, name)| *asked == hash && *name == key.name);
+            if entry.status != InteractionStatus::Confirmed || !asked_by_key {
+                return Err((
+                    "409 Conflict",
+                    "This question was already asked in this context".to_string(),
+                ));
+            }
+            return Ok((
+                "200 OK",
+                json!({
+                    "interaction": interaction.to_string(),
+                    "signature": entry.signatures.last(),
+                    "response": entry.response,
+                }),
+            ));
+        }
+
+        let instruction = interact_instruction(&program, &sponsor, context, text.to_string());
+        let blockhash = oracle
+            .rpc_client
+            .get_latest_blockhash()
+            .await
+            .map_err(|e| ("503 Service Unavailable", e.to_string()))?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&sponsor),
+            &[&self.sponsor],
+            blockhash,
+        );
+        let size = bincode::serialized_size(&transaction).unwrap_or(u64::MAX);
+        if size > PACKET_DATA_SIZE as u64 {
+            return Err(bad_request("The text is too long".to_string()));
+        }
+        let signature = oracle
+            .rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await
+            .map_err(|e| {
+                warn!(%context, error = ?e, "Failed to send the sponsored interaction");
+                (
+                    "503 Service Unavailable",
+                    "The interaction couldn't be created".to_string(),
+                )
+            })?;
+        info!(%context, %interaction, %signature, "Sent a sponsored interaction");
+        self.askers
+            .lock()
+            .unwrap()
+            .insert(interaction, (hash, key.name.clone()));
+
+        // Answered by the listener like any interaction
+        let deadline = Instant::now() + self.timeout;
+        let mut status = None;
+        while Instant::now() < deadline {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let Ok(Some(entry)) = oracle.processed.get(&interaction, text) else {
+                continue;
+            };
+            status = Some(entry.status);
+            if entry.status == InteractionStatus::Confirmed {
+                return Ok((
+                    "200 OK",
+                    json!({
+                        "interaction": interaction.to_string(),
+                        "signature": signature.to_string(),
+                        "response": entry.response,
+                    }),
+                ));
+            }
+            if entry.status == InteractionStatus::Abandoned {
+                break;
+            }
+        }
+        if !status.is_some_and(InteractionStatus::is_final) {
+            tokio::spawn(
+                hold(oracle.clone(), queued, interaction, text.to_string()).in_current_span(),
+            );
+        }
+        Ok((
+            "202 Accepted",
+            json!({
+                "interaction": interaction.to_string(),
+                "signature": signature.to_string(),
+                "status": status.map_or("pending", InteractionStatus::as_str),
+            }),
+        ))
+    }
+
+    /// Answer a `POST /api/interactions` request: the HTTP response
+    pub async fn handle(&self, oracle: &Arc<Oracle>, head: &str, body: &[u8]) -> String {
+        let Some(key) = self.authenticate(head) else {
+            return response(
+                "401 Unauthorized",
+                &json!({ "error": "Invalid API key" }),
+                None,
+            );
+        };
+        let admitted = self
+            .usage
+            .lock()
+            .unwrap()
+            .entry(key.name.clone())
+            .or_default()
+            .admit(key, Instant::now());
+        if let Err(wait) = admitted {
+            METRICS
+                .sponsored_interactions
+                .with_label_values(&[&key.name, "quota"])
+                .inc();
+            return response(
+                "429 Too Many Requests",
+                &json!({ "error": "Quota exceeded" }),
+                Some(wait.as_secs().max(1)),
+            );
+        }
+        let request: IngestRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return response(
+                    "400 Bad Request",
+                    &json!({ "error": format!("Invalid request: {}", e) }),
+                    None,
+                )
+            }
+        };
+        let Ok(context) = Pubkey::from_str(&request.context) else {
+            return response(
+                "400 Bad Request",
+                &json!({ "error": "Invalid context" }),
+                None,
+            );
+        };
+        let text = request.text.trim();
+        if text.is_empty() {
+            return response("400 Bad Request", &json!({ "error": "Empty text" }), None);
+        }
+        match self.submit(oracle, key, &context, text).await {
+            Ok((status, body)) => {
+                METRICS
+                    .sponsored_interactions
+                    .with_label_values(&[&key.name, "sent"])
+                    .inc();
+                response(status, &body, None)
+            }
+            Err((status, error)) => {
+                METRICS
+                    .sponsored_interactions
+                    .with_label_values(&[&key.name, "error"])
+                    .inc();
+                response(status, &json!({ "error": error }), None)
+            }
+        }
+    }
+}
+
+/// Keep a context held until its interaction asking `text` is settled, at most [`MAX_HOLD`]
+async fn hold(oracle: Arc<Oracle>, queued: OwnedMutexGuard<()>, interaction: Pubkey, text: String) {
+    let deadline = Instant::now() + MAX_HOLD;
+    while Instant::now() < deadline {
+        tokio::time::sleep(HOLD_POLL_INTERVAL).await;
+        let settled = oracle.processed.get(&interaction, &text);
+        if settled.is_ok_and(|entry| entry.is_some_and(|entry| entry.status.is_final())) {
+            break;
+        }
+    }
+    drop(queued);
+}
+
+fn response(status: &str, body: &Value, retry_after: Option<u64>) -> String {
+    let body = body.to_string();
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\n{}Content-Length: {}\r\nConnection: \
+         close\r\n\r\n{}",
+        status,
+        retry_after.map_or(String::new(), |secs| format!("Retry-After: {}\r\n", secs)),
+        body.len(),
+        body
+    )
+}
+
+/// Answer `POST /api/interactions` on `ingest.addr` until the listener fails
+pub async fn serve(oracle: Arc<Oracle>) -> Result<(), OracleError> {
+    let Some(ingest) = &oracle.ingest else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(&ingest.addr).await?;
+    info!(addr = %ingest.addr, "Serving sponsored ingestion");
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!(error = ?e, "Failed to accept an ingestion connection");
+                continue;
+            }
+        };
+        let oracle = oracle.clone();
+        tokio::spawn(async move {
+            let Some(ingest) = &oracle.ingest else {
+                return;
+            };
+            let Some((request, body)) = metrics::read_request(&mut stream).await else {
+                return;
+            };
+            let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+                ["POST", path] if path == INGEST_PATH => {
+                    ingest.handle(&oracle, &request, &body).await
+                }
+                _ => response("404 Not Found", &json!({ "error": "Not found" }), None),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authenticates_keys_exactly() {
+        let key = ApiKey {
+            name: "app".to_string(),
+            key: "0123456789abcdef".to_string(),
+            per_minute: None,
+            per_day: None,
+        };
+        let ingest = Ingest::new(
+            "127.0.0.1:0".to_string(),
+            Keypair::new(),
+            vec![key],
+            Duration::from_secs(1),
+        );
+        let head = |header: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\n{}\r\nHost: oracle",
+                INGEST_PATH, header
+            )
+        };
+        for header in [
+            "Authorization: Bearer 0123456789abcdef",
+            "x-api-key:  0123456789abcdef ",
+        ] {
+            let key = ingest
+                .authenticate(&head(header))
+                .map(|key| key.name.as_str());
+            assert_eq!(key, Some("app"), "{}", header);
+        }
+        for header in [
+            "Authorization: Bearer 0123456789abcdeF",
+            "Authorization: Bearer 0123456789abcde",
+            "X-Api-Key: 0123456789abcdef0",
+            "X-Api-Key: ",
+        ] {
+            assert!(ingest.authenticate(&head(header)).is_none(), "{}", header);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! Jito bundle submission for callback transactions.
+//!
+//! On a congested mainnet a priority fee alone doesn't guarantee that a callback lands in time.
+//! With `callback.jito_url` set, each callback transaction also transfers
+//! `callback.jito_tip_lamports` to one of the block engine's tip accounts and is submitted to
+//! the block engine as a single transaction bundle, which Jito validators include in the slots
+//! they lead.
+//!
+//! The bundle path only replaces the broadcast: landing is still watched through the RPC node,
+//! see [`crate::confirmation`]. When the block engine rejects a bundle or can't be reached, the
+//! same transaction is sent through the RPC node instead. The tip is part of the transaction,
+//! so it is paid whichever way the transaction lands. When the tip accounts can't be fetched,
+//! the callback is sent without a tip through the RPC node.
+
+use crate::OracleError;
+use base64::Engine;
+use serde_json::{json, Value};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::VersionedTransaction;
+use std::str::FromStr;
+use tokio::sync::OnceCell;
+
+/// Jito rejects bundles tipping less
+pub const MIN_TIP_LAMPORTS: u64 = 1_000;
+pub const DEFAULT_TIP_LAMPORTS: u64 = 10_000;
+
+/// The tip of a callback transaction, transferred by the payer
+pub fn tip_instruction(payer: &Pubkey, tip_account: &Pubkey, lamports: u64) -> Instruction {
+    system_instruction::transfer(payer, tip_account, lamports)
+}
+
+/// Client of a Jito block engine's bundle API
+pub struct JitoClient {
+    url: String,
+    pub tip_lamports: u64,
+    tip_accounts: OnceCell<Vec<Pubkey>>,
+    http: reqwest::Client,
+}
+
+impl JitoClient {
+    /// `url` is the block engine, e.g. `https://mainnet.block-engine.jito.wtf`
+    pub fn new(url: String, tip_lamports: u64) -> Self {
+        Self {
+            url: format!("{}/api/v1/bundles", url.trim_end_matches('/')),
+            tip_lamports,
+            tip_accounts: OnceCell::new(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, OracleError> {
+        let response: Value = self
+            .http
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": "llm-oracle",
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(format!("Block engine {} failed: {}", method, error).into());
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| format!("Unexpected block engine response: {}", response).into())
+    }
+
+    /// A tip account picked at random, spreading the tips as Jito recommends. The accounts are
+    /// fetched from the block engine once.
+    pub async fn tip_account(&self) -> Result<Pubkey, OracleError> {
+        let accounts = self
+            .tip_accounts
+            .get_or_try_init(|| async {
+                let result = self.call("getTipAccounts", json!([])).await?;
+                let accounts = result
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(Value::as_str)
+                    .map(Pubkey::from_str)
+                    .collect::<Result<Vec<Pubkey>, _>>()?;
+                if accounts.is_empty() {
+                    return Err::<_, OracleError>("Block engine returned no tip accounts".into());
+                }
+                Ok(accounts)
+            })
+            .await?;
+        Ok(accounts[rand::random_range(0..accounts.len())])
+    }
+
+    /// Submit `transactions` as a bundle and return its id
+    pub async fn send_bundle(
+        &self,
+        transactions: &[VersionedTransaction],
+    ) -> Result<String, OracleError> {
+        let encoded = transactions
+            .iter()
+            .map(|transaction| {
+                Ok(base64::engine::general_purpose::STANDARD
+                    .encode(bincode::serialize(transaction)?))
+            })
+            .collect::<Result<Vec<String>, OracleError>>()?;
+        let result = self
+            .call("sendBundle", json!([encoded, { "encoding": "base64" }]))
+            .await?;
+        result
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("Unexpected sendBundle result: {}", result).into())
+    }
+}
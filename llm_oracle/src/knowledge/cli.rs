@@ -0,0 +1,108 @@
+//! `llm_oracle kb ...` commands managing the documents of a context.
+
+use super::{embeddings, KnowledgeBase, UpsertOutcome};
+use crate::OracleError;
+use clap::Subcommand;
+use solana_sdk::pubkey::Pubkey;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Subcommand)]
+pub enum KbCommand {
+    /// Index a new document
+    Add {
+        #[arg(long)]
+        context: Pubkey,
+        file: PathBuf,
+    },
+    /// Re-index an existing document
+    Update {
+        #[arg(long)]
+        context: Pubkey,
+        file: PathBuf,
+    },
+    /// Remove a document and its chunks
+    Remove {
+        #[arg(long)]
+        context: Pubkey,
+        file: PathBuf,
+    },
+    /// List the documents of a context
+    List {
+        #[arg(long)]
+        context: Pubkey,
+    },
+}
+
+/// Documents are named after their file
+fn document_name(file: &Path) -> Result<String, OracleError> {
+    file.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Invalid document path {:?}", file).into())
+}
+
+async fn upsert(
+    knowledge_base: &KnowledgeBase,
+    context: &Pubkey,
+    file: &Path,
+    update: bool,
+) -> Result<(), OracleError> {
+    let name = document_name(file)?;
+    let exists = knowledge_base.document(context, &name)?.is_some();
+    if !update && exists {
+        return Err(format!("{} already exists, use `kb update`", name).into());
+    }
+    if update && !exists {
+        return Err(format!("{} doesn't exist, use `kb add`", name).into());
+    }
+    let content = std::fs::read_to_string(file)?;
+    let source = file.to_string_lossy();
+    let embedder = embeddings::from_env()?;
+    match knowledge_base
+        .upsert(embedder.as_ref(), context, &name, &source, &content)
+        .await?
+    {
+        UpsertOutcome::Unchanged { version } => {
+            println!("{} is unchanged (version {})", name, version)
+        }
+        UpsertOutcome::Stored {
+            version,
+            chunks,
+            embedded,
+        } => println!(
+            "Indexed {} as version {} ({} chunks, {} embedded with {})",
+            name,
+            version,
+            chunks,
+            embedded,
+            embedder.name()
+        ),
+    }
+    Ok(())
+}
+
+/// Run a `kb` subcommand
+pub async fn run(command: KbCommand) -> Result<(), OracleError> {
+    let knowledge_base = KnowledgeBase::from_env()?;
+    match command {
+        KbCommand::Add { context, file } => upsert(&knowledge_base, &context, &file, false).await?,
+        KbCommand::Update { context, file } => {
+            upsert(&knowledge_base, &context, &file, true).await?
+        }
+        KbCommand::Remove { context, file } => {
+            let name = document_name(&file)?;
+            if !knowledge_base.remove(&context, &name).await? {
+                return Err(format!("{} doesn't exist", name).into());
+            }
+            println!("Removed {}", name);
+        }
+        KbCommand::List { context } => {
+            for (name, record) in knowledge_base.documents(&context)? {
+                println!(
+                    "{}\tversion {}\t{} chunks\t{}\tupdated {}",
+                    name, record.version, record.chunks, record.source, record.updated_at
+                );
+            }
+        }
+    }
+    Ok(())
+}
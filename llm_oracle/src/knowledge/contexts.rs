@@ -0,0 +1,239 @@
+//! Retrieval over every context account of the program.
+//!
+//! With `CONTEXT_RETRIEVAL` set, the text of every `ContextAccount` is indexed in the knowledge
+//! base under the id of the program owning it (each account a document named by its pubkey) and re-indexed every
+//! `CONTEXT_INDEX_INTERVAL_SECS`; unchanged accounts are not re-embedded. The prompt then carries
+//! the chunks closest to the interaction text instead of the whole context: a long context only
+//! contributes its relevant parts, and other contexts of the same program can contribute theirs. A context short
+//! enough to fit in one chunk is still sent in full.
+
+use super::{Embedder, KnowledgeBase, SearchHit, UpsertOutcome, DEFAULT_CHUNK_CHARS};
+use crate::config::env_flag;
+use crate::decode::decode_context;
+use crate::oracle::Oracle;
+use crate::OracleError;
+use anchor_lang::Discriminator;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+pub const DEFAULT_TOP_K: usize = 6;
+pub const DEFAULT_MIN_SCORE: f32 = 0.25;
+pub const DEFAULT_INDEX_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Knowledge base namespace of the index of `program`: the program id stands in for a context
+pub fn namespace(program: &Pubkey) -> Pubkey {
+    *program
+}
+
+/// Index of the programs' context accounts
+pub struct ContextIndex {
+    knowledge_base: Arc<KnowledgeBase>,
+    embedder: Box<dyn Embedder>,
+    top_k: usize,
+    min_score: f32,
+    interval: Duration,
+}
+
+impl ContextIndex {
+    pub fn new(
+        knowledge_base: Arc<KnowledgeBase>,
+        embedder: Box<dyn Embedder>,
+        top_k: usize,
+        min_score: f32,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            knowledge_base,
+            embedder,
+            top_k,
+            min_score,
+            interval,
+        }
+    }
+
+    /// Configure from `CONTEXT_RETRIEVAL_TOP_K`, `CONTEXT_RETRIEVAL_MIN_SCORE` and
+    /// `CONTEXT_INDEX_INTERVAL_SECS`, when `CONTEXT_RETRIEVAL` is set
+    pub fn from_env(
+        knowledge_base: Arc<KnowledgeBase>,
+        embedder: Box<dyn Embedder>,
+    ) -> Result<Option<Self>, OracleError> {
+        fn parse<T: std::str::FromStr>(var: &str, default: T) -> Result<T, OracleError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            match env::var(var) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| format!("Invalid {} {:?}: {}", var, value, e).into()),
+                Err(_) => Ok(default),
+            }
+        }
+
+        if !env_flag("CONTEXT_RETRIEVAL") {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(
+            knowledge_base,
+            embedder,
+            parse("CONTEXT_RETRIEVAL_TOP_K", DEFAULT_TOP_K)?,
+            parse("CONTEXT_RETRIEVAL_MIN_SCORE", DEFAULT_MIN_SCORE)?,
+            Duration::from_secs(parse(
+                "CONTEXT_INDEX_INTERVAL_SECS",
+                DEFAULT_INDEX_INTERVAL.as_secs(),
+            )?),
+        )))
+    }
+
+    /// Index every context account of `program` and drop the ones that no longer exist.
+    /// Returns the number of accounts indexed and of accounts re-embedded.
+    pub async fn refresh(
+        &self,
+        rpc_client: &RpcClient,
+        program: &Pubkey,
+    ) -> Result<(usize, usize), OracleError> {
+        let accounts = rpc_client
+            .get_program_accounts_with_config(
+                program,
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new(
+                        0,
+                        MemcmpEncodedBytes::Bytes(
+                            solana_gpt_oracle::ContextAccount::DISCRIMINATOR.to_vec(),
+                        ),
+                    ))]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let namespace = namespace(program);
+        let mut indexed = HashSet::new();
+        let mut embedded = 0;
+        for (pubkey, account) in accounts {
+            let Some(context) = decode_context(&account.data) else {
+                continue;
+            };
+            if context.text.trim().is_empty() {
+                continue;
+            }
+            let document = pubkey.to_string();
+            let outcome = self
+                .knowledge_base
+                .upsert(
+                    self.embedder.as_ref(),
+                    &namespace,
+                    &document,
+                    &format!("account:{}", pubkey),
+                    &context.text,
+                )
+                .await;
+            match outcome {
+                Ok(UpsertOutcome::Stored { .. }) => embedded += 1,
+                Ok(UpsertOutcome::Unchanged { .. }) => {}
+                Err(e) => {
+                    warn!(context = %pubkey, error = ?e, "Failed to index the context");
+                    continue;
+                }
+            }
+            indexed.insert(document);
+        }
+        for (document, _) in self.knowledge_base.documents(&namespace)? {
+            if !indexed.contains(&document) {
+                self.knowledge_base.remove(&namespace, &document).await?;
+            }
+        }
+        Ok((indexed.len(), embedded))
+    }
+
+    /// Drop the chunks of a context of `program`. Returns whether it was indexed.
+    pub async fn remove(&self, program: &Pubkey, context: &Pubkey) -> Result<bool, OracleError> {
+        self.knowledge_base
+            .remove(&namespace(program), &context.to_string())
+            .await
+    }
+
+    /// The indexed chunks closest to `text`, across every context of `program`
+    pub async fn retrieve(
+        &self,
+        program: &Pubkey,
+        text: &str,
+    ) -> Result<Vec<SearchHit>, OracleError> {
+        let query = self
+            .embedder
+            .embed(&[text.to_string()])
+            .await?
+            .pop()
+            .ok_or("Embedder returned no vector for the query")?;
+        Ok(self
+            .knowledge_base
+            .search(&namespace(program), &query, self.top_k)?
+            .into_iter()
+            .filter(|hit| hit.score >= self.min_score)
+            .collect())
+    }
+
+    /// The context part of the prompt of an interaction of `context`, a context account of
+    /// `program`: the chunks relevant to `text`, after the context's own text when it fits in
+    /// one chunk. Falls back to the whole context text when nothing relevant is indexed.
+    pub async fn assemble(
+        &self,
+        program: &Pubkey,
+        context: &Pubkey,
+        context_text: &str,
+        text: &str,
+    ) -> Result<String, OracleError> {
+        let short = context_text.chars().count() <= DEFAULT_CHUNK_CHARS;
+        let own = context.to_string();
+        let hits: Vec<SearchHit> = self
+            .retrieve(program, text)
+            .await?
+            .into_iter()
+            .filter(|hit| !(short && hit.document == own))
+            .collect();
+        debug!(chunks = hits.len(), "Retrieved context chunks");
+        if hits.is_empty() {
+            return Ok(context_text.to_string());
+        }
+        let mut parts = Vec::new();
+        if short {
+            parts.push(context_text.to_string());
+        }
+        parts.extend(
+            hits.iter()
+                .map(|hit| format!("[{}#{}] {}", hit.document, hit.index, hit.text)),
+        );
+        Ok(parts.join("\n\n"))
+    }
+}
+
+/// Keep the index up to date, every `CONTEXT_INDEX_INTERVAL_SECS`
+pub async fn run(oracle: Arc<Oracle>) {
+    let Some(index) = &oracle.context_index else {
+        return;
+    };
+    let mut interval = tokio::time::interval(index.interval);
+    loop {
+        interval.tick().await;
+        for program in &oracle.config.programs {
+            match index.refresh(&oracle.rpc_client, &program.id).await {
+                Ok((indexed, embedded)) => {
+                    info!(program = %program.id, indexed, embedded, "Indexed context accounts")
+                }
+                Err(e) => {
+                    warn!(program = %program.id, error = ?e, "Failed to index context accounts")
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use super::{Embedder, KnowledgeBase, UpsertOutcome};
+use crate::OracleError;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub const DEFAULT_RECRAWL_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Pages larger than this are not indexed
+const MAX_PAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Tags whose end starts a new paragraph in the extracted text
+const BLOCK_TAGS: [&str; 13] = [
+    "p", "div", "br", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "section", "article",
+];
+
+/// Reduce an HTML page to its visible text, one paragraph per block element
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_lowercase();
+        rest = &rest[start + end + 1..];
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect();
+        // Drop invisible content entirely
+        if !tag.starts_with('/') && matches!(name.as_str(), "script" | "style" | "noscript") {
+            let closing = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&closing) {
+                Some(close) => &rest[close..],
+                None => "",
+            };
+            continue;
+        }
+        if BLOCK_TAGS.contains(&name.as_str()) {
+            text.push_str("\n\n");
+        } else {
+            text.push(' ');
+        }
+    }
+    text.push_str(rest);
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split("\n\n")
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Keeps URL-backed context documents fresh: every `interval` each source is fetched, diffed
+/// against the stored version and re-indexed when it changed. Only hosts on the allowlist are
+/// ever fetched.
+pub struct Crawler {
+    sources: Vec<(Pubkey, String)>,
+    interval: Duration,
+    http: reqwest::Client,
+}
+
+impl Crawler {
+    pub fn new(
+        sources: Vec<(Pubkey, String)>,
+        allowed_hosts: &HashSet<String>,
+        interval: Duration,
+    ) -> Result<Self, OracleError> {
+        for (_, url) in &sources {
+            let host = reqwest::Url::parse(url)
+                .map_err(|e| format!("Invalid knowledge URL {:?}: {}", url, e))?
+                .host_str()
+                .map(str::to_lowercase)
+                .ok_or_else(|| format!("Knowledge URL {:?} has no host", url))?;
+            if !allowed_hosts.contains(&host) {
+                return Err(
+                    format!("Knowledge URL {:?} is not on KNOWLEDGE_URL_ALLOWLIST", url).into(),
+                );
+            }
+        }
+        Ok(Self {
+            sources,
+            interval,
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()?,
+        })
+    }
+
+    /// Configure from `KNOWLEDGE_URLS` (`<context pubkey>=<url>` pairs), `KNOWLEDGE_URL_ALLOWLIST`
+    /// (hosts) and `KNOWLEDGE_RECRAWL_SECS`. `None` when no URL is configured.
+    pub fn from_env() -> Result<Option<Self>, OracleError> {
+        let Ok(value) = env::var("KNOWLEDGE_URLS") else {
+            return Ok(None);
+        };
+        let sources = value
+            .split(',')
+            .map(str::trim)
+            .filter(|source| !source.is_empty())
+            .map(|source| -> Result<(Pubkey, String), OracleError> {
+                let (context, url) = source.split_once('=').ok_or_else(|| {
+                    format!(
+                        "Invalid knowledge URL {:?}, expected <context pubkey>=<url>",
+                        source
+                    )
+                })?;
+                let context = Pubkey::from_str(context.trim())
+                    .map_err(|e| format!("Invalid context pubkey {:?}: {}", context, e))?;
+                Ok((context, url.trim().to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let allowed_hosts = env::var("KNOWLEDGE_URL_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|host| host.trim().to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+        let interval = match env::var("KNOWLEDGE_RECRAWL_SECS") {
+            Ok(value) => Duration::from_secs(
+                value
+                    .parse()
+                    .map_err(|e| format!("Invalid KNOWLEDGE_RECRAWL_SECS {:?}: {}", value, e))?,
+            ),
+            Err(_) => DEFAULT_RECRAWL_INTERVAL,
+        };
+        Ok(Some(Self::new(sources, &allowed_hosts, interval)?))
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, OracleError> {
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        let is_html = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("html"));
+        let body = response.bytes().await?;
+        if body.len() > MAX_PAGE_BYTES {
+            return Err(format!("{} is larger than {} bytes", url, MAX_PAGE_BYTES).into());
+        }
+        let body = String::from_utf8_lossy(&body);
+        Ok(if is_html {
+            html_to_text(&body)
+        } else {
+            body.to_string()
+        })
+    }
+
+    /// Refresh every source once
+    pub async fn crawl(&self, knowledge_base: &KnowledgeBase, embedder: &dyn Embedder) {
+        for (context, url) in &self.sources {
+            let content = match self.fetch(url).await {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!(%url, error = ?e, "Failed to crawl");
+                    continue;
+                }
+            };
+            match knowledge_base
+                .upsert(embedder, context, url, url, &content)
+                .await
+            {
+                Ok(UpsertOutcome::Unchanged { .. }) => {}
+                Ok(UpsertOutcome::Stored {
+                    version,
+                    chunks,
+                    embedded,
+                }) => info!(
+                    %url,
+                    %context,
+                    version,
+                    changed_chunks = embedded,
+                    chunks,
+                    "Re-indexed knowledge source"
+                ),
+                Err(e) => warn!(%url, error = ?e, "Failed to index"),
+            }
+        }
+    }
+
+    /// Crawl now and then every `interval`, forever
+    pub async fn run(self, knowledge_base: Arc<KnowledgeBase>, embedder: Box<dyn Embedder>) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            self.crawl(&knowledge_base, embedder.as_ref()).await;
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::env;
+
+/// Texts sent per embedding request
+const MAX_BATCH: usize = 100;
+
+/// Turns text into vectors for the knowledge base
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Provider and model, e.g. `"openai/text-embedding-3-small"`
+    fn name(&self) -> &str;
+
+    /// One vector per text, in order
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, OracleError>;
+}
+
+fn vector(value: &Value) -> Option<Vec<f32>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect()
+}
+
+pub struct OpenAIEmbedder {
+    api_key: String,
+    model: String,
+    name: String,
+    client: reqwest::Client,
+}
+
+impl OpenAIEmbedder {
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
+            api_key,
+            name: format!("openai/{}", model),
+            model,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAIEmbedder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, OracleError> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(MAX_BATCH) {
+            let response = self
+                .client
+                .post("https://api.openai.com/v1/embeddings")
+                .bearer_auth(&self.api_key)
+                .json(&json!({ "model": self.model, "input": batch }))
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await?;
+                return Err(format!("OpenAI embeddings error ({}): {}", status, error_text).into());
+            }
+            let response: Value = response.json().await?;
+            let mut data: Vec<&Value> = response["data"]
+                .as_array()
+                .ok_or("OpenAI embeddings response has no data")?
+                .iter()
+                .collect();
+            data.sort_by_key(|item| item["index"].as_u64().unwrap_or_default());
+            for item in data {
+                vectors.push(vector(&item["embedding"]).ok_or("Invalid OpenAI embedding")?);
+            }
+        }
+        Ok(vectors)
+    }
+}
+
+pub struct GeminiEmbedder {
+    api_key: String,
+    model: String,
+    name: String,
+    client: reqwest::Client,
+}
+
+impl GeminiEmbedder {
+    pub fn new(api_key: String, model: String) -> Self {
+        Self {
+            api_key,
+            name: format!("gemini/{}", model),
+            model,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Embedder for GeminiEmbedder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, OracleError> {
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{}:batchEmbedContents",
+            self.model
+        );
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(MAX_BATCH) {
+            let requests: Vec<Value> = batch
+                .iter()
+                .map(|text| {
+                    json!({
+                        "model": format!("models/{}", self.model),
+                        "content": { "parts": [{ "text": text }] },
+                    })
+                })
+                .collect();
+            let response = self
+                .client
+                .post(&url)
+                .header("x-goog-api-key", &self.api_key)
+                .json(&json!({ "requests": requests }))
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await?;
+                return Err(format!("Gemini embeddings error ({}): {}", status, error_text).into());
+            }
+            let response: Value = response.json().await?;
+            for item in response["embeddings"]
+                .as_array()
+                .ok_or("Gemini embeddings response has no embeddings")?
+            {
+                vectors.push(vector(&item["values"]).ok_or("Invalid Gemini embedding")?);
+            }
+        }
+        Ok(vectors)
+    }
+}
+
+/// Pick the embedder from the API keys in the environment, like [`crate::providers::from_env`].
+/// `EMBEDDING_MODEL` overrides the provider's default model.
+pub fn from_env() -> Result<Box<dyn Embedder>, OracleError> {
+    let gemini_key = env::var("GEMINI_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty() && key != "your-gemini-api-key-here");
+    let openai_key = env::var("OPENAI_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty());
+    let model = env::var("EMBEDDING_MODEL").ok();
+
+    if let Some(gemini_key) = gemini_key {
+        let model = model.unwrap_or("text-embedding-004".to_string());
+        Ok(Box::new(GeminiEmbedder::new(gemini_key, model)))
+    } else if let Some(openai_key) = openai_key {
+        let model = model.unwrap_or("text-embedding-3-small".to_string());
+        Ok(Box::new(OpenAIEmbedder::new(openai_key, model)))
+    } else {
+        Err(
+            "No valid API key found for embeddings. Please set GEMINI_API_KEY or OPENAI_API_KEY"
+                .into(),
+        )
+    }
+}
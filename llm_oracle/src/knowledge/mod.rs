@@ -0,0 +1,445 @@
+//! Knowledge base of per-context documents for retrieval augmented answers.
+//!
+//! Documents are split into overlapping chunks, embedded with an [`Embedder`] and stored in an
+//! embedded sled database. Every update of a document gets a new version; the new chunks are
+//! written and the old ones retired in a single transaction, so a search never sees a mix of
+//! both.
+
+use crate::config::deployment_path;
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+};
+use sled::Transactional;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod cli;
+pub mod contexts;
+pub mod crawler;
+pub mod embeddings;
+
+pub use crate::tools::Citation;
+pub use contexts::ContextIndex;
+pub use crawler::Crawler;
+pub use embeddings::{Embedder, GeminiEmbedder, OpenAIEmbedder};
+
+pub const DEFAULT_CHUNK_CHARS: usize = 1000;
+pub const DEFAULT_CHUNK_OVERLAP: usize = 100;
+
+/// The current version of a document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentRecord {
+    pub version: u64,
+    pub chunks: usize,
+    /// File or URL the document was read from
+    pub source: String,
+    /// Hex sha256 of the content, to skip re-embedding unchanged documents
+    pub content_hash: String,
+    /// Embedder that produced the vectors; they are only comparable within one model
+    pub embedder: String,
+    /// Unix timestamp of the last content change
+    pub updated_at: u64,
+    /// Unix timestamp the source was last read at, whether or not it changed
+    #[serde(default)]
+    pub checked_at: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChunk {
+    text: String,
+    embedding: Vec<f32>,
+}
+
+/// A chunk matching a search
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub document: String,
+    pub version: u64,
+    pub index: usize,
+    pub source: String,
+    pub text: String,
+    pub score: f32,
+    pub updated_at: u64,
+    pub checked_at: u64,
+}
+
+impl SearchHit {
+    pub fn citation(&self) -> Citation {
+        Citation {
+            document: self.document.clone(),
+            version: self.version,
+            chunk: self.index,
+            source: self.source.clone(),
+            score: self.score,
+            checked_at: self.checked_at,
+        }
+    }
+}
+
+/// `[sources: a#1, b#0]`, dropping the lowest ranked citations (and cutting the last one) to
+/// stay within `max_chars`
+pub fn compact_citations(citations: &[Citation], max_chars: usize) -> Option<String> {
+    const PREFIX: &str = "[sources: ";
+    let budget = max_chars.checked_sub(PREFIX.len() + 1)?;
+    let mut ids: Vec<String> = Vec::new();
+    for citation in citations {
+        let id = citation.compact();
+        if ids.contains(&id) {
+            continue;
+        }
+        let used: usize = ids.iter().map(|id| id.chars().count() + 2).sum();
+        let remaining = budget.saturating_sub(used);
+        if id.chars().count() <= remaining {
+            ids.push(id);
+        } else {
+            if ids.is_empty() && remaining > 1 {
+                let trimmed: String = id.chars().take(remaining - 1).collect();
+                ids.push(format!("{}…", trimmed));
+            }
+            break;
+        }
+    }
+    if ids.is_empty() {
+        return None;
+    }
+    Some(format!("{}{}]", PREFIX, ids.join(", ")))
+}
+
+/// Result of [`KnowledgeBase::upsert`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    /// The content hash matches the stored version, nothing was re-embedded
+    Unchanged { version: u64 },
+    /// `embedded` of the `chunks` were new; the others reused their previous vectors
+    Stored {
+        version: u64,
+        chunks: usize,
+        embedded: usize,
+    },
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn document_key(context: &Pubkey, document: &str) -> String {
+    format!("{}/{}", context, document)
+}
+
+fn chunk_key(context: &Pubkey, document: &str, version: u64, index: usize) -> String {
+    format!("{}/{}/{:020}/{:06}", context, document, version, index)
+}
+
+/// Freshness of a source for citations, e.g. `as of 2024-06-01`
+pub fn as_of(timestamp: u64) -> String {
+    match chrono::DateTime::from_timestamp(timestamp as i64, 0) {
+        Some(time) => format!("as of {}", time.format("%Y-%m-%d")),
+        None => "as of an unknown date".to_string(),
+    }
+}
+
+fn transaction_error(e: TransactionError<String>) -> OracleError {
+    match e {
+        TransactionError::Abort(reason) => reason.into(),
+        TransactionError::Storage(e) => e.into(),
+    }
+}
+
+/// Split text into chunks of at most `max_chars` characters, packing whole paragraphs where
+/// possible. Consecutive chunks share `overlap` characters so sentences cut at a boundary stay
+/// retrievable.
+pub fn chunk_text(text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let overlap = overlap.min(max_chars / 2);
+    let mut pieces: Vec<String> = Vec::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        let chars: Vec<char> = paragraph.chars().collect();
+        for piece in chars.chunks(max_chars) {
+            pieces.push(piece.iter().collect());
+        }
+    }
+
+    let mut chunks: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for piece in pieces {
+        if !current.is_empty() && current.chars().count() + piece.chars().count() + 2 > max_chars {
+            let tail: String = {
+                let chars: Vec<char> = current.chars().collect();
+                chars[chars.len().saturating_sub(overlap)..]
+                    .iter()
+                    .collect()
+            };
+            chunks.push(std::mem::take(&mut current));
+            if tail.chars().count() + piece.chars().count() + 2 <= max_chars {
+                current = tail;
+            }
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&piece);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+/// Versioned, embedded vector store of context documents
+pub struct KnowledgeBase {
+    db: sled::Db,
+    documents: sled::Tree,
+    chunks: sled::Tree,
+}
+
+impl KnowledgeBase {
+    pub fn open(path: &str) -> Result<Self, OracleError> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            documents: db.open_tree("documents")?,
+            chunks: db.open_tree("chunks")?,
+            db,
+        })
+    }
+
+    /// Open the knowledge base at `KNOWLEDGE_PATH` (default `./oracle-knowledge`), under the
+    /// deployment name when set
+    pub fn from_env() -> Result<Self, OracleError> {
+        let path = env::var("KNOWLEDGE_PATH").unwrap_or("./oracle-knowledge".to_string());
+        Self::open(&deployment_path(&path))
+    }
+
+    pub fn document(
+        &self,
+        context: &Pubkey,
+        document: &str,
+    ) -> Result<Option<DocumentRecord>, OracleError> {
+        match self.documents.get(document_key(context, document))? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Documents of a context, by name
+    pub fn documents(
+        &self,
+        context: &Pubkey,
+    ) -> Result<Vec<(String, DocumentRecord)>, OracleError> {
+        let prefix = format!("{}/", context);
+        self.documents
+            .scan_prefix(&prefix)
+            .map(|entry| {
+                let (key, bytes) = entry?;
+                let name = String::from_utf8_lossy(&key[prefix.len()..]).to_string();
+                Ok((name, serde_json::from_slice(&bytes)?))
+            })
+            .collect()
+    }
+
+    /// Chunk, embed and store a document as its next version, retiring the previous one
+    pub async fn upsert(
+        &self,
+        embedder: &dyn Embedder,
+        context: &Pubkey,
+        document: &str,
+        source: &str,
+        content: &str,
+    ) -> Result<UpsertOutcome, OracleError> {
+        let content_hash = hex::encode(Sha256::digest(content.as_bytes()));
+        let previous = self.document(context, document)?;
+        if let Some(previous) = &previous {
+            if previous.content_hash == content_hash && previous.embedder == embedder.name() {
+                let record = DocumentRecord {
+                    checked_at: now(),
+                    ..previous.clone()
+                };
+                self.documents.insert(
+                    document_key(context, document),
+                    serde_json::to_vec(&record)?,
+                )?;
+                return Ok(UpsertOutcome::Unchanged {
+                    version: previous.version,
+                });
+            }
+        }
+
+        let texts = chunk_text(content, DEFAULT_CHUNK_CHARS, DEFAULT_CHUNK_OVERLAP);
+        if texts.is_empty() {
+            return Err(format!("Document {:?} has no content", document).into());
+        }
+
+        // Only embed the chunks whose text changed since the previous version
+        let mut reusable: HashMap<String, Vec<f32>> = HashMap::new();
+        if let Some(previous) = previous
+            .as_ref()
+            .filter(|previous| previous.embedder == embedder.name())
+        {
+            for index in 0..previous.chunks {
+                let key = chunk_key(context, document, previous.version, index);
+                if let Some(bytes) = self.chunks.get(key)? {
+                    let chunk: StoredChunk = serde_json::from_slice(&bytes)?;
+                    reusable.insert(chunk.text, chunk.embedding);
+                }
+            }
+        }
+        let changed: Vec<String> = texts
+            .iter()
+            .filter(|text| !reusable.contains_key(*text))
+            .cloned()
+            .collect();
+        let fresh = if changed.is_empty() {
+            Vec::new()
+        } else {
+            embedder.embed(&changed).await?
+        };
+        if fresh.len() != changed.len() {
+            return Err(format!(
+                "Embedder returned {} vectors for {} chunks",
+                fresh.len(),
+                changed.len()
+            )
+            .into());
+        }
+        let embedded = changed.len();
+        reusable.extend(changed.into_iter().zip(fresh));
+        let embeddings: Vec<Vec<f32>> = texts.iter().map(|text| reusable[text].clone()).collect();
+
+        let version = previous.as_ref().map_or(1, |previous| previous.version + 1);
+        let record = DocumentRecord {
+            version,
+            chunks: texts.len(),
+            source: source.to_string(),
+            content_hash,
+            embedder: embedder.name().to_string(),
+            updated_at: now(),
+            checked_at: now(),
+        };
+        let record_bytes = serde_json::to_vec(&record)?;
+        let chunk_bytes = texts
+            .into_iter()
+            .zip(embeddings)
+            .map(|(text, embedding)| serde_json::to_vec(&StoredChunk { text, embedding }))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        (&self.documents, &self.chunks)
+            .transaction(
+                |(documents, chunks)| -> ConflictableTransactionResult<(), String> {
+                    let current = documents.get(document_key(context, document))?;
+                    let current_version = match &current {
+                        Some(bytes) => {
+                            serde_json::from_slice::<DocumentRecord>(bytes)
+                                .map_err(|e| ConflictableTransactionError::Abort(e.to_string()))?
+                                .version
+                        }
+                        None => 0,
+                    };
+                    if current_version + 1 != version {
+                        return Err(ConflictableTransactionError::Abort(format!(
+                            "Document {:?} was updated concurrently",
+                            document
+                        )));
+                    }
+                    for (index, bytes) in chunk_bytes.iter().enumerate() {
+                        chunks.insert(
+                            chunk_key(context, document, version, index).as_bytes(),
+                            bytes.as_slice(),
+                        )?;
+                    }
+                    documents.insert(
+                        document_key(context, document).as_bytes(),
+                        record_bytes.as_slice(),
+                    )?;
+                    if let Some(previous) = &previous {
+                        for index in 0..previous.chunks {
+                            chunks.remove(
+                                chunk_key(context, document, previous.version, index).as_bytes(),
+                            )?;
+                        }
+                    }
+                    Ok(())
+                },
+            )
+            .map_err(transaction_error)?;
+        self.db.flush_async().await?;
+        Ok(UpsertOutcome::Stored {
+            version,
+            chunks: record.chunks,
+            embedded,
+        })
+    }
+
+    /// Remove a document and all of its chunks. Returns whether it existed.
+    pub async fn remove(&self, context: &Pubkey, document: &str) -> Result<bool, OracleError> {
+        let Some(record) = self.document(context, document)? else {
+            return Ok(false);
+        };
+        (&self.documents, &self.chunks)
+            .transaction(
+                |(documents, chunks)| -> ConflictableTransactionResult<(), String> {
+                    documents.remove(document_key(context, document).as_bytes())?;
+                    for index in 0..record.chunks {
+                        chunks.remove(
+                            chunk_key(context, document, record.version, index).as_bytes(),
+                        )?;
+                    }
+                    Ok(())
+                },
+            )
+            .map_err(transaction_error)?;
+        self.db.flush_async().await?;
+        Ok(true)
+    }
+
+    /// The `limit` chunks of a context closest to `query`
+    pub fn search(
+        &self,
+        context: &Pubkey,
+        query: &[f32],
+        limit: usize,
+    ) -> Result<Vec<SearchHit>, OracleError> {
+        let mut hits = Vec::new();
+        for (document, record) in self.documents(context)? {
+            for index in 0..record.chunks {
+                let key = chunk_key(context, &document, record.version, index);
+                let Some(bytes) = self.chunks.get(key)? else {
+                    continue;
+                };
+                let chunk: StoredChunk = serde_json::from_slice(&bytes)?;
+                hits.push(SearchHit {
+                    document: document.clone(),
+                    version: record.version,
+                    index,
+                    source: record.source.clone(),
+                    score: cosine_similarity(query, &chunk.embedding),
+                    text: chunk.text,
+                    updated_at: record.updated_at,
+                    checked_at: record.checked_at,
+                });
+            }
+        }
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
@@ -0,0 +1,114 @@
+//! Off-chain LLM oracle for the `solana-gpt-oracle` program.
+//!
+//! The binary in `main.rs` is a thin wrapper around this crate: configuration is loaded by
+//! [`config`], interactions are discovered by [`listener`], answered by [`processor`] and
+//! written back on-chain by [`callback`]. Everything is public so the oracle can be embedded in
+//! other services or extended with a custom [`providers::ChatProvider`].
+//!
+//! Optional subsystems are cargo features, all on by default, so a slim oracle can be built with
+//! only those it uses: the `gemini`, `openai` and `local-llm` providers, the `dashboard` of
+//! `llm_oracle top`, the `rag` knowledge base and context retrieval, Prometheus `metrics`, the
+//! `geyser` listener, the `graphql` API and `email` notifications.
+
+pub mod ack;
+pub mod admin;
+pub mod admin_api;
+pub mod archive;
+pub mod attestation;
+pub mod audit;
+pub mod batching;
+pub mod blinks;
+pub mod callback;
+pub mod compression;
+pub mod config;
+pub mod confirmation;
+pub mod context_import;
+pub mod context_settings;
+pub mod context_watch;
+pub mod costs;
+pub mod decode;
+pub mod dedup;
+pub mod digest;
+pub mod dlq;
+pub mod encryption;
+pub mod eta;
+pub mod fee_payers;
+pub mod fees;
+pub mod flood;
+pub mod functions;
+pub mod game;
+#[cfg(feature = "geyser")]
+pub mod geyser;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod guardrails;
+pub mod health;
+pub mod identity;
+pub mod images;
+pub mod incidents;
+pub mod ingest;
+pub mod jito;
+#[cfg(feature = "rag")]
+pub mod knowledge;
+pub mod limits;
+pub mod listener;
+pub mod logging;
+pub mod lookup_tables;
+pub mod memory;
+pub mod metrics;
+pub mod monitor;
+pub mod multiplex;
+pub mod nonce;
+pub mod notify;
+pub mod oracle;
+pub mod processor;
+pub mod prompts;
+pub mod providers;
+pub mod push;
+pub mod ratings;
+pub mod reconcile;
+pub mod recovery;
+pub mod refunds;
+pub mod refusals;
+pub mod reload;
+pub mod response_cache;
+pub mod response_length;
+pub mod retention;
+pub mod review;
+pub mod rpc_check;
+pub mod shutdown;
+pub mod status;
+pub mod streaming;
+pub mod structured;
+pub mod tools;
+pub mod trace;
+pub mod tuning;
+pub mod tx_audit;
+pub mod verification;
+pub mod webhooks;
+pub mod worker_pool;
+
+/// Error type used across the oracle. `Send + Sync` so results can cross task boundaries.
+pub type OracleError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Optional features this build was compiled with, printed by `llm_oracle check-config`
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "gemini")]
+    "gemini",
+    #[cfg(feature = "openai")]
+    "openai",
+    #[cfg(feature = "local-llm")]
+    "local-llm",
+    #[cfg(feature = "dashboard")]
+    "dashboard",
+    #[cfg(feature = "rag")]
+    "rag",
+    #[cfg(feature = "metrics")]
+    "metrics",
+    #[cfg(feature = "geyser")]
+    "geyser",
+    #[cfg(feature = "graphql")]
+    "graphql",
+    #[cfg(feature = "email")]
+    "email",
+];
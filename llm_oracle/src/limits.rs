@@ -0,0 +1,187 @@
+//! Rate limits.
+//!
+//! Interactions are counted per context account and per interaction creator over a sliding
+//! minute, against `limits.per_context_per_minute` and `limits.per_user_per_minute`. Daily
+//! token and spend budgets per provider are enforced by [`crate::providers::SpendBudget`].
+//!
+//! What happens to an interaction over a limit depends on `limits.action`:
+//!
+//! - `defer` (the default): it waits until the limit lets it through. Rate limited
+//!   interactions wait in the worker pool before taking a worker, so a spammy context doesn't
+//!   hold up the others. Interactions over a budget give their worker back and are queued again
+//!   at midnight UTC, left detected in the ledger meanwhile.
+//! - `respond`: it is answered with `limits.response` without calling the LLM.
+//!
+//! Either way, an interaction over a limit is never processed silently: it is logged and
+//! counted in `interactions_limited_total`.
+
+use crate::metrics::METRICS;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+pub const DEFAULT_LIMIT_RESPONSE: &str = "Rate limited, please try again later.";
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Keys tracked before the ones idle for a whole window are dropped
+const MAX_TRACKED: usize = 10_000;
+
+/// What to do with an interaction over a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitAction {
+    /// Wait until the limit lets it through
+    Defer,
+    /// Answer with `limits.response`
+    Respond,
+}
+
+impl FromStr for LimitAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "defer" => Ok(Self::Defer),
+            "respond" => Ok(Self::Respond),
+            other => Err(format!("expected defer or respond, got {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for LimitAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Defer => write!(f, "defer"),
+            Self::Respond => write!(f, "respond"),
+        }
+    }
+}
+
+/// Interactions admitted per key over the last minute
+#[derive(Default)]
+struct Window {
+    admitted: HashMap<Pubkey, VecDeque<Instant>>,
+}
+
+impl Window {
+    /// How long until `key` may be admitted again, `None` when it may be now
+    fn wait(&mut self, key: &Pubkey, limit: u32, now: Instant) -> Option<Duration> {
+        let admitted = self.admitted.entry(*key).or_default();
+        while admitted
+            .front()
+            .is_some_and(|admitted| now.duration_since(*admitted) >= WINDOW)
+        {
+            admitted.pop_front();
+        }
+        if admitted.len() < limit as usize {
+            return None;
+        }
+        admitted
+            .front()
+            .map(|oldest| WINDOW.saturating_sub(now.duration_since(*oldest)))
+    }
+
+    fn admit(&mut self, key: &Pubkey, now: Instant) {
+        self.admitted.entry(*key).or_default().push_back(now);
+        if self.admitted.len() > MAX_TRACKED {
+            self.admitted.retain(|_, admitted| {
+                admitted
+                    .back()
+                    .is_some_and(|last| now.duration_since(*last) < WINDOW)
+            });
+        }
+    }
+}
+
+#[derive(Default)]
+struct Windows {
+    contexts: Window,
+    users: Window,
+}
+
+/// Sliding window limits per context account and per interaction creator
+pub struct RateLimiter {
+    per_context_per_minute: Option<u32>,
+    per_user_per_minute: Option<u32>,
+    action: LimitAction,
+    windows: Mutex<Windows>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        per_context_per_minute: Option<u32>,
+        per_user_per_minute: Option<u32>,
+        action: LimitAction,
+    ) -> Self {
+        Self {
+            per_context_per_minute,
+            per_user_per_minute,
+            action,
+            windows: Mutex::new(Windows::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_context_per_minute.is_some() || self.per_user_per_minute.is_some()
+    }
+
+    /// Count an interaction of `user` in `context` if both are under their limits. Otherwise
+    /// returns which limit is reached and how long until it lets the interaction through.
+    fn try_admit(&self, context: &Pubkey, user: &Pubkey) -> Result<(), (&'static str, Duration)> {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if let Some(limit) = self.per_context_per_minute {
+            if let Some(wait) = windows.contexts.wait(context, limit, now) {
+                return Err(("context", wait));
+            }
+        }
+        if let Some(limit) = self.per_user_per_minute {
+            if let Some(wait) = windows.users.wait(user, limit, now) {
+                return Err(("user", wait));
+            }
+        }
+        if self.per_context_per_minute.is_some() {
+            windows.contexts.admit(context, now);
+        }
+        if self.per_user_per_minute.is_some() {
+            windows.users.admit(user, now);
+        }
+        Ok(())
+    }
+
+    /// Admit an interaction of `user` in `context`. With `limits.action = "defer"`, waits until
+    /// it is under the limits; returns whether it should be answered with `limits.response`
+    /// instead.
+    pub async fn admit(&self, context: &Pubkey, user: &Pubkey) -> bool {
+        let mut deferred = false;
+        loop {
+            let Err((limit, wait)) = self.try_admit(context, user) else {
+                return false;
+            };
+            match self.action {
+                LimitAction::Respond => {
+                    info!(%context, %user, limit, "Rate limited, answering with the limit response");
+                    METRICS
+                        .interactions_limited
+                        .with_label_values(&[limit, "respond"])
+                        .inc();
+                    return true;
+                }
+                LimitAction::Defer => {
+                    if !deferred {
+                        info!(%context, %user, limit, ?wait, "Rate limited, deferring");
+                        METRICS
+                            .interactions_limited
+                            .with_label_values(&[limit, "defer"])
+                            .inc();
+                        deferred = true;
+                    }
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
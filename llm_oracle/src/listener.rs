@@ -0,0 +1,495 @@
+use crate::config::ListenerBackend;
+use crate::decode::InteractionView;
+#[cfg(feature = "geyser")]
+use crate::geyser;
+use crate::health::HEALTH;
+use crate::incidents::{self, SUBSCRIPTION_DEAD};
+use crate::metrics::METRICS;
+use crate::multiplex::Multiplexer;
+use crate::notify::{Event, Severity};
+use crate::oracle::Oracle;
+use crate::worker_pool::WorkerPool;
+use crate::OracleError;
+use anchor_lang::Discriminator;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+/// Delay before the first reconnection attempt, doubled on every consecutive failure
+pub const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+pub const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Most accounts of a `getMultipleAccounts` call, the default `processing.backlog_page_size`
+pub const MAX_BACKLOG_PAGE_SIZE: usize = 100;
+/// Calls made for a page of the backlog before giving up on it
+pub const PAGE_ATTEMPTS: u32 = 3;
+/// Pages of the backlog between two progress reports
+const PROGRESS_PAGES: usize = 10;
+
+/// Filters matching every `Interaction` account of an oracle program
+pub fn interaction_filters() -> Vec<RpcFilterType> {
+    vec![RpcFilterType::Memcmp(Memcmp::new(
+        0,
+        MemcmpEncodedBytes::Bytes(solana_gpt_oracle::Interaction::DISCRIMINATOR.to_vec()),
+    ))]
+}
+
+/// Fetch the interactions of `program` that haven't been answered yet
+pub async fn pending_interactions(
+    rpc_client: &RpcClient,
+    program: &Pubkey,
+) -> Result<Vec<(Pubkey, solana_gpt_oracle::Interaction)>, OracleError> {
+    let keys = program_account_keys(rpc_client, program, interaction_filters(), None).await?;
+    let mut pending = Vec::new();
+    for page in keys.chunks(MAX_BACKLOG_PAGE_SIZE) {
+        let accounts = fetch_page(rpc_client, page, None).await?;
+        pending.extend(page.iter().zip(accounts).filter_map(|(pubkey, account)| {
+            InteractionView::parse(&account?.data)
+                .filter(|view| !view.is_processed)
+                .and_then(|view| view.decode().ok())
+                .map(|interaction| (*pubkey, interaction))
+        }));
+    }
+    Ok(pending)
+}
+
+/// Addresses of the accounts of `program` matching `filters`, fetched without their data
+async fn program_account_keys(
+    rpc_client: &RpcClient,
+    program: &Pubkey,
+    filters: Vec<RpcFilterType>,
+    min_context_slot: Option<u64>,
+) -> Result<Vec<Pubkey>, OracleError> {
+    let mut config = program_accounts_config(filters, min_context_slot);
+    config.account_config.data_slice = Some(UiDataSliceConfig {
+        offset: 0,
+        length: 0,
+    });
+    let accounts = rpc_client
+        .get_program_accounts_with_config(program, config)
+        .await?;
+    Ok(accounts.into_iter().map(|(pubkey, _)| pubkey).collect())
+}
+
+/// Fetch a page of at most [`MAX_BACKLOG_PAGE_SIZE`] accounts, trying failed calls
+/// [`PAGE_ATTEMPTS`] times. Accounts closed since they were listed are `None`.
+async fn fetch_page(
+    rpc_client: &RpcClient,
+    keys: &[Pubkey],
+    min_context_slot: Option<u64>,
+) -> Result<Vec<Option<Account>>, OracleError> {
+    let config = program_accounts_config(Vec::new(), min_context_slot).account_config;
+    let mut attempt = 0;
+    loop {
+        match rpc_client
+            .get_multiple_accounts_with_config(keys, config.clone())
+            .await
+        {
+            Ok(response) => return Ok(response.value),
+            Err(e) if attempt + 1 < PAGE_ATTEMPTS => {
+                debug!(error = ?e, attempt, "Failed to fetch a page of accounts, retrying");
+                tokio::time::sleep(backoff_delay(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+pub fn program_accounts_config(
+    filters: Vec<RpcFilterType>,
+    min_context_slot: Option<u64>,
+) -> RpcProgramAccountsConfig {
+    RpcProgramAccountsConfig {
+        account_config: RpcAccountInfoConfig {
+            commitment: Some(CommitmentConfig::processed()),
+            encoding: Some(UiAccountEncoding::Base64),
+            min_context_slot,
+            ..Default::default()
+        },
+        filters: Some(filters),
+        ..Default::default()
+    }
+}
+
+/// State of the program subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ListenerState {
+    /// Waiting before the next connection attempt, after `failures` consecutive failures
+    Backoff { failures: u32 },
+    /// Connecting, subscribing and gap-filling; `failures` carries over if this fails too
+    Connecting { failures: u32 },
+}
+
+fn backoff_delay(failures: u32) -> Duration {
+    RECONNECT_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(failures))
+        .min(RECONNECT_MAX_DELAY)
+}
+
+/// An account update: the account, the slot it was written at and its data
+pub type AccountUpdate = (Pubkey, u64, Vec<u8>);
+
+/// Where program updates come from
+#[derive(Debug, Clone, Copy)]
+enum Source<'a> {
+    /// `program_subscribe` on a websocket endpoint
+    Websocket(&'a str),
+    /// A Yellowstone gRPC endpoint, see [`crate::geyser`]
+    Geyser(&'a str),
+}
+
+impl Source<'_> {
+    fn url(&self) -> &str {
+        match self {
+            Source::Websocket(url) | Source::Geyser(url) => url,
+        }
+    }
+}
+
+/// Subscribe to every configured program and dispatch interactions as they arrive. Each program
+/// has its own subscriptions, gap-fills and incidents; interactions of all of them share the
+/// worker pool. The subscription is
+/// re-established with exponential backoff whenever it drops or fails, and every (re)subscription
+/// is followed by a gap-fill pass so interactions created while disconnected are not missed.
+/// Updates come from `solana.websocket_url` or, with `solana.listener = "geyser"`, from
+/// `solana.geyser_url`. With a secondary websocket endpoint, both are subscribed to, see
+/// [`crate::multiplex`].
+pub async fn run_oracle(oracle: &Oracle, worker_pool: &WorkerPool) -> Result<(), OracleError> {
+    let programs = oracle
+        .config
+        .programs
+        .iter()
+        .map(|program| run_program(oracle, worker_pool, &program.id));
+    futures::future::try_join_all(programs).await?;
+    Ok(())
+}
+
+/// The subscriptions of one program
+async fn run_program(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    program: &Pubkey,
+) -> Result<(), OracleError> {
+    let primary = match &oracle.config.listener {
+        ListenerBackend::Websocket => Source::Websocket(&oracle.config.websocket_url),
+        ListenerBackend::Geyser { url } => Source::Geyser(url),
+    };
+    let Some(secondary) = &oracle.config.secondary_websocket_url else {
+        return run_source(oracle, worker_pool, program, primary, None).await;
+    };
+    let secondary = Source::Websocket(secondary);
+    let multiplexer = Multiplexer::new(
+        *program,
+        primary.url().to_string(),
+        secondary.url().to_string(),
+        Duration::from_secs(oracle.config.incidents.subscription_lag_secs),
+    );
+    let (primary, secondary, ()) = futures::join!(
+        run_source(
+            oracle,
+            worker_pool,
+            program,
+            primary,
+            Some((&multiplexer, 0))
+        ),
+        run_source(
+            oracle,
+            worker_pool,
+            program,
+            secondary,
+            Some((&multiplexer, 1))
+        ),
+        multiplexer.watch(),
+    );
+    primary.and(secondary)
+}
+
+/// The subscription loop of one endpoint, which is `source` of the multiplexer when there are
+/// two
+async fn run_source(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    program: &Pubkey,
+    source: Source<'_>,
+    multiplexer: Option<(&Multiplexer, usize)>,
+) -> Result<(), OracleError> {
+    let url = source.url();
+    let filters = interaction_filters();
+    let mut last_seen_slot = None;
+    // Set while the subscription is down
+    let mut down_since = None;
+    let dead_after = Duration::from_secs(oracle.config.incidents.subscription_dead_secs);
+    let key = match multiplexer {
+        Some(_) => format!("{} via {}", program, url),
+        None => program.to_string(),
+    };
+    let mut state = ListenerState::Connecting { failures: 0 };
+    loop {
+        state = match state {
+            ListenerState::Backoff { failures } => {
+                if let Some((multiplexer, source)) = multiplexer {
+                    multiplexer.set_up(source, false);
+                }
+                HEALTH.subscription(program, url, false);
+                let down_for = down_since.get_or_insert_with(Instant::now).elapsed();
+                if down_for >= dead_after {
+                    // Only critical when no other subscription delivers interactions
+                    let other_up = multiplexer
+                        .is_some_and(|(multiplexer, source)| multiplexer.is_up(1 - source));
+                    let (severity, impact) = if other_up {
+                        (
+                            Severity::Warning,
+                            "Interactions are still received from the other provider.",
+                        )
+                    } else {
+                        (Severity::Critical, "Interactions are not being answered.")
+                    };
+                    incidents::trigger(
+                        Event::new(
+                            severity,
+                            SUBSCRIPTION_DEAD,
+                            "Program subscription is down",
+                            format!(
+                                "No subscription to {} at {} for {}s, {} failed attempt(s). {}",
+                                program,
+                                url,
+                                down_for.as_secs(),
+                                failures,
+                                impact
+                            ),
+                        )
+                        .with_key(&key),
+                    );
+                }
+                let delay = backoff_delay(failures);
+                warn!(
+                    %program,
+                    url = %url,
+                    ?delay,
+                    attempt = failures + 1,
+                    "Reconnecting to the program subscription"
+                );
+                tokio::time::sleep(delay).await;
+                METRICS.websocket_reconnects.inc();
+                ListenerState::Connecting { failures }
+            }
+            ListenerState::Connecting { failures } => {
+                let listened = listen(
+                    oracle,
+                    worker_pool,
+                    program,
+                    source,
+                    multiplexer,
+                    &filters,
+                    &mut last_seen_slot,
+                    &mut down_since,
+                    &key,
+                )
+                .await;
+                match listened {
+                    // The stream ended after a successful subscription: start over
+                    Ok(()) => {
+                        warn!(%program, "Program subscription closed");
+                        ListenerState::Backoff { failures: 0 }
+                    }
+                    Err(e) => {
+                        error!(%program, error = ?e, "Program subscription failed");
+                        ListenerState::Backoff {
+                            failures: failures.saturating_add(1),
+                        }
+                    }
+                }
+            }
+        };
+    }
+}
+
+/// Subscribe, gap-fill from `last_seen_slot` and dispatch updates until the stream ends.
+/// `down_since` is cleared once subscribed, resolving the incident of `key`.
+#[allow(clippy::too_many_arguments)]
+async fn listen(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    program: &Pubkey,
+    source: Source<'_>,
+    multiplexer: Option<(&Multiplexer, usize)>,
+    filters: &[RpcFilterType],
+    last_seen_slot: &mut Option<u64>,
+    down_since: &mut Option<Instant>,
+    key: &str,
+) -> Result<(), OracleError> {
+    let url = source.url();
+    let pubsub_client;
+    let mut unsubscribe = None;
+    let mut stream: BoxStream<'_, Result<AccountUpdate, OracleError>> = match source {
+        Source::Websocket(url) => {
+            pubsub_client = PubsubClient::new(url).await?;
+            let (stream, unsubscribe_fn) = pubsub_client
+                .program_subscribe(
+                    program,
+                    Some(program_accounts_config(filters.to_vec(), None)),
+                )
+                .await?;
+            unsubscribe = Some(unsubscribe_fn);
+            stream
+                .filter_map(|update| async move {
+                    let pubkey = Pubkey::from_str(&update.value.pubkey).ok()?;
+                    let data = update.value.account.data.decode()?;
+                    Some(Ok::<_, OracleError>((pubkey, update.context.slot, data)))
+                })
+                .boxed()
+        }
+        #[cfg(feature = "geyser")]
+        Source::Geyser(url) => geyser::subscribe(url, program).await?,
+        #[cfg(not(feature = "geyser"))]
+        Source::Geyser(_) => {
+            return Err("The geyser listener needs the oracle built with the geyser feature".into())
+        }
+    };
+
+    // Subscribed first so nothing falls between the gap-fill and the first update
+    let slot = fetch_and_process_program_accounts(
+        oracle,
+        program,
+        filters.to_vec(),
+        worker_pool,
+        *last_seen_slot,
+    )
+    .await?;
+    match last_seen_slot {
+        Some(last_seen_slot) => {
+            info!(%program, %url, from = last_seen_slot, to = slot, "Subscribed, gap-filled")
+        }
+        None => info!(%program, %url, slot, "Subscribed"),
+    }
+    *last_seen_slot = Some(last_seen_slot.map_or(slot, |last| last.max(slot)));
+    if let Some((multiplexer, source)) = multiplexer {
+        multiplexer.set_up(source, true);
+    }
+    HEALTH.subscription(program, url, true);
+    if down_since.take().is_some() {
+        incidents::resolve(SUBSCRIPTION_DEAD, key);
+    }
+
+    while let Some(update) = stream.next().await {
+        let (interaction_pubkey, slot, data) = update?;
+        *last_seen_slot = Some(last_seen_slot.map_or(slot, |last| last.max(slot)));
+        let new = multiplexer.map_or(true, |(multiplexer, source)| {
+            multiplexer.accept(source, interaction_pubkey, slot, &data)
+        });
+        if new {
+            worker_pool.dispatch_at(*program, interaction_pubkey, data, slot);
+        }
+    }
+    if let Some(unsubscribe) = unsubscribe {
+        unsubscribe().await;
+    }
+
+    Ok(())
+}
+
+/// Fetch all open interactions of `program` and dispatch them to the worker pool, oldest first.
+/// With `min_context_slot`, the RPC node must have caught up to that slot, so a lagging node
+/// can't hide interactions seen before a disconnect. Returns the slot the accounts were fetched
+/// at.
+///
+/// The addresses are listed first, then the accounts fetched `backlog_page_size` at a time, so
+/// a large backlog doesn't rest on a single huge call. Pages failing [`PAGE_ATTEMPTS`] times
+/// are skipped: the rest of the backlog is dispatched, then the pass fails so the caller
+/// gap-fills again.
+pub async fn fetch_and_process_program_accounts(
+    oracle: &Oracle,
+    program: &Pubkey,
+    filters: Vec<RpcFilterType>,
+    worker_pool: &WorkerPool,
+    min_context_slot: Option<u64>,
+) -> Result<u64, OracleError> {
+    let rpc_client = &oracle.rpc_client;
+    let started = Instant::now();
+    let slot = rpc_client
+        .get_slot_with_commitment(CommitmentConfig::processed())
+        .await?;
+    let keys = program_account_keys(rpc_client, program, filters, min_context_slot).await?;
+    let pages = keys.len().div_ceil(oracle.config.backlog_page_size);
+
+    let mut backlog = Vec::new();
+    let mut failed = 0;
+    for (index, page) in keys.chunks(oracle.config.backlog_page_size).enumerate() {
+        let accounts = match fetch_page(rpc_client, page, min_context_slot).await {
+            Ok(accounts) => accounts,
+            Err(e) => {
+                warn!(%program, page = index + 1, error = ?e, "Failed to fetch a backlog page");
+                failed += page.len();
+                continue;
+            }
+        };
+        for (pubkey, account) in page.iter().zip(accounts) {
+            let Some(account) = account else {
+                continue;
+            };
+            let Some(view) = InteractionView::parse(&account.data) else {
+                continue;
+            };
+            if view.is_processed {
+                continue;
+            }
+            // Callbacks may have landed without their account update being seen, e.g. before a
+            // restart: the ledger keeps those from being answered twice
+            match oracle.processed.contains(pubkey, view.text) {
+                Ok(true) => {
+                    debug!(interaction = %pubkey, "Already answered, skipping");
+                    METRICS.duplicates_skipped.inc();
+                    continue;
+                }
+                Ok(false) => {}
+                // The worker checks the ledger again
+                Err(e) => warn!(interaction = %pubkey, error = ?e, "Failed to read the ledger"),
+            }
+            backlog.push((*pubkey, view.created_at, account.data));
+        }
+        if (index + 1) % PROGRESS_PAGES == 0 {
+            info!(
+                %program,
+                page = index + 1,
+                pages,
+                pending = backlog.len(),
+                "Fetching the backlog"
+            );
+        }
+    }
+
+    // Oldest first: the closest to their refund timeout
+    backlog.sort_by_key(|(_, created_at, _)| *created_at);
+    let pending = backlog.len();
+    for (pubkey, _, data) in backlog {
+        worker_pool.dispatch(*program, pubkey, data);
+    }
+    if pending > 0 || failed > 0 {
+        info!(
+            %program,
+            accounts = keys.len(),
+            pending,
+            failed,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Backlog dispatched"
+        );
+    }
+    if failed > 0 {
+        return Err(format!(
+            "{} backlog accounts of {} couldn't be fetched",
+            failed, program
+        )
+        .into());
+    }
+    Ok(slot)
+}
@@ -0,0 +1,40 @@
+//! Structured logging with `tracing`.
+//!
+//! Levels are filtered with `RUST_LOG` (default `info`), `LOG_FORMAT=json` switches to one JSON
+//! object per line, and `LOG_REDACT_TEXT` keeps prompt and response text out of the logs.
+
+use crate::config::env_flag;
+use crate::OracleError;
+use std::env;
+use std::sync::OnceLock;
+use tracing_subscriber::EnvFilter;
+
+static REDACT_TEXT: OnceLock<bool> = OnceLock::new();
+
+/// Install the global subscriber
+pub fn init() -> Result<(), OracleError> {
+    REDACT_TEXT.get_or_init(|| env_flag("LOG_REDACT_TEXT"));
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().try_init()?,
+        Err(_) | Ok("text") => builder.try_init()?,
+        Ok(other) => {
+            return Err(format!(
+                "Invalid LOG_FORMAT {:?}, expected \"text\" or \"json\"",
+                other
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
+/// Prompt or response text as it may appear in logs
+pub fn redact(text: &str) -> String {
+    if *REDACT_TEXT.get_or_init(|| env_flag("LOG_REDACT_TEXT")) {
+        format!("[redacted, {} bytes]", text.len())
+    } else {
+        text.to_string()
+    }
+}
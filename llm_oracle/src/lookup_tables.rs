@@ -0,0 +1,270 @@
+//! Address lookup tables.
+//!
+//! A callback carries every account of the interaction's `callback_account_metas`, so callbacks
+//! of programs needing many accounts can outgrow a legacy transaction. Those are sent as v0
+//! transactions referencing the accounts through the lookup tables of `callback.lookup_tables`;
+//! callbacks that fit stay legacy.
+//!
+//! With `callback.auto_lookup_table`, the payer also maintains its own tables: accounts seen in
+//! `callback.lookup_table_min_uses` callbacks are added to the last table it owns, and a new
+//! table is created when there is none or it is full. Tables owned by the payer are found again
+//! on restart, so they don't need to be configured.
+
+use crate::callback::LAMPORTS_PER_SIGNATURE;
+use crate::config::LookupTableConfig;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::address_lookup_table::instruction::{create_lookup_table, extend_lookup_table};
+use solana_sdk::address_lookup_table::state::{AddressLookupTable, LOOKUP_TABLE_MAX_ADDRESSES};
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use solana_sdk::transaction::Transaction;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+pub const DEFAULT_LOOKUP_TABLE_MIN_USES: u32 = 3;
+/// Tables are reloaded this often, to pick up extensions made elsewhere
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+/// Addresses added per extension transaction, to stay within the packet size
+const MAX_ADDRESSES_PER_EXTENSION: usize = 20;
+/// Offset of the authority in a lookup table account: discriminator, deactivation slot, last
+/// extended slot and its start index, then the `Option` tag
+const AUTHORITY_OFFSET: usize = 22;
+
+#[derive(Default)]
+struct State {
+    tables: Vec<AddressLookupTableAccount>,
+    loaded_at: Option<Instant>,
+    /// Callbacks each account appeared in, for the auto table
+    uses: HashMap<Pubkey, u32>,
+}
+
+pub struct LookupTables {
+    config: LookupTableConfig,
+    state: Mutex<State>,
+}
+
+impl LookupTables {
+    pub fn new(config: LookupTableConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.auto || !self.config.addresses.is_empty()
+    }
+
+    /// The tables holding accounts of `instruction`, after adding its frequent accounts to the
+    /// auto table. Failures are logged: the callback is then sent without the missing tables.
+    pub async fn prepare(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+        instruction: &Instruction,
+    ) -> Vec<AddressLookupTableAccount> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        let accounts: HashSet<Pubkey> = instruction
+            .accounts
+            .iter()
+            .filter(|meta| !meta.is_signer)
+            .map(|meta| meta.pubkey)
+            .collect();
+        let mut state = self.state.lock().await;
+        if state
+            .loaded_at
+            .map_or(true, |at| at.elapsed() >= REFRESH_INTERVAL)
+        {
+            match self.load(rpc_client, &payer.pubkey()).await {
+                Ok(tables) => state.tables = tables,
+                Err(e) => warn!(error = ?e, "Failed to load the address lookup tables"),
+            }
+            state.loaded_at = Some(Instant::now());
+        }
+
+        if self.config.auto {
+            let mut frequent = Vec::new();
+            for account in &accounts {
+                let uses = state.uses.entry(*account).or_default();
+                *uses += 1;
+                if *uses >= self.config.min_uses
+                    && !state
+                        .tables
+                        .iter()
+                        .any(|table| table.addresses.contains(account))
+                {
+                    frequent.push(*account);
+                }
+            }
+            if !frequent.is_empty() {
+                match self
+                    .extend(rpc_client, payer, &state.tables, &frequent)
+                    .await
+                {
+                    Ok(()) => {
+                        for account in &frequent {
+                            state.uses.remove(account);
+                        }
+                        // Reload so the extended table is used from the next callback on
+                        state.loaded_at = None;
+                    }
+                    Err(e) => warn!(error = ?e, "Failed to extend the address lookup table"),
+                }
+            }
+        }
+
+        state
+            .tables
+            .iter()
+            .filter(|table| {
+                table
+                    .addresses
+                    .iter()
+                    .any(|address| accounts.contains(address))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The configured tables and, in auto mode, those owned by the payer. Deactivated tables
+    /// are skipped.
+    async fn load(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &Pubkey,
+    ) -> Result<Vec<AddressLookupTableAccount>, OracleError> {
+        let mut accounts = Vec::new();
+        let configured = rpc_client
+            .get_multiple_accounts(&self.config.addresses)
+            .await?;
+        for (address, account) in self.config.addresses.iter().zip(configured) {
+            match account {
+                Some(account) => accounts.push((*address, account.data)),
+                None => warn!(table = %address, "Address lookup table doesn't exist"),
+            }
+        }
+        if self.config.auto {
+            for (address, account) in self.owned(rpc_client, payer).await? {
+                if !self.config.addresses.contains(&address) {
+                    accounts.push((address, account));
+                }
+            }
+        }
+
+        let mut tables = Vec::new();
+        for (key, data) in accounts {
+            let table = AddressLookupTable::deserialize(&data)
+                .map_err(|e| format!("Invalid address lookup table {}: {}", key, e))?;
+            if table.meta.deactivation_slot != u64::MAX {
+                continue;
+            }
+            tables.push(AddressLookupTableAccount {
+                key,
+                addresses: table.addresses.to_vec(),
+            });
+        }
+        Ok(tables)
+    }
+
+    /// Tables whose authority is the payer
+    async fn owned(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Vec<u8>)>, OracleError> {
+        let accounts = rpc_client
+            .get_program_accounts_with_config(
+                &solana_sdk::address_lookup_table::program::id(),
+                RpcProgramAccountsConfig {
+                    filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new(
+                        AUTHORITY_OFFSET,
+                        MemcmpEncodedBytes::Bytes(payer.to_bytes().to_vec()),
+                    ))]),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?;
+        Ok(accounts
+            .into_iter()
+            .map(|(address, account)| (address, account.data))
+            .collect())
+    }
+
+    /// Add `addresses` to the payer's last table with room for them, creating one if needed
+    async fn extend(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+        tables: &[AddressLookupTableAccount],
+        addresses: &[Pubkey],
+    ) -> Result<(), OracleError> {
+        let authority = payer.pubkey();
+        let owned: HashSet<Pubkey> = self
+            .owned(rpc_client, &authority)
+            .await?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+        let mut table = tables
+            .iter()
+            .rev()
+            .find(|table| {
+                owned.contains(&table.key)
+                    && table.addresses.len() + addresses.len() <= LOOKUP_TABLE_MAX_ADDRESSES
+            })
+            .map(|table| table.key);
+        if table.is_none() {
+            let recent_slot = rpc_client
+                .get_slot_with_commitment(CommitmentConfig::finalized())
+                .await?;
+            let (instruction, address) = create_lookup_table(authority, authority, recent_slot);
+            self.send(rpc_client, payer, instruction).await?;
+            info!(table = %address, "Created an address lookup table");
+            table = Some(address);
+        }
+        let table = table.unwrap();
+        for chunk in addresses.chunks(MAX_ADDRESSES_PER_EXTENSION) {
+            let instruction =
+                extend_lookup_table(table, authority, Some(authority), chunk.to_vec());
+            self.send(rpc_client, payer, instruction).await?;
+        }
+        info!(table = %table, accounts = addresses.len(), "Extended the address lookup table");
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+        instruction: Instruction,
+    ) -> Result<(), OracleError> {
+        let recent_blockhash = rpc_client.get_latest_blockhash().await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&payer.pubkey()),
+            &[payer],
+            recent_blockhash,
+        );
+        rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await?;
+        METRICS.fee_lamports.inc_by(LAMPORTS_PER_SIGNATURE);
+        Ok(())
+    }
+}
@@ -1,524 +1,1130 @@
-use anchor_lang::prelude::AccountMeta;
-use anchor_lang::{AccountDeserialize, AnchorSerialize, Discriminator};
-use chatgpt::client::ChatGPT;
-use chatgpt::config::ModelConfiguration;
-use chatgpt::types::{ChatMessage, Role};
-use futures::StreamExt;
-use memory::InteractionMemory;
-use serde::{Deserialize, Serialize};
-use solana_account_decoder::UiAccountEncoding;
-use solana_client::pubsub_client::PubsubClient;
-use solana_client::rpc_client::RpcClient;
-use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
-use solana_sdk::compute_budget::ComputeBudgetInstruction;
-use solana_sdk::{
-    commitment_config::CommitmentConfig,
-    instruction::Instruction,
-    pubkey::Pubkey,
-    signature::{Keypair, Signer},
-    transaction::Transaction,
+use anchor_lang::AccountDeserialize;
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use llm_oracle::admin::{self, AdminCommand};
+use llm_oracle::admin_api;
+use llm_oracle::archive::Archive;
+use llm_oracle::audit::{self, AuditCommand, AuditLog};
+use llm_oracle::batching::CallbackBatcher;
+use llm_oracle::blinks::{self, BlinkArgs, BlinkStore};
+use llm_oracle::callback::CallbackSender;
+use llm_oracle::compression::CompressionClient;
+use llm_oracle::config::{
+    self, cli::ConfigCommand, deployment_name, env_flag, ListenerBackend, OracleConfig,
 };
-use std::env;
-use std::error::Error;
-use std::str::FromStr;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
-
-mod memory;
-
-const MAX_TX_RETRY_ATTEMPTS: u8 = 5;
-const MAX_API_RETRY_ATTEMPTS: u8 = 3;
-
-// =============================================================================
-// LLM Provider Abstraction (OpenAI + Gemini)
-// =============================================================================
-
-enum LLMProvider {
-    OpenAI(ChatGPT),
-    Gemini(GeminiClient),
-}
-
-impl LLMProvider {
-    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, Box<dyn Error>> {
-        match self {
-            LLMProvider::OpenAI(client) => {
-                let messages_vec = messages.to_vec();
-                let response = client.send_history(&messages_vec).await?;
-                Ok(response.message().content.clone())
-            }
-            LLMProvider::Gemini(client) => client.send_message(messages).await,
-        }
-    }
-}
-
-// Gemini API Client
-struct GeminiClient {
-    api_key: String,
-    client: reqwest::Client,
-}
-
-#[derive(Serialize)]
-struct GeminiRequest {
-    contents: Vec<GeminiContent>,
-    #[serde(rename = "generationConfig")]
-    generation_config: GeminiGenerationConfig,
-}
-
-#[derive(Serialize)]
-struct GeminiContent {
-    parts: Vec<GeminiPart>,
-    role: String,
-}
-
-#[derive(Serialize)]
-struct GeminiPart {
-    text: String,
-}
+use llm_oracle::context_import::{self, ContextCommand};
+use llm_oracle::costs::CostLedger;
+use llm_oracle::dedup::{self, ProcessedSet};
+use llm_oracle::digest::{self, Digest};
+use llm_oracle::dlq::{self, DeadLetterQueue};
+use llm_oracle::fee_payers::FeePayers;
+use llm_oracle::fees::FeeEstimator;
+use llm_oracle::functions::ChainFunctions;
+use llm_oracle::game::GameSessions;
+#[cfg(feature = "graphql")]
+use llm_oracle::graphql;
+use llm_oracle::guardrails::Guardrails;
+use llm_oracle::ingest::{self, Ingest};
+use llm_oracle::jito::JitoClient;
+#[cfg(feature = "rag")]
+use llm_oracle::knowledge::{self, cli::KbCommand, ContextIndex, Crawler, KnowledgeBase};
+use llm_oracle::listener::{pending_interactions, run_oracle};
+use llm_oracle::lookup_tables::LookupTables;
+use llm_oracle::nonce::NoncePool;
+use llm_oracle::notify::{self, Event, Severity};
+use llm_oracle::oracle::Oracle;
+use llm_oracle::processor::process_interaction;
+use llm_oracle::prompts::PromptTemplates;
+use llm_oracle::push::{self, PushHub};
+use llm_oracle::response_cache::ResponseCache;
+use llm_oracle::retention;
+use llm_oracle::review::{self, cli::ReviewCommand, ReviewQueue};
+use llm_oracle::rpc_check;
+use llm_oracle::structured::StructuredOutputs;
+#[cfg(feature = "rag")]
+use llm_oracle::tools::KnowledgeTool;
+use llm_oracle::tools::Tools;
+use llm_oracle::webhooks::ContextWebhooks;
+use llm_oracle::worker_pool::WorkerPool;
+use llm_oracle::{
+    context_watch, flood, incidents, logging, memory, metrics, providers, ratings, reconcile,
+    recovery, refunds, reload, shutdown, tuning, OracleError,
+};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{write_keypair_file, Keypair, Signer};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, info_span, warn, Instrument};
 
-#[derive(Serialize)]
-struct GeminiGenerationConfig {
-    temperature: f32,
-    #[serde(rename = "maxOutputTokens")]
-    max_output_tokens: u32,
+/// Answers solana-gpt-oracle interactions with an LLM
+#[derive(Parser)]
+#[command(name = "llm_oracle", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Deserialize)]
-struct GeminiResponse {
-    candidates: Vec<GeminiCandidate>,
+#[derive(Subcommand)]
+enum Command {
+    /// Listen for interactions and answer them (the default)
+    Run,
+    /// Load and validate the configuration, then exit
+    CheckConfig,
+    /// Print the configuration in effect, or upgrade the config file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Print the interactions that haven't been answered yet
+    ListPending,
+    /// Check that an RPC endpoint supports every method the oracle uses, and behaves as expected
+    RpcCheck {
+        /// RPC endpoint to check (defaults to RPC_URL)
+        #[arg(long)]
+        rpc: Option<String>,
+        /// Websocket endpoint to check (defaults to WEBSOCKET_URL)
+        #[arg(long)]
+        ws: Option<String>,
+    },
+    /// Process a single interaction now
+    Replay { interaction: Pubkey },
+    /// Generate a new oracle identity keypair
+    Keygen {
+        /// Write the keypair to this file (Solana CLI JSON format) instead of printing it
+        #[arg(long)]
+        outfile: Option<PathBuf>,
+    },
+    /// Administrative transactions, signable with a hardware wallet
+    #[command(subcommand)]
+    Admin(AdminCommand),
+    /// Create contexts in bulk
+    #[command(subcommand)]
+    Context(ContextCommand),
+    /// Publish a context as a Solana Action, for Blinks served by the metrics server
+    Blink(BlinkArgs),
+    /// Manage the knowledge base of a context
+    #[cfg(feature = "rag")]
+    #[command(subcommand)]
+    Kb(KbCommand),
+    /// Export the audit log of the answers sent, verifying its hash chain
+    #[command(subcommand)]
+    Audit(AuditCommand),
+    /// Inspect and retry callbacks in the dead-letter queue
+    #[command(subcommand)]
+    Dlq(DlqCommand),
+    /// Label sampled and flagged interactions, and export the labels
+    #[command(subcommand)]
+    Review(ReviewCommand),
+    /// Print the email digest of the last period
+    Digest {
+        /// Also email it
+        #[arg(long)]
+        send: bool,
+    },
+    /// Print the daily average user rating of each context and provider
+    Ratings {
+        /// Days to look back
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+        /// Only this context
+        #[arg(long)]
+        context: Option<Pubkey>,
+    },
+    /// Delete the prompts, responses and conversation of an interaction from every local store
+    Purge {
+        #[arg(long)]
+        interaction: Pubkey,
+    },
+    /// Refund the interactions left unanswered past the refund timeout, once
+    Refunds,
+    /// Print the daily tokens, callback fees and refunds of each context
+    Costs {
+        /// Days to look back
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+        /// Only this context
+        #[arg(long)]
+        context: Option<Pubkey>,
+    },
+    /// Suggest prompt template changes from the recent failures, for review
+    SuggestPrompts {
+        /// Days of failures to analyze
+        #[arg(long, default_value_t = 7)]
+        days: u64,
+        /// Write the suggested templates and diffs here instead of PROMPT_SUGGESTIONS_DIR
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Watch a running oracle: queue, interactions in flight, errors, spend and payer balance
+    #[cfg(feature = "dashboard")]
+    Top {
+        /// Address of the oracle's metrics server (defaults to metrics.addr)
+        #[arg(long)]
+        addr: Option<String>,
+    },
 }
 
-#[derive(Deserialize)]
-struct GeminiCandidate {
-    content: GeminiResponseContent,
+#[derive(Subcommand)]
+enum DlqCommand {
+    /// Print the queued callbacks
+    List,
+    /// Send queued callbacks again now
+    Retry {
+        /// Retry this interaction only
+        interaction: Option<Pubkey>,
+    },
 }
 
-#[derive(Deserialize)]
-struct GeminiResponseContent {
-    parts: Vec<GeminiResponsePart>,
+/// Oracle built from the configuration, with the crawler it needs started by `run`
+struct Setup {
+    oracle: Arc<Oracle>,
+    #[cfg(feature = "rag")]
+    crawler: Option<(Crawler, Arc<KnowledgeBase>)>,
 }
 
-#[derive(Deserialize)]
-struct GeminiResponsePart {
-    text: String,
-}
+fn build_oracle() -> Result<Setup, OracleError> {
+    let config = OracleConfig::load()?;
+    notify::init(&config.notify)?;
+    let llm_provider = providers::from_config(&config.llm)?;
+    let mut program_providers = HashMap::new();
+    for program in config
+        .programs
+        .iter()
+        .filter(|program| program.overrides_llm())
+    {
+        program_providers.insert(
+            program.id,
+            providers::from_config(&config.program_llm(program))?,
+        );
+    }
 
-impl GeminiClient {
-    fn new(api_key: String) -> Self {
-        Self {
-            api_key,
-            client: reqwest::Client::new(),
+    #[cfg_attr(not(feature = "rag"), allow(unused_mut))]
+    let mut tools = Tools::from_env(&config.rpc_url)?;
+    let functions = ChainFunctions::load(&config.functions, &config.rpc_url);
+    #[cfg(not(feature = "rag"))]
+    for var in ["KNOWLEDGE_URLS", "KNOWLEDGE_RETRIEVAL", "CONTEXT_RETRIEVAL"] {
+        if std::env::var(var).is_ok_and(|value| !value.is_empty()) {
+            return Err(format!("{} needs the oracle built with the rag feature", var).into());
         }
     }
+    #[cfg(feature = "rag")]
+    let crawler = Crawler::from_env()?;
+    #[cfg(feature = "rag")]
+    let retrieval = env_flag("KNOWLEDGE_RETRIEVAL");
+    #[cfg(feature = "rag")]
+    let mut knowledge_base = None;
+    #[cfg(feature = "rag")]
+    let context_retrieval = env_flag("CONTEXT_RETRIEVAL");
+    #[cfg(feature = "rag")]
+    if crawler.is_some() || retrieval || context_retrieval {
+        knowledge_base = Some(Arc::new(KnowledgeBase::from_env()?));
+    }
+    #[cfg(feature = "rag")]
+    if let (true, Some(knowledge_base)) = (retrieval, &knowledge_base) {
+        tools.register(Box::new(KnowledgeTool::from_env(
+            knowledge_base.clone(),
+            knowledge::embeddings::from_env()?,
+        )?));
+    }
 
-    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, Box<dyn Error>> {
-        // 0xAbim: Added validation to prevent empty contents array
-        if messages.is_empty() {
-            return Err("Cannot send empty message history to Gemini API".into());
+    #[cfg(feature = "rag")]
+    let context_index = match &knowledge_base {
+        Some(knowledge_base) => {
+            ContextIndex::from_env(knowledge_base.clone(), knowledge::embeddings::from_env()?)?
         }
+        None => None,
+    };
 
-        // Convert ChatMessage history to Gemini format
-        let contents: Vec<GeminiContent> = messages
-            .iter()
-            .map(|msg| {
-                let role = match msg.role {
-                    Role::User => "user",
-                    Role::System => "user", // Gemini doesn't have system role
-                    Role::Assistant => "model",
-                    Role::Function => "model", // Treat function as model
-                };
-                GeminiContent {
-                    parts: vec![GeminiPart {
-                        text: msg.content.clone(),
-                    }],
-                    role: role.to_string(),
-                }
-            })
-            .collect();
-
-        let request = GeminiRequest {
-            contents,
-            generation_config: GeminiGenerationConfig {
-                temperature: 0.7,
-                max_output_tokens: 100,
-            },
-        };
+    let interaction_memory = memory::from_env(config.memory_limits())?;
+    let processed = ProcessedSet::from_env(config.dedup_capacity)?;
+    let dlq = DeadLetterQueue::from_env(config.dlq_max_attempts)?;
+    let guardrails =
+        Guardrails::new(&config.guardrails)?.with_refusal_codes(config.refusals.enabled);
+    let callback_sender = CallbackSender::new(
+        FeeEstimator::from_env()?,
+        config.compute_unit_margin_percent,
+        config.max_tx_retries,
+        LookupTables::new(config.lookup_tables.clone()),
+        config
+            .durable_nonce
+            .then(|| NoncePool::new(config.nonce_accounts)),
+        (config.batch_window_ms > 0).then(|| {
+            CallbackBatcher::new(
+                Duration::from_millis(config.batch_window_ms),
+                config.max_batch_size,
+            )
+        }),
+        config.confirmation,
+        config
+            .jito_url
+            .clone()
+            .map(|url| JitoClient::new(url, config.jito_tip_lamports)),
+        FeePayers::load(&config.fee_payers, &config.payer.pubkey())?,
+    );
+    let archive = Archive::from_env()?;
+    let audit = AuditLog::open(&config.audit)?;
+    let response_cache = ResponseCache::from_env(config.cache.ttl_secs, config.cache.max_entries)?;
+    let games = GameSessions::load(config.game_state_machines.as_deref())?;
+    let structured = StructuredOutputs::load(config.structured_output_schemas.as_deref())?;
+    let review = ReviewQueue::load(&config.review)?;
+    let ingest = Ingest::load(&config.ingest)?;
+    let push = PushHub::load(&config.push)?;
+    let webhooks = ContextWebhooks::load(config.context_webhooks.as_deref())?;
+    if config.ratings && archive.is_none() {
+        return Err(
+            "ratings.enabled (RATINGS) requires ARCHIVE_PATH, where ratings are joined with \
+             responses"
+                .into(),
+        );
+    }
+    let oracle = Arc::new(Oracle::new(
+        config,
+        llm_provider,
+        program_providers,
+        interaction_memory,
+        games,
+        tools,
+        callback_sender,
+        archive,
+        audit,
+        processed,
+        dlq,
+        CostLedger::from_env()?,
+        guardrails,
+        structured,
+        functions,
+        #[cfg(feature = "rag")]
+        context_index,
+        review,
+        PromptTemplates::from_env()?,
+        ingest,
+        response_cache,
+        push,
+        webhooks,
+    ));
+    Ok(Setup {
+        oracle,
+        #[cfg(feature = "rag")]
+        crawler: crawler.zip(knowledge_base),
+    })
+}
 
-        // 0xAbim: Added Gemini API endpoint 
-        let url = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.0-flash:generateContent";
+async fn run() -> Result<(), OracleError> {
+    let setup = build_oracle()?;
+    let oracle = setup.oracle;
+    let config = &oracle.config;
+    info!(
+        identity = %config.payer.pubkey(),
+        rpc = %config.rpc_url,
+        ws = %config.websocket_url,
+        listener = ?config.listener,
+        max_concurrent_interactions = config.max_concurrent_interactions,
+        "Starting oracle"
+    );
 
-        let response = self.client
-            .post(url)
-            .header("x-goog-api-key", &self.api_key)
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
+    if let Some(addr) = config.metrics_addr.clone() {
+        let oracle = oracle.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = metrics::serve(&addr, oracle).await {
+                    error!(error = ?e, "Metrics server stopped");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    if oracle.ingest.is_some() {
+        let oracle = oracle.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = ingest::serve(oracle).await {
+                    error!(error = ?e, "Ingestion server stopped");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    if oracle.push.is_some() {
+        let oracle = oracle.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = push::serve(oracle).await {
+                    error!(error = ?e, "Push server stopped");
+                }
+            }
+            .in_current_span(),
+        );
+    }
+    #[cfg(feature = "rag")]
+    if let Some((crawler, knowledge_base)) = setup.crawler {
+        tokio::spawn(
+            crawler
+                .run(knowledge_base, knowledge::embeddings::from_env()?)
+                .in_current_span(),
+        );
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await?;
-            return Err(format!("Gemini API error ({}): {}", status, error_text).into());
-        }
+    #[cfg(feature = "rag")]
+    if oracle.context_index.is_some() {
+        tokio::spawn(knowledge::contexts::run(oracle.clone()).in_current_span());
+    }
 
-        let gemini_response: GeminiResponse = response.json().await?;
+    if config.ratings {
+        tokio::spawn(ratings::run(oracle.clone()).in_current_span());
+    }
+    if config.prompt_suggestions.dir.is_some() {
+        tokio::spawn(tuning::run(oracle.clone()).in_current_span());
+    }
+    tokio::spawn(dlq::run(oracle.clone()).in_current_span());
+    tokio::spawn(dedup::run(oracle.clone()).in_current_span());
+    tokio::spawn(reload::run(oracle.clone()).in_current_span());
+    tokio::spawn(retention::run(oracle.clone()).in_current_span());
+    tokio::spawn(context_watch::run(oracle.clone()).in_current_span());
+    tokio::spawn(incidents::watch_payer(oracle.clone()).in_current_span());
+    if let Some(digest_config) = config.digest.clone() {
+        let digest = Digest::new(digest_config)?;
+        tokio::spawn(digest::run(oracle.clone(), digest).in_current_span());
+    }
 
-        if let Some(candidate) = gemini_response.candidates.first() {
-            if let Some(part) = candidate.content.parts.first() {
-                return Ok(part.text.clone());
+    let worker_pool = WorkerPool::new(oracle.clone(), config.max_concurrent_interactions);
+    admin_api::attach(worker_pool.clone());
+    if oracle.flood.is_enabled() {
+        tokio::spawn(flood::run(oracle.clone(), worker_pool.clone()).in_current_span());
+    }
+    // Before listening, so the gap-fill doesn't race the interactions being resumed
+    if let Err(e) = recovery::recover(&oracle, &worker_pool).await {
+        error!(error = ?e, "Crash recovery failed");
+        notify::emit(Event::new(
+            Severity::Warning,
+            "recovery_failed",
+            "Crash recovery failed",
+            e.to_string(),
+        ));
+    }
+    if config.reconcile.enabled {
+        tokio::spawn(reconcile::run(oracle.clone(), worker_pool.clone()).in_current_span());
+    }
+    // After recovery, so the interactions it resumes are held by the pool
+    if config.refunds.enabled {
+        tokio::spawn(refunds::run(oracle.clone(), worker_pool.clone()).in_current_span());
+    }
+    let listen = async {
+        loop {
+            if let Err(e) = run_oracle(&oracle, &worker_pool).await {
+                error!(error = ?e, "Error encountered. Waiting 30 seconds before retry...");
+                notify::emit(Event::new(
+                    Severity::Warning,
+                    "listener_error",
+                    "Listener failed, restarting in 30 seconds",
+                    e.to_string(),
+                ));
+                // 0xAbim: Added delay to prevent infinite loop on persistent errors
+                tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
             }
         }
-
-        Err("No response from Gemini API".into())
+    };
+    tokio::select! {
+        _ = listen => {}
+        () = shutdown::signal() => {}
+    }
+    tokio::select! {
+        () = shutdown::drain(&oracle, &worker_pool) => {}
+        () = shutdown::signal() => warn!("Exiting without waiting for the interactions in flight"),
     }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    dotenv::dotenv().ok(); // Load .env file
-    let (rpc_url, websocket_url, llm_provider, payer, identity_pda) = load_config()?;
-    let mut interaction_memory = InteractionMemory::new(10);
-    println!(" Oracle identity: {:?}", payer.pubkey());
-    println!(" RPC: {:?}", rpc_url.as_str());
-    println!(" WS: {:?}", websocket_url.as_str());
-    loop {
-        if let Err(e) = run_oracle(
-            rpc_url.as_str(),
-            websocket_url.as_str(),
-            &llm_provider,
-            &payer,
-            &identity_pda,
-            &mut interaction_memory,
-        )
-        .await
-        {
-            eprintln!("Error encountered: {:?}. Waiting 30 seconds before retry...", e);
-            // 0xAbim: Added delay to prevent infinite loop on persistent errors
-            tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+fn check_config() -> Result<(), OracleError> {
+    let setup = build_oracle()?;
+    let oracle = setup.oracle;
+    let config = &oracle.config;
+    println!("features:       {}", llm_oracle::FEATURES.join(", "));
+    println!(
+        "deployment:     {}",
+        config.name.as_deref().unwrap_or("default")
+    );
+    println!("identity:       {}", config.payer.pubkey());
+    println!(
+        "payer checks:   below {} SOL every {}s{}{}",
+        lamports_to_sol(config.incidents.payer_min_lamports),
+        config.incidents.payer_check_secs,
+        if config.incidents.pause_when_payer_empty {
+            ", pausing LLM calls"
+        } else {
+            ""
+        },
+        match config.incidents.payer_airdrop_lamports {
+            Some(lamports) => format!(", airdropping {} SOL", lamports_to_sol(lamports)),
+            None => String::new(),
         }
+    );
+    match &oracle.callback_sender.fee_payers {
+        Some(fee_payers) => println!(
+            "fee payers:     {}, each used above {} SOL",
+            fee_payers
+                .pubkeys()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            lamports_to_sol(config.fee_payers.min_lamports)
+        ),
+        None => println!("fee payers:     the identity"),
     }
-}
-
-async fn run_oracle(
-    rpc_url: &str,
-    websocket_url: &str,
-    llm_provider: &LLMProvider,
-    payer: &Keypair,
-    identity_pda: &Pubkey,
-    interaction_memory: &mut InteractionMemory,
-) -> Result<(), Box<dyn Error>> {
-    let rpc_client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::processed());
-
-    let (tx, rx) = mpsc::channel(100);
-    let mut stream = ReceiverStream::new(rx);
-
-    let rpc_config = RpcAccountInfoConfig {
-        commitment: Some(CommitmentConfig::processed()),
-        encoding: Some(UiAccountEncoding::Base64),
-        ..Default::default()
-    };
-
-    let filters = vec![solana_client::rpc_filter::RpcFilterType::Memcmp(
-        solana_client::rpc_filter::Memcmp::new(
-            0,
-            solana_client::rpc_filter::MemcmpEncodedBytes::Bytes(
-                solana_gpt_oracle::Interaction::DISCRIMINATOR.to_vec(),
-            ),
+    println!("rpc:            {}", config.rpc_url);
+    match &config.listener {
+        ListenerBackend::Websocket => println!("websocket:      {}", config.websocket_url),
+        ListenerBackend::Geyser { url } => println!("geyser:         {}", url),
+    }
+    if let Some(url) = &config.secondary_websocket_url {
+        println!("websocket (2):  {}", url);
+    }
+    println!("llm provider:   {}", oracle.llm_provider.name());
+    let limit =
+        |limit: Option<u32>| limit.map_or("unlimited".to_string(), |limit| limit.to_string());
+    println!(
+        "rate limits:    {}/min per context, {}/min per user, {} when over",
+        limit(config.limits.per_context_per_minute),
+        limit(config.limits.per_user_per_minute),
+        config.limits.action
+    );
+    for (provider, tokens) in &config.llm.daily_tokens {
+        println!("budget:         {} {} tokens/day", provider, tokens);
+    }
+    for (provider, usd) in &config.llm.daily_usd {
+        println!("budget:         {} ${}/day", provider, usd);
+    }
+    for program in &config.programs {
+        println!(
+            "program:        {} (identity pda {}, provider {})",
+            program.id,
+            program.identity_pda,
+            oracle.provider(&program.id).name()
+        );
+    }
+    println!(
+        "prompts:        {} context template(s)",
+        oracle.prompts.get().overrides().len()
+    );
+    println!(
+        "reload:         on SIGHUP{}",
+        if admin_api::enabled(&oracle) {
+            " and POST /admin/reload"
+        } else {
+            ""
+        }
+    );
+    println!("tools:          {}", oracle.tools.names().join(", "));
+    match &oracle.functions {
+        Some(functions) => println!("functions:      {}", functions.names().join(", ")),
+        None => println!("functions:      off"),
+    }
+    #[cfg(feature = "rag")]
+    {
+        println!("crawler:        {}", setup.crawler.is_some());
+        println!("context index:  {}", oracle.context_index.is_some());
+    }
+    println!(
+        "lookup tables:  {}{}",
+        config.lookup_tables.addresses.len(),
+        if config.lookup_tables.auto {
+            " + auto"
+        } else {
+            ""
+        }
+    );
+    if config.batch_window_ms > 0 {
+        println!(
+            "batching:       {} ms window, up to {} callbacks",
+            config.batch_window_ms, config.max_batch_size
+        );
+    } else {
+        println!("batching:       off");
+    }
+    if config.streaming_callbacks {
+        println!(
+            "streaming:      partial callbacks every {} bytes",
+            config.stream_min_bytes
+        );
+    } else {
+        println!("streaming:      off");
+    }
+    match config.attester() {
+        Some(attester) => println!("attestations:   signed by {}", attester.pubkey()),
+        None => println!("attestations:   off"),
+    }
+    if config.refunds.enabled {
+        println!(
+            "refunds:        after {}s unanswered, checked every {}s",
+            solana_gpt_oracle::REFUND_TIMEOUT_SECS,
+            config.refunds.interval_secs
+        );
+    } else {
+        println!("refunds:        off");
+    }
+    if config.durable_nonce {
+        println!("durable nonce:  {} account(s)", config.nonce_accounts);
+    } else {
+        println!("durable nonce:  off");
+    }
+    match &config.jito_url {
+        Some(url) => println!(
+            "jito:           {} ({} lamports tip)",
+            url, config.jito_tip_lamports
         ),
-    )];
-
-    fetch_and_process_program_accounts(
-        &rpc_client,
-        filters.clone(),
-        payer,
-        identity_pda,
-        llm_provider,
-        interaction_memory,
-    )
-    .await?;
-
-    let program_config = RpcProgramAccountsConfig {
-        account_config: rpc_config,
-        filters: Some(filters),
-        ..Default::default()
-    };
-
-    let subscription = PubsubClient::program_subscribe(
-        &websocket_url,
-        &solana_gpt_oracle::ID,
-        Some(program_config),
-    )?;
-
-    tokio::spawn(async move {
-        for update in subscription.1 {
-            if tx.send(update).await.is_err() {
-                eprintln!("Receiver dropped");
-                break;
+        None => println!("jito:           off"),
+    }
+    match &config.tx_audit_dir {
+        Some(dir) => println!("tx audit:       {}", dir),
+        None => println!("tx audit:       off"),
+    }
+    println!("archive:        {}", oracle.archive.is_some());
+    match &config.audit.dir {
+        Some(dir) => println!(
+            "audit log:      {}, rotated at {} bytes",
+            dir, config.audit.max_bytes
+        ),
+        None => println!("audit log:      off"),
+    }
+    println!("ratings:        {}", config.ratings);
+    match &oracle.review {
+        Some(review) => println!("review:         {}% sampled", review.sample_percent()),
+        None => println!("review:         off"),
+    }
+    match &config.digest {
+        Some(digest) => println!("digest:         {}", digest.period),
+        None => println!("digest:         off"),
+    }
+    println!(
+        "notify:         {}",
+        config
+            .notify
+            .channels
+            .iter()
+            .map(|channel| channel.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!("structured:     {} context(s)", oracle.structured.len());
+    println!(
+        "health:         /healthz, /readyz on METRICS_ADDR, stalled after {}s",
+        config.health_stall_secs
+    );
+    println!(
+        "shutdown:       waits {}s for the interactions in flight",
+        config.shutdown_timeout_secs
+    );
+    if config.images.enabled {
+        println!(
+            "images:         up to {} bytes, {}{}",
+            config.images.max_bytes,
+            if oracle.llm_provider.sees_images() {
+                "seen by the model"
+            } else {
+                "not seen by the model"
+            },
+            if config.images.allowed_hosts.is_empty() {
+                String::new()
+            } else {
+                format!(", from {}", config.images.allowed_hosts.join(", "))
             }
-        }
-    });
-
-    while let Some(update) = stream.next().await {
-        if let Ok(interaction_pubkey) = Pubkey::from_str(&update.value.pubkey) {
-            if let Some(data) = update.value.account.data.decode() {
-                process_interaction(
-                    payer,
-                    identity_pda,
-                    llm_provider,
-                    &rpc_client,
-                    interaction_pubkey,
-                    data,
-                    interaction_memory,
-                )
-                .await?;
+        );
+    } else {
+        println!("images:         off");
+    }
+    match &config.encryption_key {
+        Some(key) => println!(
+            "encryption:     prompts sealed to {}",
+            solana_sdk::bs58::encode(key.public_key().as_bytes()).into_string()
+        ),
+        None => println!("encryption:     off"),
+    }
+    if config.context_settings.enabled {
+        println!(
+            "ctx settings:   up to {} tokens, models: {}",
+            config.context_settings.max_tokens,
+            if config.context_settings.models.is_empty() {
+                "none".to_string()
+            } else {
+                config.context_settings.models.join(", ")
             }
-        }
+        );
+    } else {
+        println!("ctx settings:   off");
     }
-
+    if config.response_length.enabled {
+        println!(
+            "resp length:    adaptive, up to {} tokens, adjusted every {} answers over {}% cut",
+            config.response_length.max_tokens,
+            config.response_length.window,
+            config.response_length.target_percent
+        );
+    } else {
+        println!("resp length:    fixed");
+    }
+    match (config.refusals.enabled, config.refusals.max_age_secs) {
+        (true, Some(max_age)) => println!("refusals:       codes, expired after {}s", max_age),
+        (true, None) => println!("refusals:       codes"),
+        (false, _) => println!("refusals:       text"),
+    }
+    match (config.flood.max_per_second, config.flood.deactivate) {
+        (Some(max), true) => println!("flood:          over {}/s deactivates the context", max),
+        (Some(max), false) => println!(
+            "flood:          over {}/s throttles the context for {}s",
+            max, config.flood.throttle_secs
+        ),
+        (None, _) => println!("flood:          unguarded"),
+    }
+    if config.reconcile.enabled {
+        println!(
+            "reconcile:      every {}s, entries older than {}s",
+            config.reconcile.interval_secs, config.reconcile.min_age_secs
+        );
+    } else {
+        println!("reconcile:      off");
+    }
+    match config.retention.prompt_days {
+        Some(days) => println!("retention:      prompts deleted after {} day(s)", days),
+        None => println!("retention:      kept"),
+    }
+    match &oracle.response_cache {
+        Some(cache) => println!(
+            "response cache: {}s, {} answers in memory",
+            cache.ttl().as_secs(),
+            config.cache.max_entries
+        ),
+        None => println!("response cache: off"),
+    }
+    match &oracle.ingest {
+        Some(ingest) => println!(
+            "ingestion:      POST {} on {}, {} key(s), sponsored by {}",
+            ingest::INGEST_PATH,
+            ingest.addr(),
+            ingest.keys().len(),
+            ingest.sponsor()
+        ),
+        None => println!("ingestion:      off"),
+    }
+    match &oracle.push {
+        Some(push) => println!(
+            "push:           ws://{}, {} key(s)",
+            push.addr(),
+            push.keys()
+        ),
+        None => println!("push:           off"),
+    }
+    if oracle.webhooks.is_empty() {
+        println!("webhooks:       off");
+    } else {
+        println!("webhooks:       {} context(s)", oracle.webhooks.len());
+    }
+    #[cfg(feature = "graphql")]
+    match (graphql::enabled(&oracle), oracle.archive.is_some()) {
+        (true, true) => println!("graphql:        {} on METRICS_ADDR", graphql::GRAPHQL_PATH),
+        (true, false) => println!(
+            "graphql:        {} on METRICS_ADDR, costs only (no ARCHIVE_PATH)",
+            graphql::GRAPHQL_PATH
+        ),
+        (false, _) => println!("graphql:        off"),
+    }
+    #[cfg(not(feature = "graphql"))]
+    println!("graphql:        not built");
+    if admin_api::enabled(&oracle) {
+        println!("admin api:      {} on METRICS_ADDR", admin_api::ADMIN_PATH);
+    } else {
+        println!("admin api:      off");
+    }
+    match &config.compression_url {
+        Some(_) => println!("compression:    compressed interactions listed, not answered"),
+        None => println!("compression:    off"),
+    }
+    match BlinkStore::from_env() {
+        Some(_) => println!("blinks:         {} on METRICS_ADDR", blinks::ACTIONS_PATH),
+        None => println!("blinks:         off"),
+    }
+    println!("Configuration OK");
     Ok(())
 }
 
-/// Process an interaction and respond to it
-async fn process_interaction(
-    payer: &Keypair,
-    identity_pda: &Pubkey,
-    llm_provider: &LLMProvider,
-    rpc_client: &RpcClient,
-    interaction_pubkey: Pubkey,
-    data: Vec<u8>,
-    interaction_memory: &mut InteractionMemory,
-) -> Result<(), Box<dyn Error>> {
-    if let Ok(interaction) =
-        solana_gpt_oracle::Interaction::try_deserialize_unchecked(&mut data.as_slice())
-    {
-        if interaction.is_processed == true {
-            return Ok(());
+async fn list_pending() -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let rpc_client = RpcClient::new(config.rpc_url);
+    let compression = config
+        .compression_url
+        .map(CompressionClient::new)
+        .transpose()?;
+    let mut count = 0;
+    for program in &config.programs {
+        let pending = pending_interactions(&rpc_client, &program.id).await?;
+        for (pubkey, interaction) in &pending {
+            println!(
+                "{}\tprogram {}\tcontext {}\tuser {}\t{:?}",
+                pubkey, program.id, interaction.context, interaction.user, interaction.text
+            );
         }
-        println!("Processing interaction: {:?}", interaction_pubkey);
-        if let Ok(context_data) = rpc_client.get_account(&interaction.context) {
-            if let Ok(context) = solana_gpt_oracle::ContextAccount::try_deserialize_unchecked(
-                &mut context_data.data.as_slice(),
-            ) {
+        count += pending.len();
+        if let Some(compression) = &compression {
+            let pending = compression.pending_interactions(&program.id).await?;
+            for compressed in &pending {
+                let interaction = &compressed.interaction;
                 println!(
-                    "Interaction: {:?}, Pubkey: {:?}",
-                    interaction, interaction_pubkey
-                );
-
-                // Get a response from the OpenAI API
-                let mut previous_history = interaction_memory
-                    .get_history(&interaction_pubkey)
-                    .unwrap_or(Vec::new())
-                    .clone();
-                interaction_memory.add_interaction(
-                    interaction_pubkey,
-                    interaction.text.clone(),
-                    Role::User,
+                    "{}\tprogram {}\tcontext {}\tuser {}\t{:?}\tcompressed",
+                    compressed.account.id(),
+                    program.id,
+                    interaction.context,
+                    interaction.user,
+                    interaction.text
                 );
-                previous_history.push(ChatMessage {
-                    role: Role::User,
-                    content: format!(
-                        "With context: {:?}, respond to: {:?}",
-                        context.text, interaction.text
-                    ),
-                });
-                let mut api_attempts = 0;
-                let mut response_content = String::new();
-                while api_attempts < MAX_API_RETRY_ATTEMPTS {
-                    match llm_provider.send_message(&previous_history).await {
-                        Ok(response) => {
-                            response_content = response;
-                            break;
-                        }
-                        Err(e) => {
-                            api_attempts += 1;
-                            // 0xAbim: Improved retry logic - only skip messages if we have enough, keep at least 1
-                            let skip_count = (api_attempts * 2) as usize;
-                            if previous_history.len() > skip_count + 1 {
-                                previous_history = previous_history
-                                    .iter()
-                                    .skip(skip_count)
-                                    .cloned()
-                                    .collect();
-                            }
-                            eprintln!(
-                                "API call failed (attempt {}/{}): {:?}",
-                                api_attempts, MAX_API_RETRY_ATTEMPTS, e
-                            );
-                            if api_attempts >= MAX_API_RETRY_ATTEMPTS {
-                                return Err(e);
-                            }
-                        }
-                    }
-                }
+            }
+            count += pending.len();
+        }
+    }
+    println!("{} pending interaction(s)", count);
+    Ok(())
+}
 
-                interaction_memory.add_interaction(
-                    interaction_pubkey,
-                    response_content.clone(),
-                    Role::System,
-                );
+async fn rpc_check(rpc: Option<String>, ws: Option<String>) -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let rpc_url = rpc.unwrap_or(config.rpc_url);
+    let websocket_url = ws.unwrap_or(config.websocket_url);
+    let program = config.programs.first().ok_or("No program configured")?.id;
+    println!("Checking {} and {}", rpc_url, websocket_url);
+    let results = rpc_check::run(&rpc_url, &websocket_url, &program, &config.payer.pubkey()).await;
+    for result in &results {
+        println!(
+            "{:<36} {:<12} {:>6}ms  {}",
+            result.method,
+            result.outcome,
+            result.elapsed.as_millis(),
+            result.detail
+        );
+    }
+    let problems = results
+        .iter()
+        .filter(|result| result.outcome != rpc_check::Outcome::Supported)
+        .count();
+    if problems > 0 {
+        return Err(format!("{} of {} checks didn't pass", problems, results.len()).into());
+    }
+    println!("All {} checks passed", results.len());
+    Ok(())
+}
 
-                let response_data = [
-                    solana_gpt_oracle::instruction::CallbackFromLlm::DISCRIMINATOR.to_vec(),
-                    response_content.try_to_vec()?,
-                ]
-                .concat();
+async fn replay(interaction_pubkey: Pubkey) -> Result<(), OracleError> {
+    let Setup { oracle, .. } = build_oracle()?;
+    let account = oracle.rpc_client.get_account(&interaction_pubkey).await?;
+    let interaction = solana_gpt_oracle::Interaction::try_deserialize(&mut account.data.as_slice())
+        .map_err(|e| format!("{} is not an interaction: {}", interaction_pubkey, e))?;
+    if interaction.is_processed {
+        println!("{} has already been answered", interaction_pubkey);
+        return Ok(());
+    }
+    oracle.config.program(&account.owner)?;
+    process_interaction(&oracle, account.owner, interaction_pubkey, account.data).await
+}
 
-                let mut callback_instruction = Instruction {
-                    program_id: solana_gpt_oracle::ID,
-                    accounts: vec![
-                        AccountMeta::new(payer.pubkey(), true),
-                        AccountMeta::new_readonly(*identity_pda, false),
-                        AccountMeta::new(interaction_pubkey, false),
-                        AccountMeta::new_readonly(interaction.callback_program_id, false),
-                    ],
-                    data: response_data,
+async fn dead_letters(command: DlqCommand) -> Result<(), OracleError> {
+    let Setup { oracle, .. } = build_oracle()?;
+    let letters = match command {
+        DlqCommand::List => {
+            let letters = oracle.dlq.list()?;
+            for letter in &letters {
+                let next = match letter.next_attempt_at {
+                    Some(at) => format!("next retry at {}", at),
+                    None => "retries exhausted".to_string(),
                 };
-
-                // Add the remaining accounts from the callback_account_metas
-                let remaining_accounts: Vec<AccountMeta> = interaction
-                    .callback_account_metas
-                    .iter()
-                    .map(|meta| AccountMeta {
-                        pubkey: meta.pubkey,
-                        is_signer: meta.is_signer,
-                        is_writable: meta.is_writable,
-                    })
-                    .collect();
-                callback_instruction.accounts.extend(remaining_accounts);
-
-                // Send the response with the callback transaction
-                let mut attempts = 0;
-                while attempts < MAX_TX_RETRY_ATTEMPTS {
-                    if let Ok(recent_blockhash) = rpc_client
-                        .get_latest_blockhash_with_commitment(CommitmentConfig::processed())
-                    {
-                        let compute_budget_instruction =
-                            ComputeBudgetInstruction::set_compute_unit_limit(300_000);
-                        let priority_fee_instruction =
-                            ComputeBudgetInstruction::set_compute_unit_price(1_000_000);
-
-                        let transaction = Transaction::new_signed_with_payer(
-                            &[
-                                compute_budget_instruction,
-                                priority_fee_instruction,
-                                callback_instruction.clone(),
-                            ],
-                            Some(&payer.pubkey()),
-                            &[&payer],
-                            recent_blockhash.0,
-                        );
-
-                        match rpc_client.send_and_confirm_transaction(&transaction) {
-                            Ok(signature) => {
-                                println!("Transaction signature: {}\n", signature);
-                                break;
-                            }
-                            Err(e) => {
-                                attempts += 1;
-                                eprintln!("Failed to send transaction: {:?}\n", e)
-                            }
-                        }
-                    }
-                }
+                println!(
+                    "{}\t{} attempt(s)\t{}\t{}",
+                    letter.interaction, letter.attempts, next, letter.last_error
+                );
             }
+            println!("{} dead letter(s)", letters.len());
+            return Ok(());
+        }
+        DlqCommand::Retry {
+            interaction: Some(interaction),
+        } => vec![oracle
+            .dlq
+            .get(&interaction)?
+            .ok_or_else(|| format!("{} isn't in the dead-letter queue", interaction))?],
+        DlqCommand::Retry { interaction: None } => oracle.dlq.list()?,
+    };
+    for letter in letters {
+        dlq::retry(&oracle, &letter).await?;
+        let interaction = letter.interaction.parse()?;
+        match oracle.dlq.get(&interaction)? {
+            Some(letter) => println!("{} failed again: {}", interaction, letter.last_error),
+            None => println!("{} is out of the queue", interaction),
         }
     }
     Ok(())
 }
 
-/// Fetch all open interactions and process them
-async fn fetch_and_process_program_accounts(
-    rpc_client: &RpcClient,
-    filters: Vec<solana_client::rpc_filter::RpcFilterType>,
-    payer: &Keypair,
-    identity_pda: &Pubkey,
-    llm_provider: &LLMProvider,
-    interaction_memory: &mut InteractionMemory,
-) -> Result<(), Box<dyn Error>> {
-    let rpc_config = RpcAccountInfoConfig {
-        commitment: Some(CommitmentConfig::processed()),
-        encoding: Some(UiAccountEncoding::Base64),
-        ..Default::default()
-    };
-
-    let program_config = RpcProgramAccountsConfig {
-        account_config: rpc_config,
-        filters: Some(filters),
-        ..Default::default()
-    };
-
-    let accounts =
-        rpc_client.get_program_accounts_with_config(&solana_gpt_oracle::ID, program_config)?;
+async fn print_digest(send: bool) -> Result<(), OracleError> {
+    let Setup { oracle, .. } = build_oracle()?;
+    let config = oracle
+        .config
+        .digest
+        .clone()
+        .ok_or("Set DIGEST_SCHEDULE (or `digest.schedule`) to configure digests")?;
+    let digest = Digest::new(config)?;
+    let end = digest.period().next_boundary(Utc::now()) - digest.period().length();
+    let report = digest.report(&oracle, end, 0)?;
+    let (subject, body) = digest.render(&report)?;
+    println!("{}\n\n{}", subject.trim(), body);
+    if send {
+        digest.send(&report).await?;
+        println!("Sent");
+    }
+    Ok(())
+}
 
-    for (pubkey, account) in accounts {
-        process_interaction(
-            payer,
-            identity_pda,
-            llm_provider,
-            rpc_client,
-            pubkey,
-            account.data,
-            interaction_memory,
-        )
-        .await?;
+fn print_ratings(days: u64, context: Option<Pubkey>) -> Result<(), OracleError> {
+    let archive = Archive::from_env()?.ok_or("Set ARCHIVE_PATH to read the ratings")?;
+    let since = Utc::now() - chrono::TimeDelta::days(days as i64);
+    let mut rated = archive.ratings(since.timestamp_millis().max(0) as u64)?;
+    if let Some(context) = context {
+        rated.retain(|rating| rating.context == context.to_string());
     }
+    for (label, trends) in [
+        (
+            "context",
+            ratings::daily_trends(&rated, |rating| rating.context.clone()),
+        ),
+        (
+            "provider",
+            ratings::daily_trends(&rated, |rating| {
+                rating.provider.clone().unwrap_or("unknown".to_string())
+            }),
+        ),
+    ] {
+        for ((key, day), trend) in trends {
+            println!(
+                "{} {}\t{}\t{} rating(s)\taverage {:.2}",
+                label,
+                key,
+                day,
+                trend.count,
+                trend.average()
+            );
+        }
+    }
+    println!("{} rating(s) in the last {} day(s)", rated.len(), days);
+    Ok(())
+}
 
+fn purge(interaction: Pubkey) -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let purged = retention::purge(&config, &interaction)?;
+    println!(
+        "archive:      {} record(s) and rating(s) deleted",
+        purged.archive
+    );
+    println!("ledger:       {} response(s) deleted", purged.ledger);
+    println!("review:       {} item(s) deleted", purged.review);
+    println!("cache:        {} answer(s) deleted", purged.cache);
+    println!("dead letters: {} deleted", purged.dead_letters);
+    println!(
+        "conversation: {}",
+        if purged.conversation {
+            "deleted"
+        } else {
+            "none"
+        }
+    );
+    println!(
+        "{} and its callbacks are on-chain and can't be deleted",
+        interaction
+    );
     Ok(())
 }
 
-/// Load the Oracle configuration
-fn load_config() -> Result<(String, String, LLMProvider, Keypair, Pubkey), Box<dyn Error>> {
-    let identity = env::var("IDENTITY").unwrap_or(
-        "62LxqpAW6SWhp7iKBjCQneapn1w6btAhW7xHeREWSpPzw3xZbHCfAFesSR4R76ejQXCLWrndn37cKCCLFvx6Swps"
-            .to_string(),
+async fn refund_overdue() -> Result<(), OracleError> {
+    let Setup { oracle, .. } = build_oracle()?;
+    let refunded = refunds::sweep(&oracle, None).await?;
+    println!(
+        "Refunded {} interaction(s), {} SOL{}",
+        refunded.interactions,
+        lamports_to_sol(refunded.lamports),
+        if refunded.failed > 0 {
+            format!(", {} failed", refunded.failed)
+        } else {
+            String::new()
+        }
     );
-    let rpc_url = env::var("RPC_URL").unwrap_or("https://devnet.magicblock.app/".to_string());
-    let websocket_url = env::var("WEBSOCKET_URL").unwrap_or("ws://devnet.magicblock.app/".to_string());
+    Ok(())
+}
 
-    // Detect which LLM provider to use based on API keys
-    let llm_provider = if let Ok(gemini_key) = env::var("GEMINI_API_KEY") {
-        if !gemini_key.is_empty() && gemini_key != "your-gemini-api-key-here" {
-            println!("🤖 Using Gemini AI (gemini-2.0-flash)");
-            LLMProvider::Gemini(GeminiClient::new(gemini_key))
-        } else if let Ok(openai_key) = env::var("OPENAI_API_KEY") {
-            if !openai_key.is_empty() {
-                println!("🤖 Using OpenAI (gpt-4o)");
-                LLMProvider::OpenAI(ChatGPT::new_with_config(
-                    openai_key.as_str(),
-                    ModelConfiguration {
-                        engine: chatgpt::config::ChatGPTEngine::Custom("gpt-4o"),
-                        presence_penalty: 0.3,
-                        frequency_penalty: 0.3,
-                        max_tokens: Some(100),
-                        ..Default::default()
-                    },
-                )?)
+fn print_costs(days: u64, context: Option<Pubkey>) -> Result<(), OracleError> {
+    let ledger = CostLedger::from_env()?;
+    // Prices are optional: without a valid configuration, costs are printed in tokens only
+    let usd_per_1k_tokens = OracleConfig::load()
+        .map(|config| config.llm.usd_per_1k_tokens)
+        .unwrap_or_default();
+    let since = (Utc::now() - chrono::TimeDelta::days(days as i64)).date_naive();
+    let mut costs = ledger.since(since)?;
+    if let Some(context) = context {
+        costs.retain(|cost| cost.context == context.to_string());
+    }
+    let (mut tokens, mut fee_lamports, mut refunded_lamports, mut usd) = (0, 0, 0, 0.0);
+    for cost in &costs {
+        let providers: Vec<String> = cost
+            .tokens
+            .iter()
+            .map(|(provider, tokens)| {
+                format!("{} {}+{}", provider, tokens.prompt, tokens.completion)
+            })
+            .collect();
+        let cost_usd = cost.usd(&usd_per_1k_tokens);
+        println!(
+            "{}\t{}\t{} interaction(s)\t{} token(s) ({})\t{} SOL fees{}{}",
+            cost.day,
+            cost.context,
+            cost.interactions,
+            cost.total_tokens(),
+            providers.join(", "),
+            lamports_to_sol(cost.fee_lamports),
+            if cost.refunds > 0 {
+                format!(
+                    "\t{} refund(s) of {} SOL",
+                    cost.refunds,
+                    lamports_to_sol(cost.refunded_lamports)
+                )
             } else {
-                return Err("No valid API key found. Please set GEMINI_API_KEY or OPENAI_API_KEY in .env file".into());
+                String::new()
+            },
+            if usd_per_1k_tokens.is_empty() {
+                String::new()
+            } else {
+                format!("\t${:.4}", cost_usd)
             }
+        );
+        tokens += cost.total_tokens();
+        fee_lamports += cost.fee_lamports;
+        refunded_lamports += cost.refunded_lamports;
+        usd += cost_usd;
+    }
+    println!(
+        "{} token(s), {} SOL of fees and {} SOL refunded in the last {} day(s){}",
+        tokens,
+        lamports_to_sol(fee_lamports),
+        lamports_to_sol(refunded_lamports),
+        days,
+        if usd_per_1k_tokens.is_empty() {
+            String::new()
         } else {
-            return Err("No valid API key found. Please set GEMINI_API_KEY or OPENAI_API_KEY in .env file".into());
-        }
-    } else if let Ok(openai_key) = env::var("OPENAI_API_KEY") {
-        if !openai_key.is_empty() {
-            println!("🤖 Using OpenAI (gpt-4o)");
-            LLMProvider::OpenAI(ChatGPT::new_with_config(
-                openai_key.as_str(),
-                ModelConfiguration {
-                    engine: chatgpt::config::ChatGPTEngine::Custom("gpt-4o"),
-                    presence_penalty: 0.3,
-                    frequency_penalty: 0.3,
-                    max_tokens: Some(100),
-                    ..Default::default()
-                },
-            )?)
-        } else {
-            return Err("No valid API key found. Please set GEMINI_API_KEY or OPENAI_API_KEY in .env file".into());
+            format!(", ${:.2} of tokens", usd)
         }
-    } else {
-        return Err("No valid API key found. Please set GEMINI_API_KEY or OPENAI_API_KEY in .env file".into());
+    );
+    Ok(())
+}
+
+async fn suggest_prompts(days: u64, output: Option<PathBuf>) -> Result<(), OracleError> {
+    let output = match output {
+        Some(output) => output,
+        None => OracleConfig::load()?
+            .prompt_suggestions
+            .dir
+            .map(PathBuf::from)
+            .ok_or("Set `prompt_suggestions.dir` (PROMPT_SUGGESTIONS_DIR) or pass --output")?,
     };
+    let Setup { oracle, .. } = build_oracle()?;
+    let since = Utc::now() - chrono::TimeDelta::days(days as i64);
+    let suggestions = tuning::suggest(
+        &oracle,
+        since.timestamp_millis().max(0) as u64,
+        oracle.config.prompt_suggestions.min_failures,
+    )
+    .await?;
+    tuning::write(&suggestions, &output)?;
+    for suggestion in &suggestions {
+        println!(
+            "{}
+{}
+",
+            suggestion.rationale, suggestion.diff
+        );
+    }
+    println!(
+        "{} suggestion(s) written to {}",
+        suggestions.len(),
+        output.display()
+    );
+    Ok(())
+}
 
-    let payer = Keypair::from_base58_string(&identity);
-    let identity_pda = Pubkey::find_program_address(&[b"identity"], &solana_gpt_oracle::ID).0;
-    Ok((rpc_url, websocket_url, llm_provider, payer, identity_pda))
+#[cfg(feature = "dashboard")]
+async fn top(addr: Option<String>) -> Result<(), OracleError> {
+    let config = OracleConfig::load()?;
+    let addr = addr
+        .or(config.metrics_addr)
+        .ok_or("Set metrics.addr (METRICS_ADDR) or pass --addr")?;
+    llm_oracle::monitor::top::run(
+        llm_oracle::monitor::top::status_url(&addr),
+        config.rpc_url,
+        config.payer.pubkey(),
+    )
+    .await
+}
+
+fn keygen(outfile: Option<PathBuf>) -> Result<(), OracleError> {
+    let keypair = Keypair::new();
+    match outfile {
+        Some(path) => {
+            write_keypair_file(&keypair, &path)
+                .map_err(|e| format!("Can't write {}: {}", path.display(), e))?;
+            println!("Wrote {} to {}", keypair.pubkey(), path.display());
+        }
+        None => {
+            println!("# {}", keypair.pubkey());
+            println!("IDENTITY={}", keypair.to_base58_string());
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), OracleError> {
+    dotenv::dotenv().ok(); // Load .env file
+    let cli = Cli::parse();
+    logging::init()?;
+
+    // Every log line carries the deployment name
+    let span = info_span!(
+        "oracle",
+        deployment = deployment_name().unwrap_or("default")
+    );
+    async {
+        match cli.command.unwrap_or(Command::Run) {
+            Command::Run => run().await,
+            Command::CheckConfig => check_config(),
+            Command::Config(command) => config::cli::run(command),
+            Command::ListPending => list_pending().await,
+            Command::RpcCheck { rpc, ws } => rpc_check(rpc, ws).await,
+            Command::Replay { interaction } => replay(interaction).await,
+            Command::Keygen { outfile } => keygen(outfile),
+            Command::Admin(command) => admin::run(command).await,
+            Command::Context(command) => context_import::run(command).await,
+            Command::Blink(args) => blinks::run(args).await,
+            #[cfg(feature = "rag")]
+            Command::Kb(command) => knowledge::cli::run(command).await,
+            Command::Audit(command) => audit::run(command, &OracleConfig::load()?.audit),
+            Command::Dlq(command) => dead_letters(command).await,
+            Command::Review(command) => {
+                review::cli::run(command, &OracleConfig::load()?.review).await
+            }
+            Command::Digest { send } => print_digest(send).await,
+            Command::Ratings { days, context } => print_ratings(days, context),
+            Command::Purge { interaction } => purge(interaction),
+            Command::Refunds => refund_overdue().await,
+            Command::Costs { days, context } => print_costs(days, context),
+            Command::SuggestPrompts { days, output } => suggest_prompts(days, output).await,
+            #[cfg(feature = "dashboard")]
+            Command::Top { addr } => top(addr).await,
+        }
+    }
+    .instrument(span)
+    .await
 }
@@ -0,0 +1,246 @@
+use crate::config::deployment_path;
+use crate::metrics::METRICS;
+use crate::providers::{ChatMessage, Role};
+use crate::OracleError;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::time::{Duration, SystemTime};
+use tracing::{debug, info};
+
+mod sled_store;
+
+pub use sled_store::SledMemory;
+
+/// Conversation history older than this is dropped by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(1200);
+pub const DEFAULT_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+/// Bounds of the conversation history kept by a [`MemoryStore`]
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimits {
+    /// Messages kept per interaction
+    pub max_history: usize,
+    /// Messages older than this expire
+    pub ttl: Duration,
+    /// Total message bytes kept; the least recently used conversations are evicted beyond it
+    pub max_bytes: usize,
+}
+
+/// Storage for the conversation history of each interaction account
+pub trait MemoryStore: Send {
+    fn add_interaction(
+        &mut self,
+        pubkey: Pubkey,
+        text: String,
+        role: Role,
+    ) -> Result<(), OracleError>;
+
+    fn get_history(&self, pubkey: &Pubkey) -> Result<Option<Vec<ChatMessage>>, OracleError>;
+
+    /// Forget a conversation that has ended
+    fn close(&mut self, pubkey: &Pubkey) -> Result<(), OracleError>;
+
+    fn clean_old_entries(&mut self) -> Result<(), OracleError>;
+
+    /// State of the game played in an interaction, see [`crate::game`]
+    fn game_state(&self, pubkey: &Pubkey) -> Result<Option<String>, OracleError>;
+
+    /// Record the state of the game played in an interaction, or clear it with `None`. Unlike
+    /// the conversation, it doesn't expire.
+    fn set_game_state(&mut self, pubkey: &Pubkey, state: Option<&str>) -> Result<(), OracleError>;
+
+    /// Persist buffered writes, if the backend has any
+    fn flush(&mut self) -> Result<(), OracleError> {
+        Ok(())
+    }
+}
+
+/// Create the memory store selected by `MEMORY_BACKEND` (`memory` or `sled`)
+pub fn from_env(limits: MemoryLimits) -> Result<Box<dyn MemoryStore>, OracleError> {
+    match env::var("MEMORY_BACKEND").as_deref() {
+        Err(_) | Ok("memory") => Ok(Box::new(InteractionMemory::new(limits))),
+        Ok("sled") => {
+            let path =
+                deployment_path(&env::var("MEMORY_PATH").unwrap_or("./oracle-memory".to_string()));
+            info!(%path, "Memory: sled");
+            Ok(Box::new(SledMemory::open(&path, limits)?))
+        }
+        Ok(other) => Err(format!(
+            "Invalid MEMORY_BACKEND {:?}, expected \"memory\" or \"sled\"",
+            other
+        )
+        .into()),
+    }
+}
+
+struct TimedChatMessage {
+    message: ChatMessage,
+    timestamp: SystemTime,
+}
+
+#[derive(Default)]
+struct Conversation {
+    messages: VecDeque<TimedChatMessage>,
+    bytes: usize,
+    last_used: Option<SystemTime>,
+}
+
+impl Conversation {
+    fn pop_oldest(&mut self) -> usize {
+        let bytes = self
+            .messages
+            .pop_front()
+            .map_or(0, |oldest| oldest.message.content.len());
+        self.bytes -= bytes;
+        bytes
+    }
+}
+
+pub struct InteractionMemory {
+    memory: HashMap<Pubkey, Conversation>,
+    game_states: HashMap<Pubkey, String>,
+    limits: MemoryLimits,
+    total_bytes: usize,
+}
+
+impl InteractionMemory {
+    pub fn new(limits: MemoryLimits) -> Self {
+        InteractionMemory {
+            memory: HashMap::new(),
+            game_states: HashMap::new(),
+            limits,
+            total_bytes: 0,
+        }
+    }
+
+    pub fn add_interaction(&mut self, pubkey: Pubkey, text: String, role: Role) {
+        let now = SystemTime::now();
+        let bytes = text.len();
+        let conversation = self.memory.entry(pubkey).or_default();
+        conversation.messages.push_back(TimedChatMessage {
+            message: ChatMessage::new(role, text),
+            timestamp: now,
+        });
+        conversation.bytes += bytes;
+        conversation.last_used = Some(now);
+        self.total_bytes += bytes;
+
+        while conversation.messages.len() > self.limits.max_history {
+            self.total_bytes -= conversation.pop_oldest(); // Remove the oldest entry
+        }
+        self.evict_least_recently_used(&pubkey);
+        if rand::random::<f64>() < 0.01 {
+            self.clean_old_entries();
+        }
+    }
+
+    /// Evict whole conversations, least recently used first, until under the byte capacity.
+    /// The conversation in use is only trimmed, keeping its latest message.
+    fn evict_least_recently_used(&mut self, in_use: &Pubkey) {
+        while self.total_bytes > self.limits.max_bytes {
+            let oldest = self
+                .memory
+                .iter()
+                .filter(|(pubkey, _)| *pubkey != in_use)
+                .min_by_key(|(_, conversation)| conversation.last_used)
+                .map(|(pubkey, _)| *pubkey);
+            match oldest {
+                Some(oldest) => {
+                    if let Some(conversation) = self.memory.remove(&oldest) {
+                        self.total_bytes -= conversation.bytes;
+                    }
+                }
+                None => {
+                    let Some(conversation) = self.memory.get_mut(in_use) else {
+                        return;
+                    };
+                    if conversation.messages.len() <= 1 {
+                        return;
+                    }
+                    self.total_bytes -= conversation.pop_oldest();
+                }
+            }
+            METRICS
+                .memory_evictions
+                .with_label_values(&["capacity"])
+                .inc();
+        }
+    }
+
+    pub fn get_history(&self, pubkey: &Pubkey) -> Option<Vec<ChatMessage>> {
+        self.memory.get(pubkey).map(|conversation| {
+            conversation
+                .messages
+                .iter()
+                .map(|timed_msg| timed_msg.message.clone())
+                .collect()
+        })
+    }
+
+    pub fn close(&mut self, pubkey: &Pubkey) {
+        if let Some(conversation) = self.memory.remove(pubkey) {
+            self.total_bytes -= conversation.bytes;
+        }
+    }
+
+    pub fn clean_old_entries(&mut self) {
+        debug!("Cleaning old entries");
+        let ttl = self.limits.ttl;
+        let now = SystemTime::now();
+        let mut expired = 0;
+
+        self.memory.retain(|_, conversation| {
+            while conversation.messages.front().is_some_and(|oldest| {
+                now.duration_since(oldest.timestamp)
+                    .unwrap_or_else(|_| Duration::new(0, 0))
+                    >= ttl
+            }) {
+                expired += conversation.pop_oldest();
+            }
+            !conversation.messages.is_empty()
+        });
+        if expired > 0 {
+            self.total_bytes -= expired;
+            METRICS.memory_evictions.with_label_values(&["ttl"]).inc();
+        }
+    }
+}
+
+impl MemoryStore for InteractionMemory {
+    fn add_interaction(
+        &mut self,
+        pubkey: Pubkey,
+        text: String,
+        role: Role,
+    ) -> Result<(), OracleError> {
+        InteractionMemory::add_interaction(self, pubkey, text, role);
+        Ok(())
+    }
+
+    fn get_history(&self, pubkey: &Pubkey) -> Result<Option<Vec<ChatMessage>>, OracleError> {
+        Ok(InteractionMemory::get_history(self, pubkey))
+    }
+
+    fn close(&mut self, pubkey: &Pubkey) -> Result<(), OracleError> {
+        InteractionMemory::close(self, pubkey);
+        Ok(())
+    }
+
+    fn clean_old_entries(&mut self) -> Result<(), OracleError> {
+        InteractionMemory::clean_old_entries(self);
+        Ok(())
+    }
+
+    fn game_state(&self, pubkey: &Pubkey) -> Result<Option<String>, OracleError> {
+        Ok(self.game_states.get(pubkey).cloned())
+    }
+
+    fn set_game_state(&mut self, pubkey: &Pubkey, state: Option<&str>) -> Result<(), OracleError> {
+        match state {
+            Some(state) => self.game_states.insert(*pubkey, state.to_string()),
+            None => self.game_states.remove(pubkey),
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use super::{MemoryLimits, MemoryStore};
+use crate::metrics::METRICS;
+use crate::providers::{ChatMessage, Role};
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    role: String,
+    content: String,
+    timestamp: u64,
+}
+
+impl StoredMessage {
+    fn to_chat_message(&self) -> ChatMessage {
+        let role = match self.role.as_str() {
+            "system" => Role::System,
+            "assistant" => Role::Assistant,
+            "function" => Role::Function,
+            _ => Role::User,
+        };
+        ChatMessage::new(role, self.content.clone())
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::Assistant => "assistant",
+        Role::User => "user",
+        Role::Function => "function",
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Conversation history persisted in an embedded sled database, so a restart doesn't wipe it.
+/// Each interaction pubkey maps to its JSON encoded history; the byte capacity applies to the
+/// encoded size and is enforced when old entries are cleaned. Game states are kept in a tree of
+/// their own, out of the way of expiry and eviction.
+pub struct SledMemory {
+    db: sled::Db,
+    game_states: sled::Tree,
+    limits: MemoryLimits,
+}
+
+impl SledMemory {
+    pub fn open(path: &str, limits: MemoryLimits) -> Result<Self, OracleError> {
+        let db = sled::open(path)?;
+        Ok(Self {
+            game_states: db.open_tree("game_states")?,
+            db,
+            limits,
+        })
+    }
+
+    fn load(&self, pubkey: &Pubkey) -> Result<Vec<StoredMessage>, OracleError> {
+        match self.db.get(pubkey.as_ref())? {
+            Some(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl MemoryStore for SledMemory {
+    fn add_interaction(
+        &mut self,
+        pubkey: Pubkey,
+        text: String,
+        role: Role,
+    ) -> Result<(), OracleError> {
+        let mut history = self.load(&pubkey)?;
+        history.push(StoredMessage {
+            role: role_name(role).to_string(),
+            content: text,
+            timestamp: now(),
+        });
+        if history.len() > self.limits.max_history {
+            history.drain(..history.len() - self.limits.max_history);
+        }
+        self.db
+            .insert(pubkey.as_ref(), serde_json::to_vec(&history)?)?;
+
+        if rand::random::<f64>() < 0.01 {
+            self.clean_old_entries()?;
+        }
+        Ok(())
+    }
+
+    fn get_history(&self, pubkey: &Pubkey) -> Result<Option<Vec<ChatMessage>>, OracleError> {
+        let history = self.load(pubkey)?;
+        if history.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(
+            history.iter().map(StoredMessage::to_chat_message).collect(),
+        ))
+    }
+
+    fn close(&mut self, pubkey: &Pubkey) -> Result<(), OracleError> {
+        self.db.remove(pubkey.as_ref())?;
+        Ok(())
+    }
+
+    fn clean_old_entries(&mut self) -> Result<(), OracleError> {
+        debug!("Cleaning old entries");
+        let cutoff = now().saturating_sub(self.limits.ttl.as_secs());
+        // (last used, size, key) of the conversations left after expiry
+        let mut conversations = Vec::new();
+        for entry in self.db.iter() {
+            let (key, bytes) = entry?;
+            let mut history: Vec<StoredMessage> = serde_json::from_slice(&bytes)?;
+            let len = history.len();
+            history.retain(|message| message.timestamp >= cutoff);
+            if history.len() != len {
+                METRICS.memory_evictions.with_label_values(&["ttl"]).inc();
+            }
+            if history.is_empty() {
+                self.db.remove(key)?;
+                continue;
+            }
+            let mut size = bytes.len();
+            if history.len() != len {
+                let encoded = serde_json::to_vec(&history)?;
+                size = encoded.len();
+                self.db.insert(&key, encoded)?;
+            }
+            let last_used = history.last().map_or(0, |message| message.timestamp);
+            conversations.push((last_used, size, key));
+        }
+
+        // Least recently used conversations go first once over capacity
+        let mut total: usize = conversations.iter().map(|(_, size, _)| size).sum();
+        conversations.sort_by_key(|(last_used, _, _)| *last_used);
+        for (_, size, key) in conversations {
+            if total <= self.limits.max_bytes {
+                break;
+            }
+            self.db.remove(key)?;
+            total -= size;
+            METRICS
+                .memory_evictions
+                .with_label_values(&["capacity"])
+                .inc();
+        }
+        Ok(())
+    }
+
+    fn game_state(&self, pubkey: &Pubkey) -> Result<Option<String>, OracleError> {
+        match self.game_states.get(pubkey.as_ref())? {
+            Some(bytes) => Ok(Some(String::from_utf8(bytes.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_game_state(&mut self, pubkey: &Pubkey, state: Option<&str>) -> Result<(), OracleError> {
+        match state {
+            Some(state) => self.game_states.insert(pubkey.as_ref(), state.as_bytes())?,
+            None => self.game_states.remove(pubkey.as_ref())?,
+        };
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), OracleError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for SledMemory {
+    fn drop(&mut self) {
+        let _ = self.db.flush();
+    }
+}
@@ -0,0 +1,939 @@
+//! Prometheus metrics.
+//!
+//! Metrics are always recorded; they are only exported when `METRICS_ADDR` is set, by a minimal
+//! HTTP server answering `GET /metrics` in the Prometheus text format. Without the `metrics`
+//! feature, `prometheus` isn't built in: `/metrics` is not served, and only the unlabelled
+//! counters and gauges read by the digest and `llm_oracle top` are kept. The same server answers
+//! `GET /status` with the [`crate::monitor`] snapshot polled by `llm_oracle top`, and the
+//! `GET /healthz` and `GET /readyz` probes of [`crate::health`], the Solana Actions of
+//! [`crate::blinks`], the GraphQL API of [`crate::graphql`] and the admin API of
+//! [`crate::admin_api`].
+
+use crate::admin_api::{self, ADMIN_PATH};
+use crate::blinks;
+use crate::config::deployment_name;
+#[cfg(feature = "graphql")]
+use crate::graphql::{self, GRAPHQL_PATH};
+use crate::health::{Report, HEALTH};
+use crate::monitor::MONITOR;
+use crate::oracle::Oracle;
+use crate::OracleError;
+#[cfg(not(feature = "metrics"))]
+use noop as prometheus;
+#[cfg(feature = "metrics")]
+use prometheus::{Encoder, TextEncoder};
+use prometheus::{
+    GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info};
+
+#[cfg(not(feature = "metrics"))]
+pub mod noop;
+
+pub struct Metrics {
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    registry: Registry,
+    /// Interactions handled by the worker pool, by `result` (`ok` or `error`)
+    pub interactions_processed: IntCounterVec,
+    /// Latency of LLM calls, by `provider`
+    pub llm_latency: HistogramVec,
+    /// Failed LLM calls that were retried or given up on, by `provider`
+    pub llm_retries: IntCounterVec,
+    /// Failed LLM calls by `provider` and `class` (`rate_limited`, `server`, `context_length`,
+    /// `bad_request` or `other`)
+    pub llm_errors: IntCounterVec,
+    /// Failed calls to a provider of the failover chain, by `provider`
+    pub llm_failovers: IntCounterVec,
+    /// Interactions over a rate limit or budget, by `limit` (`context`, `user`, `budget` or
+    /// `flood`) and `action` (`defer`, `respond` or `skip`)
+    pub interactions_limited: IntCounterVec,
+    /// Tokens spent today by providers with a daily budget, by `provider`
+    pub budget_tokens_spent: IntGaugeVec,
+    /// Hallucination guard results, by `outcome` (`supported`, `regenerated`, `hedged` or `error`)
+    pub verifications: IntCounterVec,
+    pub transaction_send_attempts: IntCounter,
+    /// Failed callback attempts, by `stage` (`blockhash`, `simulation`, `expired` or `failed`)
+    pub transaction_failures: IntCounterVec,
+    /// Time from sending a callback attempt to its outcome, by `outcome` (`landed`, `failed` or
+    /// `expired`)
+    pub callback_attempt_seconds: HistogramVec,
+    /// Callback transactions sent again while waiting for them to land
+    pub transaction_rebroadcasts: IntCounter,
+    /// Base and priority fees of the landed callback transactions
+    pub fee_lamports: IntCounter,
+    /// Tokens of the LLM calls, by `provider` and `kind` (`prompt` or `completion`)
+    pub llm_tokens: IntCounterVec,
+    /// LLM calls whose tokens were reported by the provider or estimated, by `provider` and
+    /// `source` (`reported` or `estimated`)
+    pub llm_token_usage_source: IntCounterVec,
+    /// Tokens counted with the tokenizer over those reported, by `provider` and `kind`
+    pub llm_token_estimate_ratio: HistogramVec,
+    /// Tokens of the LLM calls answering the interactions of a context, by `context`
+    pub context_tokens: IntCounterVec,
+    /// Callback fees of the interactions of a context, by `context`
+    pub context_fee_lamports: IntCounterVec,
+    /// Jito tips of the landed callback transactions
+    pub jito_tip_lamports: IntCounter,
+    /// Interaction transactions built for wallets submitting through a Blink
+    pub blink_transactions: IntCounter,
+    /// Response cache lookups, by `result` (`hit` or `miss`)
+    pub response_cache: IntCounterVec,
+    /// Requests to the sponsored ingestion endpoint, by API `key` name and `result` (`sent`,
+    /// `quota` or `error`)
+    pub sponsored_interactions: IntCounterVec,
+    /// Clients connected to the push server of [`crate::push`]
+    pub push_subscribers: IntGauge,
+    /// Confirmed answers sent to push subscribers
+    pub answers_pushed: IntCounter,
+    /// Deliveries to context webhooks, by `result` (`delivered` or `failed`), see
+    /// [`crate::webhooks`]
+    pub webhooks: IntCounterVec,
+    /// Sealed prompts, by `result` (`opened` or `failed`), see [`crate::encryption`]
+    pub sealed_prompts: IntCounterVec,
+    /// Callbacks carrying part of an answer still being generated, see [`crate::streaming`]
+    pub partial_callbacks: IntCounter,
+    /// Images referenced by interactions, by `outcome` (`attached`, `unsupported` by the model,
+    /// or `failed` to fetch or over the limits), see [`crate::images`]
+    pub image_inputs: IntCounterVec,
+    /// Callback broadcasts submitted to the Jito block engine, by `result` (`sent` or `rejected`,
+    /// then sent through RPC)
+    pub bundles: IntCounterVec,
+    pub compute_units_requested: Histogram,
+    pub websocket_reconnects: IntCounter,
+    /// Program updates of each websocket `source` with two subscriptions, by `result` (`first`,
+    /// `duplicate` or `missed`)
+    pub subscription_updates: IntCounterVec,
+    /// Time by which the second websocket `source` delivered an update after the first
+    pub subscription_lag_seconds: HistogramVec,
+    /// Acknowledgement memos sent for picked up interactions
+    pub acks_sent: IntCounter,
+    /// Unanswered interactions refunded to their creator, see [`crate::refunds`]
+    pub refunds: IntCounter,
+    pub refunded_lamports: IntCounter,
+    /// Moving average of the time to answer an interaction, by `context`
+    pub response_time_estimate: GaugeVec,
+    /// Context accounts found closed, whose interactions are no longer answered
+    pub contexts_deactivated: IntCounter,
+    /// Contexts taken for a flood, throttled or deactivated, see [`crate::flood`]
+    pub contexts_flooded: IntCounter,
+    /// Ledger entries checked against the chain, see [`crate::reconcile`]
+    pub reconciliation_checked: IntCounter,
+    /// Divergences between the ledger and the chain repaired, by `kind` (`vanished`,
+    /// `missed_confirmation`, `answered_elsewhere`, `stalled` or `unconfirmed`)
+    pub reconciliation_drift: IntCounterVec,
+    /// Conversation history dropped, by `reason` (`ttl` or `capacity`)
+    pub memory_evictions: IntCounterVec,
+    /// Answered interactions forgotten by the dedup set, by `store` (`memory` or `disk`)
+    pub dedup_evictions: IntCounterVec,
+    /// Updates of already answered interactions that were skipped
+    pub duplicates_skipped: IntCounter,
+    /// Callbacks waiting in the dead-letter queue
+    pub dlq_entries: IntGauge,
+    /// Dead letter retries, by `result` (`landed`, `failed` or `dropped`)
+    pub dlq_retries: IntCounterVec,
+    /// Interaction status transitions, by the `status` entered
+    pub interaction_transitions: IntCounterVec,
+    /// Interactions resumed at startup, by `outcome`
+    pub recoveries: IntCounterVec,
+    /// Responses changed by the guardrails, by `outcome` (`truncated`, `empty`, `blocklisted`,
+    /// `flagged` or `moderation_error`)
+    pub guardrails: IntCounterVec,
+    /// Answers cut short, by `kind`: `tokens` at the output token limit, `bytes` to fit on-chain
+    pub response_cuts: IntCounterVec,
+    /// Reloads of the prompts, guardrails and generation settings, by `outcome` (`ok` or
+    /// `failed`)
+    pub reloads: IntCounterVec,
+    /// Balance of each fee payer of the rotation, as last fetched less what it paid since, by
+    /// `payer`
+    pub fee_payer_lamports: IntGaugeVec,
+    /// Interactions answered with a refusal code, by `code`
+    pub refusals: IntCounterVec,
+    /// Interaction accounts with updates queued or being processed
+    pub queue_depth: IntGauge,
+    /// Responses of structured output contexts, by `outcome` (`valid`, `corrected` or `invalid`)
+    pub structured_outputs: IntCounterVec,
+    /// Answers of the consensus provider, by `outcome` (`agreed` or `disagreed`)
+    pub consensus: IntCounterVec,
+    /// Notifications delivered, by `channel` and `result` (`sent` or `failed`)
+    pub notifications: IntCounterVec,
+    /// Chain functions called by the model, by `function` and `result` (`ok` or `error`)
+    pub function_calls: IntCounterVec,
+    /// Interactions queued for human review, by `reason` (`sampled` or `flagged`)
+    pub review_queued: IntCounterVec,
+    /// On-chain user ratings of responses, from 1 to 5, by `context`
+    pub response_ratings: HistogramVec,
+    /// Size of the interaction texts picked up, in bytes
+    pub interaction_text_bytes: Histogram,
+    /// Size of the responses sent back, in bytes
+    pub response_bytes: Histogram,
+    /// Callbacks per landed callback transaction
+    pub callback_batch_size: Histogram,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("metric names are unique");
+    metric
+}
+
+impl Metrics {
+    fn new() -> Self {
+        // Every series carries the deployment name, so several oracles can share a scraper
+        let labels = deployment_name()
+            .map(|name| HashMap::from([("deployment".to_string(), name.to_string())]));
+        let registry =
+            Registry::new_custom(Some("oracle".to_string()), labels).expect("valid metrics prefix");
+        Self {
+            interactions_processed: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("interactions_processed_total", "Interactions processed"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            llm_latency: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("llm_latency_seconds", "LLM call latency")
+                        .buckets(vec![0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 15.0, 30.0, 60.0]),
+                    &["provider"],
+                )
+                .unwrap(),
+            ),
+            llm_retries: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("llm_retries_total", "Failed LLM calls"),
+                    &["provider"],
+                )
+                .unwrap(),
+            ),
+            llm_errors: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("llm_errors_total", "Failed LLM calls by error class"),
+                    &["provider", "class"],
+                )
+                .unwrap(),
+            ),
+            llm_failovers: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "llm_failovers_total",
+                        "LLM calls failed over to the next provider",
+                    ),
+                    &["provider"],
+                )
+                .unwrap(),
+            ),
+            interactions_limited: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "interactions_limited_total",
+                        "Interactions over a rate limit or budget",
+                    ),
+                    &["limit", "action"],
+                )
+                .unwrap(),
+            ),
+            budget_tokens_spent: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "budget_tokens_spent",
+                        "Tokens spent today by providers with a daily budget",
+                    ),
+                    &["provider"],
+                )
+                .unwrap(),
+            ),
+            verifications: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "verifications_total",
+                        "Answers checked against their sources",
+                    ),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            transaction_send_attempts: register(
+                &registry,
+                IntCounter::new(
+                    "transaction_send_attempts_total",
+                    "Callback transaction attempts",
+                )
+                .unwrap(),
+            ),
+            transaction_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "transaction_failures_total",
+                        "Failed callback transaction attempts",
+                    ),
+                    &["stage"],
+                )
+                .unwrap(),
+            ),
+            callback_attempt_seconds: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "callback_attempt_seconds",
+                        "Time from sending a callback attempt to its outcome",
+                    )
+                    .buckets(vec![
+                        0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 90.0, 180.0,
+                    ]),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            transaction_rebroadcasts: register(
+                &registry,
+                IntCounter::new(
+                    "transaction_rebroadcasts_total",
+                    "Callback transactions sent again while waiting for them to land",
+                )
+                .unwrap(),
+            ),
+            fee_lamports: register(
+                &registry,
+                IntCounter::new("fee_lamports_total", "Lamports spent on callback fees").unwrap(),
+            ),
+            llm_tokens: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("llm_tokens_total", "Tokens of the LLM calls"),
+                    &["provider", "kind"],
+                )
+                .unwrap(),
+            ),
+            llm_token_usage_source: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "llm_token_usage_source_total",
+                        "LLM calls by where their token counts come from",
+                    ),
+                    &["provider", "source"],
+                )
+                .unwrap(),
+            ),
+            llm_token_estimate_ratio: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "llm_token_estimate_ratio",
+                        "Tokens counted with the tokenizer over those reported by the provider",
+                    )
+                    .buckets(vec![0.5, 0.75, 0.9, 0.95, 1.0, 1.05, 1.1, 1.25, 1.5, 2.0]),
+                    &["provider", "kind"],
+                )
+                .unwrap(),
+            ),
+            context_tokens: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "context_tokens_total",
+                        "Tokens of the LLM calls answering the interactions of a context",
+                    ),
+                    &["context"],
+                )
+                .unwrap(),
+            ),
+            context_fee_lamports: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "context_fee_lamports_total",
+                        "Callback fees of the interactions of a context",
+                    ),
+                    &["context"],
+                )
+                .unwrap(),
+            ),
+            blink_transactions: register(
+                &registry,
+                IntCounter::new(
+                    "blink_transactions_total",
+                    "Interaction transactions built for Blink submissions",
+                )
+                .unwrap(),
+            ),
+            response_cache: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("response_cache_total", "Response cache lookups"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            sponsored_interactions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "sponsored_interactions_total",
+                        "Requests to the sponsored ingestion endpoint",
+                    ),
+                    &["key", "result"],
+                )
+                .unwrap(),
+            ),
+            push_subscribers: register(
+                &registry,
+                IntGauge::new("push_subscribers", "Clients connected to the push server").unwrap(),
+            ),
+            answers_pushed: register(
+                &registry,
+                IntCounter::new(
+                    "answers_pushed_total",
+                    "Confirmed answers sent to push subscribers",
+                )
+                .unwrap(),
+            ),
+            webhooks: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("webhooks_total", "Deliveries to context webhooks"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            sealed_prompts: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("sealed_prompts_total", "Sealed prompts received"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            partial_callbacks: register(
+                &registry,
+                IntCounter::new(
+                    "partial_callbacks_total",
+                    "Callbacks sent while their answer was being generated",
+                )
+                .unwrap(),
+            ),
+            image_inputs: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("image_inputs_total", "Images referenced by interactions"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            jito_tip_lamports: register(
+                &registry,
+                IntCounter::new(
+                    "jito_tip_lamports_total",
+                    "Lamports tipped to Jito by landed callbacks",
+                )
+                .unwrap(),
+            ),
+            bundles: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "bundles_total",
+                        "Callback broadcasts submitted to the Jito block engine",
+                    ),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            compute_units_requested: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "compute_units_requested",
+                        "Compute unit limit of the landed callback transactions",
+                    )
+                    .buckets(prometheus::exponential_buckets(10_000.0, 2.0, 8).unwrap()),
+                )
+                .unwrap(),
+            ),
+            websocket_reconnects: register(
+                &registry,
+                IntCounter::new(
+                    "websocket_reconnects_total",
+                    "Program subscription reconnects",
+                )
+                .unwrap(),
+            ),
+            subscription_updates: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "subscription_updates_total",
+                        "Program updates of each websocket source",
+                    ),
+                    &["source", "result"],
+                )
+                .unwrap(),
+            ),
+            subscription_lag_seconds: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "subscription_lag_seconds",
+                        "Time by which the second websocket source delivered an update",
+                    )
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+                    &["source"],
+                )
+                .unwrap(),
+            ),
+            acks_sent: register(
+                &registry,
+                IntCounter::new("acks_sent_total", "Acknowledgement transactions sent").unwrap(),
+            ),
+            refunds: register(
+                &registry,
+                IntCounter::new("refunds_total", "Unanswered interactions refunded").unwrap(),
+            ),
+            refunded_lamports: register(
+                &registry,
+                IntCounter::new(
+                    "refunded_lamports_total",
+                    "Lamports refunded to the creators of unanswered interactions",
+                )
+                .unwrap(),
+            ),
+            response_time_estimate: register(
+                &registry,
+                GaugeVec::new(
+                    Opts::new(
+                        "response_time_estimate_seconds",
+                        "Expected time to answer an interaction",
+                    ),
+                    &["context"],
+                )
+                .unwrap(),
+            ),
+            memory_evictions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("memory_evictions_total", "Conversation history evictions"),
+                    &["reason"],
+                )
+                .unwrap(),
+            ),
+            contexts_deactivated: register(
+                &registry,
+                IntCounter::new(
+                    "contexts_deactivated_total",
+                    "Context accounts found closed",
+                )
+                .unwrap(),
+            ),
+            contexts_flooded: register(
+                &registry,
+                IntCounter::new("contexts_flooded_total", "Contexts taken for a flood").unwrap(),
+            ),
+            reconciliation_checked: register(
+                &registry,
+                IntCounter::new(
+                    "reconciliation_checked_total",
+                    "Ledger entries checked against the chain",
+                )
+                .unwrap(),
+            ),
+            reconciliation_drift: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "reconciliation_drift_total",
+                        "Divergences between the ledger and the chain repaired",
+                    ),
+                    &["kind"],
+                )
+                .unwrap(),
+            ),
+            dedup_evictions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("dedup_evictions_total", "Dedup set evictions"),
+                    &["store"],
+                )
+                .unwrap(),
+            ),
+            duplicates_skipped: register(
+                &registry,
+                IntCounter::new(
+                    "duplicates_skipped_total",
+                    "Updates of answered interactions skipped",
+                )
+                .unwrap(),
+            ),
+            dlq_entries: register(
+                &registry,
+                IntGauge::new("dlq_entries", "Callbacks in the dead-letter queue").unwrap(),
+            ),
+            dlq_retries: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("dlq_retries_total", "Dead letter retries"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            interaction_transitions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "interaction_transitions_total",
+                        "Interaction status transitions",
+                    ),
+                    &["status"],
+                )
+                .unwrap(),
+            ),
+            recoveries: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("recoveries_total", "Interactions resumed at startup"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            guardrails: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("guardrails_total", "Responses changed by the guardrails"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            response_cuts: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("response_cuts_total", "Answers cut short"),
+                    &["kind"],
+                )
+                .unwrap(),
+            ),
+            reloads: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "reloads_total",
+                        "Reloads of the prompts, guardrails and generation settings",
+                    ),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            fee_payer_lamports: register(
+                &registry,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "fee_payer_lamports",
+                        "Balance of each fee payer of the rotation",
+                    ),
+                    &["payer"],
+                )
+                .unwrap(),
+            ),
+            refusals: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "refusals_total",
+                        "Interactions answered with a refusal code",
+                    ),
+                    &["code"],
+                )
+                .unwrap(),
+            ),
+            queue_depth: register(
+                &registry,
+                IntGauge::new(
+                    "queue_depth",
+                    "Interaction accounts with queued or in-flight updates",
+                )
+                .unwrap(),
+            ),
+            structured_outputs: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "structured_outputs_total",
+                        "Responses of structured output contexts",
+                    ),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            consensus: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("consensus_total", "Answers of the consensus provider"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            notifications: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("notifications_total", "Notifications delivered"),
+                    &["channel", "result"],
+                )
+                .unwrap(),
+            ),
+            function_calls: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "function_calls_total",
+                        "Chain functions called by the model",
+                    ),
+                    &["function", "result"],
+                )
+                .unwrap(),
+            ),
+            review_queued: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "review_queued_total",
+                        "Interactions queued for human review",
+                    ),
+                    &["reason"],
+                )
+                .unwrap(),
+            ),
+            response_ratings: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("response_ratings", "User ratings of responses")
+                        .buckets(vec![1.0, 2.0, 3.0, 4.0, 5.0]),
+                    &["context"],
+                )
+                .unwrap(),
+            ),
+            interaction_text_bytes: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "interaction_text_bytes",
+                        "Size of the interaction texts picked up",
+                    )
+                    .buckets(prometheus::exponential_buckets(16.0, 2.0, 10).unwrap()),
+                )
+                .unwrap(),
+            ),
+            response_bytes: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new("response_bytes", "Size of the responses sent back")
+                        .buckets(prometheus::exponential_buckets(16.0, 2.0, 10).unwrap()),
+                )
+                .unwrap(),
+            ),
+            callback_batch_size: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "callback_batch_size",
+                        "Callbacks per landed callback transaction",
+                    )
+                    .buckets(vec![1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0]),
+                )
+                .unwrap(),
+            ),
+            registry,
+        }
+    }
+
+    /// All metrics in the Prometheus text format
+    #[cfg(feature = "metrics")]
+    pub fn render(&self) -> Result<String, OracleError> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// A probe report, with a 503 when the check fails
+fn probe_response(report: Report) -> String {
+    let status = if report.ok {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    };
+    match serde_json::to_string(&report) {
+        Ok(body) => format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        ),
+        Err(e) => {
+            error!(error = ?e, "Failed to render the probe report");
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        }
+    }
+}
+
+/// Largest request read, headers and body
+const MAX_REQUEST_BYTES: usize = 16 * 1024;
+/// Longest a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read the head of a request and its body, as long as its `Content-Length`; `None` for a request
+/// over [`MAX_REQUEST_BYTES`] or not sent within [`REQUEST_TIMEOUT`]
+pub async fn read_request(stream: &mut TcpStream) -> Option<(String, Vec<u8>)> {
+    tokio::time::timeout(REQUEST_TIMEOUT, read_bounded(stream))
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn read_bounded(stream: &mut TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buffer).await.ok()?;
+        request.extend_from_slice(&buffer[..read]);
+        let end = request.windows(4).position(|window| window == b"\r\n\r\n");
+        if let Some(end) = end {
+            let head = String::from_utf8_lossy(&request[..end]).to_string();
+            let length = head
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if length > MAX_REQUEST_BYTES.saturating_sub(end + 4) {
+                return None;
+            }
+            if request.len() >= end + 4 + length || read == 0 {
+                let body = request[end + 4..].iter().take(length).copied().collect();
+                return Some((head, body));
+            }
+        }
+        if read == 0 || request.len() > MAX_REQUEST_BYTES {
+            return None;
+        }
+    }
+}
+
+/// Whether a request head carries `Authorization: Bearer <token>`. Tokens are compared in
+/// constant time, and an empty token authorizes nothing.
+pub fn authorized(head: &str, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+    head.lines().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("authorization")
+                && value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .is_some_and(|sent| constant_time_eq(sent.trim(), token))
+        })
+    })
+}
+
+/// Compare two strings without returning early on the first differing byte
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Serve `GET /metrics`, `GET /status` for `llm_oracle top`, the `GET /healthz` and
+/// `GET /readyz` probes, the Blinks, `POST /graphql` and `/admin/` on `addr` until the listener
+/// fails
+pub async fn serve(addr: &str, oracle: Arc<Oracle>) -> Result<(), OracleError> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Metrics: http://{}/metrics", listener.local_addr()?);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let oracle = oracle.clone();
+        tokio::spawn(async move {
+            let Some((request, body)) = read_request(&mut stream).await else {
+                return;
+            };
+            let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+                #[cfg(feature = "graphql")]
+                ["POST", path] if path == GRAPHQL_PATH => {
+                    graphql::handle(&oracle, &request, &body).await
+                }
+                [method, path] if path.starts_with(ADMIN_PATH) => {
+                    admin_api::handle(&oracle, method, path, &request).await
+                }
+                [method, path] if blinks::is_route(path) => {
+                    blinks::handle(&oracle, method, path, &body).await
+                }
+                #[cfg(feature = "metrics")]
+                ["GET", "/metrics"] => match METRICS.render() {
+                    Ok(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    Err(e) => {
+                        error!(error = ?e, "Failed to render metrics");
+                        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                    }
+                },
+                ["GET", "/status"] => match serde_json::to_string(&MONITOR.snapshot()) {
+                    Ok(body) => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    ),
+                    Err(e) => {
+                        error!(error = ?e, "Failed to render status");
+                        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                    }
+                },
+                ["GET", "/healthz"] => probe_response(HEALTH.liveness(&oracle)),
+                ["GET", "/readyz"] => probe_response(HEALTH.readiness(&oracle)),
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorizes_the_bearer_token() {
+        let head = "POST /graphql HTTP/1.1\r\nauthorization:  Bearer secret \r\nHost: oracle";
+        assert!(authorized(head, "secret"));
+        assert!(!authorized(head, "secreT"));
+        assert!(!authorized(head, "secrets"));
+        assert!(!authorized("GET /admin/providers HTTP/1.1", "secret"));
+    }
+
+    #[test]
+    fn refuses_an_empty_token() {
+        assert!(!authorized("GET / HTTP/1.1\r\nAuthorization: Bearer ", ""));
+        assert!(!authorized("GET / HTTP/1.1\r\nAuthorization: Bearer", ""));
+    }
+}
@@ -0,0 +1,163 @@
+//! Stand-ins for the `prometheus` types used by [`super::Metrics`], when the `metrics` feature is
+//! off. Counters and gauges keep their value, which the digest and `llm_oracle top` read; labelled
+//! series and histograms are discarded.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub type Result<T> = std::result::Result<T, Infallible>;
+
+pub mod core {
+    pub trait Collector {}
+}
+
+pub fn exponential_buckets(_start: f64, _factor: f64, _count: usize) -> Result<Vec<f64>> {
+    Ok(Vec::new())
+}
+
+pub struct Opts;
+
+impl Opts {
+    pub fn new(_name: impl Into<String>, _help: impl Into<String>) -> Self {
+        Self
+    }
+}
+
+pub struct HistogramOpts;
+
+impl HistogramOpts {
+    pub fn new(_name: impl Into<String>, _help: impl Into<String>) -> Self {
+        Self
+    }
+
+    pub fn buckets(self, _buckets: Vec<f64>) -> Self {
+        self
+    }
+}
+
+pub struct Registry;
+
+impl Registry {
+    pub fn new_custom(
+        _prefix: Option<String>,
+        _labels: Option<HashMap<String, String>>,
+    ) -> Result<Self> {
+        Ok(Self)
+    }
+
+    pub fn register(&self, _collector: Box<dyn core::Collector>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IntCounter(Arc<AtomicU64>);
+
+impl IntCounter {
+    pub fn new(_name: impl Into<String>, _help: impl Into<String>) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IntGauge(Arc<AtomicI64>);
+
+impl IntGauge {
+    pub fn new(_name: impl Into<String>, _help: impl Into<String>) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gauge;
+
+impl Gauge {
+    pub fn set(&self, _value: f64) {}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram;
+
+impl Histogram {
+    pub fn with_opts(_opts: HistogramOpts) -> Result<Self> {
+        Ok(Self)
+    }
+
+    pub fn observe(&self, _value: f64) {}
+}
+
+/// A labelled series, whose children are discarded
+#[derive(Debug, Clone, Default)]
+pub struct MetricVec<T>(PhantomData<T>);
+
+impl<T: Default> MetricVec<T> {
+    pub fn new(_opts: Opts, _labels: &[&str]) -> Result<Self> {
+        Ok(Self(PhantomData))
+    }
+
+    pub fn with_label_values(&self, _values: &[&str]) -> T {
+        T::default()
+    }
+
+    pub fn remove_label_values(&self, _values: &[&str]) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub type IntCounterVec = MetricVec<IntCounter>;
+pub type IntGaugeVec = MetricVec<IntGauge>;
+pub type GaugeVec = MetricVec<Gauge>;
+
+#[derive(Debug, Clone, Default)]
+pub struct HistogramVec;
+
+impl HistogramVec {
+    pub fn new(_opts: HistogramOpts, _labels: &[&str]) -> Result<Self> {
+        Ok(Self)
+    }
+
+    pub fn with_label_values(&self, _values: &[&str]) -> Histogram {
+        Histogram
+    }
+}
+
+impl core::Collector for IntCounter {}
+impl core::Collector for IntGauge {}
+impl core::Collector for Histogram {}
+impl core::Collector for HistogramVec {}
+impl<T> core::Collector for MetricVec<T> {}
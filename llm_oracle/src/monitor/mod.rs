@@ -0,0 +1,112 @@
+//! Live view of a running oracle for operators.
+//!
+//! The oracle keeps the interactions in flight with their stage and its most recent errors in
+//! [`MONITOR`], served as JSON on `GET /status` next to the Prometheus metrics. `llm_oracle top`
+//! ([`top`]) renders it in the terminal.
+
+use crate::metrics::METRICS;
+use crate::status::InteractionStatus;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "dashboard")]
+pub mod top;
+
+/// Errors kept for `GET /status`
+pub const RECENT_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InFlight {
+    pub interaction: String,
+    pub status: InteractionStatus,
+    /// Seconds since the interaction entered its current status
+    pub secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    /// Unix timestamp in seconds
+    pub at: u64,
+    pub interaction: Option<String>,
+    pub message: String,
+}
+
+/// What `GET /status` returns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Interaction accounts with updates queued or being processed
+    pub queue_depth: i64,
+    /// Oldest first
+    pub in_flight: Vec<InFlight>,
+    /// Most recent first
+    pub recent_errors: Vec<ErrorRecord>,
+    /// Fees paid for callback transactions since startup
+    pub fee_lamports: u64,
+    pub dlq_entries: i64,
+}
+
+#[derive(Default)]
+struct State {
+    in_flight: HashMap<Pubkey, (InteractionStatus, Instant)>,
+    errors: VecDeque<ErrorRecord>,
+}
+
+#[derive(Default)]
+pub struct Monitor {
+    state: Mutex<State>,
+}
+
+impl Monitor {
+    /// Follow an interaction status change; settled and failed interactions leave the view
+    pub fn status(&self, interaction: &Pubkey, status: InteractionStatus) {
+        let mut state = self.state.lock().unwrap();
+        if status.is_final() || status == InteractionStatus::Failed {
+            state.in_flight.remove(interaction);
+        } else {
+            state
+                .in_flight
+                .insert(*interaction, (status, Instant::now()));
+        }
+    }
+
+    pub fn error(&self, interaction: Option<&Pubkey>, message: impl ToString) {
+        let mut state = self.state.lock().unwrap();
+        if state.errors.len() == RECENT_ERRORS {
+            state.errors.pop_back();
+        }
+        state.errors.push_front(ErrorRecord {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            interaction: interaction.map(ToString::to_string),
+            message: message.to_string(),
+        });
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.lock().unwrap();
+        let mut in_flight: Vec<InFlight> = state
+            .in_flight
+            .iter()
+            .map(|(interaction, (status, since))| InFlight {
+                interaction: interaction.to_string(),
+                status: *status,
+                secs: since.elapsed().as_secs(),
+            })
+            .collect();
+        in_flight.sort_by_key(|entry| std::cmp::Reverse(entry.secs));
+        Snapshot {
+            queue_depth: METRICS.queue_depth.get(),
+            in_flight,
+            recent_errors: state.errors.iter().cloned().collect(),
+            fee_lamports: METRICS.fee_lamports.get(),
+            dlq_entries: METRICS.dlq_entries.get(),
+        }
+    }
+}
+
+pub static MONITOR: LazyLock<Monitor> = LazyLock::new(Monitor::default);
@@ -0,0 +1,189 @@
+//! `llm_oracle top`: terminal monitor of a running oracle, polling its `GET /status`.
+
+use super::Snapshot;
+use crate::OracleError;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use std::time::{Duration, Instant};
+
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+pub const BALANCE_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// `GET /status` of the oracle serving metrics on `addr`
+pub fn status_url(addr: &str) -> String {
+    // A server listening on every interface is reachable locally
+    let addr = addr.replacen("0.0.0.0", "127.0.0.1", 1);
+    format!("http://{}/status", addr)
+}
+
+#[derive(Default)]
+struct View {
+    snapshot: Option<Result<Snapshot, String>>,
+    balance: Option<Result<u64, String>>,
+}
+
+fn sol(lamports: u64) -> String {
+    format!("{:.6} SOL", lamports as f64 / LAMPORTS_PER_SOL as f64)
+}
+
+fn draw(frame: &mut Frame, view: &View, status_url: &str, payer: &Pubkey) {
+    let [header, in_flight, errors] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Min(5),
+        Constraint::Length(12),
+    ])
+    .areas(frame.area());
+
+    let balance = match &view.balance {
+        Some(Ok(lamports)) => sol(*lamports),
+        Some(Err(e)) => format!("unavailable ({})", e),
+        None => "…".to_string(),
+    };
+    let mut lines = vec![Line::from(format!("payer {}  balance {}", payer, balance))];
+    match &view.snapshot {
+        Some(Ok(snapshot)) => lines.push(Line::from(format!(
+            "queue depth {}  in flight {}  dead letters {}  callback fees {}",
+            snapshot.queue_depth,
+            snapshot.in_flight.len(),
+            snapshot.dlq_entries,
+            sol(snapshot.fee_lamports)
+        ))),
+        Some(Err(e)) => lines.push(Line::from(format!("{} unreachable: {}", status_url, e)).red()),
+        None => lines.push(Line::from(format!("Connecting to {}…", status_url))),
+    }
+    lines.push(Line::from("q to quit").dark_gray());
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" llm_oracle top ")),
+        header,
+    );
+
+    let snapshot = view
+        .snapshot
+        .as_ref()
+        .and_then(|snapshot| snapshot.as_ref().ok());
+    let rows = snapshot
+        .map(|snapshot| {
+            snapshot
+                .in_flight
+                .iter()
+                .map(|entry| {
+                    Row::new(vec![
+                        entry.interaction.clone(),
+                        entry.status.to_string(),
+                        format!("{}s", entry.secs),
+                    ])
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(45),
+                Constraint::Length(12),
+                Constraint::Length(8),
+            ],
+        )
+        .header(Row::new(vec!["Interaction", "Stage", "For"]).bold())
+        .block(Block::bordered().title(" In flight ")),
+        in_flight,
+    );
+
+    let rows = snapshot
+        .map(|snapshot| {
+            snapshot
+                .recent_errors
+                .iter()
+                .map(|error| {
+                    let at = chrono::DateTime::from_timestamp(error.at as i64, 0)
+                        .map(|at| at.format("%H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    Row::new(vec![
+                        at,
+                        error.interaction.clone().unwrap_or_default(),
+                        error.message.clone(),
+                    ])
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Length(45),
+                Constraint::Min(20),
+            ],
+        )
+        .style(Style::default().fg(Color::Red))
+        .block(Block::bordered().title(" Recent errors ")),
+        errors,
+    );
+}
+
+async fn fetch(client: &reqwest::Client, status_url: &str) -> Result<Snapshot, OracleError> {
+    Ok(client
+        .get(status_url)
+        .timeout(REFRESH_INTERVAL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+async fn run_loop(
+    terminal: &mut DefaultTerminal,
+    status_url: &str,
+    rpc_client: &RpcClient,
+    payer: &Pubkey,
+) -> Result<(), OracleError> {
+    let client = reqwest::Client::new();
+    let mut view = View::default();
+    let mut refreshed: Option<Instant> = None;
+    let mut balance_refreshed: Option<Instant> = None;
+    loop {
+        if refreshed.map_or(true, |at| at.elapsed() >= REFRESH_INTERVAL) {
+            view.snapshot = Some(fetch(&client, status_url).await.map_err(|e| e.to_string()));
+            refreshed = Some(Instant::now());
+        }
+        if balance_refreshed.map_or(true, |at| at.elapsed() >= BALANCE_REFRESH_INTERVAL) {
+            view.balance = Some(
+                rpc_client
+                    .get_balance(payer)
+                    .await
+                    .map_err(|e| e.to_string()),
+            );
+            balance_refreshed = Some(Instant::now());
+        }
+        terminal.draw(|frame| draw(frame, &view, status_url, payer))?;
+
+        if event::poll(Duration::from_millis(250))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Run the monitor until `q` is pressed
+pub async fn run(status_url: String, rpc_url: String, payer: Pubkey) -> Result<(), OracleError> {
+    let rpc_client = RpcClient::new(rpc_url);
+    let mut terminal = ratatui::init();
+    let result = run_loop(&mut terminal, &status_url, &rpc_client, &payer).await;
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,196 @@
+//! Program subscriptions on two websocket providers.
+//!
+//! With `solana.secondary_websocket_url` set, the listener subscribes to the program on both
+//! `solana.websocket_url` and the secondary endpoint, so an outage or a stalled stream at one
+//! provider doesn't hide interactions (with `solana.listener = "geyser"`, the Geyser stream takes
+//! the place of `solana.websocket_url`). Updates are deduplicated by account, slot and data (the
+//! websocket API has no write version, and an account written twice in a slot differs in its
+//! data) before they reach the worker pool.
+//!
+//! Each update delivered by one source is expected from the other within
+//! `incidents.subscription_lag_secs`. A source that misses that opens a
+//! [`SUBSCRIPTION_LAGGING`] incident, resolved once it delivers in time again; the
+//! `subscription_lag_seconds` histogram shows by how much the second source trails the first.
+
+use crate::incidents::{self, SUBSCRIPTION_LAGGING};
+use crate::metrics::METRICS;
+use crate::notify::{Event, Severity};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+pub const DEFAULT_SUBSCRIPTION_LAG_SECS: u64 = 10;
+/// Updates remembered to deduplicate the second delivery
+const DEDUP_CAPACITY: usize = 10_000;
+
+/// Account, slot and hash of the data
+type UpdateKey = (Pubkey, u64, u64);
+
+struct Delivery {
+    /// Source that delivered it first
+    first: usize,
+    at: Instant,
+    /// Delivered by the other source too, or already counted as missed by it
+    settled: bool,
+}
+
+#[derive(Default)]
+struct State {
+    deliveries: HashMap<UpdateKey, Delivery>,
+    order: VecDeque<UpdateKey>,
+    /// Updates each source delivered in time since the last check
+    on_time: [u64; 2],
+    lagging: [bool; 2],
+}
+
+pub struct Multiplexer {
+    program: Pubkey,
+    urls: [String; 2],
+    lag_alert: Duration,
+    state: Mutex<State>,
+    /// Whether each source is subscribed
+    up: [AtomicBool; 2],
+}
+
+impl Multiplexer {
+    pub fn new(program: Pubkey, primary: String, secondary: String, lag_alert: Duration) -> Self {
+        Self {
+            program,
+            urls: [primary, secondary],
+            lag_alert,
+            state: Mutex::new(State::default()),
+            up: [AtomicBool::new(false), AtomicBool::new(false)],
+        }
+    }
+
+    pub fn set_up(&self, source: usize, up: bool) {
+        self.up[source].store(up, Ordering::Relaxed);
+    }
+
+    pub fn is_up(&self, source: usize) -> bool {
+        self.up[source].load(Ordering::Relaxed)
+    }
+
+    /// Whether an update delivered by `source` is new and should be dispatched
+    pub fn accept(&self, source: usize, pubkey: Pubkey, slot: u64, data: &[u8]) -> bool {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let key = (pubkey, slot, hasher.finish());
+        let url = &self.urls[source];
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        match state.deliveries.get_mut(&key) {
+            Some(delivery) => {
+                if delivery.first != source && !delivery.settled {
+                    delivery.settled = true;
+                    let lag = delivery.at.elapsed();
+                    METRICS
+                        .subscription_lag_seconds
+                        .with_label_values(&[url])
+                        .observe(lag.as_secs_f64());
+                    if lag < self.lag_alert {
+                        state.on_time[source] += 1;
+                    }
+                }
+                METRICS
+                    .subscription_updates
+                    .with_label_values(&[url, "duplicate"])
+                    .inc();
+                false
+            }
+            None => {
+                state.deliveries.insert(
+                    key,
+                    Delivery {
+                        first: source,
+                        at: Instant::now(),
+                        settled: false,
+                    },
+                );
+                state.order.push_back(key);
+                while state.order.len() > DEDUP_CAPACITY {
+                    if let Some(oldest) = state.order.pop_front() {
+                        state.deliveries.remove(&oldest);
+                    }
+                }
+                state.on_time[source] += 1;
+                METRICS
+                    .subscription_updates
+                    .with_label_values(&[url, "first"])
+                    .inc();
+                true
+            }
+        }
+    }
+
+    /// Count the updates a source didn't deliver within the lag threshold, and open or
+    /// resolve the lag incidents
+    fn check(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut missed = [0u64; 2];
+        for delivery in state.deliveries.values_mut() {
+            if !delivery.settled && delivery.at.elapsed() >= self.lag_alert {
+                delivery.settled = true;
+                // A source that is down is reported as such by the listener
+                let other = 1 - delivery.first;
+                if self.is_up(other) {
+                    missed[other] += 1;
+                }
+            }
+        }
+        for source in 0..2 {
+            let url = &self.urls[source];
+            let key = format!("{} via {}", self.program, url);
+            if missed[source] > 0 {
+                METRICS
+                    .subscription_updates
+                    .with_label_values(&[url, "missed"])
+                    .inc_by(missed[source]);
+                warn!(
+                    program = %self.program,
+                    source = %url,
+                    missed = missed[source],
+                    "Subscription lags behind the other provider"
+                );
+                if !state.lagging[source] {
+                    state.lagging[source] = true;
+                    incidents::trigger(
+                        Event::new(
+                            Severity::Warning,
+                            SUBSCRIPTION_LAGGING,
+                            "Program subscription is lagging",
+                            format!(
+                                "{} didn't deliver {} update(s) of {} within {}s of {}. \
+                                 Interactions are still received from the other provider.",
+                                url,
+                                missed[source],
+                                self.program,
+                                self.lag_alert.as_secs(),
+                                self.urls[1 - source]
+                            ),
+                        )
+                        .with_key(&key),
+                    );
+                }
+            } else if state.lagging[source] && state.on_time[source] > 0 {
+                state.lagging[source] = false;
+                incidents::resolve(SUBSCRIPTION_LAGGING, &key);
+            }
+        }
+        state.on_time = [0; 2];
+    }
+
+    /// Check the sources against each other until the process stops
+    pub async fn watch(&self) {
+        let mut interval = tokio::time::interval((self.lag_alert / 2).max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            self.check();
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Durable nonces for callback transactions.
+//!
+//! A callback signed with a recent blockhash expires about a minute later, which a slow model
+//! and a few retries can outlast. With `callback.durable_nonce`, callbacks are signed with the
+//! blockhash stored in a nonce account instead, and advance it as their first instruction, so
+//! they stay valid until they land. A timed out attempt and its retry share the nonce: at most
+//! one of them lands.
+//!
+//! A nonce is used by one transaction at a time, so the payer owns a pool of
+//! `callback.nonce_accounts` nonce accounts, derived from its key with the seeds
+//! `oracle-nonce-<i>`. Missing accounts are created (rent-exempt, paid by the payer) the first
+//! time a callback needs one.
+
+use crate::callback::LAMPORTS_PER_SIGNATURE;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::nonce_utils;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::nonce::state::State;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use solana_sdk::system_instruction::{advance_nonce_account, create_nonce_account_with_seed};
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
+use std::sync::Mutex;
+use tokio::sync::{OnceCell, Semaphore, SemaphorePermit};
+use tracing::info;
+
+const SEED_PREFIX: &str = "oracle-nonce-";
+
+/// Address of the payer's `index`th nonce account
+pub fn address(authority: &Pubkey, index: usize) -> Pubkey {
+    Pubkey::create_with_seed(
+        authority,
+        &format!("{}{}", SEED_PREFIX, index),
+        &system_program::id(),
+    )
+    .expect("nonce seeds are short")
+}
+
+/// The instruction a transaction using the nonce of `account` starts with
+pub fn advance_instruction(account: &Pubkey, authority: &Pubkey) -> Instruction {
+    advance_nonce_account(account, authority)
+}
+
+pub struct NoncePool {
+    size: usize,
+    /// Accounts not leased, filled once they all exist
+    free: Mutex<Vec<Pubkey>>,
+    permits: Semaphore,
+    ready: OnceCell<()>,
+}
+
+/// A nonce account used by one callback, back in the pool when dropped
+pub struct NonceLease<'a> {
+    pool: &'a NoncePool,
+    pub account: Pubkey,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl NonceLease<'_> {
+    /// The durable blockhash to sign with
+    pub async fn blockhash(&self, rpc_client: &RpcClient) -> Result<Hash, OracleError> {
+        let account = rpc_client
+            .get_account_with_commitment(&self.account, CommitmentConfig::processed())
+            .await?
+            .value
+            .ok_or_else(|| format!("Nonce account {} doesn't exist", self.account))?;
+        Ok(nonce_utils::data_from_account(&account)?.blockhash())
+    }
+}
+
+impl Drop for NonceLease<'_> {
+    fn drop(&mut self) {
+        self.pool.free.lock().unwrap().push(self.account);
+    }
+}
+
+impl NoncePool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            free: Mutex::new(Vec::new()),
+            permits: Semaphore::new(0),
+            ready: OnceCell::new(),
+        }
+    }
+
+    /// Lease a nonce account, waiting for one when they are all in use
+    pub async fn acquire(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+    ) -> Result<NonceLease<'_>, OracleError> {
+        self.ready
+            .get_or_try_init(|| self.create_missing(rpc_client, payer))
+            .await?;
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| "Nonce pool closed")?;
+        let account = self
+            .free
+            .lock()
+            .unwrap()
+            .pop()
+            .ok_or("No free nonce account")?;
+        Ok(NonceLease {
+            pool: self,
+            account,
+            _permit: permit,
+        })
+    }
+
+    /// Create the accounts of the pool that don't exist yet, then open the pool
+    async fn create_missing(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &dyn Signer,
+    ) -> Result<(), OracleError> {
+        let authority = payer.pubkey();
+        let accounts: Vec<Pubkey> = (0..self.size)
+            .map(|index| address(&authority, index))
+            .collect();
+        let existing = rpc_client.get_multiple_accounts(&accounts).await?;
+        let lamports = rpc_client
+            .get_minimum_balance_for_rent_exemption(State::size())
+            .await?;
+        for (index, (account, existing)) in accounts.iter().zip(existing).enumerate() {
+            if existing.is_some() {
+                continue;
+            }
+            let instructions = create_nonce_account_with_seed(
+                &authority,
+                account,
+                &authority,
+                &format!("{}{}", SEED_PREFIX, index),
+                &authority,
+                lamports,
+            );
+            let recent_blockhash = rpc_client.get_latest_blockhash().await?;
+            let transaction = Transaction::new_signed_with_payer(
+                &instructions,
+                Some(&authority),
+                &[payer],
+                recent_blockhash,
+            );
+            rpc_client
+                .send_and_confirm_transaction(&transaction)
+                .await?;
+            METRICS.fee_lamports.inc_by(LAMPORTS_PER_SIGNATURE);
+            info!(%account, "Created a nonce account");
+        }
+        *self.free.lock().unwrap() = accounts;
+        self.permits.add_permits(self.size);
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use super::{headline, Event, Notifier};
+use crate::guardrails::truncate;
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Characters Discord accepts in a message
+const MAX_CONTENT: usize = 2000;
+
+/// Discord webhook
+pub struct DiscordNotifier {
+    name: String,
+    url: String,
+    client: reqwest::Client,
+}
+
+impl DiscordNotifier {
+    pub fn new(name: String, url: String) -> Self {
+        Self {
+            name,
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError> {
+        let content = format!("**{}**\n{}", headline(event), event.body);
+        self.client
+            .post(&self.url)
+            .json(&json!({ "content": truncate(&content, MAX_CONTENT) }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! Notifications.
+//!
+//! Alerts and digests are [`Event`]s with a severity, delivered to the channels configured in
+//! the `[notify]` section of the config file: Slack, Discord, generic webhooks, email, the
+//! PagerDuty Events API and Opsgenie, each a [`Notifier`]. Routes pick the channels of an event by its kind
+//! and severity; without routes, every channel receives warnings and critical events.
+//!
+//! ```toml
+//! [[notify.channels]]
+//! name = "ops"
+//! kind = "slack"
+//! url = "https://hooks.slack.com/services/..."
+//!
+//! [[notify.channels]]
+//! name = "oncall"
+//! kind = "pagerduty"
+//! routing_key = "..."
+//!
+//! [[notify.routes]]
+//! min_severity = "warning"
+//! channels = ["ops"]
+//!
+//! [[notify.routes]]
+//! events = ["dlq_exhausted"]
+//! min_severity = "critical"
+//! channels = ["oncall"]
+//! ```
+//!
+//! Call sites only [`emit`] events, so channels are added without touching them.
+
+use crate::config::{deployment_name, ChannelConfig, ChannelKind, NotifyConfig};
+use crate::metrics::METRICS;
+use crate::OracleError;
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use tracing::{debug, warn, Instrument};
+
+mod discord;
+#[cfg(feature = "email")]
+mod email;
+mod opsgenie;
+mod pagerduty;
+mod slack;
+mod webhook;
+
+pub use discord::DiscordNotifier;
+#[cfg(feature = "email")]
+pub use email::EmailNotifier;
+pub use opsgenie::OpsgenieNotifier;
+pub use pagerduty::PagerDutyNotifier;
+pub use slack::SlackNotifier;
+pub use webhook::WebhookNotifier;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub severity: Severity,
+    /// What happened, matched by routes, e.g. `dlq_exhausted` or `digest`
+    pub kind: &'static str,
+    pub title: String,
+    pub body: String,
+    /// Identifies repeated alerts about the same thing, e.g. an interaction pubkey
+    pub key: Option<String>,
+    /// The condition reported by the event with the same kind and key is over, see
+    /// [`crate::incidents`]
+    pub resolved: bool,
+}
+
+impl Event {
+    pub fn new(
+        severity: Severity,
+        kind: &'static str,
+        title: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Self {
+        Self {
+            severity,
+            kind,
+            title: title.into(),
+            body: body.into(),
+            key: None,
+            resolved: false,
+        }
+    }
+
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// The event announcing the end of this one
+    pub fn into_resolved(mut self) -> Self {
+        self.resolved = true;
+        self
+    }
+}
+
+/// One line summary of an event, naming the deployment
+pub fn headline(event: &Event) -> String {
+    let status = if event.resolved {
+        "RESOLVED".to_string()
+    } else {
+        event.severity.to_string().to_uppercase()
+    };
+    format!(
+        "[{}] {}: {}",
+        deployment_name().unwrap_or("oracle"),
+        status,
+        event.title
+    )
+}
+
+/// A notification channel
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the channel in the config
+    fn name(&self) -> &str;
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError>;
+}
+
+/// Channels of the events matching `events` (all when empty) and `min_severity`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "default_min_severity")]
+    pub min_severity: Severity,
+    pub channels: Vec<String>,
+}
+
+fn default_min_severity() -> Severity {
+    Severity::Info
+}
+
+impl Route {
+    pub fn matches(&self, event: &Event) -> bool {
+        event.severity >= self.min_severity
+            && (self.events.is_empty() || self.events.iter().any(|kind| kind == event.kind))
+    }
+}
+
+/// Configured channels and the routes between them and events
+#[derive(Default)]
+pub struct Notifications {
+    channels: Vec<Arc<dyn Notifier>>,
+    routes: Vec<Route>,
+}
+
+impl Notifications {
+    pub fn new(channels: Vec<Arc<dyn Notifier>>, routes: Vec<Route>) -> Self {
+        Self { channels, routes }
+    }
+
+    pub fn from_config(config: &NotifyConfig) -> Result<Self, OracleError> {
+        let channels = config
+            .channels
+            .iter()
+            .map(build)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(channels, config.routes.clone()))
+    }
+
+    /// Channels an event is delivered to
+    pub fn channels_for(&self, event: &Event) -> Vec<Arc<dyn Notifier>> {
+        if self.routes.is_empty() {
+            if event.severity < Severity::Warning {
+                return Vec::new();
+            }
+            return self.channels.clone();
+        }
+        self.channels
+            .iter()
+            .filter(|channel| {
+                self.routes.iter().any(|route| {
+                    route.matches(event) && route.channels.iter().any(|name| name == channel.name())
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Deliver an event to its channels, failing if any channel failed
+    pub async fn send(&self, event: &Event) -> Result<(), OracleError> {
+        let channels = self.channels_for(event);
+        if channels.is_empty() {
+            debug!(event = event.kind, "No channel for the event");
+            return Ok(());
+        }
+        let results = join_all(channels.iter().map(|channel| channel.notify(event))).await;
+        let mut failed = Vec::new();
+        for (channel, result) in channels.iter().zip(results) {
+            let outcome = match result {
+                Ok(()) => "sent",
+                Err(e) => {
+                    warn!(channel = channel.name(), event = event.kind, error = ?e, "Failed to notify");
+                    failed.push(channel.name().to_string());
+                    "failed"
+                }
+            };
+            METRICS
+                .notifications
+                .with_label_values(&[channel.name(), outcome])
+                .inc();
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("Failed to notify {}", failed.join(", ")).into())
+        }
+    }
+}
+
+fn build(config: &ChannelConfig) -> Result<Arc<dyn Notifier>, OracleError> {
+    let name = config.name.clone();
+    Ok(match &config.kind {
+        ChannelKind::Slack { url } => Arc::new(SlackNotifier::new(name, url.clone())),
+        ChannelKind::Discord { url } => Arc::new(DiscordNotifier::new(name, url.clone())),
+        ChannelKind::Webhook { url } => Arc::new(WebhookNotifier::new(name, url.clone())),
+        #[cfg(feature = "email")]
+        ChannelKind::Email { smtp_url, from, to } => {
+            Arc::new(EmailNotifier::new(name, smtp_url, from, to)?)
+        }
+        #[cfg(not(feature = "email"))]
+        ChannelKind::Email { .. } => {
+            return Err(format!(
+                "Email channel {} needs the oracle built with the email feature",
+                name
+            )
+            .into())
+        }
+        ChannelKind::Pagerduty { routing_key } => {
+            Arc::new(PagerDutyNotifier::new(name, routing_key.clone()))
+        }
+        ChannelKind::Opsgenie { api_key, api_url } => Arc::new(OpsgenieNotifier::new(
+            name,
+            api_key.clone(),
+            api_url.clone(),
+        )),
+    })
+}
+
+static NOTIFICATIONS: OnceLock<Notifications> = OnceLock::new();
+
+/// Set up the channels [`emit`] and [`send`] deliver to. Only the first call has an effect.
+pub fn init(config: &NotifyConfig) -> Result<(), OracleError> {
+    let notifications = Notifications::from_config(config)?;
+    let _ = NOTIFICATIONS.set(notifications);
+    Ok(())
+}
+
+/// Deliver an event in the background
+pub fn emit(event: Event) {
+    let Some(notifications) = NOTIFICATIONS.get() else {
+        return;
+    };
+    if notifications.channels_for(&event).is_empty() {
+        return;
+    }
+    tokio::spawn(
+        async move {
+            let _ = notifications.send(&event).await;
+        }
+        .in_current_span(),
+    );
+}
+
+/// Deliver an event and wait for every channel
+pub async fn send(event: &Event) -> Result<(), OracleError> {
+    match NOTIFICATIONS.get() {
+        Some(notifications) => notifications.send(event).await,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,88 @@
+use super::{Event, Notifier, Severity};
+use crate::config::deployment_name;
+use crate::guardrails::truncate;
+use crate::OracleError;
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::json;
+
+const DEFAULT_API_URL: &str = "https://api.opsgenie.com";
+/// Limits of the Alert API
+const MAX_MESSAGE_BYTES: usize = 130;
+const MAX_DESCRIPTION_BYTES: usize = 15000;
+
+/// Opsgenie Alert API: every event creates an alert, deduplicated by an alias built from its
+/// key. Resolved events close the alert with the same alias.
+pub struct OpsgenieNotifier {
+    name: String,
+    api_key: String,
+    api_url: String,
+    client: reqwest::Client,
+}
+
+impl OpsgenieNotifier {
+    pub fn new(name: String, api_key: String, api_url: Option<String>) -> Self {
+        Self {
+            name,
+            api_key,
+            api_url: api_url
+                .unwrap_or(DEFAULT_API_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn url(&self, segments: &[&str]) -> Result<Url, OracleError> {
+        let mut url = Url::parse(&self.api_url)?;
+        url.path_segments_mut()
+            .map_err(|_| format!("Invalid Opsgenie API URL {}", self.api_url))?
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Notifier for OpsgenieNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError> {
+        let source = deployment_name().unwrap_or("llm_oracle");
+        let alias = event
+            .key
+            .as_ref()
+            .map(|key| format!("{}:{}:{}", source, event.kind, key));
+        let request = if event.resolved {
+            let Some(alias) = alias else {
+                return Ok(());
+            };
+            let mut url = self.url(&["v2", "alerts", &alias, "close"])?;
+            url.query_pairs_mut().append_pair("identifierType", "alias");
+            self.client
+                .post(url)
+                .json(&json!({ "source": source, "note": event.title }))
+        } else {
+            let priority = match event.severity {
+                Severity::Info => "P5",
+                Severity::Warning => "P3",
+                Severity::Critical => "P1",
+            };
+            self.client.post(self.url(&["v2", "alerts"])?).json(&json!({
+                "message": truncate(&event.title, MAX_MESSAGE_BYTES),
+                "alias": alias,
+                "description": truncate(&event.body, MAX_DESCRIPTION_BYTES),
+                "priority": priority,
+                "source": source,
+                "tags": [event.kind],
+            }))
+        };
+        request
+            .header("Authorization", format!("GenieKey {}", self.api_key))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use super::{Event, Notifier, Severity};
+use crate::config::deployment_name;
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::json;
+
+const EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// PagerDuty Events API v2: every event triggers an alert, deduplicated by its key. Resolved
+/// events resolve the alert with the same key.
+pub struct PagerDutyNotifier {
+    name: String,
+    routing_key: String,
+    client: reqwest::Client,
+}
+
+impl PagerDutyNotifier {
+    pub fn new(name: String, routing_key: String) -> Self {
+        Self {
+            name,
+            routing_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for PagerDutyNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError> {
+        let severity = match event.severity {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        };
+        let source = deployment_name().unwrap_or("llm_oracle");
+        let dedup_key = event
+            .key
+            .as_ref()
+            .map(|key| format!("{}:{}:{}", source, event.kind, key));
+        if event.resolved {
+            let Some(dedup_key) = dedup_key else {
+                return Ok(());
+            };
+            let body = json!({
+                "routing_key": self.routing_key,
+                "event_action": "resolve",
+                "dedup_key": dedup_key,
+            });
+            self.client
+                .post(EVENTS_URL)
+                .json(&body)
+                .send()
+                .await?
+                .error_for_status()?;
+            return Ok(());
+        }
+        let mut body = json!({
+            "routing_key": self.routing_key,
+            "event_action": "trigger",
+            "payload": {
+                "summary": event.title,
+                "source": source,
+                "severity": severity,
+                "component": event.kind,
+                "custom_details": { "details": event.body },
+            },
+        });
+        if let Some(dedup_key) = dedup_key {
+            body["dedup_key"] = json!(dedup_key);
+        }
+        self.client
+            .post(EVENTS_URL)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
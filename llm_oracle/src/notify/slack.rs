@@ -0,0 +1,39 @@
+use super::{headline, Event, Notifier};
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Slack incoming webhook
+pub struct SlackNotifier {
+    name: String,
+    url: String,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(name: String, url: String) -> Self {
+        Self {
+            name,
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError> {
+        let text = format!("*{}*\n```{}```", headline(event), event.body);
+        self.client
+            .post(&self.url)
+            .json(&json!({ "text": text }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use super::{Event, Notifier};
+use crate::config::deployment_name;
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// POSTs every event as JSON: `{ "deployment", "severity", "event", "title", "body", "key" }`
+pub struct WebhookNotifier {
+    name: String,
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(name: String, url: String) -> Self {
+        Self {
+            name,
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn notify(&self, event: &Event) -> Result<(), OracleError> {
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "deployment": deployment_name(),
+                "severity": event.severity,
+                "event": event.kind,
+                "title": event.title,
+                "body": event.body,
+                "key": event.key,
+                "resolved": event.resolved,
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use crate::archive::Archive;
+use crate::audit::AuditLog;
+use crate::callback::CallbackSender;
+use crate::config::OracleConfig;
+use crate::context_settings::ContextProviders;
+use crate::context_watch::ContextWatch;
+use crate::costs::CostLedger;
+use crate::dedup::ProcessedSet;
+use crate::dlq::DeadLetterQueue;
+use crate::eta::LatencyTracker;
+use crate::flood::FloodGuard;
+use crate::functions::ChainFunctions;
+use crate::game::GameSessions;
+use crate::guardrails::Guardrails;
+use crate::images::ImageFetcher;
+use crate::ingest::Ingest;
+#[cfg(feature = "rag")]
+use crate::knowledge::ContextIndex;
+use crate::limits::RateLimiter;
+use crate::memory::MemoryStore;
+use crate::prompts::PromptTemplates;
+use crate::providers::{ChatProvider, GenerationParams};
+use crate::push::PushHub;
+use crate::reload::Reloadable;
+use crate::response_cache::ResponseCache;
+use crate::response_length::ResponseLengths;
+use crate::review::ReviewQueue;
+use crate::structured::StructuredOutputs;
+use crate::tools::Tools;
+use crate::webhooks::ContextWebhooks;
+use crate::OracleError;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Shared state of a running oracle, handed to every worker
+pub struct Oracle {
+    pub config: OracleConfig,
+    pub llm_provider: Box<dyn ChatProvider>,
+    /// Providers of the programs overriding `llm.provider` or `llm.model`
+    pub program_providers: HashMap<Pubkey, Box<dyn ChatProvider>>,
+    pub rpc_client: RpcClient,
+    pub interaction_memory: Mutex<Box<dyn MemoryStore>>,
+    pub game_sessions: GameSessions,
+    pub tools: Tools,
+    pub callback_sender: CallbackSender,
+    pub archive: Option<Archive>,
+    /// Hash-chained log of the answers sent, see [`crate::audit`]
+    pub audit: Option<AuditLog>,
+    pub latency: LatencyTracker,
+    /// Limits per context and interaction creator, see [`crate::limits`]
+    pub rate_limiter: RateLimiter,
+    /// Contexts held for flooding, see [`crate::flood`]
+    pub flood: FloodGuard,
+    /// Context accounts watched for closure, see [`crate::context_watch`]
+    pub contexts: ContextWatch,
+    pub processed: ProcessedSet,
+    pub dlq: DeadLetterQueue,
+    /// Costs per context and day, see [`crate::costs`]
+    pub costs: CostLedger,
+    /// Reloaded with the prompts and the generation settings, see [`crate::reload`]
+    pub guardrails: Reloadable<Guardrails>,
+    /// Generation settings of `llm`, as last reloaded
+    pub generation: Reloadable<GenerationParams>,
+    pub structured: StructuredOutputs,
+    pub functions: Option<ChainFunctions>,
+    /// Images of the interactions, when `images.enabled`, see [`crate::images`]
+    pub images: Option<ImageFetcher>,
+    /// Providers of the contexts with on-chain model settings, when `context_settings.enabled`,
+    /// see [`crate::context_settings`]
+    pub context_providers: Option<ContextProviders>,
+    /// Response lengths of the contexts, when `response_length.enabled`, see
+    /// [`crate::response_length`]
+    pub response_lengths: Option<ResponseLengths>,
+    #[cfg(feature = "rag")]
+    pub context_index: Option<ContextIndex>,
+    pub review: Option<ReviewQueue>,
+    pub prompts: Reloadable<PromptTemplates>,
+    /// Sponsored HTTP ingestion, see [`crate::ingest`]
+    pub ingest: Option<Ingest>,
+    /// Answers of repeated questions, see [`crate::response_cache`]
+    pub response_cache: Option<ResponseCache>,
+    /// Subscribers to confirmed answers, see [`crate::push`]
+    pub push: Option<PushHub>,
+    /// Webhooks fired by confirmed answers, see [`crate::webhooks`]
+    pub webhooks: ContextWebhooks,
+}
+
+impl Oracle {
+    pub fn new(
+        config: OracleConfig,
+        llm_provider: Box<dyn ChatProvider>,
+        program_providers: HashMap<Pubkey, Box<dyn ChatProvider>>,
+        interaction_memory: Box<dyn MemoryStore>,
+        game_sessions: GameSessions,
+        tools: Tools,
+        callback_sender: CallbackSender,
+        archive: Option<Archive>,
+        audit: Option<AuditLog>,
+        processed: ProcessedSet,
+        dlq: DeadLetterQueue,
+        costs: CostLedger,
+        guardrails: Guardrails,
+        structured: StructuredOutputs,
+        functions: Option<ChainFunctions>,
+        #[cfg(feature = "rag")] context_index: Option<ContextIndex>,
+        review: Option<ReviewQueue>,
+        prompts: PromptTemplates,
+        ingest: Option<Ingest>,
+        response_cache: Option<ResponseCache>,
+        push: Option<PushHub>,
+        webhooks: ContextWebhooks,
+    ) -> Self {
+        let rpc_client =
+            RpcClient::new_with_commitment(config.rpc_url.clone(), CommitmentConfig::processed());
+        let rate_limiter = RateLimiter::new(
+            config.limits.per_context_per_minute,
+            config.limits.per_user_per_minute,
+            config.limits.action,
+        );
+        let flood = FloodGuard::new(config.flood.clone());
+        let images = config
+            .images
+            .enabled
+            .then(|| ImageFetcher::new(config.images.clone()));
+        let context_providers = config
+            .context_settings
+            .enabled
+            .then(|| ContextProviders::new(config.context_settings.clone()));
+        let response_lengths = config
+            .response_length
+            .enabled
+            .then(|| ResponseLengths::new(config.response_length.clone()));
+        let generation = Reloadable::new(GenerationParams::from(&config.llm));
+        Self {
+            config,
+            llm_provider,
+            program_providers,
+            rpc_client,
+            interaction_memory: Mutex::new(interaction_memory),
+            game_sessions,
+            tools,
+            callback_sender,
+            archive,
+            audit,
+            latency: LatencyTracker::default(),
+            rate_limiter,
+            flood,
+            contexts: ContextWatch::default(),
+            processed,
+            dlq,
+            costs,
+            guardrails: Reloadable::new(guardrails),
+            generation,
+            structured,
+            functions,
+            images,
+            context_providers,
+            response_lengths,
+            #[cfg(feature = "rag")]
+            context_index,
+            review,
+            prompts: Reloadable::new(prompts),
+            ingest,
+            response_cache,
+            push,
+            webhooks,
+        }
+    }
+
+    /// The provider answering the interactions of `program`
+    pub fn provider(&self, program: &Pubkey) -> &dyn ChatProvider {
+        self.program_providers
+            .get(program)
+            .unwrap_or(&self.llm_provider)
+            .as_ref()
+    }
+
+    /// Write the conversations, the ledger of processed interactions, the costs, the archive and
+    /// the response cache to disk
+    pub fn flush(&self) -> Result<(), OracleError> {
+        self.interaction_memory.lock().unwrap().flush()?;
+        self.processed.flush()?;
+        self.costs.flush()?;
+        if let Some(archive) = &self.archive {
+            archive.flush()?;
+        }
+        if let Some(cache) = &self.response_cache {
+            cache.flush()?;
+        }
+        Ok(())
+    }
+}
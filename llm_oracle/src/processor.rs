@@ -0,0 +1,1166 @@
+use crate::admin::{self, upgrade_instruction};
+use crate::archive::ArchiveRecord;
+use crate::attestation;
+use crate::callback::{
+    build_callback_instruction, build_callback_instructions, prompt_hash_tag, CallbackError,
+};
+use crate::config::{deployment_name, LlmConfig};
+use crate::context_watch;
+use crate::costs;
+use crate::decode::{decode_context, InteractionView};
+use crate::encryption;
+use crate::functions::ChainFunctions;
+use crate::game::{state_token, TurnOutcome};
+use crate::guardrails::sanitize;
+use crate::health::HEALTH;
+use crate::images;
+use crate::incidents;
+use crate::limits::LimitAction;
+use crate::logging::redact;
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::prompts::{HistoryMessage, PromptVars};
+use crate::providers::{
+    classify, retry_after, truncate_history, BudgetExhausted, ChatMessage, ChatProvider,
+    ErrorClass, FunctionReply, FunctionRound, FunctionSpec, Role,
+};
+use crate::refusals::{self, Refusal};
+use crate::reload;
+use crate::response_cache;
+use crate::response_length;
+use crate::review::ReviewItem;
+use crate::status::InteractionStatus;
+use crate::streaming;
+use crate::structured::OutputSchema;
+use crate::tools::ToolInput;
+use crate::trace;
+use crate::verification::{self, UNVERIFIED_MARKER};
+use crate::OracleError;
+use chrono::Utc;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::field::{self, Empty};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
+
+/// Process an interaction of `program` and respond to it
+#[instrument(
+    skip_all,
+    fields(
+        interaction = %interaction_pubkey,
+        program = %program,
+        context = Empty,
+        trace_id = Empty,
+        provider = oracle.provider(&program).name()
+    )
+)]
+pub async fn process_interaction(
+    oracle: &Oracle,
+    program: Pubkey,
+    interaction_pubkey: Pubkey,
+    data: Vec<u8>,
+) -> Result<(), OracleError> {
+    let trace_id = trace::new_id();
+    Span::current().record("trace_id", field::display(&trace_id));
+    let processed = trace::scope(
+        trace_id,
+        reload::scope(response_length::scope(process(
+            oracle,
+            program,
+            interaction_pubkey,
+            data,
+        ))),
+    );
+    let (result, meter) = costs::metered(processed).await;
+    oracle.costs.record(&meter);
+    result
+}
+
+async fn process(
+    oracle: &Oracle,
+    program: Pubkey,
+    interaction_pubkey: Pubkey,
+    data: Vec<u8>,
+) -> Result<(), OracleError> {
+    let rpc_client = &oracle.rpc_client;
+    let provider = oracle.provider(&program);
+    // Most updates are for answered interactions: skip them without a full decode
+    let Some(view) = InteractionView::parse(&data) else {
+        return Ok(());
+    };
+    if view.is_processed {
+        return Ok(());
+    }
+    if oracle.processed.contains(&interaction_pubkey, view.text)? {
+        debug!("Interaction already answered, skipping");
+        METRICS.duplicates_skipped.inc();
+        return Ok(());
+    }
+    // The answer is already there, the dead-letter queue retries its callback
+    if let Some(letter) = oracle.dlq.get(&interaction_pubkey)? {
+        if letter.prompt == view.text {
+            debug!("Interaction is in the dead-letter queue, skipping");
+            return Ok(());
+        }
+    }
+    if let Ok(interaction) = view.decode() {
+        let started = Instant::now();
+        Span::current().record("context", field::display(&interaction.context));
+        costs::attribute(&interaction.context);
+        info!("Processing interaction");
+        METRICS
+            .interaction_text_bytes
+            .observe(interaction.text.len() as f64);
+        let abandon = || {
+            oracle.processed.transition(
+                &interaction_pubkey,
+                &interaction.text,
+                InteractionStatus::Abandoned,
+                &[],
+            )
+        };
+        if oracle.contexts.is_deactivated(&interaction.context) {
+            info!("Context deactivated, not answering");
+            return abandon();
+        }
+        let context_data = rpc_client
+            .get_account_with_commitment(&interaction.context, rpc_client.commitment())
+            .await?
+            .value
+            .filter(|account| account.owner == program);
+        let Some(context_data) = context_data else {
+            info!("Context closed, not answering");
+            context_watch::deactivate(oracle, &program, &interaction.context).await;
+            return abandon();
+        };
+        oracle
+            .contexts
+            .watch(&program, &interaction.context, &interaction_pubkey);
+        if let Some(context) = decode_context(&context_data.data) {
+            debug!(
+                user = %interaction.user,
+                text = %redact(&interaction.text),
+                "Interaction"
+            );
+            // Sealed prompts are answered from their plaintext; the ledger keys on the text
+            // on-chain
+            let opened = match &oracle.config.encryption_key {
+                Some(key) if encryption::is_sealed(&interaction.text) => {
+                    match key.open(&interaction.text) {
+                        Ok(text) => {
+                            METRICS.sealed_prompts.with_label_values(&["opened"]).inc();
+                            Some(solana_gpt_oracle::Interaction {
+                                text,
+                                ..interaction.clone()
+                            })
+                        }
+                        Err(e) => {
+                            METRICS.sealed_prompts.with_label_values(&["failed"]).inc();
+                            warn!(error = ?e, "Failed to open the sealed prompt, not answering");
+                            return abandon();
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let plain = opened.as_ref().unwrap_or(&interaction);
+            let ledger = &oracle.processed;
+            ledger.transition(
+                &interaction_pubkey,
+                &interaction.text,
+                InteractionStatus::Claimed,
+                &[],
+            )?;
+
+            // Stale questions are refused without calling the LLM
+            let now = Utc::now().timestamp();
+            if refusals::is_expired(&oracle.config.refusals, interaction.created_at, now) {
+                info!(
+                    age_secs = now - interaction.created_at,
+                    "Interaction expired"
+                );
+                return submit_response(
+                    oracle,
+                    &program,
+                    &interaction_pubkey,
+                    &interaction,
+                    &refusals::refuse(Refusal::Expired),
+                )
+                .await;
+            }
+
+            // Turn-based games: reject illegal turns without calling the LLM
+            let turn = oracle.game_sessions.check_turn(
+                &**oracle.interaction_memory.lock().unwrap(),
+                &interaction.context,
+                &interaction_pubkey,
+                &plain.text,
+            )?;
+            if let Some(rejection) = turn.as_ref().and_then(TurnOutcome::rejection_response) {
+                info!(%rejection, "Rejecting turn");
+                return submit_response(
+                    oracle,
+                    &program,
+                    &interaction_pubkey,
+                    &interaction,
+                    &rejection,
+                )
+                .await;
+            }
+
+            // With context settings, the context may be answered by another model
+            let context_provider = match &oracle.context_providers {
+                Some(providers) => providers
+                    .provider(oracle, &program, &interaction.context)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(error = ?e, "Failed to read the context settings, ignoring them");
+                        None
+                    }),
+                None => None,
+            };
+            let provider = context_provider.as_deref().unwrap_or(provider);
+            // The interaction is answered with the prompts, guardrails and generation settings
+            // reloaded last when it started; contexts with settings keep theirs
+            let prompts = oracle.prompts.get();
+            let guardrails = oracle.guardrails.get();
+            let generation = oracle.generation.get();
+            let base_tokens = generation.max_tokens;
+            if context_provider.is_none() {
+                reload::use_generation(generation);
+            }
+            // With adaptive response length, answers are sized to what the context's got cut to;
+            // contexts with settings keep the max tokens they chose
+            let length = oracle
+                .response_lengths
+                .as_ref()
+                .map(|lengths| lengths.limits(&interaction.context, base_tokens));
+            if let (Some(limits), None) = (length, &context_provider) {
+                response_length::set_max_tokens(limits.max_tokens);
+            }
+
+            // With `incidents.pause_when_payer_empty`, an answer that can't land isn't paid for
+            incidents::payer_funded().await;
+
+            // Get a response from the LLM provider
+            ledger.transition(
+                &interaction_pubkey,
+                &interaction.text,
+                InteractionStatus::Generating,
+                &[],
+            )?;
+            let mut previous_history = oracle
+                .interaction_memory
+                .lock()
+                .unwrap()
+                .get_history(&interaction_pubkey)?
+                .unwrap_or(Vec::new());
+            // With context retrieval, only the parts of the contexts relevant to the text
+            #[cfg(feature = "rag")]
+            let context_text = match &oracle.context_index {
+                Some(index) => index
+                    .assemble(&program, &interaction.context, &context.text, &plain.text)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!(error = ?e, "Context retrieval failed, sending the whole context");
+                        context.text.clone()
+                    }),
+                None => context.text.clone(),
+            };
+            #[cfg(not(feature = "rag"))]
+            let context_text = context.text.clone();
+            // With image inputs, the question follows the `[image:<uri>]` tag
+            let image = oracle.images.as_ref().and_then(|fetcher| {
+                images::parse(&plain.text).map(|(uri, question)| (fetcher, uri, question))
+            });
+            let question = image.map_or(plain.text.as_str(), |(_, _, question)| question);
+            let mut prompt = prompts.render(
+                &interaction.context,
+                &PromptVars {
+                    context: context_text,
+                    context_pubkey: interaction.context.to_string(),
+                    text: question.to_string(),
+                    interaction: interaction_pubkey.to_string(),
+                    user: interaction.user.to_string(),
+                    history: previous_history.iter().map(HistoryMessage::from).collect(),
+                    identity: oracle.config.payer.pubkey().to_string(),
+                    deployment: deployment_name().unwrap_or("default").to_string(),
+                },
+            )?;
+            if let Some(TurnOutcome::Accepted {
+                action,
+                from,
+                to,
+                allowed_next,
+            }) = &turn
+            {
+                prompt.push_str(&format!(
+                    "\nThe player played {:?}, moving the game from state {:?} to {:?}. \
+                         Next allowed actions: {}.",
+                    action,
+                    from,
+                    to,
+                    allowed_next.join(", ")
+                ));
+            }
+
+            // Ground the prompt with data from the enabled tools
+            let tool_outputs = oracle
+                .tools
+                .run(&ToolInput {
+                    interaction_pubkey: &interaction_pubkey,
+                    interaction: plain,
+                    context_text: &context.text,
+                })
+                .await;
+            for output in &tool_outputs {
+                prompt.push_str(&format!("\n[{}] {}", output.tool, output.prompt));
+            }
+            let schema = oracle.structured.get(&interaction.context);
+            if let Some(schema) = schema {
+                prompt.push('\n');
+                prompt.push_str(&schema.instructions());
+            }
+            if let Some(max_chars) = length.and_then(|limits| limits.max_chars) {
+                prompt.push_str(&format!("\nAnswer in under {} characters.", max_chars));
+            }
+            let mut images = Vec::new();
+            if let Some((fetcher, uri, _)) = image {
+                match fetcher.attach(provider, uri).await {
+                    Ok(image) => images.push(image),
+                    Err(note) => {
+                        prompt.push('\n');
+                        prompt.push_str(&note);
+                    }
+                }
+            }
+
+            // Only first questions that don't depend on a game, tool data, an image or context
+            // settings are cached
+            let cache = oracle.response_cache.as_ref().filter(|_| {
+                previous_history.is_empty()
+                    && turn.is_none()
+                    && tool_outputs.is_empty()
+                    && image.is_none()
+                    && context_provider.is_none()
+            });
+            let cache_key = cache.map(|_| {
+                response_cache::key(
+                    &interaction.context,
+                    &interaction.text,
+                    &model_id(oracle, &program),
+                )
+            });
+            let cached = cache
+                .zip(cache_key.as_ref())
+                .and_then(|(cache, key)| cache.get(key));
+            previous_history.push(ChatMessage {
+                images,
+                ..ChatMessage::new(Role::User, prompt)
+            });
+            let dropped = truncate_history(
+                provider,
+                &mut previous_history,
+                oracle.config.llm.history_token_budget,
+            );
+            if dropped > 0 {
+                debug!(dropped, "Dropped history beyond the token budget");
+            }
+            // Structured outputs are constrained to their schema instead
+            let functions = oracle
+                .functions
+                .as_ref()
+                .filter(|functions| schema.is_none() && functions.applies_to(&interaction.context));
+            // Only answers that need no validation once complete are streamed
+            let stream = oracle.config.streaming_callbacks
+                && schema.is_none()
+                && functions.is_none()
+                && cached.is_none()
+                && turn.is_none()
+                && tool_outputs
+                    .iter()
+                    .all(|output| output.citations.is_empty() && output.callback_suffix.is_none())
+                && !oracle.config.prompt_hash_callbacks
+                && oracle.config.attester().is_none()
+                && !guardrails.inspects()
+                && encryption::reply_key(&interaction.text).is_none();
+            let mut streamed = false;
+            let mut flags = Vec::new();
+            let mut response_content = loop {
+                if let Some(cached) = &cached {
+                    debug!("Answering from the response cache");
+                    break cached.clone();
+                }
+                let response = match functions {
+                    Some(functions) => {
+                        call_with_functions(oracle, provider, functions, &previous_history).await
+                    }
+                    None if stream => {
+                        let answer = streaming::stream(
+                            oracle,
+                            &program,
+                            provider,
+                            &interaction_pubkey,
+                            &interaction,
+                            &previous_history,
+                        )
+                        .await;
+                        match answer {
+                            Ok(answer) => {
+                                streamed = answer.sent;
+                                if answer.interrupted {
+                                    flags.push("interrupted".to_string());
+                                }
+                                Ok(answer.response)
+                            }
+                            Err(e) => {
+                                warn!(error = ?e, "Streaming failed, asking without streaming");
+                                call_llm(oracle, provider, &previous_history, None).await
+                            }
+                        }
+                    }
+                    None => {
+                        call_llm(
+                            oracle,
+                            provider,
+                            &previous_history,
+                            schema.map(|schema| &schema.schema),
+                        )
+                        .await
+                    }
+                };
+                let exhausted = match response {
+                    Ok(response) => break response,
+                    // Only the history sent shrinks, the conversation in memory is kept whole
+                    Err(e)
+                        if classify(&e) == ErrorClass::ContextLength
+                            && previous_history.len() > 1 =>
+                    {
+                        let dropped = (previous_history.len() - 1).div_ceil(2);
+                        previous_history.drain(..dropped);
+                        warn!(
+                            dropped,
+                            "Prompt over the context window, retrying with less history"
+                        );
+                        continue;
+                    }
+                    Err(e) => match e.downcast::<BudgetExhausted>() {
+                        Ok(exhausted) => exhausted,
+                        Err(e) => return Err(e),
+                    },
+                };
+                match oracle.config.limits.action {
+                    LimitAction::Defer => {
+                        info!(resets_in = ?exhausted.resets_in, "{}, deferring", exhausted);
+                        METRICS
+                            .interactions_limited
+                            .with_label_values(&["budget", "defer"])
+                            .inc();
+                        // The worker pool holds it without a worker until the budget resets
+                        return Err((*exhausted).into());
+                    }
+                    LimitAction::Respond => {
+                        info!("{}, answering with the limit response", exhausted);
+                        METRICS
+                            .interactions_limited
+                            .with_label_values(&["budget", "respond"])
+                            .inc();
+                        let response = refusals::response(
+                            &oracle.config.refusals,
+                            Refusal::OverBudget,
+                            &oracle.config.limits.response,
+                        );
+                        return submit_response(
+                            oracle,
+                            &program,
+                            &interaction_pubkey,
+                            &interaction,
+                            &response,
+                        )
+                        .await;
+                    }
+                }
+            };
+            debug!(response = %redact(&response_content), "LLM response");
+            let capped = response_length::take_capped();
+            // A streamed answer is in the audit log already
+            if let Some(audit) = oracle.audit.as_ref().filter(|_| !streamed) {
+                let source = if cached.is_some() {
+                    "cache"
+                } else {
+                    provider.name()
+                };
+                audit.generated(&interaction_pubkey, source, &response_content);
+            }
+
+            // A streamed answer is already on-chain
+            if !streamed {
+                ledger.transition(
+                    &interaction_pubkey,
+                    &interaction.text,
+                    InteractionStatus::Validating,
+                    &[],
+                )?;
+            }
+            if let Some(schema) = schema {
+                response_content = conform(
+                    oracle,
+                    provider,
+                    schema,
+                    &previous_history,
+                    response_content,
+                )
+                .await?;
+                if response_content == Refusal::InvalidFormat.tag() {
+                    flags.push(Refusal::InvalidFormat.code().to_string());
+                }
+            }
+            if !streamed {
+                ledger.save_response(&interaction_pubkey, &interaction.text, &response_content)?;
+            }
+
+            // Check the answer against the sources it was grounded on
+            let sources: Vec<&str> = tool_outputs
+                .iter()
+                .filter(|output| !output.citations.is_empty())
+                .map(|output| output.prompt.as_str())
+                .collect();
+            // Hedging would break the JSON of structured outputs
+            if let (Some(guard), false, false) = (
+                oracle.config.llm.hallucination_guard,
+                sources.is_empty(),
+                schema.is_some(),
+            ) {
+                response_content = verification::verify(
+                    provider,
+                    guard,
+                    &previous_history,
+                    &sources,
+                    response_content,
+                )
+                .instrument(info_span!("verification", %guard))
+                .await;
+            }
+            if response_content.starts_with(UNVERIFIED_MARKER) {
+                flags.push("unverified".to_string());
+            }
+            if let (Some(lengths), None) = (&oracle.response_lengths, &cached) {
+                let max_bytes = guardrails.max_bytes();
+                let cut = sanitize(&response_content).len() > max_bytes;
+                lengths.record(&interaction.context, base_tokens, max_bytes, capped, cut);
+            }
+            let (checked, rejection) = guardrails.check(&response_content).await;
+            response_content = checked;
+            flags.extend(rejection.map(str::to_string));
+            if let (Some(cache), Some(key), None, true) =
+                (cache, cache_key, &cached, flags.is_empty())
+            {
+                cache.put(key, &response_content, &interaction_pubkey);
+            }
+            // The question is remembered with its answer, so an interaction deferred or failed
+            // before it is answered isn't asked twice when it comes back
+            {
+                let mut interaction_memory = oracle.interaction_memory.lock().unwrap();
+                interaction_memory.add_interaction(
+                    interaction_pubkey,
+                    plain.text.clone(),
+                    Role::User,
+                )?;
+                interaction_memory.add_interaction(
+                    interaction_pubkey,
+                    response_content.clone(),
+                    Role::System,
+                )?;
+            }
+
+            for suffix in tool_outputs
+                .iter()
+                .filter_map(|o| o.callback_suffix.as_ref())
+            {
+                response_content.push(' ');
+                response_content.push_str(suffix);
+            }
+
+            // A terminal state ends the game, and with it the conversation
+            let mut finished = false;
+            if let Some(TurnOutcome::Accepted {
+                to, allowed_next, ..
+            }) = turn
+            {
+                finished = allowed_next.is_empty();
+                response_content = format!("{} {}", state_token(&to), response_content);
+                oracle.game_sessions.commit(
+                    &mut **oracle.interaction_memory.lock().unwrap(),
+                    &interaction.context,
+                    &interaction_pubkey,
+                    &to,
+                )?;
+            }
+
+            if let Some(archive) = &oracle.archive {
+                let citations = tool_outputs
+                    .iter()
+                    .flat_map(|output| output.citations.iter().cloned())
+                    .collect();
+                let mut record = ArchiveRecord::new(
+                    &interaction_pubkey,
+                    &interaction,
+                    &response_content,
+                    provider.name(),
+                    citations,
+                );
+                record.latency_ms = Some(started.elapsed().as_millis() as u64);
+                if let Err(e) = archive.record(&record) {
+                    warn!(error = ?e, "Failed to archive the response");
+                }
+            }
+            if let Some(review) = &oracle.review {
+                let item = ReviewItem::new(
+                    &interaction_pubkey,
+                    &interaction,
+                    &previous_history,
+                    &response_content,
+                    provider.name(),
+                    flags,
+                );
+                if let Err(e) = review.offer(&item) {
+                    warn!(error = ?e, "Failed to queue the interaction for review");
+                }
+            }
+
+            // Send the response with the callback transaction
+            if !streamed {
+                submit_response(
+                    oracle,
+                    &program,
+                    &interaction_pubkey,
+                    &interaction,
+                    &response_content,
+                )
+                .await?;
+            }
+            oracle
+                .latency
+                .record(&interaction.context, started.elapsed());
+            HEALTH.answered();
+            if finished {
+                oracle
+                    .interaction_memory
+                    .lock()
+                    .unwrap()
+                    .close(&interaction_pubkey)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Answer an interaction of `program` over a rate limit with `limits.response`, or its refusal
+/// code, without calling the LLM
+pub async fn respond_limited(
+    oracle: &Oracle,
+    program: Pubkey,
+    interaction_pubkey: Pubkey,
+    data: Vec<u8>,
+) -> Result<(), OracleError> {
+    let Some(view) = InteractionView::parse(&data) else {
+        return Ok(());
+    };
+    let interaction = view.decode()?;
+    oracle.processed.transition(
+        &interaction_pubkey,
+        &interaction.text,
+        InteractionStatus::Claimed,
+        &[],
+    )?;
+    let response = refusals::response(
+        &oracle.config.refusals,
+        Refusal::OverBudget,
+        &oracle.config.limits.response,
+    );
+    let (result, meter) = costs::metered(async {
+        costs::attribute(&interaction.context);
+        submit_response(
+            oracle,
+            &program,
+            &interaction_pubkey,
+            &interaction,
+            &response,
+        )
+        .await
+    })
+    .await;
+    oracle.costs.record(&meter);
+    result
+}
+
+/// Provider and model answering the interactions of `program`, which the response cache keys on
+fn model_id(oracle: &Oracle, program: &Pubkey) -> String {
+    let overrides = oracle.config.program(program).ok();
+    let model = match overrides {
+        Some(overrides) if overrides.provider.is_some() || overrides.model.is_some() => {
+            overrides.model.as_deref()
+        }
+        _ => oracle.config.llm.model.as_deref(),
+    };
+    format!(
+        "{}/{}",
+        oracle.provider(program).name(),
+        model.unwrap_or("default")
+    )
+}
+
+/// Ask the LLM provider, retrying failed calls up to `llm.max_retries` times
+async fn call_llm(
+    oracle: &Oracle,
+    provider: &dyn ChatProvider,
+    messages: &[ChatMessage],
+    schema: Option<&Value>,
+) -> Result<String, OracleError> {
+    with_retries(oracle, provider.name(), || async move {
+        match schema {
+            Some(schema) => provider.send_structured(messages, schema).await,
+            None => provider.send_message(messages).await,
+        }
+    })
+    .await
+}
+
+/// Let the model call chain functions before it answers. After `max_rounds` rounds of calls,
+/// the functions are withdrawn so the model answers with the results it has.
+async fn call_with_functions(
+    oracle: &Oracle,
+    provider: &dyn ChatProvider,
+    functions: &ChainFunctions,
+    messages: &[ChatMessage],
+) -> Result<String, OracleError> {
+    let specs = functions.specs();
+    let names = functions.names();
+    let mut rounds: Vec<FunctionRound> = Vec::new();
+    loop {
+        let offered: &[FunctionSpec] = if rounds.len() < functions.max_rounds() {
+            specs.as_slice()
+        } else {
+            &[]
+        };
+        let reply = with_retries(oracle, provider.name(), || {
+            provider.send_with_functions(messages, offered, &rounds)
+        })
+        .await?;
+        let calls = match reply {
+            FunctionReply::Text(text) => return Ok(text),
+            FunctionReply::Calls(_) if offered.is_empty() => {
+                return Err(
+                    format!("No answer after {} rounds of function calls", rounds.len()).into(),
+                )
+            }
+            FunctionReply::Calls(calls) => calls,
+        };
+        let mut results = Vec::with_capacity(calls.len());
+        for call in &calls {
+            let result = functions
+                .call(call)
+                .instrument(info_span!("function_call", function = %call.name))
+                .await;
+            debug!(function = %call.name, arguments = %call.arguments, ?result, "Function call");
+            // Names come from the model: don't let them become metric labels
+            let function = if names.contains(&call.name.as_str()) {
+                call.name.as_str()
+            } else {
+                "unknown"
+            };
+            let outcome = if result.is_ok() { "ok" } else { "error" };
+            METRICS
+                .function_calls
+                .with_label_values(&[function, outcome])
+                .inc();
+            results.push(result.unwrap_or_else(|e| format!("Error: {}", e)));
+        }
+        rounds.push(FunctionRound { calls, results });
+    }
+}
+
+/// Run `call` to `provider` until it succeeds, up to `llm.max_retries` times. Retries wait for
+/// an exponential backoff with jitter, or the `Retry-After` of rate limited calls. Requests the
+/// API rejects aren't retried, prompts over the context window are left to the caller.
+async fn with_retries<T, F, Fut>(
+    oracle: &Oracle,
+    provider: &str,
+    mut call: F,
+) -> Result<T, OracleError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OracleError>>,
+{
+    let mut api_attempts = 0;
+    let max_attempts = oracle.config.llm.max_retries.max(1);
+    loop {
+        let started = Instant::now();
+        let span = info_span!("llm_call", attempt = api_attempts + 1);
+        let result = call().instrument(span).await;
+        METRICS
+            .llm_latency
+            .with_label_values(&[provider])
+            .observe(started.elapsed().as_secs_f64());
+        match result {
+            Ok(response) => {
+                HEALTH.provider(provider, true);
+                return Ok(response);
+            }
+            // Calls are refused until the budget resets, retrying is pointless
+            Err(e) if e.is::<BudgetExhausted>() => return Err(e),
+            Err(e) => {
+                api_attempts += 1;
+                let class = classify(&e);
+                METRICS.llm_retries.with_label_values(&[provider]).inc();
+                METRICS
+                    .llm_errors
+                    .with_label_values(&[provider, class.as_str()])
+                    .inc();
+                if !class.retryable() {
+                    warn!(class = class.as_str(), error = ?e, "API call rejected");
+                    return Err(e);
+                }
+                if api_attempts >= max_attempts {
+                    warn!(
+                        attempt = api_attempts,
+                        class = class.as_str(),
+                        error = ?e,
+                        "API call failed, giving up"
+                    );
+                    HEALTH.provider(provider, false);
+                    return Err(e);
+                }
+                let delay = backoff(&oracle.config.llm, api_attempts, retry_after(&e));
+                warn!(
+                    attempt = api_attempts,
+                    max_attempts,
+                    class = class.as_str(),
+                    delay_ms = delay.as_millis() as u64,
+                    error = ?e,
+                    "API call failed, retrying"
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Wait before retry number `attempt`: the `Retry-After` of the API if any, otherwise a random
+/// delay up to `retry_base_ms` doubled at each attempt, both capped at `retry_max_ms`
+fn backoff(config: &LlmConfig, attempt: u8, retry_after: Option<Duration>) -> Duration {
+    let max = Duration::from_millis(config.retry_max_ms);
+    if let Some(retry_after) = retry_after {
+        return retry_after.min(max);
+    }
+    let ceiling = config
+        .retry_base_ms
+        .saturating_mul(1 << (attempt - 1).min(32))
+        .min(config.retry_max_ms);
+    Duration::from_millis(rand::random_range(0..=ceiling))
+}
+
+/// `response` as JSON matching `schema`. A response that doesn't match is sent back to the
+/// model with the validation errors, up to `llm.schema_corrections` times, then refused as
+/// `invalid_format` with refusal codes on.
+async fn conform(
+    oracle: &Oracle,
+    provider: &dyn ChatProvider,
+    schema: &OutputSchema,
+    history: &[ChatMessage],
+    mut response: String,
+) -> Result<String, OracleError> {
+    let mut messages = history.to_vec();
+    let mut corrections = 0;
+    loop {
+        match schema.validate(&response) {
+            Ok(json) => {
+                let outcome = if corrections == 0 {
+                    "valid"
+                } else {
+                    "corrected"
+                };
+                METRICS
+                    .structured_outputs
+                    .with_label_values(&[outcome])
+                    .inc();
+                return Ok(json);
+            }
+            Err(error) if corrections < oracle.config.llm.schema_corrections => {
+                corrections += 1;
+                debug!(corrections, %error, "Response doesn't match the output schema, correcting");
+                messages.push(ChatMessage::new(Role::Assistant, response));
+                messages.push(ChatMessage::new(
+                    Role::User,
+                    schema.correction_prompt(&error),
+                ));
+                response = call_llm(oracle, provider, &messages, Some(&schema.schema)).await?;
+            }
+            Err(error) => {
+                METRICS
+                    .structured_outputs
+                    .with_label_values(&["invalid"])
+                    .inc();
+                if oracle.config.refusals.enabled {
+                    warn!(corrections, %error, "Response doesn't match the output schema");
+                    return Ok(refusals::refuse(Refusal::InvalidFormat));
+                }
+                return Err(format!(
+                    "Response doesn't match the output schema after {} correction(s): {}",
+                    corrections, error
+                )
+                .into());
+            }
+        }
+    }
+}
+
+/// Grow an interaction of `program` created before `created_at` was recorded, which the program
+/// can't deserialize for its callback otherwise, see [`crate::decode`]. The instruction leaves
+/// those that deserialize as they are; a failure is left to the callback to surface.
+pub async fn upgrade_legacy(
+    oracle: &Oracle,
+    program: &Pubkey,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+) {
+    if interaction.created_at != 0 {
+        return;
+    }
+    let payer = oracle.config.payer.as_ref();
+    let instruction = upgrade_instruction(program, &payer.pubkey(), interaction_pubkey);
+    match admin::send(&oracle.rpc_client, payer, &[instruction]).await {
+        Ok(signature) => debug!(%signature, "Interaction upgraded to the current layout"),
+        Err(e) => warn!(error = ?e, "Failed to upgrade the interaction to the current layout"),
+    }
+}
+
+/// Build and send the callback transaction(s) for a response to an interaction of `program`, and
+/// record the outcome in the ledger. A callback that can't be sent goes to the dead-letter queue.
+pub async fn submit_response(
+    oracle: &Oracle,
+    program: &Pubkey,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    response: &str,
+) -> Result<(), OracleError> {
+    oracle.processed.transition(
+        interaction_pubkey,
+        &interaction.text,
+        InteractionStatus::Submitting,
+        &[],
+    )?;
+    oracle
+        .processed
+        .save_response(interaction_pubkey, &interaction.text, response)?;
+    if oracle.contexts.is_deactivated(&interaction.context) {
+        warn!("Context deactivated while it was answered, dropping the callback");
+        return oracle.processed.transition(
+            interaction_pubkey,
+            &interaction.text,
+            InteractionStatus::Abandoned,
+            &[],
+        );
+    }
+    upgrade_legacy(oracle, program, interaction_pubkey, interaction).await;
+    let answer = response;
+    let mut response = response.to_string();
+    if oracle.config.encryption_key.is_some() {
+        if let Some(reply_key) = encryption::reply_key(&interaction.text) {
+            response = encryption::seal(&reply_key, &response)?;
+        }
+    }
+    if oracle.config.prompt_hash_callbacks {
+        // The account may have been rewritten with a new question while this one was answered
+        let current = oracle
+            .rpc_client
+            .get_account_data(interaction_pubkey)
+            .await?;
+        let current = InteractionView::parse(&current).ok_or("Invalid interaction account")?;
+        if current.is_processed || current.text != interaction.text {
+            warn!("Interaction changed while it was answered, dropping the callback");
+            return oracle.processed.transition(
+                interaction_pubkey,
+                &interaction.text,
+                InteractionStatus::Abandoned,
+                &[],
+            );
+        }
+        response = format!("{} {}", prompt_hash_tag(&interaction.text), response);
+    }
+    let attestation = match oracle.config.attester() {
+        Some(attester) => {
+            let (attested, verification) =
+                attestation::attest(attester, interaction_pubkey, &response)?;
+            response = attested;
+            Some(verification)
+        }
+        None => None,
+    };
+
+    let program = oracle.config.program(program)?;
+    let payer = oracle.config.payer.as_ref();
+    // Every chunk carries the same accounts
+    let tables = oracle
+        .callback_sender
+        .lookup_tables
+        .prepare(
+            &oracle.rpc_client,
+            payer,
+            &build_callback_instruction(
+                &payer.pubkey(),
+                program,
+                interaction_pubkey,
+                interaction,
+                "",
+            )?,
+        )
+        .await;
+    METRICS.response_bytes.observe(response.len() as f64);
+    let callback_transactions = build_callback_instructions(
+        &payer.pubkey(),
+        program,
+        interaction_pubkey,
+        interaction,
+        &response,
+        oracle.config.chunked_callbacks,
+        attestation.as_ref(),
+        &tables,
+        &oracle.callback_sender.sizing_envelope(&payer.pubkey()),
+    )?;
+    // Chunks are sent one after the other so they land in order
+    let mut signatures = Vec::new();
+    let mut failure = None;
+    let record_sent = |signature: &Signature| {
+        let recorded =
+            oracle
+                .processed
+                .record_sent(interaction_pubkey, &interaction.text, signature);
+        if let Err(e) = recorded {
+            warn!(error = ?e, "Failed to record the callback signature");
+        }
+    };
+    let batcher = oracle.callback_sender.batcher.as_ref().filter(|_| {
+        matches!(callback_transactions.as_slice(), [callback] if callback.len() == 1)
+            && tables.is_empty()
+    });
+    for instructions in callback_transactions {
+        let sent = match batcher {
+            Some(batcher) => {
+                batcher
+                    .submit(
+                        oracle,
+                        interaction_pubkey,
+                        &interaction.text,
+                        instructions[0].clone(),
+                    )
+                    .await
+            }
+            None => {
+                oracle
+                    .callback_sender
+                    .send(
+                        &oracle.rpc_client,
+                        payer,
+                        &instructions,
+                        &tables,
+                        &record_sent,
+                    )
+                    .await
+            }
+        };
+        match sent {
+            Ok(landed) => {
+                info!(signature = %landed.signature, "Callback transaction landed");
+                costs::record_fee(landed.fee_lamports);
+                signatures.push(landed.signature);
+            }
+            Err(e) if e.is::<CallbackError>() => {
+                error!(error = %e, "Callback can't land");
+                failure = Some(e);
+                break;
+            }
+            Err(e) => {
+                error!(error = ?e, "Giving up on callback transaction");
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    settle(
+        oracle,
+        interaction_pubkey,
+        interaction,
+        answer,
+        &response,
+        &signatures,
+        failure,
+    )
+}
+
+/// Record the outcome of the callbacks carrying `answer`, as `callback`, in the ledger and the
+/// audit log: confirmed, pushed to subscribers and sent to the context's webhooks (sealed when
+/// the callback is, see [`encryption::outgoing`]), or sent to the dead-letter queue after a
+/// `failure`
+pub fn settle(
+    oracle: &Oracle,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    answer: &str,
+    callback: &str,
+    signatures: &[Signature],
+    failure: Option<OracleError>,
+) -> Result<(), OracleError> {
+    let status = match failure {
+        Some(e) => {
+            let retrying = oracle.dlq.push(
+                interaction_pubkey,
+                &interaction.text,
+                answer,
+                &e.to_string(),
+            )?;
+            if retrying {
+                InteractionStatus::Failed
+            } else {
+                InteractionStatus::Abandoned
+            }
+        }
+        None => {
+            oracle.dlq.remove(interaction_pubkey)?;
+            InteractionStatus::Confirmed
+        }
+    };
+    oracle
+        .processed
+        .transition(interaction_pubkey, &interaction.text, status, signatures)?;
+    if let Some(audit) = &oracle.audit {
+        let recorded = audit.record(
+            interaction_pubkey,
+            interaction,
+            answer,
+            callback,
+            signatures,
+            status.as_str(),
+        );
+        if let Err(e) = recorded {
+            error!(error = ?e, "Failed to append to the audit log");
+        }
+    }
+    if status == InteractionStatus::Confirmed {
+        let outgoing = encryption::outgoing(
+            oracle.config.encryption_key.is_some(),
+            &interaction.text,
+            answer,
+            callback,
+        );
+        if let Some(push) = &oracle.push {
+            push.publish(interaction_pubkey, interaction, outgoing, signatures);
+        }
+        oracle
+            .webhooks
+            .fire(interaction_pubkey, interaction, outgoing, signatures);
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Prompt templates.
+//!
+//! The prompt of an interaction is a [minijinja](https://docs.rs/minijinja) template rendered
+//! with [`PromptVars`]: [`DEFAULT_TEMPLATE`] (`prompts/default.j2`), the file at
+//! `PROMPT_TEMPLATE` instead when set, or `<context pubkey>.j2` in `PROMPT_TEMPLATE_DIR` for the
+//! interactions of that context. Game turns, tool outputs and output schemas are appended to
+//! the rendered prompt.
+//!
+//! The conversation history is sent to the model as messages either way; `history` is there for
+//! templates that want to quote or summarize it.
+
+use crate::providers::{ChatMessage, Role};
+use crate::OracleError;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_TEMPLATE: &str = include_str!("../prompts/default.j2");
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl From<&ChatMessage> for HistoryMessage {
+    fn from(message: &ChatMessage) -> Self {
+        let role = match message.role {
+            Role::System => "system",
+            Role::Assistant => "assistant",
+            _ => "user",
+        };
+        Self {
+            role,
+            content: message.content.clone(),
+        }
+    }
+}
+
+/// What the prompt templates are rendered with
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PromptVars {
+    /// Context text, or the retrieved parts of the contexts with context retrieval
+    pub context: String,
+    pub context_pubkey: String,
+    /// The interaction text
+    pub text: String,
+    pub interaction: String,
+    pub user: String,
+    /// Earlier messages of the conversation, oldest first
+    pub history: Vec<HistoryMessage>,
+    /// Pubkey of the oracle identity
+    pub identity: String,
+    pub deployment: String,
+}
+
+/// The default template and the overrides by context
+pub struct PromptTemplates {
+    default: String,
+    overrides: HashMap<Pubkey, String>,
+}
+
+impl PromptTemplates {
+    pub fn new(default: String, overrides: HashMap<Pubkey, String>) -> Result<Self, OracleError> {
+        // Fail at startup rather than at the first interaction
+        validate(&default).map_err(|e| format!("Invalid prompt template: {}", e))?;
+        for (context, source) in &overrides {
+            validate(source)
+                .map_err(|e| format!("Invalid prompt template of {}: {}", context, e))?;
+        }
+        Ok(Self { default, overrides })
+    }
+
+    /// Load `PROMPT_TEMPLATE` and the `<context pubkey>.j2` files of `PROMPT_TEMPLATE_DIR`
+    pub fn from_env() -> Result<Self, OracleError> {
+        let default = match env::var("PROMPT_TEMPLATE") {
+            Ok(path) => fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read PROMPT_TEMPLATE {}: {}", path, e))?,
+            Err(_) => DEFAULT_TEMPLATE.to_string(),
+        };
+        let mut overrides = HashMap::new();
+        if let Ok(dir) = env::var("PROMPT_TEMPLATE_DIR") {
+            for entry in fs::read_dir(&dir)
+                .map_err(|e| format!("Failed to read PROMPT_TEMPLATE_DIR {}: {}", dir, e))?
+            {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("j2") {
+                    continue;
+                }
+                let context = context_of(&path)?;
+                overrides.insert(context, fs::read_to_string(&path)?);
+            }
+        }
+        Self::new(default, overrides)
+    }
+
+    /// Contexts with their own template
+    pub fn overrides(&self) -> Vec<Pubkey> {
+        self.overrides.keys().copied().collect()
+    }
+
+    /// The template of the interactions of `context`
+    pub fn source(&self, context: &Pubkey) -> &str {
+        self.overrides.get(context).unwrap_or(&self.default)
+    }
+
+    /// The prompt of an interaction of `context`
+    pub fn render(&self, context: &Pubkey, vars: &PromptVars) -> Result<String, OracleError> {
+        render_source(self.source(context), vars)
+    }
+}
+
+fn render_source(source: &str, vars: &PromptVars) -> Result<String, OracleError> {
+    let env = minijinja::Environment::new();
+    Ok(env.render_str(source, vars)?.trim().to_string())
+}
+
+/// Check that `source` is a template the prompts can be rendered with
+pub fn validate(source: &str) -> Result<(), OracleError> {
+    render_source(source, &PromptVars::default()).map(|_| ())
+}
+
+/// Override files are named after their context
+fn context_of(path: &Path) -> Result<Pubkey, OracleError> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    Pubkey::from_str(stem).map_err(|e| {
+        format!(
+            "Prompt template {:?} isn't named after a context pubkey: {}",
+            path, e
+        )
+        .into()
+    })
+}
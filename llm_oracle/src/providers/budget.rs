@@ -0,0 +1,238 @@
+use super::{ChatMessage, ChatProvider, FunctionReply, FunctionRound, FunctionSpec, ProviderError};
+use crate::costs::{self, Tokens};
+use crate::incidents;
+use crate::metrics::METRICS;
+use crate::notify::{Event, Severity};
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::warn;
+
+/// Opened while a provider is out of budget for the day
+pub const BUDGET_EXHAUSTED: &str = "budget_exhausted";
+
+/// A call refused because the provider's daily budget is spent
+#[derive(Debug, Clone)]
+pub struct BudgetExhausted {
+    pub provider: String,
+    /// Until the budget is reset, at midnight UTC
+    pub resets_in: Duration,
+}
+
+impl fmt::Display for BudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Daily budget of {} exhausted, reset in {}s",
+            self.provider,
+            self.resets_in.as_secs()
+        )
+    }
+}
+
+impl Error for BudgetExhausted {}
+
+/// Limits of a [`SpendBudget`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub daily_tokens: Option<u64>,
+    pub daily_usd: Option<f64>,
+    /// Price of the provider's tokens, prompt and completion alike
+    pub usd_per_1k_tokens: f64,
+}
+
+struct Spent {
+    day: NaiveDate,
+    tokens: u64,
+}
+
+/// Spending of each provider today
+static SPENT: LazyLock<Mutex<HashMap<String, Arc<Mutex<Spent>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Refuses calls to a provider once the tokens sent to and received from it today reach
+/// `daily_tokens`, or their price reaches `daily_usd`. Tokens are counted with the provider's
+/// tokenizer, an estimate of what it bills. Every client of a provider counts against the same
+/// spending, those of other programs and context settings included, and it outlives reloads.
+pub struct SpendBudget {
+    inner: Box<dyn ChatProvider>,
+    budget: Budget,
+    spent: Arc<Mutex<Spent>>,
+}
+
+fn until_midnight() -> Duration {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + chrono::Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    (midnight - now).to_std().unwrap_or_default()
+}
+
+impl SpendBudget {
+    pub fn new(inner: Box<dyn ChatProvider>, budget: Budget) -> Self {
+        let spent = SPENT
+            .lock()
+            .unwrap()
+            .entry(inner.name().to_string())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Spent {
+                    day: Utc::now().date_naive(),
+                    tokens: 0,
+                }))
+            })
+            .clone();
+        Self {
+            inner,
+            budget,
+            spent,
+        }
+    }
+
+    fn exhausted(&self, tokens: u64) -> bool {
+        let cost = tokens as f64 / 1000.0 * self.budget.usd_per_1k_tokens;
+        self.budget
+            .daily_tokens
+            .is_some_and(|limit| tokens >= limit)
+            || self.budget.daily_usd.is_some_and(|limit| cost >= limit)
+    }
+
+    /// Fail with [`BudgetExhausted`] once today's budget is spent
+    fn check(&self) -> Result<(), ProviderError> {
+        let provider = self.inner.name();
+        let mut spent = self.spent.lock().unwrap();
+        let today = Utc::now().date_naive();
+        if spent.day != today {
+            *spent = Spent {
+                day: today,
+                tokens: 0,
+            };
+            incidents::resolve(BUDGET_EXHAUSTED, provider);
+        }
+        if !self.exhausted(spent.tokens) {
+            return Ok(());
+        }
+        let exhausted = BudgetExhausted {
+            provider: provider.to_string(),
+            resets_in: until_midnight(),
+        };
+        incidents::trigger(
+            Event::new(
+                Severity::Warning,
+                BUDGET_EXHAUSTED,
+                format!("LLM provider {} is out of budget", provider),
+                format!(
+                    "{} tokens spent today (${:.2}); calls are refused until midnight UTC",
+                    spent.tokens,
+                    spent.tokens as f64 / 1000.0 * self.budget.usd_per_1k_tokens
+                ),
+            )
+            .with_key(provider),
+        );
+        Err(exhausted.into())
+    }
+
+    /// Count the tokens of a call: those reported by the provider's meter, or else an estimate
+    fn spend(&self, messages: &[ChatMessage], reply: &str, reported: Option<Tokens>) {
+        let tokens = match reported {
+            Some(reported) => reported.total(),
+            None => {
+                (messages
+                    .iter()
+                    .map(|message| self.inner.count_tokens(&message.content))
+                    .sum::<usize>()
+                    + self.inner.count_tokens(reply)) as u64
+            }
+        };
+        let mut spent = self.spent.lock().unwrap();
+        spent.tokens += tokens;
+        METRICS
+            .budget_tokens_spent
+            .with_label_values(&[self.inner.name()])
+            .set(spent.tokens as i64);
+        if self.exhausted(spent.tokens) {
+            warn!(
+                provider = self.inner.name(),
+                tokens = spent.tokens,
+                "Daily budget exhausted"
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for SpendBudget {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        self.check()?;
+        let (reply, reported) = costs::reported_usage(self.inner.send_message(messages)).await;
+        let reply = reply?;
+        self.spend(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.check()?;
+        let (reply, reported) =
+            costs::reported_usage(self.inner.send_structured(messages, schema)).await;
+        let reply = reply?;
+        self.spend(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        self.check()?;
+        let (reply, reported) =
+            costs::reported_usage(self.inner.send_with_functions(messages, functions, rounds))
+                .await;
+        let reply = reply?;
+        let text = match &reply {
+            FunctionReply::Text(text) => text.clone(),
+            FunctionReply::Calls(calls) => calls
+                .iter()
+                .map(|call| format!("{}{}", call.name, call.arguments))
+                .collect(),
+        };
+        self.spend(messages, &text, reported);
+        Ok(reply)
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        self.check()?;
+        let (reply, reported) =
+            costs::reported_usage(self.inner.stream_message(messages, deltas)).await;
+        let reply = reply?;
+        self.spend(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.inner.count_tokens(text)
+    }
+
+    fn sees_images(&self) -> bool {
+        self.inner.sees_images()
+    }
+}
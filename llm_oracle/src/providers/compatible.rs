@@ -0,0 +1,477 @@
+use super::{
+    sse, ApiError, ChatMessage, ChatProvider, FunctionCall, FunctionReply, FunctionRound,
+    FunctionSpec, GenerationParams, Image, ProviderError, Role,
+};
+use crate::costs::{self, Tokens};
+use crate::response_length;
+use crate::trace;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+
+/// Client for self-hosted OpenAI-compatible chat completions endpoints (Ollama, vLLM,
+/// LM Studio, ...). `base_url` is the API root, e.g. `http://localhost:11434/v1`. Images are
+/// sent as `image_url` parts, to models declared with `llm.vision`.
+pub struct OpenAICompatibleClient {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    params: GenerationParams,
+    client: reqwest::Client,
+    /// Ask for the usage of streamed replies, which not every endpoint supports
+    stream_usage: bool,
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<CompletionMessage<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<CompletionTool<'a>>,
+    /// Send the reply as server-sent events of [`CompletionChunk`]s
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+/// Ask for a last event with the usage of a streamed reply
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+/// JSON mode: the reply is a JSON object, its schema is given in the prompt
+#[derive(Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+#[derive(Serialize)]
+struct CompletionTool<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: CompletionFunctionSpec<'a>,
+}
+
+#[derive(Serialize)]
+struct CompletionFunctionSpec<'a> {
+    name: &'a str,
+    description: &'a str,
+    parameters: &'a Value,
+}
+
+#[derive(Serialize)]
+struct CompletionMessage<'a> {
+    role: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<CompletionContent<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<CompletionToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<&'a str>,
+}
+
+impl<'a> CompletionMessage<'a> {
+    fn new(role: &'static str, content: &'a str) -> Self {
+        Self {
+            role,
+            content: Some(CompletionContent::Text(content)),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// A message with images is sent as parts, the text first
+    fn with_images(role: &'static str, content: &'a str, images: &[Image]) -> Self {
+        if images.is_empty() {
+            return Self::new(role, content);
+        }
+        let parts = std::iter::once(CompletionPart::Text { text: content })
+            .chain(images.iter().map(|image| CompletionPart::ImageUrl {
+                image_url: CompletionImageUrl {
+                    url: image.data_url(),
+                },
+            }))
+            .collect();
+        Self {
+            content: Some(CompletionContent::Parts(parts)),
+            ..Self::new(role, content)
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum CompletionContent<'a> {
+    Text(&'a str),
+    Parts(Vec<CompletionPart<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CompletionPart<'a> {
+    Text { text: &'a str },
+    ImageUrl { image_url: CompletionImageUrl },
+}
+
+#[derive(Serialize)]
+struct CompletionImageUrl {
+    url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CompletionToolCall {
+    #[serde(default)]
+    id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    kind: String,
+    function: CompletionFunctionCall,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+/// `arguments` is a JSON object serialized as a string
+#[derive(Serialize, Deserialize)]
+struct CompletionFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+    usage: Option<CompletionUsage>,
+}
+
+/// Tokens billed for a request
+#[derive(Deserialize)]
+struct CompletionUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+impl From<CompletionUsage> for Tokens {
+    fn from(usage: CompletionUsage) -> Self {
+        Tokens {
+            prompt: usage.prompt_tokens,
+            completion: usage.completion_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    message: CompletionResponseMessage,
+    /// `length` when the reply stopped at `max_tokens`
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<CompletionToolCall>,
+}
+
+/// An event of a streamed reply
+#[derive(Deserialize)]
+struct CompletionChunk {
+    #[serde(default)]
+    choices: Vec<CompletionChunkChoice>,
+    /// In the last event, with `stream_options.include_usage`
+    usage: Option<CompletionUsage>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChunkChoice {
+    delta: CompletionDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CompletionDelta {
+    content: Option<String>,
+}
+
+impl OpenAICompatibleClient {
+    pub fn new(
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+        params: GenerationParams,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            params,
+            client: reqwest::Client::new(),
+            stream_usage: false,
+        }
+    }
+
+    /// Ask for the usage of streamed replies too, in a last event
+    pub fn with_stream_usage(mut self) -> Self {
+        self.stream_usage = true;
+        self
+    }
+
+    /// Chat completion, in JSON mode when `json` is set
+    async fn complete(
+        &self,
+        messages: &[ChatMessage],
+        json: bool,
+    ) -> Result<String, ProviderError> {
+        let message = self.request(messages, json, &[], &[]).await?;
+        message
+            .content
+            .ok_or_else(|| "No content in LLM endpoint response".into())
+    }
+
+    /// The reply message to `messages`, followed by the function calls and results of `rounds`
+    async fn request(
+        &self,
+        messages: &[ChatMessage],
+        json: bool,
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<CompletionResponseMessage, ProviderError> {
+        let response = self.post(messages, json, functions, rounds, false).await?;
+        let completion: CompletionResponse = response.json().await?;
+        if let Some(usage) = completion.usage {
+            costs::report_usage(usage.into());
+        }
+        completion
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| {
+                if choice.finish_reason.as_deref() == Some("length") {
+                    response_length::report_capped();
+                }
+                choice.message
+            })
+            .ok_or_else(|| "No choices in LLM endpoint response".into())
+    }
+
+    /// Chat completion streamed to `deltas`
+    async fn stream(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let response = self.post(messages, false, &[], &[], true).await?;
+        let mut reply = String::new();
+        sse::read_events(response, |data| {
+            let chunk: CompletionChunk = serde_json::from_str(data)?;
+            if let Some(usage) = chunk.usage {
+                costs::report_usage(usage.into());
+            }
+            for choice in &chunk.choices {
+                if choice.finish_reason.as_deref() == Some("length") {
+                    response_length::report_capped();
+                }
+            }
+            for content in chunk
+                .choices
+                .into_iter()
+                .filter_map(|choice| choice.delta.content)
+            {
+                reply.push_str(&content);
+                let _ = deltas.send(content);
+            }
+            Ok(())
+        })
+        .await?;
+        if reply.is_empty() {
+            return Err("No content in LLM endpoint response".into());
+        }
+        Ok(reply)
+    }
+
+    /// Send a chat completion request, failing on an error status
+    async fn post(
+        &self,
+        messages: &[ChatMessage],
+        json: bool,
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+        stream: bool,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let params = self.params.live();
+        let mut request_messages: Vec<CompletionMessage> = params
+            .system_prompt
+            .iter()
+            .map(|prompt| CompletionMessage::new("system", prompt))
+            .chain(messages.iter().map(|message| {
+                let role = match message.role {
+                    Role::System => "system",
+                    Role::Assistant => "assistant",
+                    _ => "user",
+                };
+                CompletionMessage::with_images(role, &message.content, &message.images)
+            }))
+            .collect();
+        for round in rounds {
+            request_messages.push(CompletionMessage {
+                role: "assistant",
+                content: None,
+                tool_calls: round
+                    .calls
+                    .iter()
+                    .map(|call| CompletionToolCall {
+                        id: call.id.clone(),
+                        kind: function_kind(),
+                        function: CompletionFunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.to_string(),
+                        },
+                    })
+                    .collect(),
+                tool_call_id: None,
+            });
+            for (call, result) in round.calls.iter().zip(&round.results) {
+                request_messages.push(CompletionMessage {
+                    tool_call_id: Some(&call.id),
+                    ..CompletionMessage::new("tool", result)
+                });
+            }
+        }
+        let request = CompletionRequest {
+            model: &self.model,
+            messages: request_messages,
+            temperature: params.temperature,
+            max_tokens: response_length::max_tokens(params.max_tokens),
+            top_p: params.top_p,
+            presence_penalty: params.presence_penalty,
+            frequency_penalty: params.frequency_penalty,
+            response_format: json.then_some(ResponseFormat {
+                kind: "json_object",
+            }),
+            tools: functions
+                .iter()
+                .map(|function| CompletionTool {
+                    kind: "function",
+                    function: CompletionFunctionSpec {
+                        name: function.name,
+                        description: function.description,
+                        parameters: &function.parameters,
+                    },
+                })
+                .collect(),
+            stream,
+            stream_options: (stream && self.stream_usage).then_some(StreamOptions {
+                include_usage: true,
+            }),
+        };
+
+        let mut builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&request);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        if let Some(trace_id) = trace::current() {
+            builder = builder
+                .header("X-Client-Request-Id", &trace_id)
+                .header("X-Request-Id", &trace_id);
+        }
+        let response = builder.send().await?;
+        if let Some(request_id) = response.headers().get("x-request-id") {
+            debug!(request_id = ?request_id, "LLM endpoint request");
+        }
+        if !response.status().is_success() {
+            return Err(ApiError::from_response("LLM endpoint error", response)
+                .await
+                .into());
+        }
+        Ok(response)
+    }
+
+    /// Chat completion offering `functions` to the model
+    async fn complete_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        let message = self.request(messages, false, functions, rounds).await?;
+        if message.tool_calls.is_empty() {
+            return message
+                .content
+                .map(FunctionReply::Text)
+                .ok_or_else(|| "No content in LLM endpoint response".into());
+        }
+        let calls = message
+            .tool_calls
+            .into_iter()
+            .map(|call| FunctionCall {
+                id: call.id,
+                arguments: serde_json::from_str(&call.function.arguments).unwrap_or(Value::Null),
+                name: call.function.name,
+            })
+            .collect();
+        Ok(FunctionReply::Calls(calls))
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OpenAICompatibleClient {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        self.complete(messages, false).await
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        _schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.complete(messages, true).await
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        self.complete_with_functions(messages, functions, rounds)
+            .await
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        self.stream(messages, deltas).await
+    }
+
+    fn sees_images(&self) -> bool {
+        self.params.vision.unwrap_or(false)
+    }
+}
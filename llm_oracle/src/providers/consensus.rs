@@ -0,0 +1,192 @@
+use super::{ChatMessage, ChatProvider, ProviderError};
+use crate::metrics::METRICS;
+use async_trait::async_trait;
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use tracing::{debug, warn};
+
+/// How the answers of the providers of a [`ConsensusProvider`] are reconciled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsensusPolicy {
+    /// Every provider gives the same answer
+    Exact,
+    /// More than half of the providers give the same answer
+    Majority,
+    /// The median of the numbers answered, when more than half of the providers answer one
+    Median,
+}
+
+impl FromStr for ConsensusPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "exact" => Ok(Self::Exact),
+            "majority" => Ok(Self::Majority),
+            "median" => Ok(Self::Median),
+            other => Err(format!(
+                "expected exact, majority or median, got {:?}",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConsensusPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact => write!(f, "exact"),
+            Self::Majority => write!(f, "majority"),
+            Self::Median => write!(f, "median"),
+        }
+    }
+}
+
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-?\d+(?:\.\d+)?").unwrap());
+
+/// Answers compared for agreement: JSON re-serialized, text trimmed, lowercased and without
+/// trailing punctuation
+fn normalize(answer: &str) -> String {
+    match serde_json::from_str::<Value>(answer) {
+        Ok(value) => value.to_string(),
+        Err(_) => answer
+            .trim()
+            .trim_end_matches(['.', '!'])
+            .trim()
+            .to_lowercase(),
+    }
+}
+
+/// Queries every provider in parallel and answers only when their answers agree according to
+/// the policy. A provider that fails counts as a dissenting answer.
+pub struct ConsensusProvider {
+    providers: Vec<Box<dyn ChatProvider>>,
+    policy: ConsensusPolicy,
+}
+
+impl ConsensusProvider {
+    pub fn new(providers: Vec<Box<dyn ChatProvider>>, policy: ConsensusPolicy) -> Self {
+        Self { providers, policy }
+    }
+
+    async fn send(
+        &self,
+        messages: &[ChatMessage],
+        schema: Option<&Value>,
+    ) -> Result<String, ProviderError> {
+        let results = join_all(self.providers.iter().map(|provider| async move {
+            match schema {
+                Some(schema) => provider.send_structured(messages, schema).await,
+                None => provider.send_message(messages).await,
+            }
+        }))
+        .await;
+        let mut answers = Vec::new();
+        for (provider, result) in self.providers.iter().zip(results) {
+            match result {
+                Ok(answer) => {
+                    debug!(provider = provider.name(), %answer, "Consensus answer");
+                    answers.push(answer);
+                }
+                Err(e) => {
+                    warn!(provider = provider.name(), error = ?e, "Consensus provider failed")
+                }
+            }
+        }
+
+        let agreed = self.reconcile(&answers);
+        let outcome = if agreed.is_some() {
+            "agreed"
+        } else {
+            "disagreed"
+        };
+        METRICS.consensus.with_label_values(&[outcome]).inc();
+        agreed.ok_or_else(|| {
+            format!(
+                "No {} consensus between {} answer(s) of {} providers",
+                self.policy,
+                answers.len(),
+                self.providers.len()
+            )
+            .into()
+        })
+    }
+
+    fn reconcile(&self, answers: &[String]) -> Option<String> {
+        let total = self.providers.len();
+        match self.policy {
+            ConsensusPolicy::Exact => {
+                let first = answers.first()?;
+                let normalized = normalize(first);
+                (answers.len() == total
+                    && answers.iter().all(|answer| normalize(answer) == normalized))
+                .then(|| first.clone())
+            }
+            ConsensusPolicy::Majority => {
+                let mut votes: HashMap<String, (usize, &String)> = HashMap::new();
+                for answer in answers {
+                    votes.entry(normalize(answer)).or_insert((0, answer)).0 += 1;
+                }
+                votes
+                    .into_values()
+                    .find(|(count, _)| *count * 2 > total)
+                    .map(|(_, answer)| answer.clone())
+            }
+            ConsensusPolicy::Median => {
+                let mut numbers: Vec<f64> = answers
+                    .iter()
+                    .filter_map(|answer| NUMBER.find(answer)?.as_str().parse().ok())
+                    .collect();
+                if numbers.len() * 2 <= total {
+                    return None;
+                }
+                numbers.sort_by(f64::total_cmp);
+                let middle = numbers.len() / 2;
+                let median = if numbers.len() % 2 == 0 {
+                    (numbers[middle - 1] + numbers[middle]) / 2.0
+                } else {
+                    numbers[middle]
+                };
+                Some(median.to_string())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ConsensusProvider {
+    fn name(&self) -> &str {
+        "consensus"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        self.send(messages, None).await
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.send(messages, Some(schema)).await
+    }
+
+    /// The largest count of the providers, so the history fits all of them
+    fn count_tokens(&self, text: &str) -> usize {
+        self.providers
+            .iter()
+            .map(|provider| provider.count_tokens(text))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Only when all the providers do, so they answer the same question
+    fn sees_images(&self) -> bool {
+        self.providers.iter().all(|provider| provider.sees_images())
+    }
+}
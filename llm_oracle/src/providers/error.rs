@@ -0,0 +1,148 @@
+use super::ProviderError;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Phrases of the errors returned for prompts over the model's context window, by status 400
+/// or 413 depending on the API
+const CONTEXT_LENGTH_MARKERS: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "exceeds the maximum number of tokens",
+    "prompt is too long",
+    "too many tokens",
+];
+
+/// An error status returned by an LLM API
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    /// e.g. `Gemini API error`
+    pub label: &'static str,
+    pub status: StatusCode,
+    /// From the `Retry-After` header
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl ApiError {
+    /// The error of `response`, which has an error status
+    pub async fn from_response(label: &'static str, response: reqwest::Response) -> Self {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        Self {
+            label,
+            status,
+            retry_after,
+            body,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.label, self.status, self.body)
+    }
+}
+
+impl Error for ApiError {}
+
+/// `Retry-After` as a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    (at.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+/// What a failed call says about retrying it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// 429: retry after `Retry-After`, or backing off
+    RateLimited,
+    /// 5xx, timeouts and connection errors: retry backing off
+    Server,
+    /// The prompt is over the model's context window: retry with less history
+    ContextLength,
+    /// Other 4xx: the same request fails again
+    BadRequest,
+    /// Anything else, retried backing off
+    Other,
+}
+
+impl ErrorClass {
+    /// Label of the `llm_errors_total` metric
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorClass::RateLimited => "rate_limited",
+            ErrorClass::Server => "server",
+            ErrorClass::ContextLength => "context_length",
+            ErrorClass::BadRequest => "bad_request",
+            ErrorClass::Other => "other",
+        }
+    }
+
+    /// Whether sending the same request again may succeed
+    pub fn retryable(self) -> bool {
+        !matches!(self, ErrorClass::ContextLength | ErrorClass::BadRequest)
+    }
+}
+
+fn is_context_length(message: &str) -> bool {
+    let message = message.to_lowercase();
+    CONTEXT_LENGTH_MARKERS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+/// Classify an error of a provider. The errors of clients without access to the status, such
+/// as `chatgpt`'s, are classified from their message.
+pub fn classify(error: &ProviderError) -> ErrorClass {
+    if let Some(error) = error.downcast_ref::<ApiError>() {
+        return match error.status {
+            StatusCode::TOO_MANY_REQUESTS => ErrorClass::RateLimited,
+            status if status.is_server_error() => ErrorClass::Server,
+            StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE
+                if is_context_length(&error.body) =>
+            {
+                ErrorClass::ContextLength
+            }
+            // 401, 403, 404...: the same request fails again
+            status if status.is_client_error() && status != StatusCode::REQUEST_TIMEOUT => {
+                ErrorClass::BadRequest
+            }
+            _ => ErrorClass::Server,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+        return match error.status() {
+            Some(StatusCode::TOO_MANY_REQUESTS) => ErrorClass::RateLimited,
+            Some(status) if status.is_client_error() => ErrorClass::BadRequest,
+            _ => ErrorClass::Server,
+        };
+    }
+    let message = error.to_string();
+    if is_context_length(&message) {
+        ErrorClass::ContextLength
+    } else if message.contains("rate limit") || message.contains("rate_limit") {
+        ErrorClass::RateLimited
+    } else {
+        ErrorClass::Other
+    }
+}
+
+/// The `Retry-After` of an error, if the API sent one
+pub fn retry_after(error: &ProviderError) -> Option<Duration> {
+    error
+        .downcast_ref::<ApiError>()
+        .and_then(|error| error.retry_after)
+}
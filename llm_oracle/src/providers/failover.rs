@@ -0,0 +1,195 @@
+use super::{
+    BudgetExhausted, ChatMessage, ChatProvider, FunctionReply, FunctionRound, FunctionSpec,
+    ProviderError,
+};
+use crate::incidents::{self, PROVIDER_HARD_DOWN};
+use crate::metrics::METRICS;
+use crate::notify::{self, Event, Severity};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    /// Set when the breaker trips; the provider is skipped until then
+    open_until: Option<Instant>,
+}
+
+/// Tries an ordered list of providers until one answers. A provider failing
+/// `breaker_threshold` times in a row is skipped for `cooldown`, then given another chance:
+/// one success reinstates it, one failure trips the breaker again.
+pub struct FailoverProvider {
+    providers: Vec<Box<dyn ChatProvider>>,
+    health: Vec<Mutex<Health>>,
+    breaker_threshold: u32,
+    cooldown: Duration,
+}
+
+impl FailoverProvider {
+    pub fn new(
+        providers: Vec<Box<dyn ChatProvider>>,
+        breaker_threshold: u32,
+        cooldown: Duration,
+    ) -> Self {
+        let health = providers.iter().map(|_| Mutex::default()).collect();
+        Self {
+            providers,
+            health,
+            breaker_threshold: breaker_threshold.max(1),
+            cooldown,
+        }
+    }
+
+    fn is_available(&self, index: usize) -> bool {
+        let health = self.health[index].lock().unwrap();
+        health
+            .open_until
+            .map_or(true, |open_until| Instant::now() >= open_until)
+    }
+
+    fn record_success(&self, index: usize) {
+        let mut health = self.health[index].lock().unwrap();
+        if health.open_until.take().is_some() {
+            info!(
+                provider = self.providers[index].name(),
+                "LLM provider reinstated"
+            );
+            incidents::resolve(PROVIDER_HARD_DOWN, self.providers[index].name());
+        }
+        health.consecutive_failures = 0;
+    }
+
+    fn record_failure(&self, index: usize) {
+        let mut health = self.health[index].lock().unwrap();
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.breaker_threshold {
+            // Still failing when given another chance after the cooldown
+            let hard_down = health.open_until.is_some();
+            health.open_until = Some(Instant::now() + self.cooldown);
+            warn!(
+                provider = self.providers[index].name(),
+                failures = health.consecutive_failures,
+                cooldown = ?self.cooldown,
+                "LLM provider circuit breaker tripped"
+            );
+            notify::emit(
+                Event::new(
+                    Severity::Warning,
+                    "provider_down",
+                    format!("LLM provider {} is failing", self.providers[index].name()),
+                    format!(
+                        "{} consecutive failures, skipped for {:?}",
+                        health.consecutive_failures, self.cooldown
+                    ),
+                )
+                .with_key(self.providers[index].name()),
+            );
+            if hard_down {
+                incidents::trigger(
+                    Event::new(
+                        Severity::Critical,
+                        PROVIDER_HARD_DOWN,
+                        format!("LLM provider {} is down", self.providers[index].name()),
+                        format!(
+                            "Still failing after its {:?} circuit breaker cooldown, {} \
+                             consecutive failures",
+                            self.cooldown, health.consecutive_failures
+                        ),
+                    )
+                    .with_key(self.providers[index].name()),
+                );
+            }
+        }
+    }
+
+    /// Send with `request` to the first available provider that answers
+    async fn send<'a, T>(
+        &'a self,
+        request: impl Fn(&'a dyn ChatProvider) -> BoxFuture<'a, Result<T, ProviderError>>,
+    ) -> Result<T, ProviderError> {
+        // When every breaker is open, try them all anyway rather than failing outright
+        let mut order: Vec<usize> = (0..self.providers.len())
+            .filter(|&index| self.is_available(index))
+            .collect();
+        if order.is_empty() {
+            order = (0..self.providers.len()).collect();
+        }
+
+        let mut last_error: ProviderError = "No LLM provider configured".into();
+        for index in order {
+            let provider = self.providers[index].as_ref();
+            match request(provider).await {
+                Ok(response) => {
+                    self.record_success(index);
+                    return Ok(response);
+                }
+                // Out of budget isn't a failure: the provider is skipped until midnight UTC
+                Err(e) if e.is::<BudgetExhausted>() => {
+                    debug!(
+                        provider = provider.name(),
+                        "LLM provider out of budget, failing over"
+                    );
+                    last_error = e;
+                }
+                Err(e) => {
+                    warn!(provider = provider.name(), error = ?e, "LLM provider failed, failing over");
+                    METRICS
+                        .llm_failovers
+                        .with_label_values(&[provider.name()])
+                        .inc();
+                    self.record_failure(index);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[async_trait]
+impl ChatProvider for FailoverProvider {
+    fn name(&self) -> &str {
+        "failover"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        self.send(|provider| provider.send_message(messages)).await
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.send(|provider| provider.send_structured(messages, schema))
+            .await
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        self.send(|provider| provider.send_with_functions(messages, functions, rounds))
+            .await
+    }
+
+    /// The largest count of the chain, so the history fits whichever provider answers
+    fn count_tokens(&self, text: &str) -> usize {
+        self.providers
+            .iter()
+            .map(|provider| provider.count_tokens(text))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// Only when every provider of the chain does, whichever answers
+    fn sees_images(&self) -> bool {
+        self.providers.iter().all(|provider| provider.sees_images())
+    }
+}
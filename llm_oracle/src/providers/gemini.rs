@@ -0,0 +1,456 @@
+use super::{
+    sse, ApiError, ChatMessage, ChatProvider, FunctionCall, FunctionReply, FunctionRound,
+    FunctionSpec, GenerationParams, Image, ProviderError, Role,
+};
+use crate::costs::{self, Tokens};
+use crate::response_length;
+use crate::trace;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Client metadata of the requests, followed by the trace ID, see [`crate::trace`]
+const CLIENT_METADATA: &str = concat!("llm-oracle/", env!("CARGO_PKG_VERSION"));
+
+// Gemini API Client
+pub struct GeminiClient {
+    api_key: String,
+    model: String,
+    params: GenerationParams,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct GeminiRequest {
+    contents: Vec<GeminiContent>,
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
+    #[serde(rename = "generationConfig")]
+    generation_config: GeminiGenerationConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<GeminiTool>,
+}
+
+#[derive(Serialize)]
+struct GeminiTool {
+    #[serde(rename = "functionDeclarations")]
+    function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Serialize)]
+struct GeminiFunctionDeclaration {
+    name: &'static str,
+    description: &'static str,
+    #[serde(rename = "parametersJsonSchema")]
+    parameters_json_schema: Value,
+}
+
+#[derive(Serialize)]
+struct GeminiContent {
+    parts: Vec<GeminiPart>,
+    role: String,
+}
+
+#[derive(Serialize, Default)]
+struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(rename = "inlineData", skip_serializing_if = "Option::is_none")]
+    inline_data: Option<GeminiInlineData>,
+    #[serde(rename = "functionCall", skip_serializing_if = "Option::is_none")]
+    function_call: Option<Value>,
+    #[serde(rename = "functionResponse", skip_serializing_if = "Option::is_none")]
+    function_response: Option<Value>,
+}
+
+impl GeminiPart {
+    fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            ..Self::default()
+        }
+    }
+
+    fn image(image: &Image) -> Self {
+        Self {
+            inline_data: Some(GeminiInlineData {
+                mime_type: image.mime_type.clone(),
+                data: image.data.clone(),
+            }),
+            ..Self::default()
+        }
+    }
+}
+
+/// An image, base64-encoded
+#[derive(Serialize)]
+struct GeminiInlineData {
+    #[serde(rename = "mimeType")]
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct GeminiGenerationConfig {
+    temperature: f32,
+    #[serde(rename = "maxOutputTokens")]
+    max_output_tokens: u32,
+    #[serde(rename = "topP", skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(rename = "presencePenalty", skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<&'static str>,
+    #[serde(rename = "responseJsonSchema", skip_serializing_if = "Option::is_none")]
+    response_json_schema: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponse {
+    candidates: Vec<GeminiCandidate>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsage>,
+}
+
+/// Tokens billed for a request; thinking tokens are billed as output
+#[derive(Deserialize, Clone, Copy)]
+struct GeminiUsage {
+    #[serde(rename = "promptTokenCount", default)]
+    prompt_token_count: u64,
+    #[serde(rename = "candidatesTokenCount", default)]
+    candidates_token_count: u64,
+    #[serde(rename = "thoughtsTokenCount", default)]
+    thoughts_token_count: u64,
+}
+
+impl From<GeminiUsage> for Tokens {
+    fn from(usage: GeminiUsage) -> Self {
+        Tokens {
+            prompt: usage.prompt_token_count,
+            completion: usage.candidates_token_count + usage.thoughts_token_count,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GeminiCandidate {
+    content: GeminiResponseContent,
+    /// `MAX_TOKENS` when the reply stopped at `maxOutputTokens`
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponseContent {
+    parts: Vec<GeminiResponsePart>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponsePart {
+    text: Option<String>,
+    #[serde(rename = "functionCall")]
+    function_call: Option<GeminiFunctionCall>,
+}
+
+/// An event of a streamed reply; the last one may only carry the finish reason
+#[derive(Deserialize)]
+struct GeminiStreamChunk {
+    #[serde(default)]
+    candidates: Vec<GeminiStreamCandidate>,
+    /// Running totals, complete in the last event
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsage>,
+}
+
+#[derive(Deserialize)]
+struct GeminiStreamCandidate {
+    content: Option<GeminiResponseContent>,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeminiFunctionCall {
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+impl GeminiClient {
+    pub fn new(api_key: String, model: String, params: GenerationParams) -> Self {
+        Self {
+            api_key,
+            model,
+            params,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Generate a reply, constrained to JSON matching `schema` when set
+    async fn generate(
+        &self,
+        messages: &[ChatMessage],
+        schema: Option<&Value>,
+    ) -> Result<String, ProviderError> {
+        match self.generate_with_functions(messages, schema, &[], &[]).await? {
+            FunctionReply::Text(text) => Ok(text),
+            FunctionReply::Calls(_) => Err("Unexpected function call from Gemini API".into()),
+        }
+    }
+
+    /// Generate a reply streamed to `deltas`
+    async fn stream(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let request = self.request(messages, None, &[], &[])?;
+        let response = self.post(&request, "streamGenerateContent?alt=sse").await?;
+        let mut reply = String::new();
+        let mut usage = None;
+        sse::read_events(response, |data| {
+            let chunk: GeminiStreamChunk = serde_json::from_str(data)?;
+            usage = chunk.usage_metadata.or(usage);
+            let texts = chunk
+                .candidates
+                .into_iter()
+                .take(1)
+                .filter_map(|candidate| {
+                    if candidate.finish_reason.as_deref() == Some("MAX_TOKENS") {
+                        response_length::report_capped();
+                    }
+                    candidate.content
+                })
+                .flat_map(|content| content.parts)
+                .filter_map(|part| part.text);
+            for text in texts {
+                reply.push_str(&text);
+                let _ = deltas.send(text);
+            }
+            Ok(())
+        })
+        .await?;
+        if let Some(usage) = usage {
+            costs::report_usage(usage.into());
+        }
+        if reply.is_empty() {
+            return Err("No response from Gemini API".into());
+        }
+        Ok(reply)
+    }
+
+    /// Generate a reply to `messages` followed by the function calls and results of `rounds`,
+    /// which may call one of `functions`
+    async fn generate_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        schema: Option<&Value>,
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        let request = self.request(messages, schema, functions, rounds)?;
+        let response = self.post(&request, "generateContent").await?;
+        let gemini_response: GeminiResponse = response.json().await?;
+        if let Some(usage) = gemini_response.usage_metadata {
+            costs::report_usage(usage.into());
+        }
+
+        if let Some(candidate) = gemini_response.candidates.into_iter().next() {
+            if candidate.finish_reason.as_deref() == Some("MAX_TOKENS") {
+                response_length::report_capped();
+            }
+            let mut calls = Vec::new();
+            let mut text = None;
+            for part in candidate.content.parts {
+                if let Some(call) = part.function_call {
+                    calls.push(FunctionCall {
+                        id: format!("call_{}", calls.len()),
+                        name: call.name,
+                        arguments: call.args,
+                    });
+                } else if text.is_none() {
+                    text = part.text;
+                }
+            }
+            if !calls.is_empty() {
+                return Ok(FunctionReply::Calls(calls));
+            }
+            if let Some(text) = text {
+                return Ok(FunctionReply::Text(text));
+            }
+        }
+
+        Err("No response from Gemini API".into())
+    }
+
+    /// The request for a reply to `messages` followed by the function calls and results of
+    /// `rounds`
+    fn request(
+        &self,
+        messages: &[ChatMessage],
+        schema: Option<&Value>,
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<GeminiRequest, ProviderError> {
+        // 0xAbim: Added validation to prevent empty contents array
+        if messages.is_empty() {
+            return Err("Cannot send empty message history to Gemini API".into());
+        }
+
+        // Convert ChatMessage history to Gemini format
+        let mut contents: Vec<GeminiContent> = messages
+            .iter()
+            .map(|msg| {
+                let role = match msg.role {
+                    Role::User => "user",
+                    Role::System => "user", // Gemini doesn't have system role
+                    Role::Assistant => "model",
+                    Role::Function => "model", // Treat function as model
+                };
+                // Images follow the text of their message
+                let parts = std::iter::once(GeminiPart::text(msg.content.clone()))
+                    .chain(msg.images.iter().map(GeminiPart::image))
+                    .collect();
+                GeminiContent {
+                    parts,
+                    role: role.to_string(),
+                }
+            })
+            .collect();
+        for round in rounds {
+            contents.push(GeminiContent {
+                parts: round
+                    .calls
+                    .iter()
+                    .map(|call| GeminiPart {
+                        function_call: Some(json!({ "name": call.name, "args": call.arguments })),
+                        ..GeminiPart::default()
+                    })
+                    .collect(),
+                role: "model".to_string(),
+            });
+            contents.push(GeminiContent {
+                parts: round
+                    .calls
+                    .iter()
+                    .zip(&round.results)
+                    .map(|(call, result)| GeminiPart {
+                        function_response: Some(json!({
+                            "name": call.name,
+                            "response": { "result": result },
+                        })),
+                        ..GeminiPart::default()
+                    })
+                    .collect(),
+                role: "user".to_string(),
+            });
+        }
+
+        let params = self.params.live();
+        let request = GeminiRequest {
+            contents,
+            system_instruction: params.system_prompt.as_ref().map(|prompt| GeminiContent {
+                parts: vec![GeminiPart::text(prompt.clone())],
+                role: "user".to_string(),
+            }),
+            generation_config: GeminiGenerationConfig {
+                temperature: params.temperature.unwrap_or(0.7),
+                max_output_tokens: response_length::max_tokens(params.max_tokens),
+                top_p: params.top_p,
+                presence_penalty: params.presence_penalty,
+                frequency_penalty: params.frequency_penalty,
+                response_mime_type: schema.map(|_| "application/json"),
+                response_json_schema: schema.cloned(),
+            },
+            tools: if functions.is_empty() {
+                Vec::new()
+            } else {
+                vec![GeminiTool {
+                    function_declarations: functions
+                        .iter()
+                        .map(|function| GeminiFunctionDeclaration {
+                            name: function.name,
+                            description: function.description,
+                            parameters_json_schema: function.parameters.clone(),
+                        })
+                        .collect(),
+                }]
+            },
+        };
+        Ok(request)
+    }
+
+    /// Send `request` to the model's `method`, failing on an error status
+    async fn post(
+        &self,
+        request: &GeminiRequest,
+        method: &str,
+    ) -> Result<reqwest::Response, ProviderError> {
+        // 0xAbim: Added Gemini API endpoint 
+        let url = format!(
+            "https://generativelanguage.googleapis.com/v1beta/models/{}:{}",
+            self.model, method
+        );
+
+        let mut builder = self.client
+            .post(&url)
+            .header("x-goog-api-key", &self.api_key)
+            .header("Content-Type", "application/json");
+        if let Some(trace_id) = trace::current() {
+            builder = builder
+                .header("X-Request-Id", &trace_id)
+                .header("x-goog-api-client", format!("{} trace/{}", CLIENT_METADATA, trace_id));
+        }
+        let response = builder.json(request).send().await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::from_response("Gemini API error", response).await.into());
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl ChatProvider for GeminiClient {
+    fn name(&self) -> &str {
+        "gemini"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        self.generate(messages, None).await
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.generate(messages, Some(schema)).await
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        self.generate_with_functions(messages, None, functions, rounds)
+            .await
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        self.stream(messages, deltas).await
+    }
+
+    /// Gemini models take images, unless `llm.vision` says otherwise
+    fn sees_images(&self) -> bool {
+        self.params.vision.unwrap_or(true)
+    }
+}
@@ -0,0 +1,90 @@
+use super::{ChatMessage, ChatProvider, FunctionReply, FunctionRound, FunctionSpec, ProviderError};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Semaphore;
+
+/// Permits of each provider and the limit they were created with
+static PERMITS: LazyLock<Mutex<HashMap<String, (usize, Arc<Semaphore>)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Caps the requests in flight to a provider, independently of the worker pool size: calls
+/// beyond the limit wait for a permit. Every client of a provider draws from the same permits,
+/// those of other programs and context settings included.
+pub struct ConcurrencyLimit {
+    inner: Box<dyn ChatProvider>,
+    permits: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(inner: Box<dyn ChatProvider>, max_concurrent_requests: usize) -> Self {
+        let mut shared = PERMITS.lock().unwrap();
+        let entry = shared.entry(inner.name().to_string()).or_insert_with(|| {
+            (
+                max_concurrent_requests,
+                Arc::new(Semaphore::new(max_concurrent_requests)),
+            )
+        });
+        // A reloaded limit starts afresh; the clients built with the old one keep theirs
+        if entry.0 != max_concurrent_requests {
+            *entry = (
+                max_concurrent_requests,
+                Arc::new(Semaphore::new(max_concurrent_requests)),
+            );
+        }
+        let permits = entry.1.clone();
+        Self { inner, permits }
+    }
+}
+
+#[async_trait]
+impl ChatProvider for ConcurrencyLimit {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        let _permit = self.permits.acquire().await?;
+        self.inner.send_message(messages).await
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        let _permit = self.permits.acquire().await?;
+        self.inner.send_structured(messages, schema).await
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        let _permit = self.permits.acquire().await?;
+        self.inner
+            .send_with_functions(messages, functions, rounds)
+            .await
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let _permit = self.permits.acquire().await?;
+        self.inner.stream_message(messages, deltas).await
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.inner.count_tokens(text)
+    }
+
+    fn sees_images(&self) -> bool {
+        self.inner.sees_images()
+    }
+}
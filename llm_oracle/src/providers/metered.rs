@@ -0,0 +1,115 @@
+use super::{ChatMessage, ChatProvider, FunctionReply, FunctionRound, FunctionSpec, ProviderError};
+use crate::costs::{self, Tokens};
+use crate::metrics::METRICS;
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Reports the tokens of every successful call to the interaction's cost meter, see
+/// [`crate::costs`]: those the provider reported, or else those counted with its tokenizer. The
+/// tokens recorded are reported in turn to the wrappers of the provider, such as its budget.
+pub struct Metered {
+    inner: Box<dyn ChatProvider>,
+}
+
+impl Metered {
+    pub fn new(inner: Box<dyn ChatProvider>) -> Self {
+        Self { inner }
+    }
+
+    fn record(&self, messages: &[ChatMessage], reply: &str, reported: Option<Tokens>) {
+        let prompt: usize = messages
+            .iter()
+            .map(|message| self.inner.count_tokens(&message.content))
+            .sum();
+        let estimated = Tokens {
+            prompt: prompt as u64,
+            completion: self.inner.count_tokens(reply) as u64,
+        };
+        let source = if reported.is_some() {
+            "reported"
+        } else {
+            "estimated"
+        };
+        METRICS
+            .llm_token_usage_source
+            .with_label_values(&[self.inner.name(), source])
+            .inc();
+        let tokens = match reported {
+            Some(reported) => {
+                costs::reconcile(self.inner.name(), estimated, reported);
+                reported
+            }
+            None => estimated,
+        };
+        costs::record_tokens(self.inner.name(), tokens);
+        costs::report_usage(tokens);
+    }
+}
+
+#[async_trait]
+impl ChatProvider for Metered {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        let (reply, reported) = costs::reported_usage(self.inner.send_message(messages)).await;
+        let reply = reply?;
+        self.record(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        let (reply, reported) =
+            costs::reported_usage(self.inner.send_structured(messages, schema)).await;
+        let reply = reply?;
+        self.record(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        let (reply, reported) =
+            costs::reported_usage(self.inner.send_with_functions(messages, functions, rounds))
+                .await;
+        let reply = reply?;
+        let text = match &reply {
+            FunctionReply::Text(text) => text.clone(),
+            FunctionReply::Calls(calls) => calls
+                .iter()
+                .map(|call| format!("{}{}", call.name, call.arguments))
+                .collect(),
+        };
+        self.record(messages, &text, reported);
+        Ok(reply)
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let (reply, reported) =
+            costs::reported_usage(self.inner.stream_message(messages, deltas)).await;
+        let reply = reply?;
+        self.record(messages, &reply, reported);
+        Ok(reply)
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.inner.count_tokens(text)
+    }
+
+    fn sees_images(&self) -> bool {
+        self.inner.sees_images()
+    }
+}
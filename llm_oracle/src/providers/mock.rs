@@ -0,0 +1,108 @@
+use super::{ChatMessage, ChatProvider, ProviderError};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::env;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const DEFAULT_MOCK_RESPONSE: &str = "This is a mock response.";
+
+enum Reply {
+    Text(String),
+    Error(String),
+}
+
+/// Answers without calling a model, for tests and local runs. Scripted replies are returned
+/// first, in order; then the reply of the first rule whose pattern is in the prompt, or the
+/// default reply. Streamed replies are sent word by word. The prompts received are kept for
+/// assertions.
+pub struct MockProvider {
+    default: String,
+    script: Mutex<VecDeque<Reply>>,
+    /// Substring of the prompt and the reply to it
+    rules: Vec<(String, String)>,
+    prompts: Mutex<Vec<String>>,
+}
+
+impl MockProvider {
+    pub fn new(default: impl Into<String>) -> Self {
+        Self {
+            default: default.into(),
+            script: Mutex::new(VecDeque::new()),
+            rules: Vec::new(),
+            prompts: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answering `MOCK_RESPONSE`, or [`DEFAULT_MOCK_RESPONSE`]
+    pub fn from_env() -> Self {
+        Self::new(env::var("MOCK_RESPONSE").unwrap_or(DEFAULT_MOCK_RESPONSE.to_string()))
+    }
+
+    /// Answer `reply` to the next call not answered by an earlier scripted reply
+    pub fn with_reply(self, reply: impl Into<String>) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back(Reply::Text(reply.into()));
+        self
+    }
+
+    /// Fail the next call not answered by an earlier scripted reply
+    pub fn with_error(self, error: impl Into<String>) -> Self {
+        self.script
+            .lock()
+            .unwrap()
+            .push_back(Reply::Error(error.into()));
+        self
+    }
+
+    /// Answer `reply` to prompts containing `pattern`, once the script is over
+    pub fn with_rule(mut self, pattern: impl Into<String>, reply: impl Into<String>) -> Self {
+        self.rules.push((pattern.into(), reply.into()));
+        self
+    }
+
+    /// The last message of every call, oldest first
+    pub fn prompts(&self) -> Vec<String> {
+        self.prompts.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChatProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        let prompt = messages
+            .last()
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+        self.prompts.lock().unwrap().push(prompt.clone());
+        match self.script.lock().unwrap().pop_front() {
+            Some(Reply::Text(reply)) => return Ok(reply),
+            Some(Reply::Error(error)) => return Err(error.into()),
+            None => {}
+        }
+        Ok(self
+            .rules
+            .iter()
+            .find(|(pattern, _)| prompt.contains(pattern.as_str()))
+            .map_or(&self.default, |(_, reply)| reply)
+            .clone())
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let reply = self.send_message(messages).await?;
+        for word in reply.split_inclusive(' ') {
+            let _ = deltas.send(word.to_string());
+        }
+        Ok(reply)
+    }
+}
@@ -0,0 +1,447 @@
+//! LLM provider abstraction.
+//!
+//! Every backend the oracle can talk to implements [`ChatProvider`]. The OpenAI and Gemini
+//! clients ship with the crate; downstream users can implement the trait for their own
+//! inference gateway and hand it to the oracle instead. [`MockProvider`] answers canned replies
+//! without a model, for tests.
+//!
+//! Each client is behind a cargo feature, all on by default: `gemini`, `openai` and `local-llm`
+//! for OpenAI-compatible endpoints. A provider configured but not built in fails at startup.
+//!
+//! Messages may carry [`Image`]s, sent as image parts to the providers whose
+//! [`ChatProvider::sees_images`] is set, see [`crate::images`].
+//!
+//! Clients fail on error statuses with an [`ApiError`], which [`classify`] sorts into what the
+//! oracle's retries do with it.
+
+use crate::config::LlmConfig;
+use crate::reload;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::info;
+
+mod budget;
+// The OpenAI client sends the requests `chatgpt` doesn't support through it
+#[cfg(any(feature = "openai", feature = "local-llm"))]
+mod compatible;
+mod consensus;
+mod error;
+mod failover;
+#[cfg(feature = "gemini")]
+mod gemini;
+mod limit;
+mod metered;
+mod mock;
+#[cfg(feature = "openai")]
+mod openai;
+#[cfg(any(feature = "gemini", feature = "openai", feature = "local-llm"))]
+mod sse;
+
+pub use budget::{Budget, BudgetExhausted, SpendBudget, BUDGET_EXHAUSTED};
+#[cfg(any(feature = "openai", feature = "local-llm"))]
+pub use compatible::OpenAICompatibleClient;
+pub use consensus::{ConsensusPolicy, ConsensusProvider};
+pub use error::{classify, retry_after, ApiError, ErrorClass};
+pub use failover::FailoverProvider;
+#[cfg(feature = "gemini")]
+pub use gemini::GeminiClient;
+pub use limit::ConcurrencyLimit;
+pub use metered::Metered;
+pub use mock::{MockProvider, DEFAULT_MOCK_RESPONSE};
+#[cfg(feature = "openai")]
+pub use openai::{OpenAIClient, OPENAI_VISION_MODELS};
+
+/// Error type returned by providers.
+pub type ProviderError = crate::OracleError;
+
+/// Author of a [`ChatMessage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    Assistant,
+    User,
+    Function,
+}
+
+/// A message of the conversation sent to a provider
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// Sent after the text, to models that see images
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+}
+
+/// An image part of a [`ChatMessage`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Image {
+    /// e.g. `image/png`
+    pub mime_type: String,
+    /// Base64 of the image
+    pub data: String,
+}
+
+impl Image {
+    /// `data:` URL of the image
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.data)
+    }
+}
+
+/// A function the model may call, its parameters described by a JSON Schema
+#[derive(Debug, Clone)]
+pub struct FunctionSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Value,
+}
+
+/// A call of a [`FunctionSpec`] requested by the model
+#[derive(Debug, Clone)]
+pub struct FunctionCall {
+    /// Identifies the call in the result sent back, where the API has one
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+/// Calls the model requested in one turn, with their results in the same order
+#[derive(Debug, Clone)]
+pub struct FunctionRound {
+    pub calls: Vec<FunctionCall>,
+    pub results: Vec<String>,
+}
+
+/// A reply when functions are offered: either the answer, or calls to run first
+#[derive(Debug, Clone)]
+pub enum FunctionReply {
+    Text(String),
+    Calls(Vec<FunctionCall>),
+}
+
+#[async_trait]
+pub trait ChatProvider: Send + Sync {
+    /// Short name used in logs, e.g. `"gemini"`.
+    fn name(&self) -> &str;
+
+    /// Send the conversation history and return the model's reply.
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError>;
+
+    /// Like [`Self::send_message`], constraining the reply to JSON matching `schema` where the
+    /// API supports it. Defaults to `send_message`, relying on the schema given in the prompt.
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        let _ = schema;
+        self.send_message(messages).await
+    }
+
+    /// Send the conversation history followed by the function calls of the previous `rounds`
+    /// and their results, letting the model call one of `functions` instead of answering.
+    /// Defaults to `send_message` for APIs without function calling: the model always answers.
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        let _ = (functions, rounds);
+        self.send_message(messages).await.map(FunctionReply::Text)
+    }
+
+    /// Like [`Self::send_message`], also sending the reply to `deltas` piece by piece as the
+    /// model writes it. Defaults to `send_message`, sending the whole reply at once.
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        let reply = self.send_message(messages).await?;
+        let _ = deltas.send(reply.clone());
+        Ok(reply)
+    }
+
+    /// Number of tokens `text` takes in the model's context. Defaults to an estimate of one
+    /// token per four characters.
+    fn count_tokens(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+
+    /// Whether the model understands the images of messages. Defaults to `false`: images are
+    /// neither fetched nor sent.
+    fn sees_images(&self) -> bool {
+        false
+    }
+}
+
+/// Character-based token estimate, for models without a public tokenizer
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Tokens added to every message by the chat format
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Drop the oldest messages until the history fits in `budget` tokens. The last message, the
+/// current prompt with its context, is always kept. Returns the number of messages dropped.
+pub fn truncate_history(
+    provider: &dyn ChatProvider,
+    messages: &mut Vec<ChatMessage>,
+    budget: usize,
+) -> usize {
+    let mut used = 0;
+    let mut kept = 0;
+    for (position, message) in messages.iter().rev().enumerate() {
+        used += provider.count_tokens(&message.content) + TOKENS_PER_MESSAGE;
+        if position > 0 && used > budget {
+            break;
+        }
+        kept += 1;
+    }
+    let dropped = messages.len() - kept;
+    messages.drain(..dropped);
+    dropped
+}
+
+/// Generation settings shared by the clients; unset values use the provider's defaults
+#[derive(Debug, Clone, Default)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    pub max_tokens: u32,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// Sent ahead of every conversation, as the provider's system instruction
+    pub system_prompt: Option<String>,
+    /// Whether the model sees images; detected from its name when unset
+    pub vision: Option<bool>,
+}
+
+impl From<&LlmConfig> for GenerationParams {
+    fn from(config: &LlmConfig) -> Self {
+        Self {
+            temperature: config.temperature,
+            max_tokens: config.max_tokens,
+            top_p: config.top_p,
+            presence_penalty: config.presence_penalty,
+            frequency_penalty: config.frequency_penalty,
+            system_prompt: config.system_prompt.clone(),
+            vision: config.vision,
+        }
+    }
+}
+
+impl GenerationParams {
+    /// These settings, or those reloaded when the current interaction uses them, see
+    /// [`crate::reload`]. Settings a reload leaves unset keep their value, and so does `vision`.
+    pub fn live(&self) -> GenerationParams {
+        let Some(live) = reload::generation() else {
+            return self.clone();
+        };
+        GenerationParams {
+            temperature: live.temperature.or(self.temperature),
+            max_tokens: live.max_tokens,
+            top_p: live.top_p.or(self.top_p),
+            presence_penalty: live.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: live.frequency_penalty.or(self.frequency_penalty),
+            system_prompt: live
+                .system_prompt
+                .clone()
+                .or_else(|| self.system_prompt.clone()),
+            vision: self.vision,
+        }
+    }
+}
+
+pub const DEFAULT_GEMINI_MODEL: &str = "gemini-2.0-flash";
+pub const DEFAULT_OPENAI_MODEL: &str = "gpt-4o";
+
+/// Create the configured LLM provider. Without an explicit `llm.provider` it is detected: a
+/// self-hosted endpoint when `llm.base_url` is set, otherwise from the API keys in the
+/// environment, Gemini taking priority when both keys are set. With `llm.fallback_providers`,
+/// the providers are chained behind a [`FailoverProvider`]. With `llm.consensus_providers`,
+/// they are all queried behind a [`ConsensusProvider`] instead.
+pub fn from_config(config: &LlmConfig) -> Result<Box<dyn ChatProvider>, ProviderError> {
+    if !config.consensus_providers.is_empty() {
+        let providers = config
+            .consensus_providers
+            .iter()
+            .map(|provider| {
+                let model = match config.provider.as_deref() {
+                    Some(primary) if primary == provider && config.model.is_some() => {
+                        config.model.as_deref()
+                    }
+                    _ => config.provider_models.get(provider).map(String::as_str),
+                };
+                build(config, provider, model)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        info!(
+            providers = ?config.consensus_providers,
+            policy = %config.consensus,
+            "Answering by consensus between LLM providers"
+        );
+        return Ok(Box::new(ConsensusProvider::new(
+            providers,
+            config.consensus,
+        )));
+    }
+    let primary = primary_provider(config)?;
+    let primary_model = config.model.clone();
+    let mut chain = vec![build(config, primary, primary_model.as_deref())?];
+    for fallback in &config.fallback_providers {
+        if fallback == primary {
+            continue;
+        }
+        let model = config.provider_models.get(fallback).map(String::as_str);
+        chain.push(build(config, fallback, model)?);
+    }
+    if chain.len() == 1 {
+        return Ok(chain.remove(0));
+    }
+    info!(
+        providers = ?chain.iter().map(|provider| provider.name()).collect::<Vec<_>>(),
+        "Failing over between LLM providers"
+    );
+    Ok(Box::new(FailoverProvider::new(
+        chain,
+        config.breaker_threshold,
+        Duration::from_secs(config.breaker_cooldown_secs),
+    )))
+}
+
+fn primary_provider(config: &LlmConfig) -> Result<&str, ProviderError> {
+    Ok(match config.provider.as_deref() {
+        Some(provider) => provider,
+        None if config.base_url.is_some() => "local",
+        None if api_key("GEMINI_API_KEY").is_some() => "gemini",
+        None if api_key("OPENAI_API_KEY").is_some() => "openai",
+        None => {
+            return Err(
+                "No valid API key found. Please set GEMINI_API_KEY or OPENAI_API_KEY in .env file"
+                    .into(),
+            )
+        }
+    })
+}
+
+fn api_key(var: &str) -> Option<String> {
+    env::var(var)
+        .ok()
+        .filter(|key| !key.is_empty() && key != "your-gemini-api-key-here")
+}
+
+#[cfg(not(all(feature = "gemini", feature = "openai", feature = "local-llm")))]
+fn not_built(provider: &str, feature: &str) -> ProviderError {
+    format!(
+        "LLM provider {} isn't built in, build the oracle with the {} feature",
+        provider, feature
+    )
+    .into()
+}
+
+/// Build one provider, metered for cost accounting, with its concurrency limit and daily budget.
+/// `model` overrides the provider's default.
+fn build(
+    config: &LlmConfig,
+    provider: &str,
+    model: Option<&str>,
+) -> Result<Box<dyn ChatProvider>, ProviderError> {
+    let client: Box<dyn ChatProvider> = match provider {
+        #[cfg(feature = "gemini")]
+        "gemini" => {
+            let key = api_key("GEMINI_API_KEY")
+                .ok_or("gemini is configured but GEMINI_API_KEY is not set")?;
+            let model = model.unwrap_or(DEFAULT_GEMINI_MODEL);
+            info!("🤖 Using Gemini AI ({})", model);
+            Box::new(GeminiClient::new(
+                key,
+                model.to_string(),
+                GenerationParams::from(config),
+            ))
+        }
+        #[cfg(feature = "openai")]
+        "openai" => {
+            let key = api_key("OPENAI_API_KEY")
+                .ok_or("openai is configured but OPENAI_API_KEY is not set")?;
+            let model = model.unwrap_or(DEFAULT_OPENAI_MODEL);
+            info!("🤖 Using OpenAI ({})", model);
+            Box::new(OpenAIClient::new(
+                &key,
+                model,
+                GenerationParams::from(config),
+            )?)
+        }
+        #[cfg(feature = "local-llm")]
+        "local" => {
+            let base_url = config
+                .base_url
+                .clone()
+                .ok_or("local is configured but LLM_BASE_URL is not set")?;
+            let model = model.ok_or("local is configured without a model")?;
+            info!("🤖 Using {} at {}", model, base_url);
+            Box::new(OpenAICompatibleClient::new(
+                base_url,
+                api_key("LLM_API_KEY"),
+                model.to_string(),
+                GenerationParams::from(config),
+            ))
+        }
+        "mock" => {
+            info!("🤖 Using canned mock responses");
+            Box::new(MockProvider::from_env())
+        }
+        #[cfg(not(feature = "gemini"))]
+        "gemini" => return Err(not_built("gemini", "gemini")),
+        #[cfg(not(feature = "openai"))]
+        "openai" => return Err(not_built("openai", "openai")),
+        #[cfg(not(feature = "local-llm"))]
+        "local" => return Err(not_built("local", "local-llm")),
+        other => return Err(format!("Unknown LLM provider {:?}", other).into()),
+    };
+    let client: Box<dyn ChatProvider> = Box::new(Metered::new(client));
+    let client: Box<dyn ChatProvider> = match config.max_concurrent_requests.get(provider) {
+        Some(&limit) => {
+            info!(provider, limit, "Limiting concurrent LLM requests");
+            Box::new(ConcurrencyLimit::new(client, limit))
+        }
+        None => client,
+    };
+    let budget = Budget {
+        daily_tokens: config.daily_tokens.get(provider).copied(),
+        daily_usd: config.daily_usd.get(provider).copied(),
+        usd_per_1k_tokens: config
+            .usd_per_1k_tokens
+            .get(provider)
+            .copied()
+            .unwrap_or_default(),
+    };
+    if budget.daily_tokens.is_none() && budget.daily_usd.is_none() {
+        return Ok(client);
+    }
+    info!(
+        provider,
+        daily_tokens = ?budget.daily_tokens,
+        daily_usd = ?budget.daily_usd,
+        "Enforcing a daily LLM budget"
+    );
+    Ok(Box::new(SpendBudget::new(client, budget)))
+}
@@ -0,0 +1,154 @@
+use super::{
+    ChatMessage, ChatProvider, FunctionReply, FunctionRound, FunctionSpec, GenerationParams,
+    OpenAICompatibleClient, ProviderError, Role,
+};
+use crate::costs::{self, Tokens};
+use crate::trace;
+use async_trait::async_trait;
+use chatgpt::client::ChatGPT;
+use chatgpt::config::ModelConfiguration;
+use serde_json::Value;
+use tiktoken_rs::CoreBPE;
+use tokio::sync::mpsc::UnboundedSender;
+
+const OPENAI_API_URL: &str = "https://api.openai.com/v1";
+
+/// Prefixes of the models taking images
+pub const OPENAI_VISION_MODELS: &[&str] =
+    &["gpt-4o", "gpt-4.1", "gpt-4-turbo", "gpt-5", "o3", "o4"];
+
+/// OpenAI chat completions client
+pub struct OpenAIClient {
+    client: ChatGPT,
+    /// Structured output, function calling and streaming requests, which `chatgpt` doesn't
+    /// support
+    json_client: OpenAICompatibleClient,
+    system_prompt: Option<String>,
+    tokenizer: CoreBPE,
+    vision: bool,
+}
+
+impl OpenAIClient {
+    pub fn new(
+        api_key: &str,
+        model: &str,
+        params: GenerationParams,
+    ) -> Result<Self, ProviderError> {
+        let defaults = ModelConfiguration::default();
+        let client = ChatGPT::new_with_config(
+            api_key,
+            ModelConfiguration {
+                // The engine name must be 'static; clients are created once at startup
+                engine: chatgpt::config::ChatGPTEngine::Custom(Box::leak(
+                    model.to_string().into_boxed_str(),
+                )),
+                temperature: params.temperature.unwrap_or(defaults.temperature),
+                top_p: params.top_p.unwrap_or(defaults.top_p),
+                presence_penalty: params.presence_penalty.unwrap_or(0.3),
+                frequency_penalty: params.frequency_penalty.unwrap_or(0.3),
+                max_tokens: Some(params.max_tokens),
+                ..defaults
+            },
+        )?;
+        // Models unknown to tiktoken (e.g. fine-tunes) use the GPT-4o encoding
+        let tokenizer = tiktoken_rs::get_bpe_from_model(model)
+            .or_else(|_| tiktoken_rs::o200k_base())
+            .map_err(|e| format!("Can't load the tokenizer of {}: {}", model, e))?;
+        // With the defaults of `chatgpt`, so both clients answer alike
+        let json_client = OpenAICompatibleClient::new(
+            OPENAI_API_URL.to_string(),
+            Some(api_key.to_string()),
+            model.to_string(),
+            GenerationParams {
+                temperature: params.temperature.or(Some(defaults.temperature)),
+                presence_penalty: params.presence_penalty.or(Some(0.3)),
+                frequency_penalty: params.frequency_penalty.or(Some(0.3)),
+                ..params.clone()
+            },
+        )
+        .with_stream_usage();
+        let vision = params.vision.unwrap_or_else(|| {
+            OPENAI_VISION_MODELS
+                .iter()
+                .any(|prefix| model.starts_with(prefix))
+        });
+        Ok(Self {
+            client,
+            json_client,
+            system_prompt: params.system_prompt,
+            tokenizer,
+            vision,
+        })
+    }
+}
+
+#[async_trait]
+impl ChatProvider for OpenAIClient {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    async fn send_message(&self, messages: &[ChatMessage]) -> Result<String, ProviderError> {
+        // `chatgpt` only sends text, without the trace ID
+        if trace::current().is_some() || messages.iter().any(|message| !message.images.is_empty()) {
+            return self.json_client.send_message(messages).await;
+        }
+        let mut messages_vec = Vec::with_capacity(messages.len() + 1);
+        if let Some(system_prompt) = &self.system_prompt {
+            messages_vec.push(chatgpt::types::ChatMessage {
+                role: chatgpt::types::Role::System,
+                content: system_prompt.clone(),
+            });
+        }
+        messages_vec.extend(messages.iter().map(|message| chatgpt::types::ChatMessage {
+            role: match message.role {
+                Role::System => chatgpt::types::Role::System,
+                Role::Assistant => chatgpt::types::Role::Assistant,
+                Role::User => chatgpt::types::Role::User,
+                Role::Function => chatgpt::types::Role::Function,
+            },
+            content: message.content.clone(),
+        }));
+        let response = self.client.send_history(&messages_vec).await?;
+        costs::report_usage(Tokens {
+            prompt: response.usage.prompt_tokens.into(),
+            completion: response.usage.completion_tokens.into(),
+        });
+        Ok(response.message().content.clone())
+    }
+
+    async fn send_structured(
+        &self,
+        messages: &[ChatMessage],
+        schema: &Value,
+    ) -> Result<String, ProviderError> {
+        self.json_client.send_structured(messages, schema).await
+    }
+
+    async fn send_with_functions(
+        &self,
+        messages: &[ChatMessage],
+        functions: &[FunctionSpec],
+        rounds: &[FunctionRound],
+    ) -> Result<FunctionReply, ProviderError> {
+        self.json_client
+            .send_with_functions(messages, functions, rounds)
+            .await
+    }
+
+    async fn stream_message(
+        &self,
+        messages: &[ChatMessage],
+        deltas: &UnboundedSender<String>,
+    ) -> Result<String, ProviderError> {
+        self.json_client.stream_message(messages, deltas).await
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.encode_with_special_tokens(text).len()
+    }
+
+    fn sees_images(&self) -> bool {
+        self.vision
+    }
+}
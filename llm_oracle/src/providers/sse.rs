@@ -0,0 +1,26 @@
+use super::ProviderError;
+
+/// Read the server-sent events of a streaming response, passing the payload of every `data:`
+/// line to `on_data` until the stream ends or sends `[DONE]`
+pub async fn read_events(
+    mut response: reqwest::Response,
+    mut on_data: impl FnMut(&str) -> Result<(), ProviderError>,
+) -> Result<(), ProviderError> {
+    let mut buffer = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+        // Events can be split across chunks, only complete lines are parsed
+        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:") else {
+                continue;
+            };
+            match data.trim() {
+                "[DONE]" => return Ok(()),
+                data => on_data(data)?,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,297 @@
+//! Push of confirmed answers.
+//!
+//! With `push.keys_file` (`PUSH_KEYS_FILE`) and `push.addr` (`PUSH_ADDR`) set, dApp backends can
+//! connect to a WebSocket server at `push.addr` instead of indexing the chain for callbacks. They
+//! authenticate with `Authorization: Bearer <key>` on the upgrade request, keys being compared
+//! in constant time, and receive one JSON message per answered interaction of the contexts of
+//! their key, once its callback is confirmed:
+//!
+//! ```json
+//! {"type": "answer", "interaction": "...", "context": "...", "user": "...", "text": "...",
+//!  "response": "...", "signatures": ["..."], "confirmed_at": 1700000000}
+//! ```
+//!
+//! Keys are listed in a JSON file, `[{"name": "app", "key": "...", "contexts": ["<pubkey>"]}]`.
+//! Answers are not stored: a client only gets those confirmed while it is connected, and one too
+//! slow to keep up gets `{"type": "lagged", "missed": n}` in place of the answers it missed.
+//! Subscribers and pushed answers are counted in `push_subscribers` and `answers_pushed_total`.
+
+use crate::config::PushConfig;
+use crate::metrics::{self, METRICS};
+use crate::oracle::Oracle;
+use crate::OracleError;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::HashSet;
+use std::fs;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, warn};
+
+/// Answers buffered for each subscriber before it lags
+const CAPACITY: usize = 1024;
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A subscriber and the contexts it receives the answers of
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushKey {
+    /// Shown in logs instead of the key
+    pub name: String,
+    pub key: String,
+    pub contexts: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    name: String,
+    key: String,
+    contexts: HashSet<Pubkey>,
+}
+
+/// A confirmed answer, serialized once for every subscriber
+#[derive(Debug)]
+struct Pushed {
+    context: Pubkey,
+    message: String,
+}
+
+/// The WebSocket server's subscribers and the answers broadcast to them
+pub struct PushHub {
+    addr: String,
+    subscribers: Vec<Subscriber>,
+    sender: broadcast::Sender<Arc<Pushed>>,
+}
+
+impl PushHub {
+    pub fn new(addr: String, keys: Vec<PushKey>) -> Result<Self, OracleError> {
+        let mut subscribers = Vec::new();
+        for key in keys {
+            if key.key.len() < 16 {
+                return Err(format!("Push key {} must be at least 16 characters", key.name).into());
+            }
+            let contexts = key
+                .contexts
+                .iter()
+                .map(|context| {
+                    Pubkey::from_str(context)
+                        .map_err(|e| format!("Invalid context {} of {}: {}", context, key.name, e))
+                })
+                .collect::<Result<_, _>>()?;
+            subscribers.push(Subscriber {
+                name: key.name,
+                key: key.key,
+                contexts,
+            });
+        }
+        Ok(Self {
+            addr,
+            subscribers,
+            sender: broadcast::channel(CAPACITY).0,
+        })
+    }
+
+    /// The server of `config`; `None` when it has no keys file
+    pub fn load(config: &PushConfig) -> Result<Option<Self>, OracleError> {
+        let Some(path) = &config.keys_file else {
+            return Ok(None);
+        };
+        let keys: Vec<PushKey> = serde_json::from_str(
+            &fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read `push.keys_file` {}: {}", path, e))?,
+        )
+        .map_err(|e| format!("Invalid `push.keys_file` {}: {}", path, e))?;
+        let addr = config
+            .addr
+            .clone()
+            .ok_or("`push.keys_file` requires `push.addr`")?;
+        let hub = Self::new(addr, keys)?;
+        info!(addr = %hub.addr, keys = hub.subscribers.len(), "Answer push enabled");
+        Ok(Some(hub))
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn keys(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Send the confirmed answer of an interaction to the subscribers of its context
+    pub fn publish(
+        &self,
+        interaction_pubkey: &Pubkey,
+        interaction: &solana_gpt_oracle::Interaction,
+        response: &str,
+        signatures: &[Signature],
+    ) {
+        let message = json!({
+            "type": "answer",
+            "interaction": interaction_pubkey.to_string(),
+            "context": interaction.context.to_string(),
+            "user": interaction.user.to_string(),
+            "text": interaction.text,
+            "response": response,
+            "signatures": signatures.iter().map(Signature::to_string).collect::<Vec<_>>(),
+            "confirmed_at": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+        // Fails when nobody is connected
+        let _ = self.sender.send(Arc::new(Pushed {
+            context: interaction.context,
+            message: message.to_string(),
+        }));
+    }
+
+    /// The subscriber of an upgrade request, from `Authorization: Bearer`, compared in constant
+    /// time
+    fn authenticate(&self, request: &Request) -> Option<&Subscriber> {
+        let presented = request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)?;
+        self.subscribers
+            .iter()
+            .find(|subscriber| metrics::constant_time_eq(&subscriber.key, presented))
+    }
+}
+
+/// Accept subscribers on `push.addr` until the oracle stops
+pub async fn serve(oracle: Arc<Oracle>) -> Result<(), OracleError> {
+    let Some(hub) = &oracle.push else {
+        return Ok(());
+    };
+    let listener = TcpListener::bind(&hub.addr).await?;
+    info!(addr = %hub.addr, "Serving answer push");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!(error = ?e, "Failed to accept a push connection");
+                continue;
+            }
+        };
+        let oracle = oracle.clone();
+        tokio::spawn(async move {
+            if let Some(hub) = &oracle.push {
+                subscribe(hub, stream, peer).await;
+            }
+        });
+    }
+}
+
+async fn subscribe(hub: &PushHub, stream: TcpStream, peer: SocketAddr) {
+    let mut subscriber = None;
+    let authenticate = |request: &Request, response: Response| {
+        subscriber = hub.authenticate(request);
+        match subscriber {
+            Some(_) => Ok(response),
+            None => {
+                let mut refused = ErrorResponse::new(Some("Unknown push key".to_string()));
+                *refused.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(refused)
+            }
+        }
+    };
+    let mut socket = match tokio_tungstenite::accept_hdr_async(stream, authenticate).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!(%peer, error = %e, "Push connection refused");
+            return;
+        }
+    };
+    let Some(subscriber) = subscriber else {
+        return;
+    };
+    let mut answers = hub.sender.subscribe();
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    info!(%peer, key = %subscriber.name, "Push subscriber connected");
+    METRICS.push_subscribers.inc();
+    loop {
+        let sent = tokio::select! {
+            answer = answers.recv() => match answer {
+                Ok(answer) if subscriber.contexts.contains(&answer.context) => {
+                    METRICS.answers_pushed.inc();
+                    socket.send(Message::Text(answer.message.clone())).await
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(key = %subscriber.name, missed, "Push subscriber lagging");
+                    let lagged = json!({"type": "lagged", "missed": missed});
+                    socket.send(Message::Text(lagged.to_string())).await
+                }
+                Err(RecvError::Closed) => break,
+            },
+            received = socket.next() => match received {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by the socket, anything else is ignored
+                Some(Ok(_)) => Ok(()),
+            },
+            _ = ping.tick() => socket.send(Message::Ping(Vec::new())).await,
+        };
+        if let Err(e) = sent {
+            debug!(%peer, error = %e, "Push connection lost");
+            break;
+        }
+    }
+    METRICS.push_subscribers.dec();
+    info!(%peer, key = %subscriber.name, "Push subscriber disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hub() -> PushHub {
+        let key = PushKey {
+            name: "app".to_string(),
+            key: "0123456789abcdef".to_string(),
+            contexts: vec![Pubkey::new_unique().to_string()],
+        };
+        PushHub::new("127.0.0.1:0".to_string(), vec![key]).unwrap()
+    }
+
+    fn request(uri: &str, authorization: Option<&str>) -> Request {
+        let mut request = Request::builder().uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn authenticates_the_bearer_key_only() {
+        let hub = hub();
+        let subscriber = hub.authenticate(&request("/", Some("Bearer 0123456789abcdef")));
+        assert_eq!(
+            subscriber.map(|subscriber| subscriber.name.as_str()),
+            Some("app")
+        );
+        for authorization in [
+            "Bearer 0123456789abcdeF",
+            "Bearer 0123456789abcde",
+            "Bearer ",
+        ] {
+            assert!(hub
+                .authenticate(&request("/", Some(authorization)))
+                .is_none());
+        }
+        assert!(hub
+            .authenticate(&request("/?key=0123456789abcdef", None))
+            .is_none());
+    }
+}
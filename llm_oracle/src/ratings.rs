@@ -0,0 +1,184 @@
+//! User ratings.
+//!
+//! Users rate the response to their interaction on-chain with the program's `rate_response`
+//! instruction, which emits a `ResponseRated` event. With `ratings.enabled` (`RATINGS`) set, the
+//! oracle subscribes to the logs of each configured program, joins every rating with the archived
+//! response it rates (so `ARCHIVE_PATH` is required), stores it in the archive and observes it in
+//! the `response_ratings` histogram by context. `llm_oracle ratings` prints the daily average of
+//! each context, and of each provider, to compare prompt and model changes.
+//!
+//! Ratings emitted while the subscription is down are not fetched back.
+
+use crate::archive::{Archive, RatingRecord};
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::OracleError;
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::Engine;
+use chrono::DateTime;
+use futures::StreamExt;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter};
+use solana_gpt_oracle::ResponseRated;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// The `ResponseRated` events emitted by `program` itself in a transaction's logs. Events
+/// logged while another program runs are ignored, so a CPI can't forge ratings.
+pub fn rating_events(logs: &[String], program: &Pubkey) -> Vec<ResponseRated> {
+    let program_id = program.to_string();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+    for log in logs {
+        let Some(rest) = log.strip_prefix("Program ") else {
+            continue;
+        };
+        if let Some(data) = rest.strip_prefix("data: ") {
+            if stack.last() != Some(&program_id.as_str()) {
+                continue;
+            }
+            let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(data) else {
+                continue;
+            };
+            let Some(payload) = bytes.strip_prefix(ResponseRated::DISCRIMINATOR) else {
+                continue;
+            };
+            if let Ok(event) = ResponseRated::deserialize(&mut &payload[..]) {
+                events.push(event);
+            }
+            continue;
+        }
+        let mut words = rest.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(program), Some("invoke")) => stack.push(program),
+            (Some(_), Some("success" | "failed:")) => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Store a rating with the response it rates. A response rated again keeps its latest rating,
+/// and is observed in `response_ratings` only the first time.
+pub fn record(
+    archive: &Archive,
+    event: &ResponseRated,
+    signature: &str,
+) -> Result<bool, OracleError> {
+    let rated = archive.latest(&event.interaction)?;
+    let rating = RatingRecord {
+        interaction: event.interaction.to_string(),
+        context: event.context.to_string(),
+        user: event.user.to_string(),
+        score: event.score,
+        signature: signature.to_string(),
+        response: rated.as_ref().map(|record| record.response.clone()),
+        answered_at: rated.as_ref().map(|record| record.created_at),
+        provider: rated.map(|record| record.provider),
+        rated_at: now_millis(),
+    };
+    let new = archive.record_rating(&rating)?;
+    if new {
+        METRICS
+            .response_ratings
+            .with_label_values(&[&rating.context])
+            .observe(rating.score as f64);
+    }
+    Ok(new)
+}
+
+async fn subscribe(
+    oracle: &Oracle,
+    archive: &Archive,
+    program: &Pubkey,
+) -> Result<(), OracleError> {
+    let pubsub_client = PubsubClient::new(&oracle.config.websocket_url).await?;
+    let (mut stream, unsubscribe) = pubsub_client
+        .logs_subscribe(
+            RpcTransactionLogsFilter::Mentions(vec![program.to_string()]),
+            RpcTransactionLogsConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+            },
+        )
+        .await?;
+    info!(%program, "Subscribed to ratings");
+    while let Some(update) = stream.next().await {
+        if update.value.err.is_some() {
+            continue;
+        }
+        for event in rating_events(&update.value.logs, program) {
+            match record(archive, &event, &update.value.signature) {
+                Ok(true) => debug!(
+                    interaction = %event.interaction,
+                    score = event.score,
+                    "Response rated"
+                ),
+                Ok(false) => {}
+                Err(e) => warn!(error = ?e, "Failed to record a rating"),
+            }
+        }
+    }
+    unsubscribe().await;
+    Ok(())
+}
+
+/// Record the ratings of every program until the process stops
+pub async fn run(oracle: Arc<Oracle>) {
+    let Some(archive) = &oracle.archive else {
+        return;
+    };
+    let programs = oracle.config.programs.iter().map(|program| async {
+        loop {
+            if let Err(e) = subscribe(&oracle, archive, &program.id).await {
+                warn!(program = %program.id, error = ?e, "Ratings subscription failed");
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+    futures::future::join_all(programs).await;
+}
+
+/// Count and average score of a group of ratings on a day
+#[derive(Debug, Clone, Default)]
+pub struct Trend {
+    pub count: usize,
+    pub total: u64,
+}
+
+impl Trend {
+    pub fn average(&self) -> f64 {
+        self.total as f64 / self.count.max(1) as f64
+    }
+}
+
+/// Daily trends of `ratings` grouped by `key`, e.g. the context or the provider
+pub fn daily_trends(
+    ratings: &[RatingRecord],
+    key: impl Fn(&RatingRecord) -> String,
+) -> BTreeMap<(String, String), Trend> {
+    let mut trends: BTreeMap<(String, String), Trend> = BTreeMap::new();
+    for rating in ratings {
+        let day = DateTime::from_timestamp_millis(rating.rated_at as i64)
+            .map(|at| at.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        let trend = trends.entry((key(rating), day)).or_default();
+        trend.count += 1;
+        trend.total += rating.score as u64;
+    }
+    trends
+}
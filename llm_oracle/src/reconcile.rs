@@ -0,0 +1,311 @@
+//! Ledger reconciliation.
+//!
+//! The ledger ([`crate::dedup::ProcessedSet`]) only learns about the chain from the updates the
+//! oracle sees and the callbacks it sends, so it can fall out of step with it: a confirmation
+//! lost between a callback landing and its status being recorded, a worker gone mid-stage, an
+//! account answered or closed behind the oracle's back. With `reconcile.enabled` (`RECONCILE`),
+//! the ledger is checked against the interaction accounts and callback transactions every
+//! `reconcile.interval_secs` (`RECONCILE_INTERVAL_SECS`), and the divergences repaired. Entries
+//! that changed in the last `reconcile.min_age_secs` (`RECONCILE_MIN_AGE_SECS`) are left alone,
+//! as are interactions held by the worker pool or deferred for flooding (see [`crate::flood`])
+//! and failed ones with a dead letter:
+//!
+//! - `vanished`: an unsettled interaction was closed or asks something else; it is abandoned
+//! - `missed_confirmation`: an unsettled interaction was answered by a callback of the oracle;
+//!   it is confirmed with the signatures that landed
+//! - `answered_elsewhere`: an unsettled interaction was answered without any callback of the
+//!   oracle landing; it is abandoned
+//! - `stalled`: an unsettled interaction is still unanswered but no worker holds it, a phantom
+//!   pending; it is resumed from its stage like after a crash, see [`crate::recovery`]
+//! - `exhausted`: a stalled interaction was resumed [`MAX_RESUMES`] times already without
+//!   settling, e.g. a failed callback without a dead letter; it is abandoned, for
+//!   `POST /admin/reprocess/<interaction>` to answer it again or the refund watchdog to refund it
+//! - `unconfirmed`: an interaction confirmed over the last [`CONFIRMED_LOOKBACK`] is unanswered,
+//!   and none of its callbacks is found on-chain; it is answered again
+//!
+//! Entries checked are counted in `reconciliation_checked_total` and divergences in
+//! `reconciliation_drift_total`, by `kind`. A run finding any sends a [`LEDGER_DRIFT`] event.
+
+use crate::decode::InteractionView;
+use crate::dedup::{prompt_hash, LedgerEntry};
+use crate::metrics::METRICS;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::recovery;
+use crate::status::InteractionStatus;
+use crate::worker_pool::WorkerPool;
+use crate::OracleError;
+use solana_sdk::account::Account;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+pub const LEDGER_DRIFT: &str = "ledger_drift";
+
+/// Times a stalled interaction is resumed before it is abandoned
+pub const MAX_RESUMES: u32 = 3;
+
+/// How far back confirmed interactions are checked
+pub const CONFIRMED_LOOKBACK: Duration = Duration::from_secs(24 * 3600);
+
+/// Outcome of a [`reconcile`] run
+#[derive(Debug, Default)]
+pub struct Reconciled {
+    pub checked: usize,
+    /// Divergences repaired, by kind
+    pub drift: BTreeMap<&'static str, usize>,
+    /// Entries that couldn't be checked or repaired, tried again by the next run
+    pub failed: usize,
+}
+
+impl Reconciled {
+    fn record(&mut self, interaction: &Pubkey, checked: Result<Option<&'static str>, OracleError>) {
+        self.checked += 1;
+        METRICS.reconciliation_checked.inc();
+        match checked {
+            Ok(Some(kind)) => {
+                info!(%interaction, kind, "Ledger drift repaired");
+                METRICS
+                    .reconciliation_drift
+                    .with_label_values(&[kind])
+                    .inc();
+                *self.drift.entry(kind).or_default() += 1;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!(%interaction, error = ?e, "Failed to reconcile an interaction");
+                self.failed += 1;
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The accounts of `pubkeys`, `None` for the closed ones
+async fn accounts(
+    oracle: &Oracle,
+    pubkeys: &[Pubkey],
+) -> Result<Vec<Option<Account>>, OracleError> {
+    let mut accounts = Vec::with_capacity(pubkeys.len());
+    for pubkeys in pubkeys.chunks(100) {
+        accounts.extend(oracle.rpc_client.get_multiple_accounts(pubkeys).await?);
+    }
+    Ok(accounts)
+}
+
+/// Check an unsettled entry against its account, returning the kind of drift repaired
+async fn check_unsettled(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    interaction_pubkey: Pubkey,
+    hash: [u8; 32],
+    entry: LedgerEntry,
+    account: Option<Account>,
+) -> Result<Option<&'static str>, OracleError> {
+    if worker_pool.is_pending(&interaction_pubkey) || oracle.flood.is_deferred(&interaction_pubkey)
+    {
+        return Ok(None);
+    }
+    let ledger = &oracle.processed;
+    if entry.status == InteractionStatus::Failed {
+        let letter = oracle.dlq.get(&interaction_pubkey)?;
+        if letter.is_some_and(|letter| prompt_hash(&letter.prompt) == hash) {
+            return Ok(None);
+        }
+    }
+    let account = account.unwrap_or_default();
+    let view = InteractionView::parse(&account.data).filter(|view| prompt_hash(view.text) == hash);
+    let Some(view) = view else {
+        ledger.abandon_by_hash(&interaction_pubkey, &hash)?;
+        return Ok(Some("vanished"));
+    };
+    if !view.is_processed {
+        if ledger.count_resume(&interaction_pubkey, &hash)? > MAX_RESUMES {
+            ledger.abandon_by_hash(&interaction_pubkey, &hash)?;
+            return Ok(Some("exhausted"));
+        }
+        recovery::resume(
+            oracle,
+            worker_pool,
+            interaction_pubkey,
+            hash,
+            entry.status,
+            entry.response,
+            entry.sent,
+        )
+        .await?;
+        return Ok(Some("stalled"));
+    }
+    let landed = recovery::landed(oracle, &entry.sent).await?;
+    if landed.is_empty() && entry.signatures.is_empty() {
+        ledger.transition(
+            &interaction_pubkey,
+            view.text,
+            InteractionStatus::Abandoned,
+            &[],
+        )?;
+        return Ok(Some("answered_elsewhere"));
+    }
+    if entry.status != InteractionStatus::Submitting {
+        ledger.transition(
+            &interaction_pubkey,
+            view.text,
+            InteractionStatus::Submitting,
+            &[],
+        )?;
+    }
+    ledger.transition(
+        &interaction_pubkey,
+        view.text,
+        InteractionStatus::Confirmed,
+        &landed,
+    )?;
+    Ok(Some("missed_confirmation"))
+}
+
+/// Check a confirmed entry against its account, returning the kind of drift repaired
+async fn check_confirmed(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    interaction_pubkey: Pubkey,
+    hash: [u8; 32],
+    entry: LedgerEntry,
+    account: Option<Account>,
+) -> Result<Option<&'static str>, OracleError> {
+    // Closed or delegated since: there is nothing left to answer here
+    let Some(account) = account.filter(|account| oracle.config.program(&account.owner).is_ok())
+    else {
+        return Ok(None);
+    };
+    let Some(view) = InteractionView::parse(&account.data) else {
+        return Ok(None);
+    };
+    // The same question asked again after the answer is a new interaction, which the ledger
+    // skips on purpose
+    if view.is_processed
+        || prompt_hash(view.text) != hash
+        || view.created_at > entry.updated_at as i64
+        || entry.signatures.is_empty()
+        || worker_pool.is_pending(&interaction_pubkey)
+    {
+        return Ok(None);
+    }
+    if !recovery::landed(oracle, &entry.signatures)
+        .await?
+        .is_empty()
+    {
+        return Ok(None);
+    }
+    if !oracle.processed.unconfirm(&interaction_pubkey, view.text)? {
+        return Ok(None);
+    }
+    worker_pool.dispatch(account.owner, interaction_pubkey, account.data);
+    Ok(Some("unconfirmed"))
+}
+
+/// Check the ledger against the chain once, repairing the divergences
+pub async fn reconcile(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+) -> Result<Reconciled, OracleError> {
+    let now = now();
+    let settled_before = now.saturating_sub(oracle.config.reconcile.min_age_secs);
+    let mut reconciled = Reconciled::default();
+
+    let unsettled: Vec<_> = oracle
+        .processed
+        .unsettled()?
+        .into_iter()
+        .filter(|(_, _, entry)| entry.updated_at <= settled_before)
+        .collect();
+    let pubkeys: Vec<Pubkey> = unsettled.iter().map(|(pubkey, _, _)| *pubkey).collect();
+    let accounts = accounts(oracle, &pubkeys).await?;
+    for ((interaction_pubkey, hash, entry), account) in unsettled.into_iter().zip(accounts) {
+        let span =
+            info_span!("reconcile", interaction = %interaction_pubkey, status = %entry.status);
+        let checked = check_unsettled(
+            oracle,
+            worker_pool,
+            interaction_pubkey,
+            hash,
+            entry,
+            account,
+        )
+        .instrument(span)
+        .await;
+        reconciled.record(&interaction_pubkey, checked);
+    }
+
+    let since = now.saturating_sub(CONFIRMED_LOOKBACK.as_secs());
+    let confirmed: Vec<_> = oracle
+        .processed
+        .confirmed_since(since)?
+        .into_iter()
+        .filter(|(_, _, entry)| entry.updated_at <= settled_before)
+        .collect();
+    let pubkeys: Vec<Pubkey> = confirmed.iter().map(|(pubkey, _, _)| *pubkey).collect();
+    let accounts = accounts(oracle, &pubkeys).await?;
+    for ((interaction_pubkey, hash, entry), account) in confirmed.into_iter().zip(accounts) {
+        let span =
+            info_span!("reconcile", interaction = %interaction_pubkey, status = %entry.status);
+        let checked = check_confirmed(
+            oracle,
+            worker_pool,
+            interaction_pubkey,
+            hash,
+            entry,
+            account,
+        )
+        .instrument(span)
+        .await;
+        reconciled.record(&interaction_pubkey, checked);
+    }
+    Ok(reconciled)
+}
+
+/// Reconcile every `reconcile.interval_secs`, crash recovery having done it at startup
+pub async fn run(oracle: Arc<Oracle>, worker_pool: WorkerPool) {
+    let interval = Duration::from_secs(oracle.config.reconcile.interval_secs);
+    loop {
+        tokio::time::sleep(interval).await;
+        let reconciled = match reconcile(&oracle, &worker_pool).await {
+            Ok(reconciled) => reconciled,
+            Err(e) => {
+                error!(error = ?e, "Reconciliation failed");
+                continue;
+            }
+        };
+        if reconciled.drift.is_empty() {
+            debug!(
+                checked = reconciled.checked,
+                failed = reconciled.failed,
+                "Ledger in step with the chain"
+            );
+            continue;
+        }
+        let drift = reconciled
+            .drift
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+        warn!(checked = reconciled.checked, %drift, "Reconciliation repaired drift");
+        notify::emit(Event::new(
+            Severity::Warning,
+            LEDGER_DRIFT,
+            "Ledger out of step with the chain",
+            format!(
+                "Reconciliation checked {} interaction(s) and repaired: {}.",
+                reconciled.checked, drift
+            ),
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Crash recovery.
+//!
+//! Before listening, the oracle resumes every interaction the ledger left unsettled from the
+//! stage it reached, so a crash doesn't cost another LLM call for answers already generated:
+//!
+//! - `Submitting`: the callback transactions sent are checked by signature. If the account
+//!   has been answered since, the interaction is confirmed; otherwise the persisted response is
+//!   submitted again.
+//! - `Validating`: the draft couldn't be checked against its sources, which aren't persisted,
+//!   so it is hedged, goes through the guardrails again and is submitted. Drafts of structured
+//!   output contexts are only persisted once they match their schema and aren't hedged.
+//! - `Detected`, `Claimed`, `Generating`: nothing usable was generated, the interaction is
+//!   dispatched to be answered again.
+//!
+//! Interactions closed or rewritten in the meantime are abandoned. Failed interactions are left
+//! to the dead-letter queue, or to the gap-fill when their callback wasn't queued.
+
+use crate::decode::InteractionView;
+use crate::dedup::prompt_hash;
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::processor::submit_response;
+use crate::status::InteractionStatus;
+use crate::verification::hedge;
+use crate::worker_pool::WorkerPool;
+use crate::OracleError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::str::FromStr;
+use tracing::{info, info_span, warn, Instrument};
+
+/// Signatures of `sent` that landed without error, looked up in the transaction history as they
+/// may be older than the recent status cache
+pub async fn landed(oracle: &Oracle, sent: &[String]) -> Result<Vec<Signature>, OracleError> {
+    let signatures = sent
+        .iter()
+        .map(|signature| Signature::from_str(signature))
+        .collect::<Result<Vec<_>, _>>()?;
+    if signatures.is_empty() {
+        return Ok(Vec::new());
+    }
+    let statuses = oracle
+        .rpc_client
+        .get_signature_statuses_with_history(&signatures)
+        .await?
+        .value;
+    Ok(signatures
+        .into_iter()
+        .zip(statuses)
+        .filter(|(_, status)| status.as_ref().is_some_and(|status| status.err.is_none()))
+        .map(|(signature, _)| signature)
+        .collect())
+}
+
+/// Resume an unsettled interaction from the stage it reached, returning the outcome
+pub async fn resume(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    interaction_pubkey: Pubkey,
+    hash: [u8; 32],
+    status: InteractionStatus,
+    response: Option<String>,
+    sent: Vec<String>,
+) -> Result<&'static str, OracleError> {
+    let account = oracle
+        .rpc_client
+        .get_account_with_commitment(&interaction_pubkey, oracle.rpc_client.commitment())
+        .await?
+        .value
+        .unwrap_or_default();
+    // The owner is the program the interaction belongs to
+    let program = account.owner;
+    let view = InteractionView::parse(&account.data);
+    let Some(view) = view.filter(|view| prompt_hash(view.text) == hash) else {
+        // Closed or rewritten: the prompt this entry is about is gone
+        info!("Interaction no longer exists, abandoning");
+        oracle
+            .processed
+            .abandon_by_hash(&interaction_pubkey, &hash)?;
+        return Ok("abandoned");
+    };
+    let ledger = &oracle.processed;
+
+    if view.is_processed {
+        let landed = landed(oracle, &sent).await?;
+        let status = if landed.is_empty() {
+            InteractionStatus::Abandoned
+        } else {
+            InteractionStatus::Confirmed
+        };
+        info!(%status, "Interaction answered while down");
+        ledger.transition(&interaction_pubkey, view.text, status, &landed)?;
+        return Ok(status.as_str());
+    }
+
+    let interaction = view.decode()?;
+    match (status, response) {
+        (InteractionStatus::Submitting, Some(response)) => {
+            info!("Submitting the persisted response again");
+            submit_response(
+                oracle,
+                &program,
+                &interaction_pubkey,
+                &interaction,
+                &response,
+            )
+            .await?;
+            Ok("resubmitted")
+        }
+        (InteractionStatus::Validating, Some(draft)) => {
+            info!("Submitting the unverified draft");
+            ledger.transition(
+                &interaction_pubkey,
+                view.text,
+                InteractionStatus::Submitting,
+                &[],
+            )?;
+            let draft = match oracle.structured.get(&interaction.context) {
+                Some(_) => draft,
+                None => hedge(&draft, &[]),
+            };
+            let response = oracle.guardrails.get().apply(&draft).await;
+            submit_response(
+                oracle,
+                &program,
+                &interaction_pubkey,
+                &interaction,
+                &response,
+            )
+            .await?;
+            Ok("resubmitted")
+        }
+        _ => {
+            info!("No usable response persisted, answering again");
+            ledger.transition(
+                &interaction_pubkey,
+                view.text,
+                InteractionStatus::Detected,
+                &[],
+            )?;
+            worker_pool.dispatch(program, interaction_pubkey, account.data);
+            Ok("redispatched")
+        }
+    }
+}
+
+/// Resume the interactions left unsettled by the previous run
+pub async fn recover(oracle: &Oracle, worker_pool: &WorkerPool) -> Result<(), OracleError> {
+    let unsettled = oracle.processed.unsettled()?;
+    let mut resumed = 0;
+    for (interaction_pubkey, hash, entry) in unsettled {
+        if entry.status == InteractionStatus::Failed {
+            continue;
+        }
+        let span =
+            info_span!("recovery", interaction = %interaction_pubkey, status = %entry.status);
+        let result = resume(
+            oracle,
+            worker_pool,
+            interaction_pubkey,
+            hash,
+            entry.status,
+            entry.response,
+            entry.sent,
+        )
+        .instrument(span)
+        .await;
+        match result {
+            Ok(outcome) => {
+                resumed += 1;
+                METRICS.recoveries.with_label_values(&[outcome]).inc();
+            }
+            Err(e) => {
+                warn!(interaction = %interaction_pubkey, error = ?e, "Failed to resume interaction");
+                METRICS.recoveries.with_label_values(&["error"]).inc();
+            }
+        }
+    }
+    if resumed > 0 {
+        info!(resumed, "Resumed interactions from the ledger");
+    }
+    Ok(())
+}
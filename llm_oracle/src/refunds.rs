@@ -0,0 +1,200 @@
+//! Refund watchdog.
+//!
+//! The program's `refund_interaction` closes an interaction left unanswered for
+//! `solana_gpt_oracle::REFUND_TIMEOUT_SECS` and returns its lamports, the rent its creator paid,
+//! to the creator. Anyone can send it. With `refunds.enabled` (`REFUND_WATCHDOG`), the oracle
+//! looks for such interactions of its programs every `refunds.interval_secs`
+//! (`REFUND_INTERVAL_SECS`) and refunds them, paying the fees, so users get their funds back
+//! when the oracle can't answer them. The watchdog leaves alone the interactions the worker pool
+//! holds or defers for flooding and those whose callback the dead-letter queue retries, which
+//! are still being answered.
+//! `llm_oracle refunds` does a single pass over all of them, for an oracle that is down for good.
+//!
+//! Refunds are added to the costs of their context (`llm_oracle costs`) and counted in the
+//! `refunds_total` and `refunded_lamports_total` metrics. Refunded interactions are abandoned in
+//! the ledger and dropped from the dead-letter queue.
+
+use crate::admin::upgrade_instruction;
+use crate::listener::pending_interactions;
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::status::InteractionStatus;
+use crate::worker_pool::WorkerPool;
+use crate::OracleError;
+use anchor_lang::Discriminator;
+use chrono::Utc;
+use solana_gpt_oracle::REFUND_TIMEOUT_SECS;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use solana_sdk::transaction::Transaction;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// Refunds sent by a [`sweep`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Refunded {
+    pub interactions: usize,
+    pub lamports: u64,
+    /// Refunds that failed, tried again by the next sweep
+    pub failed: usize,
+}
+
+pub fn refund_instruction(
+    program: &Pubkey,
+    interaction_pubkey: &Pubkey,
+    user: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program,
+        accounts: vec![
+            AccountMeta::new(*interaction_pubkey, false),
+            AccountMeta::new(*user, false),
+        ],
+        data: solana_gpt_oracle::instruction::RefundInteraction::DISCRIMINATOR.to_vec(),
+    }
+}
+
+/// Whether an interaction created at `created_at` can be refunded at `now`, both Unix timestamps
+pub fn is_overdue(created_at: i64, now: i64) -> bool {
+    now >= created_at.saturating_add(REFUND_TIMEOUT_SECS)
+}
+
+/// Whether the running oracle is still answering an interaction: held by `worker_pool`, deferred
+/// for flooding, or with a dead letter for its question
+fn is_answering(
+    oracle: &Oracle,
+    worker_pool: &WorkerPool,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+) -> Result<bool, OracleError> {
+    if worker_pool.is_pending(interaction_pubkey) || oracle.flood.is_deferred(interaction_pubkey) {
+        return Ok(true);
+    }
+    Ok(oracle
+        .dlq
+        .get(interaction_pubkey)?
+        .is_some_and(|letter| letter.prompt == interaction.text))
+}
+
+/// Refund the overdue interactions of every program of the oracle, except those the running
+/// oracle's `worker_pool` is still answering
+pub async fn sweep(
+    oracle: &Oracle,
+    worker_pool: Option<&WorkerPool>,
+) -> Result<Refunded, OracleError> {
+    let now = Utc::now().timestamp();
+    let mut refunded = Refunded::default();
+    for program in &oracle.config.programs {
+        let pending = pending_interactions(&oracle.rpc_client, &program.id).await?;
+        for (pubkey, interaction) in pending {
+            if !is_overdue(interaction.created_at, now) {
+                continue;
+            }
+            if let Some(worker_pool) = worker_pool {
+                if is_answering(oracle, worker_pool, &pubkey, &interaction)? {
+                    debug!(interaction = %pubkey, "Overdue interaction still being answered");
+                    continue;
+                }
+            }
+            match refund(oracle, &program.id, &pubkey, &interaction).await {
+                Ok(lamports) => {
+                    refunded.interactions += 1;
+                    refunded.lamports += lamports;
+                }
+                Err(e) => {
+                    warn!(interaction = %pubkey, error = ?e, "Refund failed");
+                    refunded.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(refunded)
+}
+
+/// Send the refund of an interaction, returning the lamports refunded
+async fn refund(
+    oracle: &Oracle,
+    program: &Pubkey,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+) -> Result<u64, OracleError> {
+    let lamports = oracle.rpc_client.get_balance(interaction_pubkey).await?;
+    let payer = oracle.config.payer.as_ref();
+    let mut instructions = Vec::new();
+    if interaction.created_at == 0 {
+        // Created before `created_at` was recorded: it may not deserialize without it
+        instructions.push(upgrade_instruction(
+            program,
+            &payer.pubkey(),
+            interaction_pubkey,
+        ));
+    }
+    instructions.push(refund_instruction(
+        program,
+        interaction_pubkey,
+        &interaction.user,
+    ));
+    let recent_blockhash = oracle.rpc_client.get_latest_blockhash().await?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    let signature = oracle
+        .rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .await?;
+    info!(
+        interaction = %interaction_pubkey,
+        user = %interaction.user,
+        lamports,
+        %signature,
+        "Refunded an unanswered interaction"
+    );
+    settle(oracle, interaction_pubkey, interaction, lamports);
+    Ok(lamports)
+}
+
+/// Account for a refund and stop answering the interaction
+fn settle(
+    oracle: &Oracle,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    lamports: u64,
+) {
+    METRICS.refunds.inc();
+    METRICS.refunded_lamports.inc_by(lamports);
+    oracle.costs.record_refund(&interaction.context, lamports);
+    if let Err(e) = oracle.processed.transition(
+        interaction_pubkey,
+        &interaction.text,
+        InteractionStatus::Abandoned,
+        &[],
+    ) {
+        warn!(interaction = %interaction_pubkey, error = ?e, "Failed to abandon the interaction");
+    }
+    if let Err(e) = oracle.dlq.remove(interaction_pubkey) {
+        warn!(interaction = %interaction_pubkey, error = ?e, "Failed to drop the dead letter");
+    }
+}
+
+/// Sweep every `refunds.interval_secs` until the process exits
+pub async fn run(oracle: Arc<Oracle>, worker_pool: WorkerPool) {
+    let interval = Duration::from_secs(oracle.config.refunds.interval_secs);
+    loop {
+        match sweep(&oracle, Some(&worker_pool)).await {
+            Ok(refunded) if refunded.interactions > 0 || refunded.failed > 0 => info!(
+                interactions = refunded.interactions,
+                lamports = refunded.lamports,
+                failed = refunded.failed,
+                "Refund sweep done"
+            ),
+            Ok(_) => {}
+            Err(e) => error!(error = ?e, "Refund sweep failed"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
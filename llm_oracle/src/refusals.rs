@@ -0,0 +1,70 @@
+//! Refusal codes.
+//!
+//! With `refusals.enabled` (`REFUSAL_CODES`), the interactions the oracle won't answer get a
+//! machine-readable refusal, `[refusal:<code>]`, instead of a free-text apology, for callback
+//! programs to branch on with `solana_gpt_oracle::refusal::parse`:
+//!
+//! - `moderation`: the answer matched the blocklist or was flagged by moderation, instead of
+//!   `guardrails.fallback_response`
+//! - `expired`: the interaction was older than `refusals.max_age_secs` (`REFUSAL_MAX_AGE_SECS`)
+//!   when picked up, e.g. after downtime; the LLM isn't called
+//! - `over_budget`: the interaction is over a rate limit or a provider's daily budget with
+//!   `limits.action = "respond"`, instead of `limits.response`
+//! - `invalid_format`: the answer was empty, or still didn't match the output schema of its
+//!   context after `llm.schema_corrections`, instead of failing the interaction
+//!
+//! Refusals are counted in `refusals_total`, by `code`.
+
+use crate::config::RefusalConfig;
+use crate::metrics::METRICS;
+pub use solana_gpt_oracle::refusal::Refusal;
+use tracing::info;
+
+/// The response refusing an interaction for `refusal`, counted
+pub fn refuse(refusal: Refusal) -> String {
+    info!(code = refusal.code(), "Refusing the interaction");
+    METRICS.refusals.with_label_values(&[refusal.code()]).inc();
+    refusal.tag()
+}
+
+/// The response to an interaction refused for `refusal`: its code with refusal codes on, `text`
+/// otherwise
+pub fn response(config: &RefusalConfig, refusal: Refusal, text: &str) -> String {
+    if config.enabled {
+        refuse(refusal)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Whether an interaction created at `created_at` is too old to answer at `now`, both Unix
+/// timestamps. Interactions created before `created_at` was recorded have it at 0: their age is
+/// unknown, and they never expire.
+pub fn is_expired(config: &RefusalConfig, created_at: i64, now: i64) -> bool {
+    created_at != 0
+        && config
+            .max_age_secs
+            .is_some_and(|max_age| now.saturating_sub(created_at) > max_age as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_interactions_of_known_age_only() {
+        let config = RefusalConfig {
+            enabled: true,
+            max_age_secs: Some(600),
+        };
+        let now = 1_700_000_000;
+        assert!(!is_expired(&config, now - 600, now));
+        assert!(is_expired(&config, now - 601, now));
+        assert!(!is_expired(&config, 0, now));
+        let config = RefusalConfig {
+            enabled: true,
+            max_age_secs: None,
+        };
+        assert!(!is_expired(&config, now - 86_400, now));
+    }
+}
@@ -0,0 +1,128 @@
+//! Hot reload.
+//!
+//! On SIGHUP, or `POST /admin/reload` with the admin API, the oracle reads its config file and
+//! prompt templates again and swaps in, without restarting or dropping its subscriptions:
+//!
+//! - the prompt templates: `PROMPT_TEMPLATE` and the context templates of `PROMPT_TEMPLATE_DIR`
+//! - the guardrails: response size, blocklist (the blocklist file included), moderation and
+//!   fallback response
+//! - the generation settings of `llm`: temperature, max tokens, top p, penalties and system
+//!   prompt
+//!
+//! Everything is loaded before anything is swapped, so a reload that fails, e.g. on a template
+//! that doesn't compile or an invalid blocklist pattern, changes nothing. Interactions in flight
+//! finish with what they started with. Environment variables still take precedence over the
+//! file, as the environment of a running process doesn't change; a generation setting removed
+//! from the file keeps the value the oracle started with, and contexts with their own settings
+//! (see [`crate::context_settings`]) keep theirs. Other settings still need a restart.
+//!
+//! Reloads are counted in `reloads_total`, by `outcome`.
+
+use crate::guardrails::Guardrails;
+use crate::metrics::METRICS;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::prompts::PromptTemplates;
+use crate::providers::GenerationParams;
+use crate::OracleError;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+
+/// A value swapped whole on reload; readers keep the one they got for as long as they need it
+pub struct Reloadable<T>(RwLock<Arc<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(RwLock::new(Arc::new(value)))
+    }
+
+    /// The current value
+    pub fn get(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+tokio::task_local! {
+    static GENERATION: RefCell<Option<Arc<GenerationParams>>>;
+}
+
+/// Run `future` with its own generation settings, see [`use_generation`]
+pub async fn scope<F: Future>(future: F) -> F::Output {
+    GENERATION.scope(RefCell::new(None), future).await
+}
+
+/// Have the provider calls of the current interaction use `params`, the settings reloaded last
+pub fn use_generation(params: Arc<GenerationParams>) {
+    let _ = GENERATION.try_with(|generation| *generation.borrow_mut() = Some(params));
+}
+
+/// The generation settings of the current interaction, if set with [`use_generation`]
+pub fn generation() -> Option<Arc<GenerationParams>> {
+    GENERATION
+        .try_with(|generation| generation.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+/// Load the config file and prompt templates again, and swap in what they change
+pub fn reload(oracle: &Oracle) -> Result<(), OracleError> {
+    let loaded = oracle.config.reload()?;
+    let prompts = PromptTemplates::from_env()?;
+    let guardrails =
+        Guardrails::new(&loaded.guardrails)?.with_refusal_codes(oracle.config.refusals.enabled);
+    let templates = prompts.overrides().len();
+    let blocklist = loaded.guardrails.blocklist.len();
+    oracle.prompts.set(prompts);
+    oracle.guardrails.set(guardrails);
+    oracle.generation.set(GenerationParams::from(&loaded.llm));
+    info!(templates, blocklist, "Settings reloaded");
+    Ok(())
+}
+
+/// [`reload`], logged, counted and notified when it fails
+pub fn reload_logged(oracle: &Oracle) -> Result<(), OracleError> {
+    match reload(oracle) {
+        Ok(()) => {
+            METRICS.reloads.with_label_values(&["ok"]).inc();
+            Ok(())
+        }
+        Err(e) => {
+            warn!(error = %e, "Reload failed, keeping the current settings");
+            METRICS.reloads.with_label_values(&["failed"]).inc();
+            notify::emit(Event::new(
+                Severity::Warning,
+                "reload_failed",
+                "Reload failed, the current settings are kept",
+                e.to_string(),
+            ));
+            Err(e)
+        }
+    }
+}
+
+/// Reload on every SIGHUP
+pub async fn run(oracle: Arc<Oracle>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!(error = ?e, "Can't handle SIGHUP, reload with the admin API instead");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading");
+            let _ = reload_logged(&oracle);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = oracle;
+}
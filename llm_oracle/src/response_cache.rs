@@ -0,0 +1,208 @@
+//! Response cache.
+//!
+//! With `cache.ttl_secs` set, the answer to the first question of a conversation is kept for that
+//! long, keyed by a hash of the context account, the normalized question (trimmed, lowercased,
+//! whitespace collapsed) and the provider and model answering it. The same question asked again
+//! in the same context is answered from the cache without calling the LLM. Follow-ups, game turns
+//! and answers grounded on tool data depend on more than the question and are never cached, nor
+//! are answers flagged by the hallucination guard or the guardrails.
+//!
+//! The most recent `cache.max_entries` answers are kept in memory, and every answer in a sled
+//! database at `RESPONSE_CACHE_PATH`, so the cache survives restarts. Lookups are counted in
+//! `response_cache_total`.
+
+use crate::config::deployment_path;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+pub const DEFAULT_CACHE_PATH: &str = "./oracle-response-cache";
+
+pub type CacheKey = [u8; 32];
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Cached {
+    response: String,
+    /// Unix timestamp in seconds
+    cached_at: u64,
+    /// The interaction whose answer this is
+    #[serde(default)]
+    interaction: Option<String>,
+}
+
+/// Key of `text` asked in `context` and answered by `model`
+pub fn key(context: &Pubkey, text: &str, model: &str) -> CacheKey {
+    let normalized = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let mut hasher = Sha256::new();
+    hasher.update(context.as_ref());
+    hasher.update(normalized.as_bytes());
+    hasher.update([0]);
+    hasher.update(model.as_bytes());
+    hasher.finalize().into()
+}
+
+#[derive(Default)]
+struct Recent {
+    entries: HashMap<CacheKey, Cached>,
+    /// Insertion order, oldest first
+    order: VecDeque<CacheKey>,
+}
+
+pub struct ResponseCache {
+    ttl: Duration,
+    max_entries: usize,
+    recent: Mutex<Recent>,
+    db: sled::Db,
+}
+
+impl ResponseCache {
+    pub fn open(path: &str, ttl: Duration, max_entries: usize) -> Result<Self, OracleError> {
+        Ok(Self {
+            ttl,
+            max_entries,
+            recent: Mutex::new(Recent::default()),
+            db: sled::open(path)?,
+        })
+    }
+
+    /// The cache at `RESPONSE_CACHE_PATH`, `None` without a TTL
+    pub fn from_env(
+        ttl_secs: Option<u64>,
+        max_entries: usize,
+    ) -> Result<Option<Self>, OracleError> {
+        let Some(ttl_secs) = ttl_secs else {
+            return Ok(None);
+        };
+        let path = deployment_path(
+            &env::var("RESPONSE_CACHE_PATH").unwrap_or(DEFAULT_CACHE_PATH.to_string()),
+        );
+        let cache = Self::open(&path, Duration::from_secs(ttl_secs), max_entries)?;
+        let purged = cache.purge()?;
+        info!(%path, ttl_secs, purged, "Response cache");
+        Ok(Some(cache))
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn is_fresh(&self, cached: &Cached) -> bool {
+        now().saturating_sub(cached.cached_at) < self.ttl.as_secs()
+    }
+
+    fn remember(&self, key: CacheKey, cached: Cached) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.entries.insert(key, cached).is_none() {
+            recent.order.push_back(key);
+        }
+        while recent.order.len() > self.max_entries {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.entries.remove(&oldest);
+            }
+        }
+    }
+
+    /// The cached answer of `key`, unless it expired
+    pub fn get(&self, key: &CacheKey) -> Option<String> {
+        let recent = self.recent.lock().unwrap().entries.get(key).cloned();
+        let cached = match recent {
+            Some(cached) => Some(cached),
+            None => match self.db.get(key) {
+                Ok(bytes) => bytes.and_then(|bytes| serde_json::from_slice(&bytes).ok()),
+                Err(e) => {
+                    warn!(error = ?e, "Failed to read the response cache");
+                    None
+                }
+            },
+        };
+        match cached.filter(|cached| self.is_fresh(cached)) {
+            Some(cached) => {
+                METRICS.response_cache.with_label_values(&["hit"]).inc();
+                let response = cached.response.clone();
+                self.remember(*key, cached);
+                Some(response)
+            }
+            None => {
+                METRICS.response_cache.with_label_values(&["miss"]).inc();
+                None
+            }
+        }
+    }
+
+    /// Cache `response`, the answer of `interaction`
+    pub fn put(&self, key: CacheKey, response: &str, interaction: &Pubkey) {
+        let cached = Cached {
+            response: response.to_string(),
+            cached_at: now(),
+            interaction: Some(interaction.to_string()),
+        };
+        let stored = serde_json::to_vec(&cached)
+            .map_err(OracleError::from)
+            .and_then(|bytes| Ok(self.db.insert(key, bytes)?));
+        if let Err(e) = stored {
+            warn!(error = ?e, "Failed to write the response cache");
+        }
+        self.remember(key, cached);
+    }
+
+    /// Drop the answers matching `remove`, and those that can't be read
+    fn remove_where(&self, remove: impl Fn(&Cached) -> bool) -> Result<usize, OracleError> {
+        let mut removed = 0;
+        for entry in self.db.iter() {
+            let (key, bytes) = entry?;
+            let remove =
+                serde_json::from_slice::<Cached>(&bytes).map_or(true, |cached| remove(&cached));
+            if remove {
+                self.db.remove(&key)?;
+                if let Ok(key) = CacheKey::try_from(key.as_ref()) {
+                    let mut recent = self.recent.lock().unwrap();
+                    if recent.entries.remove(&key).is_some() {
+                        recent.order.retain(|recent| *recent != key);
+                    }
+                }
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Drop the expired answers from disk
+    fn purge(&self) -> Result<usize, OracleError> {
+        self.remove_where(|cached| !self.is_fresh(cached))
+    }
+
+    /// Drop the answers cached before `cutoff` (Unix seconds)
+    pub fn remove_before(&self, cutoff: u64) -> Result<usize, OracleError> {
+        self.remove_where(|cached| cached.cached_at < cutoff)
+    }
+
+    /// Drop the answer of an interaction
+    pub fn remove_interaction(&self, interaction: &Pubkey) -> Result<usize, OracleError> {
+        let interaction = interaction.to_string();
+        self.remove_where(|cached| cached.interaction.as_ref() == Some(&interaction))
+    }
+
+    pub fn flush(&self) -> Result<(), OracleError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
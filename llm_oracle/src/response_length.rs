@@ -0,0 +1,210 @@
+//! Adaptive response length.
+//!
+//! With `response_length.enabled` (`ADAPTIVE_LENGTH`), the oracle counts for each context how
+//! many of its answers are cut: by the model stopping at its output token limit, as the provider
+//! reports it (`finish_reason` `length`, Gemini's `MAX_TOKENS`), or by the guardrails truncating
+//! them to `max_response_bytes`, the room an answer has on-chain. Every
+//! `response_length.window` (`ADAPTIVE_LENGTH_WINDOW`) answers of a context, its limits are
+//! adjusted when more than `response_length.target_percent` (`ADAPTIVE_LENGTH_TARGET_PERCENT`)
+//! of them were cut:
+//!
+//! - answers cut on-chain get the prompt instruction "Answer in under N characters", N starting
+//!   at nine tenths of `max_response_bytes` and going down a tenth each time, to a quarter of it.
+//!   More tokens would only be wasted.
+//! - answers cut at the token limit get a quarter more tokens, up to `response_length.max_tokens`
+//!   (`ADAPTIVE_LENGTH_MAX_TOKENS`, twice `llm.max_tokens` by default), which bounds what an
+//!   answer can cost; past it they are asked to be shorter like the above
+//!
+//! A window without cuts relaxes the instruction a step, dropping it once it allows what fits
+//! on-chain. Token limits aren't lowered again. Contexts answered with their own settings (see
+//! [`crate::context_settings`]) keep the max tokens they chose. The limits live in memory and
+//! start over when the oracle restarts; cuts are counted in `response_cuts_total`.
+
+use crate::config::ResponseLengthConfig;
+use crate::metrics::METRICS;
+use solana_sdk::pubkey::Pubkey;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use tracing::info;
+
+/// Contexts tracked at most; all start over when there are more
+pub const MAX_CONTEXTS: usize = 4096;
+/// Characters per token assumed when asking for answers shorter than a token limit
+const CHARS_PER_TOKEN: usize = 4;
+
+tokio::task_local! {
+    static CALL: RefCell<Call>;
+}
+
+/// Limits of the interaction being processed, and whether the model hit them
+#[derive(Debug, Default)]
+struct Call {
+    max_tokens: Option<u32>,
+    capped: bool,
+}
+
+/// Run `future` with its own max tokens override and token limit reports
+pub async fn scope<F: Future>(future: F) -> F::Output {
+    CALL.scope(RefCell::new(Call::default()), future).await
+}
+
+/// Have the provider calls of the current interaction ask for up to `max_tokens`
+pub fn set_max_tokens(max_tokens: u32) {
+    let _ = CALL.try_with(|call| call.borrow_mut().max_tokens = Some(max_tokens));
+}
+
+/// Max tokens to ask for: those of the current interaction, or else `default`
+pub fn max_tokens(default: u32) -> u32 {
+    CALL.try_with(|call| call.borrow().max_tokens)
+        .ok()
+        .flatten()
+        .unwrap_or(default)
+}
+
+/// Report that the model stopped at the token limit
+pub fn report_capped() {
+    let _ = CALL.try_with(|call| call.borrow_mut().capped = true);
+}
+
+/// Whether the model stopped at the token limit since the last call
+pub fn take_capped() -> bool {
+    CALL.try_with(|call| std::mem::take(&mut call.borrow_mut().capped))
+        .unwrap_or(false)
+}
+
+/// What the answers of a context are held to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_tokens: u32,
+    /// Characters the model is asked to stay under
+    pub max_chars: Option<usize>,
+}
+
+/// Cuts of the current window of a context, and its limits
+#[derive(Debug, Clone, Default)]
+struct ContextLength {
+    answers: u32,
+    token_cuts: u32,
+    byte_cuts: u32,
+    /// Raised max tokens
+    max_tokens: Option<u32>,
+    max_chars: Option<usize>,
+}
+
+impl ContextLength {
+    fn limits(&self, base_tokens: u32) -> Limits {
+        Limits {
+            max_tokens: self
+                .max_tokens
+                .map_or(base_tokens, |raised| raised.max(base_tokens)),
+            max_chars: self.max_chars,
+        }
+    }
+}
+
+pub struct ResponseLengths {
+    config: ResponseLengthConfig,
+    contexts: Mutex<HashMap<Pubkey, ContextLength>>,
+}
+
+impl ResponseLengths {
+    pub fn new(config: ResponseLengthConfig) -> Self {
+        Self {
+            config,
+            contexts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The limits of the answers of `context`, given `llm.max_tokens`
+    pub fn limits(&self, context: &Pubkey, base_tokens: u32) -> Limits {
+        self.contexts
+            .lock()
+            .unwrap()
+            .get(context)
+            .cloned()
+            .unwrap_or_default()
+            .limits(base_tokens)
+    }
+
+    /// Count an answer of `context`, cut at the token limit or to `max_bytes`, adjusting its
+    /// limits at the end of a window
+    pub fn record(
+        &self,
+        context: &Pubkey,
+        base_tokens: u32,
+        max_bytes: usize,
+        token_cut: bool,
+        byte_cut: bool,
+    ) {
+        if token_cut {
+            METRICS.response_cuts.with_label_values(&["tokens"]).inc();
+        }
+        if byte_cut {
+            METRICS.response_cuts.with_label_values(&["bytes"]).inc();
+        }
+        let mut contexts = self.contexts.lock().unwrap();
+        if contexts.len() >= MAX_CONTEXTS && !contexts.contains_key(context) {
+            contexts.clear();
+        }
+        let length = contexts.entry(*context).or_default();
+        length.answers += 1;
+        length.token_cuts += u32::from(token_cut);
+        length.byte_cuts += u32::from(byte_cut);
+        if length.answers < self.config.window {
+            return;
+        }
+        let before = length.limits(base_tokens);
+        let limits = self.adjust(length, before, max_bytes);
+        if limits != before {
+            info!(
+                %context,
+                answers = length.answers,
+                token_cuts = length.token_cuts,
+                byte_cuts = length.byte_cuts,
+                max_tokens = limits.max_tokens,
+                max_chars = ?limits.max_chars,
+                "Adjusted the response length of a context"
+            );
+            if limits.max_tokens != before.max_tokens {
+                length.max_tokens = Some(limits.max_tokens);
+            }
+            length.max_chars = limits.max_chars;
+        }
+        length.answers = 0;
+        length.token_cuts = 0;
+        length.byte_cuts = 0;
+    }
+
+    /// The limits of a context after a window of answers
+    fn adjust(&self, length: &ContextLength, mut limits: Limits, max_bytes: usize) -> Limits {
+        let over = |cuts: u32| {
+            u64::from(cuts) * 100 > u64::from(length.answers) * self.config.target_percent
+        };
+        let shorter = |max_chars: Option<usize>, from: usize| {
+            Some(
+                (max_chars.unwrap_or(from) * 9 / 10)
+                    .max(max_bytes / 4)
+                    .max(1),
+            )
+        };
+        if over(length.byte_cuts) {
+            limits.max_chars = shorter(limits.max_chars, max_bytes);
+        } else if over(length.token_cuts) {
+            if limits.max_tokens < self.config.max_tokens {
+                limits.max_tokens =
+                    (limits.max_tokens + limits.max_tokens.div_ceil(4)).min(self.config.max_tokens);
+            } else {
+                let fits = limits.max_tokens as usize * CHARS_PER_TOKEN;
+                limits.max_chars = shorter(limits.max_chars, fits.min(max_bytes));
+            }
+        } else if length.byte_cuts == 0 && length.token_cuts == 0 {
+            limits.max_chars = limits
+                .max_chars
+                .map(|max_chars| max_chars + max_chars.div_ceil(10))
+                .filter(|&max_chars| max_chars < max_bytes);
+        }
+        limits
+    }
+}
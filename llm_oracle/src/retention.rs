@@ -0,0 +1,124 @@
+//! Data retention.
+//!
+//! With `retention.prompt_days` set, the text of prompts and responses is deleted that many days
+//! after they were answered, keeping what metrics and costs are computed from: archive records
+//! keep the SHA-256 of their prompt and response, their provider, timestamps, latency and
+//! citations, ratings lose the response they rated, and ledger entries keep their status and
+//! signatures. Review items and cached answers are deleted. The policy is applied at startup and
+//! every [`INTERVAL`]. Conversations expire with `memory.ttl_secs` and costs are aggregated per
+//! context, without text.
+//!
+//! `llm_oracle purge --interaction <pubkey>` deletes the data of one interaction, for deletion
+//! requests: its archive records and ratings, the responses of its ledger entries, its review
+//! item, dead letter, cached answer and conversation. Stop the oracle first, its stores are
+//! locked while it runs. The knowledge base holds context texts and documents, never interaction
+//! data. The interaction account and its callback transactions are on-chain and can't be
+//! deleted.
+
+use crate::archive::Archive;
+use crate::config::OracleConfig;
+use crate::dedup::ProcessedSet;
+use crate::dlq::DeadLetterQueue;
+use crate::memory;
+use crate::oracle::Oracle;
+use crate::response_cache::ResponseCache;
+use crate::review::ReviewQueue;
+use crate::OracleError;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// How often the retention policy is applied
+pub const INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Records deleted or redacted, by store
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Purged {
+    pub archive: usize,
+    pub ledger: usize,
+    pub review: usize,
+    pub cache: usize,
+    pub dead_letters: usize,
+    pub conversation: bool,
+}
+
+impl Purged {
+    pub fn is_empty(&self) -> bool {
+        self.archive + self.ledger + self.review + self.cache + self.dead_letters == 0
+            && !self.conversation
+    }
+}
+
+/// Delete the text of the prompts and responses answered more than `days` ago
+pub fn apply(oracle: &Oracle, days: u64) -> Result<Purged, OracleError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let cutoff = now.saturating_sub(days * 24 * 3600);
+    let mut purged = Purged {
+        ledger: oracle.processed.redact_before(cutoff)?,
+        ..Purged::default()
+    };
+    if let Some(archive) = &oracle.archive {
+        purged.archive = archive.redact_before(cutoff * 1000)?;
+    }
+    if let Some(review) = &oracle.review {
+        purged.review = review.remove_before(cutoff * 1000)?;
+    }
+    if let Some(cache) = &oracle.response_cache {
+        purged.cache = cache.remove_before(cutoff)?;
+    }
+    Ok(purged)
+}
+
+/// Apply the retention policy every [`INTERVAL`]
+pub async fn run(oracle: Arc<Oracle>) {
+    let Some(days) = oracle.config.retention.prompt_days else {
+        return;
+    };
+    info!(
+        days,
+        "Deleting prompts and responses after the retention period"
+    );
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        match apply(&oracle, days) {
+            Ok(purged) if purged.is_empty() => {}
+            Ok(purged) => info!(?purged, "Applied the retention policy"),
+            Err(e) => warn!(error = ?e, "Failed to apply the retention policy"),
+        }
+    }
+}
+
+/// Delete the data of `interaction` from the stores configured in the environment
+pub fn purge(config: &OracleConfig, interaction: &Pubkey) -> Result<Purged, OracleError> {
+    let ledger = ProcessedSet::from_env(config.dedup_capacity)?;
+    let mut purged = Purged {
+        ledger: ledger.redact(interaction)?,
+        ..Purged::default()
+    };
+    ledger.flush()?;
+    if let Some(archive) = Archive::from_env()? {
+        purged.archive = archive.purge(interaction)?;
+    }
+    if let Some(review) = ReviewQueue::load(&config.review)? {
+        purged.review = review.remove(interaction)? as usize;
+    }
+    if let Some(cache) = ResponseCache::from_env(config.cache.ttl_secs, config.cache.max_entries)? {
+        purged.cache = cache.remove_interaction(interaction)?;
+        cache.flush()?;
+    }
+    let dlq = DeadLetterQueue::from_env(config.dlq_max_attempts)?;
+    if dlq.get(interaction)?.is_some() {
+        dlq.remove(interaction)?;
+        purged.dead_letters = 1;
+    }
+    let mut memory = memory::from_env(config.memory_limits())?;
+    purged.conversation = memory.get_history(interaction)?.is_some();
+    memory.close(interaction)?;
+    memory.flush()?;
+    Ok(purged)
+}
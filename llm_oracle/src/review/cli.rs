@@ -0,0 +1,136 @@
+//! `llm_oracle review ...` commands labeling the queued interactions.
+
+use super::{ExportFormat, Label, ReviewItem, ReviewQueue};
+use crate::config::ReviewConfig;
+use crate::OracleError;
+use clap::Subcommand;
+use solana_sdk::pubkey::Pubkey;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Subcommand)]
+pub enum ReviewCommand {
+    /// Label the pending interactions one by one
+    Label,
+    /// List the pending interactions
+    List {
+        /// Labeled interactions too
+        #[arg(long)]
+        all: bool,
+    },
+    /// Label one interaction
+    Set {
+        interaction: Pubkey,
+        /// `good` or `bad`
+        label: Label,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Write the labeled interactions as JSONL
+    Export {
+        /// `eval` for every labeled item, `finetune` for the good answers as chat examples
+        #[arg(long, default_value = "eval")]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+fn print_item(item: &ReviewItem) {
+    println!("== {} (context {})", item.interaction, item.context);
+    if !item.flags.is_empty() {
+        println!("flags: {}", item.flags.join(", "));
+    }
+    for message in &item.messages {
+        println!("-- {}\n{}", message.role, message.content);
+    }
+    println!("-- response ({})\n{}", item.provider, item.response);
+}
+
+fn read_line(prompt: &str) -> Result<Option<String>, OracleError> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Walk through the pending items until they are all labeled or the reviewer quits
+fn label_pending(queue: &ReviewQueue) -> Result<(), OracleError> {
+    let pending = queue.pending()?;
+    let total = pending.len();
+    let mut labeled = 0;
+    for (i, item) in pending.into_iter().enumerate() {
+        println!("\n[{}/{}]", i + 1, total);
+        print_item(&item);
+        let label = loop {
+            let Some(answer) = read_line("[g]ood, [b]ad, [s]kip or [q]uit? ")? else {
+                break None;
+            };
+            match answer.as_str() {
+                "g" | "good" => break Some(Label::Good),
+                "b" | "bad" => break Some(Label::Bad),
+                "s" | "skip" => break None,
+                "q" | "quit" => {
+                    println!("Labeled {} of {} pending", labeled, total);
+                    return Ok(());
+                }
+                _ => continue,
+            }
+        };
+        let Some(label) = label else {
+            continue;
+        };
+        let note = read_line("Note (optional): ")?.filter(|note| !note.is_empty());
+        queue.label(&item.interaction.parse()?, label, note)?;
+        labeled += 1;
+    }
+    println!("Labeled {} of {} pending", labeled, total);
+    Ok(())
+}
+
+/// Run a `review` subcommand
+pub async fn run(command: ReviewCommand, config: &ReviewConfig) -> Result<(), OracleError> {
+    let queue = ReviewQueue::load(config)?
+        .ok_or("Set `review.path` (REVIEW_PATH) to use the review queue")?;
+    match command {
+        ReviewCommand::Label => label_pending(&queue)?,
+        ReviewCommand::List { all } => {
+            let items = if all {
+                queue.items()?
+            } else {
+                queue.pending()?
+            };
+            for item in &items {
+                let label = item.label.map(|label| label.to_string());
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    item.interaction,
+                    item.context,
+                    label.as_deref().unwrap_or("pending"),
+                    item.flags.join(",")
+                );
+            }
+            println!("{} interaction(s)", items.len());
+        }
+        ReviewCommand::Set {
+            interaction,
+            label,
+            note,
+        } => {
+            queue.label(&interaction, label, note)?;
+            println!("Labeled {} {}", interaction, label);
+        }
+        ReviewCommand::Export { format, output } => {
+            let written = match output {
+                Some(path) => queue.export(format, &mut std::fs::File::create(path)?)?,
+                None => queue.export(format, &mut io::stdout().lock())?,
+            };
+            eprintln!("Exported {} interaction(s)", written);
+        }
+    }
+    Ok(())
+}
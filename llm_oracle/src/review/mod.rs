@@ -0,0 +1,288 @@
+//! Human review queue.
+//!
+//! With `review.path` (`REVIEW_PATH`) set, `review.sample_percent` (`REVIEW_SAMPLE_PERCENT`) of the
+//! answered interactions, and every interaction flagged while validating its answer (rejected by
+//! the guardrails or marked unverified by the hallucination guard), are copied to a sled database
+//! with the messages the model was sent. `llm_oracle review` labels them good or bad, and exports
+//! the labels as JSONL for evaluation, or the good answers as chat fine-tuning examples.
+//!
+//! Sampling hashes the interaction pubkey, so the same interactions are picked on every replica.
+
+use crate::config::{deployment_path, ReviewConfig};
+use crate::metrics::METRICS;
+use crate::providers::{ChatMessage, Role};
+use crate::OracleError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod cli;
+
+pub const DEFAULT_SAMPLE_PERCENT: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Label {
+    Good,
+    Bad,
+}
+
+impl FromStr for Label {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "good" => Ok(Self::Good),
+            "bad" => Ok(Self::Bad),
+            other => Err(format!("expected good or bad, got {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Good => write!(f, "good"),
+            Self::Bad => write!(f, "bad"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl From<&ChatMessage> for ReviewMessage {
+    fn from(message: &ChatMessage) -> Self {
+        let role = match message.role {
+            Role::System => "system",
+            Role::Assistant => "assistant",
+            _ => "user",
+        };
+        Self {
+            role: role.to_string(),
+            content: message.content.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewItem {
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    /// What the model was sent, history and grounding included
+    pub messages: Vec<ReviewMessage>,
+    /// The response as sent in the callback
+    pub response: String,
+    pub provider: String,
+    /// Why the interaction was queued besides sampling, e.g. `unverified` or `blocklisted`
+    #[serde(default)]
+    pub flags: Vec<String>,
+    pub label: Option<Label>,
+    pub note: Option<String>,
+    /// Unix timestamps in milliseconds
+    pub created_at: u64,
+    pub labeled_at: Option<u64>,
+}
+
+impl ReviewItem {
+    pub fn new(
+        interaction_pubkey: &Pubkey,
+        interaction: &solana_gpt_oracle::Interaction,
+        messages: &[ChatMessage],
+        response: &str,
+        provider: &str,
+        flags: Vec<String>,
+    ) -> Self {
+        Self {
+            interaction: interaction_pubkey.to_string(),
+            context: interaction.context.to_string(),
+            user: interaction.user.to_string(),
+            messages: messages.iter().map(ReviewMessage::from).collect(),
+            response: response.to_string(),
+            provider: provider.to_string(),
+            flags,
+            label: None,
+            note: None,
+            created_at: now_millis(),
+            labeled_at: None,
+        }
+    }
+}
+
+/// Formats of `llm_oracle review export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Every labeled item, label and note included
+    Eval,
+    /// The good answers as `{"messages": [...]}` chat examples
+    Finetune,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "eval" => Ok(Self::Eval),
+            "finetune" => Ok(Self::Finetune),
+            other => Err(format!("expected eval or finetune, got {:?}", other)),
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Whether `interaction` falls in the `percent` sample
+pub fn sampled(interaction: &Pubkey, percent: f64) -> bool {
+    let hash = Sha256::digest(interaction.as_ref());
+    let bucket = u64::from_le_bytes(hash[..8].try_into().unwrap()) % 10_000;
+    (bucket as f64) < percent * 100.0
+}
+
+pub struct ReviewQueue {
+    db: sled::Db,
+    sample_percent: f64,
+}
+
+impl ReviewQueue {
+    pub fn open(path: &str, sample_percent: f64) -> Result<Self, OracleError> {
+        Ok(Self {
+            db: sled::open(path)?,
+            sample_percent,
+        })
+    }
+
+    /// Open the queue of `config` (under the deployment name when set); `None` when review is
+    /// disabled
+    pub fn load(config: &ReviewConfig) -> Result<Option<Self>, OracleError> {
+        let Some(path) = &config.path else {
+            return Ok(None);
+        };
+        Ok(Some(Self::open(
+            &deployment_path(path),
+            config.sample_percent,
+        )?))
+    }
+
+    pub fn sample_percent(&self) -> f64 {
+        self.sample_percent
+    }
+
+    /// Queue `item` if it is flagged or sampled. Returns whether it was queued.
+    pub fn offer(&self, item: &ReviewItem) -> Result<bool, OracleError> {
+        let reason = if !item.flags.is_empty() {
+            "flagged"
+        } else if sampled(&Pubkey::from_str(&item.interaction)?, self.sample_percent) {
+            "sampled"
+        } else {
+            return Ok(false);
+        };
+        self.db
+            .insert(&item.interaction, serde_json::to_vec(item)?)?;
+        METRICS.review_queued.with_label_values(&[reason]).inc();
+        Ok(true)
+    }
+
+    pub fn get(&self, interaction: &Pubkey) -> Result<Option<ReviewItem>, OracleError> {
+        match self.db.get(interaction.to_string())? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every queued item, oldest first
+    pub fn items(&self) -> Result<Vec<ReviewItem>, OracleError> {
+        let mut items = self
+            .db
+            .iter()
+            .map(|entry| Ok(serde_json::from_slice(&entry?.1)?))
+            .collect::<Result<Vec<ReviewItem>, OracleError>>()?;
+        items.sort_by_key(|item| item.created_at);
+        Ok(items)
+    }
+
+    /// Items not labeled yet, oldest first
+    pub fn pending(&self) -> Result<Vec<ReviewItem>, OracleError> {
+        let mut items = self.items()?;
+        items.retain(|item| item.label.is_none());
+        Ok(items)
+    }
+
+    pub fn label(
+        &self,
+        interaction: &Pubkey,
+        label: Label,
+        note: Option<String>,
+    ) -> Result<(), OracleError> {
+        let mut item = self
+            .get(interaction)?
+            .ok_or_else(|| format!("{} is not in the review queue", interaction))?;
+        item.label = Some(label);
+        item.note = note;
+        item.labeled_at = Some(now_millis());
+        self.db
+            .insert(interaction.to_string(), serde_json::to_vec(&item)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Delete the item of an interaction. Returns whether there was one.
+    pub fn remove(&self, interaction: &Pubkey) -> Result<bool, OracleError> {
+        Ok(self.db.remove(interaction.to_string())?.is_some())
+    }
+
+    /// Delete the items queued before `cutoff` (Unix milliseconds), labeled or not. Returns the
+    /// number deleted.
+    pub fn remove_before(&self, cutoff: u64) -> Result<usize, OracleError> {
+        let mut removed = 0;
+        for item in self.items()? {
+            if item.created_at < cutoff {
+                self.db.remove(&item.interaction)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Write the labeled items as JSONL. Returns the number of lines written.
+    pub fn export(&self, format: ExportFormat, out: &mut impl Write) -> Result<usize, OracleError> {
+        let mut written = 0;
+        for item in self.items()? {
+            let line = match (format, item.label) {
+                (_, None) => continue,
+                (ExportFormat::Eval, Some(_)) => serde_json::to_string(&item)?,
+                (ExportFormat::Finetune, Some(Label::Good)) => {
+                    let mut messages = item.messages;
+                    messages.push(ReviewMessage {
+                        role: "assistant".to_string(),
+                        content: item.response,
+                    });
+                    serde_json::to_string(&serde_json::json!({ "messages": messages }))?
+                }
+                (ExportFormat::Finetune, Some(Label::Bad)) => continue,
+            };
+            writeln!(out, "{}", line)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for ReviewQueue {
+    fn drop(&mut self) {
+        let _ = self.db.flush();
+    }
+}
@@ -0,0 +1,431 @@
+//! RPC compatibility check.
+//!
+//! `llm_oracle rpc-check [--rpc <url>] [--ws <url>]` calls every RPC and websocket method the
+//! oracle relies on against an endpoint, `solana.rpc_url` and `solana.websocket_url` by default,
+//! so an operator can validate a new provider, or a Firedancer node, before switching to it. Each
+//! check reports whether the method is supported and, for the behaviors the oracle depends on,
+//! whether the endpoint behaves like Agave:
+//!
+//! - `getProgramAccounts` applies memcmp filters and data slices, gap-fills depend on both
+//! - `minContextSlot` is enforced, so a lagging node can't hide interactions after a reconnect
+//! - `simulateTransaction` returns the units consumed, which callback compute budgets are sized on
+//! - signature statuses are served from history, for callbacks confirmed after a restart
+//!
+//! Nothing is sent: the checks only read, and the simulation is of a transfer the payer makes to
+//! itself.
+
+use crate::listener::{interaction_filters, program_accounts_config};
+use crate::OracleError;
+use anchor_lang::Discriminator;
+use futures::StreamExt;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcSimulateTransactionConfig, RpcTransactionLogsConfig,
+    RpcTransactionLogsFilter,
+};
+use solana_client::rpc_request::RpcError;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// JSON-RPC code of unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+/// Slots ahead of the tip asked for as `minContextSlot`, which the endpoint must refuse
+const FUTURE_SLOTS: u64 = 1_000_000;
+const TIMEOUT: Duration = Duration::from_secs(15);
+/// Wait for a notification before a subscription counts as working
+const NOTIFICATION_WAIT: Duration = Duration::from_secs(2);
+
+/// Result of a check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Supported,
+    /// Supported, but behaving differently from what the oracle expects
+    Differs,
+    Unsupported,
+    Failed,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Outcome::Supported => "ok",
+            Outcome::Differs => "DIFFERS",
+            Outcome::Unsupported => "UNSUPPORTED",
+            Outcome::Failed => "FAILED",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub method: &'static str,
+    pub outcome: Outcome,
+    pub detail: String,
+    pub elapsed: Duration,
+}
+
+/// What a supported method did
+enum Verdict {
+    Supported(String),
+    Differs(String),
+}
+
+fn supported(detail: impl ToString) -> Result<Verdict, OracleError> {
+    Ok(Verdict::Supported(detail.to_string()))
+}
+
+fn differs(detail: impl ToString) -> Result<Verdict, OracleError> {
+    Ok(Verdict::Differs(detail.to_string()))
+}
+
+/// Whether an error says the method doesn't exist
+fn is_unsupported(error: &OracleError) -> bool {
+    if let Some(error) = error.downcast_ref::<ClientError>() {
+        if let ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) = error.kind() {
+            return *code == METHOD_NOT_FOUND;
+        }
+    }
+    let message = error.to_string().to_lowercase();
+    message.contains("method not found") || message.contains("not supported")
+}
+
+/// Whether an error is the refusal of a `minContextSlot` the node hasn't reached
+fn is_min_context_slot_error(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => {
+            *code == -32016 || message.contains("inimum context slot")
+        }
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    results: Vec<CheckResult>,
+}
+
+impl Report {
+    async fn check(
+        &mut self,
+        method: &'static str,
+        call: impl Future<Output = Result<Verdict, OracleError>>,
+    ) {
+        let started = Instant::now();
+        let (outcome, detail) = match tokio::time::timeout(TIMEOUT, call).await {
+            Ok(Ok(Verdict::Supported(detail))) => (Outcome::Supported, detail),
+            Ok(Ok(Verdict::Differs(detail))) => (Outcome::Differs, detail),
+            Ok(Err(e)) if is_unsupported(&e) => (Outcome::Unsupported, e.to_string()),
+            Ok(Err(e)) => (Outcome::Failed, e.to_string()),
+            Err(_) => (
+                Outcome::Failed,
+                format!("no answer in {}s", TIMEOUT.as_secs()),
+            ),
+        };
+        self.results.push(CheckResult {
+            method,
+            outcome,
+            detail,
+            elapsed: started.elapsed(),
+        });
+    }
+}
+
+/// Run every check against `rpc_url` and `websocket_url`, for the interactions of `program`
+/// answered by `payer`
+pub async fn run(
+    rpc_url: &str,
+    websocket_url: &str,
+    program: &Pubkey,
+    payer: &Pubkey,
+) -> Vec<CheckResult> {
+    let rpc_client =
+        RpcClient::new_with_commitment(rpc_url.to_string(), CommitmentConfig::processed());
+    let rpc = &rpc_client;
+    let mut report = Report::default();
+
+    report
+        .check("getVersion", async {
+            supported(rpc.get_version().await?.solana_core)
+        })
+        .await;
+    report
+        .check("getGenesisHash", async {
+            supported(rpc.get_genesis_hash().await?)
+        })
+        .await;
+    let mut slot = 0;
+    report
+        .check("getSlot", async {
+            slot = rpc
+                .get_slot_with_commitment(CommitmentConfig::processed())
+                .await?;
+            supported(format!("slot {}", slot))
+        })
+        .await;
+    report
+        .check("getBlockHeight", async {
+            let height = rpc
+                .get_block_height_with_commitment(CommitmentConfig::confirmed())
+                .await?;
+            supported(format!("height {}", height))
+        })
+        .await;
+    let mut blockhash = Hash::default();
+    report
+        .check("getLatestBlockhash", async {
+            blockhash = rpc.get_latest_blockhash().await?;
+            supported(blockhash)
+        })
+        .await;
+    report
+        .check("getBalance", async {
+            supported(format!("payer {} lamports", rpc.get_balance(payer).await?))
+        })
+        .await;
+    report
+        .check("getMinimumBalanceForRentExemption", async {
+            let lamports = rpc.get_minimum_balance_for_rent_exemption(129).await?;
+            supported(format!("{} lamports for an interaction", lamports))
+        })
+        .await;
+    report
+        .check("getAccountInfo", async {
+            let account = rpc
+                .get_account_with_commitment(program, CommitmentConfig::processed())
+                .await?
+                .value;
+            match account {
+                Some(account) if account.executable => supported("program found"),
+                Some(_) => differs(format!("{} isn't executable", program)),
+                None => differs(format!("{} not found on this cluster", program)),
+            }
+        })
+        .await;
+    report
+        .check("getMultipleAccounts", async {
+            let config = RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                min_context_slot: Some(slot),
+                ..program_accounts_config(Vec::new(), None).account_config
+            };
+            let accounts = rpc
+                .get_multiple_accounts_with_config(&[*program, *payer], config)
+                .await?
+                .value;
+            match accounts.len() {
+                2 => supported("2 accounts"),
+                n => differs(format!("{} accounts returned for 2", n)),
+            }
+        })
+        .await;
+    report
+        .check("getMultipleAccounts minContextSlot", async {
+            if slot == 0 {
+                return Err("needs the slot from getSlot".into());
+            }
+            let config = RpcAccountInfoConfig {
+                min_context_slot: Some(slot + FUTURE_SLOTS),
+                ..program_accounts_config(Vec::new(), None).account_config
+            };
+            match rpc
+                .get_multiple_accounts_with_config(&[*program], config)
+                .await
+            {
+                Ok(_) => differs("a future minContextSlot was accepted: lagging nodes go unseen"),
+                Err(e) if is_min_context_slot_error(&e) => supported("future slot refused"),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await;
+    report
+        .check("getProgramAccounts filters", async {
+            let mut config = program_accounts_config(interaction_filters(), None);
+            config.account_config.data_slice = Some(UiDataSliceConfig {
+                offset: 0,
+                length: 8,
+            });
+            let accounts = rpc
+                .get_program_accounts_with_config(program, config)
+                .await?;
+            let discriminator = &solana_gpt_oracle::Interaction::DISCRIMINATOR[..];
+            if let Some((pubkey, _)) = accounts
+                .iter()
+                .find(|(_, account)| account.data != discriminator)
+            {
+                return differs(format!(
+                    "{} returned without matching the memcmp filter or the data slice",
+                    pubkey
+                ));
+            }
+            supported(format!("{} interaction(s)", accounts.len()))
+        })
+        .await;
+    report
+        .check("getProgramAccounts minContextSlot", async {
+            if slot == 0 {
+                return Err("needs the slot from getSlot".into());
+            }
+            let config = program_accounts_config(interaction_filters(), Some(slot + FUTURE_SLOTS));
+            match rpc.get_program_accounts_with_config(program, config).await {
+                Ok(_) => {
+                    differs("a future minContextSlot was accepted: gap-fills can miss updates")
+                }
+                Err(e) if is_min_context_slot_error(&e) => supported("future slot refused"),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await;
+    report
+        .check("getSignatureStatuses", async {
+            rpc.get_signature_statuses(&[Signature::default()]).await?;
+            supported("recent statuses")
+        })
+        .await;
+    let mut recent_signature = None;
+    report
+        .check("getSignaturesForAddress", async {
+            let signatures = rpc
+                .get_signatures_for_address_with_config(
+                    program,
+                    GetConfirmedSignaturesForAddress2Config {
+                        limit: Some(1),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            recent_signature = signatures.first().map(|status| status.signature.clone());
+            supported(format!("{} signature(s)", signatures.len()))
+        })
+        .await;
+    report
+        .check("getSignatureStatuses history", async {
+            let Some(signature) = &recent_signature else {
+                return supported("no transaction of the program to look up");
+            };
+            let signature: Signature = signature.parse()?;
+            let status = rpc
+                .get_signature_statuses_with_history(&[signature])
+                .await?
+                .value
+                .into_iter()
+                .next()
+                .flatten();
+            match status {
+                Some(_) => supported("found in history"),
+                None => differs(format!("{} missing from the status history", signature)),
+            }
+        })
+        .await;
+    report
+        .check("getRecentPrioritizationFees", async {
+            let fees = rpc.get_recent_prioritization_fees(&[*program]).await?;
+            if fees.is_empty() {
+                differs("no recent fees: callbacks pay no priority fee")
+            } else {
+                supported(format!("{} slot(s) of fees", fees.len()))
+            }
+        })
+        .await;
+    report
+        .check("simulateTransaction", async {
+            let transaction = Transaction::new_unsigned(Message::new_with_blockhash(
+                &[system_instruction::transfer(payer, payer, 0)],
+                Some(payer),
+                &blockhash,
+            ));
+            let simulation = rpc
+                .simulate_transaction_with_config(
+                    &transaction,
+                    RpcSimulateTransactionConfig {
+                        sig_verify: false,
+                        replace_recent_blockhash: true,
+                        commitment: Some(CommitmentConfig::processed()),
+                        ..Default::default()
+                    },
+                )
+                .await?
+                .value;
+            match (simulation.err, simulation.units_consumed) {
+                (Some(error), _) => supported(format!("simulated, failing with {:?}", error)),
+                (None, Some(units)) => supported(format!("{} units consumed", units)),
+                (None, None) => {
+                    differs("no unitsConsumed: callbacks request the maximum compute units")
+                }
+            }
+        })
+        .await;
+
+    let pubsub = match tokio::time::timeout(TIMEOUT, PubsubClient::new(websocket_url)).await {
+        Ok(Ok(pubsub)) => Some(pubsub),
+        Ok(Err(e)) => {
+            report.check("websocket", async { Err(e.into()) }).await;
+            None
+        }
+        Err(_) => {
+            report
+                .check("websocket", async { Err("no connection".into()) })
+                .await;
+            None
+        }
+    };
+    if let Some(pubsub) = &pubsub {
+        report
+            .check("programSubscribe", async {
+                let (mut stream, unsubscribe) = pubsub
+                    .program_subscribe(
+                        program,
+                        Some(program_accounts_config(interaction_filters(), None)),
+                    )
+                    .await?;
+                let _ = tokio::time::timeout(NOTIFICATION_WAIT, stream.next()).await;
+                unsubscribe().await;
+                supported("subscribed with filters")
+            })
+            .await;
+        report
+            .check("accountSubscribe", async {
+                let (mut stream, unsubscribe) = pubsub
+                    .account_subscribe(
+                        program,
+                        Some(RpcAccountInfoConfig {
+                            commitment: Some(CommitmentConfig::confirmed()),
+                            encoding: Some(UiAccountEncoding::Base64),
+                            ..Default::default()
+                        }),
+                    )
+                    .await?;
+                let _ = tokio::time::timeout(NOTIFICATION_WAIT, stream.next()).await;
+                unsubscribe().await;
+                supported("subscribed")
+            })
+            .await;
+        report
+            .check("logsSubscribe", async {
+                let (mut stream, unsubscribe) = pubsub
+                    .logs_subscribe(
+                        RpcTransactionLogsFilter::Mentions(vec![program.to_string()]),
+                        RpcTransactionLogsConfig {
+                            commitment: Some(CommitmentConfig::confirmed()),
+                        },
+                    )
+                    .await?;
+                let _ = tokio::time::timeout(NOTIFICATION_WAIT, stream.next()).await;
+                unsubscribe().await;
+                supported("subscribed to mentions")
+            })
+            .await;
+    }
+    report.results
+}
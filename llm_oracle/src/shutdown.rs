@@ -0,0 +1,63 @@
+//! Graceful shutdown.
+//!
+//! On SIGINT (Ctrl-C) or SIGTERM the oracle stops listening, so no new interaction is started,
+//! and waits up to `processing.shutdown_timeout_secs` for the ones in flight to finish their LLM
+//! call and land their callback. It then flushes the memory store and the ledgers and exits.
+//!
+//! Nothing is lost either way: updates that were queued but not started are delivered again by
+//! the gap-fill of the next start, and interactions still in flight at the timeout are resumed
+//! from the ledger by [`crate::recovery`]. A second signal exits without waiting.
+
+use crate::oracle::Oracle;
+use crate::worker_pool::WorkerPool;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Resolves on the next SIGINT or SIGTERM
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error = ?e, "Can't handle SIGTERM, only SIGINT");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Stop starting interactions, wait for the ones in flight up to the shutdown timeout, and flush
+/// what the oracle keeps on disk
+pub async fn drain(oracle: &Oracle, worker_pool: &WorkerPool) {
+    worker_pool.close();
+    let timeout = Duration::from_secs(oracle.config.shutdown_timeout_secs);
+    info!(
+        in_flight = worker_pool.in_flight(),
+        ?timeout,
+        "Shutting down, waiting for the interactions in flight"
+    );
+    if tokio::time::timeout(timeout, worker_pool.drained())
+        .await
+        .is_err()
+    {
+        warn!(
+            in_flight = worker_pool.in_flight(),
+            "Shutdown timeout, the interactions left are resumed on the next start"
+        );
+    }
+    match oracle.flush() {
+        Ok(()) => info!("Oracle stopped"),
+        Err(e) => error!(error = ?e, "Failed to flush the oracle state"),
+    }
+}
@@ -0,0 +1,81 @@
+//! Lifecycle of an interaction.
+//!
+//! Every interaction the oracle answers moves through [`InteractionStatus`], persisted in the
+//! ledger ([`crate::dedup::ProcessedSet`]) on every transition. The ledger is what the listener,
+//! the worker pool, the processor and the dead-letter queue consult, rather than inferring the
+//! state of an interaction from memory, retry counters or the chain.
+//!
+//! The happy path is `Detected -> Claimed -> Generating -> Validating -> Submitting ->
+//! Confirmed`. Anything unsettled can become `Failed` or `Abandoned`, and a failed callback goes
+//! back to `Submitting` when the dead-letter queue retries it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionStatus {
+    /// Seen unanswered on-chain and queued to the worker pool
+    Detected,
+    /// Picked up by a worker
+    Claimed,
+    /// Waiting for the LLM
+    Generating,
+    /// Answer being checked by the hallucination guard and the guardrails
+    Validating,
+    /// Callback transaction(s) being sent
+    Submitting,
+    /// Every callback transaction landed
+    Confirmed,
+    /// Processing or the callback failed; retried by the dead-letter queue or the next update
+    Failed,
+    /// Given up on: the account changed while it was answered, or retries ran out
+    Abandoned,
+}
+
+impl InteractionStatus {
+    /// Whether the interaction is settled and must not be answered again
+    pub fn is_final(self) -> bool {
+        matches!(self, Self::Confirmed | Self::Abandoned)
+    }
+
+    /// Whether `self -> next` is a valid transition
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use InteractionStatus::*;
+        match (self, next) {
+            (current, next) if current == next => true,
+            // A new update starts over anything that isn't settled
+            (current, Detected) => !current.is_final(),
+            // Replays claim failed interactions directly
+            (Detected | Failed, Claimed) => true,
+            // Rejected game turns are submitted without generating
+            (Claimed, Generating | Submitting) => true,
+            (Generating, Validating | Submitting) => true,
+            (Validating, Submitting) => true,
+            (Submitting, Confirmed) => true,
+            // An operator may retry an abandoned callback from the dead-letter queue
+            (Failed | Abandoned, Submitting) => true,
+            (current, Failed | Abandoned) => !current.is_final(),
+            _ => false,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Detected => "detected",
+            Self::Claimed => "claimed",
+            Self::Generating => "generating",
+            Self::Validating => "validating",
+            Self::Submitting => "submitting",
+            Self::Confirmed => "confirmed",
+            Self::Failed => "failed",
+            Self::Abandoned => "abandoned",
+        }
+    }
+}
+
+impl fmt::Display for InteractionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
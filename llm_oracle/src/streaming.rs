@@ -0,0 +1,268 @@
+//! Streaming callbacks.
+//!
+//! With `STREAMING_CALLBACKS`, answers are sent on-chain while the model writes them instead of
+//! once complete. The reply is read from the provider's token stream (server-sent events for
+//! OpenAI, Gemini and OpenAI-compatible endpoints; other providers send it whole), and every
+//! `STREAM_MIN_BYTES` received, the text so far goes out in a partial callback prefixed with
+//! `[i/+] `. The finalization callback carries the rest prefixed with `[n/n] `, `n` being the
+//! number of callbacks sent, so callback programs reassemble streamed answers like chunked ones.
+//! Callbacks are sent one after the other and land in order.
+//!
+//! Only answers that need no validation once complete are streamed: not for contexts with an
+//! output schema or chain functions, game turns, answers grounded on cited sources, cached
+//! answers, with prompt hash callbacks or attestations, or when the guardrails have a blocklist
+//! or moderation. Answers are still sanitized and truncated to `max_response_bytes` as they
+//! arrive. A reply received whole before a partial callback was due goes through the usual path.
+//!
+//! When the stream fails after partial callbacks were sent, the text received is finalized as is
+//! and flagged `interrupted` for review. When a partial callback fails, the rest of the answer is
+//! still received and the whole of it goes to the dead-letter queue, whose retry sends it again
+//! from `[1/n]`.
+
+use crate::callback::{build_callback_instruction, chunk_capacity};
+use crate::config::ProgramConfig;
+use crate::costs;
+use crate::guardrails::{strip_hidden, truncate};
+use crate::health::HEALTH;
+use crate::metrics::METRICS;
+use crate::oracle::Oracle;
+use crate::processor::{settle, upgrade_legacy};
+use crate::providers::{ChatMessage, ChatProvider};
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use solana_sdk::address_lookup_table::AddressLookupTableAccount;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use std::slice;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::{error, info, info_span, warn, Instrument};
+
+pub const DEFAULT_STREAM_MIN_BYTES: usize = 64;
+
+/// A streamed answer
+#[derive(Debug, Clone)]
+pub struct Streamed {
+    pub response: String,
+    /// Sent in callbacks; otherwise the reply was received whole and still has to be sent
+    pub sent: bool,
+    /// The stream failed after partial callbacks were sent, `response` is what was received
+    pub interrupted: bool,
+}
+
+/// The callbacks of a streamed answer, sent in order
+struct Callbacks<'a> {
+    oracle: &'a Oracle,
+    program: &'a ProgramConfig,
+    interaction_pubkey: &'a Pubkey,
+    interaction: &'a solana_gpt_oracle::Interaction,
+    tables: Vec<AddressLookupTableAccount>,
+    signatures: Vec<Signature>,
+    sent: usize,
+    /// Once a callback fails, the next ones aren't sent
+    failure: Option<OracleError>,
+}
+
+impl Callbacks<'_> {
+    async fn send(&mut self, piece: &str, last: bool) -> Result<(), OracleError> {
+        if self.failure.is_some() {
+            return Ok(());
+        }
+        if self.sent == 0 {
+            self.oracle.processed.transition(
+                self.interaction_pubkey,
+                &self.interaction.text,
+                InteractionStatus::Submitting,
+                &[],
+            )?;
+        }
+        self.sent += 1;
+        let header = if last {
+            format!("[{}/{}] ", self.sent, self.sent)
+        } else {
+            format!("[{}/+] ", self.sent)
+        };
+        let payer = self.oracle.config.payer.as_ref();
+        let instruction = build_callback_instruction(
+            &payer.pubkey(),
+            self.program,
+            self.interaction_pubkey,
+            self.interaction,
+            &format!("{}{}", header, piece),
+        )?;
+        let record_sent = |signature: &Signature| {
+            let recorded = self.oracle.processed.record_sent(
+                self.interaction_pubkey,
+                &self.interaction.text,
+                signature,
+            );
+            if let Err(e) = recorded {
+                warn!(error = ?e, "Failed to record the callback signature");
+            }
+        };
+        let sent = self
+            .oracle
+            .callback_sender
+            .send(
+                &self.oracle.rpc_client,
+                payer,
+                slice::from_ref(&instruction),
+                &self.tables,
+                &record_sent,
+            )
+            .await;
+        match sent {
+            Ok(landed) => {
+                info!(
+                    signature = %landed.signature,
+                    callback = self.sent,
+                    last,
+                    "Callback transaction landed"
+                );
+                if !last {
+                    METRICS.partial_callbacks.inc();
+                }
+                costs::record_fee(landed.fee_lamports);
+                self.signatures.push(landed.signature);
+            }
+            Err(e) => {
+                error!(error = %e, "Streamed callback failed, no more are sent");
+                self.failure = Some(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ask `provider` for the answer to an interaction of `program`, sending it in callbacks as it is
+/// generated. Fails without having sent anything when the stream fails before a partial callback
+/// was due.
+pub async fn stream(
+    oracle: &Oracle,
+    program: &Pubkey,
+    provider: &dyn ChatProvider,
+    interaction_pubkey: &Pubkey,
+    interaction: &solana_gpt_oracle::Interaction,
+    messages: &[ChatMessage],
+) -> Result<Streamed, OracleError> {
+    upgrade_legacy(oracle, program, interaction_pubkey, interaction).await;
+    let program = oracle.config.program(program)?;
+    let payer = oracle.config.payer.as_ref();
+    let tables = oracle
+        .callback_sender
+        .lookup_tables
+        .prepare(
+            &oracle.rpc_client,
+            payer,
+            &build_callback_instruction(
+                &payer.pubkey(),
+                program,
+                interaction_pubkey,
+                interaction,
+                "",
+            )?,
+        )
+        .await;
+    let capacity = chunk_capacity(
+        &payer.pubkey(),
+        program,
+        interaction_pubkey,
+        interaction,
+        &tables,
+        &oracle.callback_sender.sizing_envelope(&payer.pubkey()),
+    )?;
+    let min_bytes = oracle.config.stream_min_bytes.min(capacity);
+    let max_bytes = oracle.guardrails.get().max_bytes();
+    let mut callbacks = Callbacks {
+        oracle,
+        program,
+        interaction_pubkey,
+        interaction,
+        tables,
+        signatures: Vec::new(),
+        sent: 0,
+        failure: None,
+    };
+
+    let started = Instant::now();
+    let (deltas, mut received) = mpsc::unbounded_channel();
+    // The channel closes when the generation ends
+    let generation = async move { provider.stream_message(messages, &deltas).await }
+        .instrument(info_span!("llm_stream"));
+    let sending = async {
+        let mut answer = String::new();
+        let mut pending = String::new();
+        while let Some(delta) = received.recv().await {
+            // Only text followed by more is sent before the stream ends, so that a reply
+            // received whole isn't split into a partial and an empty final callback
+            while pending.len() >= min_bytes {
+                let piece = truncate(&pending, capacity).to_string();
+                callbacks.send(&piece, false).await?;
+                pending.drain(..piece.len());
+            }
+            let mut delta = strip_hidden(&delta);
+            if answer.is_empty() {
+                delta = delta.trim_start().to_string();
+            }
+            let delta = truncate(&delta, max_bytes - answer.len());
+            answer.push_str(delta);
+            pending.push_str(delta);
+        }
+        Ok::<_, OracleError>((answer, pending))
+    };
+    let (generated, sending) = tokio::join!(generation, sending);
+    METRICS
+        .llm_latency
+        .with_label_values(&[provider.name()])
+        .observe(started.elapsed().as_secs_f64());
+    let (answer, pending) = sending?;
+    if callbacks.sent == 0 {
+        let response = generated?;
+        HEALTH.provider(provider.name(), true);
+        return Ok(Streamed {
+            response,
+            sent: false,
+            interrupted: false,
+        });
+    }
+    let interrupted = match generated {
+        Ok(_) => {
+            HEALTH.provider(provider.name(), true);
+            false
+        }
+        Err(e) => {
+            warn!(error = ?e, "Stream failed after partial callbacks, finalizing the rest");
+            true
+        }
+    };
+
+    let mut rest = pending.trim_end();
+    while rest.len() > capacity {
+        let piece = truncate(rest, capacity);
+        callbacks.send(piece, false).await?;
+        rest = &rest[piece.len()..];
+    }
+    callbacks.send(rest, true).await?;
+    let answer = answer.trim_end();
+    METRICS.response_bytes.observe(answer.len() as f64);
+    oracle
+        .processed
+        .save_response(interaction_pubkey, &interaction.text, answer)?;
+    if let Some(audit) = &oracle.audit {
+        audit.generated(interaction_pubkey, provider.name(), answer);
+    }
+    settle(
+        oracle,
+        interaction_pubkey,
+        interaction,
+        answer,
+        answer,
+        &callbacks.signatures,
+        callbacks.failure.take(),
+    )?;
+    Ok(Streamed {
+        response: answer.to_string(),
+        sent: true,
+        interrupted,
+    })
+}
@@ -0,0 +1,137 @@
+//! Structured (JSON) output.
+//!
+//! Contexts listed in the `structured.schemas` JSON file (`STRUCTURED_OUTPUT_SCHEMAS`) are answered
+//! with JSON matching their JSON Schema, for callback programs that parse the response. The schema
+//! is added to the prompt and passed to the provider (OpenAI JSON mode, which only produces
+//! objects, and Gemini `responseJsonSchema`). Every response is validated; an invalid one is sent
+//! back to the model with the validation errors, up to `llm.schema_corrections` times, and the
+//! interaction fails if it is still invalid.
+//!
+//! ```json
+//! {
+//!   "<context pubkey>": {
+//!     "type": "object",
+//!     "properties": {
+//!       "decision": { "enum": ["approve", "reject"] },
+//!       "score": { "type": "number", "minimum": 0, "maximum": 1 }
+//!     },
+//!     "required": ["decision", "score"]
+//!   }
+//! }
+//! ```
+
+use crate::OracleError;
+use jsonschema::Validator;
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+
+/// Validation errors quoted back to the model
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// JSON Schema of the responses of a context
+pub struct OutputSchema {
+    pub schema: Value,
+    validator: Validator,
+}
+
+impl OutputSchema {
+    pub fn new(schema: Value) -> Result<Self, OracleError> {
+        let validator = jsonschema::validator_for(&schema).map_err(|e| e.to_string())?;
+        Ok(Self { schema, validator })
+    }
+
+    /// Instructions appended to the prompt
+    pub fn instructions(&self) -> String {
+        format!(
+            "Respond with only a JSON value, without code fences or commentary, matching this \
+             JSON Schema: {}",
+            self.schema
+        )
+    }
+
+    /// The response as compact JSON, or a description of why it doesn't match the schema
+    pub fn validate(&self, response: &str) -> Result<String, String> {
+        let value: Value = serde_json::from_str(strip_code_fence(response))
+            .map_err(|e| format!("the response is not valid JSON: {}", e))?;
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(&value)
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| match error.instance_path.to_string() {
+                path if path.is_empty() => error.to_string(),
+                path => format!("{}: {}", path, error),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(format!(
+                "the response doesn't match the schema: {}",
+                errors.join("; ")
+            ));
+        }
+        Ok(value.to_string())
+    }
+
+    /// Prompt asking the model to fix a response rejected with `error`
+    pub fn correction_prompt(&self, error: &str) -> String {
+        format!(
+            "Your previous answer was rejected because {}. {}",
+            error,
+            self.instructions()
+        )
+    }
+}
+
+/// Models often wrap JSON in a Markdown code block despite the instructions
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(inner) = text.strip_prefix("```") else {
+        return text;
+    };
+    let inner = inner.strip_suffix("```").unwrap_or(inner);
+    // Drop the language tag, e.g. ```json
+    match inner.split_once('\n') {
+        Some((tag, body)) if !tag.trim_start().starts_with(['{', '[']) => body.trim(),
+        _ => inner.trim(),
+    }
+}
+
+/// Output schemas by context
+#[derive(Default)]
+pub struct StructuredOutputs {
+    schemas: HashMap<Pubkey, OutputSchema>,
+}
+
+impl StructuredOutputs {
+    /// Load the schemas from the JSON file at `path`, if set
+    pub fn load(path: Option<&str>) -> Result<Self, OracleError> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let raw: HashMap<String, Value> = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| format!("Invalid structured output file {}: {}", path, e))?;
+        let mut schemas = HashMap::new();
+        for (context, schema) in raw {
+            let context = Pubkey::from_str(&context)
+                .map_err(|e| format!("Invalid context pubkey {:?} in {}: {}", context, path, e))?;
+            let schema = OutputSchema::new(schema)
+                .map_err(|e| format!("Invalid JSON Schema of context {}: {}", context, e))?;
+            schemas.insert(context, schema);
+        }
+        Ok(Self { schemas })
+    }
+
+    pub fn get(&self, context: &Pubkey) -> Option<&OutputSchema> {
+        self.schemas.get(context)
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+}
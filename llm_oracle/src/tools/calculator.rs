@@ -0,0 +1,441 @@
+use super::{Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+
+/// Only evaluate the first few expressions of an interaction
+const MAX_EXPRESSIONS: usize = 5;
+
+/// Characters an arithmetic expression in free text can be made of
+const EXPRESSION_CHARS: &str = "0123456789.+-*/%^() ";
+
+/// Parentheses, signs and exponents nested deeper than this are rejected, not recursed into
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Operator(char),
+    /// A `%` following a number or a parenthesis: the value divided by 100
+    Percent,
+    Open,
+    Close,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = expression.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            ' ' => i += 1,
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("Invalid number {:?}", number))?;
+                tokens.push(Token::Number(value));
+            }
+            '%' if matches!(
+                tokens.last(),
+                Some(Token::Number(_) | Token::Close | Token::Percent)
+            ) =>
+            {
+                tokens.push(Token::Percent);
+                i += 1;
+            }
+            // "50% of 80"
+            'o' if chars.get(i + 1) == Some(&'f') && tokens.last() == Some(&Token::Percent) => {
+                tokens.push(Token::Operator('*'));
+                i += 2;
+            }
+            c @ ('+' | '-' | '*' | '/' | '^') => {
+                tokens.push(Token::Operator(c));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            other => return Err(format!("Unexpected character {:?}", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `+ -`, `* /`, right associative `^`, unary minus, percent and
+/// parentheses
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        while let Some(Token::Operator(operator @ ('+' | '-'))) = self.peek() {
+            self.next();
+            let rhs = self.term()?;
+            value = if operator == '+' {
+                value + rhs
+            } else {
+                value - rhs
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.power()?;
+        while let Some(Token::Operator(operator @ ('*' | '/'))) = self.peek() {
+            self.next();
+            let rhs = self.power()?;
+            if operator == '/' && rhs == 0.0 {
+                return Err("Division by zero".to_string());
+            }
+            value = if operator == '*' {
+                value * rhs
+            } else {
+                value / rhs
+            };
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.unary()?;
+        if let Some(Token::Operator('^')) = self.peek() {
+            self.next();
+            self.descend()?;
+            let exponent = self.power()?;
+            self.depth -= 1;
+            return Ok(base.powf(exponent));
+        }
+        Ok(base)
+    }
+
+    /// Count a level of recursion, failing past [`MAX_DEPTH`] instead of overflowing the stack
+    fn descend(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("Nested deeper than {} levels", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    /// Parentheses and signs recurse through here, exponents through `power`
+    fn unary(&mut self) -> Result<f64, String> {
+        self.descend()?;
+        let value = match self.peek() {
+            Some(Token::Operator('-')) => {
+                self.next();
+                self.unary().map(|value| -value)
+            }
+            Some(Token::Operator('+')) => {
+                self.next();
+                self.unary()
+            }
+            _ => self.percent(),
+        }?;
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn percent(&mut self) -> Result<f64, String> {
+        let mut value = self.primary()?;
+        while let Some(Token::Percent) = self.peek() {
+            self.next();
+            value /= 100.0;
+        }
+        Ok(value)
+    }
+
+    fn primary(&mut self) -> Result<f64, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Open) => {
+                let value = self.expression()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err("Missing closing parenthesis".to_string()),
+                }
+            }
+            other => Err(format!("Unexpected token {:?}", other)),
+        }
+    }
+}
+
+/// Evaluate an arithmetic expression
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.expression()?;
+    if parser.position != parser.tokens.len() {
+        return Err(format!("Unexpected trailing input in {:?}", expression));
+    }
+    if !value.is_finite() {
+        return Err(format!("{:?} has no finite value", expression));
+    }
+    Ok(value)
+}
+
+/// Format a result without float noise: integers as such, other values with at most 10 decimals
+pub fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        return format!("{}", value as i64);
+    }
+    let formatted = format!("{:.10}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Numbers joined by dashes or slashes without spaces, two by a dash ("pages 10-20") or three by
+/// the same separator ("2024-12-25", "12/25/2024"): ranges and dates rather than arithmetic
+fn is_range_or_date(candidate: &str) -> bool {
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    let dashes: Vec<&str> = candidate.split('-').collect();
+    let slashes: Vec<&str> = candidate.split('/').collect();
+    ((2..=3).contains(&dashes.len()) && dashes.iter().all(|part| is_number(part)))
+        || (slashes.len() == 3 && slashes.iter().all(|part| is_number(part)))
+}
+
+/// Numbers with more than one dot: versions ("1.2.3") and addresses
+fn is_version(candidate: &str) -> bool {
+    candidate
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .any(|number| number.matches('.').count() > 1)
+}
+
+/// Extend a run of expression characters over the "of" in "50% of 80"
+fn skip_percent_of(chars: &[char], end: usize) -> Option<usize> {
+    let before = chars[..end].iter().rev().find(|c| **c != ' ')?;
+    let after = chars.get(end..end + 3)?;
+    (*before == '%' && after == ['o', 'f', ' ']).then_some(end + 3)
+}
+
+/// Arithmetic expressions mentioned in free text: runs of digits and operators containing at
+/// least one binary operation, not glued to words (pubkeys, `v1.2`), and not ranges, dates or
+/// versions.
+pub fn find_expressions(text: &str) -> Vec<String> {
+    let mut expressions = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut start = 0;
+    while start < chars.len() {
+        if !EXPRESSION_CHARS.contains(chars[start]) || chars[start] == ' ' {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        loop {
+            while end < chars.len() && EXPRESSION_CHARS.contains(chars[end]) {
+                end += 1;
+            }
+            match skip_percent_of(&chars, end) {
+                Some(next) => end = next,
+                None => break,
+            }
+        }
+        let glued = (start > 0 && chars[start - 1].is_alphanumeric())
+            || (end < chars.len() && chars[end - 1] != ' ' && chars[end].is_alphanumeric());
+        let candidate: String = chars[start..end].iter().collect();
+        let candidate = candidate.trim().trim_end_matches('.').trim();
+        let has_operation = tokenize(candidate).is_ok_and(|tokens| {
+            tokens
+                .iter()
+                .filter(|token| matches!(token, Token::Number(_)))
+                .count()
+                >= 2
+                && tokens
+                    .iter()
+                    .any(|token| matches!(token, Token::Operator(_)))
+        });
+        if !glued
+            && has_operation
+            && !is_range_or_date(candidate)
+            && !is_version(candidate)
+            && !expressions.contains(&candidate.to_string())
+        {
+            expressions.push(candidate.to_string());
+        }
+        start = end;
+    }
+    expressions
+}
+
+/// Evaluates the arithmetic in an interaction so the LLM quotes exact results instead of
+/// computing them itself.
+pub struct CalculatorTool;
+
+#[async_trait]
+impl Tool for CalculatorTool {
+    fn name(&self) -> &str {
+        "calculator"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let results: Vec<String> = find_expressions(&input.interaction.text)
+            .iter()
+            .filter_map(|expression| {
+                let value = evaluate(expression).ok()?;
+                Some(format!("{} = {}", expression, format_number(value)))
+            })
+            .take(MAX_EXPRESSIONS)
+            .collect();
+        if results.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Arithmetic found in the request, evaluated exactly. The expressions were read \
+                 literally from the text: use a result only where it matches what is being \
+                 asked. {}",
+                results.join("; ")
+            ),
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_gpt_oracle::Interaction;
+    use solana_sdk::pubkey::Pubkey;
+
+    async fn run(text: &str) -> Option<String> {
+        let interaction = Interaction {
+            context: Pubkey::new_unique(),
+            user: Pubkey::new_unique(),
+            text: text.to_string(),
+            callback_program_id: Pubkey::new_unique(),
+            callback_discriminator: [0; 8],
+            callback_account_metas: Vec::new(),
+            is_processed: false,
+            created_at: 1_700_000_000,
+        };
+        let input = ToolInput {
+            interaction_pubkey: &Pubkey::new_unique(),
+            interaction: &interaction,
+            context_text: "",
+        };
+        CalculatorTool
+            .run(&input)
+            .await
+            .unwrap()
+            .map(|output| output.prompt)
+    }
+
+    #[test]
+    fn evaluates_with_precedence() {
+        assert_eq!(evaluate("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(evaluate("(2 + 3) * 4").unwrap(), 20.0);
+        assert_eq!(evaluate("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(evaluate("-(2 + 3) * 2").unwrap(), -10.0);
+        assert_eq!(evaluate("7 / 2").unwrap(), 3.5);
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+    }
+
+    #[test]
+    fn reads_percent_as_hundredths() {
+        assert_eq!(evaluate("10% + 5").unwrap(), 5.1);
+        assert_eq!(evaluate("50% of 80").unwrap(), 40.0);
+        assert_eq!(evaluate("200 * 15%").unwrap(), 30.0);
+        assert_eq!(evaluate("(1 + 1)%").unwrap(), 0.02);
+        assert!(evaluate("10 % 3").is_err());
+        assert!(evaluate("% 3").is_err());
+    }
+
+    #[test]
+    fn caps_the_nesting_depth() {
+        let nested = format!(
+            "{}1{}",
+            "(".repeat(MAX_DEPTH - 1),
+            ")".repeat(MAX_DEPTH - 1)
+        );
+        assert_eq!(evaluate(&nested).unwrap(), 1.0);
+        assert!(evaluate(&format!("{}1", "(".repeat(100_000))).is_err());
+        assert!(evaluate(&format!("{}1", "-".repeat(100_000))).is_err());
+        assert!(evaluate(&format!("2{}", "^2".repeat(100_000))).is_err());
+    }
+
+    #[test]
+    fn finds_arithmetic_in_text() {
+        assert_eq!(
+            find_expressions("What is 12.5 * (3 + 4)? And 10% of 250."),
+            ["12.5 * (3 + 4)", "10% of 250"]
+        );
+        assert_eq!(find_expressions("Tip 15% + 2 on it"), ["15% + 2"]);
+        assert_eq!(find_expressions("Split 1/4 of the pot"), ["1/4"]);
+    }
+
+    #[test]
+    fn skips_ranges_dates_and_versions() {
+        for text in [
+            "Read pages 10-20",
+            "Due on 2024-12-25",
+            "Due on 12/25/2024",
+            "Due on 25-12-2024",
+            "Upgrade to 1.2.3",
+            "Ping 192.168.1.1",
+            "Build v1.2+3",
+            "Wallet 3xK9+2",
+        ] {
+            assert!(find_expressions(text).is_empty(), "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_math_heavy_prompts_through_the_tool() {
+        let prompt = run("What's 1234 * 5678, and 20% of 4500?").await.unwrap();
+        assert!(prompt.contains("1234 * 5678 = 7006652"), "{}", prompt);
+        assert!(prompt.contains("20% of 4500 = 900"), "{}", prompt);
+        assert!(!prompt.contains("don't redo"));
+    }
+
+    #[tokio::test]
+    async fn leaves_other_prompts_alone() {
+        for text in [
+            "What is the capital of France?",
+            "Summarize chapters 3-5 of the 2024-01-15 report on release 2.1.0",
+            "Tell me about 1 + a",
+        ] {
+            assert_eq!(run(text).await, None, "{:?}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn caps_the_number_of_expressions() {
+        let text = (1..=8)
+            .map(|i| format!("{} + {},", i, i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let prompt = run(&text).await.unwrap();
+        assert_eq!(prompt.matches(" = ").count(), MAX_EXPRESSIONS);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use chrono::{Days, NaiveDate, Utc};
+
+/// Only describe the first few dates of an interaction
+const MAX_DATES: usize = 5;
+
+/// Words that make the tool state the current date even when no date is mentioned
+const DATE_WORDS: [&str; 6] = [
+    "today",
+    "tomorrow",
+    "yesterday",
+    "date",
+    "weekday",
+    "days until",
+];
+
+/// ISO (`YYYY-MM-DD`) dates mentioned in free text
+pub fn find_dates(text: &str) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    for word in text.split(|c: char| !(c.is_ascii_digit() || c == '-')) {
+        if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+            if !dates.contains(&date) {
+                dates.push(date);
+            }
+        }
+    }
+    dates
+}
+
+/// Offsets like "30 days after 2025-01-01" or "2 weeks from today"
+fn find_offsets(text: &str, today: NaiveDate) -> Vec<String> {
+    let words: Vec<&str> = text
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !(c.is_alphanumeric() || c == '-')))
+        .collect();
+    let mut offsets = Vec::new();
+    for window in words.windows(4) {
+        let [count, unit, direction, anchor] = window else {
+            continue;
+        };
+        let Ok(count) = count.parse::<u64>() else {
+            continue;
+        };
+        let days = match unit.to_lowercase().as_str() {
+            "day" | "days" => count,
+            "week" | "weeks" => count * 7,
+            _ => continue,
+        };
+        let anchor_date = match anchor.to_lowercase().as_str() {
+            "today" | "now" => today,
+            other => match NaiveDate::parse_from_str(other, "%Y-%m-%d") {
+                Ok(date) => date,
+                Err(_) => continue,
+            },
+        };
+        let date = match direction.to_lowercase().as_str() {
+            "after" | "from" => anchor_date.checked_add_days(Days::new(days)),
+            "before" => anchor_date.checked_sub_days(Days::new(days)),
+            _ => continue,
+        };
+        if let Some(date) = date {
+            offsets.push(format!(
+                "{} {} {} {} is {} ({})",
+                count,
+                unit,
+                direction,
+                anchor,
+                date,
+                date.format("%A")
+            ));
+        }
+    }
+    offsets
+}
+
+/// States the current date and computes weekdays, day differences and offsets of the dates an
+/// interaction mentions, so the LLM doesn't have to do calendar math.
+pub struct DateTool;
+
+#[async_trait]
+impl Tool for DateTool {
+    fn name(&self) -> &str {
+        "dates"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let text = &input.interaction.text;
+        let lowercase = text.to_lowercase();
+        let today = Utc::now().date_naive();
+        let dates: Vec<NaiveDate> = find_dates(text).into_iter().take(MAX_DATES).collect();
+        let offsets = find_offsets(text, today);
+        if dates.is_empty()
+            && offsets.is_empty()
+            && !DATE_WORDS.iter().any(|word| lowercase.contains(word))
+        {
+            return Ok(None);
+        }
+
+        let mut facts = vec![format!("today (UTC) is {} ({})", today, today.format("%A"))];
+        for date in &dates {
+            let days = (*date - today).num_days();
+            let relative = match days {
+                0 => "today".to_string(),
+                days if days > 0 => format!("{} days from today", days),
+                days => format!("{} days ago", -days),
+            };
+            facts.push(format!("{} is a {}, {}", date, date.format("%A"), relative));
+        }
+        for pair in dates.windows(2) {
+            facts.push(format!(
+                "there are {} days between {} and {}",
+                (pair[1] - pair[0]).num_days().abs(),
+                pair[0],
+                pair[1]
+            ));
+        }
+        facts.extend(offsets);
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Exact calendar facts. Use these values and don't compute dates yourself: {}",
+                facts.join("; ")
+            ),
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Tool, ToolInput, ToolOutput};
+use crate::config::env_flag;
+use crate::knowledge::{as_of, compact_citations, Embedder, KnowledgeBase};
+use crate::OracleError;
+use async_trait::async_trait;
+use std::env;
+use std::sync::Arc;
+
+pub const DEFAULT_TOP_K: usize = 4;
+pub const DEFAULT_MIN_SCORE: f32 = 0.25;
+pub const DEFAULT_CITATION_MAX_CHARS: usize = 120;
+
+/// Retrieves the knowledge base chunks of the interaction's context closest to its text and
+/// grounds the prompt on them. In citation mode the chunk ids are appended to the callback.
+pub struct KnowledgeTool {
+    knowledge_base: Arc<KnowledgeBase>,
+    embedder: Box<dyn Embedder>,
+    top_k: usize,
+    min_score: f32,
+    /// Maximum length of the citations appended to the callback, `None` to not cite
+    citation_max_chars: Option<usize>,
+}
+
+impl KnowledgeTool {
+    pub fn new(
+        knowledge_base: Arc<KnowledgeBase>,
+        embedder: Box<dyn Embedder>,
+        top_k: usize,
+        min_score: f32,
+        citation_max_chars: Option<usize>,
+    ) -> Self {
+        Self {
+            knowledge_base,
+            embedder,
+            top_k,
+            min_score,
+            citation_max_chars,
+        }
+    }
+
+    /// Configure from `KNOWLEDGE_TOP_K`, `KNOWLEDGE_MIN_SCORE`, `CITATIONS` and
+    /// `CITATION_MAX_CHARS`
+    pub fn from_env(
+        knowledge_base: Arc<KnowledgeBase>,
+        embedder: Box<dyn Embedder>,
+    ) -> Result<Self, OracleError> {
+        fn parse<T: std::str::FromStr>(var: &str, default: T) -> Result<T, OracleError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            match env::var(var) {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| format!("Invalid {} {:?}: {}", var, value, e).into()),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let citation_max_chars = if env_flag("CITATIONS") {
+            Some(parse("CITATION_MAX_CHARS", DEFAULT_CITATION_MAX_CHARS)?)
+        } else {
+            None
+        };
+        Ok(Self::new(
+            knowledge_base,
+            embedder,
+            parse("KNOWLEDGE_TOP_K", DEFAULT_TOP_K)?,
+            parse("KNOWLEDGE_MIN_SCORE", DEFAULT_MIN_SCORE)?,
+            citation_max_chars,
+        ))
+    }
+}
+
+#[async_trait]
+impl Tool for KnowledgeTool {
+    fn name(&self) -> &str {
+        "knowledge"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let context = &input.interaction.context;
+        if self.knowledge_base.documents(context)?.is_empty() {
+            return Ok(None);
+        }
+        let query = self
+            .embedder
+            .embed(&[input.interaction.text.clone()])
+            .await?
+            .pop()
+            .ok_or("Embedder returned no vector for the query")?;
+        let hits: Vec<_> = self
+            .knowledge_base
+            .search(context, &query, self.top_k)?
+            .into_iter()
+            .filter(|hit| hit.score >= self.min_score)
+            .collect();
+        if hits.is_empty() {
+            return Ok(None);
+        }
+
+        let excerpts: Vec<String> = hits
+            .iter()
+            .map(|hit| {
+                format!(
+                    "({}, {}) {}",
+                    hit.citation().compact(),
+                    as_of(hit.checked_at),
+                    hit.text
+                )
+            })
+            .collect();
+        let citations: Vec<_> = hits.iter().map(|hit| hit.citation()).collect();
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Relevant excerpts from the context's documents, with their source and freshness. \
+                 Prefer them over prior knowledge and mention the date when it matters:\n{}",
+                excerpts.join("\n")
+            ),
+            callback_suffix: self
+                .citation_max_chars
+                .and_then(|max_chars| compact_citations(&citations, max_chars)),
+            citations,
+        }))
+    }
+}
@@ -0,0 +1,241 @@
+//! Tools that ground a response in external, verifiable data.
+//!
+//! Before the LLM is called every registered [`Tool`] gets a look at the interaction. A tool that
+//! applies returns text injected into the prompt and, optionally, a suffix appended verbatim to
+//! the callback so consumers can audit the data the answer was based on.
+
+use crate::config::env_flag;
+use crate::OracleError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
+
+pub mod calculator;
+pub mod dates;
+#[cfg(feature = "rag")]
+pub mod knowledge;
+pub mod nft_metadata;
+pub mod prices;
+pub mod randomness;
+pub mod realms;
+pub mod tx_history;
+pub mod units;
+pub mod wallet;
+
+pub use calculator::CalculatorTool;
+pub use dates::DateTool;
+#[cfg(feature = "rag")]
+pub use knowledge::KnowledgeTool;
+pub use nft_metadata::NftMetadataTool;
+pub use prices::PriceTool;
+pub use randomness::DrandBeacon;
+pub use realms::RealmsTool;
+pub use tx_history::TransactionHistoryTool;
+pub use units::UnitConversionTool;
+pub use wallet::WalletTool;
+
+/// Where part of an answer came from, archived in full for verification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    pub document: String,
+    pub version: u64,
+    pub chunk: usize,
+    pub source: String,
+    pub score: f32,
+    pub checked_at: u64,
+}
+
+impl Citation {
+    /// Short id for the callback: the URL without its scheme, or the document name, and the chunk
+    pub fn compact(&self) -> String {
+        let source = self
+            .source
+            .strip_prefix("https://")
+            .or_else(|| self.source.strip_prefix("http://"))
+            .filter(|_| self.source == self.document)
+            .unwrap_or(&self.document);
+        format!("{}#{}", source.trim_end_matches('/'), self.chunk)
+    }
+}
+
+/// What a tool gets to see of an interaction
+pub struct ToolInput<'a> {
+    pub interaction_pubkey: &'a Pubkey,
+    pub interaction: &'a solana_gpt_oracle::Interaction,
+    pub context_text: &'a str,
+}
+
+pub struct ToolOutput {
+    pub tool: String,
+    /// Injected into the prompt
+    pub prompt: String,
+    /// Appended to the callback response
+    pub callback_suffix: Option<String>,
+    /// Knowledge base chunks the prompt was grounded on, archived with the response
+    pub citations: Vec<Citation>,
+}
+
+#[async_trait]
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Returns `None` when the tool doesn't apply to this interaction.
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError>;
+}
+
+/// The set of tools enabled for this oracle
+#[derive(Default)]
+pub struct Tools {
+    tools: Vec<Box<dyn Tool>>,
+}
+
+impl Tools {
+    pub fn register(&mut self, tool: Box<dyn Tool>) {
+        self.tools.push(tool);
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.tools.iter().map(|tool| tool.name()).collect()
+    }
+
+    /// Register the tools enabled through environment variables
+    pub fn from_env(rpc_url: &str) -> Result<Self, OracleError> {
+        let mut tools = Self::default();
+        if let Some(contexts) = context_list("RANDOMNESS_CONTEXTS")? {
+            let url = env::var("RANDOMNESS_BEACON_URL")
+                .unwrap_or(randomness::DEFAULT_DRAND_URL.to_string());
+            tools.register(Box::new(DrandBeacon::new(url, contexts)));
+        }
+        if env_flag("NFT_METADATA_TOOL") {
+            let rpc_url = env::var("NFT_METADATA_RPC_URL").unwrap_or(rpc_url.to_string());
+            let das_url = env::var("NFT_METADATA_DAS_URL").ok();
+            tools.register(Box::new(NftMetadataTool::new(rpc_url, das_url)));
+        }
+        if let Ok(queries) = env::var("WALLET_TOOL_QUERIES") {
+            let cache_secs = match env::var("WALLET_TOOL_CACHE_SECS") {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| format!("Invalid WALLET_TOOL_CACHE_SECS {:?}: {}", value, e))?,
+                Err(_) => 30,
+            };
+            tools.register(Box::new(WalletTool::new(
+                rpc_url.to_string(),
+                wallet::parse_queries(&queries)?,
+                Duration::from_secs(cache_secs),
+            )));
+        }
+        if env_flag("TX_HISTORY_TOOL") {
+            let limit = match env::var("TX_HISTORY_TOOL_LIMIT") {
+                Ok(value) => value
+                    .parse()
+                    .map_err(|e| format!("Invalid TX_HISTORY_TOOL_LIMIT {:?}: {}", value, e))?,
+                Err(_) => 10,
+            };
+            tools.register(Box::new(TransactionHistoryTool::new(
+                rpc_url.to_string(),
+                limit,
+                Duration::from_secs(60),
+            )));
+        }
+        if let Ok(value) = env::var("REALMS_CONTEXTS") {
+            let program_id = match env::var("GOVERNANCE_PROGRAM_ID") {
+                Ok(program_id) => Pubkey::from_str(&program_id)
+                    .map_err(|e| format!("Invalid GOVERNANCE_PROGRAM_ID: {}", e))?,
+                Err(_) => realms::GOVERNANCE_PROGRAM_ID,
+            };
+            tools.register(Box::new(RealmsTool::new(
+                rpc_url.to_string(),
+                program_id,
+                pubkey_pairs("REALMS_CONTEXTS", &value)?,
+            )));
+        }
+        if let Ok(value) = env::var("PRICE_FEEDS") {
+            let feeds = value
+                .split(',')
+                .filter(|feed| !feed.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<prices::PriceFeed>, _>>()?;
+            let rpc_url = env::var("PRICE_FEEDS_RPC_URL").unwrap_or(rpc_url.to_string());
+            tools.register(Box::new(PriceTool::new(rpc_url, feeds)));
+        }
+        if env_flag("CALCULATOR_TOOL") {
+            tools.register(Box::new(CalculatorTool));
+        }
+        if env_flag("UNIT_CONVERSION_TOOL") {
+            tools.register(Box::new(UnitConversionTool));
+        }
+        if env_flag("DATE_TOOL") {
+            tools.register(Box::new(DateTool));
+        }
+        Ok(tools)
+    }
+
+    /// Run every tool, logging (and skipping) the ones that fail
+    pub async fn run(&self, input: &ToolInput<'_>) -> Vec<ToolOutput> {
+        let mut outputs = Vec::new();
+        for tool in &self.tools {
+            match tool.run(input).await {
+                Ok(Some(output)) => outputs.push(output),
+                Ok(None) => {}
+                Err(e) => warn!(tool = tool.name(), error = ?e, "Tool failed"),
+            }
+        }
+        outputs
+    }
+}
+
+/// Parse a comma separated list of context pubkeys. `None` when the variable is unset.
+pub fn context_list(var: &str) -> Result<Option<HashSet<Pubkey>>, OracleError> {
+    let Ok(value) = env::var(var) else {
+        return Ok(None);
+    };
+    let contexts = value
+        .split(',')
+        .map(str::trim)
+        .filter(|context| !context.is_empty())
+        .map(|context| {
+            Pubkey::from_str(context)
+                .map_err(|e| format!("Invalid pubkey {:?} in {}: {}", context, var, e))
+        })
+        .collect::<Result<HashSet<_>, _>>()?;
+    Ok(Some(contexts))
+}
+
+/// Parse a comma separated list of `<pubkey>:<pubkey>` pairs
+pub fn pubkey_pairs(var: &str, value: &str) -> Result<HashMap<Pubkey, Pubkey>, OracleError> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| -> Result<(Pubkey, Pubkey), OracleError> {
+            let (left, right) = pair
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid pair {:?} in {}, expected <a>:<b>", pair, var))?;
+            let parse = |pubkey: &str| {
+                Pubkey::from_str(pubkey.trim())
+                    .map_err(|e| format!("Invalid pubkey {:?} in {}: {}", pubkey, var, e))
+            };
+            Ok((parse(left)?, parse(right)?))
+        })
+        .collect()
+}
+
+/// Pubkeys mentioned in free text
+pub fn find_pubkeys(text: &str) -> Vec<Pubkey> {
+    let mut pubkeys = Vec::new();
+    for word in text.split(|c: char| !c.is_ascii_alphanumeric()) {
+        if (32..=44).contains(&word.len()) {
+            if let Ok(pubkey) = Pubkey::from_str(word) {
+                if !pubkeys.contains(&pubkey) {
+                    pubkeys.push(pubkey);
+                }
+            }
+        }
+    }
+    pubkeys
+}
@@ -0,0 +1,242 @@
+use super::{find_pubkeys, Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use tracing::warn;
+
+pub const METADATA_PROGRAM_ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+/// Only look up the first few mints mentioned in an interaction
+const MAX_MINTS_PER_INTERACTION: usize = 3;
+/// Off-chain metadata JSON larger than this is ignored
+const MAX_OFFCHAIN_JSON_BYTES: usize = 64 * 1024;
+const MAX_ATTRIBUTES: usize = 20;
+
+/// Metadata of an NFT, as injected into the prompt
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NftMetadata {
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub image: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl NftMetadata {
+    fn describe(&self) -> String {
+        let mut description = format!(
+            "NFT {}: name {:?}, symbol {:?}",
+            self.mint, self.name, self.symbol
+        );
+        if let Some(image) = &self.image {
+            description.push_str(&format!(", image {}", image));
+        }
+        if !self.attributes.is_empty() {
+            let attributes: Vec<String> = self
+                .attributes
+                .iter()
+                .map(|(trait_type, value)| format!("{}={}", trait_type, value))
+                .collect();
+            description.push_str(&format!(", attributes: {}", attributes.join(", ")));
+        }
+        description
+    }
+
+    fn apply_offchain_json(&mut self, json: &Value) {
+        if let Some(image) = json.get("image").and_then(Value::as_str) {
+            self.image = Some(image.to_string());
+        }
+        if let Some(attributes) = json.get("attributes").and_then(Value::as_array) {
+            self.attributes = attributes
+                .iter()
+                .filter_map(|attribute| {
+                    let trait_type = attribute.get("trait_type")?.as_str()?.to_string();
+                    let value = match attribute.get("value")? {
+                        Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    Some((trait_type, value))
+                })
+                .take(MAX_ATTRIBUTES)
+                .collect();
+        }
+    }
+}
+
+/// Derive the Metaplex metadata PDA of a mint
+pub fn metadata_pda(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"metadata", METADATA_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &METADATA_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Decode name, symbol and uri from a Metaplex `Metadata` account
+pub fn parse_metadata_account(mint: Pubkey, data: &[u8]) -> Option<NftMetadata> {
+    fn read_string(data: &[u8], offset: &mut usize) -> Option<String> {
+        let len_bytes: [u8; 4] = data.get(*offset..*offset + 4)?.try_into().ok()?;
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        *offset += 4;
+        let bytes = data.get(*offset..*offset + len)?;
+        *offset += len;
+        Some(
+            String::from_utf8_lossy(bytes)
+                .trim_matches(char::from(0))
+                .to_string(),
+        )
+    }
+
+    // key (1) + update authority (32) + mint (32)
+    let mut offset = 1 + 32 + 32;
+    let name = read_string(data, &mut offset)?;
+    let symbol = read_string(data, &mut offset)?;
+    let uri = read_string(data, &mut offset)?;
+    Some(NftMetadata {
+        mint,
+        name,
+        symbol,
+        uri,
+        ..Default::default()
+    })
+}
+
+/// Looks up Metaplex metadata for mints mentioned in the interaction text, through a DAS
+/// (`getAsset`) endpoint when configured and the metadata account plus its off-chain JSON otherwise.
+pub struct NftMetadataTool {
+    rpc_client: RpcClient,
+    http: reqwest::Client,
+    das_url: Option<String>,
+}
+
+impl NftMetadataTool {
+    pub fn new(rpc_url: String, das_url: Option<String>) -> Self {
+        Self {
+            rpc_client: RpcClient::new(rpc_url),
+            http: reqwest::Client::new(),
+            das_url,
+        }
+    }
+
+    async fn lookup(&self, mint: Pubkey) -> Result<Option<NftMetadata>, OracleError> {
+        match &self.das_url {
+            Some(das_url) => self.lookup_das(das_url, mint).await,
+            None => self.lookup_onchain(mint).await,
+        }
+    }
+
+    async fn lookup_onchain(&self, mint: Pubkey) -> Result<Option<NftMetadata>, OracleError> {
+        let Some(account) = self
+            .rpc_client
+            .get_account_with_commitment(&metadata_pda(&mint), self.rpc_client.commitment())
+            .await?
+            .value
+        else {
+            return Ok(None);
+        };
+        let Some(mut metadata) = parse_metadata_account(mint, &account.data) else {
+            return Ok(None);
+        };
+        if metadata.uri.starts_with("http") {
+            match self.fetch_offchain_json(&metadata.uri).await {
+                Ok(json) => metadata.apply_offchain_json(&json),
+                Err(e) => warn!(%mint, error = ?e, "Failed to fetch off-chain metadata"),
+            }
+        }
+        Ok(Some(metadata))
+    }
+
+    async fn fetch_offchain_json(&self, uri: &str) -> Result<Value, OracleError> {
+        let response = self.http.get(uri).send().await?;
+        if response
+            .content_length()
+            .is_some_and(|len| len as usize > MAX_OFFCHAIN_JSON_BYTES)
+        {
+            return Err("Off-chain metadata is too large".into());
+        }
+        let bytes = response.bytes().await?;
+        if bytes.len() > MAX_OFFCHAIN_JSON_BYTES {
+            return Err("Off-chain metadata is too large".into());
+        }
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn lookup_das(
+        &self,
+        das_url: &str,
+        mint: Pubkey,
+    ) -> Result<Option<NftMetadata>, OracleError> {
+        let response: Value = self
+            .http
+            .post(das_url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": "llm-oracle",
+                "method": "getAsset",
+                "params": { "id": mint.to_string() },
+            }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        let Some(content) = response.pointer("/result/content") else {
+            return Ok(None);
+        };
+        let field = |pointer: &str| {
+            content
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let mut metadata = NftMetadata {
+            mint,
+            name: field("/metadata/name"),
+            symbol: field("/metadata/symbol"),
+            uri: field("/json_uri"),
+            ..Default::default()
+        };
+        if let Some(json) = content.get("metadata") {
+            metadata.apply_offchain_json(json);
+        }
+        if let Some(image) = content.pointer("/links/image").and_then(Value::as_str) {
+            metadata.image = Some(image.to_string());
+        }
+        Ok(Some(metadata))
+    }
+}
+
+#[async_trait]
+impl Tool for NftMetadataTool {
+    fn name(&self) -> &str {
+        "nft_metadata"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let mut descriptions = Vec::new();
+        for mint in find_pubkeys(&input.interaction.text)
+            .into_iter()
+            .take(MAX_MINTS_PER_INTERACTION)
+        {
+            if let Some(metadata) = self.lookup(mint).await? {
+                descriptions.push(metadata.describe());
+            }
+        }
+        if descriptions.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Metadata of the NFTs mentioned by the user: {}",
+                descriptions.join("; ")
+            ),
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,184 @@
+use super::{Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Magic number of legacy (push) Pyth accounts
+const PYTH_LEGACY_MAGIC: u32 = 0xa1b2_c3d4;
+/// Legacy Pyth account type of price accounts
+const PYTH_LEGACY_PRICE_ACCOUNT: u32 = 3;
+/// Anchor discriminator of the Pyth receiver `PriceUpdateV2` account
+const PYTH_PRICE_UPDATE_V2_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+/// Prices older than this are flagged as stale in the prompt
+const STALE_AFTER_SECS: i64 = 60;
+
+/// A price feed configured for the tool, e.g. `SOL/USD`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceFeed {
+    pub symbol: String,
+    pub account: Pubkey,
+}
+
+impl FromStr for PriceFeed {
+    type Err = OracleError;
+
+    /// Parse `SOL/USD:<price account>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (symbol, account) = value.rsplit_once(':').ok_or_else(|| {
+            format!(
+                "Invalid price feed {:?}, expected <symbol>:<account>",
+                value
+            )
+        })?;
+        Ok(Self {
+            symbol: symbol.trim().to_uppercase(),
+            account: Pubkey::from_str(account.trim())
+                .map_err(|e| format!("Invalid price account {:?}: {}", account, e))?,
+        })
+    }
+}
+
+impl PriceFeed {
+    /// Whether the interaction text asks about this feed
+    fn is_mentioned(&self, text: &str) -> bool {
+        let base = self.symbol.split('/').next().unwrap_or(&self.symbol);
+        text.split(|c: char| !c.is_ascii_alphanumeric())
+            .any(|word| word.eq_ignore_ascii_case(base))
+    }
+}
+
+/// A decoded price with its confidence interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub price: f64,
+    pub confidence: f64,
+    pub publish_time: i64,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset + N)?.try_into().ok()
+}
+
+fn scaled(value: i64, exponent: i32) -> f64 {
+    value as f64 * 10f64.powi(exponent)
+}
+
+/// Decode a Pyth price account, either a `PriceUpdateV2` (pull oracle) or a legacy price account
+pub fn parse_pyth_price(data: &[u8]) -> Option<Price> {
+    if read::<8>(data, 0)? == PYTH_PRICE_UPDATE_V2_DISCRIMINATOR {
+        // discriminator (8) + write authority (32) + verification level (1 or 2 bytes)
+        let offset = match data.get(40)? {
+            0 => 42,
+            _ => 41,
+        };
+        // feed id (32) + price (8) + conf (8) + exponent (4) + publish time (8)
+        let message = offset + 32;
+        let price = i64::from_le_bytes(read(data, message)?);
+        let confidence = u64::from_le_bytes(read(data, message + 8)?);
+        let exponent = i32::from_le_bytes(read(data, message + 16)?);
+        let publish_time = i64::from_le_bytes(read(data, message + 20)?);
+        return Some(Price {
+            price: scaled(price, exponent),
+            confidence: scaled(confidence as i64, exponent),
+            publish_time,
+        });
+    }
+
+    if u32::from_le_bytes(read(data, 0)?) != PYTH_LEGACY_MAGIC
+        || u32::from_le_bytes(read(data, 8)?) != PYTH_LEGACY_PRICE_ACCOUNT
+    {
+        return None;
+    }
+    let exponent = i32::from_le_bytes(read(data, 20)?);
+    let publish_time = i64::from_le_bytes(read(data, 96)?);
+    // Aggregate price info
+    let price = i64::from_le_bytes(read(data, 208)?);
+    let confidence = u64::from_le_bytes(read(data, 216)?);
+    Some(Price {
+        price: scaled(price, exponent),
+        confidence: scaled(confidence as i64, exponent),
+        publish_time,
+    })
+}
+
+/// Reads configured Pyth price accounts and exposes the current price and confidence interval of
+/// the feeds an interaction mentions. The raw numbers are also appended to the callback.
+pub struct PriceTool {
+    rpc_client: RpcClient,
+    feeds: Vec<PriceFeed>,
+}
+
+impl PriceTool {
+    pub fn new(rpc_url: String, feeds: Vec<PriceFeed>) -> Self {
+        Self {
+            rpc_client: RpcClient::new(rpc_url),
+            feeds,
+        }
+    }
+}
+
+#[async_trait]
+impl Tool for PriceTool {
+    fn name(&self) -> &str {
+        "prices"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let feeds: Vec<&PriceFeed> = self
+            .feeds
+            .iter()
+            .filter(|feed| feed.is_mentioned(&input.interaction.text))
+            .collect();
+        if feeds.is_empty() {
+            return Ok(None);
+        }
+
+        let accounts: Vec<Pubkey> = feeds.iter().map(|feed| feed.account).collect();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let mut facts = Vec::new();
+        let mut raw = Vec::new();
+        for (feed, account) in feeds
+            .iter()
+            .zip(self.rpc_client.get_multiple_accounts(&accounts).await?)
+        {
+            let Some(price) = account.and_then(|account| parse_pyth_price(&account.data)) else {
+                warn!(symbol = %feed.symbol, account = %feed.account, "Couldn't decode price feed");
+                continue;
+            };
+            let age = now - price.publish_time;
+            let stale = if age > STALE_AFTER_SECS {
+                format!(" (STALE, {}s old)", age)
+            } else {
+                String::new()
+            };
+            facts.push(format!(
+                "{} = {} ± {}{}",
+                feed.symbol, price.price, price.confidence, stale
+            ));
+            raw.push(format!(
+                "{}={}±{}",
+                feed.symbol, price.price, price.confidence
+            ));
+        }
+        if facts.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Current oracle prices (price ± confidence interval). Quote these exact values \
+                 rather than estimates: {}",
+                facts.join(", ")
+            ),
+            callback_suffix: Some(format!("[prices:{}]", raw.join(","))),
+            citations: Vec::new(),
+        }))
+    }
+}
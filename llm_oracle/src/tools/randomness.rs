@@ -0,0 +1,94 @@
+use super::{Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+
+/// Latest round of the League of Entropy default chain
+pub const DEFAULT_DRAND_URL: &str = "https://api.drand.sh/public/latest";
+
+const DICE_ROLLS: usize = 4;
+
+#[derive(Deserialize)]
+struct DrandRound {
+    round: u64,
+    randomness: String,
+    signature: String,
+}
+
+/// Injects a drand beacon round into the prompt of opted-in contexts, so dice rolls and other
+/// random outcomes come from a publicly verifiable source instead of the model.
+pub struct DrandBeacon {
+    client: reqwest::Client,
+    url: String,
+    contexts: HashSet<Pubkey>,
+}
+
+impl DrandBeacon {
+    pub fn new(url: String, contexts: HashSet<Pubkey>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            contexts,
+        }
+    }
+
+    async fn latest_round(&self) -> Result<(u64, Vec<u8>), OracleError> {
+        let response = self.client.get(&self.url).send().await?;
+        if !response.status().is_success() {
+            return Err(format!("drand beacon error ({})", response.status()).into());
+        }
+        let round: DrandRound = response.json().await?;
+        let randomness = hex::decode(&round.randomness)?;
+        let signature = hex::decode(&round.signature)?;
+        // drand defines the round's randomness as sha256(signature); the BLS signature itself can
+        // be checked by the consumer against the chain's public key.
+        if Sha256::digest(&signature).as_slice() != randomness.as_slice() {
+            return Err(format!(
+                "drand round {} randomness doesn't match its signature",
+                round.round
+            )
+            .into());
+        }
+        Ok((round.round, randomness))
+    }
+}
+
+/// Unbiased dice rolls from random bytes, using rejection sampling
+pub fn dice_rolls(randomness: &[u8], sides: u8, count: usize) -> Vec<u8> {
+    let limit = 256 - (256 % sides as u16);
+    randomness
+        .iter()
+        .filter(|byte| (**byte as u16) < limit)
+        .take(count)
+        .map(|byte| byte % sides + 1)
+        .collect()
+}
+
+#[async_trait]
+impl Tool for DrandBeacon {
+    fn name(&self) -> &str {
+        "drand"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        if !self.contexts.contains(&input.interaction.context) {
+            return Ok(None);
+        }
+        let (round, randomness) = self.latest_round().await?;
+        let randomness_hex = hex::encode(&randomness);
+        let rolls = dice_rolls(&randomness, 6, DICE_ROLLS);
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Verified randomness (drand round {}): {}. Six-sided dice rolls derived from it, in order: {:?}. \
+                 Use these values for any dice roll or random outcome; never invent your own.",
+                round, randomness_hex, rolls
+            ),
+            callback_suffix: Some(format!("[drand:{}:{}]", round, randomness_hex)),
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,332 @@
+use super::{find_pubkeys, Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const GOVERNANCE_PROGRAM_ID: Pubkey = pubkey!("GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw");
+
+/// `GovernanceAccountType::ProposalV2`
+const PROPOSAL_V2: u8 = 14;
+/// `GovernanceAccountType` of the governance accounts (V1 and V2 variants)
+const GOVERNANCE_ACCOUNT_TYPES: [u8; 8] = [3, 4, 9, 10, 18, 19, 20, 21];
+/// Most recent proposals included in the prompt
+const MAX_PROPOSALS: usize = 10;
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+const PROPOSAL_KEYWORDS: [&str; 4] = ["proposal", "vote", "dao", "governance"];
+
+/// The parts of an SPL Governance `ProposalV2` the oracle cares about
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub pubkey: Pubkey,
+    pub governance: Pubkey,
+    pub state: String,
+    pub options: Vec<(String, u64)>,
+    pub deny_vote_weight: Option<u64>,
+    pub abstain_vote_weight: Option<u64>,
+    pub draft_at: i64,
+    pub voting_completed_at: Option<i64>,
+    pub name: String,
+    pub description_link: String,
+}
+
+impl Proposal {
+    fn describe(&self) -> String {
+        let options: Vec<String> = self
+            .options
+            .iter()
+            .map(|(label, weight)| format!("{:?}={}", label, weight))
+            .collect();
+        let mut description = format!(
+            "Proposal {} {:?} ({}): votes {}",
+            self.pubkey,
+            self.name,
+            self.state,
+            options.join(", ")
+        );
+        if let Some(deny) = self.deny_vote_weight {
+            description.push_str(&format!(", against={}", deny));
+        }
+        if let Some(abstain) = self.abstain_vote_weight {
+            description.push_str(&format!(", abstain={}", abstain));
+        }
+        if !self.description_link.is_empty() {
+            description.push_str(&format!(", description: {}", self.description_link));
+        }
+        description
+    }
+}
+
+/// Minimal Borsh reader for governance accounts
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset + len)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        Some(i64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn pubkey(&mut self) -> Option<Pubkey> {
+        Pubkey::try_from(self.bytes(32)?).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        Some(String::from_utf8_lossy(self.bytes(len)?).to_string())
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> Option<T>) -> Option<Option<T>> {
+        match self.u8()? {
+            0 => Some(None),
+            _ => Some(Some(read(self)?)),
+        }
+    }
+}
+
+fn proposal_state(state: u8) -> &'static str {
+    match state {
+        0 => "draft",
+        1 => "signing off",
+        2 => "voting",
+        3 => "succeeded",
+        4 => "executing",
+        5 => "completed",
+        6 => "cancelled",
+        7 => "defeated",
+        8 => "executing with errors",
+        9 => "vetoed",
+        _ => "unknown",
+    }
+}
+
+/// Decode a `ProposalV2` account
+pub fn parse_proposal(pubkey: Pubkey, data: &[u8]) -> Option<Proposal> {
+    let mut reader = Reader { data, offset: 0 };
+    if reader.u8()? != PROPOSAL_V2 {
+        return None;
+    }
+    let governance = reader.pubkey()?;
+    let _governing_token_mint = reader.pubkey()?;
+    let state = proposal_state(reader.u8()?).to_string();
+    let _token_owner_record = reader.pubkey()?;
+    let _signatories_count = reader.u8()?;
+    let _signatories_signed_off_count = reader.u8()?;
+    // VoteType: SingleChoice (0) or MultiChoice (1) with 4 u8 fields
+    if reader.u8()? == 1 {
+        reader.bytes(4)?;
+    }
+    let options_len = reader.u32()?;
+    let mut options = Vec::new();
+    for _ in 0..options_len {
+        let label = reader.string()?;
+        let vote_weight = reader.u64()?;
+        let _vote_result = reader.u8()?;
+        let _transactions_executed_count = reader.u16()?;
+        let _transactions_count = reader.u16()?;
+        let _transactions_next_index = reader.u16()?;
+        options.push((label, vote_weight));
+    }
+    let deny_vote_weight = reader.option(Reader::u64)?;
+    let _reserved1 = reader.u8()?;
+    let abstain_vote_weight = reader.option(Reader::u64)?;
+    let _start_voting_at = reader.option(Reader::i64)?;
+    let draft_at = reader.i64()?;
+    let _signing_off_at = reader.option(Reader::i64)?;
+    let _voting_at = reader.option(Reader::i64)?;
+    let _voting_at_slot = reader.option(Reader::u64)?;
+    let voting_completed_at = reader.option(Reader::i64)?;
+    let _executing_at = reader.option(Reader::i64)?;
+    let _closed_at = reader.option(Reader::i64)?;
+    let _execution_flags = reader.u8()?;
+    let _max_vote_weight = reader.option(Reader::u64)?;
+    let _max_voting_time = reader.option(Reader::u32)?;
+    // VoteThreshold: YesVotePercentage(u8) | QuorumPercentage(u8) | Disabled
+    let _vote_threshold = reader.option(|reader| match reader.u8()? {
+        2 => Some(()),
+        _ => reader.u8().map(|_| ()),
+    })?;
+    reader.bytes(64)?;
+    let name = reader.string()?;
+    let description_link = reader.string()?;
+
+    Some(Proposal {
+        pubkey,
+        governance,
+        state,
+        options,
+        deny_vote_weight,
+        abstain_vote_weight,
+        draft_at,
+        voting_completed_at,
+        name,
+        description_link,
+    })
+}
+
+/// Exposes the live SPL Governance (Realms) proposals of the realm configured for a context, so
+/// DAO contexts can ask the oracle to summarize or compare them.
+pub struct RealmsTool {
+    rpc_client: RpcClient,
+    program_id: Pubkey,
+    /// context -> realm
+    realms: HashMap<Pubkey, Pubkey>,
+    cache: Mutex<HashMap<Pubkey, (Instant, Vec<Proposal>)>>,
+}
+
+impl RealmsTool {
+    pub fn new(rpc_url: String, program_id: Pubkey, realms: HashMap<Pubkey, Pubkey>) -> Self {
+        Self {
+            rpc_client: RpcClient::new(rpc_url),
+            program_id,
+            realms,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn accounts(
+        &self,
+        filters: Vec<RpcFilterType>,
+    ) -> Result<Vec<(Pubkey, solana_sdk::account::Account)>, OracleError> {
+        Ok(self
+            .rpc_client
+            .get_program_accounts_with_config(
+                &self.program_id,
+                RpcProgramAccountsConfig {
+                    filters: Some(filters),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            )
+            .await?)
+    }
+
+    /// All proposals of a realm, newest first
+    pub async fn proposals(&self, realm: &Pubkey) -> Result<Vec<Proposal>, OracleError> {
+        if let Some((fetched_at, proposals)) = self.cache.lock().unwrap().get(realm) {
+            if fetched_at.elapsed() < CACHE_TTL {
+                return Ok(proposals.clone());
+            }
+        }
+
+        let governances = self
+            .accounts(vec![RpcFilterType::Memcmp(Memcmp::new(
+                1,
+                MemcmpEncodedBytes::Bytes(realm.to_bytes().to_vec()),
+            ))])
+            .await?;
+        let mut proposals = Vec::new();
+        for (governance, account) in governances {
+            if !account
+                .data
+                .first()
+                .is_some_and(|kind| GOVERNANCE_ACCOUNT_TYPES.contains(kind))
+            {
+                continue;
+            }
+            let accounts = self
+                .accounts(vec![
+                    RpcFilterType::Memcmp(Memcmp::new(
+                        0,
+                        MemcmpEncodedBytes::Bytes(vec![PROPOSAL_V2]),
+                    )),
+                    RpcFilterType::Memcmp(Memcmp::new(
+                        1,
+                        MemcmpEncodedBytes::Bytes(governance.to_bytes().to_vec()),
+                    )),
+                ])
+                .await?;
+            proposals.extend(
+                accounts
+                    .into_iter()
+                    .filter_map(|(pubkey, account)| parse_proposal(pubkey, &account.data)),
+            );
+        }
+        proposals.sort_by(|a, b| b.draft_at.cmp(&a.draft_at));
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(*realm, (Instant::now(), proposals.clone()));
+        Ok(proposals)
+    }
+}
+
+#[async_trait]
+impl Tool for RealmsTool {
+    fn name(&self) -> &str {
+        "realms"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let Some(realm) = self.realms.get(&input.interaction.context) else {
+            return Ok(None);
+        };
+        let text = input.interaction.text.to_lowercase();
+        if !PROPOSAL_KEYWORDS
+            .iter()
+            .any(|keyword| text.contains(keyword))
+        {
+            return Ok(None);
+        }
+
+        let proposals = self.proposals(realm).await?;
+        // Restrict to the proposals mentioned by pubkey, if any
+        let mentioned = find_pubkeys(&input.interaction.text);
+        let selected: Vec<String> = proposals
+            .iter()
+            .filter(|proposal| mentioned.is_empty() || mentioned.contains(&proposal.pubkey))
+            .take(MAX_PROPOSALS)
+            .map(Proposal::describe)
+            .collect();
+        let prompt = if selected.is_empty() {
+            format!("The realm {} has no matching proposals.", realm)
+        } else {
+            format!(
+                "Live governance proposals of realm {} (newest first): {}",
+                realm,
+                selected.join(" | ")
+            )
+        };
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt,
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,246 @@
+use super::{find_pubkeys, Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_request::RpcRequest;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Hard cap on transactions fetched per wallet, whatever the configuration says
+pub const MAX_TRANSACTIONS: usize = 25;
+
+/// Words signalling that the user asks about past activity
+const ACTIVITY_KEYWORDS: [&str; 5] = ["activity", "transaction", "history", "recent", "explain"];
+
+/// Facts extracted from one transaction
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TransactionFacts {
+    pub signature: String,
+    pub block_time: Option<i64>,
+    pub succeeded: bool,
+    pub fee_lamports: u64,
+    pub sol_change: f64,
+    pub token_changes: Vec<(String, f64)>,
+    pub instructions: Vec<String>,
+}
+
+impl TransactionFacts {
+    fn describe(&self) -> String {
+        let when = self
+            .block_time
+            .and_then(|time| chrono::DateTime::from_timestamp(time, 0))
+            .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or("unknown time".to_string());
+        let mut facts = format!(
+            "{} {} ({}): SOL change {:+}, fee {} lamports",
+            when,
+            &self.signature[..self.signature.len().min(12)],
+            if self.succeeded { "ok" } else { "failed" },
+            self.sol_change,
+            self.fee_lamports
+        );
+        for (mint, change) in &self.token_changes {
+            facts.push_str(&format!(", token {} change {:+}", mint, change));
+        }
+        if !self.instructions.is_empty() {
+            facts.push_str(&format!(", instructions: {}", self.instructions.join(" ")));
+        }
+        facts
+    }
+}
+
+/// Extract the facts concerning `wallet` from a `jsonParsed` encoded transaction
+pub fn transaction_facts(
+    wallet: &Pubkey,
+    signature: &str,
+    transaction: &Value,
+) -> TransactionFacts {
+    let wallet = wallet.to_string();
+    let meta = &transaction["meta"];
+    let message = &transaction["transaction"]["message"];
+
+    let wallet_index = message["accountKeys"].as_array().and_then(|keys| {
+        keys.iter()
+            .position(|key| key["pubkey"].as_str() == Some(wallet.as_str()))
+    });
+    let sol_change = wallet_index
+        .and_then(|index| {
+            let pre = meta["preBalances"][index].as_i64()?;
+            let post = meta["postBalances"][index].as_i64()?;
+            Some((post - pre) as f64 / 1_000_000_000.0)
+        })
+        .unwrap_or_default();
+
+    let token_balances = |field: &str| -> HashMap<String, f64> {
+        meta[field]
+            .as_array()
+            .map(|balances| {
+                balances
+                    .iter()
+                    .filter(|balance| balance["owner"].as_str() == Some(wallet.as_str()))
+                    .filter_map(|balance| {
+                        Some((
+                            balance["mint"].as_str()?.to_string(),
+                            balance["uiTokenAmount"]["uiAmount"]
+                                .as_f64()
+                                .unwrap_or_default(),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let pre_tokens = token_balances("preTokenBalances");
+    let post_tokens = token_balances("postTokenBalances");
+    let mut token_changes: Vec<(String, f64)> = post_tokens
+        .keys()
+        .chain(pre_tokens.keys())
+        .map(|mint| {
+            let change = post_tokens.get(mint).copied().unwrap_or_default()
+                - pre_tokens.get(mint).copied().unwrap_or_default();
+            (mint.clone(), change)
+        })
+        .filter(|(_, change)| *change != 0.0)
+        .collect();
+    token_changes.sort_by(|a, b| a.0.cmp(&b.0));
+    token_changes.dedup_by(|a, b| a.0 == b.0);
+
+    let instructions = message["instructions"]
+        .as_array()
+        .map(|instructions| {
+            instructions
+                .iter()
+                .map(|instruction| {
+                    match (
+                        instruction["program"].as_str(),
+                        instruction["parsed"]["type"].as_str(),
+                    ) {
+                        (Some(program), Some(kind)) => format!("{}:{}", program, kind),
+                        (Some(program), None) => program.to_string(),
+                        _ => instruction["programId"]
+                            .as_str()
+                            .unwrap_or("unknown")
+                            .to_string(),
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    TransactionFacts {
+        signature: signature.to_string(),
+        block_time: transaction["blockTime"].as_i64(),
+        succeeded: meta["err"].is_null(),
+        fee_lamports: meta["fee"].as_u64().unwrap_or_default(),
+        sol_change,
+        token_changes,
+        instructions,
+    }
+}
+
+/// Pre-summarizes a wallet's most recent transactions into structured facts, for "explain my
+/// activity" prompts. At most `limit` transactions are fetched and summaries are cached for
+/// `cache_ttl`, which bounds the RPC cost of a single interaction.
+pub struct TransactionHistoryTool {
+    rpc_client: RpcClient,
+    limit: usize,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<Pubkey, (Instant, Vec<String>)>>,
+}
+
+impl TransactionHistoryTool {
+    pub fn new(rpc_url: String, limit: usize, cache_ttl: Duration) -> Self {
+        Self {
+            rpc_client: RpcClient::new(rpc_url),
+            limit: limit.clamp(1, MAX_TRANSACTIONS),
+            cache_ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn summarize(&self, wallet: &Pubkey) -> Result<Vec<String>, OracleError> {
+        if let Some((fetched_at, summary)) = self.cache.lock().unwrap().get(wallet) {
+            if fetched_at.elapsed() < self.cache_ttl {
+                return Ok(summary.clone());
+            }
+        }
+
+        let signatures = self
+            .rpc_client
+            .get_signatures_for_address_with_config(
+                wallet,
+                GetConfirmedSignaturesForAddress2Config {
+                    limit: Some(self.limit),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let mut summary = Vec::new();
+        for status in signatures {
+            let transaction: Value = self
+                .rpc_client
+                .send(
+                    RpcRequest::GetTransaction,
+                    json!([status.signature, {
+                        "encoding": "jsonParsed",
+                        "maxSupportedTransactionVersion": 0,
+                    }]),
+                )
+                .await?;
+            if transaction.is_null() {
+                continue;
+            }
+            summary.push(transaction_facts(wallet, &status.signature, &transaction).describe());
+        }
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(*wallet, (Instant::now(), summary.clone()));
+        Ok(summary)
+    }
+}
+
+#[async_trait]
+impl Tool for TransactionHistoryTool {
+    fn name(&self) -> &str {
+        "tx_history"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let text = input.interaction.text.to_lowercase();
+        if !ACTIVITY_KEYWORDS
+            .iter()
+            .any(|keyword| text.contains(keyword))
+        {
+            return Ok(None);
+        }
+        // The first mentioned pubkey, or the user's own wallet
+        let wallet = find_pubkeys(&input.interaction.text)
+            .into_iter()
+            .next()
+            .unwrap_or(input.interaction.user);
+
+        let summary = self.summarize(&wallet).await?;
+        let prompt = if summary.is_empty() {
+            format!("Wallet {} has no recent transactions.", wallet)
+        } else {
+            format!(
+                "The {} most recent transactions of wallet {} (newest first): {}",
+                summary.len(),
+                wallet,
+                summary.join(" | ")
+            )
+        };
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt,
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,170 @@
+use super::calculator::format_number;
+use super::{Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+
+/// Only run the first few conversions of an interaction
+const MAX_CONVERSIONS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Length,
+    Mass,
+    Time,
+    Data,
+    Sol,
+    Temperature,
+}
+
+/// Dimension and factor to the base unit (metre, gram, second, byte, lamport). Temperatures are
+/// converted separately.
+fn unit(name: &str) -> Option<(Dimension, f64)> {
+    use Dimension::*;
+    Some(match name {
+        "mm" | "millimeter" | "millimeters" | "millimetre" | "millimetres" => (Length, 0.001),
+        "cm" | "centimeter" | "centimeters" | "centimetre" | "centimetres" => (Length, 0.01),
+        "m" | "meter" | "meters" | "metre" | "metres" => (Length, 1.0),
+        "km" | "kilometer" | "kilometers" | "kilometre" | "kilometres" => (Length, 1000.0),
+        "inch" | "inches" => (Length, 0.0254),
+        "ft" | "foot" | "feet" => (Length, 0.3048),
+        "yd" | "yard" | "yards" => (Length, 0.9144),
+        "mi" | "mile" | "miles" => (Length, 1609.344),
+        "mg" | "milligram" | "milligrams" => (Mass, 0.001),
+        "g" | "gram" | "grams" => (Mass, 1.0),
+        "kg" | "kilogram" | "kilograms" => (Mass, 1000.0),
+        "t" | "tonne" | "tonnes" => (Mass, 1_000_000.0),
+        "oz" | "ounce" | "ounces" => (Mass, 28.349_523_125),
+        "lb" | "lbs" | "pound" | "pounds" => (Mass, 453.592_37),
+        "ms" | "millisecond" | "milliseconds" => (Time, 0.001),
+        "s" | "sec" | "secs" | "second" | "seconds" => (Time, 1.0),
+        "min" | "mins" | "minute" | "minutes" => (Time, 60.0),
+        "h" | "hr" | "hrs" | "hour" | "hours" => (Time, 3600.0),
+        "day" | "days" => (Time, 86_400.0),
+        "week" | "weeks" => (Time, 604_800.0),
+        "slot" | "slots" => (Time, 0.4),
+        "b" | "byte" | "bytes" => (Data, 1.0),
+        "kb" | "kilobyte" | "kilobytes" => (Data, 1e3),
+        "mb" | "megabyte" | "megabytes" => (Data, 1e6),
+        "gb" | "gigabyte" | "gigabytes" => (Data, 1e9),
+        "kib" => (Data, 1024.0),
+        "mib" => (Data, 1_048_576.0),
+        "gib" => (Data, 1_073_741_824.0),
+        "lamport" | "lamports" => (Sol, 1.0),
+        "sol" => (Sol, 1e9),
+        "c" | "°c" | "celsius" => (Temperature, 0.0),
+        "f" | "°f" | "fahrenheit" => (Temperature, 0.0),
+        "k" | "kelvin" => (Temperature, 0.0),
+        _ => return None,
+    })
+}
+
+fn to_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "c" | "°c" | "celsius" => value + 273.15,
+        "f" | "°f" | "fahrenheit" => (value - 32.0) * 5.0 / 9.0 + 273.15,
+        _ => value,
+    }
+}
+
+fn from_kelvin(value: f64, unit: &str) -> f64 {
+    match unit {
+        "c" | "°c" | "celsius" => value - 273.15,
+        "f" | "°f" | "fahrenheit" => (value - 273.15) * 9.0 / 5.0 + 32.0,
+        _ => value,
+    }
+}
+
+/// Convert `value` between two units of the same dimension
+pub fn convert(value: f64, from: &str, to: &str) -> Option<f64> {
+    let (from_dimension, from_factor) = unit(from)?;
+    let (to_dimension, to_factor) = unit(to)?;
+    if from_dimension != to_dimension {
+        return None;
+    }
+    if from_dimension == Dimension::Temperature {
+        return Some(from_kelvin(to_kelvin(value, from), to));
+    }
+    Some(value * from_factor / to_factor)
+}
+
+/// Split `10km` into `(10, "km")`
+fn split_quantity(word: &str) -> Option<(f64, &str)> {
+    let split = word
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(word.len());
+    let value = word[..split].parse().ok()?;
+    Some((value, &word[split..]))
+}
+
+/// Conversions asked for in free text, e.g. "10 km in miles" or "2.5sol to lamports"
+pub fn find_conversions(text: &str) -> Vec<(f64, String, String)> {
+    let lowercase = text.to_lowercase();
+    let words: Vec<&str> = lowercase
+        .split_whitespace()
+        .map(|word| word.trim_end_matches(|c: char| matches!(c, '?' | '!' | ',' | ';' | ':')))
+        .map(|word| word.trim_end_matches('.'))
+        .collect();
+    let mut conversions = Vec::new();
+    for i in 0..words.len() {
+        let Some((value, attached)) = split_quantity(words[i]) else {
+            continue;
+        };
+        let (from, rest) = if attached.is_empty() {
+            match words.get(i + 1) {
+                Some(from) => (*from, i + 2),
+                None => continue,
+            }
+        } else {
+            (attached, i + 1)
+        };
+        let (Some(keyword), Some(to)) = (words.get(rest), words.get(rest + 1)) else {
+            continue;
+        };
+        if !matches!(*keyword, "to" | "in" | "into" | "as") {
+            continue;
+        }
+        if convert(value, from, to).is_some() {
+            conversions.push((value, from.to_string(), to.to_string()));
+        }
+    }
+    conversions
+}
+
+/// Converts quantities between units deterministically, including SOL and lamports
+pub struct UnitConversionTool;
+
+#[async_trait]
+impl Tool for UnitConversionTool {
+    fn name(&self) -> &str {
+        "units"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let results: Vec<String> = find_conversions(&input.interaction.text)
+            .into_iter()
+            .take(MAX_CONVERSIONS)
+            .filter_map(|(value, from, to)| {
+                let converted = convert(value, &from, &to)?;
+                Some(format!(
+                    "{} {} = {} {}",
+                    format_number(value),
+                    from,
+                    format_number(converted),
+                    to
+                ))
+            })
+            .collect();
+        if results.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Exact unit conversions. Use these values and don't convert anything yourself: {}",
+                results.join("; ")
+            ),
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
@@ -0,0 +1,204 @@
+use super::{find_pubkeys, Tool, ToolInput, ToolOutput};
+use crate::OracleError;
+use async_trait::async_trait;
+use solana_account_decoder::UiAccountData;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EHCNRLb1RW7iLkXnV9oJ");
+
+/// Only look up the first few wallets mentioned in an interaction
+const MAX_WALLETS_PER_INTERACTION: usize = 2;
+/// Only list the largest holdings of a wallet
+const MAX_TOKEN_HOLDINGS: usize = 20;
+
+/// Words that make the tool look up the interaction's own user when no wallet is mentioned
+const SELF_REFERENCES: [&str; 4] = ["my wallet", "my balance", "my portfolio", "my tokens"];
+
+/// Queries the wallet tool is allowed to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WalletQuery {
+    SolBalance,
+    TokenBalances,
+}
+
+impl FromStr for WalletQuery {
+    type Err = OracleError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "sol" => Ok(WalletQuery::SolBalance),
+            "spl" => Ok(WalletQuery::TokenBalances),
+            other => Err(format!(
+                "Unknown wallet query {:?}, expected \"sol\" or \"spl\"",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+/// Grounds "summarize this wallet" style prompts with the SOL and SPL token balances of the
+/// wallets mentioned in the interaction. Results are cached for `cache_ttl` to bound RPC cost.
+pub struct WalletTool {
+    rpc_client: RpcClient,
+    queries: HashSet<WalletQuery>,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<(Pubkey, WalletQuery), (Instant, String)>>,
+}
+
+impl WalletTool {
+    pub fn new(rpc_url: String, queries: HashSet<WalletQuery>, cache_ttl: Duration) -> Self {
+        Self {
+            rpc_client: RpcClient::new(rpc_url),
+            queries,
+            cache_ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn query(&self, wallet: Pubkey, query: WalletQuery) -> Result<String, OracleError> {
+        if let Some((fetched_at, value)) = self.cache.lock().unwrap().get(&(wallet, query)) {
+            if fetched_at.elapsed() < self.cache_ttl {
+                return Ok(value.clone());
+            }
+        }
+        let value = match query {
+            WalletQuery::SolBalance => self.sol_balance(&wallet).await?,
+            WalletQuery::TokenBalances => self.token_balances(&wallet).await?,
+        };
+        self.cache
+            .lock()
+            .unwrap()
+            .insert((wallet, query), (Instant::now(), value.clone()));
+        Ok(value)
+    }
+
+    async fn sol_balance(&self, wallet: &Pubkey) -> Result<String, OracleError> {
+        let lamports = self.rpc_client.get_balance(wallet).await?;
+        Ok(format!("{} SOL", lamports_to_sol(lamports)))
+    }
+
+    async fn token_balances(&self, wallet: &Pubkey) -> Result<String, OracleError> {
+        let mut holdings: Vec<(String, f64, String)> = Vec::new();
+        for program_id in [TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID] {
+            let accounts = self
+                .rpc_client
+                .get_token_accounts_by_owner(wallet, TokenAccountsFilter::ProgramId(program_id))
+                .await?;
+            for account in accounts {
+                let UiAccountData::Json(parsed) = account.account.data else {
+                    continue;
+                };
+                let info = &parsed.parsed["info"];
+                let (Some(mint), Some(amount)) = (
+                    info["mint"].as_str(),
+                    info["tokenAmount"]["uiAmountString"].as_str(),
+                ) else {
+                    continue;
+                };
+                let ui_amount = info["tokenAmount"]["uiAmount"].as_f64().unwrap_or_default();
+                if ui_amount > 0.0 {
+                    holdings.push((mint.to_string(), ui_amount, amount.to_string()));
+                }
+            }
+        }
+        if holdings.is_empty() {
+            return Ok("no SPL tokens".to_string());
+        }
+        holdings.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let total = holdings.len();
+        let listed: Vec<String> = holdings
+            .iter()
+            .take(MAX_TOKEN_HOLDINGS)
+            .map(|(mint, _, amount)| format!("{} of mint {}", amount, mint))
+            .collect();
+        let mut summary = format!("{} SPL token holdings: {}", total, listed.join(", "));
+        if total > MAX_TOKEN_HOLDINGS {
+            summary.push_str(&format!(" (and {} more)", total - MAX_TOKEN_HOLDINGS));
+        }
+        Ok(summary)
+    }
+
+    async fn is_wallet(&self, pubkey: &Pubkey) -> Result<bool, OracleError> {
+        let account = self
+            .rpc_client
+            .get_account_with_commitment(pubkey, self.rpc_client.commitment())
+            .await?
+            .value;
+        Ok(account.is_some_and(|account| account.owner == solana_sdk::system_program::ID))
+    }
+}
+
+#[async_trait]
+impl Tool for WalletTool {
+    fn name(&self) -> &str {
+        "wallet"
+    }
+
+    async fn run(&self, input: &ToolInput<'_>) -> Result<Option<ToolOutput>, OracleError> {
+        let mut wallets = Vec::new();
+        for pubkey in find_pubkeys(&input.interaction.text) {
+            if wallets.len() >= MAX_WALLETS_PER_INTERACTION {
+                break;
+            }
+            if self.is_wallet(&pubkey).await? {
+                wallets.push(pubkey);
+            }
+        }
+        let text = input.interaction.text.to_lowercase();
+        if wallets.is_empty() && SELF_REFERENCES.iter().any(|phrase| text.contains(phrase)) {
+            wallets.push(input.interaction.user);
+        }
+        if wallets.is_empty() {
+            return Ok(None);
+        }
+
+        let mut facts = Vec::new();
+        for wallet in wallets {
+            let mut wallet_facts = Vec::new();
+            for query in [WalletQuery::SolBalance, WalletQuery::TokenBalances] {
+                if self.queries.contains(&query) {
+                    wallet_facts.push(self.query(wallet, query).await?);
+                }
+            }
+            let owner = if wallet == input.interaction.user {
+                " (the user)"
+            } else {
+                ""
+            };
+            facts.push(format!(
+                "Wallet {}{}: {}",
+                wallet,
+                owner,
+                wallet_facts.join("; ")
+            ));
+        }
+        Ok(Some(ToolOutput {
+            tool: self.name().to_string(),
+            prompt: format!(
+                "Current on-chain balances. Base any statement about these wallets on this data only: {}",
+                facts.join(" | ")
+            ),
+            callback_suffix: None,
+            citations: Vec::new(),
+        }))
+    }
+}
+
+/// Parse `WALLET_TOOL_QUERIES`, e.g. `sol,spl`
+pub fn parse_queries(value: &str) -> Result<HashSet<WalletQuery>, OracleError> {
+    value
+        .split(',')
+        .filter(|query| !query.trim().is_empty())
+        .map(WalletQuery::from_str)
+        .collect()
+}
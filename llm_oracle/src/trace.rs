@@ -0,0 +1,34 @@
+//! Trace IDs of interactions.
+//!
+//! Each interaction processed gets a random trace ID, recorded in the `trace_id` field of its
+//! log span, its archive record and its audit log entry, and sent with every provider request
+//! made for it, so a provider's support can find the requests of an on-chain interaction:
+//!
+//! - OpenAI and OpenAI-compatible endpoints get it as `X-Client-Request-Id` (which OpenAI keeps
+//!   with the request) and `X-Request-Id`, and the `x-request-id` OpenAI answers with is logged;
+//!   the OpenAI requests of interactions skip `chatgpt`, which can't send headers
+//! - Gemini gets it as `X-Request-Id` and in the `x-goog-api-client` client metadata
+//!
+//! A replay or dead-letter retry is a new attempt with a new ID; the interaction's pubkey, also
+//! in the span, ties them together.
+
+use std::future::Future;
+
+tokio::task_local! {
+    static TRACE_ID: String;
+}
+
+/// A new trace ID: 32 random hex digits
+pub fn new_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Run `future` with `id` as the trace ID of the provider requests it makes
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    TRACE_ID.scope(id, future).await
+}
+
+/// The trace ID of the interaction being processed
+pub fn current() -> Option<String> {
+    TRACE_ID.try_with(String::clone).ok()
+}
@@ -0,0 +1,310 @@
+//! Prompt tuning suggestions.
+//!
+//! Collects what went wrong recently: interactions flagged by the guardrails or the
+//! hallucination guard or labeled bad in the review queue, and responses rated 2 or less by
+//! their users in the archive. Failures are clustered by context and kind, and the model is
+//! shown the context's prompt template with the failures of each cluster and asked for a
+//! revised template. Each suggestion is written to `prompt_suggestions.dir`
+//! (`PROMPT_SUGGESTIONS_DIR`) as `<context pubkey>.j2`, ready to be copied to
+//! `PROMPT_TEMPLATE_DIR`, with a `<context pubkey>.diff` against the current template explaining
+//! the change.
+//!
+//! Nothing is applied: an operator reviews the suggestions. With `prompt_suggestions.dir` set
+//! the analysis runs every `prompt_suggestions.interval_secs`
+//! (`PROMPT_SUGGESTIONS_INTERVAL_SECS`); `llm_oracle suggest-prompts` runs it once.
+
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::prompts;
+use crate::providers::{ChatMessage, Role};
+use crate::review::Label;
+use crate::OracleError;
+use similar::TextDiff;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+pub const DEFAULT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+/// Failures of a context needed before a change is suggested
+pub const DEFAULT_MIN_FAILURES: usize = 3;
+/// Ratings at or below this score count as failures
+pub const LOW_RATING: u8 = 2;
+/// Failures quoted to the model per cluster
+const MAX_EXAMPLES: usize = 8;
+/// Characters of a prompt or response quoted to the model
+const MAX_EXAMPLE_CHARS: usize = 600;
+/// Kind of the notification sent when suggestions are written
+pub const SUGGESTIONS_EVENT: &str = "prompt_suggestions";
+
+/// Something that went wrong with a response
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub context: String,
+    /// e.g. `unverified`, `blocklisted`, `labeled bad` or `rated 1/5`
+    pub kind: String,
+    pub prompt: String,
+    pub response: String,
+    pub note: Option<String>,
+}
+
+/// A revised template for a context, with the failures it addresses
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub context: String,
+    /// Failure counts by kind
+    pub failures: BTreeMap<String, usize>,
+    pub rationale: String,
+    pub template: String,
+    /// Unified diff from the current template
+    pub diff: String,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn excerpt(text: &str) -> String {
+    match text.char_indices().nth(MAX_EXAMPLE_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// The failures recorded since `since` (Unix milliseconds)
+pub fn failures(oracle: &Oracle, since: u64) -> Result<Vec<Failure>, OracleError> {
+    let mut failures = Vec::new();
+    if let Some(review) = &oracle.review {
+        for item in review.items()? {
+            if item.created_at < since {
+                continue;
+            }
+            let mut kinds = item.flags.clone();
+            if item.label == Some(Label::Bad) {
+                kinds.push("labeled bad".to_string());
+            }
+            let prompt = item
+                .messages
+                .last()
+                .map(|message| message.content.clone())
+                .unwrap_or_default();
+            for kind in kinds {
+                failures.push(Failure {
+                    context: item.context.clone(),
+                    kind,
+                    prompt: prompt.clone(),
+                    response: item.response.clone(),
+                    note: item.note.clone(),
+                });
+            }
+        }
+    }
+    if let Some(archive) = &oracle.archive {
+        for rating in archive.ratings(since)? {
+            if rating.score > LOW_RATING {
+                continue;
+            }
+            let prompt = archive
+                .latest(&Pubkey::from_str(&rating.interaction)?)?
+                .map(|record| record.prompt)
+                .unwrap_or_default();
+            failures.push(Failure {
+                context: rating.context,
+                kind: format!("rated {}/5", rating.score),
+                prompt,
+                response: rating.response.unwrap_or_default(),
+                note: None,
+            });
+        }
+    }
+    Ok(failures)
+}
+
+/// Failures grouped by context, for the contexts with at least `min_failures`
+pub fn clusters(failures: Vec<Failure>, min_failures: usize) -> BTreeMap<String, Vec<Failure>> {
+    let mut clusters: BTreeMap<String, Vec<Failure>> = BTreeMap::new();
+    for failure in failures {
+        clusters
+            .entry(failure.context.clone())
+            .or_default()
+            .push(failure);
+    }
+    clusters.retain(|_, failures| failures.len() >= min_failures);
+    for failures in clusters.values_mut() {
+        failures.sort_by(|a, b| a.kind.cmp(&b.kind));
+    }
+    clusters
+}
+
+fn tuning_prompt(template: &str, failures: &[Failure]) -> String {
+    let mut examples = String::new();
+    // Spread the examples over the kinds of failure
+    let mut by_kind: BTreeMap<&str, Vec<&Failure>> = BTreeMap::new();
+    for failure in failures {
+        by_kind.entry(&failure.kind).or_default().push(failure);
+    }
+    let per_kind = (MAX_EXAMPLES / by_kind.len().max(1)).max(1);
+    for (kind, failures) in &by_kind {
+        for failure in failures.iter().take(per_kind) {
+            examples.push_str(&format!(
+                "\n[{}]\nPrompt: {}\nResponse: {}\n",
+                kind,
+                excerpt(&failure.prompt),
+                excerpt(&failure.response)
+            ));
+            if let Some(note) = &failure.note {
+                examples.push_str(&format!("Reviewer note: {}\n", note));
+            }
+        }
+    }
+    format!(
+        "You maintain the prompt template of an on-chain LLM oracle. The template is a \
+         minijinja template rendered with these variables: context (the context text), \
+         context_pubkey, text (the user's message), interaction, user, history (a list of \
+         {{role, content}}), identity and deployment.\n\n\
+         Current template:\n```jinja\n{}\n```\n\n\
+         These responses failed ({} in total; the kind of failure is in brackets):\n{}\n\
+         Propose a revised template that would avoid these failures. Keep the variables the \
+         current template uses. First explain the change in at most three sentences, then give \
+         the complete revised template in a single ```jinja code block.",
+        template.trim(),
+        failures.len(),
+        examples
+    )
+}
+
+/// The rationale before the code block and the template inside it
+fn parse_reply(reply: &str) -> Option<(String, String)> {
+    let start = reply.find("```")?;
+    let rationale = reply[..start].trim().to_string();
+    let body = &reply[start + 3..];
+    // Drop the language tag
+    let body = &body[body.find('\n')? + 1..];
+    let end = body.find("```")?;
+    Some((rationale, body[..end].trim().to_string()))
+}
+
+/// Ask the model for a revised template for each cluster of failures since `since`
+pub async fn suggest(
+    oracle: &Oracle,
+    since: u64,
+    min_failures: usize,
+) -> Result<Vec<Suggestion>, OracleError> {
+    let mut suggestions = Vec::new();
+    let prompts = oracle.prompts.get();
+    for (context, failures) in clusters(failures(oracle, since)?, min_failures) {
+        let current = prompts.source(&Pubkey::from_str(&context)?);
+        let reply = oracle
+            .llm_provider
+            .send_message(&[ChatMessage::new(
+                Role::User,
+                tuning_prompt(current, &failures),
+            )])
+            .await?;
+        let Some((rationale, template)) = parse_reply(&reply) else {
+            warn!(%context, "No template in the tuning suggestion");
+            continue;
+        };
+        if let Err(e) = prompts::validate(&template) {
+            warn!(%context, error = %e, "Suggested template is invalid");
+            continue;
+        }
+        if template == current.trim() {
+            continue;
+        }
+        let file = format!("{}.j2", context);
+        let diff = TextDiff::from_lines(current.trim(), &template)
+            .unified_diff()
+            .header(&format!("a/{}", file), &format!("b/{}", file))
+            .to_string();
+        let mut counts = BTreeMap::new();
+        for failure in &failures {
+            *counts.entry(failure.kind.clone()).or_default() += 1;
+        }
+        suggestions.push(Suggestion {
+            context,
+            failures: counts,
+            rationale,
+            template,
+            diff,
+        });
+    }
+    Ok(suggestions)
+}
+
+/// Write `<context>.j2` and `<context>.diff` for each suggestion to `dir`
+pub fn write(suggestions: &[Suggestion], dir: &Path) -> Result<(), OracleError> {
+    fs::create_dir_all(dir)?;
+    for suggestion in suggestions {
+        fs::write(
+            dir.join(format!("{}.j2", suggestion.context)),
+            format!("{}\n", suggestion.template),
+        )?;
+        let failures: Vec<String> = suggestion
+            .failures
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        fs::write(
+            dir.join(format!("{}.diff", suggestion.context)),
+            format!(
+                "Context {}\nFailures: {}\n\n{}\n\n{}",
+                suggestion.context,
+                failures.join(", "),
+                suggestion.rationale,
+                suggestion.diff
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// Analyze the failures of every `prompt_suggestions.interval_secs` and write the suggestions
+pub async fn run(oracle: Arc<Oracle>) {
+    let config = &oracle.config.prompt_suggestions;
+    let Some(dir) = config.dir.as_deref().map(Path::new) else {
+        return;
+    };
+    let period = Duration::from_secs(config.interval_secs);
+    let mut interval = tokio::time::interval(period);
+    // The first tick is immediate, and there is nothing to analyze at startup
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let since = now_millis().saturating_sub(period.as_millis() as u64);
+        let written = match suggest(&oracle, since, config.min_failures).await {
+            Ok(suggestions) => write(&suggestions, dir).map(|()| suggestions),
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(suggestions) if suggestions.is_empty() => {
+                info!("No prompt changes to suggest")
+            }
+            Ok(suggestions) => {
+                let contexts: Vec<&str> = suggestions
+                    .iter()
+                    .map(|suggestion| suggestion.context.as_str())
+                    .collect();
+                info!(count = suggestions.len(), "Wrote prompt suggestions");
+                notify::emit(Event::new(
+                    Severity::Info,
+                    SUGGESTIONS_EVENT,
+                    "Prompt changes suggested",
+                    format!(
+                        "Suggested templates for {} are in {} for review.",
+                        contexts.join(", "),
+                        dir.display()
+                    ),
+                ));
+            }
+            Err(e) => warn!(error = ?e, "Failed to analyze the failures"),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Pre-sign transaction dumps.
+//!
+//! With `solana.tx_audit_dir` set, the oracle identity is wrapped in an [`AuditingSigner`] that
+//! writes every message to the directory before signing it: callbacks and their simulations,
+//! acknowledgements, nonce and lookup table maintenance alike. Each dump is a JSON file named
+//! `<unix millis>-<message hash>.json` holding the message in base64 (as it is signed, for
+//! external verification tooling) and decoded, and the signature once signed. A message that
+//! can't be dumped isn't signed, so the directory is a complete record of what the oracle signed.
+
+use crate::identity::OracleSigner;
+use crate::OracleError;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::message::VersionedMessage;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, Signer};
+use solana_sdk::signer::SignerError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// The message decoded: its accounts, instructions and lookups
+pub fn decode_message(message: &[u8]) -> Result<Value, OracleError> {
+    let message: VersionedMessage = bincode::deserialize(message)?;
+    let keys = message.static_account_keys();
+    let account = |index: usize| match keys.get(index) {
+        Some(key) => json!({
+            "pubkey": key.to_string(),
+            "signer": message.is_signer(index),
+            "writable": message.is_maybe_writable(index, None),
+        }),
+        // Loaded from a lookup table
+        None => json!({ "lookup_index": index }),
+    };
+    let instructions: Vec<Value> = message
+        .instructions()
+        .iter()
+        .map(|instruction| {
+            json!({
+                "program_id": keys
+                    .get(instruction.program_id_index as usize)
+                    .map(Pubkey::to_string),
+                "accounts": instruction
+                    .accounts
+                    .iter()
+                    .map(|index| account(*index as usize))
+                    .collect::<Vec<Value>>(),
+                "data": base64::engine::general_purpose::STANDARD.encode(&instruction.data),
+            })
+        })
+        .collect();
+    let lookups: Vec<Value> = message
+        .address_table_lookups()
+        .unwrap_or_default()
+        .iter()
+        .map(|lookup| {
+            json!({
+                "table": lookup.account_key.to_string(),
+                "writable_indexes": lookup.writable_indexes,
+                "readonly_indexes": lookup.readonly_indexes,
+            })
+        })
+        .collect();
+    Ok(json!({
+        "version": match message {
+            VersionedMessage::Legacy(_) => "legacy",
+            VersionedMessage::V0(_) => "v0",
+        },
+        "fee_payer": keys.first().map(Pubkey::to_string),
+        "recent_blockhash": message.recent_blockhash().to_string(),
+        "account_keys": keys.iter().map(Pubkey::to_string).collect::<Vec<String>>(),
+        "instructions": instructions,
+        "address_table_lookups": lookups,
+    }))
+}
+
+/// Signs with the oracle identity after dumping each message to a directory
+pub struct AuditingSigner {
+    inner: OracleSigner,
+    dir: PathBuf,
+}
+
+impl AuditingSigner {
+    pub fn new(inner: OracleSigner, dir: PathBuf) -> Result<Self, OracleError> {
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Can't create the audit directory {}: {}", dir.display(), e))?;
+        Ok(Self { inner, dir })
+    }
+
+    fn dump(
+        &self,
+        path: &Path,
+        message: &[u8],
+        signature: Option<&Signature>,
+    ) -> Result<(), OracleError> {
+        let decoded = decode_message(message)
+            .unwrap_or_else(|e| json!({ "error": format!("Can't decode the message: {}", e) }));
+        let dump = json!({
+            "signer": self.inner.try_pubkey()?.to_string(),
+            "message": base64::engine::general_purpose::STANDARD.encode(message),
+            "decoded": decoded,
+            "signature": signature.map(Signature::to_string),
+        });
+        fs::write(path, serde_json::to_vec_pretty(&dump)?)?;
+        Ok(())
+    }
+}
+
+impl Signer for AuditingSigner {
+    fn try_pubkey(&self) -> Result<Pubkey, SignerError> {
+        self.inner.try_pubkey()
+    }
+
+    fn try_sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let hash = hex::encode(&Sha256::digest(message)[..8]);
+        let path = self.dir.join(format!("{}-{}.json", millis, hash));
+        self.dump(&path, message, None).map_err(|e| {
+            SignerError::Custom(format!("Refusing to sign, the audit dump failed: {}", e))
+        })?;
+        let signature = self.inner.try_sign_message(message)?;
+        // Signed already: a failure here only loses the signature, found by the message hash
+        if let Err(e) = self.dump(&path, message, Some(&signature)) {
+            warn!(path = %path.display(), error = ?e, "Failed to add the signature to the audit dump");
+        }
+        Ok(signature)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.inner.is_interactive()
+    }
+}
@@ -0,0 +1,161 @@
+//! Hallucination guard.
+//!
+//! When enabled, answers grounded on retrieved sources go through a verification pass before
+//! the callback: the model lists the factual claims of its draft and judges each against the
+//! sources. Unsupported claims get the answer regenerated without them, or flagged in place.
+
+use crate::metrics::METRICS;
+use crate::providers::{ChatMessage, ChatProvider, Role};
+use crate::OracleError;
+use std::fmt;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/// Regenerations attempted before falling back to hedging
+pub const MAX_REGENERATIONS: usize = 1;
+/// Prefix of answers containing claims the sources don't support
+pub const UNVERIFIED_MARKER: &str = "[unverified]";
+
+/// What to do with an answer containing unsupported claims
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HallucinationGuard {
+    /// Flag the answer and list the unsupported claims
+    Hedge,
+    /// Ask the model to answer again without the unsupported claims, hedging if it can't
+    Regenerate,
+}
+
+impl FromStr for HallucinationGuard {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "hedge" => Ok(Self::Hedge),
+            "regenerate" => Ok(Self::Regenerate),
+            other => Err(format!("expected hedge or regenerate, got {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for HallucinationGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hedge => write!(f, "hedge"),
+            Self::Regenerate => write!(f, "regenerate"),
+        }
+    }
+}
+
+fn verification_prompt(sources: &[&str], answer: &str) -> String {
+    format!(
+        "You are checking an answer against its sources.\n\
+         Sources:\n{}\n\n\
+         Answer:\n{}\n\n\
+         List every factual claim made by the answer, one per line, as \
+         \"SUPPORTED: <claim>\" when the sources entail it and \"UNSUPPORTED: <claim>\" otherwise. \
+         Opinions, greetings and restatements of the question are not claims. \
+         Reply with \"NONE\" if the answer makes no factual claim.",
+        sources.join("\n"),
+        answer
+    )
+}
+
+/// Parse the `UNSUPPORTED:` lines of a verification reply
+pub fn unsupported_claims(reply: &str) -> Vec<String> {
+    reply
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().trim_start_matches(['-', '*', ' ']);
+            line.strip_prefix("UNSUPPORTED:")
+                .map(|claim| claim.trim().to_string())
+        })
+        .filter(|claim| !claim.is_empty())
+        .collect()
+}
+
+/// Flag an answer whose claims couldn't all be verified
+pub fn hedge(answer: &str, unsupported: &[String]) -> String {
+    if unsupported.is_empty() {
+        format!("{} {}", UNVERIFIED_MARKER, answer)
+    } else {
+        format!(
+            "{} {} (not supported by the sources: {})",
+            UNVERIFIED_MARKER,
+            answer,
+            unsupported.join("; ")
+        )
+    }
+}
+
+async fn check(
+    provider: &dyn ChatProvider,
+    sources: &[&str],
+    answer: &str,
+) -> Result<Vec<String>, OracleError> {
+    let reply = provider
+        .send_message(&[ChatMessage::new(
+            Role::User,
+            verification_prompt(sources, answer),
+        )])
+        .await?;
+    Ok(unsupported_claims(&reply))
+}
+
+/// Verify `answer` against `sources`, the prompts of the tools that retrieved them. `history`
+/// is the conversation the answer was generated from, reused when regenerating. A failing
+/// verification call hedges the answer rather than letting it through unchecked.
+pub async fn verify(
+    provider: &dyn ChatProvider,
+    guard: HallucinationGuard,
+    history: &[ChatMessage],
+    sources: &[&str],
+    answer: String,
+) -> String {
+    let mut answer = answer;
+    let mut regenerations = 0;
+    loop {
+        let unsupported = match check(provider, sources, &answer).await {
+            Ok(unsupported) => unsupported,
+            Err(e) => {
+                warn!(error = ?e, "Verification failed, hedging the answer");
+                METRICS.verifications.with_label_values(&["error"]).inc();
+                return hedge(&answer, &[]);
+            }
+        };
+        if unsupported.is_empty() {
+            let outcome = if regenerations == 0 {
+                "supported"
+            } else {
+                "regenerated"
+            };
+            METRICS.verifications.with_label_values(&[outcome]).inc();
+            return answer;
+        }
+        info!(claims = ?unsupported, "Answer has unsupported claims");
+        if guard == HallucinationGuard::Hedge || regenerations >= MAX_REGENERATIONS {
+            METRICS.verifications.with_label_values(&["hedged"]).inc();
+            return hedge(&answer, &unsupported);
+        }
+
+        regenerations += 1;
+        let mut messages = history.to_vec();
+        messages.push(ChatMessage::new(Role::Assistant, answer.clone()));
+        messages.push(ChatMessage::new(
+            Role::User,
+            format!(
+                "These claims of your answer are not supported by the sources: {}. \
+                 Answer again using only facts from the sources, and say so when they don't \
+                 cover the question.",
+                unsupported.join("; ")
+            ),
+        ));
+        match provider.send_message(&messages).await {
+            Ok(regenerated) => answer = regenerated,
+            Err(e) => {
+                warn!(error = ?e, "Regeneration failed, hedging the answer");
+                METRICS.verifications.with_label_values(&["hedged"]).inc();
+                return hedge(&answer, &unsupported);
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Per-context webhooks.
+//!
+//! Contexts listed in the `webhooks.contexts` (`CONTEXT_WEBHOOKS`) JSON file have their answers
+//! POSTed to the operator's backends once the callback is confirmed, to ping a service or update a
+//! leaderboard without indexing the chain:
+//!
+//! ```json
+//! {
+//!   "<context pubkey>": [
+//!     {
+//!       "url": "https://backend.example.com/answered",
+//!       "secret": "<signing key>",
+//!       "template": "{\"player\": {{ user | tojson }}, \"verdict\": {{ response | tojson }}}",
+//!       "max_retries": 5
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The body is the `template`, a minijinja template rendered with [`WebhookVars`], or the JSON
+//! of the variables when there is none. With a `secret`, requests carry `X-Oracle-Timestamp` and
+//! `X-Oracle-Signature: sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>` keyed by the
+//! secret. Failed deliveries are retried up to `max_retries` times (default 3) backing off from
+//! one second, except those the backend refuses with a 4xx other than 429. Deliveries run in the
+//! background and are not persisted: those in flight when the oracle stops are lost.
+
+use crate::config::deployment_name;
+use crate::metrics::METRICS;
+use crate::OracleError;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn, Instrument};
+
+const DEFAULT_MAX_RETRIES: u8 = 3;
+/// Wait before the first retry, doubled at each retry
+const RETRY_BASE: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A webhook of a context
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContextWebhook {
+    pub url: String,
+    /// Key of the signature of the body, unsigned when unset
+    pub secret: Option<String>,
+    /// minijinja template of the body, rendered with [`WebhookVars`]
+    pub template: Option<String>,
+    /// Defaults to `application/json`
+    pub content_type: Option<String>,
+    pub max_retries: Option<u8>,
+}
+
+/// What webhook templates are rendered with
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookVars {
+    pub interaction: String,
+    pub context: String,
+    pub user: String,
+    /// The interaction text
+    pub text: String,
+    pub response: String,
+    /// Signatures of the callback transactions
+    pub signatures: Vec<String>,
+    /// Unix time of the confirmation
+    pub confirmed_at: u64,
+    pub deployment: String,
+}
+
+/// Webhooks by context
+#[derive(Default)]
+pub struct ContextWebhooks {
+    webhooks: HashMap<Pubkey, Vec<Arc<ContextWebhook>>>,
+    client: reqwest::Client,
+}
+
+impl ContextWebhooks {
+    /// Load the webhooks from the JSON file at `path`, if set
+    pub fn load(path: Option<&str>) -> Result<Self, OracleError> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+        let raw: HashMap<String, Vec<ContextWebhook>> =
+            serde_json::from_str(&fs::read_to_string(path)?)
+                .map_err(|e| format!("Invalid context webhooks file {}: {}", path, e))?;
+        let env = minijinja::Environment::new();
+        let mut webhooks = HashMap::new();
+        for (context, hooks) in raw {
+            let context = Pubkey::from_str(&context)
+                .map_err(|e| format!("Invalid context pubkey {:?} in {}: {}", context, path, e))?;
+            for hook in &hooks {
+                reqwest::Url::parse(&hook.url)
+                    .map_err(|e| format!("Invalid webhook URL of context {}: {}", context, e))?;
+                if let Some(template) = &hook.template {
+                    env.template_from_str(template).map_err(|e| {
+                        format!("Invalid webhook template of context {}: {}", context, e)
+                    })?;
+                }
+            }
+            webhooks.insert(context, hooks.into_iter().map(Arc::new).collect());
+        }
+        let client = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self { webhooks, client })
+    }
+
+    /// Contexts with webhooks
+    pub fn len(&self) -> usize {
+        self.webhooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty()
+    }
+
+    /// Send the confirmed answer of an interaction to the webhooks of its context, in the
+    /// background
+    pub fn fire(
+        &self,
+        interaction_pubkey: &Pubkey,
+        interaction: &solana_gpt_oracle::Interaction,
+        response: &str,
+        signatures: &[Signature],
+    ) {
+        let Some(hooks) = self.webhooks.get(&interaction.context) else {
+            return;
+        };
+        let vars = WebhookVars {
+            interaction: interaction_pubkey.to_string(),
+            context: interaction.context.to_string(),
+            user: interaction.user.to_string(),
+            text: interaction.text.clone(),
+            response: response.to_string(),
+            signatures: signatures.iter().map(Signature::to_string).collect(),
+            confirmed_at: unix_now(),
+            deployment: deployment_name().unwrap_or_default().to_string(),
+        };
+        for hook in hooks {
+            let body = match render(hook, &vars) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!(url = %hook.url, error = ?e, "Failed to render a webhook");
+                    METRICS.webhooks.with_label_values(&["failed"]).inc();
+                    continue;
+                }
+            };
+            tokio::spawn(deliver(self.client.clone(), hook.clone(), body).in_current_span());
+        }
+    }
+}
+
+fn render(hook: &ContextWebhook, vars: &WebhookVars) -> Result<String, OracleError> {
+    match &hook.template {
+        Some(template) => Ok(minijinja::Environment::new().render_str(template, vars)?),
+        None => Ok(serde_json::to_string(vars)?),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`
+pub fn signature(secret: &str, timestamp: u64, body: &str) -> String {
+    // HMAC takes keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn deliver(client: reqwest::Client, hook: Arc<ContextWebhook>, body: String) {
+    let max_retries = hook.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+    let mut attempt = 0;
+    loop {
+        let timestamp = unix_now();
+        let mut request = client
+            .post(&hook.url)
+            .header(
+                "Content-Type",
+                hook.content_type.as_deref().unwrap_or("application/json"),
+            )
+            .header("X-Oracle-Timestamp", timestamp)
+            .body(body.clone());
+        if let Some(secret) = &hook.secret {
+            request = request.header("X-Oracle-Signature", signature(secret, timestamp, &body));
+        }
+        let (error, retryable) = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!(url = %hook.url, "Webhook delivered");
+                METRICS.webhooks.with_label_values(&["delivered"]).inc();
+                return;
+            }
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.is_server_error() || status.as_u16() == 429;
+                (format!("status {}", status), retryable)
+            }
+            Err(e) => (e.to_string(), true),
+        };
+        if !retryable || attempt >= max_retries {
+            warn!(url = %hook.url, attempts = attempt + 1, %error, "Webhook delivery failed");
+            METRICS.webhooks.with_label_values(&["failed"]).inc();
+            return;
+        }
+        attempt += 1;
+        debug!(url = %hook.url, attempt, %error, "Webhook delivery failed, retrying");
+        tokio::time::sleep(RETRY_BASE * 2u32.pow((attempt as u32 - 1).min(6))).await;
+    }
+}
@@ -0,0 +1,417 @@
+use crate::ack::send_ack;
+use crate::admin_api;
+use crate::decode::InteractionView;
+use crate::limits::LimitAction;
+use crate::metrics::METRICS;
+use crate::monitor::MONITOR;
+use crate::notify::{self, Event, Severity};
+use crate::oracle::Oracle;
+use crate::processor::{process_interaction, respond_limited};
+use crate::providers::BudgetExhausted;
+use crate::status::InteractionStatus;
+use crate::OracleError;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, error, warn, Instrument};
+
+/// Bounded pool dispatching interactions to tasks.
+///
+/// At most `max_concurrent` interactions are processed at once. Updates for the same interaction
+/// account are queued behind each other and handled by a single task, so a conversation is never
+/// processed out of order. Interaction accounts are unique across programs, so the program an
+/// update came from is kept alongside its data.
+///
+/// Once [closed](Self::close) for a shutdown, the pool only finishes the interactions it started.
+#[derive(Clone)]
+pub struct WorkerPool {
+    oracle: Arc<Oracle>,
+    permits: Arc<Semaphore>,
+    max_concurrent: usize,
+    pending: Arc<Mutex<HashMap<Pubkey, VecDeque<(Pubkey, Vec<u8>)>>>>,
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl WorkerPool {
+    pub fn new(oracle: Arc<Oracle>, max_concurrent: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+        Self {
+            oracle,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            closing: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Queue an interaction update of `program`. Spawns a worker unless one is already draining
+    /// this pubkey.
+    pub fn dispatch(&self, program: Pubkey, interaction_pubkey: Pubkey, data: Vec<u8>) {
+        if self.is_closing() {
+            debug!(interaction = %interaction_pubkey, "Shutting down, ignoring the update");
+            return;
+        }
+        self.detect(&interaction_pubkey, &data);
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(queue) = pending.get_mut(&interaction_pubkey) {
+                queue.push_back((program, data));
+                return;
+            }
+            pending.insert(interaction_pubkey, VecDeque::from([(program, data)]));
+            METRICS.queue_depth.set(pending.len() as i64);
+        }
+        self.spawn(interaction_pubkey);
+    }
+
+    /// Queue an interaction update of `program` landed at `slot`, counting a new interaction
+    /// against the flood protection
+    pub fn dispatch_at(
+        &self,
+        program: Pubkey,
+        interaction_pubkey: Pubkey,
+        data: Vec<u8>,
+        slot: u64,
+    ) {
+        self.count(&interaction_pubkey, &data, slot);
+        self.dispatch(program, interaction_pubkey, data);
+    }
+
+    /// Count an interaction seen for the first time against the flood protection of its
+    /// context: redeliveries of one queued, in flight or in the ledger don't count
+    fn count(&self, interaction_pubkey: &Pubkey, data: &[u8], slot: u64) {
+        if !self.oracle.flood.is_enabled() || self.is_pending(interaction_pubkey) {
+            return;
+        }
+        let Some(view) = InteractionView::parse(data).filter(|view| !view.is_processed) else {
+            return;
+        };
+        match self.oracle.processed.get(interaction_pubkey, view.text) {
+            Ok(None) => self.oracle.flood.count(&view.context, slot),
+            Ok(Some(_)) => {}
+            Err(e) => {
+                warn!(interaction = %interaction_pubkey, error = ?e, "Failed to read the ledger")
+            }
+        }
+    }
+
+    /// Queue an interaction update of `program` unless updates of the account are queued or in
+    /// flight already, checked under the same lock. Returns whether it was queued.
+    pub fn dispatch_idle(
+        &self,
+        program: Pubkey,
+        interaction_pubkey: Pubkey,
+        data: Vec<u8>,
+    ) -> bool {
+        if self.is_closing() {
+            return false;
+        }
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.contains_key(&interaction_pubkey) {
+                return false;
+            }
+            self.detect(&interaction_pubkey, &data);
+            pending.insert(interaction_pubkey, VecDeque::from([(program, data)]));
+            METRICS.queue_depth.set(pending.len() as i64);
+        }
+        self.spawn(interaction_pubkey);
+        true
+    }
+
+    fn spawn(&self, interaction_pubkey: Pubkey) {
+        let pool = self.clone();
+        tokio::spawn(async move { pool.drain(interaction_pubkey).await }.in_current_span());
+    }
+
+    /// Mark an unanswered interaction as detected, unless the ledger already tracks it: in
+    /// flight, settled, or failed with its callback in the dead-letter queue
+    fn detect(&self, interaction_pubkey: &Pubkey, data: &[u8]) {
+        let Some(view) = InteractionView::parse(data).filter(|view| !view.is_processed) else {
+            return;
+        };
+        if let Err(e) = self.try_detect(interaction_pubkey, view.text) {
+            warn!(interaction = %interaction_pubkey, error = ?e, "Failed to update the ledger");
+        }
+    }
+
+    fn try_detect(&self, interaction_pubkey: &Pubkey, prompt: &str) -> Result<(), OracleError> {
+        let oracle = &self.oracle;
+        if let Some(entry) = oracle.processed.get(interaction_pubkey, prompt)? {
+            if entry.status != InteractionStatus::Failed {
+                return Ok(());
+            }
+            let letter = oracle.dlq.get(interaction_pubkey)?;
+            if letter.is_some_and(|letter| letter.prompt == prompt) {
+                return Ok(());
+            }
+        }
+        oracle
+            .processed
+            .transition(interaction_pubkey, prompt, InteractionStatus::Detected, &[])
+    }
+
+    /// Number of interaction accounts with queued or in-flight updates
+    pub fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Whether updates of an interaction account are queued or being processed
+    pub fn is_pending(&self, interaction_pubkey: &Pubkey) -> bool {
+        self.pending
+            .lock()
+            .unwrap()
+            .contains_key(interaction_pubkey)
+    }
+
+    /// Stop starting interactions: new updates are ignored and queued ones dropped, they are
+    /// delivered again on the next start
+    pub fn close(&self) {
+        self.closing.send_replace(true);
+    }
+
+    pub fn is_closing(&self) -> bool {
+        *self.closing.borrow()
+    }
+
+    async fn closed(&self) {
+        let _ = self.closing.subscribe().wait_for(|closing| *closing).await;
+    }
+
+    /// Resolves once no interaction is queued or in flight
+    pub async fn drained(&self) {
+        while self.in_flight() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Drop the queued updates of an interaction account
+    fn abandon(&self, interaction_pubkey: &Pubkey) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.remove(interaction_pubkey);
+        METRICS.queue_depth.set(pending.len() as i64);
+    }
+
+    /// Acknowledge an unanswered interaction in the background, with the number of interactions
+    /// that have to be answered before a worker frees up for it
+    fn ack(&self, interaction_pubkey: Pubkey, data: &[u8]) {
+        let Some(interaction) = InteractionView::parse(data) else {
+            return;
+        };
+        if interaction.is_processed {
+            return;
+        }
+        let queue_position = self.in_flight().saturating_sub(self.max_concurrent);
+        let eta =
+            self.oracle
+                .latency
+                .eta(&interaction.context, queue_position, self.max_concurrent);
+        let oracle = self.oracle.clone();
+        tokio::spawn(async move {
+            match send_ack(&oracle, &interaction_pubkey, queue_position, eta).await {
+                Ok(signature) => {
+                    debug!(interaction = %interaction_pubkey, %signature, queue_position, ?eta, "Sent ack")
+                }
+                Err(e) => {
+                    warn!(interaction = %interaction_pubkey, error = ?e, "Failed to send ack")
+                }
+            }
+        }
+        .in_current_span());
+    }
+
+    /// Count an unanswered interaction against the rate limits, waiting without a worker while
+    /// it is over them with `limits.action = "defer"`. Returns whether it gets the limit response
+    /// instead of an answer.
+    async fn admit(&self, interaction_pubkey: &Pubkey, data: &[u8]) -> bool {
+        if !self.oracle.rate_limiter.is_enabled() {
+            return false;
+        }
+        let Some(view) = InteractionView::parse(data).filter(|view| !view.is_processed) else {
+            return false;
+        };
+        // Redelivered updates of answered interactions don't count
+        let answered = self
+            .oracle
+            .processed
+            .contains(interaction_pubkey, view.text);
+        if answered.unwrap_or(false) {
+            return false;
+        }
+        self.oracle
+            .rate_limiter
+            .admit(&view.context, &view.user)
+            .await
+    }
+
+    /// Defer an unanswered interaction of `program` while its context is held for flooding,
+    /// leaving it detected in the ledger. Returns whether it is deferred.
+    fn flooded(&self, program: Pubkey, interaction_pubkey: &Pubkey, data: &[u8]) -> bool {
+        if !self.oracle.flood.is_enabled() {
+            return false;
+        }
+        let Some(view) = InteractionView::parse(data).filter(|view| !view.is_processed) else {
+            return false;
+        };
+        // Redelivered updates of answered interactions aren't deferred
+        let answered = self
+            .oracle
+            .processed
+            .contains(interaction_pubkey, view.text);
+        if answered.unwrap_or(false)
+            || !self
+                .oracle
+                .flood
+                .defer(&view.context, program, *interaction_pubkey)
+        {
+            return false;
+        }
+        debug!(interaction = %interaction_pubkey, "Context flooding, deferring");
+        METRICS
+            .interactions_limited
+            .with_label_values(&["flood", "defer"])
+            .inc();
+        true
+    }
+
+    /// Hold an interaction the daily budget deferred without a worker until the budget resets,
+    /// leaving it detected in the ledger, then queue its update again unless a newer one came in
+    /// meanwhile. Returns whether a shutdown ended the wait instead.
+    async fn defer_over_budget(
+        &self,
+        program: Pubkey,
+        interaction_pubkey: &Pubkey,
+        data: Vec<u8>,
+        resets_in: Duration,
+    ) -> bool {
+        if let Some(view) = InteractionView::parse(&data) {
+            let detected = self.oracle.processed.transition(
+                interaction_pubkey,
+                view.text,
+                InteractionStatus::Detected,
+                &[],
+            );
+            if let Err(e) = detected {
+                warn!(interaction = %interaction_pubkey, error = ?e, "Failed to update the ledger");
+            }
+        }
+        tokio::select! {
+            () = tokio::time::sleep(resets_in) => {}
+            () = self.closed() => return true,
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(queue) = pending.get_mut(interaction_pubkey) {
+            if queue.is_empty() {
+                queue.push_back((program, data));
+            }
+        }
+        false
+    }
+
+    async fn drain(&self, interaction_pubkey: Pubkey) {
+        loop {
+            // The (possibly empty) queue stays in the map while an update is being processed, so
+            // new updates for this pubkey keep queuing behind this worker instead of spawning another.
+            if self.is_closing() {
+                self.abandon(&interaction_pubkey);
+                return;
+            }
+            let (program, next) = {
+                let mut pending = self.pending.lock().unwrap();
+                match pending
+                    .get_mut(&interaction_pubkey)
+                    .and_then(|queue| queue.pop_front())
+                {
+                    Some(update) => update,
+                    None => {
+                        pending.remove(&interaction_pubkey);
+                        METRICS.queue_depth.set(pending.len() as i64);
+                        return;
+                    }
+                }
+            };
+
+            if self.flooded(program, &interaction_pubkey, &next) {
+                continue;
+            }
+            if self.oracle.config.ack_transactions {
+                self.ack(interaction_pubkey, &next);
+            }
+            // Waiting for a pause, a rate limit or a worker ends with a shutdown
+            let admitted = tokio::select! {
+                admitted = async {
+                    admin_api::resumed().await;
+                    let limited = self.admit(&interaction_pubkey, &next).await;
+                    let permit = self.permits.acquire().await.ok()?;
+                    Some((limited, permit))
+                } => admitted,
+                () = self.closed() => None,
+            };
+            let Some((limited, permit)) = admitted else {
+                self.abandon(&interaction_pubkey);
+                return;
+            };
+            let prompt = InteractionView::parse(&next).map(|view| view.text.to_string());
+            let processed = if limited {
+                respond_limited(&self.oracle, program, interaction_pubkey, next.clone()).await
+            } else {
+                process_interaction(&self.oracle, program, interaction_pubkey, next.clone()).await
+            };
+            // Over the daily budget with `limits.action = "defer"`, the worker is freed for the
+            // wait
+            let over_budget = processed
+                .as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<BudgetExhausted>())
+                .filter(|_| self.oracle.config.limits.action == LimitAction::Defer)
+                .map(|exhausted| exhausted.resets_in);
+            if let Some(resets_in) = over_budget {
+                drop(permit);
+                if self
+                    .defer_over_budget(program, &interaction_pubkey, next, resets_in)
+                    .await
+                {
+                    self.abandon(&interaction_pubkey);
+                    return;
+                }
+                continue;
+            }
+            match processed {
+                Ok(()) => METRICS
+                    .interactions_processed
+                    .with_label_values(&["ok"])
+                    .inc(),
+                Err(e) => {
+                    METRICS
+                        .interactions_processed
+                        .with_label_values(&["error"])
+                        .inc();
+                    error!(interaction = %interaction_pubkey, error = ?e, "Failed to process interaction");
+                    MONITOR.error(Some(&interaction_pubkey), &e);
+                    notify::emit(
+                        Event::new(
+                            Severity::Warning,
+                            "interaction_failed",
+                            format!("Failed to process {}", interaction_pubkey),
+                            e.to_string(),
+                        )
+                        .with_key(interaction_pubkey.to_string()),
+                    );
+                    if let Some(prompt) = prompt {
+                        let failed = self.oracle.processed.transition(
+                            &interaction_pubkey,
+                            &prompt,
+                            InteractionStatus::Failed,
+                            &[],
+                        );
+                        if let Err(e) = failed {
+                            warn!(interaction = %interaction_pubkey, error = ?e, "Failed to update the ledger");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! End-to-end test of the oracle against a local validator.
+//!
+//! Starts `solana-test-validator` with the oracle program (`anchor build` first, or point
+//! `ORACLE_PROGRAM_SO` at the built program), creates a context and an interaction, runs the
+//! oracle binary with the mock provider and waits for the callback to land. Needs the Solana CLI
+//! on the `PATH`:
+//!
+//! ```sh
+//! anchor build && cargo test -p llm_oracle --features integration
+//! ```
+
+#![cfg(feature = "integration")]
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use llm_oracle::admin::{context_address, create_context_instruction, interact_instruction};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
+use solana_transaction_status::UiTransactionEncoding;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Ports apart from a validator already running on the defaults
+const RPC_PORT: u16 = 18899;
+const FAUCET_PORT: u16 = 19900;
+/// The public test identity of `.env.example`, the only one the program accepts callbacks from
+const TEST_IDENTITY: &str =
+    "62LxqpAW6SWhp7iKBjCQneapn1w6btAhW7xHeREWSpPzw3xZbHCfAFesSR4R76ejQXCLWrndn37cKCCLFvx6Swps";
+const MOCK_RESPONSE: &str = "Localnet says hi";
+
+/// A child process killed when dropped
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("llm-oracle-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn program_so() -> PathBuf {
+    let path = env::var("ORACLE_PROGRAM_SO")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| {
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../target/deploy/solana_gpt_oracle.so")
+        });
+    assert!(
+        path.exists(),
+        "{} not found: run `anchor build` or set ORACLE_PROGRAM_SO",
+        path.display()
+    );
+    path
+}
+
+async fn start_validator() -> (Process, RpcClient) {
+    let ledger = scratch_dir("ledger");
+    let child = Command::new("solana-test-validator")
+        .arg("--reset")
+        .arg("--quiet")
+        .arg("--ledger")
+        .arg(&ledger)
+        .args(["--rpc-port", &RPC_PORT.to_string()])
+        .args(["--faucet-port", &FAUCET_PORT.to_string()])
+        .args([
+            "--gossip-port",
+            "18000",
+            "--dynamic-port-range",
+            "18001-18030",
+        ])
+        .arg("--bpf-program")
+        .arg(solana_gpt_oracle::ID.to_string())
+        .arg(program_so())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("solana-test-validator must be on the PATH");
+    let validator = Process(child);
+    let rpc_client = RpcClient::new_with_commitment(
+        format!("http://127.0.0.1:{}", RPC_PORT),
+        CommitmentConfig::confirmed(),
+    );
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while rpc_client.get_health().await.is_err() {
+        assert!(Instant::now() < deadline, "The validator didn't start");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    (validator, rpc_client)
+}
+
+async fn airdrop(rpc_client: &RpcClient, to: &Pubkey) {
+    let signature = rpc_client
+        .request_airdrop(to, 10 * LAMPORTS_PER_SOL)
+        .await
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !rpc_client.confirm_transaction(&signature).await.unwrap() {
+        assert!(Instant::now() < deadline, "Airdrop to {} not confirmed", to);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+async fn send(rpc_client: &RpcClient, payer: &Keypair, instruction: Instruction) {
+    let blockhash = rpc_client.get_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[payer],
+        blockhash,
+    );
+    rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .await
+        .unwrap();
+}
+
+fn initialize_instruction(program_id: &Pubkey, payer: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: solana_gpt_oracle::accounts::Initialize {
+            payer: *payer,
+            identity: Pubkey::find_program_address(&[b"identity"], program_id).0,
+            counter: Pubkey::find_program_address(&[b"counter"], program_id).0,
+            system_program: system_program::id(),
+        }
+        .to_account_metas(None),
+        data: solana_gpt_oracle::instruction::Initialize {}.data(),
+    }
+}
+
+/// Run the oracle binary against the validator, answering with the mock provider
+fn start_oracle() -> Process {
+    let child = Command::new(env!("CARGO_BIN_EXE_llm_oracle"))
+        .arg("run")
+        // Keeps the oracle's databases apart from the developer's
+        .current_dir(scratch_dir("oracle"))
+        .env("RPC_URL", format!("http://127.0.0.1:{}", RPC_PORT))
+        .env("WEBSOCKET_URL", format!("ws://127.0.0.1:{}", RPC_PORT + 1))
+        .env("IDENTITY", TEST_IDENTITY)
+        .env("LLM_PROVIDER", "mock")
+        .env("MOCK_RESPONSE", MOCK_RESPONSE)
+        .env_remove("ORACLE_CONFIG")
+        .env_remove("IDENTITY_KEYPAIR_PATH")
+        .spawn()
+        .expect("Failed to start the oracle");
+    Process(child)
+}
+
+#[tokio::test]
+async fn callback_lands_on_localnet() {
+    let (_validator, rpc_client) = start_validator().await;
+    let program_id = solana_gpt_oracle::ID;
+    let user = Keypair::new();
+    let identity = Keypair::from_base58_string(TEST_IDENTITY);
+    airdrop(&rpc_client, &user.pubkey()).await;
+    airdrop(&rpc_client, &identity.pubkey()).await;
+
+    send(
+        &rpc_client,
+        &user,
+        initialize_instruction(&program_id, &user.pubkey()),
+    )
+    .await;
+    send(
+        &rpc_client,
+        &user,
+        create_context_instruction(
+            &program_id,
+            &user.pubkey(),
+            0,
+            "You answer questions about Solana.".to_string(),
+        ),
+    )
+    .await;
+    let context = context_address(&program_id, 0);
+
+    let _oracle = start_oracle();
+    // Sent while the oracle runs, so it is answered live rather than by the startup gap-fill
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    send(
+        &rpc_client,
+        &user,
+        interact_instruction(
+            &program_id,
+            &user.pubkey(),
+            &context,
+            "What is a PDA?".to_string(),
+        ),
+    )
+    .await;
+    let interaction = Pubkey::find_program_address(
+        &[
+            solana_gpt_oracle::Interaction::seed(),
+            user.pubkey().as_ref(),
+            context.as_ref(),
+        ],
+        &program_id,
+    )
+    .0;
+
+    let deadline = Instant::now() + Duration::from_secs(90);
+    loop {
+        let data = rpc_client.get_account_data(&interaction).await.unwrap();
+        let account =
+            solana_gpt_oracle::Interaction::try_deserialize(&mut data.as_slice()).unwrap();
+        if account.is_processed {
+            break;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "The callback didn't land in time"
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    // The most recent transaction touching the interaction is the callback
+    let callback = rpc_client
+        .get_signatures_for_address(&interaction)
+        .await
+        .unwrap()
+        .into_iter()
+        .next()
+        .expect("No transaction on the interaction");
+    assert!(
+        callback.err.is_none(),
+        "The callback failed: {:?}",
+        callback.err
+    );
+    let transaction = rpc_client
+        .get_transaction(
+            &callback.signature.parse().unwrap(),
+            UiTransactionEncoding::Json,
+        )
+        .await
+        .unwrap();
+    let logs: Option<Vec<String>> = transaction
+        .transaction
+        .meta
+        .expect("No transaction metadata")
+        .log_messages
+        .into();
+    assert!(
+        logs.unwrap_or_default()
+            .iter()
+            .any(|line| line.contains(MOCK_RESPONSE)),
+        "The callback didn't carry the mock response"
+    );
+}
@@ -243,8 +243,13 @@ pub struct InteractAgent<'info> {
     pub interaction: AccountInfo<'info>,
     #[account(seeds = [b"agent"], bump)]
     pub agent: Account<'info, Agent>,
-    #[account(address = agent.context)]
-    pub context_account: Account<'info, ContextAccount>,
+    /// CHECK: the agent's context, created before or after its creator was recorded
+    #[account(
+        address = agent.context,
+        owner = solana_gpt_oracle::ID,
+        constraint = ContextAccount::is_context(&context_account)?
+    )]
+    pub context_account: AccountInfo<'info>,
     #[account(
         init_if_needed,
         payer = payer,
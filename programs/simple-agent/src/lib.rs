@@ -88,8 +88,13 @@ pub struct InteractAgent<'info> {
     pub interaction: AccountInfo<'info>,
     #[account(seeds = [b"agent"], bump)]
     pub agent: Account<'info, Agent>,
-    #[account(address = agent.context)]
-    pub context_account: Account<'info, ContextAccount>,
+    /// CHECK: the agent's context, created before or after its creator was recorded
+    #[account(
+        address = agent.context,
+        owner = solana_gpt_oracle::ID,
+        constraint = ContextAccount::is_context(&context_account)?
+    )]
+    pub context_account: AccountInfo<'info>,
     /// CHECK: Checked oracle id
     #[account(address = solana_gpt_oracle::ID)]
     pub oracle_program: AccountInfo<'info>,
@@ -0,0 +1,179 @@
+//! Signed response attestations.
+//!
+//! With attestations on, the oracle ends each response with ` [attestation:<hex signature>]`:
+//! the ed25519 signature of `sha256(interaction || response)` by the oracle identity or a
+//! dedicated attestation key, `response` being the text before the tag. The transaction carrying
+//! the response verifies that signature with the Ed25519 program first, so a callback program
+//! checks the attestation with [`verify`], which finds the verification among the instructions of
+//! the transaction. Responses split across several callbacks are signed whole, and the tag comes
+//! with the last piece.
+//!
+//! Off-chain, [`split`] and [`message`] give the signature and the signed message to check with
+//! any ed25519 implementation.
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::hashv;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+
+/// Starts the tag ending an attested response
+pub const TAG_PREFIX: &str = " [attestation:";
+
+/// The message signed for the `response` to `interaction`
+pub fn message(interaction: &Pubkey, response: &str) -> [u8; 32] {
+    hashv(&[interaction.as_ref(), response.as_bytes()]).to_bytes()
+}
+
+/// Split an attested response into the response and its signature
+pub fn split(attested: &str) -> Option<(&str, [u8; 64])> {
+    let (response, tag) = attested.rsplit_once(TAG_PREFIX)?;
+    let hex = tag.strip_suffix(']')?;
+    if hex.len() != 128 {
+        return None;
+    }
+    let mut signature = [0u8; 64];
+    for (i, byte) in signature.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some((response, signature))
+}
+
+/// Whether `data` is that of an Ed25519 program instruction verifying `signature` of `message`
+/// by `attester`, all three in the instruction itself
+fn verifies(data: &[u8], attester: &Pubkey, signature: &[u8; 64], message: &[u8; 32]) -> bool {
+    // One signature, then the offsets of the signature, key and message, each followed by the
+    // index of the instruction holding it
+    let offset = |field: usize| {
+        data.get(2 + 2 * field..4 + 2 * field)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let this = Some(u16::MAX as usize);
+    if data.first() != Some(&1)
+        || offset(1) != this
+        || offset(3) != this
+        || offset(6) != this
+        || offset(5) != Some(message.len())
+    {
+        return false;
+    }
+    let (Some(signature_offset), Some(key_offset), Some(message_offset)) =
+        (offset(0), offset(2), offset(4))
+    else {
+        return false;
+    };
+    data.get(key_offset..key_offset + 32) == Some(attester.as_ref())
+        && data.get(signature_offset..signature_offset + 64) == Some(&signature[..])
+        && data.get(message_offset..message_offset + 32) == Some(&message[..])
+}
+
+/// The response of an attested callback, once checked that the transaction verified its
+/// signature by `attester`. `instructions` is the instructions sysvar, which the callback program
+/// receives by listing `sysvar::instructions::ID` in the account metas of its interactions.
+pub fn verify<'a>(
+    instructions: &AccountInfo,
+    attester: &Pubkey,
+    interaction: &Pubkey,
+    attested: &'a str,
+) -> Result<&'a str> {
+    let (response, signature) = split(attested).ok_or(ProgramError::InvalidInstructionData)?;
+    let message = message(interaction, response);
+    for index in 0..load_current_index_checked(instructions)? {
+        let instruction = load_instruction_at_checked(index as usize, instructions)?;
+        if instruction.program_id == ed25519_program::ID
+            && verifies(&instruction.data, attester, &signature, &message)
+        {
+            return Ok(response);
+        }
+    }
+    Err(ProgramError::MissingRequiredSignature.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The data of an Ed25519 program instruction as the oracle builds it: the offsets, then the
+    /// key, signature and message
+    fn instruction_data(attester: &Pubkey, signature: &[u8; 64], message: &[u8]) -> Vec<u8> {
+        let key_offset = 16u16;
+        let signature_offset = key_offset + 32;
+        let message_offset = signature_offset + 64;
+        let mut data = vec![1, 0];
+        for value in [
+            signature_offset,
+            u16::MAX,
+            key_offset,
+            u16::MAX,
+            message_offset,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(attester.as_ref());
+        data.extend_from_slice(signature);
+        data.extend_from_slice(message);
+        data
+    }
+
+    #[test]
+    fn verifies_the_instruction_of_the_attestation() {
+        let attester = Pubkey::new_unique();
+        let message = message(&Pubkey::new_unique(), "42");
+        let signature = [7u8; 64];
+        let data = instruction_data(&attester, &signature, &message);
+        assert!(verifies(&data, &attester, &signature, &message));
+
+        assert!(!verifies(
+            &data,
+            &Pubkey::new_unique(),
+            &signature,
+            &message
+        ));
+        assert!(!verifies(&data, &attester, &[8u8; 64], &message));
+        assert!(!verifies(&data, &attester, &signature, &[0u8; 32]));
+        assert!(!verifies(
+            &data[..data.len() - 1],
+            &attester,
+            &signature,
+            &message
+        ));
+    }
+
+    #[test]
+    fn rejects_data_held_by_other_instructions() {
+        let attester = Pubkey::new_unique();
+        let message = message(&Pubkey::new_unique(), "42");
+        let signature = [7u8; 64];
+        let data = instruction_data(&attester, &signature, &message);
+        // The key, signature or message read from another instruction of the transaction
+        for field in [1, 3, 6] {
+            let mut other = data.clone();
+            other[2 + 2 * field..4 + 2 * field].copy_from_slice(&0u16.to_le_bytes());
+            assert!(!verifies(&other, &attester, &signature, &message));
+        }
+        let mut two = data.clone();
+        two[0] = 2;
+        assert!(!verifies(&two, &attester, &signature, &message));
+    }
+
+    #[test]
+    fn splits_the_attestation_tag() {
+        let signature = [0xabu8; 64];
+        let attested = format!("The answer{}{}]", TAG_PREFIX, "ab".repeat(64));
+        assert_eq!(split(&attested), Some(("The answer", signature)));
+
+        assert_eq!(split("The answer"), None);
+        assert_eq!(split(&format!("The answer{}ab]", TAG_PREFIX)), None);
+        assert_eq!(
+            split(&format!("The answer{}{}", TAG_PREFIX, "ab".repeat(64))),
+            None
+        );
+        assert_eq!(
+            split(&format!("The answer{}{}]", TAG_PREFIX, "zz".repeat(64))),
+            None
+        );
+    }
+}
@@ -5,10 +5,16 @@ use anchor_lang::solana_program::program::invoke_signed;
 use ephemeral_rollups_sdk::anchor::{delegate, ephemeral};
 use ephemeral_rollups_sdk::cpi::DelegateConfig;
 
+pub mod attestation;
+pub mod refusal;
+
 declare_id!("KumM927g39X6ERsnuvJHXHKYxEY8dPLSRgVcvokNyXX");
 
 const ORACLE_IDENTITY: Pubkey = pubkey!("tEsT3eV6RFCWs1BZ7AXTzasHqTtMnMLCB2tjQ42TDXD");
 
+/// Seconds after which an unanswered interaction can be refunded to its creator
+pub const REFUND_TIMEOUT_SECS: i64 = 3600;
+
 #[ephemeral]
 #[program]
 pub mod solana_gpt_oracle {
@@ -21,10 +27,33 @@ pub mod solana_gpt_oracle {
     pub fn create_llm_context(ctx: Context<CreateLlmContext>, text: String) -> Result<()> {
         let context_account = &mut ctx.accounts.context_account;
         context_account.text = text;
+        context_account.authority = ctx.accounts.payer.key();
         ctx.accounts.counter.count += 1;
         Ok(())
     }
 
+    /// Store the model settings of a context, which the oracle answers its interactions with
+    /// instead of its defaults. Only the creator of the context may, and becomes the authority of
+    /// the settings; contexts created before their creator was recorded can't have settings.
+    pub fn create_context_settings(
+        ctx: Context<CreateContextSettings>,
+        settings: LlmSettings,
+    ) -> Result<()> {
+        let context_settings = &mut ctx.accounts.context_settings;
+        context_settings.authority = ctx.accounts.authority.key();
+        context_settings.context = ctx.accounts.context_account.key();
+        context_settings.settings = settings;
+        Ok(())
+    }
+
+    pub fn update_context_settings(
+        ctx: Context<UpdateContextSettings>,
+        settings: LlmSettings,
+    ) -> Result<()> {
+        ctx.accounts.context_settings.settings = settings;
+        Ok(())
+    }
+
     pub fn interact_with_llm(
         ctx: Context<InteractWithLlm>,
         text: String,
@@ -98,6 +127,7 @@ pub mod solana_gpt_oracle {
         interaction.callback_discriminator = callback_discriminator;
         interaction.callback_account_metas = account_metas.unwrap_or_default();
         interaction.is_processed = false;
+        interaction.created_at = Clock::get()?.unix_timestamp;
 
         interaction.try_serialize(&mut interaction_data.as_mut())?;
         Ok(())
@@ -172,6 +202,80 @@ pub mod solana_gpt_oracle {
         Ok(())
     }
 
+    pub fn rate_response(ctx: Context<RateResponse>, score: u8) -> Result<()> {
+        if !(1..=5).contains(&score) {
+            return Err(ProgramError::InvalidArgument.into());
+        }
+        let interaction = &ctx.accounts.interaction;
+        if !interaction.is_processed {
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+        emit!(ResponseRated {
+            interaction: interaction.key(),
+            context: interaction.context,
+            user: interaction.user,
+            score,
+        });
+        Ok(())
+    }
+
+    /// Close an interaction left unanswered for `REFUND_TIMEOUT_SECS`, returning its lamports to
+    /// its creator. Anyone can crank it.
+    pub fn refund_interaction(ctx: Context<RefundInteraction>) -> Result<()> {
+        let interaction = &ctx.accounts.interaction;
+        if interaction.is_processed {
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        if now < interaction.created_at.saturating_add(REFUND_TIMEOUT_SECS) {
+            return Err(ProgramError::InvalidArgument.into());
+        }
+        emit!(InteractionRefunded {
+            interaction: interaction.key(),
+            context: interaction.context,
+            user: interaction.user,
+            lamports: interaction.to_account_info().lamports(),
+        });
+        Ok(())
+    }
+
+    /// Grow an interaction created before `created_at` was added to the layout of the current
+    /// one, so that it deserializes again. Its `created_at` is 0, refundable right away like the
+    /// interactions of that time whose padding reads as one. Anyone can crank it; it does nothing
+    /// to an interaction that already deserializes.
+    pub fn upgrade_interaction(ctx: Context<UpgradeInteraction>) -> Result<()> {
+        let interaction_info = ctx.accounts.interaction.to_account_info();
+        let len = {
+            let data = interaction_info.try_borrow_data()?;
+            if data.get(..8) != Some(&Interaction::DISCRIMINATOR[..]) {
+                return Err(ProgramError::InvalidAccountData.into());
+            }
+            if Interaction::try_deserialize(&mut &data[..]).is_ok() {
+                return Ok(());
+            }
+            data.len()
+        };
+        let space = len + 8;
+        let rent = Rent::get()?;
+        let additional_rent = rent
+            .minimum_balance(space)
+            .saturating_sub(interaction_info.lamports());
+        interaction_info.realloc(space, true)?;
+        if additional_rent > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.payer.to_account_info(),
+                    to: interaction_info.clone(),
+                },
+            );
+            anchor_lang::system_program::transfer(cpi_context, additional_rent)?;
+        }
+        let data = interaction_info.try_borrow_data()?;
+        Interaction::try_deserialize(&mut &data[..])?;
+        Ok(())
+    }
+
     pub fn delegate_interaction(ctx: Context<DelegateInteraction>) -> Result<()> {
         ctx.accounts.delegate_interaction(
             &ctx.accounts.payer,
@@ -224,7 +328,7 @@ pub struct CreateLlmContext<'info> {
     #[account(
         init,
         payer = payer,
-        space = 8 + text.as_bytes().len() + 8,
+        space = 8 + text.as_bytes().len() + 8 + 32,
         seeds = [ContextAccount::seed(), &counter.count.to_le_bytes()],
         bump
     )]
@@ -232,6 +336,42 @@ pub struct CreateLlmContext<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+#[instruction(settings: LlmSettings)]
+pub struct CreateContextSettings<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(has_one = authority)]
+    pub context_account: Account<'info, ContextAccount>,
+    #[account(
+        init,
+        payer = authority,
+        space = ContextSettings::space(&settings),
+        seeds = [ContextSettings::seed(), context_account.key().as_ref()],
+        bump
+    )]
+    pub context_settings: Account<'info, ContextSettings>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(settings: LlmSettings)]
+pub struct UpdateContextSettings<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [ContextSettings::seed(), context_settings.context.as_ref()],
+        bump,
+        realloc = ContextSettings::space(&settings),
+        realloc::payer = authority,
+        realloc::zero = false
+    )]
+    pub context_settings: Account<'info, ContextSettings>,
+    pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 #[instruction(text: String, callback_program_id: Pubkey, callback_discriminator: [u8; 8], account_metas: Option<Vec<AccountMeta>>)]
 pub struct InteractWithLlm<'info> {
@@ -244,8 +384,9 @@ pub struct InteractWithLlm<'info> {
         bump
     )]
     pub interaction: AccountInfo<'info>,
-    /// CHECK: we accept any context
-    pub context_account: Account<'info, ContextAccount>,
+    /// CHECK: we accept any context, created before or after its creator was recorded
+    #[account(owner = crate::ID, constraint = ContextAccount::is_context(&context_account)?)]
+    pub context_account: AccountInfo<'info>,
     pub system_program: Program<'info, System>,
 }
 
@@ -268,6 +409,32 @@ pub struct CallbackFromOracle<'info> {
     pub identity: Account<'info, Identity>,
 }
 
+#[derive(Accounts)]
+pub struct RateResponse<'info> {
+    pub user: Signer<'info>,
+    #[account(has_one = user)]
+    pub interaction: Account<'info, Interaction>,
+}
+
+#[derive(Accounts)]
+pub struct RefundInteraction<'info> {
+    #[account(mut, has_one = user, close = user)]
+    pub interaction: Account<'info, Interaction>,
+    /// CHECK: the creator of the interaction, receiving its lamports
+    #[account(mut)]
+    pub user: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpgradeInteraction<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: an interaction in the layout preceding `created_at`, which doesn't deserialize
+    #[account(mut, owner = crate::ID)]
+    pub interaction: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
 #[delegate]
 #[derive(Accounts)]
 pub struct DelegateInteraction<'info> {
@@ -280,8 +447,9 @@ pub struct DelegateInteraction<'info> {
         bump
     )]
     pub interaction: AccountInfo<'info>,
-    /// CHECK: we accept any context
-    pub context_account: Account<'info, ContextAccount>,
+    /// CHECK: we accept any context, created before or after its creator was recorded
+    #[account(owner = crate::ID, constraint = ContextAccount::is_context(&context_account)?)]
+    pub context_account: AccountInfo<'info>,
 }
 
 /// Accounts
@@ -289,12 +457,59 @@ pub struct DelegateInteraction<'info> {
 #[account]
 pub struct ContextAccount {
     pub text: String,
+    /// Creator of the context, who may give it settings. Contexts created before it was
+    /// recorded don't deserialize with it, and are only read by their discriminator.
+    pub authority: Pubkey,
 }
 
 impl ContextAccount {
     pub fn seed() -> &'static [u8] {
         b"test-context"
     }
+
+    /// Whether `account` holds a context, in either layout
+    pub fn is_context(account: &AccountInfo) -> Result<bool> {
+        Ok(account
+            .try_borrow_data()?
+            .starts_with(ContextAccount::DISCRIMINATOR))
+    }
+}
+
+/// Model settings of a context, overriding the oracle's defaults where set
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct LlmSettings {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub system_prompt: Option<String>,
+}
+
+impl LlmSettings {
+    /// Borsh size of the settings
+    pub fn size(&self) -> usize {
+        let text = |text: &Option<String>| 1 + text.as_ref().map_or(0, |text| 4 + text.len());
+        text(&self.model) + 5 + 5 + text(&self.system_prompt)
+    }
+}
+
+/// PDA of a context holding its [`LlmSettings`]
+#[account]
+#[derive(Debug)]
+pub struct ContextSettings {
+    /// May update the settings
+    pub authority: Pubkey,
+    pub context: Pubkey,
+    pub settings: LlmSettings,
+}
+
+impl ContextSettings {
+    pub fn seed() -> &'static [u8] {
+        b"context-settings"
+    }
+
+    pub fn space(settings: &LlmSettings) -> usize {
+        8 + 32 + 32 + settings.size()
+    }
 }
 
 #[account]
@@ -307,6 +522,8 @@ pub struct Interaction {
     pub callback_discriminator: [u8; 8],
     pub callback_account_metas: Vec<AccountMeta>,
     pub is_processed: bool,
+    /// Unix timestamp of the request, starting the refund timeout
+    pub created_at: i64,
 }
 
 impl Interaction {
@@ -315,7 +532,7 @@ impl Interaction {
     }
 
     pub fn space(text: &String, account_metas_len: usize) -> usize {
-        121 + text.as_bytes().len() + account_metas_len * AccountMeta::size()
+        129 + text.as_bytes().len() + account_metas_len * AccountMeta::size()
     }
 }
 
@@ -338,4 +555,79 @@ pub struct Counter {
 }
 
 #[account]
-pub struct Identity {}
\ No newline at end of file
+pub struct Identity {}
+
+/// Events
+
+/// A user rated the response to their interaction, from 1 (bad) to 5 (good)
+#[event]
+pub struct ResponseRated {
+    pub interaction: Pubkey,
+    pub context: Pubkey,
+    pub user: Pubkey,
+    pub score: u8,
+}
+
+/// An unanswered interaction was closed and its lamports returned to its creator
+#[event]
+pub struct InteractionRefunded {
+    pub interaction: Pubkey,
+    pub context: Pubkey,
+    pub user: Pubkey,
+    pub lamports: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A context account in the layout preceding its `authority`
+    fn legacy_context(text: &str) -> Vec<u8> {
+        let mut data = ContextAccount::DISCRIMINATOR.to_vec();
+        text.to_string().serialize(&mut data).unwrap();
+        data
+    }
+
+    fn with_account<T>(mut data: Vec<u8>, check: impl FnOnce(&AccountInfo) -> T) -> T {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &crate::ID,
+            false,
+            0,
+        );
+        check(&account)
+    }
+
+    #[test]
+    fn accepts_a_context_preceding_its_authority() {
+        let data = legacy_context("You are a helpful agent");
+        // Doesn't deserialize as the current layout, so consumers can't take an `Account`
+        assert!(ContextAccount::try_deserialize(&mut data.as_slice()).is_err());
+        assert!(with_account(data, |account| ContextAccount::is_context(account)).unwrap());
+    }
+
+    #[test]
+    fn accepts_a_context_with_its_authority() {
+        let context = ContextAccount {
+            text: "You are a helpful agent".to_string(),
+            authority: Pubkey::new_unique(),
+        };
+        let mut data = Vec::new();
+        context.try_serialize(&mut data).unwrap();
+        assert!(with_account(data, |account| ContextAccount::is_context(account)).unwrap());
+    }
+
+    #[test]
+    fn rejects_other_accounts() {
+        let mut data = Counter::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&7u32.to_le_bytes());
+        assert!(!with_account(data, |account| ContextAccount::is_context(account)).unwrap());
+        assert!(!with_account(Vec::new(), |account| ContextAccount::is_context(account)).unwrap());
+    }
+}
@@ -0,0 +1,113 @@
+//! Machine-readable refusals.
+//!
+//! With refusal codes on, the oracle answers the interactions it won't answer with
+//! `[refusal:<code>]` instead of a free-text apology, e.g. `[refusal:over_budget]`, so a callback
+//! program can branch on why with [`parse`]. A refusal is the whole response, between the
+//! `[prompt:...]` tag and the attestation tag when the oracle adds them.
+
+use crate::attestation;
+
+/// Starts the tag of a refusal
+pub const TAG_PREFIX: &str = "[refusal:";
+
+/// Why the oracle didn't answer an interaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The answer matched the oracle's blocklist or was flagged by moderation
+    Moderation,
+    /// The interaction waited too long to be answered
+    Expired,
+    /// The interaction is over a rate limit or the oracle's LLM budget
+    OverBudget,
+    /// The answer was empty or didn't match the output schema of the context
+    InvalidFormat,
+}
+
+impl Refusal {
+    pub const ALL: [Refusal; 4] = [
+        Refusal::Moderation,
+        Refusal::Expired,
+        Refusal::OverBudget,
+        Refusal::InvalidFormat,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Refusal::Moderation => "moderation",
+            Refusal::Expired => "expired",
+            Refusal::OverBudget => "over_budget",
+            Refusal::InvalidFormat => "invalid_format",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|refusal| refusal.code() == code)
+    }
+
+    /// The response carrying the refusal, `[refusal:<code>]`
+    pub fn tag(&self) -> String {
+        format!("{}{}]", TAG_PREFIX, self.code())
+    }
+}
+
+/// The refusal a response is, if it is one
+pub fn parse(response: &str) -> Option<Refusal> {
+    let response = response
+        .split(attestation::TAG_PREFIX)
+        .next()
+        .unwrap_or(response)
+        .trim_end();
+    let (before, tag) = response.rsplit_once(TAG_PREFIX)?;
+    // Only the prompt hash tag comes before it
+    let prompt_tag = before
+        .strip_prefix("[prompt:")
+        .and_then(|rest| rest.strip_suffix("] "))
+        .is_some_and(|hash| hash.bytes().all(|byte| byte.is_ascii_hexdigit()));
+    if !before.is_empty() && !prompt_tag {
+        return None;
+    }
+    Refusal::from_code(tag.strip_suffix(']')?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_tag_of_each_code() {
+        for refusal in Refusal::ALL {
+            assert_eq!(parse(&refusal.tag()), Some(refusal));
+            assert_eq!(Refusal::from_code(refusal.code()), Some(refusal));
+        }
+        assert_eq!(parse("[refusal:unknown]"), None);
+        assert_eq!(parse("[refusal:expired"), None);
+    }
+
+    #[test]
+    fn parses_the_tag_between_the_prompt_and_attestation_tags() {
+        let prompt_tag = format!("[prompt:{}]", "ab".repeat(32));
+        let attestation_tag = format!("{}{}]", attestation::TAG_PREFIX, "cd".repeat(64));
+        assert_eq!(
+            parse(&format!("{} [refusal:over_budget]", prompt_tag)),
+            Some(Refusal::OverBudget)
+        );
+        assert_eq!(
+            parse(&format!(
+                "{} [refusal:moderation]{}",
+                prompt_tag, attestation_tag
+            )),
+            Some(Refusal::Moderation)
+        );
+        assert_eq!(
+            parse(&format!("[refusal:expired]{}", attestation_tag)),
+            Some(Refusal::Expired)
+        );
+    }
+
+    #[test]
+    fn ignores_a_tag_inside_an_answer() {
+        assert_eq!(parse("Refuse with [refusal:expired]"), None);
+        assert_eq!(parse("[prompt:zz] [refusal:expired]"), None);
+        assert_eq!(parse("[refusal:expired] and more"), None);
+    }
+}